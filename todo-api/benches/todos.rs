@@ -0,0 +1,175 @@
+use axum::body::Body;
+use axum::http::{header, Request};
+use axum::Router;
+use chrono::Utc;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use tower::ServiceExt;
+
+use rust_todo::auth::AuthKeys;
+use rust_todo::config::DEFAULT_API_PREFIX;
+use rust_todo::create_app;
+use rust_todo::repositories::comment::CommentRepositoryForMemory;
+use rust_todo::repositories::health::HealthRepositoryForMemory;
+use rust_todo::repositories::label::{Label, LabelRepositoryForMemory};
+use rust_todo::repositories::todo::{
+    list_todos, merge_todo_rows, CreateTodo, SortField, TodoEntity, TodoListQuery, TodoRepository,
+    TodoRepositoryForMemory,
+};
+use rust_todo::repositories::user::{User, UserRepositoryForMemory};
+use rust_todo::repositories::webhook::WebhookRepositoryForMemory;
+
+const TODO_COUNT: i64 = 10_000;
+const USER_ID: i32 = 1;
+const SECRET: &[u8] = b"bench-secret";
+
+fn labels() -> Vec<Label> {
+    ["work", "home", "errand"]
+        .into_iter()
+        .zip(1..)
+        .map(|(name, id)| Label {
+            id,
+            name: name.to_string(),
+            color: "#1e90ff".to_string(),
+            description: None,
+        })
+        .collect()
+}
+
+// 半数のTodoにラベルを2つ付ける。一覧のレスポンスで最も件数の多い形に寄せている
+fn payloads() -> Vec<CreateTodo> {
+    (0..TODO_COUNT)
+        .map(|i| {
+            let labels = if i % 2 == 0 { vec![1, 2] } else { vec![] };
+            CreateTodo::new(format!("todo number {}", i), labels)
+        })
+        .collect()
+}
+
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Runtime::new().unwrap()
+}
+
+fn seeded_repository(rt: &tokio::runtime::Runtime) -> TodoRepositoryForMemory {
+    let repository = TodoRepositoryForMemory::new(labels());
+    rt.block_on(repository.create_many(USER_ID, payloads()))
+        .expect("failed seed todos");
+    repository
+}
+
+fn memory_all(c: &mut Criterion) {
+    let rt = runtime();
+    let repository = seeded_repository(&rt);
+    let mut group = c.benchmark_group("memory_all");
+    group.bench_function("default_page", |b| {
+        b.to_async(&rt)
+            .iter(|| repository.all(USER_ID, TodoListQuery::default()))
+    });
+    group.bench_function("search_sorted_by_text", |b| {
+        let query = TodoListQuery {
+            q: Some("number 9".to_string()),
+            sort: Some(SortField::Text),
+            ..TodoListQuery::default()
+        };
+        b.to_async(&rt)
+            .iter(|| repository.all(USER_ID, query.clone()))
+    });
+    group.finish();
+}
+
+// ロックや非同期の処理を除いた、絞り込み・並べ替え・ページングのみ
+fn filter_and_sort(c: &mut Criterion) {
+    let todos = entities();
+    let query = TodoListQuery::default();
+    let now = Utc::now();
+    c.bench_function("list_todos", |b| {
+        b.iter(|| list_todos(&todos, &query, now, i64::from))
+    });
+}
+
+fn entities() -> Vec<TodoEntity> {
+    let labels = labels();
+    (1..=TODO_COUNT)
+        .map(|id| {
+            let labels = if id % 2 == 0 {
+                labels[..2].to_vec()
+            } else {
+                vec![]
+            };
+            TodoEntity::new(id, format!("todo number {}", id), labels)
+        })
+        .collect()
+}
+
+fn serialize(c: &mut Criterion) {
+    let todos = entities();
+    c.bench_function("serialize_10k", |b| {
+        b.iter(|| serde_json::to_vec(&todos).unwrap())
+    });
+}
+
+// joinの結果と同じく、ラベルの数だけTodoが重複した行を組み立てる
+fn assemble_labels(c: &mut Criterion) {
+    let rows: Vec<TodoEntity> = entities()
+        .into_iter()
+        .flat_map(|todo| {
+            if todo.labels.is_empty() {
+                return vec![todo];
+            }
+            todo.labels
+                .iter()
+                .map(|label| TodoEntity {
+                    labels: vec![label.clone()],
+                    ..todo.clone()
+                })
+                .collect()
+        })
+        .collect();
+    c.bench_function("merge_todo_rows", |b| {
+        b.iter_batched(|| rows.clone(), merge_todo_rows, BatchSize::LargeInput)
+    });
+}
+
+fn router_list(c: &mut Criterion) {
+    let rt = runtime();
+    let app: Router = create_app(
+        seeded_repository(&rt),
+        LabelRepositoryForMemory::new(),
+        UserRepositoryForMemory::new(),
+        CommentRepositoryForMemory::new(),
+        WebhookRepositoryForMemory::new(),
+        HealthRepositoryForMemory,
+        AuthKeys::new(SECRET),
+        DEFAULT_API_PREFIX,
+    );
+    let token = AuthKeys::new(SECRET)
+        .issue(&User {
+            id: USER_ID,
+            username: "bench".to_string(),
+            password_hash: String::new(),
+            created_at: Utc::now(),
+        })
+        .unwrap();
+    let authorization = format!("Bearer {}", token);
+    c.bench_function("router_get_todos", |b| {
+        b.to_async(&rt).iter(|| async {
+            let req = Request::builder()
+                .uri("/api/v1/todos?limit=100")
+                .header(header::AUTHORIZATION, &authorization)
+                .body(Body::empty())
+                .unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert!(res.status().is_success());
+            hyper::body::to_bytes(res.into_body()).await.unwrap()
+        })
+    });
+}
+
+criterion_group!(
+    benches,
+    memory_all,
+    filter_and_sort,
+    serialize,
+    assemble_labels,
+    router_list
+);
+criterion_main!(benches);
@@ -0,0 +1,6 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // protocをインストールしていない環境でもビルドできるよう、同梱のバイナリでgRPCのコードを生成する
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    tonic_build::compile_protos("proto/todo.proto")?;
+    Ok(())
+}
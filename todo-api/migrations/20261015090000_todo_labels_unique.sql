@@ -0,0 +1,7 @@
+DELETE FROM todo_labels a
+USING todo_labels b
+WHERE a.id > b.id
+  AND a.todo_id = b.todo_id
+  AND a.label_id = b.label_id;
+
+CREATE UNIQUE INDEX todo_labels_todo_id_label_id_key ON todo_labels (todo_id, label_id);
@@ -0,0 +1,4 @@
+-- 既存のTodoは所有者が存在しないため、どのユーザーからも参照されない
+ALTER TABLE todos ADD COLUMN user_id INTEGER REFERENCES users (id);
+
+CREATE INDEX todos_user_id_idx ON todos (user_id);
@@ -0,0 +1,2 @@
+-- 楽観的排他制御用。更新のたびに1ずつ増える
+ALTER TABLE todos ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,3 @@
+ALTER TABLE todos ADD COLUMN due_date TIMESTAMPTZ;
+
+CREATE INDEX todos_due_date_idx ON todos (due_date) WHERE due_date IS NOT NULL;
@@ -0,0 +1,2 @@
+-- 1: low, 2: medium, 3: high。数値で持つことで優先度順に並べ替えられる
+ALTER TABLE todos ADD COLUMN priority SMALLINT NOT NULL DEFAULT 2 CHECK (priority BETWEEN 1 AND 3);
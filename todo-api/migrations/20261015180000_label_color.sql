@@ -0,0 +1,3 @@
+ALTER TABLE labels
+    ADD COLUMN color VARCHAR(7) NOT NULL DEFAULT '#808080' CHECK (color ~ '^#[0-9A-Fa-f]{6}$'),
+    ADD COLUMN description TEXT;
@@ -0,0 +1,24 @@
+-- 大文字小文字違いの重複は最も古いラベルへ寄せてから削除する
+INSERT INTO todo_labels (todo_id, label_id)
+SELECT tl.todo_id, keep.id
+FROM todo_labels tl
+JOIN labels dup ON dup.id = tl.label_id
+JOIN labels keep ON lower(keep.name) = lower(dup.name) AND keep.id < dup.id
+WHERE NOT EXISTS (
+    SELECT 1 FROM labels older
+    WHERE lower(older.name) = lower(dup.name) AND older.id < keep.id
+)
+ON CONFLICT (todo_id, label_id) DO NOTHING;
+
+DELETE FROM todo_labels tl
+USING labels dup, labels keep
+WHERE dup.id = tl.label_id
+  AND lower(keep.name) = lower(dup.name)
+  AND keep.id < dup.id;
+
+DELETE FROM labels dup
+USING labels keep
+WHERE lower(keep.name) = lower(dup.name)
+  AND keep.id < dup.id;
+
+CREATE UNIQUE INDEX labels_lower_name_key ON labels (lower(name));
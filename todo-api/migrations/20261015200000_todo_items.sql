@@ -0,0 +1,10 @@
+-- Todoを完全に削除した場合はチェックリストの項目も削除する
+CREATE TABLE todo_items (
+  id SERIAL PRIMARY KEY,
+  todo_id INTEGER NOT NULL REFERENCES todos (id) ON DELETE CASCADE,
+  text TEXT NOT NULL,
+  completed BOOLEAN NOT NULL DEFAULT false,
+  position INTEGER NOT NULL
+);
+
+CREATE INDEX todo_items_todo_id_position_idx ON todo_items (todo_id, position);
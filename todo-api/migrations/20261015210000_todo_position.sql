@@ -0,0 +1,7 @@
+-- 手動で並べ替えた表示順。大きいほど上に表示し、移動時は前後のTodoの中間の値を使う
+ALTER TABLE todos ADD COLUMN position BIGINT NOT NULL DEFAULT 0;
+
+-- 既存のTodoは従来の既定の並び(新しい順)を保つ
+UPDATE todos SET position = id::bigint * 1024;
+
+CREATE INDEX todos_user_id_position_idx ON todos (user_id, position DESC, id DESC);
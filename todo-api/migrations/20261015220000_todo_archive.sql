@@ -0,0 +1,2 @@
+-- NULLは未アーカイブ。アーカイブしたTodoは既定の一覧から除く
+ALTER TABLE todos ADD COLUMN archived_at TIMESTAMPTZ;
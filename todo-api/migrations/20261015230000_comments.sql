@@ -0,0 +1,10 @@
+-- Todoを完全に削除した場合はコメントも削除する
+CREATE TABLE comments (
+  id SERIAL PRIMARY KEY,
+  todo_id INTEGER NOT NULL REFERENCES todos (id) ON DELETE CASCADE,
+  author TEXT NOT NULL,
+  body TEXT NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX comments_todo_id_created_at_idx ON comments (todo_id, created_at DESC, id DESC);
@@ -0,0 +1,12 @@
+-- Todoの変更履歴。Todoを完全に削除した場合は履歴も削除する
+CREATE TABLE todo_activities (
+  id SERIAL PRIMARY KEY,
+  todo_id INTEGER NOT NULL REFERENCES todos (id) ON DELETE CASCADE,
+  action TEXT NOT NULL,
+  old_value JSONB,
+  new_value JSONB,
+  actor_id INTEGER NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX todo_activities_todo_id_created_at_idx ON todo_activities (todo_id, created_at DESC, id DESC);
@@ -0,0 +1,15 @@
+-- Todoのイベントの通知先。eventsが空の場合は全てのイベントを通知する
+CREATE TABLE webhooks (
+  id SERIAL PRIMARY KEY,
+  user_id INTEGER NOT NULL REFERENCES users (id),
+  url TEXT NOT NULL,
+  secret TEXT NOT NULL,
+  events TEXT[] NOT NULL DEFAULT '{}',
+  -- 最後の配信結果。応答を受け取れなかった場合、last_statusはNULLになる
+  last_status INTEGER,
+  last_error TEXT,
+  last_delivered_at TIMESTAMPTZ,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now()
+);
+
+CREATE INDEX webhooks_user_id_idx ON webhooks (user_id);
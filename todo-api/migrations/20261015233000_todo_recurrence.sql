@@ -0,0 +1,4 @@
+-- 繰り返しの指定。nullの場合は繰り返さない
+ALTER TABLE todos ADD COLUMN recurrence JSONB;
+-- 完了時に作成した次の回。作成済みの場合は再度完了しても作成しない
+ALTER TABLE todos ADD COLUMN next_occurrence_id INTEGER REFERENCES todos (id) ON DELETE SET NULL;
@@ -0,0 +1,7 @@
+-- 通知する日時と、通知済みの日時。remind_atを変更するとreminded_atは消える
+ALTER TABLE todos ADD COLUMN remind_at TIMESTAMPTZ;
+ALTER TABLE todos ADD COLUMN reminded_at TIMESTAMPTZ;
+
+-- 通知待ちのTodoのみを対象に、通知の時刻順に探す
+CREATE INDEX todos_pending_reminder_idx ON todos (remind_at)
+  WHERE remind_at IS NOT NULL AND reminded_at IS NULL;
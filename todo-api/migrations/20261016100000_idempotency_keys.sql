@@ -0,0 +1,12 @@
+-- Idempotency-Keyを付けた作成のリクエスト。同じキーの再送には作成済みのレスポンスを返す
+CREATE TABLE idempotency_keys (
+  user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  key TEXT NOT NULL,
+  fingerprint TEXT NOT NULL,
+  -- 作成を終えるまではNULL
+  response JSONB,
+  created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+  PRIMARY KEY (user_id, key)
+);
+
+CREATE INDEX idempotency_keys_created_at_idx ON idempotency_keys (created_at);
@@ -0,0 +1,3 @@
+-- 作成時の重複の確認で、ユーザーの未完了のTodoをテキストで探す
+CREATE INDEX todos_open_text_idx ON todos (user_id, text)
+  WHERE NOT completed AND deleted_at IS NULL AND archived_at IS NULL;
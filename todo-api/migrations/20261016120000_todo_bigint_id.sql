@@ -0,0 +1,7 @@
+-- 長期間の運用や大きなidを含むデータの取り込みで32bitを使い切らないよう、Todoのidを64bitにする
+ALTER SEQUENCE todos_id_seq AS BIGINT;
+ALTER TABLE todos ALTER COLUMN id TYPE BIGINT, ALTER COLUMN next_occurrence_id TYPE BIGINT;
+ALTER TABLE todo_labels ALTER COLUMN todo_id TYPE BIGINT;
+ALTER TABLE todo_items ALTER COLUMN todo_id TYPE BIGINT;
+ALTER TABLE comments ALTER COLUMN todo_id TYPE BIGINT;
+ALTER TABLE todo_activities ALTER COLUMN todo_id TYPE BIGINT;
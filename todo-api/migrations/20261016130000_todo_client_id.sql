@@ -0,0 +1,3 @@
+-- オフラインのクライアントが採番したid。同期の再送で重複して作成しないよう、ユーザーごとに一意にする
+ALTER TABLE todos ADD COLUMN client_id UUID;
+ALTER TABLE todos ADD CONSTRAINT todos_user_id_client_id_key UNIQUE (user_id, client_id);
@@ -0,0 +1,19 @@
+-- Todoの変更と同じトランザクションで記録し、commitの後で配信タスクがWebhookへ送る
+-- 送るまでプロセスが落ちても行は残るため、再起動後に続きから配信する
+CREATE TABLE events (
+  id BIGSERIAL PRIMARY KEY,
+  user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+  type TEXT NOT NULL,
+  payload JSONB NOT NULL,
+  created_at TIMESTAMPTZ NOT NULL,
+  -- 配信を終えるまではNULL
+  published_at TIMESTAMPTZ,
+  attempts INTEGER NOT NULL DEFAULT 0,
+  next_attempt_at TIMESTAMPTZ NOT NULL,
+  last_error TEXT,
+  -- 再送の上限に達した場合に入る。以降は配信しない
+  failed_at TIMESTAMPTZ
+);
+
+CREATE INDEX events_pending_idx ON events (next_attempt_at, id)
+  WHERE published_at IS NULL AND failed_at IS NULL;
@@ -0,0 +1,2 @@
+-- ラベルはユーザーに属さないため、ラベルの変更のイベントはuser_idをNULLで記録し、全てのユーザーへ配信する
+ALTER TABLE events ALTER COLUMN user_id DROP NOT NULL;
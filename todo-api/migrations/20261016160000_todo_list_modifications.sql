@@ -0,0 +1,6 @@
+-- 完全な削除・並べ替え・ラベルの変更など、Todoのupdated_atやdeleted_atに残らない一覧の変更の日時
+-- 一覧のLast-Modifiedは、これとTodoのupdated_at・deleted_atのうち最も新しいものにする
+CREATE TABLE todo_list_modifications (
+  user_id INTEGER PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+  modified_at TIMESTAMPTZ NOT NULL
+);
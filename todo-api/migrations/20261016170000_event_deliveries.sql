@@ -0,0 +1,11 @@
+-- 取り出したイベントは、この日時までは他のインスタンスから取り出さない
+-- 取り出しはすぐにcommitするため、配信の間は行をロックしない。配信中に落ちた場合は過ぎた後に取り出し直す
+ALTER TABLE events ADD COLUMN locked_until TIMESTAMPTZ;
+
+-- 配信先・宛先ごとに送り終えたイベント。送り直す際は送り終えた配信先へは送らない
+CREATE TABLE event_deliveries (
+  event_id BIGINT NOT NULL REFERENCES events (id) ON DELETE CASCADE,
+  sink TEXT NOT NULL,
+  delivered_at TIMESTAMPTZ NOT NULL,
+  PRIMARY KEY (event_id, sink)
+);
@@ -0,0 +1,101 @@
+-- Postgres側のmigrationsを適用し終えた状態と同じスキーマ
+-- 日時はsqlxの形式(UTCの"YYYY-MM-DD HH:MM:SS.fff")の文字列で持ち、文字列の比較で前後を判定する
+CREATE TABLE users (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  username TEXT NOT NULL UNIQUE,
+  password_hash TEXT NOT NULL DEFAULT '',
+  created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now'))
+);
+
+-- 1: low, 2: medium, 3: high
+CREATE TABLE todos (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  text TEXT NOT NULL,
+  completed BOOLEAN NOT NULL DEFAULT false,
+  created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+  updated_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now')),
+  user_id INTEGER REFERENCES users (id),
+  deleted_at TEXT,
+  version INTEGER NOT NULL DEFAULT 1,
+  due_date TEXT,
+  priority INTEGER NOT NULL DEFAULT 2 CHECK (priority BETWEEN 1 AND 3),
+  position INTEGER NOT NULL DEFAULT 0,
+  archived_at TEXT,
+  recurrence TEXT,
+  next_occurrence_id INTEGER REFERENCES todos (id) ON DELETE SET NULL,
+  remind_at TEXT,
+  reminded_at TEXT
+);
+
+CREATE INDEX todos_user_id_idx ON todos (user_id);
+CREATE INDEX todos_deleted_at_idx ON todos (deleted_at) WHERE deleted_at IS NOT NULL;
+CREATE INDEX todos_due_date_idx ON todos (due_date) WHERE due_date IS NOT NULL;
+CREATE INDEX todos_user_id_position_idx ON todos (user_id, position DESC, id DESC);
+CREATE INDEX todos_pending_reminder_idx ON todos (remind_at)
+  WHERE remind_at IS NOT NULL AND reminded_at IS NULL;
+
+CREATE TABLE labels (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  name TEXT NOT NULL,
+  color TEXT NOT NULL DEFAULT '#808080'
+    CHECK (length(color) = 7 AND color GLOB '#[0-9A-Fa-f][0-9A-Fa-f][0-9A-Fa-f][0-9A-Fa-f][0-9A-Fa-f][0-9A-Fa-f]'),
+  description TEXT
+);
+
+CREATE UNIQUE INDEX labels_lower_name_key ON labels (lower(name));
+
+CREATE TABLE todo_labels (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  todo_id INTEGER NOT NULL REFERENCES todos (id) DEFERRABLE INITIALLY DEFERRED,
+  label_id INTEGER NOT NULL REFERENCES labels (id) DEFERRABLE INITIALLY DEFERRED
+);
+
+CREATE UNIQUE INDEX todo_labels_todo_id_label_id_key ON todo_labels (todo_id, label_id);
+
+CREATE TABLE todo_items (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  todo_id INTEGER NOT NULL REFERENCES todos (id) ON DELETE CASCADE,
+  text TEXT NOT NULL,
+  completed BOOLEAN NOT NULL DEFAULT false,
+  position INTEGER NOT NULL
+);
+
+CREATE INDEX todo_items_todo_id_position_idx ON todo_items (todo_id, position);
+
+CREATE TABLE comments (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  todo_id INTEGER NOT NULL REFERENCES todos (id) ON DELETE CASCADE,
+  author TEXT NOT NULL,
+  body TEXT NOT NULL,
+  created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now'))
+);
+
+CREATE INDEX comments_todo_id_created_at_idx ON comments (todo_id, created_at DESC, id DESC);
+
+-- old_value・new_valueはJSONの文字列
+CREATE TABLE todo_activities (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  todo_id INTEGER NOT NULL REFERENCES todos (id) ON DELETE CASCADE,
+  action TEXT NOT NULL,
+  old_value TEXT,
+  new_value TEXT,
+  actor_id INTEGER NOT NULL,
+  created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now'))
+);
+
+CREATE INDEX todo_activities_todo_id_created_at_idx ON todo_activities (todo_id, created_at DESC, id DESC);
+
+-- eventsはイベント名のJSON配列。空の場合は全てのイベントを通知する
+CREATE TABLE webhooks (
+  id INTEGER PRIMARY KEY AUTOINCREMENT,
+  user_id INTEGER NOT NULL REFERENCES users (id),
+  url TEXT NOT NULL,
+  secret TEXT NOT NULL,
+  events TEXT NOT NULL DEFAULT '[]',
+  last_status INTEGER,
+  last_error TEXT,
+  last_delivered_at TEXT,
+  created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%d %H:%M:%f', 'now'))
+);
+
+CREATE INDEX webhooks_user_id_idx ON webhooks (user_id);
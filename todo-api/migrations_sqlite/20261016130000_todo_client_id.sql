@@ -0,0 +1,3 @@
+-- オフラインのクライアントが採番したid。sqlxはUUIDを16バイトのBLOBとして保存する
+ALTER TABLE todos ADD COLUMN client_id BLOB;
+CREATE UNIQUE INDEX todos_user_id_client_id_key ON todos (user_id, client_id);
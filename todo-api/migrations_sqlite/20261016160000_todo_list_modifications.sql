@@ -0,0 +1,5 @@
+-- 一覧のLast-Modifiedに使う、Todoのupdated_atやdeleted_atに残らない変更の日時
+CREATE TABLE todo_list_modifications (
+  user_id INTEGER PRIMARY KEY REFERENCES users (id) ON DELETE CASCADE,
+  modified_at TEXT NOT NULL
+);
@@ -0,0 +1,174 @@
+use std::sync::Arc;
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use axum::async_trait;
+use axum::extract::{Extension, FromRequest, RequestParts};
+use axum::http::header::AUTHORIZATION;
+use axum::http::StatusCode;
+use chrono::{Duration, Utc};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use serde::{Deserialize, Serialize};
+
+use crate::error::AppError;
+use crate::repositories::user::User;
+
+const TOKEN_LIFETIME_HOURS: i64 = 24;
+
+#[derive(Clone)]
+pub struct AuthKeys {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+}
+
+impl AuthKeys {
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            encoding: EncodingKey::from_secret(secret),
+            decoding: DecodingKey::from_secret(secret),
+        }
+    }
+
+    pub fn issue(&self, user: &User) -> anyhow::Result<String> {
+        let claims = Claims {
+            sub: user.id,
+            username: user.username.clone(),
+            exp: (Utc::now() + Duration::hours(TOKEN_LIFETIME_HOURS)).timestamp(),
+        };
+        Ok(encode(&Header::default(), &claims, &self.encoding)?)
+    }
+
+    fn verify(&self, token: &str) -> anyhow::Result<Claims> {
+        let data = decode::<Claims>(token, &self.decoding, &Validation::default())?;
+        Ok(data.claims)
+    }
+
+    // 有効なトークンの場合のみユーザーIDを返す。認証前のmiddlewareでリクエストを区別するために使う
+    pub fn user_id(&self, token: &str) -> Option<i32> {
+        self.verify(token).ok().map(|claims| claims.sub)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    sub: i32,
+    username: String,
+    exp: i64,
+}
+
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|e| anyhow::anyhow!("fail hash password: {}", e))?;
+    Ok(hash.to_string())
+}
+
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    PasswordHash::new(password_hash)
+        .map(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+        .unwrap_or(false)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthUser {
+    pub id: i32,
+    pub username: String,
+}
+
+pub(crate) fn unauthorized(message: &str) -> AppError {
+    AppError::new(StatusCode::UNAUTHORIZED, "unauthorized", message)
+}
+
+#[async_trait]
+impl<B> FromRequest<B> for AuthUser
+where
+    B: Send,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let Extension(keys) = Extension::<Arc<AuthKeys>>::from_request(req)
+            .await
+            .map_err(|e| AppError::internal(e.to_string()))?;
+
+        let header = req
+            .headers()
+            .and_then(|headers| headers.get(AUTHORIZATION))
+            .ok_or_else(|| unauthorized("Missing Authorization header"))?;
+        let token = header
+            .to_str()
+            .ok()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| unauthorized("Malformed Authorization header"))?;
+        let claims = keys
+            .verify(token)
+            .map_err(|_| unauthorized("Invalid or expired token"))?;
+
+        Ok(AuthUser {
+            id: claims.sub,
+            username: claims.username,
+        })
+    }
+}
+
+#[cfg(test)]
+pub mod test_utils {
+    use super::*;
+
+    pub const TEST_SECRET: &[u8] = b"test-secret";
+
+    pub fn test_keys() -> AuthKeys {
+        AuthKeys::new(TEST_SECRET)
+    }
+
+    // ルーターのテストで使う、インメモリのユーザーに対応したトークンを発行する
+    pub fn test_token(id: i32) -> String {
+        let user = User {
+            id,
+            username: format!("user{}", id),
+            password_hash: String::new(),
+            created_at: Utc::now(),
+        };
+        test_keys().issue(&user).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::test_utils::*;
+    use super::*;
+
+    #[test]
+    fn password_hash_test() {
+        let hash = hash_password("correct horse").unwrap();
+        assert_ne!("correct horse", hash);
+        assert!(verify_password("correct horse", &hash));
+        assert!(!verify_password("battery staple", &hash));
+        assert!(!verify_password("correct horse", "not a hash"));
+    }
+
+    #[test]
+    fn token_test() {
+        let keys = test_keys();
+        let claims = keys.verify(&test_token(1)).unwrap();
+        assert_eq!(1, claims.sub);
+
+        assert!(AuthKeys::new(b"other-secret")
+            .verify(&test_token(1))
+            .is_err());
+
+        let expired = Claims {
+            sub: 1,
+            username: "user1".to_string(),
+            exp: (Utc::now() - Duration::hours(1)).timestamp(),
+        };
+        let token = encode(&Header::default(), &expired, &keys.encoding).unwrap();
+        assert!(keys.verify(&token).is_err());
+    }
+}
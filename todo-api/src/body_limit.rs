@@ -0,0 +1,124 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use axum::body::{Body, Bytes};
+use axum::http::header::CONTENT_LENGTH;
+use axum::http::Request;
+use axum::response::{IntoResponse, Response};
+use axum::{BoxError, Router};
+use futures_util::StreamExt;
+use thiserror::Error;
+use tower::{Layer, Service};
+
+use crate::error::AppError;
+
+// 一括で登録するルート。1件ずつのAPIより大きな本文を受け付ける
+// API_PREFIXの下と非推奨のプレフィックスなしの両方にあるため、パスの末尾で判定する
+const BULK_PATHS: [&str; 3] = ["/import", "/import/csv", "/todos/batch"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BodyLimits {
+    pub api: usize,
+    pub bulk: usize,
+}
+
+impl BodyLimits {
+    fn for_path(&self, path: &str) -> usize {
+        if BULK_PATHS.iter().any(|bulk| path.ends_with(bulk)) {
+            self.bulk
+        } else {
+            self.api
+        }
+    }
+}
+
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+#[error("Request body exceeds the limit of {limit} bytes")]
+pub struct BodyTooLarge {
+    pub limit: usize,
+}
+
+// 本文を読む途中で上限を超えた場合、エラーはextractorのrejectionに包まれて届くため、原因をたどって探す
+pub fn exceeded(e: &(dyn std::error::Error + 'static)) -> Option<BodyTooLarge> {
+    let mut source = Some(e);
+    while let Some(e) = source {
+        if let Some(e) = e.downcast_ref::<BodyTooLarge>() {
+            return Some(*e);
+        }
+        source = e.source();
+    }
+    None
+}
+
+// Content-Lengthで超過がわかる場合は読まずに413を返し、chunkedの場合は読んだ分が上限を超えた時点で打ち切る
+pub fn with_body_limits(app: Router, limits: BodyLimits) -> Router {
+    app.layer(BodyLimitLayer { limits })
+}
+
+#[derive(Clone)]
+struct BodyLimitLayer {
+    limits: BodyLimits,
+}
+
+impl<S> Layer<S> for BodyLimitLayer {
+    type Service = BodyLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        BodyLimit {
+            inner,
+            limits: self.limits,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct BodyLimit<S> {
+    inner: S,
+    limits: BodyLimits,
+}
+
+fn limited(body: Body, limit: usize) -> Body {
+    let mut read = 0;
+    Body::wrap_stream(body.map(move |chunk| -> Result<Bytes, BoxError> {
+        let chunk = chunk?;
+        read += chunk.len();
+        if read > limit {
+            return Err(BodyTooLarge { limit }.into());
+        }
+        Ok(chunk)
+    }))
+}
+
+impl<S> Service<Request<Body>> for BodyLimit<S>
+where
+    S: Service<Request<Body>, Response = Response>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let limit = self.limits.for_path(req.uri().path());
+        let content_length = req
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok());
+        let req = match content_length {
+            Some(length) if length > limit => {
+                let res = AppError::from(BodyTooLarge { limit }).into_response();
+                return Box::pin(async move { Ok(res) });
+            }
+            // 長さが宣言されている場合、hyperがそれ以上は読ませない
+            Some(_) => req,
+            None => req.map(|body| limited(body, limit)),
+        };
+        Box::pin(self.inner.call(req))
+    }
+}
@@ -0,0 +1,185 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use axum::body::{boxed, Body, Bytes};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::{HeaderMap, Request};
+use axum::response::Response;
+use axum::BoxError;
+use http_body::Body as _;
+use serde_json::Value;
+use tower::{Layer, Service};
+
+use crate::handlers::NDJSON_CONTENT_TYPE;
+
+// これを超える本文と、長さのわからない本文はバッファせずにそのまま流す
+const LOG_BODY_LIMIT: u64 = 16 * 1024;
+// 少しずつ送るレスポンスは、まとめて読むとクライアントへ届かなくなるため対象外にする
+const STREAMING_CONTENT_TYPES: [&str; 2] = [NDJSON_CONTENT_TYPE, "text/event-stream"];
+const REDACTED_FIELDS: [&str; 2] = ["password", "token"];
+const REDACTED: &str = "[REDACTED]";
+
+// リクエストとレスポンスの本文をdebugレベルで出力するか。Extensionが未設定の場合は出力しない
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogBodies(pub bool);
+
+// 圧縮前の本文を記録するため、compressionより内側に置く
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BodyLogLayer;
+
+impl<S> Layer<S> for BodyLogLayer {
+    type Service = BodyLog<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        BodyLog { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct BodyLog<S> {
+    inner: S,
+}
+
+impl<S> Service<Request<Body>> for BodyLog<S>
+where
+    S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let enabled = req.extensions().get::<LogBodies>().is_some_and(|log| log.0);
+        if !enabled {
+            return Box::pin(self.inner.call(req));
+        }
+        // poll_readyを済ませたinnerで呼び出すため、cloneと入れ替える
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let body = if is_capturable(&parts.headers, body.size_hint().exact()) {
+                match hyper::body::to_bytes(body).await {
+                    Ok(bytes) => {
+                        log_body("request", &bytes);
+                        Body::from(bytes)
+                    }
+                    Err(e) => failed(e),
+                }
+            } else {
+                body
+            };
+            let res = inner.call(Request::from_parts(parts, body)).await?;
+
+            let (parts, body) = res.into_parts();
+            let body = if is_capturable(&parts.headers, body.size_hint().exact()) {
+                match hyper::body::to_bytes(body).await {
+                    Ok(bytes) => {
+                        log_body("response", &bytes);
+                        boxed(Body::from(bytes))
+                    }
+                    Err(e) => boxed(failed(e)),
+                }
+            } else {
+                body
+            };
+            Ok(Response::from_parts(parts, body))
+        })
+    }
+}
+
+fn is_capturable(headers: &HeaderMap, size: Option<u64>) -> bool {
+    let streaming = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| {
+            STREAMING_CONTENT_TYPES
+                .iter()
+                .any(|streaming| content_type.starts_with(streaming))
+        });
+    !streaming && matches!(size, Some(size) if size > 0 && size <= LOG_BODY_LIMIT)
+}
+
+fn log_body(kind: &str, bytes: &[u8]) {
+    tracing::debug!(body = %redact(bytes), "{} body", kind);
+}
+
+// 読み切れなかった場合も、エラーは本文として下流へそのまま渡す
+pub(crate) fn failed(e: impl Into<BoxError>) -> Body {
+    let e = e.into();
+    Body::wrap_stream(futures_util::stream::once(async move {
+        Err::<Bytes, BoxError>(e)
+    }))
+}
+
+// JSONとして読める本文は、ネストしたオブジェクトも含めてpassword・tokenの値を伏せる
+fn redact(bytes: &[u8]) -> String {
+    match serde_json::from_slice::<Value>(bytes) {
+        Ok(mut value) => {
+            redact_value(&mut value);
+            value.to_string()
+        }
+        Err(_) => String::from_utf8_lossy(bytes).into_owned(),
+    }
+}
+
+fn redact_value(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if REDACTED_FIELDS.contains(&key.as_str()) {
+                    *value = Value::String(REDACTED.to_string());
+                } else {
+                    redact_value(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact_value),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    #[test]
+    fn should_redact_secret_fields() {
+        let body = br#"{"username":"alice","password":"hunter22","user":{"token":"abc"},"items":[{"token":"def","text":"keep"}]}"#;
+        let redacted: Value = serde_json::from_str(&redact(body)).unwrap();
+        assert_eq!("alice", redacted["username"]);
+        assert_eq!(REDACTED, redacted["password"]);
+        assert_eq!(REDACTED, redacted["user"]["token"]);
+        assert_eq!(REDACTED, redacted["items"][0]["token"]);
+        assert_eq!("keep", redacted["items"][0]["text"]);
+        assert!(!redact(body).contains("hunter22"));
+    }
+
+    #[test]
+    fn should_keep_non_json_body_as_text() {
+        assert_eq!("text\nfirst\n", redact(b"text\nfirst\n"));
+    }
+
+    #[test]
+    fn should_skip_streaming_and_large_bodies() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        assert!(is_capturable(&headers, Some(10)));
+        assert!(!is_capturable(&headers, Some(0)));
+        assert!(!is_capturable(&headers, Some(LOG_BODY_LIMIT + 1)));
+        assert!(!is_capturable(&headers, None));
+
+        for content_type in [NDJSON_CONTENT_TYPE, "text/event-stream; charset=utf-8"] {
+            headers.insert(CONTENT_TYPE, HeaderValue::from_static(content_type));
+            assert!(!is_capturable(&headers, Some(10)));
+        }
+    }
+}
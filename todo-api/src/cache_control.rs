@@ -0,0 +1,87 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use axum::body::Body;
+use axum::extract::Extension;
+use axum::http::header::CACHE_CONTROL;
+use axum::http::{HeaderValue, Request};
+use axum::response::Response;
+use tower::{Layer, Service};
+
+// ルートごとのCache-Controlの値。Extensionが未設定の場合はデフォルト値を使う
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachePolicy {
+    // GET /labels。変更が少ないため、ブラウザには短時間そのまま使わせる
+    pub labels: HeaderValue,
+    // GET /todos。使う前に毎回Last-ModifiedとETagで確かめさせる
+    pub todos: HeaderValue,
+    // POST・PUT・PATCH・DELETEなど、変更を伴うメソッドのレスポンス
+    pub mutations: HeaderValue,
+}
+
+impl Default for CachePolicy {
+    fn default() -> Self {
+        CachePolicy {
+            labels: HeaderValue::from_static("private, max-age=60"),
+            todos: HeaderValue::from_static("no-cache"),
+            mutations: HeaderValue::from_static("no-store"),
+        }
+    }
+}
+
+pub fn cache_policy(policy: Option<Extension<CachePolicy>>) -> CachePolicy {
+    policy.map(|Extension(policy)| policy).unwrap_or_default()
+}
+
+// 変更を伴うメソッドのレスポンスを、エラーも含めて保存させない
+// ハンドラが付けたCache-Controlはそのまま残す
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MutationCacheControlLayer;
+
+impl<S> Layer<S> for MutationCacheControlLayer {
+    type Service = MutationCacheControl<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        MutationCacheControl { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct MutationCacheControl<S> {
+    inner: S,
+}
+
+impl<S> Service<Request<Body>> for MutationCacheControl<S>
+where
+    S: Service<Request<Body>, Response = Response>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let cache_control = (!req.method().is_safe()).then(|| {
+            req.extensions()
+                .get::<CachePolicy>()
+                .cloned()
+                .unwrap_or_default()
+                .mutations
+        });
+        let future = self.inner.call(req);
+        Box::pin(async move {
+            let mut res = future.await?;
+            if let Some(cache_control) = cache_control {
+                res.headers_mut()
+                    .entry(CACHE_CONTROL)
+                    .or_insert(cache_control);
+            }
+            Ok(res)
+        })
+    }
+}
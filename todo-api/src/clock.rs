@@ -0,0 +1,86 @@
+use std::fmt;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+
+// 現在時刻の取得元。Utc::now()を直接呼ばず、テストでは時刻を固定できるようにする
+pub trait Clock: fmt::Debug + Send + Sync + 'static {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub type SharedClock = Arc<dyn Clock>;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+#[cfg(test)]
+pub mod test_utils {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Mutex;
+
+    use chrono::{Duration, TimeZone};
+
+    use super::*;
+
+    // 進めるまで同じ時刻を返す。クローンした時計は時刻を共有する
+    #[derive(Debug, Clone)]
+    pub struct MockClock {
+        now: Arc<Mutex<DateTime<Utc>>>,
+        panic_next: Arc<AtomicBool>,
+    }
+
+    impl MockClock {
+        pub fn new(now: DateTime<Utc>) -> Self {
+            MockClock {
+                now: Arc::new(Mutex::new(now)),
+                panic_next: Arc::default(),
+            }
+        }
+
+        pub fn advance(&self, duration: Duration) {
+            *self.now.lock().unwrap() += duration;
+        }
+
+        // 次のnowの呼び出しを1回だけpanicさせる。ハンドラの途中で落ちた場合を再現する
+        pub fn panic_on_next_now(&self) {
+            self.panic_next.store(true, Ordering::SeqCst);
+        }
+    }
+
+    // DBに保存しても精度が落ちないよう、秒未満を切り捨てた現在時刻から始める
+    impl Default for MockClock {
+        fn default() -> Self {
+            MockClock::new(Utc.timestamp_opt(Utc::now().timestamp(), 0).unwrap())
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> DateTime<Utc> {
+            if self.panic_next.swap(false, Ordering::SeqCst) {
+                panic!("injected panic in MockClock::now");
+            }
+            *self.now.lock().unwrap()
+        }
+    }
+
+    #[cfg(test)]
+    mod test {
+        use super::*;
+
+        #[test]
+        fn should_share_time_between_clones() {
+            let clock = MockClock::default();
+            let start = clock.now();
+            let cloned = clock.clone();
+            cloned.advance(Duration::minutes(5));
+            assert_eq!(start + Duration::minutes(5), clock.now());
+            assert_eq!(0, start.timestamp_subsec_nanos());
+        }
+    }
+}
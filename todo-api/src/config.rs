@@ -0,0 +1,878 @@
+use std::env;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+use hyper::header::HeaderValue;
+use thiserror::Error;
+
+use crate::cache_control::CachePolicy;
+use crate::naming::Naming;
+
+const DEFAULT_HOST: IpAddr = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+const DEFAULT_PORT: u16 = 8000;
+const DEFAULT_CORS_ORIGIN: &str = "http://localhost:3000";
+const DEFAULT_CORS_MAX_AGE_SECS: u32 = 10 * 60;
+const DEFAULT_DB_MAX_CONNECTIONS: u32 = 10;
+const DEFAULT_DB_ACQUIRE_TIMEOUT_SECS: u32 = 30;
+const DEFAULT_DB_IDLE_TIMEOUT_SECS: u32 = 10 * 60;
+const DEFAULT_DB_CONNECT_MAX_ATTEMPTS: u32 = 5;
+const DEFAULT_DB_CONNECT_RETRY_DELAY_MS: u32 = 500;
+pub const DEFAULT_TODO_BATCH_LIMIT: u32 = 500;
+const DEFAULT_TRASH_PURGE_INTERVAL_SECS: u32 = 3600;
+const DEFAULT_IDEMPOTENCY_KEY_TTL_SECS: u32 = 24 * 60 * 60;
+const DEFAULT_WEBHOOK_MAX_ATTEMPTS: u32 = 5;
+const DEFAULT_WEBHOOK_RETRY_DELAY_MS: u32 = 1000;
+const DEFAULT_REMINDER_INTERVAL_SECS: u32 = 60;
+const DEFAULT_CACHE_MAX_ENTRIES: u32 = 1000;
+const DEFAULT_REQUEST_TIMEOUT_SECS: u32 = 30;
+const DEFAULT_MAX_CONCURRENT_REQUESTS: u32 = 512;
+const DEFAULT_RATE_LIMIT_BURST: u32 = 20;
+const DEFAULT_BODY_LIMIT_BYTES: u32 = 64 * 1024;
+const DEFAULT_BULK_BODY_LIMIT_BYTES: u32 = 10 * 1024 * 1024;
+pub const DEFAULT_API_PREFIX: &str = "/api/v1";
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub host: IpAddr,
+    pub port: u16,
+    // 未設定の場合、gRPCのサーバーは起動しない
+    pub grpc_port: Option<u16>,
+    pub storage: Storage,
+    // STORAGE=memoryの場合は使わないため、未設定なら空になる
+    pub database_url: String,
+    // STORAGE=memoryの場合のみ。設定するとTodo・ラベル・ユーザーをこのファイルへ保存し、起動時に復元する
+    pub persist_path: Option<PathBuf>,
+    pub jwt_secret: String,
+    // CORS_ORIGINにカンマ区切りで並べたオリジン。Cookieを送れるよう、*は受け付けない
+    pub cors_origins: Vec<HeaderValue>,
+    // ブラウザがpreflightの結果を使い回す期間
+    pub cors_max_age: Duration,
+    // GET /labels・GET /todos・変更を伴うメソッドのレスポンスに付けるCache-Control
+    pub cache_policy: CachePolicy,
+    // X-Namingのないリクエストへ返すJSONのキーの命名規則。リクエストのbodyはどちらも受け付ける
+    pub json_naming: Naming,
+    pub run_migrations: bool,
+    pub db_max_connections: u32,
+    // 負荷が上がった直後の接続待ちを減らすため、アイドルでも保持しておく接続数
+    pub db_min_connections: u32,
+    // 接続を待つ上限。超えたリクエストは503で返す
+    pub db_acquire_timeout: Duration,
+    // 最小数を超えた接続は、この時間使われなければ閉じる
+    pub db_idle_timeout: Duration,
+    pub db_connect_max_attempts: u32,
+    pub db_connect_retry_delay: Duration,
+    pub todo_batch_limit: usize,
+    // 無効にすると、リクエストのbodyの未知のフィールドを従来どおり無視する
+    pub strict_requests: bool,
+    // 有効にすると、?skip_duplicatesを省略した作成でも未完了の同じテキストのTodoを返す
+    pub skip_duplicate_todos: bool,
+    // 未設定の場合、ゴミ箱の自動削除は行わない
+    pub trash_retention: Option<Duration>,
+    pub trash_purge_interval: Duration,
+    // Idempotency-Keyを覚えておく期間。過ぎた後の同じキーは新しいリクエストとして扱う
+    pub idempotency_key_ttl: Duration,
+    pub log_format: LogFormat,
+    // /metricsは認証なしで公開されるため、明示的に有効にした場合のみ組み込む
+    pub metrics_enabled: bool,
+    pub webhook_max_attempts: u32,
+    // 2回目以降の配信は、この値を2倍ずつ伸ばしながら待ってから行う
+    pub webhook_retry_delay: Duration,
+    // 通知日時を過ぎたTodoを探す間隔。通知はこの間隔の分だけ遅れる場合がある
+    pub reminder_interval: Duration,
+    // 未設定の場合、一覧の読み込みはキャッシュしない
+    pub cache_ttl: Option<Duration>,
+    pub cache_max_entries: usize,
+    // レスポンスのヘッダーを返すまでの上限。ストリーミングで返すbodyは対象外
+    pub request_timeout: Duration,
+    // 同時に処理するリクエストの上限。超えた分は待たせずに503を返す
+    pub max_concurrent_requests: usize,
+    // 未設定の場合、クライアントごとのリクエスト数は制限しない
+    pub rate_limit_per_minute: Option<u32>,
+    // 続けて受け付けるリクエスト数。使い切った後はrate_limit_per_minuteの速さで回復する
+    pub rate_limit_burst: u32,
+    // TRUSTED_PROXIESにカンマ区切りで並べたリバースプロキシのIP。ここから接続された場合のみX-Forwarded-Forを読む
+    pub trusted_proxies: Vec<IpAddr>,
+    pub body_limit: usize,
+    // /import、/import/csv、/todos/batchの本文の上限
+    pub bulk_body_limit: usize,
+    // 設定するとフロントエンドのビルド結果をこのディレクトリから配信し、APIにないパスにはindex.htmlを返す
+    pub static_dir: Option<PathBuf>,
+    // Todoなどのリソースのルートをこの下に置く。従来のプレフィックスなしのパスも非推奨として残す
+    pub api_prefix: String,
+    // 開発時のみ有効にし、/graphql/playgroundでクエリを試せるようにする
+    pub graphql_playground: bool,
+    // 設定した場合のみ、5xxのエラーとハンドラのpanicをSentryへ送る
+    pub sentry_dsn: Option<String>,
+    // 不具合の調査時のみ有効にし、リクエストとレスポンスの本文をdebugレベルで出力する。password・tokenは伏せる
+    pub log_bodies: bool,
+    // デモ・E2Eテスト用。設定した場合のみ/admin/reset・/admin/seedを組み込み、X-Admin-Tokenでこの値を求める
+    pub admin_token: Option<String>,
+}
+
+// ログ収集基盤向けのjsonと、開発時に読みやすいpretty(従来の出力)を切り替える
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    #[default]
+    Pretty,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+// memoryは外部の依存なしで起動できるが、データは再起動で失われる(デモ用)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Storage {
+    #[default]
+    Postgres,
+    Memory,
+}
+
+impl FromStr for Storage {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "postgres" => Ok(Storage::Postgres),
+            "memory" => Ok(Storage::Memory),
+            _ => Err(()),
+        }
+    }
+}
+
+// 最初の1件で止めず、問題のある変数をすべてまとめて報告する
+#[derive(Debug, Error, PartialEq, Eq)]
+#[error("invalid configuration: {}", .0.join(", "))]
+pub struct ConfigError(pub Vec<String>);
+
+impl Config {
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::from_lookup(|key| env::var(key).ok())
+    }
+
+    fn from_lookup(lookup: impl Fn(&str) -> Option<String>) -> Result<Self, ConfigError> {
+        let mut errors = vec![];
+
+        let host = parse_or(&lookup, "HOST", DEFAULT_HOST, &mut errors, "an IP address");
+        let port = parse_or(&lookup, "PORT", DEFAULT_PORT, &mut errors, "a port number");
+        let grpc_port = optional_port(&lookup, "GRPC_PORT", &mut errors);
+        if grpc_port == Some(port) {
+            errors.push(format!("GRPC_PORT must differ from PORT, got [{}]", port));
+        }
+        let run_migrations = parse_or(
+            &lookup,
+            "RUN_MIGRATIONS",
+            false,
+            &mut errors,
+            "true or false",
+        );
+        let db_max_connections = positive_or(
+            &lookup,
+            "DB_MAX_CONNECTIONS",
+            DEFAULT_DB_MAX_CONNECTIONS,
+            &mut errors,
+        );
+        let db_min_connections = parse_or(
+            &lookup,
+            "DB_MIN_CONNECTIONS",
+            0,
+            &mut errors,
+            "a non-negative integer",
+        );
+        if db_min_connections > db_max_connections {
+            errors.push(format!(
+                "DB_MIN_CONNECTIONS must not exceed DB_MAX_CONNECTIONS ({}), got [{}]",
+                db_max_connections, db_min_connections
+            ));
+        }
+        let db_acquire_timeout = positive_or(
+            &lookup,
+            "DB_ACQUIRE_TIMEOUT_SECS",
+            DEFAULT_DB_ACQUIRE_TIMEOUT_SECS,
+            &mut errors,
+        );
+        let db_idle_timeout = positive_or(
+            &lookup,
+            "DB_IDLE_TIMEOUT_SECS",
+            DEFAULT_DB_IDLE_TIMEOUT_SECS,
+            &mut errors,
+        );
+        let db_connect_max_attempts = positive_or(
+            &lookup,
+            "DB_CONNECT_MAX_ATTEMPTS",
+            DEFAULT_DB_CONNECT_MAX_ATTEMPTS,
+            &mut errors,
+        );
+        let db_connect_retry_delay = positive_or(
+            &lookup,
+            "DB_CONNECT_RETRY_DELAY_MS",
+            DEFAULT_DB_CONNECT_RETRY_DELAY_MS,
+            &mut errors,
+        );
+        let todo_batch_limit = positive_or(
+            &lookup,
+            "TODO_BATCH_LIMIT",
+            DEFAULT_TODO_BATCH_LIMIT,
+            &mut errors,
+        );
+        let strict_requests = parse_or(
+            &lookup,
+            "STRICT_REQUESTS",
+            true,
+            &mut errors,
+            "true or false",
+        );
+        let skip_duplicate_todos = parse_or(
+            &lookup,
+            "SKIP_DUPLICATE_TODOS",
+            false,
+            &mut errors,
+            "true or false",
+        );
+        let trash_retention = optional_positive(&lookup, "TRASH_RETENTION_DAYS", &mut errors);
+        let trash_purge_interval = positive_or(
+            &lookup,
+            "TRASH_PURGE_INTERVAL_SECS",
+            DEFAULT_TRASH_PURGE_INTERVAL_SECS,
+            &mut errors,
+        );
+        let idempotency_key_ttl = positive_or(
+            &lookup,
+            "IDEMPOTENCY_KEY_TTL_SECS",
+            DEFAULT_IDEMPOTENCY_KEY_TTL_SECS,
+            &mut errors,
+        );
+        let webhook_max_attempts = positive_or(
+            &lookup,
+            "WEBHOOK_MAX_ATTEMPTS",
+            DEFAULT_WEBHOOK_MAX_ATTEMPTS,
+            &mut errors,
+        );
+        let webhook_retry_delay = positive_or(
+            &lookup,
+            "WEBHOOK_RETRY_DELAY_MS",
+            DEFAULT_WEBHOOK_RETRY_DELAY_MS,
+            &mut errors,
+        );
+        let reminder_interval = positive_or(
+            &lookup,
+            "REMINDER_INTERVAL_SECS",
+            DEFAULT_REMINDER_INTERVAL_SECS,
+            &mut errors,
+        );
+        let cache_ttl = optional_positive(&lookup, "CACHE_TTL_SECS", &mut errors);
+        let cache_max_entries = positive_or(
+            &lookup,
+            "CACHE_MAX_ENTRIES",
+            DEFAULT_CACHE_MAX_ENTRIES,
+            &mut errors,
+        );
+        let request_timeout = positive_or(
+            &lookup,
+            "REQUEST_TIMEOUT_SECS",
+            DEFAULT_REQUEST_TIMEOUT_SECS,
+            &mut errors,
+        );
+        let max_concurrent_requests = positive_or(
+            &lookup,
+            "MAX_CONCURRENT_REQUESTS",
+            DEFAULT_MAX_CONCURRENT_REQUESTS,
+            &mut errors,
+        );
+        let rate_limit_per_minute =
+            optional_positive(&lookup, "RATE_LIMIT_PER_MINUTE", &mut errors);
+        let rate_limit_burst = positive_or(
+            &lookup,
+            "RATE_LIMIT_BURST",
+            DEFAULT_RATE_LIMIT_BURST,
+            &mut errors,
+        );
+        // 誤りをすべて報告するため、最初の不正なアドレスで止めずに検査する
+        let trusted_proxies: Vec<IpAddr> = lookup("TRUSTED_PROXIES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|proxy| !proxy.is_empty())
+            .filter_map(|proxy| match proxy.parse() {
+                Ok(ip) => Some(ip),
+                Err(_) => {
+                    errors.push(format!(
+                        "TRUSTED_PROXIES must be IP addresses, got [{}]",
+                        proxy
+                    ));
+                    None
+                }
+            })
+            .collect();
+        let body_limit = positive_or(
+            &lookup,
+            "BODY_LIMIT_BYTES",
+            DEFAULT_BODY_LIMIT_BYTES,
+            &mut errors,
+        );
+        let bulk_body_limit = positive_or(
+            &lookup,
+            "BULK_BODY_LIMIT_BYTES",
+            DEFAULT_BULK_BODY_LIMIT_BYTES,
+            &mut errors,
+        );
+        let metrics_enabled = parse_or(
+            &lookup,
+            "METRICS_ENABLED",
+            false,
+            &mut errors,
+            "true or false",
+        );
+        let graphql_playground = parse_or(
+            &lookup,
+            "GRAPHQL_PLAYGROUND",
+            false,
+            &mut errors,
+            "true or false",
+        );
+        let log_bodies = parse_or(&lookup, "LOG_BODIES", false, &mut errors, "true or false");
+        let log_format = parse_or(
+            &lookup,
+            "LOG_FORMAT",
+            LogFormat::default(),
+            &mut errors,
+            "json or pretty",
+        );
+        let storage = parse_or(
+            &lookup,
+            "STORAGE",
+            Storage::default(),
+            &mut errors,
+            "memory or postgres",
+        );
+        let database_url = match storage {
+            Storage::Postgres => required(&lookup, "DATABASE_URL", &mut errors),
+            Storage::Memory => Some(lookup("DATABASE_URL").unwrap_or_default()),
+        };
+        let persist_path = lookup("PERSIST_PATH")
+            .filter(|path| !path.trim().is_empty())
+            .map(PathBuf::from);
+        if persist_path.is_some() && storage != Storage::Memory {
+            errors.push("PERSIST_PATH requires STORAGE=memory".to_string());
+        }
+        let static_dir = lookup("STATIC_DIR")
+            .filter(|path| !path.trim().is_empty())
+            .map(PathBuf::from);
+        let admin_token = lookup("ADMIN_TOKEN").filter(|token| !token.trim().is_empty());
+        let sentry_dsn = lookup("SENTRY_DSN").filter(|dsn| !dsn.trim().is_empty());
+        if let Some(dsn) = &sentry_dsn {
+            if sentry::types::Dsn::from_str(dsn).is_err() {
+                errors.push(format!("SENTRY_DSN must be a Sentry DSN, got [{}]", dsn));
+            }
+        }
+        let api_prefix = lookup("API_PREFIX").unwrap_or_else(|| DEFAULT_API_PREFIX.to_string());
+        // Router::nestに渡すため、/で始まり/で終わらず、パスパラメーターを含まない形に限る
+        let is_valid_prefix = api_prefix.len() > 1
+            && api_prefix.starts_with('/')
+            && !api_prefix.ends_with('/')
+            && !api_prefix.contains(|c: char| c == ':' || c == '*' || c.is_whitespace());
+        if !is_valid_prefix {
+            errors.push(format!(
+                "API_PREFIX must be a path like {}, got [{}]",
+                DEFAULT_API_PREFIX, api_prefix
+            ));
+        }
+        let jwt_secret = required(&lookup, "JWT_SECRET", &mut errors);
+        let cors_origins = lookup("CORS_ORIGIN").unwrap_or_else(|| DEFAULT_CORS_ORIGIN.to_string());
+        // 誤りをすべて報告するため、最初の不正なオリジンで止めずに検査する
+        let cors_origins: Vec<Option<HeaderValue>> = cors_origins
+            .split(',')
+            .map(str::trim)
+            .map(|origin| match HeaderValue::from_str(origin) {
+                Ok(value) if origin.starts_with("http://") || origin.starts_with("https://") => {
+                    Some(value)
+                }
+                _ => {
+                    errors.push(format!(
+                        "CORS_ORIGIN must be an http(s) origin, got [{}]",
+                        origin
+                    ));
+                    None
+                }
+            })
+            .collect();
+        let cors_origins: Option<Vec<HeaderValue>> = cors_origins.into_iter().collect();
+        let cors_max_age = positive_or(
+            &lookup,
+            "CORS_MAX_AGE_SECS",
+            DEFAULT_CORS_MAX_AGE_SECS,
+            &mut errors,
+        );
+        let default_cache_policy = CachePolicy::default();
+        let cache_policy = CachePolicy {
+            labels: parse_or(
+                &lookup,
+                "LABELS_CACHE_CONTROL",
+                default_cache_policy.labels,
+                &mut errors,
+                "a header value",
+            ),
+            todos: parse_or(
+                &lookup,
+                "TODOS_CACHE_CONTROL",
+                default_cache_policy.todos,
+                &mut errors,
+                "a header value",
+            ),
+            mutations: parse_or(
+                &lookup,
+                "MUTATIONS_CACHE_CONTROL",
+                default_cache_policy.mutations,
+                &mut errors,
+                "a header value",
+            ),
+        };
+        let json_naming = parse_or(
+            &lookup,
+            "JSON_NAMING",
+            Naming::default(),
+            &mut errors,
+            "camel or snake",
+        );
+
+        match (database_url, jwt_secret, cors_origins) {
+            (Some(database_url), Some(jwt_secret), Some(cors_origins)) if errors.is_empty() => {
+                Ok(Self {
+                    host,
+                    port,
+                    grpc_port,
+                    storage,
+                    database_url,
+                    persist_path,
+                    jwt_secret,
+                    cors_origins,
+                    cors_max_age: Duration::from_secs(cors_max_age.into()),
+                    cache_policy,
+                    json_naming,
+                    run_migrations,
+                    db_max_connections,
+                    db_min_connections,
+                    db_acquire_timeout: Duration::from_secs(db_acquire_timeout.into()),
+                    db_idle_timeout: Duration::from_secs(db_idle_timeout.into()),
+                    db_connect_max_attempts,
+                    db_connect_retry_delay: Duration::from_millis(db_connect_retry_delay.into()),
+                    todo_batch_limit: todo_batch_limit as usize,
+                    strict_requests,
+                    skip_duplicate_todos,
+                    trash_retention: trash_retention
+                        .map(|days| Duration::from_secs(u64::from(days) * SECS_PER_DAY)),
+                    trash_purge_interval: Duration::from_secs(trash_purge_interval.into()),
+                    idempotency_key_ttl: Duration::from_secs(idempotency_key_ttl.into()),
+                    log_format,
+                    metrics_enabled,
+                    webhook_max_attempts,
+                    webhook_retry_delay: Duration::from_millis(webhook_retry_delay.into()),
+                    reminder_interval: Duration::from_secs(reminder_interval.into()),
+                    cache_ttl: cache_ttl.map(|secs| Duration::from_secs(secs.into())),
+                    cache_max_entries: cache_max_entries as usize,
+                    request_timeout: Duration::from_secs(request_timeout.into()),
+                    max_concurrent_requests: max_concurrent_requests as usize,
+                    rate_limit_per_minute,
+                    rate_limit_burst,
+                    trusted_proxies,
+                    body_limit: body_limit as usize,
+                    bulk_body_limit: bulk_body_limit as usize,
+                    static_dir,
+                    api_prefix,
+                    graphql_playground,
+                    sentry_dsn,
+                    log_bodies,
+                    admin_token,
+                })
+            }
+            _ => Err(ConfigError(errors)),
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        SocketAddr::new(self.host, self.port)
+    }
+
+    pub fn grpc_addr(&self) -> Option<SocketAddr> {
+        self.grpc_port.map(|port| SocketAddr::new(self.host, port))
+    }
+}
+
+fn required(
+    lookup: &impl Fn(&str) -> Option<String>,
+    key: &str,
+    errors: &mut Vec<String>,
+) -> Option<String> {
+    let value = lookup(key).filter(|value| !value.trim().is_empty());
+    if value.is_none() {
+        errors.push(format!("{} is not set", key));
+    }
+    value
+}
+
+fn parse_or<T: std::str::FromStr>(
+    lookup: &impl Fn(&str) -> Option<String>,
+    key: &str,
+    default: T,
+    errors: &mut Vec<String>,
+    expected: &str,
+) -> T {
+    match lookup(key) {
+        None => default,
+        Some(value) => value.trim().parse().unwrap_or_else(|_| {
+            errors.push(format!("{} must be {}, got [{}]", key, expected, value));
+            default
+        }),
+    }
+}
+
+fn positive_or(
+    lookup: &impl Fn(&str) -> Option<String>,
+    key: &str,
+    default: u32,
+    errors: &mut Vec<String>,
+) -> u32 {
+    let default = NonZeroU32::new(default).expect("default must be positive");
+    parse_or(lookup, key, default, errors, "a positive integer").get()
+}
+
+fn optional_positive(
+    lookup: &impl Fn(&str) -> Option<String>,
+    key: &str,
+    errors: &mut Vec<String>,
+) -> Option<u32> {
+    let value = lookup(key)?;
+    match value.trim().parse::<NonZeroU32>() {
+        Ok(value) => Some(value.get()),
+        Err(_) => {
+            errors.push(format!(
+                "{} must be a positive integer, got [{}]",
+                key, value
+            ));
+            None
+        }
+    }
+}
+
+fn optional_port(
+    lookup: &impl Fn(&str) -> Option<String>,
+    key: &str,
+    errors: &mut Vec<String>,
+) -> Option<u16> {
+    let value = lookup(key)?;
+    match value.trim().parse() {
+        Ok(port) => Some(port),
+        Err(_) => {
+            errors.push(format!("{} must be a port number, got [{}]", key, value));
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn load(vars: &[(&str, &str)]) -> Result<Config, ConfigError> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        Config::from_lookup(|key| vars.get(key).cloned())
+    }
+
+    #[test]
+    fn should_fall_back_to_defaults() {
+        let config = load(&[
+            ("DATABASE_URL", "postgres://localhost/todos"),
+            ("JWT_SECRET", "secret"),
+        ])
+        .unwrap();
+        assert_eq!(SocketAddr::from(([0, 0, 0, 0], 8000)), config.addr());
+        assert_eq!(None, config.grpc_addr());
+        assert_eq!(vec![DEFAULT_CORS_ORIGIN], config.cors_origins);
+        assert_eq!(Duration::from_secs(600), config.cors_max_age);
+        assert_eq!(CachePolicy::default(), config.cache_policy);
+        assert_eq!(Naming::Snake, config.json_naming);
+        assert_eq!(Storage::Postgres, config.storage);
+        assert_eq!(None, config.persist_path);
+        assert!(!config.run_migrations);
+        assert_eq!(10, config.db_max_connections);
+        assert_eq!(0, config.db_min_connections);
+        assert_eq!(Duration::from_secs(30), config.db_acquire_timeout);
+        assert_eq!(Duration::from_secs(600), config.db_idle_timeout);
+        assert_eq!(5, config.db_connect_max_attempts);
+        assert_eq!(Duration::from_millis(500), config.db_connect_retry_delay);
+        assert_eq!(500, config.todo_batch_limit);
+        assert!(config.strict_requests);
+        assert!(!config.skip_duplicate_todos);
+        assert_eq!(None, config.trash_retention);
+        assert_eq!(Duration::from_secs(3600), config.trash_purge_interval);
+        assert_eq!(
+            Duration::from_secs(24 * 60 * 60),
+            config.idempotency_key_ttl
+        );
+        assert_eq!(LogFormat::Pretty, config.log_format);
+        assert!(!config.metrics_enabled);
+        assert_eq!(5, config.webhook_max_attempts);
+        assert_eq!(Duration::from_secs(1), config.webhook_retry_delay);
+        assert_eq!(Duration::from_secs(60), config.reminder_interval);
+        assert_eq!(None, config.cache_ttl);
+        assert_eq!(1000, config.cache_max_entries);
+        assert_eq!(Duration::from_secs(30), config.request_timeout);
+        assert_eq!(512, config.max_concurrent_requests);
+        assert_eq!(None, config.rate_limit_per_minute);
+        assert_eq!(20, config.rate_limit_burst);
+        assert!(config.trusted_proxies.is_empty());
+        assert_eq!(64 * 1024, config.body_limit);
+        assert_eq!(10 * 1024 * 1024, config.bulk_body_limit);
+        assert_eq!(None, config.static_dir);
+        assert_eq!("/api/v1", config.api_prefix);
+        assert!(!config.graphql_playground);
+        assert_eq!(None, config.sentry_dsn);
+        assert!(!config.log_bodies);
+        assert_eq!(None, config.admin_token);
+    }
+
+    #[test]
+    fn should_read_overrides() {
+        let config = load(&[
+            ("HOST", "127.0.0.1"),
+            ("PORT", "3001"),
+            ("GRPC_PORT", "50051"),
+            ("DATABASE_URL", "postgres://localhost/todos"),
+            ("JWT_SECRET", "secret"),
+            (
+                "CORS_ORIGIN",
+                "https://todo.example.com, https://staging.todo.example.com",
+            ),
+            ("CORS_MAX_AGE_SECS", "3600"),
+            ("LABELS_CACHE_CONTROL", "public, max-age=300"),
+            ("TODOS_CACHE_CONTROL", "private, no-cache"),
+            ("MUTATIONS_CACHE_CONTROL", "no-store, private"),
+            ("JSON_NAMING", "Camel"),
+            ("RUN_MIGRATIONS", "true"),
+            ("DB_MAX_CONNECTIONS", "20"),
+            ("DB_MIN_CONNECTIONS", "2"),
+            ("DB_ACQUIRE_TIMEOUT_SECS", "5"),
+            ("DB_IDLE_TIMEOUT_SECS", "120"),
+            ("DB_CONNECT_MAX_ATTEMPTS", "10"),
+            ("DB_CONNECT_RETRY_DELAY_MS", "100"),
+            ("TODO_BATCH_LIMIT", "50"),
+            ("STRICT_REQUESTS", "false"),
+            ("SKIP_DUPLICATE_TODOS", "true"),
+            ("TRASH_RETENTION_DAYS", "30"),
+            ("TRASH_PURGE_INTERVAL_SECS", "60"),
+            ("IDEMPOTENCY_KEY_TTL_SECS", "600"),
+            ("LOG_FORMAT", "JSON"),
+            ("METRICS_ENABLED", "true"),
+            ("WEBHOOK_MAX_ATTEMPTS", "3"),
+            ("WEBHOOK_RETRY_DELAY_MS", "200"),
+            ("REMINDER_INTERVAL_SECS", "15"),
+            ("CACHE_TTL_SECS", "5"),
+            ("CACHE_MAX_ENTRIES", "100"),
+            ("REQUEST_TIMEOUT_SECS", "10"),
+            ("MAX_CONCURRENT_REQUESTS", "64"),
+            ("RATE_LIMIT_PER_MINUTE", "120"),
+            ("RATE_LIMIT_BURST", "5"),
+            ("TRUSTED_PROXIES", "10.0.0.1, ::1"),
+            ("BODY_LIMIT_BYTES", "1024"),
+            ("BULK_BODY_LIMIT_BYTES", "4096"),
+            ("STATIC_DIR", "/srv/todo-web/dist"),
+            ("API_PREFIX", "/todo-api/v1"),
+            ("GRAPHQL_PLAYGROUND", "true"),
+            ("SENTRY_DSN", "https://public@sentry.example.com/1"),
+            ("LOG_BODIES", "true"),
+            ("ADMIN_TOKEN", "e2e-secret"),
+        ])
+        .unwrap();
+        assert_eq!(SocketAddr::from(([127, 0, 0, 1], 3001)), config.addr());
+        assert_eq!(
+            Some(SocketAddr::from(([127, 0, 0, 1], 50051))),
+            config.grpc_addr()
+        );
+        assert_eq!(
+            vec![
+                "https://todo.example.com",
+                "https://staging.todo.example.com"
+            ],
+            config.cors_origins
+        );
+        assert_eq!(Duration::from_secs(3600), config.cors_max_age);
+        assert_eq!("public, max-age=300", config.cache_policy.labels);
+        assert_eq!("private, no-cache", config.cache_policy.todos);
+        assert_eq!("no-store, private", config.cache_policy.mutations);
+        assert_eq!(Naming::Camel, config.json_naming);
+        assert!(config.run_migrations);
+        assert_eq!(20, config.db_max_connections);
+        assert_eq!(2, config.db_min_connections);
+        assert_eq!(Duration::from_secs(5), config.db_acquire_timeout);
+        assert_eq!(Duration::from_secs(120), config.db_idle_timeout);
+        assert_eq!(10, config.db_connect_max_attempts);
+        assert_eq!(Duration::from_millis(100), config.db_connect_retry_delay);
+        assert_eq!(50, config.todo_batch_limit);
+        assert!(!config.strict_requests);
+        assert!(config.skip_duplicate_todos);
+        assert_eq!(
+            Some(Duration::from_secs(30 * 24 * 60 * 60)),
+            config.trash_retention
+        );
+        assert_eq!(Duration::from_secs(60), config.trash_purge_interval);
+        assert_eq!(Duration::from_secs(600), config.idempotency_key_ttl);
+        assert_eq!(LogFormat::Json, config.log_format);
+        assert!(config.metrics_enabled);
+        assert_eq!(3, config.webhook_max_attempts);
+        assert_eq!(Duration::from_millis(200), config.webhook_retry_delay);
+        assert_eq!(Duration::from_secs(15), config.reminder_interval);
+        assert_eq!(Some(Duration::from_secs(5)), config.cache_ttl);
+        assert_eq!(100, config.cache_max_entries);
+        assert_eq!(Duration::from_secs(10), config.request_timeout);
+        assert_eq!(64, config.max_concurrent_requests);
+        assert_eq!(Some(120), config.rate_limit_per_minute);
+        assert_eq!(5, config.rate_limit_burst);
+        assert_eq!(
+            vec![
+                IpAddr::from([10, 0, 0, 1]),
+                IpAddr::from(std::net::Ipv6Addr::LOCALHOST)
+            ],
+            config.trusted_proxies
+        );
+        assert_eq!(1024, config.body_limit);
+        assert_eq!(4096, config.bulk_body_limit);
+        assert_eq!(Some(PathBuf::from("/srv/todo-web/dist")), config.static_dir);
+        assert_eq!("/todo-api/v1", config.api_prefix);
+        assert!(config.graphql_playground);
+        assert_eq!(
+            Some("https://public@sentry.example.com/1".to_string()),
+            config.sentry_dsn
+        );
+        assert!(config.log_bodies);
+        assert_eq!(Some("e2e-secret".to_string()), config.admin_token);
+    }
+
+    #[test]
+    fn should_report_every_invalid_variable() {
+        let err = load(&[
+            ("HOST", "localhost:80"),
+            ("PORT", "70000"),
+            ("GRPC_PORT", "grpc"),
+            ("RUN_MIGRATIONS", "yes"),
+            ("DB_MIN_CONNECTIONS", "-1"),
+            ("DB_IDLE_TIMEOUT_SECS", "0"),
+            ("DB_CONNECT_MAX_ATTEMPTS", "0"),
+            ("STRICT_REQUESTS", "maybe"),
+            ("SKIP_DUPLICATE_TODOS", "1"),
+            ("TRASH_RETENTION_DAYS", "-1"),
+            ("IDEMPOTENCY_KEY_TTL_SECS", "1d"),
+            ("TRUSTED_PROXIES", "10.0.0.1,10.0.0.0/8"),
+            ("GRAPHQL_PLAYGROUND", "on"),
+            ("LOG_FORMAT", "xml"),
+            ("STORAGE", "mysql"),
+            ("PERSIST_PATH", "/var/lib/todos.json"),
+            ("SENTRY_DSN", "sentry.example.com"),
+            ("API_PREFIX", "/api/v1/"),
+            ("JWT_SECRET", " "),
+            ("CORS_ORIGIN", "https://todo.example.com,todo.example.com,*"),
+            ("CORS_MAX_AGE_SECS", "0"),
+            ("TODOS_CACHE_CONTROL", "no-cache\nmax-age=0"),
+            ("JSON_NAMING", "kebab"),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            ConfigError(vec![
+                "HOST must be an IP address, got [localhost:80]".to_string(),
+                "PORT must be a port number, got [70000]".to_string(),
+                "GRPC_PORT must be a port number, got [grpc]".to_string(),
+                "RUN_MIGRATIONS must be true or false, got [yes]".to_string(),
+                "DB_MIN_CONNECTIONS must be a non-negative integer, got [-1]".to_string(),
+                "DB_IDLE_TIMEOUT_SECS must be a positive integer, got [0]".to_string(),
+                "DB_CONNECT_MAX_ATTEMPTS must be a positive integer, got [0]".to_string(),
+                "STRICT_REQUESTS must be true or false, got [maybe]".to_string(),
+                "SKIP_DUPLICATE_TODOS must be true or false, got [1]".to_string(),
+                "TRASH_RETENTION_DAYS must be a positive integer, got [-1]".to_string(),
+                "IDEMPOTENCY_KEY_TTL_SECS must be a positive integer, got [1d]".to_string(),
+                "TRUSTED_PROXIES must be IP addresses, got [10.0.0.0/8]".to_string(),
+                "GRAPHQL_PLAYGROUND must be true or false, got [on]".to_string(),
+                "LOG_FORMAT must be json or pretty, got [xml]".to_string(),
+                "STORAGE must be memory or postgres, got [mysql]".to_string(),
+                "DATABASE_URL is not set".to_string(),
+                "PERSIST_PATH requires STORAGE=memory".to_string(),
+                "SENTRY_DSN must be a Sentry DSN, got [sentry.example.com]".to_string(),
+                "API_PREFIX must be a path like /api/v1, got [/api/v1/]".to_string(),
+                "JWT_SECRET is not set".to_string(),
+                "CORS_ORIGIN must be an http(s) origin, got [todo.example.com]".to_string(),
+                "CORS_ORIGIN must be an http(s) origin, got [*]".to_string(),
+                "CORS_MAX_AGE_SECS must be a positive integer, got [0]".to_string(),
+                "TODOS_CACHE_CONTROL must be a header value, got [no-cache\nmax-age=0]".to_string(),
+                "JSON_NAMING must be camel or snake, got [kebab]".to_string(),
+            ]),
+            err
+        );
+    }
+
+    #[test]
+    fn should_reject_grpc_port_same_as_http_port() {
+        let err = load(&[
+            ("DATABASE_URL", "postgres://localhost/todos"),
+            ("JWT_SECRET", "secret"),
+            ("GRPC_PORT", "8000"),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            ConfigError(vec![
+                "GRPC_PORT must differ from PORT, got [8000]".to_string()
+            ]),
+            err
+        );
+    }
+
+    #[test]
+    fn should_reject_more_min_connections_than_max() {
+        let err = load(&[
+            ("DATABASE_URL", "postgres://localhost/todos"),
+            ("JWT_SECRET", "secret"),
+            ("DB_MAX_CONNECTIONS", "5"),
+            ("DB_MIN_CONNECTIONS", "6"),
+        ])
+        .unwrap_err();
+        assert_eq!(
+            ConfigError(vec![
+                "DB_MIN_CONNECTIONS must not exceed DB_MAX_CONNECTIONS (5), got [6]".to_string()
+            ]),
+            err
+        );
+    }
+
+    #[test]
+    fn should_not_require_database_url_for_memory_storage() {
+        let config = load(&[("STORAGE", "Memory"), ("JWT_SECRET", "secret")]).unwrap();
+        assert_eq!(Storage::Memory, config.storage);
+        assert_eq!("", config.database_url);
+
+        let config = load(&[
+            ("STORAGE", "memory"),
+            ("JWT_SECRET", "secret"),
+            ("PERSIST_PATH", "/var/lib/todos.json"),
+        ])
+        .unwrap();
+        assert_eq!(
+            Some(PathBuf::from("/var/lib/todos.json")),
+            config.persist_path
+        );
+    }
+}
@@ -0,0 +1,248 @@
+use chrono::{DateTime, Utc};
+use csv::{ReaderBuilder, StringRecord};
+use serde::Serialize;
+use thiserror::Error;
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::repositories::todo::{CreateTodo, CreateTodoWithLabelNames, Priority};
+
+// 自前の形式(text, labels, priority, due_date)とTodoistのエクスポート(TYPE, CONTENT, PRIORITY)の両方を受け付ける
+const TEXT_COLUMNS: [&str; 2] = ["text", "content"];
+const LABEL_COLUMNS: [&str; 2] = ["labels", "label"];
+const PRIORITY_COLUMN: &str = "priority";
+const DUE_DATE_COLUMN: &str = "due_date";
+const TYPE_COLUMN: &str = "type";
+const MAX_LABEL_NAME_LENGTH: usize = 100;
+
+#[derive(Debug, Error)]
+pub enum CsvImportError {
+    #[error("CSV has no text or content column")]
+    MissingTextColumn,
+    #[error("Unparsable CSV: [{0}]")]
+    Unparsable(#[from] csv::Error),
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, ToSchema)]
+pub struct SkippedRow {
+    // ヘッダーを1行目とした行番号
+    pub line: u64,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize, Clone, Default, PartialEq, Eq, ToSchema)]
+pub struct CsvImportSummary {
+    pub imported: usize,
+    pub skipped: Vec<SkippedRow>,
+}
+
+#[derive(Debug, Default)]
+pub struct ParsedCsv {
+    pub todos: Vec<CreateTodoWithLabelNames>,
+    pub skipped: Vec<SkippedRow>,
+}
+
+struct Columns {
+    text: usize,
+    labels: Option<usize>,
+    priority: Option<usize>,
+    due_date: Option<usize>,
+    kind: Option<usize>,
+}
+
+impl Columns {
+    fn from_headers(headers: &StringRecord) -> Result<Self, CsvImportError> {
+        let names: Vec<String> = headers
+            .iter()
+            .map(|name| name.trim_start_matches('\u{feff}').trim().to_lowercase())
+            .collect();
+        let find = |candidates: &[&str]| {
+            names
+                .iter()
+                .position(|name| candidates.contains(&name.as_str()))
+        };
+        Ok(Self {
+            text: find(&TEXT_COLUMNS).ok_or(CsvImportError::MissingTextColumn)?,
+            labels: find(&LABEL_COLUMNS),
+            priority: find(&[PRIORITY_COLUMN]),
+            due_date: find(&[DUE_DATE_COLUMN]),
+            kind: find(&[TYPE_COLUMN]),
+        })
+    }
+}
+
+// 行単位の不備は読み飛ばして理由を返し、CSVとして読めない場合のみ全体をエラーにする
+pub fn parse(body: &[u8]) -> Result<ParsedCsv, CsvImportError> {
+    let mut reader = ReaderBuilder::new().flexible(true).from_reader(body);
+    let columns = Columns::from_headers(reader.headers()?)?;
+
+    let mut parsed = ParsedCsv::default();
+    for record in reader.records() {
+        let record = record?;
+        let line = record
+            .position()
+            .map(|position| position.line())
+            .unwrap_or(0);
+        match parse_row(&columns, &record) {
+            Ok(todo) => parsed.todos.push(todo),
+            Err(reason) => parsed.skipped.push(SkippedRow { line, reason }),
+        }
+    }
+    Ok(parsed)
+}
+
+fn parse_row(columns: &Columns, record: &StringRecord) -> Result<CreateTodoWithLabelNames, String> {
+    let field = |index: Option<usize>| {
+        index
+            .and_then(|index| record.get(index))
+            .map(str::trim)
+            .unwrap_or_default()
+    };
+
+    // Todoistのセクションやコメントの行は取り込まない
+    let kind = field(columns.kind);
+    if !kind.is_empty() && !kind.eq_ignore_ascii_case("task") {
+        return Err(format!("Not a task: {}", kind));
+    }
+
+    // Todoistはラベルを本文中に@nameの形式で持つため、本文から取り除いてラベルとして扱う
+    let (words, inline_labels): (Vec<&str>, Vec<&str>) = field(Some(columns.text))
+        .split_whitespace()
+        .partition(|word| !word.starts_with('@') || word.len() == 1);
+    let mut label_names: Vec<String> = inline_labels
+        .into_iter()
+        .map(|word| word.trim_start_matches('@').to_string())
+        .collect();
+    label_names.extend(
+        field(columns.labels)
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(str::to_string),
+    );
+    if label_names
+        .iter()
+        .any(|name| name.chars().count() > MAX_LABEL_NAME_LENGTH)
+    {
+        return Err("labels: Over text length".to_string());
+    }
+
+    let mut todo = CreateTodo::new(words.join(" "), vec![]);
+    if let Some(priority) = parse_priority(field(columns.priority))? {
+        todo = todo.with_priority(priority);
+    }
+    let due_date = field(columns.due_date);
+    if !due_date.is_empty() {
+        let due_date = DateTime::parse_from_rfc3339(due_date)
+            .map_err(|_| "due_date: Must be RFC 3339 date-time".to_string())?;
+        todo = todo.with_due_date(due_date.with_timezone(&Utc));
+    }
+
+    todo.validate().map_err(|errors| {
+        let mut reasons: Vec<String> = errors
+            .field_errors()
+            .into_iter()
+            .flat_map(|(field, errors)| {
+                errors.iter().map(move |error| {
+                    let message = error
+                        .message
+                        .as_ref()
+                        .map(|message| message.to_string())
+                        .unwrap_or_else(|| error.code.to_string());
+                    format!("{}: {}", field, message)
+                })
+            })
+            .collect();
+        reasons.sort();
+        reasons.join(", ")
+    })?;
+
+    Ok(CreateTodoWithLabelNames { todo, label_names })
+}
+
+// Todoistの優先度は4(p1)が最も高く、1(p4)は優先度なしを表す
+fn parse_priority(value: &str) -> Result<Option<Priority>, String> {
+    match value.to_lowercase().as_str() {
+        "" | "1" => Ok(None),
+        "2" => Ok(Some(Priority::Low)),
+        "3" => Ok(Some(Priority::Medium)),
+        "4" => Ok(Some(Priority::High)),
+        other => other
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("priority: Unknown priority {}", value)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lines(parsed: &ParsedCsv) -> Vec<(u64, &str)> {
+        parsed
+            .skipped
+            .iter()
+            .map(|row| (row.line, row.reason.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn parse_todoist_export_test() {
+        let body = "\u{feff}TYPE,CONTENT,DESCRIPTION,PRIORITY,INDENT,AUTHOR,RESPONSIBLE,DATE,DATE_LANG,TIMEZONE\n\
+            section,Inbox,,,,,,,,\n\
+            task,Buy milk @home @errand,,4,1,me,,,en,UTC\n\
+            \n\
+            task,Plain task,,1,1,me,,,en,UTC\n\
+            task,,,1,1,me,,,en,UTC\n";
+        let parsed = parse(body.as_bytes()).unwrap();
+
+        assert_eq!(2, parsed.todos.len());
+        let milk = &parsed.todos[0];
+        assert_eq!(
+            CreateTodo::new("Buy milk".to_string(), vec![]).with_priority(Priority::High),
+            milk.todo
+        );
+        assert_eq!(vec!["home", "errand"], milk.label_names);
+        assert_eq!(Priority::Medium, parsed.todos[1].todo.priority());
+        assert_eq!(
+            vec![(2, "Not a task: section"), (6, "text: Can not be empty")],
+            lines(&parsed)
+        );
+    }
+
+    #[test]
+    fn parse_own_columns_test() {
+        let body = format!(
+            "text,labels,priority,due_date\n\
+            write report,\"work, Writing\",high,2024-01-31T00:00:00Z\n\
+            {},,,\n\
+            bad date,,,tomorrow\n\
+            bad priority,,urgent,\n",
+            "a".repeat(101)
+        );
+        let parsed = parse(body.as_bytes()).unwrap();
+
+        assert_eq!(1, parsed.todos.len());
+        assert_eq!(vec!["work", "Writing"], parsed.todos[0].label_names);
+        assert_eq!(
+            vec![
+                (3, "text: Over text length"),
+                (4, "due_date: Must be RFC 3339 date-time"),
+                (5, "priority: Unknown priority urgent"),
+            ],
+            lines(&parsed)
+        );
+    }
+
+    #[test]
+    fn parse_error_test() {
+        assert!(matches!(
+            parse(b"name,color\nwork,#808080\n"),
+            Err(CsvImportError::MissingTextColumn)
+        ));
+        assert!(matches!(
+            parse(b"text\n\xff\xfe\n"),
+            Err(CsvImportError::Unparsable(_))
+        ));
+    }
+}
@@ -0,0 +1,126 @@
+use std::fmt::Display;
+use std::future::Future;
+#[cfg(feature = "sqlite")]
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::Context;
+use sqlx::postgres::PgPoolOptions;
+#[cfg(feature = "sqlite")]
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::PgPool;
+#[cfg(feature = "sqlite")]
+use sqlx::SqlitePool;
+
+use crate::config::Config;
+
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+// docker-composeではAPIとPostgresが同時に起動するため、接続できるまで待つ
+pub async fn connect(config: &Config) -> anyhow::Result<PgPool> {
+    tracing::debug!("start connect database...");
+    retry_with_backoff(
+        config.db_connect_max_attempts,
+        config.db_connect_retry_delay,
+        || {
+            // sqlx 0.5のconnect_timeoutは、プールから接続を取り出すまでの待ち時間の上限
+            PgPoolOptions::new()
+                .max_connections(config.db_max_connections)
+                .min_connections(config.db_min_connections)
+                .connect_timeout(config.db_acquire_timeout)
+                .idle_timeout(config.db_idle_timeout)
+                .connect(&config.database_url)
+        },
+    )
+    .await
+    .with_context(|| {
+        format!(
+            "fail connect database after {} attempts, url is [{}]",
+            config.db_connect_max_attempts, config.database_url
+        )
+    })
+}
+
+// SQLiteは書き込みを同時に1つしか行えないため、接続を1本に絞ってロック待ちの失敗を避ける
+// 接続が閉じるとインメモリのDBは消えるので、アイドルのまま保持し続ける
+#[cfg(feature = "sqlite")]
+pub async fn connect_sqlite(database_url: &str) -> anyhow::Result<SqlitePool> {
+    tracing::debug!("start connect sqlite database...");
+    let options = SqliteConnectOptions::from_str(database_url)
+        .with_context(|| format!("invalid sqlite url [{}]", database_url))?
+        .create_if_missing(true);
+    SqlitePoolOptions::new()
+        .max_connections(1)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .connect_with(options)
+        .await
+        .with_context(|| format!("fail connect database, url is [{}]", database_url))
+}
+
+async fn retry_with_backoff<T, E, F, Fut>(
+    max_attempts: u32,
+    initial_delay: Duration,
+    mut f: F,
+) -> Result<T, E>
+where
+    E: Display,
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let mut delay = initial_delay;
+    let mut attempt = 1;
+    loop {
+        match f().await {
+            Ok(value) => return Ok(value),
+            Err(e) if attempt >= max_attempts => return Err(e),
+            Err(e) => {
+                tracing::warn!(
+                    "database connection attempt {}/{} failed: {}, retrying in {:?}",
+                    attempt,
+                    max_attempts,
+                    e,
+                    delay
+                );
+                tokio::time::sleep(delay).await;
+                delay = (delay * 2).min(MAX_RETRY_DELAY);
+                attempt += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    async fn fail_until(succeed_on: u32, max_attempts: u32) -> (Result<u32, String>, u32) {
+        let calls = AtomicU32::new(0);
+        let res = retry_with_backoff(max_attempts, Duration::from_millis(1), || async {
+            let call = calls.fetch_add(1, Ordering::SeqCst) + 1;
+            if call >= succeed_on {
+                Ok(call)
+            } else {
+                Err(format!("attempt {} refused", call))
+            }
+        })
+        .await;
+        (res, calls.load(Ordering::SeqCst))
+    }
+
+    #[tokio::test]
+    async fn should_retry_until_success() {
+        let (res, calls) = fail_until(3, 5).await;
+        assert_eq!(Ok(3), res);
+        assert_eq!(3, calls);
+    }
+
+    #[tokio::test]
+    async fn should_give_up_after_max_attempts() {
+        let (res, calls) = fail_until(10, 3).await;
+        assert_eq!(Err("attempt 3 refused".to_string()), res);
+        assert_eq!(3, calls);
+    }
+}
@@ -0,0 +1,380 @@
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use utoipa::ToSchema;
+use validator::{ValidationErrors, ValidationErrorsKind};
+
+use crate::body_limit::BodyTooLarge;
+use crate::csv_import::CsvImportError;
+use crate::error_reporting::UnexpectedError;
+use crate::metrics::DatabaseUnavailable;
+use crate::repositories::RepositoryError;
+
+const DATABASE_UNAVAILABLE: &str = "database_unavailable";
+
+#[derive(Debug)]
+pub struct AppError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+    fields: Vec<FieldError>,
+    path: Option<String>,
+    // 競合時のみ使うため、AppError自体を小さく保つようBoxで持つ
+    current: Option<Box<Value>>,
+    // 想定外のエラーの詳細。クライアントへは返さず、レスポンスに添えてエラーの送信に使う
+    unexpected: Option<Box<str>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+// すべてのエラーレスポンスで共通のbody
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorBody {
+    error: ErrorDetail,
+    // バージョン競合・前提条件の不一致・名前重複の場合のみ、最新(既存)のリソースが入る
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[schema(value_type = Option<Object>)]
+    current: Option<Value>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorDetail {
+    #[schema(example = "not_found")]
+    code: &'static str,
+    message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    fields: Vec<FieldError>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+}
+
+impl AppError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            message: message.into(),
+            fields: vec![],
+            path: None,
+            current: None,
+            unexpected: None,
+        }
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "bad_request", message)
+    }
+
+    pub fn with_field(mut self, field: impl Into<String>, message: impl Into<String>) -> Self {
+        self.fields.push(FieldError {
+            field: field.into(),
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    // 競合時にクライアントがマージできるよう、最新のリソースを添えて返す
+    pub fn with_current(mut self, current: impl Serialize) -> Self {
+        self.current = serde_json::to_value(current).ok().map(Box::new);
+        self
+    }
+
+    pub fn validation(errors: ValidationErrors) -> Self {
+        let mut fields = vec![];
+        collect_field_errors("", &errors, &mut fields);
+        // HashMapの順序に依存しないようフィールド名で並べる
+        fields.sort_by(|a, b| a.field.cmp(&b.field));
+        Self {
+            fields,
+            ..Self::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "validation_error",
+                "Validation error",
+            )
+        }
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal_error", message)
+    }
+
+    // 想定外のエラーの詳細はクライアントへ返さずログにのみ出力する
+    fn unexpected(source: &impl std::fmt::Debug) -> Self {
+        let detail = format!("{:?}", source);
+        tracing::error!("{}", detail);
+        Self {
+            unexpected: Some(detail.into()),
+            ..Self::internal("Internal server error")
+        }
+    }
+
+    // HTTP以外(WebSocket)でもレスポンスと同じ形式で返せるようbodyのみ取り出す
+    pub fn into_body(self) -> ErrorBody {
+        ErrorBody {
+            error: ErrorDetail {
+                code: self.code,
+                message: self.message,
+                fields: self.fields,
+                path: self.path,
+            },
+            current: self.current.map(|current| *current),
+        }
+    }
+}
+
+// ネストした構造体・配列のエラーは`todos[0].text`の形式のパスで返す
+fn collect_field_errors(prefix: &str, errors: &ValidationErrors, fields: &mut Vec<FieldError>) {
+    for (field, kind) in errors.errors() {
+        let path = if prefix.is_empty() {
+            field.to_string()
+        } else {
+            format!("{}.{}", prefix, field)
+        };
+        match kind {
+            ValidationErrorsKind::Field(errors) => fields.extend(errors.iter().map(|e| {
+                FieldError {
+                    field: path.clone(),
+                    message: e
+                        .message
+                        .as_ref()
+                        .map(|message| message.to_string())
+                        .unwrap_or_else(|| e.code.to_string()),
+                }
+            })),
+            ValidationErrorsKind::Struct(errors) => collect_field_errors(&path, errors, fields),
+            ValidationErrorsKind::List(errors) => {
+                for (index, errors) in errors {
+                    collect_field_errors(&format!("{}[{}]", path, index), errors, fields);
+                }
+            }
+        }
+    }
+}
+
+impl From<RepositoryError> for AppError {
+    fn from(e: RepositoryError) -> Self {
+        match e {
+            RepositoryError::NotFound(_) => {
+                Self::new(StatusCode::NOT_FOUND, "not_found", e.to_string())
+            }
+            RepositoryError::Duplicate(_) => {
+                Self::new(StatusCode::CONFLICT, "conflict", e.to_string())
+            }
+            RepositoryError::Conflict(_) => {
+                Self::new(StatusCode::CONFLICT, "version_conflict", e.to_string())
+            }
+            RepositoryError::PreconditionFailed(_) => Self::new(
+                StatusCode::PRECONDITION_FAILED,
+                "precondition_failed",
+                e.to_string(),
+            ),
+            RepositoryError::InvalidLabel(_) => Self::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "validation_error",
+                "Validation error",
+            )
+            .with_field("labels", e.to_string()),
+            RepositoryError::Validation(message) => Self::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "validation_error",
+                message,
+            ),
+            // 接続が空くのを待てば成功するため、想定外のエラーとして送らず503で再試行を促す
+            RepositoryError::PoolTimedOut => {
+                tracing::warn!("{}", e);
+                Self::new(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    DATABASE_UNAVAILABLE,
+                    e.to_string(),
+                )
+            }
+            RepositoryError::Unexpected(source) => Self::unexpected(&source),
+        }
+    }
+}
+
+impl From<BodyTooLarge> for AppError {
+    fn from(e: BodyTooLarge) -> Self {
+        Self::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "payload_too_large",
+            e.to_string(),
+        )
+    }
+}
+
+// 行単位の不備は読み飛ばすため、ここに来るのはファイル全体を読めない場合のみ
+impl From<CsvImportError> for AppError {
+    fn from(e: CsvImportError) -> Self {
+        Self::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "invalid_csv",
+            e.to_string(),
+        )
+    }
+}
+
+impl From<anyhow::Error> for AppError {
+    fn from(e: anyhow::Error) -> Self {
+        match e.downcast::<RepositoryError>() {
+            Ok(e) => e.into(),
+            Err(e) => Self::unexpected(&e),
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(mut self) -> Response {
+        let status = self.status;
+        let unexpected = self.unexpected.take();
+        let pool_timed_out = self.code == DATABASE_UNAVAILABLE;
+        let mut res = (status, Json(self.into_body())).into_response();
+        if let Some(detail) = unexpected {
+            res.extensions_mut().insert(UnexpectedError(detail.into()));
+        }
+        if pool_timed_out {
+            res.extensions_mut().insert(DatabaseUnavailable);
+        }
+        res
+    }
+}
+
+// GraphQLではレスポンスのerrorsへ入れ、RESTと同じcode・fields・currentをextensionsで返す
+impl From<AppError> for async_graphql::Error {
+    fn from(e: AppError) -> Self {
+        let body = e.into_body();
+        let mut error = async_graphql::Error::new(body.error.message);
+        let mut extensions = async_graphql::ErrorExtensionValues::default();
+        extensions.set("code", body.error.code);
+        if !body.error.fields.is_empty() {
+            let fields = serde_json::to_value(&body.error.fields).unwrap_or_default();
+            extensions.set(
+                "fields",
+                async_graphql::Value::from_json(fields).unwrap_or_default(),
+            );
+        }
+        if let Some(current) = body.current {
+            extensions.set(
+                "current",
+                async_graphql::Value::from_json(current).unwrap_or_default(),
+            );
+        }
+        error.extensions = Some(extensions);
+        error
+    }
+}
+
+// gRPCではHTTPのステータスに対応するコードへ変換し、不正な項目はメッセージに含める
+impl From<AppError> for tonic::Status {
+    fn from(e: AppError) -> Self {
+        let code = match (e.status, e.code) {
+            (StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY, _) => {
+                tonic::Code::InvalidArgument
+            }
+            (StatusCode::UNAUTHORIZED, _) => tonic::Code::Unauthenticated,
+            (StatusCode::NOT_FOUND, _) => tonic::Code::NotFound,
+            (StatusCode::CONFLICT, "version_conflict") => tonic::Code::Aborted,
+            (StatusCode::CONFLICT, _) => tonic::Code::AlreadyExists,
+            (StatusCode::PRECONDITION_FAILED, _) => tonic::Code::FailedPrecondition,
+            (StatusCode::PAYLOAD_TOO_LARGE, _) => tonic::Code::ResourceExhausted,
+            (StatusCode::SERVICE_UNAVAILABLE, _) => tonic::Code::Unavailable,
+            _ => tonic::Code::Internal,
+        };
+        let message = if e.fields.is_empty() {
+            e.message
+        } else {
+            let fields: Vec<String> = e
+                .fields
+                .iter()
+                .map(|field| format!("{}: {}", field.field, field.message))
+                .collect();
+            format!("{} ({})", e.message, fields.join(", "))
+        };
+        tonic::Status::new(code, message)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    async fn into_parts(e: AppError) -> (StatusCode, serde_json::Value) {
+        let res = e.into_response();
+        let status = res.status();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        (status, serde_json::from_slice(&bytes).unwrap())
+    }
+
+    #[tokio::test]
+    async fn repository_error_status_test() {
+        let (status, body) = into_parts(RepositoryError::NotFound(Some(1)).into()).await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+        assert_eq!("not_found", body["error"]["code"]);
+        assert_eq!("NotFound, id is 1", body["error"]["message"]);
+
+        let (status, body) = into_parts(RepositoryError::Duplicate(Some(1)).into()).await;
+        assert_eq!(StatusCode::CONFLICT, status);
+        assert_eq!("conflict", body["error"]["code"]);
+
+        let e = AppError::from(RepositoryError::Conflict(1)).with_current(json!({ "id": 1 }));
+        let (status, body) = into_parts(e).await;
+        assert_eq!(StatusCode::CONFLICT, status);
+        assert_eq!("version_conflict", body["error"]["code"]);
+        assert_eq!(json!({ "id": 1 }), body["current"]);
+
+        let e =
+            AppError::from(RepositoryError::PreconditionFailed(1)).with_current(json!({ "id": 1 }));
+        let (status, body) = into_parts(e).await;
+        assert_eq!(StatusCode::PRECONDITION_FAILED, status);
+        assert_eq!("precondition_failed", body["error"]["code"]);
+        assert_eq!(json!({ "id": 1 }), body["current"]);
+
+        let (status, body) = into_parts(RepositoryError::PoolTimedOut.into()).await;
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, status);
+        assert_eq!("database_unavailable", body["error"]["code"]);
+
+        let (status, body) = into_parts(RepositoryError::InvalidLabel(999).into()).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+        assert_eq!("labels", body["error"]["fields"][0]["field"]);
+        assert_eq!(
+            "Label not found, id is 999",
+            body["error"]["fields"][0]["message"]
+        );
+
+        let (status, body) =
+            into_parts(RepositoryError::Validation("violates foreign key".to_string()).into())
+                .await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, status);
+        assert_eq!("validation_error", body["error"]["code"]);
+        assert_eq!("violates foreign key", body["error"]["message"]);
+
+        let (status, body) = into_parts(RepositoryError::unexpected("db is down").into()).await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status);
+        assert_eq!("internal_error", body["error"]["code"]);
+        assert_eq!("Internal server error", body["error"]["message"]);
+
+        // anyhowで包まれていても同じく変換する
+        let e = anyhow::Error::from(RepositoryError::NotFound(Some(1)));
+        let (status, _) = into_parts(e.into()).await;
+        assert_eq!(StatusCode::NOT_FOUND, status);
+
+        let e = anyhow::anyhow!("db is down");
+        let (status, body) = into_parts(e.into()).await;
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status);
+        assert_eq!("internal_error", body["error"]["code"]);
+        assert_eq!("Internal server error", body["error"]["message"]);
+    }
+}
@@ -0,0 +1,196 @@
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::cell::RefCell;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::{Arc, Once};
+use std::task::{Context, Poll};
+
+use axum::body::Body;
+use axum::http::Request;
+use axum::response::{IntoResponse, Response};
+use futures_util::FutureExt;
+use tower::{Layer, Service};
+
+use crate::error::AppError;
+use crate::telemetry::REQUEST_ID_HEADER;
+
+// 送信先に依存せず、テストでは記録するだけの実装に差し替えられるようにする
+pub trait ErrorReporter: Send + Sync + 'static {
+    fn capture(&self, event: ErrorEvent);
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorEvent {
+    pub message: String,
+    pub method: String,
+    pub path: String,
+    pub request_id: Option<String>,
+}
+
+// 5xxとpanicを送る先。Extensionが未設定の場合は送らず、panicを500にするのみ
+#[derive(Clone)]
+pub struct Reporter(pub Arc<dyn ErrorReporter>);
+
+// AppErrorがクライアントへ返さない詳細を、レスポンスに添えてこのlayerまで運ぶ
+#[derive(Debug, Clone)]
+pub struct UnexpectedError(pub String);
+
+// sentry::initで設定したクライアントへ、リクエストの情報をタグとして付けて送る
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SentryReporter;
+
+impl ErrorReporter for SentryReporter {
+    fn capture(&self, event: ErrorEvent) {
+        sentry::with_scope(
+            |scope| {
+                scope.set_tag("method", &event.method);
+                scope.set_tag("path", &event.path);
+                if let Some(request_id) = &event.request_id {
+                    scope.set_tag("request_id", request_id);
+                }
+            },
+            || sentry::capture_message(&event.message, sentry::Level::Error),
+        );
+    }
+}
+
+// 戻り値のguardをdropすると送信待ちのイベントを書き出すため、終了まで保持する
+pub fn init_sentry(dsn: &str) -> sentry::ClientInitGuard {
+    sentry::init((
+        dsn,
+        sentry::ClientOptions {
+            release: sentry::release_name!(),
+            ..Default::default()
+        },
+    ))
+}
+
+thread_local! {
+    // catch_unwindはpanicしたスレッドで戻るため、同じスレッドの直近のpanicがハンドラのもの
+    static LAST_BACKTRACE: RefCell<Option<Backtrace>> = const { RefCell::new(None) };
+}
+
+// unwindした後ではpanic箇所のbacktraceを取れないため、hookで取っておく。
+// 既存のhookも呼ぶので、ハンドラ以外のpanicはこれまで通り標準エラーに出る
+pub fn install_panic_hook() {
+    static INSTALL: Once = Once::new();
+    INSTALL.call_once(|| {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            LAST_BACKTRACE.with(|last| *last.borrow_mut() = Some(Backtrace::force_capture()));
+            previous(info);
+        }));
+    });
+}
+
+// ハンドラのpanicで接続を切らず、他のエラーと同じ形式の500を返す
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ReportErrorsLayer;
+
+impl<S> Layer<S> for ReportErrorsLayer {
+    type Service = ReportErrors<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ReportErrors { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct ReportErrors<S> {
+    inner: S,
+}
+
+impl<S> Service<Request<Body>> for ReportErrors<S>
+where
+    S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let reporter = req.extensions().get::<Reporter>().cloned();
+        // メッセージはレスポンスを受け取ってから埋める
+        let event = ErrorEvent {
+            message: String::new(),
+            method: req.method().to_string(),
+            path: req.uri().path().to_string(),
+            request_id: req
+                .headers()
+                .get(REQUEST_ID_HEADER)
+                .and_then(|id| id.to_str().ok())
+                .map(str::to_string),
+        };
+        // poll_readyを済ませたinnerで呼び出すため、cloneと入れ替える
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            let (res, message) = match AssertUnwindSafe(async move { inner.call(req).await })
+                .catch_unwind()
+                .await
+            {
+                Ok(res) => {
+                    let res = res?;
+                    let message = res
+                        .extensions()
+                        .get::<UnexpectedError>()
+                        .map(|e| e.0.clone());
+                    (res, message)
+                }
+                Err(payload) => {
+                    let message = format!("handler panicked: {}", panic_message(&*payload));
+                    match LAST_BACKTRACE.with(|last| last.borrow_mut().take()) {
+                        Some(backtrace) => tracing::error!(%backtrace, "{}", message),
+                        None => tracing::error!("{}", message),
+                    }
+                    let res = AppError::internal("Internal server error").into_response();
+                    (res, Some(message))
+                }
+            };
+            if let (Some(Reporter(reporter)), Some(message)) = (reporter, message) {
+                reporter.capture(ErrorEvent { message, ..event });
+            }
+            Ok(res)
+        })
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown panic")
+}
+
+#[cfg(test)]
+pub mod test_utils {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    // 送る代わりにイベントを貯め、テストから件数と内容を確かめる
+    #[derive(Debug, Default)]
+    pub struct RecordingReporter {
+        events: Mutex<Vec<ErrorEvent>>,
+    }
+
+    impl RecordingReporter {
+        pub fn events(&self) -> Vec<ErrorEvent> {
+            self.events.lock().unwrap().clone()
+        }
+    }
+
+    impl ErrorReporter for RecordingReporter {
+        fn capture(&self, event: ErrorEvent) {
+            self.events.lock().unwrap().push(event);
+        }
+    }
+}
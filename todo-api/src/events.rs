@@ -0,0 +1,288 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, watch};
+
+use crate::repositories::label::Label;
+use crate::repositories::todo::TodoEntity;
+
+// 購読側がこの件数以上遅れた場合、古いイベントは読み飛ばされる
+const EVENT_CAPACITY: usize = 256;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TodoEvent {
+    Created {
+        todo: TodoEntity,
+    },
+    Updated {
+        todo: TodoEntity,
+        // 完了に変更するリクエストの場合true。Webhookの絞り込みにのみ使い、WebSocketへは送らない
+        #[serde(skip)]
+        completed_now: bool,
+    },
+    Deleted {
+        id: i64,
+    },
+    // 通知日時を過ぎたTodo。リマインダーのタスクのみが発行する
+    Reminded {
+        todo: TodoEntity,
+    },
+    // ラベルは全てのユーザーで共有するため、ユーザーを問わず全ての購読者へ配信する
+    LabelCreated {
+        label: Label,
+    },
+    LabelUpdated {
+        label: Label,
+    },
+    LabelDeleted {
+        id: i32,
+    },
+    // fromを削除し、付いていたTodoをintoへ付け替えた
+    LabelsMerged {
+        from: i32,
+        into: Label,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct Published {
+    // ユーザーに属さない変更ではNone
+    user_id: Option<i32>,
+    // 発行元の接続。自身の操作は応答として返すため、同じ接続へは配信しない
+    origin: Option<u64>,
+    event: TodoEvent,
+}
+
+// Todoの変更をユーザー単位で配信する。HTTPハンドラーとWebSocket接続で共有する
+#[derive(Debug, Clone)]
+pub struct TodoEvents {
+    sender: broadcast::Sender<Published>,
+    shutdown: Arc<watch::Sender<bool>>,
+    next_id: Arc<AtomicU64>,
+    // falseの場合、変更はoutboxからbroadcastでのみ流す
+    direct: bool,
+}
+
+impl TodoEvents {
+    pub fn new() -> Self {
+        let (sender, _) = broadcast::channel(EVENT_CAPACITY);
+        let (shutdown, _) = watch::channel(false);
+        Self {
+            sender,
+            shutdown: Arc::new(shutdown),
+            next_id: Arc::new(AtomicU64::new(1)),
+            direct: true,
+        }
+    }
+
+    // 変更と同じトランザクションで記録したイベントのみを流すため、ハンドラーなどからのpublishは無視する
+    // 発行元の接続は記録されないため、WebSocketでは自身の操作のイベントも届く
+    pub fn with_outbox(self) -> Self {
+        Self {
+            direct: false,
+            ..self
+        }
+    }
+
+    pub fn publish(&self, user_id: i32, event: TodoEvent) {
+        if self.direct {
+            self.send(Some(user_id), None, event);
+        }
+    }
+
+    // ラベルの変更など、ユーザーに属さない変更を全ての購読者へ流す
+    pub fn publish_to_all(&self, event: TodoEvent) {
+        if self.direct {
+            self.send(None, None, event);
+        }
+    }
+
+    // outboxから取り出したイベントを流す。user_idがNoneの場合は全ての購読者へ届く
+    pub fn broadcast(&self, user_id: Option<i32>, event: TodoEvent) {
+        self.send(user_id, None, event);
+    }
+
+    fn send(&self, user_id: Option<i32>, origin: Option<u64>, event: TodoEvent) {
+        // 購読者がいない場合のエラーは無視する
+        let _ = self.sender.send(Published {
+            user_id,
+            origin,
+            event,
+        });
+    }
+
+    // ユーザーを問わず全ての変更を受け取る。終了処理の待ち合わせの対象にはならない
+    pub fn subscribe_all(&self) -> AllSubscription {
+        AllSubscription {
+            events: self.sender.subscribe(),
+        }
+    }
+
+    pub fn subscribe(&self, user_id: i32) -> Subscription {
+        Subscription {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            user_id,
+            sender: self.sender.clone(),
+            direct: self.direct,
+            events: self.sender.subscribe(),
+            shutdown: self.shutdown.subscribe(),
+        }
+    }
+
+    // 全ての購読を終了させ、購読側が閉じ終わるまで最大timeoutだけ待つ
+    pub async fn shutdown(&self, timeout: Duration) {
+        self.shutdown.send_replace(true);
+        if tokio::time::timeout(timeout, self.shutdown.closed())
+            .await
+            .is_err()
+        {
+            tracing::warn!("event subscribers did not close within {:?}", timeout);
+        }
+    }
+}
+
+impl Default for TodoEvents {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Subscription {
+    id: u64,
+    user_id: i32,
+    sender: broadcast::Sender<Published>,
+    direct: bool,
+    events: broadcast::Receiver<Published>,
+    shutdown: watch::Receiver<bool>,
+}
+
+impl Subscription {
+    pub fn publish(&self, event: TodoEvent) {
+        if !self.direct {
+            return;
+        }
+        let _ = self.sender.send(Published {
+            user_id: Some(self.user_id),
+            origin: Some(self.id),
+            event,
+        });
+    }
+
+    // 同じユーザーの、他の接続・HTTP経由の変更とラベルの変更を待つ。終了時はNoneを返す
+    pub async fn next(&mut self) -> Option<TodoEvent> {
+        loop {
+            if *self.shutdown.borrow() {
+                return None;
+            }
+            tokio::select! {
+                changed = self.shutdown.changed() => {
+                    if changed.is_err() {
+                        return None;
+                    }
+                }
+                received = self.events.recv() => match received {
+                    Ok(published) => {
+                        if published.user_id.is_none_or(|user_id| user_id == self.user_id)
+                            && published.origin != Some(self.id)
+                        {
+                            return Some(published.event);
+                        }
+                    }
+                    Err(RecvError::Lagged(skipped)) => {
+                        tracing::warn!("subscription lagged, skipped {} events", skipped);
+                    }
+                    Err(RecvError::Closed) => return None,
+                },
+            }
+        }
+    }
+}
+
+pub struct AllSubscription {
+    events: broadcast::Receiver<Published>,
+}
+
+impl AllSubscription {
+    // 発行したユーザーのidと組で返す。ユーザーに属さない変更ではidはNone。TodoEventsが破棄された場合はNoneを返す
+    pub async fn next(&mut self) -> Option<(Option<i32>, TodoEvent)> {
+        loop {
+            match self.events.recv().await {
+                Ok(published) => return Some((published.user_id, published.event)),
+                Err(RecvError::Lagged(skipped)) => {
+                    tracing::warn!("subscription lagged, skipped {} events", skipped);
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn subscription_filters_user_and_origin_test() {
+        let events = TodoEvents::new();
+        let mut first = events.subscribe(1);
+        let mut second = events.subscribe(1);
+
+        events.publish(2, TodoEvent::Deleted { id: 10 });
+        first.publish(TodoEvent::Deleted { id: 11 });
+        events.publish(1, TodoEvent::Deleted { id: 12 });
+
+        // 他のユーザーのイベントと自身の発行したイベントは届かない
+        assert_eq!(Some(TodoEvent::Deleted { id: 12 }), first.next().await);
+        assert_eq!(Some(TodoEvent::Deleted { id: 11 }), second.next().await);
+        assert_eq!(Some(TodoEvent::Deleted { id: 12 }), second.next().await);
+    }
+
+    #[tokio::test]
+    async fn subscribe_all_receives_every_user_test() {
+        let events = TodoEvents::new();
+        let mut all = events.subscribe_all();
+        let first = events.subscribe(1);
+
+        events.publish(2, TodoEvent::Deleted { id: 10 });
+        first.publish(TodoEvent::Deleted { id: 11 });
+
+        assert_eq!(
+            Some((Some(2), TodoEvent::Deleted { id: 10 })),
+            all.next().await
+        );
+        assert_eq!(
+            Some((Some(1), TodoEvent::Deleted { id: 11 })),
+            all.next().await
+        );
+    }
+
+    #[tokio::test]
+    async fn outbox_events_ignore_direct_publish_test() {
+        let events = TodoEvents::new().with_outbox();
+        let first = events.subscribe(1);
+        let mut second = events.subscribe(2);
+
+        // outboxへ記録したイベントのみを流し、ユーザーに属さない変更は全員へ届ける
+        events.publish(2, TodoEvent::Deleted { id: 10 });
+        first.publish(TodoEvent::Deleted { id: 11 });
+        events.broadcast(None, TodoEvent::LabelDeleted { id: 1 });
+        events.broadcast(Some(2), TodoEvent::Deleted { id: 12 });
+
+        assert_eq!(Some(TodoEvent::LabelDeleted { id: 1 }), second.next().await);
+        assert_eq!(Some(TodoEvent::Deleted { id: 12 }), second.next().await);
+    }
+
+    #[tokio::test]
+    async fn shutdown_ends_subscriptions_test() {
+        let events = TodoEvents::new();
+        let mut subscription = events.subscribe(1);
+        let handle = tokio::spawn(async move { subscription.next().await });
+
+        events.shutdown(Duration::from_secs(1)).await;
+        assert_eq!(None, handle.await.unwrap());
+    }
+}
@@ -0,0 +1,639 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use async_graphql::dataloader::{DataLoader, Loader};
+use async_graphql::{
+    Context, EmptySubscription, InputObject, MaybeUndefined, Object, Request, Schema,
+};
+use axum::extract::Extension;
+use chrono::{DateTime, Utc};
+use validator::Validate;
+
+use crate::auth::AuthUser;
+use crate::error::AppError;
+use crate::events::{TodoEvent, TodoEvents};
+use crate::handlers::todo::{publish, update_and_publish};
+use crate::repositories::comment::{Comment, CommentRepository};
+use crate::repositories::label::{Label, LabelRepository};
+use crate::repositories::todo::{
+    CreateTodo, Precondition, Priority, TodoEntity, TodoListQuery, TodoRepository, UpdateTodo,
+};
+use crate::repositories::todo_item::TodoItem;
+use crate::repositories::{PageQuery, RepositoryError};
+
+pub type TodoSchema<T, L, C> = Schema<QueryRoot<T, L, C>, MutationRoot<T, C>, EmptySubscription>;
+
+// リポジトリはスキーマのdataとして持たせ、リゾルバーからは型で取り出す
+pub fn schema<T: TodoRepository, L: LabelRepository, C: CommentRepository>(
+    todo_repository: T,
+    label_repository: L,
+    comment_repository: C,
+) -> TodoSchema<T, L, C> {
+    Schema::build(
+        QueryRoot(PhantomData),
+        MutationRoot(PhantomData),
+        EmptySubscription,
+    )
+    .data(todo_repository)
+    .data(label_repository)
+    .data(comment_repository)
+    .finish()
+}
+
+// 認証したユーザーと変更の通知先をリクエストのdataに入れる
+// DataLoaderはユーザーごとにTodoを絞り込むため、リクエストごとに作る
+pub fn with_viewer<T: TodoRepository>(
+    request: Request,
+    user: AuthUser,
+    events: Option<Extension<TodoEvents>>,
+    todo_repository: T,
+) -> Request {
+    let labels = DataLoader::new(
+        LabelLoader {
+            repository: todo_repository,
+            user_id: user.id,
+        },
+        tokio::spawn,
+    );
+    request.data(user).data(events).data(labels)
+}
+
+// 一覧の各Todoのラベルを1件ずつ引かず、同じリクエスト内のidをまとめて1回で引く
+pub struct LabelLoader<T> {
+    repository: T,
+    user_id: i32,
+}
+
+impl<T: TodoRepository> Loader<i64> for LabelLoader<T> {
+    type Value = Vec<Label>;
+    type Error = async_graphql::Error;
+
+    async fn load(&self, keys: &[i64]) -> Result<HashMap<i64, Vec<Label>>, Self::Error> {
+        self.repository
+            .labels_for_todos(self.user_id, keys.to_vec())
+            .await
+            .map_err(|e| AppError::from(e).into())
+    }
+}
+
+fn viewer<'a>(ctx: &Context<'a>) -> async_graphql::Result<&'a AuthUser> {
+    ctx.data::<AuthUser>()
+}
+
+fn validated<P: Validate>(payload: P) -> async_graphql::Result<P> {
+    payload.validate().map_err(AppError::validation)?;
+    Ok(payload)
+}
+
+pub struct Todo<T, C> {
+    todo: TodoEntity,
+    repositories: PhantomData<(T, C)>,
+}
+
+impl<T, C> From<TodoEntity> for Todo<T, C> {
+    fn from(todo: TodoEntity) -> Self {
+        Self {
+            todo,
+            repositories: PhantomData,
+        }
+    }
+}
+
+#[Object(name = "Todo")]
+impl<T: TodoRepository, C: CommentRepository> Todo<T, C> {
+    async fn id(&self) -> i64 {
+        self.todo.id
+    }
+
+    async fn text(&self) -> &str {
+        &self.todo.text
+    }
+
+    async fn completed(&self) -> bool {
+        self.todo.completed
+    }
+
+    async fn priority(&self) -> Priority {
+        self.todo.priority
+    }
+
+    async fn due_date(&self) -> Option<DateTime<Utc>> {
+        self.todo.due_date
+    }
+
+    async fn remind_at(&self) -> Option<DateTime<Utc>> {
+        self.todo.remind_at
+    }
+
+    async fn archived_at(&self) -> Option<DateTime<Utc>> {
+        self.todo.archived_at
+    }
+
+    async fn version(&self) -> i32 {
+        self.todo.version
+    }
+
+    async fn created_at(&self) -> DateTime<Utc> {
+        self.todo.created_at
+    }
+
+    async fn updated_at(&self) -> DateTime<Utc> {
+        self.todo.updated_at
+    }
+
+    async fn labels(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<Label>> {
+        let loader = ctx.data::<DataLoader<LabelLoader<T>>>()?;
+        Ok(loader.load_one(self.todo.id).await?.unwrap_or_default())
+    }
+
+    async fn items(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<TodoItem>> {
+        let user = viewer(ctx)?;
+        let items = ctx
+            .data_unchecked::<T>()
+            .items(user.id, self.todo.id)
+            .await
+            .map_err(AppError::from)?;
+        Ok(items)
+    }
+
+    // 新しい順に返す
+    async fn comments(
+        &self,
+        ctx: &Context<'_>,
+        limit: Option<u32>,
+        offset: Option<u32>,
+    ) -> async_graphql::Result<Vec<Comment>> {
+        let page = ctx
+            .data_unchecked::<C>()
+            .all(self.todo.id, PageQuery { limit, offset })
+            .await
+            .map_err(AppError::from)?;
+        Ok(page.comments)
+    }
+}
+
+#[derive(Debug, Default, InputObject)]
+pub struct TodoFilter {
+    completed: Option<bool>,
+    // テキストの部分一致。大文字小文字を区別しない
+    search: Option<String>,
+    priority: Option<Priority>,
+    due_before: Option<DateTime<Utc>>,
+    due_after: Option<DateTime<Utc>>,
+    include_archived: Option<bool>,
+    limit: Option<u32>,
+    offset: Option<u32>,
+}
+
+impl From<TodoFilter> for TodoListQuery {
+    fn from(filter: TodoFilter) -> Self {
+        TodoListQuery {
+            limit: filter.limit,
+            offset: filter.offset,
+            completed: filter.completed,
+            q: filter.search,
+            due_before: filter.due_before,
+            due_after: filter.due_after,
+            priority: filter.priority.map(|priority| priority.to_string()),
+            include_archived: filter.include_archived,
+            ..TodoListQuery::default()
+        }
+    }
+}
+
+pub struct QueryRoot<T, L, C>(PhantomData<(T, L, C)>);
+
+#[Object(name = "Query")]
+impl<T: TodoRepository, L: LabelRepository, C: CommentRepository> QueryRoot<T, L, C> {
+    // RESTの一覧と同じく、既定ではアーカイブしたTodoを含めない
+    async fn todos(
+        &self,
+        ctx: &Context<'_>,
+        filter: Option<TodoFilter>,
+    ) -> async_graphql::Result<Vec<Todo<T, C>>> {
+        let user = viewer(ctx)?;
+        let page = ctx
+            .data_unchecked::<T>()
+            .all(user.id, filter.unwrap_or_default().into())
+            .await
+            .map_err(AppError::from)?;
+        Ok(page.todos.into_iter().map(Todo::from).collect())
+    }
+
+    // 存在しない・他のユーザーのTodoはnullを返す
+    async fn todo(&self, ctx: &Context<'_>, id: i64) -> async_graphql::Result<Option<Todo<T, C>>> {
+        let user = viewer(ctx)?;
+        match ctx.data_unchecked::<T>().find(user.id, id).await {
+            Ok(todo) => Ok(Some(todo.into())),
+            Err(RepositoryError::NotFound(_)) => Ok(None),
+            Err(e) => Err(AppError::from(e).into()),
+        }
+    }
+
+    async fn labels(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<Label>> {
+        viewer(ctx)?;
+        let labels = ctx
+            .data_unchecked::<L>()
+            .all()
+            .await
+            .map_err(AppError::from)?;
+        Ok(labels)
+    }
+}
+
+#[derive(Debug, InputObject)]
+pub struct CreateTodoInput {
+    text: String,
+    #[graphql(default)]
+    labels: Vec<i32>,
+    due_date: Option<DateTime<Utc>>,
+    priority: Option<Priority>,
+}
+
+impl From<CreateTodoInput> for CreateTodo {
+    fn from(input: CreateTodoInput) -> Self {
+        let mut payload = CreateTodo::new(input.text, input.labels);
+        if let Some(due_date) = input.due_date {
+            payload = payload.with_due_date(due_date);
+        }
+        if let Some(priority) = input.priority {
+            payload = payload.with_priority(priority);
+        }
+        payload
+    }
+}
+
+// 省略した項目は変更しない。dueDateはnullを指定すると期限を消す
+#[derive(Debug, InputObject)]
+pub struct UpdateTodoInput {
+    text: Option<String>,
+    completed: Option<bool>,
+    labels: Option<Vec<i32>>,
+    // 指定した場合、保存済みのversionと一致するときのみ更新する
+    version: Option<i32>,
+    due_date: MaybeUndefined<DateTime<Utc>>,
+    priority: Option<Priority>,
+}
+
+impl From<UpdateTodoInput> for UpdateTodo {
+    fn from(input: UpdateTodoInput) -> Self {
+        let mut payload = UpdateTodo::default();
+        if let Some(text) = input.text {
+            payload = payload.with_text(text);
+        }
+        if let Some(completed) = input.completed {
+            payload = payload.with_completed(completed);
+        }
+        if let Some(labels) = input.labels {
+            payload = payload.with_labels(labels);
+        }
+        if let Some(version) = input.version {
+            payload = payload.with_version(version);
+        }
+        if let Some(due_date) = input.due_date.as_opt_ref() {
+            payload = payload.with_due_date(due_date.copied());
+        }
+        if let Some(priority) = input.priority {
+            payload = payload.with_priority(priority);
+        }
+        payload
+    }
+}
+
+pub struct MutationRoot<T, C>(PhantomData<(T, C)>);
+
+// RESTのハンドラーと同じく、変更は購読中のWebSocket接続とWebhookへ通知する
+#[Object(name = "Mutation")]
+impl<T: TodoRepository, C: CommentRepository> MutationRoot<T, C> {
+    async fn create_todo(
+        &self,
+        ctx: &Context<'_>,
+        input: CreateTodoInput,
+    ) -> async_graphql::Result<Todo<T, C>> {
+        let user = viewer(ctx)?;
+        let payload = validated(CreateTodo::from(input))?;
+        let todo = ctx
+            .data_unchecked::<T>()
+            .create(user.id, payload)
+            .await
+            .map_err(AppError::from)?;
+        publish(
+            events(ctx),
+            user.id,
+            TodoEvent::Created { todo: todo.clone() },
+        );
+        Ok(todo.into())
+    }
+
+    async fn update_todo(
+        &self,
+        ctx: &Context<'_>,
+        id: i64,
+        input: UpdateTodoInput,
+    ) -> async_graphql::Result<Todo<T, C>> {
+        let user = viewer(ctx)?;
+        let payload = validated(UpdateTodo::from(input))?;
+        let updated =
+            update_and_publish(ctx.data_unchecked::<T>(), events(ctx), user.id, id, payload)
+                .await?;
+        Ok(updated.todo.into())
+    }
+
+    // ゴミ箱へ移す。削除できた場合はtrueを返す
+    async fn delete_todo(&self, ctx: &Context<'_>, id: i64) -> async_graphql::Result<bool> {
+        let user = viewer(ctx)?;
+        ctx.data_unchecked::<T>()
+            .delete(user.id, id, Precondition::default())
+            .await
+            .map_err(AppError::from)?;
+        publish(events(ctx), user.id, TodoEvent::Deleted { id });
+        Ok(true)
+    }
+}
+
+fn events<'a>(ctx: &Context<'a>) -> &'a Option<Extension<TodoEvents>> {
+    ctx.data_unchecked::<Option<Extension<TodoEvents>>>()
+}
+
+#[cfg(test)]
+mod test {
+    use async_graphql::Variables;
+    use serde_json::{json, Value};
+
+    use crate::repositories::comment::{CommentRepositoryForMemory, CreateComment};
+    use crate::repositories::label::{CreateLabel, LabelRepositoryForMemory};
+    use crate::repositories::test_utils::FailingTodoRepository;
+    use crate::repositories::todo::TodoRepositoryForMemory;
+    use crate::repositories::todo_item::CreateTodoItem;
+
+    use super::*;
+
+    const USER_ID: i32 = 1;
+
+    type TestTodoRepository = FailingTodoRepository<TodoRepositoryForMemory>;
+
+    struct TestSchema {
+        schema:
+            TodoSchema<TestTodoRepository, LabelRepositoryForMemory, CommentRepositoryForMemory>,
+        todos: TestTodoRepository,
+        labels: LabelRepositoryForMemory,
+        comments: CommentRepositoryForMemory,
+    }
+
+    impl TestSchema {
+        fn new() -> Self {
+            let labels = LabelRepositoryForMemory::new();
+            let todos = FailingTodoRepository::new(
+                TodoRepositoryForMemory::new(vec![]).with_labels(&labels),
+            );
+            let comments = CommentRepositoryForMemory::new();
+            Self {
+                schema: schema(todos.clone(), labels.clone(), comments.clone()),
+                todos,
+                labels,
+                comments,
+            }
+        }
+
+        async fn execute(&self, query: &str, variables: Value) -> async_graphql::Response {
+            let user = AuthUser {
+                id: USER_ID,
+                username: "user1".to_string(),
+            };
+            let request = Request::new(query).variables(Variables::from_json(variables));
+            self.schema
+                .execute(with_viewer(request, user, None, self.todos.clone()))
+                .await
+        }
+
+        async fn data(&self, query: &str, variables: Value) -> Value {
+            let res = self.execute(query, variables).await;
+            assert!(res.errors.is_empty(), "{:?}", res.errors);
+            res.data.into_json().unwrap()
+        }
+
+        async fn error(&self, query: &str, variables: Value) -> Value {
+            let res = self.execute(query, variables).await;
+            assert_eq!(1, res.errors.len(), "{:?}", res.errors);
+            serde_json::to_value(&res.errors[0]).unwrap()
+        }
+    }
+
+    #[tokio::test]
+    async fn should_resolve_todos_with_associations() {
+        let test = TestSchema::new();
+        let work = test
+            .labels
+            .create(CreateLabel::new("work".to_string()))
+            .await
+            .unwrap();
+        let home = test
+            .labels
+            .create(CreateLabel::new("home".to_string()))
+            .await
+            .unwrap();
+        let report = test
+            .todos
+            .create(
+                USER_ID,
+                CreateTodo::new("write report".to_string(), vec![work.id, home.id]),
+            )
+            .await
+            .unwrap();
+        test.todos
+            .create(USER_ID, CreateTodo::new("buy milk".to_string(), vec![]))
+            .await
+            .unwrap();
+        test.todos
+            .create(
+                USER_ID + 1,
+                CreateTodo::new("theirs".to_string(), vec![work.id]),
+            )
+            .await
+            .unwrap();
+        test.todos
+            .create_item(
+                USER_ID,
+                report.id,
+                CreateTodoItem {
+                    text: "outline".to_string(),
+                },
+            )
+            .await
+            .unwrap();
+        test.comments
+            .create(
+                report.id,
+                "user1".to_string(),
+                CreateComment {
+                    body: "due friday".to_string(),
+                },
+            )
+            .await
+            .unwrap();
+
+        let data = test
+            .data(
+                r#"{
+                    todos(filter: { search: "r" }) {
+                        text
+                        labels { name }
+                        items { text completed }
+                        comments { author body }
+                    }
+                    labels { name color }
+                }"#,
+                json!({}),
+            )
+            .await;
+        assert_eq!(
+            json!({
+                "todos": [
+                    {
+                        "text": "write report",
+                        "labels": [{ "name": "work" }, { "name": "home" }],
+                        "items": [{ "text": "outline", "completed": false }],
+                        "comments": [{ "author": "user1", "body": "due friday" }],
+                    },
+                ],
+            })["todos"],
+            data["todos"]
+        );
+        assert_eq!(
+            json!([{ "name": "work", "color": "#808080" }, { "name": "home", "color": "#808080" }]),
+            data["labels"]
+        );
+
+        // 一覧の全Todoのラベルを1回の呼び出しでまとめて引く
+        let data = test
+            .data("{ todos { text labels { name } } }", json!({}))
+            .await;
+        assert_eq!(2, data["todos"].as_array().unwrap().len());
+        assert_eq!(2, test.todos.calls("labels_for_todos"));
+    }
+
+    #[tokio::test]
+    async fn should_find_own_todo_by_id() {
+        let test = TestSchema::new();
+        let mine = test
+            .todos
+            .create(USER_ID, CreateTodo::new("mine".to_string(), vec![]))
+            .await
+            .unwrap();
+        let theirs = test
+            .todos
+            .create(USER_ID + 1, CreateTodo::new("theirs".to_string(), vec![]))
+            .await
+            .unwrap();
+
+        let query = "query ($id: Int!) { todo(id: $id) { id text priority } }";
+        let data = test.data(query, json!({ "id": mine.id })).await;
+        assert_eq!(
+            json!({ "id": mine.id, "text": "mine", "priority": "MEDIUM" }),
+            data["todo"]
+        );
+        let data = test.data(query, json!({ "id": theirs.id })).await;
+        assert_eq!(Value::Null, data["todo"]);
+    }
+
+    #[tokio::test]
+    async fn should_create_update_and_delete_todo() {
+        let test = TestSchema::new();
+        let work = test
+            .labels
+            .create(CreateLabel::new("work".to_string()))
+            .await
+            .unwrap();
+
+        let data = test
+            .data(
+                r#"mutation ($labels: [Int!]!) {
+                    createTodo(input: {
+                        text: "write report",
+                        labels: $labels,
+                        dueDate: "2024-03-10T09:00:00Z",
+                        priority: HIGH,
+                    }) { id dueDate priority labels { name } }
+                }"#,
+                json!({ "labels": [work.id] }),
+            )
+            .await;
+        let created = &data["createTodo"];
+        assert_eq!("2024-03-10T09:00:00+00:00", created["dueDate"]);
+        assert_eq!("HIGH", created["priority"]);
+        assert_eq!(json!([{ "name": "work" }]), created["labels"]);
+        let id = created["id"].as_i64().unwrap();
+
+        // nullを指定した期限は消え、省略した項目は変わらない
+        let data = test
+            .data(
+                r#"mutation ($id: Int!) {
+                    updateTodo(id: $id, input: { completed: true, dueDate: null }) {
+                        completed dueDate priority version
+                    }
+                }"#,
+                json!({ "id": id }),
+            )
+            .await;
+        assert_eq!(
+            json!({ "completed": true, "dueDate": null, "priority": "HIGH", "version": 2 }),
+            data["updateTodo"]
+        );
+
+        let data = test
+            .data(
+                "mutation ($id: Int!) { deleteTodo(id: $id) }",
+                json!({ "id": id }),
+            )
+            .await;
+        assert_eq!(Value::Bool(true), data["deleteTodo"]);
+        assert!(test.todos.find(USER_ID, id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn should_report_errors_with_codes() {
+        let test = TestSchema::new();
+        let todo = test
+            .todos
+            .create(USER_ID, CreateTodo::new("write report".to_string(), vec![]))
+            .await
+            .unwrap();
+
+        let error = test
+            .error(
+                r#"mutation { createTodo(input: { text: " " }) { id } }"#,
+                json!({}),
+            )
+            .await;
+        assert_eq!("validation_error", error["extensions"]["code"]);
+        assert_eq!(
+            json!([{ "field": "text", "message": "Can not be empty" }]),
+            error["extensions"]["fields"]
+        );
+
+        let error = test
+            .error(
+                "mutation { updateTodo(id: 999, input: { text: \"x\" }) { id } }",
+                json!({}),
+            )
+            .await;
+        assert_eq!("not_found", error["extensions"]["code"]);
+
+        // バージョン競合では最新のTodoを添えて返す
+        let error = test
+            .error(
+                r#"mutation ($id: Int!) {
+                    updateTodo(id: $id, input: { text: "x", version: 9 }) { id }
+                }"#,
+                json!({ "id": todo.id }),
+            )
+            .await;
+        assert_eq!("version_conflict", error["extensions"]["code"]);
+        assert_eq!("write report", error["extensions"]["current"]["text"]);
+
+        let error = test
+            .error("mutation { deleteTodo(id: 999) }", json!({}))
+            .await;
+        assert_eq!("not_found", error["extensions"]["code"]);
+    }
+}
@@ -0,0 +1,655 @@
+use std::future::Future;
+use std::net::TcpListener;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use axum::extract::Extension;
+use chrono::{DateTime, TimeZone, Utc};
+use futures_util::{stream, Stream};
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::transport::Server;
+use tonic::{Request, Response, Status};
+use validator::Validate;
+
+use crate::auth::{unauthorized, AuthKeys};
+use crate::error::AppError;
+use crate::events::{TodoEvent, TodoEvents};
+use crate::handlers::todo::{publish, update_and_publish};
+use crate::repositories::label::Label;
+use crate::repositories::todo::{
+    CreateTodo, Precondition, Priority, TodoEntity, TodoListQuery, TodoRepository, UpdateTodo,
+};
+
+use self::proto::todo_event::Event;
+use self::proto::todo_service_server::{TodoService, TodoServiceServer};
+
+pub mod proto {
+    tonic::include_proto!("todo.v1");
+}
+
+// HTTPのアプリと同じリポジトリ・イベントを共有し、どちらの変更も互いの購読者へ届ける
+pub struct TodoGrpcService<T> {
+    repository: Arc<T>,
+    events: TodoEvents,
+    keys: Arc<AuthKeys>,
+}
+
+impl<T: TodoRepository> TodoGrpcService<T> {
+    pub fn new(repository: Arc<T>, events: TodoEvents, keys: Arc<AuthKeys>) -> Self {
+        Self {
+            repository,
+            events,
+            keys,
+        }
+    }
+
+    // RESTと同じJWTを、authorizationメタデータから読む
+    fn viewer<M>(&self, request: &Request<M>) -> Result<i32, AppError> {
+        let token = request
+            .metadata()
+            .get("authorization")
+            .ok_or_else(|| unauthorized("Missing authorization metadata"))?
+            .to_str()
+            .ok()
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| unauthorized("Malformed authorization metadata"))?;
+        self.keys
+            .user_id(token)
+            .ok_or_else(|| unauthorized("Invalid or expired token"))
+    }
+
+    // RESTのハンドラーと共通の通知処理へ渡す形にする
+    fn events(&self) -> Option<Extension<TodoEvents>> {
+        Some(Extension(self.events.clone()))
+    }
+}
+
+// 呼び出し側でbindしたlistenerを受け取るため、テストではポート0で起動できる
+pub async fn serve<T: TodoRepository>(
+    listener: TcpListener,
+    service: TodoGrpcService<T>,
+    shutdown: impl Future<Output = ()>,
+) -> anyhow::Result<()> {
+    tracing::debug!("grpc listening on {}", listener.local_addr()?);
+    listener.set_nonblocking(true)?;
+    let listener = tokio::net::TcpListener::from_std(listener)?;
+    Server::builder()
+        .add_service(TodoServiceServer::new(service))
+        .serve_with_incoming_shutdown(TcpListenerStream::new(listener), shutdown)
+        .await?;
+    Ok(())
+}
+
+type WatchTodosStream = Pin<Box<dyn Stream<Item = Result<proto::TodoEvent, Status>> + Send>>;
+
+#[tonic::async_trait]
+impl<T: TodoRepository> TodoService for TodoGrpcService<T> {
+    async fn list_todos(
+        &self,
+        request: Request<proto::ListTodosRequest>,
+    ) -> Result<Response<proto::ListTodosResponse>, Status> {
+        let user_id = self.viewer(&request)?;
+        let query = TodoListQuery::try_from(request.into_inner())?;
+        let page = self
+            .repository
+            .all(user_id, query)
+            .await
+            .map_err(AppError::from)?;
+        Ok(Response::new(proto::ListTodosResponse {
+            todos: page.todos.into_iter().map(proto::Todo::from).collect(),
+            total: page.total,
+        }))
+    }
+
+    async fn get_todo(
+        &self,
+        request: Request<proto::GetTodoRequest>,
+    ) -> Result<Response<proto::Todo>, Status> {
+        let user_id = self.viewer(&request)?;
+        let todo = self
+            .repository
+            .find(user_id, request.into_inner().id)
+            .await
+            .map_err(AppError::from)?;
+        Ok(Response::new(todo.into()))
+    }
+
+    async fn create_todo(
+        &self,
+        request: Request<proto::CreateTodoRequest>,
+    ) -> Result<Response<proto::Todo>, Status> {
+        let user_id = self.viewer(&request)?;
+        let payload = validated(CreateTodo::try_from(request.into_inner())?)?;
+        let todo = self
+            .repository
+            .create(user_id, payload)
+            .await
+            .map_err(AppError::from)?;
+        publish(
+            &self.events(),
+            user_id,
+            TodoEvent::Created { todo: todo.clone() },
+        );
+        Ok(Response::new(todo.into()))
+    }
+
+    async fn update_todo(
+        &self,
+        request: Request<proto::UpdateTodoRequest>,
+    ) -> Result<Response<proto::Todo>, Status> {
+        let user_id = self.viewer(&request)?;
+        let request = request.into_inner();
+        let id = request.id;
+        let payload = validated(UpdateTodo::try_from(request)?)?;
+        let updated = update_and_publish(
+            self.repository.as_ref(),
+            &self.events(),
+            user_id,
+            id,
+            payload,
+        )
+        .await?;
+        Ok(Response::new(updated.todo.into()))
+    }
+
+    async fn delete_todo(
+        &self,
+        request: Request<proto::DeleteTodoRequest>,
+    ) -> Result<Response<proto::DeleteTodoResponse>, Status> {
+        let user_id = self.viewer(&request)?;
+        let id = request.into_inner().id;
+        self.repository
+            .delete(user_id, id, Precondition::default())
+            .await
+            .map_err(AppError::from)?;
+        publish(&self.events(), user_id, TodoEvent::Deleted { id });
+        Ok(Response::new(proto::DeleteTodoResponse {}))
+    }
+
+    type WatchTodosStream = WatchTodosStream;
+
+    // 終了時は購読が閉じられ、ストリームも終わる
+    async fn watch_todos(
+        &self,
+        request: Request<proto::WatchTodosRequest>,
+    ) -> Result<Response<Self::WatchTodosStream>, Status> {
+        let user_id = self.viewer(&request)?;
+        let subscription = self.events.subscribe(user_id);
+        let events = stream::unfold(subscription, |mut subscription| async move {
+            loop {
+                if let Some(event) = watched(subscription.next().await?) {
+                    return Some((Ok(event), subscription));
+                }
+            }
+        });
+        Ok(Response::new(Box::pin(events)))
+    }
+}
+
+fn validated<P: Validate>(payload: P) -> Result<P, AppError> {
+    payload.validate().map_err(AppError::validation)?;
+    Ok(payload)
+}
+
+fn timestamp(at: DateTime<Utc>) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds: at.timestamp(),
+        nanos: at.timestamp_subsec_nanos() as i32,
+    }
+}
+
+fn date_time(field: &str, at: prost_types::Timestamp) -> Result<DateTime<Utc>, AppError> {
+    u32::try_from(at.nanos)
+        .ok()
+        .and_then(|nanos| Utc.timestamp_opt(at.seconds, nanos).single())
+        .ok_or_else(|| AppError::bad_request("Invalid timestamp").with_field(field, "Out of range"))
+}
+
+fn optional_date_time(
+    field: &str,
+    at: Option<prost_types::Timestamp>,
+) -> Result<Option<DateTime<Utc>>, AppError> {
+    at.map(|at| date_time(field, at)).transpose()
+}
+
+// 未指定(0)はNoneとして扱い、未知の値は不正な引数とする
+fn priority(value: i32) -> Result<Option<Priority>, AppError> {
+    match proto::Priority::try_from(value) {
+        Ok(proto::Priority::Unspecified) => Ok(None),
+        Ok(proto::Priority::Low) => Ok(Some(Priority::Low)),
+        Ok(proto::Priority::Medium) => Ok(Some(Priority::Medium)),
+        Ok(proto::Priority::High) => Ok(Some(Priority::High)),
+        Err(_) => Err(AppError::bad_request("Invalid priority")
+            .with_field("priority", format!("Unknown value {}", value))),
+    }
+}
+
+impl From<Priority> for proto::Priority {
+    fn from(priority: Priority) -> Self {
+        match priority {
+            Priority::Low => proto::Priority::Low,
+            Priority::Medium => proto::Priority::Medium,
+            Priority::High => proto::Priority::High,
+        }
+    }
+}
+
+impl From<Label> for proto::Label {
+    fn from(label: Label) -> Self {
+        Self {
+            id: label.id,
+            name: label.name,
+            color: label.color,
+            description: label.description,
+        }
+    }
+}
+
+impl From<TodoEntity> for proto::Todo {
+    fn from(todo: TodoEntity) -> Self {
+        Self {
+            id: todo.id,
+            text: todo.text,
+            completed: todo.completed,
+            labels: todo.labels.into_iter().map(proto::Label::from).collect(),
+            priority: proto::Priority::from(todo.priority).into(),
+            due_date: todo.due_date.map(timestamp),
+            remind_at: todo.remind_at.map(timestamp),
+            archived_at: todo.archived_at.map(timestamp),
+            version: todo.version,
+            created_at: Some(timestamp(todo.created_at)),
+            updated_at: Some(timestamp(todo.updated_at)),
+        }
+    }
+}
+
+// WatchTodosはTodoの変更のみを流すため、ラベルの変更はNoneにして送らない
+fn watched(event: TodoEvent) -> Option<proto::TodoEvent> {
+    let event = match event {
+        TodoEvent::Created { todo } => Event::Created(todo.into()),
+        TodoEvent::Updated { todo, .. } => Event::Updated(todo.into()),
+        TodoEvent::Deleted { id } => Event::Deleted(id),
+        TodoEvent::Reminded { todo } => Event::Reminded(todo.into()),
+        TodoEvent::LabelCreated { .. }
+        | TodoEvent::LabelUpdated { .. }
+        | TodoEvent::LabelDeleted { .. }
+        | TodoEvent::LabelsMerged { .. } => return None,
+    };
+    Some(proto::TodoEvent { event: Some(event) })
+}
+
+// RESTの一覧と同じく、既定ではアーカイブしたTodoを含めない
+impl TryFrom<proto::ListTodosRequest> for TodoListQuery {
+    type Error = AppError;
+
+    fn try_from(request: proto::ListTodosRequest) -> Result<Self, Self::Error> {
+        Ok(TodoListQuery {
+            limit: request.limit,
+            offset: request.offset,
+            completed: request.completed,
+            q: request.search,
+            due_before: optional_date_time("due_before", request.due_before)?,
+            due_after: optional_date_time("due_after", request.due_after)?,
+            priority: priority(request.priority)?.map(|priority| priority.to_string()),
+            include_archived: Some(request.include_archived),
+            ..TodoListQuery::default()
+        })
+    }
+}
+
+impl TryFrom<proto::CreateTodoRequest> for CreateTodo {
+    type Error = AppError;
+
+    fn try_from(request: proto::CreateTodoRequest) -> Result<Self, Self::Error> {
+        let mut payload = CreateTodo::new(request.text, request.labels);
+        if let Some(due_date) = optional_date_time("due_date", request.due_date)? {
+            payload = payload.with_due_date(due_date);
+        }
+        if let Some(priority) = priority(request.priority)? {
+            payload = payload.with_priority(priority);
+        }
+        Ok(payload)
+    }
+}
+
+impl TryFrom<proto::UpdateTodoRequest> for UpdateTodo {
+    type Error = AppError;
+
+    fn try_from(request: proto::UpdateTodoRequest) -> Result<Self, Self::Error> {
+        let mut payload = UpdateTodo::default();
+        if let Some(text) = request.text {
+            payload = payload.with_text(text);
+        }
+        if let Some(completed) = request.completed {
+            payload = payload.with_completed(completed);
+        }
+        if let Some(labels) = request.labels {
+            payload = payload.with_labels(labels.ids);
+        }
+        if let Some(version) = request.version {
+            payload = payload.with_version(version);
+        }
+        if request.clear_due_date {
+            payload = payload.with_due_date(None);
+        } else if let Some(due_date) = optional_date_time("due_date", request.due_date)? {
+            payload = payload.with_due_date(Some(due_date));
+        }
+        if let Some(priority) = priority(request.priority)? {
+            payload = payload.with_priority(priority);
+        }
+        Ok(payload)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use futures_util::StreamExt;
+    use tonic::codegen::InterceptedService;
+    use tonic::service::Interceptor;
+    use tonic::transport::Channel;
+    use tonic::{Code, Streaming};
+
+    use crate::auth::test_utils::{test_keys, test_token};
+    use crate::repositories::label::{CreateLabel, LabelRepository, LabelRepositoryForMemory};
+    use crate::repositories::todo::TodoRepositoryForMemory;
+
+    use super::proto::todo_service_client::TodoServiceClient;
+    use super::*;
+
+    const USER_ID: i32 = 1;
+
+    #[derive(Clone)]
+    struct Bearer(Option<String>);
+
+    impl Interceptor for Bearer {
+        fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+            if let Some(token) = &self.0 {
+                let value = format!("Bearer {}", token).parse().unwrap();
+                request.metadata_mut().insert("authorization", value);
+            }
+            Ok(request)
+        }
+    }
+
+    type TestClient = TodoServiceClient<InterceptedService<Channel, Bearer>>;
+
+    struct TestServer {
+        channel: Channel,
+        repository: TodoRepositoryForMemory,
+        labels: LabelRepositoryForMemory,
+        events: TodoEvents,
+    }
+
+    impl TestServer {
+        async fn start() -> Self {
+            let labels = LabelRepositoryForMemory::new();
+            let repository = TodoRepositoryForMemory::new(vec![]).with_labels(&labels);
+            let events = TodoEvents::new();
+            let service = TodoGrpcService::new(
+                Arc::new(repository.clone()),
+                events.clone(),
+                Arc::new(test_keys()),
+            );
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(serve(listener, service, futures_util::future::pending()));
+            let channel = Channel::from_shared(format!("http://{}", addr))
+                .unwrap()
+                .connect()
+                .await
+                .unwrap();
+            Self {
+                channel,
+                repository,
+                labels,
+                events,
+            }
+        }
+
+        fn client(&self) -> TestClient {
+            self.client_with(Some(test_token(USER_ID)))
+        }
+
+        fn client_with(&self, token: Option<String>) -> TestClient {
+            TodoServiceClient::with_interceptor(self.channel.clone(), Bearer(token))
+        }
+    }
+
+    #[tokio::test]
+    async fn should_create_get_and_list_todos() {
+        let server = TestServer::start().await;
+        let mut client = server.client();
+        let work = server
+            .labels
+            .create(CreateLabel::new("work".to_string()))
+            .await
+            .unwrap();
+        server
+            .repository
+            .create(USER_ID + 1, CreateTodo::new("theirs".to_string(), vec![]))
+            .await
+            .unwrap();
+
+        let due_date = Utc.with_ymd_and_hms(2024, 3, 10, 9, 0, 0).unwrap();
+        let created = client
+            .create_todo(proto::CreateTodoRequest {
+                text: "write report".to_string(),
+                labels: vec![work.id],
+                due_date: Some(timestamp(due_date)),
+                priority: proto::Priority::High.into(),
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!("write report", created.text);
+        assert_eq!(vec!["work".to_string()], label_names(&created));
+        assert_eq!(Some(timestamp(due_date)), created.due_date);
+        assert_eq!(proto::Priority::High, created.priority());
+        client
+            .create_todo(proto::CreateTodoRequest {
+                text: "buy milk".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+
+        let found = client
+            .get_todo(proto::GetTodoRequest { id: created.id })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(created, found);
+
+        let page = client
+            .list_todos(proto::ListTodosRequest::default())
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(2, page.total);
+        let page = client
+            .list_todos(proto::ListTodosRequest {
+                search: Some("REPORT".to_string()),
+                priority: proto::Priority::High.into(),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(vec![created], page.todos);
+    }
+
+    #[tokio::test]
+    async fn should_update_and_delete_todo() {
+        let server = TestServer::start().await;
+        let mut client = server.client();
+        let todo = server
+            .repository
+            .create(
+                USER_ID,
+                CreateTodo::new("write report".to_string(), vec![])
+                    .with_due_date(Utc.with_ymd_and_hms(2024, 3, 10, 9, 0, 0).unwrap()),
+            )
+            .await
+            .unwrap();
+
+        // 指定しない項目は変わらず、clear_due_dateで期限を消せる
+        let updated = client
+            .update_todo(proto::UpdateTodoRequest {
+                id: todo.id,
+                completed: Some(true),
+                clear_due_date: true,
+                version: Some(todo.version),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(updated.completed);
+        assert_eq!("write report", updated.text);
+        assert_eq!(None, updated.due_date);
+        assert_eq!(todo.version + 1, updated.version);
+
+        client
+            .delete_todo(proto::DeleteTodoRequest { id: todo.id })
+            .await
+            .unwrap();
+        let status = client
+            .get_todo(proto::GetTodoRequest { id: todo.id })
+            .await
+            .unwrap_err();
+        assert_eq!(Code::NotFound, status.code());
+    }
+
+    #[tokio::test]
+    async fn should_map_errors_to_status_codes() {
+        let server = TestServer::start().await;
+        let mut client = server.client();
+        let todo = server
+            .repository
+            .create(USER_ID, CreateTodo::new("write report".to_string(), vec![]))
+            .await
+            .unwrap();
+
+        let status = client
+            .create_todo(proto::CreateTodoRequest {
+                text: " ".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(Code::InvalidArgument, status.code());
+        assert!(status.message().contains("text: Can not be empty"));
+
+        let status = client
+            .create_todo(proto::CreateTodoRequest {
+                text: "write report".to_string(),
+                labels: vec![999],
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(Code::InvalidArgument, status.code());
+
+        let status = client
+            .update_todo(proto::UpdateTodoRequest {
+                id: todo.id,
+                text: Some("stale".to_string()),
+                version: Some(todo.version + 1),
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(Code::Aborted, status.code());
+
+        let status = client
+            .delete_todo(proto::DeleteTodoRequest { id: 999 })
+            .await
+            .unwrap_err();
+        assert_eq!(Code::NotFound, status.code());
+
+        let status = client
+            .list_todos(proto::ListTodosRequest {
+                priority: 9,
+                ..Default::default()
+            })
+            .await
+            .unwrap_err();
+        assert_eq!(Code::InvalidArgument, status.code());
+
+        // トークンがない・不正な場合は認証エラーにする
+        for token in [None, Some("invalid".to_string())] {
+            let status = server
+                .client_with(token)
+                .list_todos(proto::ListTodosRequest::default())
+                .await
+                .unwrap_err();
+            assert_eq!(Code::Unauthenticated, status.code());
+        }
+    }
+
+    #[tokio::test]
+    async fn should_stream_own_todo_changes() {
+        let server = TestServer::start().await;
+        let mut client = server.client();
+        let mut watch = client
+            .watch_todos(proto::WatchTodosRequest {})
+            .await
+            .unwrap()
+            .into_inner();
+
+        // HTTP側・他のユーザーの変更も同じイベントを経由する
+        server
+            .events
+            .publish(USER_ID + 1, TodoEvent::Deleted { id: 100 });
+        let created = client
+            .create_todo(proto::CreateTodoRequest {
+                text: "write report".to_string(),
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        client
+            .update_todo(proto::UpdateTodoRequest {
+                id: created.id,
+                completed: Some(true),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        client
+            .delete_todo(proto::DeleteTodoRequest { id: created.id })
+            .await
+            .unwrap();
+
+        assert_eq!(
+            Event::Created(created.clone()),
+            next_event(&mut watch).await
+        );
+        match next_event(&mut watch).await {
+            Event::Updated(todo) => assert!(todo.completed),
+            event => panic!("unexpected event {:?}", event),
+        }
+        assert_eq!(Event::Deleted(created.id), next_event(&mut watch).await);
+
+        // 終了時はストリームも終わる
+        server.events.shutdown(Duration::from_secs(5)).await;
+        assert!(watch.next().await.is_none());
+    }
+
+    async fn next_event(watch: &mut Streaming<proto::TodoEvent>) -> Event {
+        tokio::time::timeout(Duration::from_secs(5), watch.next())
+            .await
+            .expect("no event within timeout")
+            .unwrap()
+            .unwrap()
+            .event
+            .unwrap()
+    }
+
+    fn label_names(todo: &proto::Todo) -> Vec<String> {
+        todo.labels.iter().map(|label| label.name.clone()).collect()
+    }
+}
@@ -1,11 +1,46 @@
-use axum::extract::{FromRequest, RequestParts};
-use axum::http::StatusCode;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+
+use axum::body::{boxed, Full, StreamBody};
+use axum::extract::path::ErrorKind;
+use axum::extract::rejection::{JsonRejection, PathRejection};
+use axum::extract::{FromRequest, Path, RequestParts};
+use axum::http::header::{
+    CONTENT_TYPE, ETAG, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_UNMODIFIED_SINCE,
+};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::Response;
 use axum::{async_trait, BoxError, Json};
+use chrono::{DateTime, Utc};
+use futures_util::stream::{BoxStream, Stream, StreamExt};
 use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use serde_path_to_error::Segment;
+use utoipa::IntoParams;
 use validator::Validate;
 
+use crate::body_limit;
+use crate::error::AppError;
+use crate::naming::{snake_case, snake_case_keys};
+use crate::repositories::todo::Precondition;
+use crate::repositories::RepositoryError;
+
+pub mod admin;
+pub mod auth;
+pub mod backup;
+pub mod comment;
+pub mod docs;
+pub mod fallback;
+pub mod graphql;
+pub mod health;
 pub mod label;
+pub mod metrics;
 pub mod todo;
+pub mod todo_item;
+pub mod user;
+pub mod webhook;
+pub mod ws;
 
 #[derive(Debug)]
 pub struct ValidatedJson<T>(T);
@@ -18,17 +53,601 @@ where
     B::Data: Send,
     B::Error: Into<BoxError>,
 {
-    type Rejection = (StatusCode, String);
+    type Rejection = AppError;
 
     async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
-        let Json(value) = Json::<T>::from_request(req).await.map_err(|rejection| {
-            let message = format!("Json parse error: [{}]", rejection);
-            (StatusCode::BAD_REQUEST, message)
-        })?;
-        value.validate().map_err(|rejection| {
-            let message = format!("Validation error: [{}]", rejection).replace("\n", ", ");
-            (StatusCode::BAD_REQUEST, message)
-        })?;
+        let strict = StrictRequests::from_request(req).await?;
+        let Json(value) = Json::<Value>::from_request(req)
+            .await
+            .map_err(json_rejection_error)?;
+        let value: T = parse_json_value(value, strict)?;
+        value.validate().map_err(AppError::validation)?;
         Ok(ValidatedJson(value))
     }
 }
+
+pub const MERGE_PATCH_CONTENT_TYPE: &str = "application/merge-patch+json";
+
+// JSON Merge Patch(RFC 7396)でnullを受け取った項目を、消した後の値に置き換える
+// 消すことのできない項目はErrでメッセージを返す
+pub trait MergePatch {
+    fn cleared(field: &str) -> Result<Value, String>;
+}
+
+// PATCHのbody。application/merge-patch+jsonの場合はnullを項目を消す指定として読み替える
+// application/jsonの場合はValidatedJsonと同じく、消すことのできない項目へのnullは省略と同じに扱う
+#[derive(Debug)]
+pub struct ValidatedPatch<T>(T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for ValidatedPatch<T>
+where
+    T: DeserializeOwned + Validate + MergePatch,
+    B: http_body::Body + Send,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let strict = StrictRequests::from_request(req).await?;
+        let merge_patch = req.headers().is_some_and(is_merge_patch);
+        // axumのJsonは+jsonのContent-Typeも受け付ける
+        let Json(mut value) = Json::<Value>::from_request(req)
+            .await
+            .map_err(json_rejection_error)?;
+        if merge_patch {
+            clear_nulls::<T>(&mut value)?;
+        }
+        let value: T = parse_json_value(value, strict)?;
+        value.validate().map_err(AppError::validation)?;
+        Ok(ValidatedPatch(value))
+    }
+}
+
+fn is_merge_patch(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<mime::Mime>().ok())
+        .is_some_and(|mime| mime.essence_str() == MERGE_PATCH_CONTENT_TYPE)
+}
+
+// 入れ子のオブジェクトを持つbodyはないため、最上位の項目のみ読み替える
+fn clear_nulls<T: MergePatch>(value: &mut Value) -> Result<(), AppError> {
+    let object = match value.as_object_mut() {
+        Some(object) => object,
+        None => return Ok(()),
+    };
+    let mut errors = vec![];
+    for (field, value) in object.iter_mut().filter(|(_, value)| value.is_null()) {
+        // キーはparse_json_valueでsnake_caseにそろえる前のまま
+        match T::cleared(&snake_case(field)) {
+            Ok(cleared) => *value = cleared,
+            Err(message) => errors.push((field.clone(), message)),
+        }
+    }
+    if errors.is_empty() {
+        return Ok(());
+    }
+    Err(errors.into_iter().fold(
+        AppError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "validation_error",
+            "Validation error",
+        ),
+        |error, (field, message)| error.with_field(field, message),
+    ))
+}
+
+// deny_unknown_fieldsを付けたbodyの未知のフィールドを拒否するか。Extensionが未設定の場合は拒否する
+#[derive(Debug, Clone, Copy)]
+pub struct StrictRequests(pub bool);
+
+impl Default for StrictRequests {
+    fn default() -> Self {
+        Self(true)
+    }
+}
+
+#[async_trait]
+impl<B> FromRequest<B> for StrictRequests
+where
+    B: Send,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        Ok(req
+            .extensions()
+            .and_then(|extensions| extensions.get::<StrictRequests>())
+            .copied()
+            .unwrap_or_default())
+    }
+}
+
+// 一度Valueとして読み込み、型が合わない場合にどのフィールドかを特定できるようにする
+// キーはcamelCaseでも受け付ける
+pub fn parse_json_value<T: DeserializeOwned>(
+    value: Value,
+    StrictRequests(strict): StrictRequests,
+) -> Result<T, AppError> {
+    let mut value = snake_case_keys(value).map_err(|field| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_json",
+            format!("Json parse error: [duplicate field `{}`]", field),
+        )
+        .with_field(field, "Must be sent in either camelCase or snake_case")
+    })?;
+    loop {
+        let e = match serde_path_to_error::deserialize(value.clone()) {
+            Ok(parsed) => return Ok(parsed),
+            Err(e) => e,
+        };
+        let message = e.inner().to_string();
+        let (parent, name) = match unknown_field(e.path(), &message) {
+            Some(unknown) => unknown,
+            None => {
+                return Err(AppError::new(
+                    StatusCode::BAD_REQUEST,
+                    "invalid_json",
+                    format!("Json parse error: [{}]", message),
+                )
+                .with_field(e.path().to_string(), message))
+            }
+        };
+        // 従来どおり無視するため、取り除いてから読み直す
+        if !strict && remove_field(&mut value, &parent, &name) {
+            continue;
+        }
+        let field = if parent.len() < e.path().iter().count() {
+            e.path().to_string()
+        } else if parent.is_empty() {
+            name
+        } else {
+            format!("{}.{}", e.path(), name)
+        };
+        // serdeのメッセージには受け付けるフィールドの一覧が含まれる
+        return Err(AppError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "unknown_field",
+            format!("Unknown field: [{}]", field),
+        )
+        .with_field(field, message));
+    }
+}
+
+// 未知のフィールドを含むオブジェクトまでのパスと、フィールド名を返す
+// 内部タグ付きのenumではパスがフィールド名を含まないため、名前はメッセージから取り出す
+fn unknown_field<'a>(
+    path: &'a serde_path_to_error::Path,
+    message: &str,
+) -> Option<(Vec<&'a Segment>, String)> {
+    let name = message
+        .strip_prefix("unknown field `")?
+        .split('`')
+        .next()?
+        .to_string();
+    let mut parent: Vec<_> = path.iter().collect();
+    if matches!(parent.last(), Some(Segment::Map { key }) if *key == name) {
+        parent.pop();
+    }
+    Some((parent, name))
+}
+
+fn remove_field(value: &mut Value, parent: &[&Segment], name: &str) -> bool {
+    let mut current = value;
+    for segment in parent {
+        let next = match segment {
+            Segment::Map { key } => current.get_mut(key),
+            Segment::Seq { index } => current.get_mut(*index),
+            _ => None,
+        };
+        current = match next {
+            Some(next) => next,
+            None => return false,
+        };
+    }
+    current
+        .as_object_mut()
+        .is_some_and(|object| object.remove(name).is_some())
+}
+
+fn json_rejection_error(rejection: JsonRejection) -> AppError {
+    if let Some(e) = body_limit::exceeded(&rejection) {
+        return e.into();
+    }
+    match rejection {
+        JsonRejection::MissingJsonContentType(_) => AppError::new(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "unsupported_media_type",
+            "Expected request with `Content-Type: application/json`",
+        ),
+        JsonRejection::InvalidJsonBody(_) => AppError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_json",
+            format!("Json parse error: [{}]", rejection),
+        ),
+        _ => AppError::bad_request(format!("Json parse error: [{}]", rejection)),
+    }
+}
+
+#[derive(Debug)]
+pub struct ParsedQuery<T>(T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for ParsedQuery<T>
+where
+    T: DeserializeOwned,
+    B: Send,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let query = req.uri().query().unwrap_or_default();
+        // 不正な値がどのパラメータかを特定し、enumの場合は許可される値も返す
+        let deserializer =
+            serde_urlencoded::Deserializer::new(form_urlencoded::parse(query.as_bytes()));
+        let value: T = serde_path_to_error::deserialize(deserializer).map_err(|e| {
+            let field = e.path().to_string();
+            let message = e.inner().to_string();
+            AppError::bad_request(format!("Query parse error: [{}]", message))
+                .with_field(field, message)
+        })?;
+        Ok(ParsedQuery(value))
+    }
+}
+
+// axumのPathは数値でない・範囲外の値を平文の400で返すため、他のエラーと同じJSONにそろえる
+#[derive(Debug)]
+pub struct ParsedPath<T>(T);
+
+#[async_trait]
+impl<T, B> FromRequest<B> for ParsedPath<T>
+where
+    T: DeserializeOwned + Send,
+    B: Send,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        match Path::<T>::from_request(req).await {
+            Ok(Path(value)) => Ok(ParsedPath(value)),
+            Err(PathRejection::FailedToDeserializePathParams(e)) => match e.into_kind() {
+                // 型の誤りはクライアントではなくルーティングの定義の問題
+                kind @ ErrorKind::UnsupportedType { .. } => {
+                    Err(AppError::internal(kind.to_string()))
+                }
+                kind => Err(AppError::bad_request(format!(
+                    "Path parse error: [{}]",
+                    kind
+                ))),
+            },
+            Err(rejection) => Err(AppError::internal(rejection.to_string())),
+        }
+    }
+}
+
+// 本文と付随するヘッダーのハッシュを弱いETagとし、If-None-Matchと一致すれば304を返す
+pub fn etagged_json<T: Serialize>(
+    request_headers: &HeaderMap,
+    value: &T,
+    headers: Vec<(&'static str, String)>,
+) -> Result<Response, AppError> {
+    let body = serde_json::to_vec(value).map_err(anyhow::Error::from)?;
+    let etag = format!("W/\"{:016x}\"", content_hash(&body, &headers));
+    etagged_response(request_headers, body, etag, headers)
+}
+
+// 単体のリソースはETagの先頭にversionを含め、If-Matchの値から前提条件のversionを取り出せるようにする
+pub fn versioned_etagged_json<T: Serialize>(
+    request_headers: &HeaderMap,
+    version: i32,
+    value: &T,
+) -> Result<Response, AppError> {
+    let body = serde_json::to_vec(value).map_err(anyhow::Error::from)?;
+    let etag = format!("W/\"{}-{:016x}\"", version, content_hash(&body, &[]));
+    etagged_response(request_headers, body, etag, vec![])
+}
+
+fn content_hash(body: &[u8], headers: &[(&'static str, String)]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(body);
+    for (name, value) in headers.iter() {
+        hasher.write(name.as_bytes());
+        hasher.write(value.as_bytes());
+    }
+    hasher.finish()
+}
+
+fn etagged_response(
+    request_headers: &HeaderMap,
+    body: Vec<u8>,
+    etag: String,
+    headers: Vec<(&'static str, String)>,
+) -> Result<Response, AppError> {
+    let not_modified = request_headers
+        .get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| etag_matches(value, &etag));
+    let mut res = if not_modified {
+        Response::builder()
+            .status(StatusCode::NOT_MODIFIED)
+            .body(boxed(Full::default()))
+    } else {
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(boxed(Full::from(body)))
+    }
+    .map_err(anyhow::Error::from)?;
+
+    let res_headers = res.headers_mut();
+    res_headers.insert(
+        ETAG,
+        HeaderValue::from_str(&etag).map_err(anyhow::Error::from)?,
+    );
+    for (name, value) in headers {
+        res_headers.insert(
+            name,
+            HeaderValue::from_str(&value).map_err(anyhow::Error::from)?,
+        );
+    }
+    Ok(res)
+}
+
+// 一覧の共通の指定。trueの場合、{ "data": [...], "meta": {...} }で包んで返す
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct EnvelopeQuery {
+    pub envelope: Option<bool>,
+}
+
+impl EnvelopeQuery {
+    pub fn wrap<T: Serialize>(&self, data: T, meta: ListMeta) -> Result<Value, AppError> {
+        let data = serde_json::to_value(data).map_err(anyhow::Error::from)?;
+        if !self.envelope.unwrap_or(false) {
+            return Ok(data);
+        }
+        Ok(json!({ "data": data, "meta": meta }))
+    }
+}
+
+// 包んだ一覧のmeta。ページングしない一覧は全件を返すため、limitをnullにする
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ListMeta {
+    pub total: i64,
+    pub limit: Option<i64>,
+    pub offset: i64,
+}
+
+impl ListMeta {
+    pub fn page(total: i64, limit: i64, offset: i64) -> Self {
+        ListMeta {
+            total,
+            limit: Some(limit),
+            offset,
+        }
+    }
+
+    pub fn all(total: usize) -> Self {
+        ListMeta {
+            total: total as i64,
+            limit: None,
+            offset: 0,
+        }
+    }
+}
+
+// ?fields=id,textのように、一覧の各要素で返す項目をカンマ区切りで選ぶ
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct FieldsQuery {
+    pub fields: Option<String>,
+}
+
+impl FieldsQuery {
+    // 一覧を読む前に誤りを返せるよう、allowedにない項目はここで400にする
+    pub fn selection(&self, allowed: &[&str]) -> Result<FieldSelection, AppError> {
+        let fields = match &self.fields {
+            Some(fields) => fields,
+            None => return Ok(FieldSelection(None)),
+        };
+        let fields: Vec<String> = fields
+            .split(',')
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+            .map(snake_case)
+            .collect();
+        if fields.is_empty() {
+            return Err(AppError::bad_request("Query parse error: [empty fields]")
+                .with_field("fields", "Must list at least one field"));
+        }
+        if let Some(unknown) = fields
+            .iter()
+            .find(|field| !allowed.contains(&field.as_str()))
+        {
+            return Err(AppError::bad_request(format!(
+                "Query parse error: [unknown field {}]",
+                unknown
+            ))
+            .with_field("fields", format!("Must be some of {}", allowed.join(", "))));
+        }
+        Ok(FieldSelection(Some(fields)))
+    }
+}
+
+// 検査済みの?fields=。Noneの場合はすべての項目を返す
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldSelection(Option<Vec<String>>);
+
+impl FieldSelection {
+    pub fn apply<T: Serialize>(&self, items: &[T]) -> Result<Value, AppError> {
+        let mut value = serde_json::to_value(items).map_err(anyhow::Error::from)?;
+        if let (Some(fields), Value::Array(items)) = (&self.0, &mut value) {
+            for item in items.iter_mut().filter_map(Value::as_object_mut) {
+                item.retain(|key, _| fields.contains(key));
+            }
+        }
+        Ok(value)
+    }
+}
+
+// Last-Modifiedなどに使うHTTP-date。秒未満は切り捨てる
+pub fn http_date(at: DateTime<Utc>) -> String {
+    at.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+// If-Modified-Since以降に変更がないかどうか。HTTP-dateの精度に合わせ、秒単位で比べる
+// If-None-Matchがある場合はETagで判断するため、If-Modified-Sinceは評価しない(RFC 9110)
+pub fn not_modified_since(request_headers: &HeaderMap, last_modified: DateTime<Utc>) -> bool {
+    if request_headers.contains_key(IF_NONE_MATCH) {
+        return false;
+    }
+    request_headers
+        .get(IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+        .is_some_and(|since| last_modified.timestamp() <= since.timestamp())
+}
+
+// If-Match・If-Unmodified-Sinceから作る更新・削除の前提条件。どちらもない場合は無条件
+#[derive(Debug)]
+pub struct IfUnmodified(pub Precondition);
+
+#[async_trait]
+impl<B> FromRequest<B> for IfUnmodified
+where
+    B: Send,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let headers = match req.headers() {
+            Some(headers) => headers,
+            None => return Ok(IfUnmodified(Precondition::default())),
+        };
+        // If-Matchがある場合、If-Unmodified-Sinceは評価しない(RFC 9110)
+        if let Some(value) = headers.get(IF_MATCH) {
+            let value = value.to_str().unwrap_or_default().trim();
+            // *は対象が存在すれば満たすため、条件を付けない
+            if value == "*" {
+                return Ok(IfUnmodified(Precondition::default()));
+            }
+            let version = etag_version(value).ok_or_else(|| {
+                AppError::bad_request("If-Match must be a single ETag returned for the resource")
+            })?;
+            return Ok(IfUnmodified(Precondition::default().with_version(version)));
+        }
+        // 日時として読めないIf-Unmodified-Sinceは無視する(RFC 9110)
+        let since = headers
+            .get(IF_UNMODIFIED_SINCE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| DateTime::parse_from_rfc2822(value).ok());
+        Ok(IfUnmodified(match since {
+            Some(since) => Precondition::default().with_unmodified_since(since.with_timezone(&Utc)),
+            None => Precondition::default(),
+        }))
+    }
+}
+
+// versioned_etagged_jsonのETagからversionを取り出す。クライアントが弱いETagをそのまま送れるよう、W/は無視する
+fn etag_version(if_match: &str) -> Option<i32> {
+    let opaque = if_match
+        .trim_start_matches("W/")
+        .strip_prefix('"')?
+        .strip_suffix('"')
+        // 複数のETagを並べた場合
+        .filter(|opaque| !opaque.contains('"'))?;
+    opaque.split_once('-')?.0.parse().ok()
+}
+
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+// 1件ずつ1行のJSONとして書き出す。途中でエラーになった場合はbodyのエラーとしてhyperに接続を切断させ、
+// 終端まで正常に読めたように見える途中までの出力をクライアントが受け取らないようにする
+pub fn ndjson_body<T: Serialize + 'static>(
+    items: BoxStream<'static, Result<T, RepositoryError>>,
+) -> StreamBody<impl Stream<Item = anyhow::Result<Vec<u8>>>> {
+    StreamBody::new(items.map(|item| {
+        let mut line = serde_json::to_vec(&item?)?;
+        line.push(b'\n');
+        Ok(line)
+    }))
+}
+
+// If-None-Matchは弱い比較のため、W/の有無を無視して比較する
+fn etag_matches(if_none_match: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    if_none_match
+        .split(',')
+        .any(|tag| tag.trim() == "*" || opaque(tag) == opaque(etag))
+}
+
+#[cfg(test)]
+mod test {
+    use axum::response::IntoResponse;
+
+    use super::*;
+
+    #[test]
+    fn etag_matches_test() {
+        let etag = r#"W/"abc""#;
+        assert!(etag_matches(r#"W/"abc""#, etag));
+        assert!(etag_matches(r#""abc""#, etag));
+        assert!(etag_matches(r#"W/"xyz", W/"abc""#, etag));
+        assert!(etag_matches("*", etag));
+        assert!(!etag_matches(r#"W/"xyz""#, etag));
+    }
+
+    #[test]
+    fn etag_version_test() {
+        assert_eq!(Some(3), etag_version(r#"W/"3-00000000000000ab""#));
+        assert_eq!(Some(3), etag_version(r#""3-00000000000000ab""#));
+        assert_eq!(None, etag_version(r#"W/"00000000000000ab""#));
+        assert_eq!(None, etag_version(r#"W/"3-a", W/"4-b""#));
+        assert_eq!(None, etag_version("3-a"));
+    }
+
+    #[test]
+    fn field_selection_test() {
+        let allowed = ["id", "text", "completed"];
+        let items = vec![json!({ "id": 1, "text": "a", "completed": false })];
+        let select = |fields: Option<&str>| {
+            FieldsQuery {
+                fields: fields.map(String::from),
+            }
+            .selection(&allowed)
+        };
+
+        let all = select(None).unwrap();
+        assert_eq!(json!(items), all.apply(&items).unwrap());
+        let selected = select(Some(" id ,text,")).unwrap();
+        assert_eq!(
+            json!([{ "id": 1, "text": "a" }]),
+            selected.apply(&items).unwrap()
+        );
+
+        for fields in ["id,labels", "", " , "] {
+            let res = select(Some(fields)).unwrap_err().into_response();
+            assert_eq!(StatusCode::BAD_REQUEST, res.status(), "{}", fields);
+        }
+    }
+
+    #[tokio::test]
+    async fn ndjson_body_test() {
+        let items = futures_util::stream::iter(vec![Ok(1), Ok(2)]).boxed();
+        let bytes = hyper::body::to_bytes(ndjson_body(items)).await.unwrap();
+        assert_eq!(b"1\n2\n".to_vec(), bytes.to_vec());
+
+        // 途中のエラーは空の終端ではなくbodyのエラーになる
+        let items = futures_util::stream::iter(vec![
+            Ok(1),
+            Err(RepositoryError::unexpected("db is down")),
+            Ok(2),
+        ])
+        .boxed();
+        assert!(hyper::body::to_bytes(ndjson_body(items)).await.is_err());
+    }
+}
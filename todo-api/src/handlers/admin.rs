@@ -0,0 +1,171 @@
+use std::sync::Arc;
+
+use axum::extract::{Extension, FromRequest, RequestParts};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{async_trait, Json};
+use chrono::{DateTime, TimeZone, Utc};
+use serde::Deserialize;
+use validator::Validate;
+
+use crate::auth::unauthorized;
+use crate::error::AppError;
+use crate::repositories::backup::{Backup, BackupAssociation, BackupLabel, BackupTodo};
+use crate::repositories::todo::{Priority, TodoRepository};
+
+use super::ValidatedJson;
+
+pub const ADMIN_TOKEN_HEADER: &str = "x-admin-token";
+
+// ADMIN_TOKENの値。トークンがログに出ないようDebugは実装しない
+#[derive(Clone)]
+pub struct AdminToken(pub String);
+
+// X-Admin-Tokenが設定値と一致するリクエストのみ通す
+#[derive(Debug)]
+pub struct Admin;
+
+#[async_trait]
+impl<B> FromRequest<B> for Admin
+where
+    B: Send,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let Extension(AdminToken(expected)) = Extension::<AdminToken>::from_request(req)
+            .await
+            .map_err(|e| AppError::internal(e.to_string()))?;
+        let token = req
+            .headers()
+            .and_then(|headers| headers.get(ADMIN_TOKEN_HEADER))
+            .ok_or_else(|| unauthorized("Missing X-Admin-Token header"))?;
+        if !constant_time_eq(token.as_bytes(), expected.as_bytes()) {
+            return Err(unauthorized("Invalid admin token"));
+        }
+        Ok(Admin)
+    }
+}
+
+// 一致した長さから推測されないよう、途中で打ち切らずに全体を比べる
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[derive(Debug, Deserialize, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct SeedRequest {
+    pub user_id: i32,
+    // 省略した場合は組み込みのデータを使う
+    #[validate]
+    pub fixtures: Option<Backup>,
+}
+
+// すべてのユーザーのTodo・ラベルを削除する
+pub async fn reset_data<T: TodoRepository>(
+    _: Admin,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, AppError> {
+    repository.reset().await?;
+    tracing::warn!("reset all todos and labels");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// resetした上でfixturesを取り込む。idは1から採番し直すため、同じfixturesなら毎回同じidになる
+pub async fn seed_data<T: TodoRepository>(
+    _: Admin,
+    ValidatedJson(payload): ValidatedJson<SeedRequest>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, AppError> {
+    let fixtures = payload.fixtures.unwrap_or_else(default_fixtures);
+    let summary = repository.seed(payload.user_id, fixtures).await?;
+    tracing::warn!(
+        "reset all todos and labels, seeded user {}",
+        payload.user_id
+    );
+    Ok((StatusCode::CREATED, Json(summary)))
+}
+
+fn at(month: u32, day: u32, hour: u32) -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2026, month, day, hour, 0, 0).unwrap()
+}
+
+// 実行した日時によらず同じ内容になるよう、日時も固定する
+pub fn default_fixtures() -> Backup {
+    let label = |id: i32, name: &str, color: &str| BackupLabel {
+        id,
+        name: name.to_string(),
+        color: color.to_string(),
+        description: None,
+    };
+    let todo = |id: i64, text: &str, completed: bool, priority: Priority, due_date| BackupTodo {
+        id,
+        text: text.to_string(),
+        completed,
+        priority,
+        due_date,
+        created_at: at(1, 1, 9) + chrono::Duration::minutes(id),
+        updated_at: at(1, 1, 9) + chrono::Duration::minutes(id),
+    };
+    Backup {
+        labels: vec![
+            label(1, "work", "#1e90ff"),
+            label(2, "home", "#2e8b57"),
+            label(3, "errand", "#ff8c00"),
+        ],
+        todos: vec![
+            todo(
+                1,
+                "write the quarterly report",
+                false,
+                Priority::High,
+                Some(at(1, 10, 18)),
+            ),
+            todo(2, "review pull requests", false, Priority::Medium, None),
+            todo(3, "pay rent", false, Priority::High, Some(at(1, 31, 9))),
+            todo(4, "buy milk", true, Priority::Low, None),
+            todo(5, "call the dentist", false, Priority::Medium, None),
+        ],
+        associations: vec![
+            BackupAssociation {
+                todo_id: 1,
+                label_id: 1,
+            },
+            BackupAssociation {
+                todo_id: 2,
+                label_id: 1,
+            },
+            BackupAssociation {
+                todo_id: 3,
+                label_id: 2,
+            },
+            BackupAssociation {
+                todo_id: 4,
+                label_id: 2,
+            },
+            BackupAssociation {
+                todo_id: 4,
+                label_id: 3,
+            },
+        ],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_compare_whole_token() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+        assert!(!constant_time_eq(b"", b"secret"));
+    }
+
+    #[test]
+    fn default_fixtures_should_be_valid() {
+        assert!(default_fixtures().validate().is_ok());
+        assert_eq!(default_fixtures(), default_fixtures());
+    }
+}
@@ -0,0 +1,67 @@
+use std::sync::Arc;
+
+use axum::{extract::Extension, http::StatusCode, response::IntoResponse, Json};
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::auth::{hash_password, verify_password, AuthKeys};
+use crate::error::AppError;
+use crate::repositories::user::{User, UserRepository};
+
+use super::ValidatedJson;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Validate)]
+#[serde(deny_unknown_fields)]
+pub struct RegisterUser {
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 50, message = "Over text length"))]
+    username: String,
+    #[validate(length(min = 8, message = "Too short password"))]
+    #[validate(length(max = 128, message = "Over text length"))]
+    password: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Validate)]
+pub struct LoginUser {
+    username: String,
+    password: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TokenResponse {
+    pub token: String,
+    pub user: User,
+}
+
+pub async fn register<T: UserRepository>(
+    ValidatedJson(payload): ValidatedJson<RegisterUser>,
+    Extension(repository): Extension<Arc<T>>,
+    Extension(keys): Extension<Arc<AuthKeys>>,
+) -> Result<impl IntoResponse, AppError> {
+    let password_hash = hash_password(&payload.password)?;
+    let user = repository.create(payload.username, password_hash).await?;
+    let token = keys.issue(&user)?;
+
+    Ok((StatusCode::CREATED, Json(TokenResponse { token, user })))
+}
+
+pub async fn login<T: UserRepository>(
+    ValidatedJson(payload): ValidatedJson<LoginUser>,
+    Extension(repository): Extension<Arc<T>>,
+    Extension(keys): Extension<Arc<AuthKeys>>,
+) -> Result<impl IntoResponse, AppError> {
+    let user = repository
+        .find_by_username(&payload.username)
+        .await?
+        .filter(|user| verify_password(&payload.password, &user.password_hash))
+        .ok_or_else(|| {
+            AppError::new(
+                StatusCode::UNAUTHORIZED,
+                "invalid_credentials",
+                "Invalid username or password",
+            )
+        })?;
+    let token = keys.issue(&user)?;
+
+    Ok((StatusCode::OK, Json(TokenResponse { token, user })))
+}
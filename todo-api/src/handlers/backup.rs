@@ -0,0 +1,151 @@
+use std::sync::Arc;
+
+use axum::body::Bytes;
+use axum::extract::{Extension, FromRequest, Multipart, RequestParts};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{async_trait, BoxError, Json};
+
+use crate::auth::AuthUser;
+use crate::body_limit;
+use crate::csv_import::{self, CsvImportSummary};
+use crate::error::AppError;
+use crate::repositories::backup::Backup;
+use crate::repositories::todo::TodoRepository;
+
+use super::ValidatedJson;
+
+#[utoipa::path(
+    get,
+    path = "/export",
+    tag = "backup",
+    responses(
+        (status = 200, description = "Todos of the user, all labels and their associations", body = Backup),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn export_backup<T: TodoRepository>(
+    user: AuthUser,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, AppError> {
+    let backup = repository.export(user.id).await?;
+    Ok((StatusCode::OK, Json(backup)))
+}
+
+// 不正なレコードが1件でもあれば何も取り込まず、最初のレコードの位置を422で返す
+#[utoipa::path(
+    post,
+    path = "/import",
+    tag = "backup",
+    request_body = Backup,
+    responses(
+        (status = 201, description = "Number of imported todos and created labels", body = ImportSummary),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 422, description = "First invalid record in the document", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn import_backup<T: TodoRepository>(
+    user: AuthUser,
+    ValidatedJson(payload): ValidatedJson<Backup>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, AppError> {
+    let summary = repository.import(user.id, payload).await?;
+    Ok((StatusCode::CREATED, Json(summary)))
+}
+
+// 上限を超えた場合は413、それ以外の読み込みの失敗は400を返す
+fn body_error<E: std::error::Error + 'static>(e: E) -> AppError {
+    match body_limit::exceeded(&e) {
+        Some(e) => e.into(),
+        None => AppError::bad_request(e.to_string()),
+    }
+}
+
+// text/csvの本文、またはmultipart/form-dataのfileフィールド(なければ最初のフィールド)を読み込む
+#[derive(Debug)]
+pub struct CsvBody(Bytes);
+
+#[async_trait]
+impl<B> FromRequest<B> for CsvBody
+where
+    B: http_body::Body<Data = Bytes> + Default + Unpin + Send + 'static,
+    B::Error: Into<BoxError>,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let content_type = req
+            .headers()
+            .and_then(|headers| headers.get(CONTENT_TYPE))
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<mime::Mime>().ok());
+        match content_type {
+            Some(mime) if mime.essence_str() == mime::MULTIPART_FORM_DATA.essence_str() => {
+                let mut multipart = Multipart::from_request(req).await.map_err(body_error)?;
+                let mut first = None;
+                while let Some(field) = multipart.next_field().await.map_err(body_error)? {
+                    let is_file = field.name() == Some("file");
+                    if first.is_some() && !is_file {
+                        continue;
+                    }
+                    let bytes = field.bytes().await.map_err(body_error)?;
+                    first = Some(bytes);
+                    if is_file {
+                        break;
+                    }
+                }
+                first
+                    .map(CsvBody)
+                    .ok_or_else(|| AppError::bad_request("Multipart body has no file field"))
+            }
+            Some(mime) if mime.essence_str() == mime::TEXT_CSV.essence_str() => {
+                let bytes = Bytes::from_request(req).await.map_err(body_error)?;
+                Ok(CsvBody(bytes))
+            }
+            _ => Err(AppError::new(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "unsupported_media_type",
+                "Expected request with `Content-Type: text/csv` or `multipart/form-data`",
+            )),
+        }
+    }
+}
+
+// 空の本文・長すぎる本文の行は読み飛ばして行番号と理由を返し、残りを1トランザクションで登録する
+#[utoipa::path(
+    post,
+    path = "/import/csv",
+    tag = "backup",
+    request_body(content = String, content_type = "text/csv",
+        description = "CSV with a text or content column. Todoist exports are accepted as is"),
+    responses(
+        (status = 201, description = "Number of imported rows and skipped line numbers", body = CsvImportSummary),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 415, description = "Not text/csv or multipart/form-data", body = ErrorBody),
+        (status = 422, description = "Unparsable CSV or no text column", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn import_csv<T: TodoRepository>(
+    user: AuthUser,
+    CsvBody(body): CsvBody,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, AppError> {
+    let parsed = csv_import::parse(&body)?;
+    let imported = if parsed.todos.is_empty() {
+        0
+    } else {
+        repository
+            .create_many_with_label_names(user.id, parsed.todos)
+            .await?
+            .len()
+    };
+    let summary = CsvImportSummary {
+        imported,
+        skipped: parsed.skipped,
+    };
+    Ok((StatusCode::CREATED, Json(summary)))
+}
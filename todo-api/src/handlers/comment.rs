@@ -0,0 +1,119 @@
+use std::sync::Arc;
+
+use axum::extract::Extension;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Json;
+
+use crate::auth::AuthUser;
+use crate::error::AppError;
+use crate::repositories::comment::{CommentRepository, CreateComment};
+use crate::repositories::todo::TodoRepository;
+use crate::repositories::{PageQuery, RepositoryError};
+
+use super::todo::TOTAL_COUNT_HEADER;
+use super::{etagged_json, EnvelopeQuery, ListMeta, ParsedPath, ParsedQuery, ValidatedJson};
+
+// コメントの対象のTodoがなければ404にする。Todoの内容は使わないためexistsで確かめる
+async fn ensure_todo_exists<T: TodoRepository>(
+    repository: &T,
+    user_id: i32,
+    id: i64,
+) -> Result<(), AppError> {
+    if !repository.exists(user_id, id).await? {
+        return Err(RepositoryError::NotFound(Some(id)).into());
+    }
+    Ok(())
+}
+
+#[utoipa::path(
+    post,
+    path = "/todos/{id}/comments",
+    tag = "todos",
+    params(("id" = i64, Path, description = "Todo id")),
+    request_body = CreateComment,
+    responses(
+        (status = 201, description = "Created comment", body = Comment),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "Todo not found", body = ErrorBody),
+        (status = 422, description = "Invalid fields", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn create_comment<T: TodoRepository, C: CommentRepository>(
+    user: AuthUser,
+    ParsedPath(id): ParsedPath<i64>,
+    ValidatedJson(payload): ValidatedJson<CreateComment>,
+    Extension(todo_repository): Extension<Arc<T>>,
+    Extension(comment_repository): Extension<Arc<C>>,
+) -> Result<impl IntoResponse, AppError> {
+    ensure_todo_exists(&*todo_repository, user.id, id).await?;
+    let comment = comment_repository
+        .create(id, user.username, payload)
+        .await?;
+    Ok((StatusCode::CREATED, Json(comment)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/todos/{id}/comments",
+    tag = "todos",
+    params(("id" = i64, Path, description = "Todo id"), PageQuery, EnvelopeQuery),
+    responses(
+        (status = 200, description = "Page of comments, newest first", body = [Comment],
+            headers(
+                ("x-total-count" = i64, description = "Number of comments on the todo"),
+                ("etag" = String, description = "Weak ETag of the page"),
+            )),
+        (status = 304, description = "Not modified since If-None-Match"),
+        (status = 400, description = "Unparsable query parameter", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "Todo not found", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn all_comments<T: TodoRepository, C: CommentRepository>(
+    user: AuthUser,
+    ParsedPath(id): ParsedPath<i64>,
+    ParsedQuery(query): ParsedQuery<PageQuery>,
+    ParsedQuery(envelope): ParsedQuery<EnvelopeQuery>,
+    headers: HeaderMap,
+    Extension(todo_repository): Extension<Arc<T>>,
+    Extension(comment_repository): Extension<Arc<C>>,
+) -> Result<Response, AppError> {
+    ensure_todo_exists(&*todo_repository, user.id, id).await?;
+    let (limit, offset) = (query.limit(), query.offset());
+    let page = comment_repository.all(id, query).await?;
+    let meta = ListMeta::page(page.total, limit, offset);
+    etagged_json(
+        &headers,
+        &envelope.wrap(&page.comments, meta)?,
+        vec![(TOTAL_COUNT_HEADER, page.total.to_string())],
+    )
+}
+
+#[utoipa::path(
+    delete,
+    path = "/todos/{id}/comments/{comment_id}",
+    tag = "todos",
+    params(
+        ("id" = i64, Path, description = "Todo id"),
+        ("comment_id" = i32, Path, description = "Comment id"),
+    ),
+    responses(
+        (status = 204, description = "Deleted comment"),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "Todo or comment not found", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn delete_comment<T: TodoRepository, C: CommentRepository>(
+    user: AuthUser,
+    ParsedPath((id, comment_id)): ParsedPath<(i64, i32)>,
+    Extension(todo_repository): Extension<Arc<T>>,
+    Extension(comment_repository): Extension<Arc<C>>,
+) -> Result<StatusCode, AppError> {
+    ensure_todo_exists(&*todo_repository, user.id, id).await?;
+    comment_repository.delete(id, comment_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,40 @@
+use std::sync::Arc;
+
+use axum::extract::Extension;
+use axum::response::{Html, IntoResponse};
+use axum::Json;
+
+use crate::openapi::api_doc;
+
+pub const OPENAPI_PATH: &str = "/api-docs/openapi.json";
+
+// リソースのルートを置いたパス。create_appでExtensionとして渡す
+#[derive(Debug, Clone)]
+pub struct ApiPrefix(pub Arc<str>);
+
+pub async fn openapi_spec(Extension(prefix): Extension<ApiPrefix>) -> impl IntoResponse {
+    Json(api_doc(&prefix.0))
+}
+
+// axum 0.4に対応したSwagger UIの組み込みがないため、CDNの静的ファイルから表示する
+pub async fn swagger_ui() -> impl IntoResponse {
+    Html(format!(
+        r##"<!DOCTYPE html>
+<html lang="en">
+<head>
+  <meta charset="utf-8" />
+  <title>rust-todo API</title>
+  <link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@5/swagger-ui.css" />
+</head>
+<body>
+  <div id="swagger-ui"></div>
+  <script src="https://unpkg.com/swagger-ui-dist@5/swagger-ui-bundle.js" crossorigin></script>
+  <script>
+    window.ui = SwaggerUIBundle({{ url: "{}", dom_id: "#swagger-ui" }});
+  </script>
+</body>
+</html>
+"##,
+        OPENAPI_PATH
+    ))
+}
@@ -0,0 +1,141 @@
+use std::path::PathBuf;
+
+use axum::body::{boxed, Body};
+use axum::extract::Extension;
+use axum::http::header::{ALLOW, CACHE_CONTROL};
+use axum::http::{HeaderValue, Method, Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use tower::ServiceExt;
+use tower_http::services::{ServeDir, ServeFile};
+
+use crate::error::AppError;
+use crate::handlers::docs::ApiPrefix;
+
+// 非推奨のプレフィックスなしのルートの先頭のセグメント。これらとApiPrefixの下で一致しないパスは
+// index.htmlではなくJSONの404を返す
+const API_SEGMENTS: [&str; 15] = [
+    "todos",
+    "labels",
+    "stats",
+    "export",
+    "import",
+    "auth",
+    "users",
+    "webhooks",
+    "ws",
+    "healthz",
+    "readyz",
+    "metrics",
+    "api-docs",
+    "swagger-ui",
+    "graphql",
+];
+// viteがファイル名にハッシュを付けて出力するディレクトリ。内容が変われば名前も変わる
+const HASHED_ASSETS_PREFIX: &str = "/assets/";
+const IMMUTABLE: &str = "public, max-age=31536000, immutable";
+const NO_CACHE: &str = "no-cache";
+
+// STATIC_DIRを設定した場合に、フロントエンドのビルド結果を同じサーバーから配信する
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    dir: PathBuf,
+}
+
+impl StaticFiles {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    // ファイルがあればそれを返し、なければクライアント側のルーティングに任せるためindex.htmlを返す
+    async fn serve(&self, req: Request<Body>) -> Option<Response> {
+        let path = req.uri().path().to_string();
+        let res = ServeDir::new(&self.dir)
+            .oneshot(without_body(&req))
+            .await
+            .ok()?;
+        if res.status() != StatusCode::NOT_FOUND {
+            let cache_control = if path.starts_with(HASHED_ASSETS_PREFIX) {
+                IMMUTABLE
+            } else {
+                NO_CACHE
+            };
+            return Some(with_cache_control(res.map(boxed), cache_control));
+        }
+        // 存在しないアセットにHTMLを返すと、ブラウザがスクリプトとして読んで失敗するため404にする
+        if path.starts_with(HASHED_ASSETS_PREFIX) {
+            return None;
+        }
+
+        let res = ServeFile::new(self.dir.join("index.html"))
+            .oneshot(without_body(&req))
+            .await
+            .ok()?;
+        if res.status() == StatusCode::NOT_FOUND {
+            return None;
+        }
+        Some(with_cache_control(res.map(boxed), NO_CACHE))
+    }
+}
+
+// 配信するファイルの判定にはメソッド・パス・条件付きリクエストのヘッダーのみを使う
+fn without_body(req: &Request<Body>) -> Request<Body> {
+    let mut copy = Request::new(Body::empty());
+    *copy.method_mut() = req.method().clone();
+    *copy.uri_mut() = req.uri().clone();
+    *copy.headers_mut() = req.headers().clone();
+    copy
+}
+
+fn with_cache_control(mut res: Response, value: &'static str) -> Response {
+    res.headers_mut()
+        .insert(CACHE_CONTROL, HeaderValue::from_static(value));
+    res
+}
+
+fn is_api_path(path: &str, prefix: Option<&str>) -> bool {
+    if let Some(rest) = prefix.and_then(|prefix| path.strip_prefix(prefix)) {
+        if rest.is_empty() || rest.starts_with('/') {
+            return true;
+        }
+    }
+    let segment = path.trim_start_matches('/').split('/').next().unwrap_or("");
+    API_SEGMENTS.contains(&segment)
+}
+
+// 存在しないパスもフロントエンドが他のエラーと同じ形式で扱えるようにする
+pub async fn not_found(
+    static_files: Option<Extension<StaticFiles>>,
+    api_prefix: Option<Extension<ApiPrefix>>,
+    req: Request<Body>,
+) -> Response {
+    let path = req.uri().path().to_string();
+    let is_read = req.method() == Method::GET || req.method() == Method::HEAD;
+    if let Some(Extension(static_files)) = static_files {
+        let prefix = api_prefix.as_ref().map(|Extension(prefix)| &*prefix.0);
+        if is_read && !is_api_path(&path, prefix) {
+            if let Some(res) = static_files.serve(req).await {
+                return res;
+            }
+        }
+    }
+    AppError::new(StatusCode::NOT_FOUND, "not_found", "Route not found")
+        .with_path(path)
+        .into_response()
+}
+
+// axumが返す405はbodyが空のため、Allowヘッダーを残したままJSONのエラーへ差し替える
+pub fn method_not_allowed(res: Response) -> Response {
+    if res.status() != StatusCode::METHOD_NOT_ALLOWED {
+        return res;
+    }
+    let mut error = AppError::new(
+        StatusCode::METHOD_NOT_ALLOWED,
+        "method_not_allowed",
+        "Method not allowed",
+    )
+    .into_response();
+    if let Some(allow) = res.headers().get(ALLOW) {
+        error.headers_mut().insert(ALLOW, allow.clone());
+    }
+    error
+}
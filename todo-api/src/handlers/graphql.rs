@@ -0,0 +1,34 @@
+use std::sync::Arc;
+
+use async_graphql::http::{playground_source, GraphQLPlaygroundConfig};
+use axum::extract::Extension;
+use axum::response::{Html, IntoResponse};
+use axum::Json;
+
+use crate::auth::AuthUser;
+use crate::events::TodoEvents;
+use crate::graphql::{with_viewer, TodoSchema};
+use crate::repositories::comment::CommentRepository;
+use crate::repositories::label::LabelRepository;
+use crate::repositories::todo::TodoRepository;
+
+pub const GRAPHQL_PATH: &str = "/graphql";
+pub const GRAPHQL_PLAYGROUND_PATH: &str = "/graphql/playground";
+
+// 未認証の場合はRESTと同じく401を返し、クエリの誤りはGraphQLのerrorsとして200で返す
+pub async fn execute_graphql<T: TodoRepository, L: LabelRepository, C: CommentRepository>(
+    user: AuthUser,
+    events: Option<Extension<TodoEvents>>,
+    Extension(schema): Extension<TodoSchema<T, L, C>>,
+    Extension(repository): Extension<Arc<T>>,
+    Json(request): Json<async_graphql::Request>,
+) -> impl IntoResponse {
+    let request = with_viewer(request, user, events, repository.as_ref().clone());
+    Json(schema.execute(request).await)
+}
+
+pub async fn graphql_playground() -> impl IntoResponse {
+    Html(playground_source(GraphQLPlaygroundConfig::new(
+        GRAPHQL_PATH,
+    )))
+}
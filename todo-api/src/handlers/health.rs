@@ -0,0 +1,29 @@
+use std::sync::Arc;
+
+use axum::extract::Extension;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+use serde_json::json;
+
+use crate::error::AppError;
+use crate::repositories::health::HealthRepository;
+
+pub async fn healthz() -> impl IntoResponse {
+    (StatusCode::OK, Json(json!({ "status": "ok" })))
+}
+
+pub async fn readyz<T: HealthRepository>(
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, AppError> {
+    // 認証なしで呼べるため、エラーの詳細はログにのみ残す
+    repository.ping().await.map_err(|e| {
+        tracing::warn!("readiness check failed: {:#}", e);
+        AppError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "not_ready",
+            "Database is unavailable",
+        )
+    })?;
+    Ok((StatusCode::OK, Json(json!({ "status": "ready" }))))
+}
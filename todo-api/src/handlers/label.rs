@@ -1,51 +1,211 @@
 use std::sync::Arc;
 
-use axum::{
-    extract::{Extension, Path},
-    http::StatusCode,
-    response::IntoResponse,
-    Json,
-};
-use serde::{Deserialize, Serialize};
+use axum::http::header::CACHE_CONTROL;
+use axum::response::Headers;
+use axum::{extract::Extension, http::StatusCode, response::IntoResponse, Json};
+use serde::Deserialize;
+use utoipa::{IntoParams, ToSchema};
 use validator::Validate;
 
-use crate::repositories::label::LabelRepository;
+use crate::auth::AuthUser;
+use crate::cache_control::{cache_policy, CachePolicy};
+use crate::error::AppError;
+use crate::events::{TodoEvent, TodoEvents};
+use crate::repositories::label::{CreateLabel, LabelRepository, LabelWithUsage, UpdateLabel};
+use crate::repositories::RepositoryError;
 
-use super::ValidatedJson;
+use super::{EnvelopeQuery, ListMeta, ParsedPath, ParsedQuery, ValidatedJson};
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Validate)]
-pub struct CreateLabel {
-    #[validate(length(min = 1, message = "Can not be empty"))]
-    #[validate(length(max = 100, message = "Over text length"))]
-    name: String,
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct LabelListQuery {
+    include_counts: Option<bool>,
 }
 
+#[derive(Debug, Deserialize, Validate, ToSchema)]
+pub struct MergeLabel {
+    into: i32,
+}
+
+// ラベルは全てのユーザーで共有するため、全ての購読者へ通知する。Extensionが未設定の場合は何もしない
+fn publish(events: &Option<Extension<TodoEvents>>, event: TodoEvent) {
+    if let Some(Extension(events)) = events {
+        events.publish_to_all(event);
+    }
+}
+
+// 名前が重複した場合、クライアントが既存のラベルを使えるようにそのラベルを添えて返す
+async fn with_existing_label<T: LabelRepository>(repository: &T, e: RepositoryError) -> AppError {
+    let existing_id = match e {
+        RepositoryError::Duplicate(Some(id)) => id,
+        _ => return e.into(),
+    };
+    match repository.find(existing_id).await {
+        Ok(label) => AppError::from(e).with_current(label),
+        Err(e) => e.into(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/labels",
+    tag = "labels",
+    request_body = CreateLabel,
+    responses(
+        (status = 201, description = "Created label", body = Label),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 409, description = "Name already used, current holds the existing label", body = ErrorBody),
+        (status = 422, description = "Invalid fields", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
 pub async fn create_label<T: LabelRepository>(
+    _user: AuthUser,
     ValidatedJson(payload): ValidatedJson<CreateLabel>,
+    events: Option<Extension<TodoEvents>>,
     Extension(repository): Extension<Arc<T>>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let label = repository
-        .create(payload.name)
-        .await
-        .or(Err(StatusCode::INTERNAL_SERVER_ERROR))?;
+) -> Result<impl IntoResponse, AppError> {
+    let label = match repository.create(payload).await {
+        Ok(label) => label,
+        Err(e) => return Err(with_existing_label(repository.as_ref(), e).await),
+    };
+    publish(
+        &events,
+        TodoEvent::LabelCreated {
+            label: label.clone(),
+        },
+    );
 
     Ok((StatusCode::CREATED, Json(label)))
 }
 
+#[utoipa::path(
+    get,
+    path = "/labels",
+    tag = "labels",
+    params(LabelListQuery, EnvelopeQuery),
+    responses(
+        (status = 200, description = "Labels with the number of todos using them", body = [LabelWithUsage],
+            headers(("cache-control" = String, description = "private, max-age=60 unless configured"))),
+        (status = 400, description = "Unparsable query parameter", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
 pub async fn all_label<T: LabelRepository>(
+    _user: AuthUser,
+    ParsedQuery(query): ParsedQuery<LabelListQuery>,
+    ParsedQuery(envelope): ParsedQuery<EnvelopeQuery>,
+    policy: Option<Extension<CachePolicy>>,
     Extension(repository): Extension<Arc<T>>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let labels = repository.all().await.unwrap();
-    Ok((StatusCode::OK, Json(labels)))
+) -> Result<impl IntoResponse, AppError> {
+    // 集計が不要な場合はTodoとのjoinを省略する
+    let labels = if query.include_counts.unwrap_or(true) {
+        repository.all_with_counts().await?
+    } else {
+        let labels = repository.all().await?;
+        labels.into_iter().map(LabelWithUsage::from).collect()
+    };
+    let cache_control = Headers([(CACHE_CONTROL, cache_policy(policy).labels)]);
+    let meta = ListMeta::all(labels.len());
+    Ok((
+        StatusCode::OK,
+        cache_control,
+        Json(envelope.wrap(labels, meta)?),
+    ))
 }
 
+#[utoipa::path(
+    patch,
+    path = "/labels/{id}",
+    tag = "labels",
+    params(("id" = i32, Path, description = "Label id")),
+    request_body = UpdateLabel,
+    responses(
+        (status = 200, description = "Updated label", body = Label),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "Label not found", body = ErrorBody),
+        (status = 409, description = "Name already used, current holds the existing label", body = ErrorBody),
+        (status = 422, description = "Invalid fields", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn update_label<T: LabelRepository>(
+    _user: AuthUser,
+    ParsedPath(id): ParsedPath<i32>,
+    ValidatedJson(payload): ValidatedJson<UpdateLabel>,
+    events: Option<Extension<TodoEvents>>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, AppError> {
+    let label = match repository.update(id, payload).await {
+        Ok(label) => label,
+        Err(e) => return Err(with_existing_label(repository.as_ref(), e).await),
+    };
+    publish(
+        &events,
+        TodoEvent::LabelUpdated {
+            label: label.clone(),
+        },
+    );
+
+    Ok((StatusCode::OK, Json(label)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/labels/{id}",
+    tag = "labels",
+    params(("id" = i32, Path, description = "Label id")),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "Label not found", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
 pub async fn delete_label<T: LabelRepository>(
-    Path(id): Path<i32>,
+    _user: AuthUser,
+    ParsedPath(id): ParsedPath<i32>,
+    events: Option<Extension<TodoEvents>>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<StatusCode, AppError> {
+    repository.delete(id).await?;
+    publish(&events, TodoEvent::LabelDeleted { id });
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+    post,
+    path = "/labels/{id}/merge",
+    tag = "labels",
+    params(("id" = i32, Path, description = "Label id to merge and delete")),
+    request_body = MergeLabel,
+    responses(
+        (status = 200, description = "Label the todos were moved to", body = LabelWithUsage),
+        (status = 400, description = "Merging a label into itself", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "Label not found", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn merge_label<T: LabelRepository>(
+    _user: AuthUser,
+    ParsedPath(id): ParsedPath<i32>,
+    ValidatedJson(payload): ValidatedJson<MergeLabel>,
+    events: Option<Extension<TodoEvents>>,
     Extension(repository): Extension<Arc<T>>,
-) -> StatusCode {
-    repository
-        .delete(id)
-        .await
-        .map(|_| StatusCode::NO_CONTENT)
-        .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+) -> Result<impl IntoResponse, AppError> {
+    if id == payload.into {
+        return Err(AppError::bad_request("Can not merge a label into itself")
+            .with_field("into", "Must differ from the source label"));
+    }
+    let label = repository.merge(id, payload.into).await?;
+    publish(
+        &events,
+        TodoEvent::LabelsMerged {
+            from: id,
+            into: label.label.clone(),
+        },
+    );
+    Ok((StatusCode::OK, Json(label)))
 }
@@ -0,0 +1,18 @@
+use std::sync::Arc;
+
+use axum::extract::Extension;
+use axum::http::header::CONTENT_TYPE;
+use axum::http::StatusCode;
+use axum::response::{Headers, IntoResponse};
+use prometheus::TEXT_FORMAT;
+
+use crate::error::AppError;
+use crate::metrics::Metrics;
+
+pub async fn metrics(
+    Extension(metrics): Extension<Arc<Metrics>>,
+) -> Result<impl IntoResponse, AppError> {
+    let body = metrics.render()?;
+    let headers = Headers(vec![(CONTENT_TYPE, TEXT_FORMAT)]);
+    Ok((StatusCode::OK, headers, body))
+}
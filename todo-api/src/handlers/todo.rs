@@ -1,59 +1,1025 @@
+use std::future::Future;
 use std::sync::Arc;
 
-use axum::extract::{Extension, Path};
-use axum::http::StatusCode;
-use axum::response::IntoResponse;
+use axum::body::Bytes;
+use axum::extract::Extension;
+use axum::http::header::{CACHE_CONTROL, CONTENT_TYPE, LAST_MODIFIED};
+use axum::http::{HeaderMap, HeaderValue, StatusCode};
+use axum::response::{Headers, IntoResponse, Response};
 use axum::Json;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+use validator::Validate;
 
-use crate::repositories::todo::{CreateTodo, TodoRepository, UpdateTodo};
+use crate::auth::AuthUser;
+use crate::cache_control::{cache_policy, CachePolicy};
+use crate::config::DEFAULT_TODO_BATCH_LIMIT;
+use crate::error::AppError;
+use crate::events::{TodoEvent, TodoEvents};
+use crate::idempotency::{create_once, fingerprint, replayed, IdempotencyKey, Idempotent};
+use crate::repositories::todo::{
+    CreateTodo, CreateTodoBatch, DuplicateTodo, MoveTarget, MoveTodo, Priority, ReplaceTodo,
+    TodoEntity, TodoListQuery, TodoRepository, UpdateTodo, UpdateTodos,
+};
+use crate::repositories::todo_item::TodoWithItems;
+use crate::repositories::{PageQuery, RepositoryError};
 
-use super::ValidatedJson;
+use super::{
+    etagged_json, http_date, ndjson_body, not_modified_since, parse_json_value,
+    versioned_etagged_json, EnvelopeQuery, FieldsQuery, IfUnmodified, ListMeta, MergePatch,
+    ParsedPath, ParsedQuery, StrictRequests, ValidatedJson, ValidatedPatch, NDJSON_CONTENT_TYPE,
+};
 
+pub const TOTAL_COUNT_HEADER: &str = "x-total-count";
+// 作成せずに既存のTodoを返した場合、そのidを入れる
+pub const DUPLICATE_OF_HEADER: &str = "x-duplicate-of";
+// GET /todosの?fields=で選べる項目。TodoEntityの項目と同じ
+pub const TODO_FIELDS: &[&str] = &[
+    "id",
+    "client_id",
+    "text",
+    "completed",
+    "labels",
+    "created_at",
+    "updated_at",
+    "deleted_at",
+    "archived_at",
+    "version",
+    "due_date",
+    "priority",
+    "recurrence",
+    "next_occurrence_id",
+    "remind_at",
+    "reminded_at",
+];
+
+// 一括登録の上限件数。Extensionが未設定の場合はデフォルト値を使う
+#[derive(Debug, Clone, Copy)]
+pub struct TodoBatchLimit(pub usize);
+
+impl Default for TodoBatchLimit {
+    fn default() -> Self {
+        Self(DEFAULT_TODO_BATCH_LIMIT as usize)
+    }
+}
+
+fn check_batch_limit(
+    limit: Option<Extension<TodoBatchLimit>>,
+    field: &str,
+    len: usize,
+) -> Result<(), AppError> {
+    let TodoBatchLimit(limit) = limit.map(|Extension(limit)| limit).unwrap_or_default();
+    if len > limit {
+        return Err(AppError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "validation_error",
+            "Validation error",
+        )
+        .with_field(field, format!("Over max batch size {}", limit)));
+    }
+    Ok(())
+}
+
+// ?skip_duplicatesを省略した場合の既定値。Extensionが未設定の場合は重複を確認せずに作成する
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SkipDuplicates(pub bool);
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CreateTodoQuery {
+    // trueの場合、未完了の同じテキストのTodoがあれば作成せずに200で返す
+    pub skip_duplicates: Option<bool>,
+}
+
+// 変更を購読中のWebSocket接続へ通知する。Extensionが未設定の場合や、outboxから配信する場合は何もしない
+pub(crate) fn publish(events: &Option<Extension<TodoEvents>>, user_id: i32, event: TodoEvent) {
+    if let Some(Extension(events)) = events {
+        events.publish(user_id, event);
+    }
+}
+
+// バージョン競合の場合は、クライアントがマージできるよう最新のTodoを添えて返す
+pub async fn update_or_conflict<T: TodoRepository>(
+    repository: &T,
+    user_id: i32,
+    id: i64,
+    payload: UpdateTodo,
+) -> Result<TodoEntity, AppError> {
+    let result = repository.update(user_id, id, payload).await;
+    with_current_on_conflict(repository, user_id, id, result).await
+}
+
+// バージョン競合・前提条件の不一致の場合は、最新のTodoをエラーに添える
+async fn with_current_on_conflict<T: TodoRepository, R>(
+    repository: &T,
+    user_id: i32,
+    id: i64,
+    result: Result<R, RepositoryError>,
+) -> Result<R, AppError> {
+    match result {
+        Ok(value) => Ok(value),
+        Err(e @ (RepositoryError::Conflict(_) | RepositoryError::PreconditionFailed(_))) => {
+            let current = repository.find(user_id, id).await?;
+            Err(AppError::from(e).with_current(current))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+// 繰り返しのTodoを完了した場合、作成した次の回をnext_occurrenceに入れて返す
+#[derive(Debug, Serialize, ToSchema)]
+pub struct UpdatedTodo {
+    #[serde(flatten)]
+    pub todo: TodoEntity,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_occurrence: Option<TodoEntity>,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DeleteTodoQuery {
+    pub permanent: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq, Eq, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Ndjson,
+}
+
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ExportTodosQuery {
+    pub format: Option<ExportFormat>,
+}
+
+#[utoipa::path(
+    post,
+    path = "/todos",
+    tag = "todos",
+    request_body = CreateTodo,
+    params(
+        CreateTodoQuery,
+        ("Idempotency-Key" = Option<String>, Header,
+            description = "Retries with the same key return the first response instead of creating again"),
+    ),
+    responses(
+        (status = 200, description = "Existing todo with the same client_id, or existing open todo with the same text with its id in X-Duplicate-Of",
+            body = TodoEntity),
+        (status = 201, description = "Created todo, or the original response to a retried Idempotency-Key",
+            body = TodoEntity),
+        (status = 400, description = "Malformed Idempotency-Key", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 409, description = "Request with the same Idempotency-Key is in progress, or client_id used by a trashed todo",
+            body = ErrorBody),
+        (status = 422, description = "Invalid fields, unknown label id or Idempotency-Key reused for a different request",
+            body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
 pub async fn create_todo<T: TodoRepository>(
+    user: AuthUser,
+    key: IdempotencyKey,
+    ParsedQuery(query): ParsedQuery<CreateTodoQuery>,
     ValidatedJson(payload): ValidatedJson<CreateTodo>,
+    skip_duplicates: Option<Extension<SkipDuplicates>>,
+    events: Option<Extension<TodoEvents>>,
     Extension(repository): Extension<Arc<T>>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let todo = repository
-        .create(payload)
-        .await
-        .or(Err(StatusCode::NOT_FOUND))?;
-    Ok((StatusCode::CREATED, Json(todo)))
+) -> Result<Response, AppError> {
+    let SkipDuplicates(default) = skip_duplicates
+        .map(|Extension(skip)| skip)
+        .unwrap_or_default();
+    let skip_duplicates = query.skip_duplicates.unwrap_or(default);
+    let fingerprint = fingerprint("POST /todos", &payload)?;
+    let client_id = payload.client_id();
+    let mut duplicate_of = None;
+    let mut synced = false;
+    let idempotency_key = key.0.clone();
+    let created = create_once(repository.as_ref(), user.id, key, fingerprint, async {
+        // オフラインで作成したTodoの再送は、409にせず作成済みのTodoを返す
+        if let Some(client_id) = client_id {
+            if let Some(todo) = repository.find_by_client_id(user.id, client_id).await? {
+                synced = true;
+                return Ok(todo);
+            }
+        }
+        if skip_duplicates {
+            if let Some(todo) = repository
+                .find_open_by_text(user.id, payload.text())
+                .await?
+            {
+                duplicate_of = Some(todo.id);
+                return Ok(todo);
+            }
+        }
+        let created = repository
+            .create_idempotent(user.id, payload, idempotency_key.as_deref())
+            .await;
+        match (created, client_id) {
+            // 同じclient_idの作成が同時に届いた場合は、先に作成された方を返す
+            (Err(e @ RepositoryError::Duplicate(_)), Some(client_id)) => {
+                let todo = repository
+                    .find_by_client_id(user.id, client_id)
+                    .await?
+                    .ok_or(e)?;
+                synced = true;
+                Ok(todo)
+            }
+            (result, _) => Ok(result?),
+        }
+    })
+    .await?;
+    match (created, duplicate_of) {
+        (Idempotent::Created(todo), _) if synced => {
+            Ok((StatusCode::OK, Json(todo)).into_response())
+        }
+        (Idempotent::Created(todo), Some(id)) => Ok((
+            StatusCode::OK,
+            Headers([(DUPLICATE_OF_HEADER, id.to_string())]),
+            Json(todo),
+        )
+            .into_response()),
+        (Idempotent::Created(todo), None) => {
+            publish(&events, user.id, TodoEvent::Created { todo: todo.clone() });
+            Ok((StatusCode::CREATED, Json(todo)).into_response())
+        }
+        (Idempotent::Replayed(body), _) => Ok(replayed(body)),
+    }
 }
 
+#[utoipa::path(
+    post,
+    path = "/todos/batch",
+    tag = "todos",
+    request_body = CreateTodoBatch,
+    params(("Idempotency-Key" = Option<String>, Header,
+        description = "Retries with the same key return the first response instead of creating again")),
+    responses(
+        (status = 201, description = "Created todos in request order, or the original response to a retried Idempotency-Key",
+            body = [TodoEntity]),
+        (status = 400, description = "Malformed Idempotency-Key", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "Unknown label id", body = ErrorBody),
+        (status = 409, description = "Request with the same Idempotency-Key is in progress", body = ErrorBody),
+        (status = 422, description = "Invalid fields, too many todos or Idempotency-Key reused for a different request",
+            body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn create_todo_batch<T: TodoRepository>(
+    user: AuthUser,
+    key: IdempotencyKey,
+    ValidatedJson(payload): ValidatedJson<CreateTodoBatch>,
+    limit: Option<Extension<TodoBatchLimit>>,
+    events: Option<Extension<TodoEvents>>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<Response, AppError> {
+    check_batch_limit(limit, "todos", payload.todos.len())?;
+    let fingerprint = fingerprint("POST /todos/batch", &payload)?;
+    let idempotency_key = key.0.clone();
+    let created = create_once(repository.as_ref(), user.id, key, fingerprint, async {
+        Ok(repository
+            .create_many_idempotent(user.id, payload.todos, idempotency_key.as_deref())
+            .await?)
+    })
+    .await?;
+    match created {
+        Idempotent::Created(todos) => {
+            for todo in todos.iter() {
+                publish(&events, user.id, TodoEvent::Created { todo: todo.clone() });
+            }
+            Ok((StatusCode::CREATED, Json(todos)).into_response())
+        }
+        Idempotent::Replayed(body) => Ok(replayed(body)),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/todos/{id}",
+    tag = "todos",
+    params(("id" = i64, Path, description = "Todo id")),
+    responses(
+        (status = 200, description = "Todo with its checklist items", body = TodoWithItems,
+            headers(("etag" = String, description = "Weak ETag of the todo, usable in If-Match"))),
+        (status = 304, description = "Not modified since If-None-Match"),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "Todo not found", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
 pub async fn find_todo<T: TodoRepository>(
-    Path(id): Path<i32>,
+    user: AuthUser,
+    ParsedPath(id): ParsedPath<i64>,
+    headers: HeaderMap,
     Extension(repository): Extension<Arc<T>>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let todo = repository.find(id).await.or(Err(StatusCode::NOT_FOUND))?;
-    Ok((StatusCode::OK, Json(todo)))
+) -> Result<Response, AppError> {
+    let todo = repository.find(user.id, id).await?;
+    let items = repository.items(user.id, id).await?;
+    versioned_etagged_json(&headers, todo.version, &TodoWithItems { todo, items })
 }
 
+#[utoipa::path(
+    get,
+    path = "/todos/by-client-id/{client_id}",
+    tag = "todos",
+    params(("client_id" = String, Path, format = "uuid", description = "Client-generated todo id")),
+    responses(
+        (status = 200, description = "Todo with its checklist items", body = TodoWithItems,
+            headers(("etag" = String, description = "Weak ETag of the todo, usable in If-Match"))),
+        (status = 304, description = "Not modified since If-None-Match"),
+        (status = 400, description = "Malformed UUID", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "Todo not found", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn find_todo_by_client_id<T: TodoRepository>(
+    user: AuthUser,
+    ParsedPath(client_id): ParsedPath<Uuid>,
+    headers: HeaderMap,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<Response, AppError> {
+    let todo = repository
+        .find_by_client_id(user.id, client_id)
+        .await?
+        .ok_or(RepositoryError::NotFound(None))?;
+    let items = repository.items(user.id, todo.id).await?;
+    versioned_etagged_json(&headers, todo.version, &TodoWithItems { todo, items })
+}
+
+#[utoipa::path(
+    get,
+    path = "/todos",
+    tag = "todos",
+    params(TodoListQuery, FieldsQuery, EnvelopeQuery),
+    responses(
+        (status = 200, description = "Page of todos, only with the selected fields. With envelope=true, wrapped as { data, meta: { total, limit, offset } }", body = [TodoEntity],
+            headers(
+                ("x-total-count" = i64, description = "Number of todos matching the filters"),
+                ("etag" = String, description = "Weak ETag of the page"),
+                ("last-modified" = String, description = "Last change to any of the user's todos, including moves to the trash"),
+                ("cache-control" = String, description = "no-cache unless configured"),
+            )),
+        (status = 304, description = "Not modified since If-None-Match, or since If-Modified-Since without If-None-Match"),
+        (status = 400, description = "Unparsable query parameter or unknown field in fields", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 422, description = "Invalid query parameter", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+// ポーリングで同じ一覧を再取得しないよう、ETagが一致する場合は304を返す
+// 他のページのTodoが増減した場合も変わるよう、件数もETagに含める
+// If-Modified-Since以降にどのTodoも変わっていなければ、一覧を読まずに304を返す
 pub async fn all_todo<T: TodoRepository>(
+    user: AuthUser,
+    ParsedQuery(query): ParsedQuery<TodoListQuery>,
+    ParsedQuery(fields): ParsedQuery<FieldsQuery>,
+    ParsedQuery(envelope): ParsedQuery<EnvelopeQuery>,
+    headers: HeaderMap,
+    policy: Option<Extension<CachePolicy>>,
     Extension(repository): Extension<Arc<T>>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let todos = repository.all().await.unwrap();
-    Ok((StatusCode::OK, Json(todos)))
+) -> Result<Response, AppError> {
+    query.validate().map_err(AppError::validation)?;
+    let fields = fields.selection(TODO_FIELDS)?;
+    // 一覧より先に読むため、間に変更があった場合は古い日時を返し、次の取得で読み直させる
+    let last_modified = repository.last_modified(user.id).await?;
+    let mut res = match last_modified {
+        Some(at) if not_modified_since(&headers, at) => StatusCode::NOT_MODIFIED.into_response(),
+        _ => {
+            let (limit, offset) = (query.limit(), query.offset());
+            let page = repository.all(user.id, query).await?;
+            let meta = ListMeta::page(page.total, limit, offset);
+            etagged_json(
+                &headers,
+                &envelope.wrap(fields.apply(&page.todos)?, meta)?,
+                vec![(TOTAL_COUNT_HEADER, page.total.to_string())],
+            )?
+        }
+    };
+    let res_headers = res.headers_mut();
+    res_headers.insert(CACHE_CONTROL, cache_policy(policy).todos);
+    if let Some(at) = last_modified {
+        let at = HeaderValue::from_str(&http_date(at)).map_err(anyhow::Error::from)?;
+        res_headers.insert(LAST_MODIFIED, at);
+    }
+    Ok(res)
 }
 
+#[utoipa::path(
+    patch,
+    path = "/todos/{id}",
+    tag = "todos",
+    params(
+        ("id" = i64, Path, description = "Todo id"),
+        ("If-Match" = Option<String>, Header, description = "ETag from GET /todos/{id}; update only if the todo has not changed since"),
+        ("If-Unmodified-Since" = Option<String>, Header, description = "HTTP-date; update only if the todo has not changed since. Ignored with If-Match"),
+    ),
+    request_body(content = UpdateTodo, description = "With Content-Type application/merge-patch+json, null also clears priority and labels"),
+    responses(
+        (status = 201, description = "Updated todo, with the next occurrence when completing a recurring todo", body = UpdatedTodo),
+        (status = 400, description = "Malformed If-Match", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "Todo not found", body = ErrorBody),
+        (status = 409, description = "Version mismatch, current holds the latest todo", body = ErrorBody),
+        (status = 412, description = "Modified since If-Match or If-Unmodified-Since, current holds the latest todo", body = ErrorBody),
+        (status = 415, description = "Not application/json or application/merge-patch+json", body = ErrorBody),
+        (status = 422, description = "Invalid fields, null text or completed in a merge patch, or unknown label id", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
 pub async fn update_todo<T: TodoRepository>(
-    Path(id): Path<i32>,
-    ValidatedJson(payload): ValidatedJson<UpdateTodo>,
+    user: AuthUser,
+    ParsedPath(id): ParsedPath<i64>,
+    IfUnmodified(precondition): IfUnmodified,
+    ValidatedPatch(payload): ValidatedPatch<UpdateTodo>,
+    events: Option<Extension<TodoEvents>>,
     Extension(repository): Extension<Arc<T>>,
-) -> Result<impl IntoResponse, StatusCode> {
-    let todo = repository
-        .update(id, payload)
-        .await
-        .or(Err(StatusCode::NOT_FOUND))?;
-    Ok((StatusCode::CREATED, Json(todo)))
+) -> Result<impl IntoResponse, AppError> {
+    let payload = payload.with_precondition(precondition);
+    let updated = update_and_publish(repository.as_ref(), &events, user.id, id, payload).await?;
+    Ok((StatusCode::CREATED, Json(updated)))
+}
+
+// 期限・繰り返し・通知はnullのまま読み込むと消す指定になる
+impl MergePatch for UpdateTodo {
+    fn cleared(field: &str) -> Result<Value, String> {
+        match field {
+            "text" | "completed" => Err("Can not be null".to_string()),
+            "priority" => Ok(json!(Priority::default())),
+            "labels" => Ok(json!([])),
+            _ => Ok(Value::Null),
+        }
+    }
+}
+
+// GraphQLのupdateTodoと共通の更新処理。変更と、完了で作成した次の回を購読中の接続へ通知する
+pub(crate) async fn update_and_publish<T: TodoRepository>(
+    repository: &T,
+    events: &Option<Extension<TodoEvents>>,
+    user_id: i32,
+    id: i64,
+    payload: UpdateTodo,
+) -> Result<UpdatedTodo, AppError> {
+    let completed_now = payload.completes();
+    let write = repository.update(user_id, id, payload);
+    write_and_publish(repository, events, user_id, id, completed_now, write).await
+}
+
+// PATCH・PUTで共通の書き込み後の処理。writeは変更前の状態を読んだ後にawaitする
+async fn write_and_publish<T: TodoRepository>(
+    repository: &T,
+    events: &Option<Extension<TodoEvents>>,
+    user_id: i32,
+    id: i64,
+    completed_now: bool,
+    write: impl Future<Output = Result<TodoEntity, RepositoryError>>,
+) -> Result<UpdatedTodo, AppError> {
+    // 次の回を今回の完了で作成したかを判定するため、完了時のみ変更前の状態を読む
+    let spawned_before = if completed_now {
+        repository
+            .find(user_id, id)
+            .await
+            .ok()
+            .and_then(|todo| todo.next_occurrence_id)
+    } else {
+        None
+    };
+    let todo = with_current_on_conflict(repository, user_id, id, write.await).await?;
+    publish(
+        events,
+        user_id,
+        TodoEvent::Updated {
+            todo: todo.clone(),
+            completed_now,
+        },
+    );
+
+    let next_occurrence = match todo.next_occurrence_id {
+        Some(next) if completed_now && spawned_before.is_none() => {
+            let next = repository.find(user_id, next).await?;
+            publish(events, user_id, TodoEvent::Created { todo: next.clone() });
+            Some(next)
+        }
+        _ => None,
+    };
+    Ok(UpdatedTodo {
+        todo,
+        next_occurrence,
+    })
+}
+
+#[utoipa::path(
+    put,
+    path = "/todos/{id}",
+    tag = "todos",
+    params(
+        ("id" = i64, Path, description = "Todo id"),
+        ("If-Match" = Option<String>, Header, description = "ETag from GET /todos/{id}; replace only if the todo has not changed since"),
+        ("If-Unmodified-Since" = Option<String>, Header, description = "HTTP-date; replace only if the todo has not changed since. Ignored with If-Match"),
+    ),
+    request_body = ReplaceTodo,
+    responses(
+        (status = 200, description = "Replaced todo, with the next occurrence when completing a recurring todo", body = UpdatedTodo),
+        (status = 400, description = "Malformed If-Match, or text or completed missing", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "Todo not found; PUT never creates a todo", body = ErrorBody),
+        (status = 409, description = "Version mismatch, current holds the latest todo", body = ErrorBody),
+        (status = 412, description = "Modified since If-Match or If-Unmodified-Since, current holds the latest todo", body = ErrorBody),
+        (status = 422, description = "Invalid fields or unknown label id", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+// PATCHと異なり、bodyで省略した項目は消すか既定値に戻す
+pub async fn replace_todo<T: TodoRepository>(
+    user: AuthUser,
+    ParsedPath(id): ParsedPath<i64>,
+    IfUnmodified(precondition): IfUnmodified,
+    ValidatedJson(payload): ValidatedJson<ReplaceTodo>,
+    events: Option<Extension<TodoEvents>>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, AppError> {
+    let payload = payload.with_precondition(precondition);
+    let completed_now = payload.completes();
+    let write = repository.replace(user.id, id, payload);
+    let replaced = write_and_publish(
+        repository.as_ref(),
+        &events,
+        user.id,
+        id,
+        completed_now,
+        write,
+    )
+    .await?;
+    Ok((StatusCode::OK, Json(replaced)))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/todos",
+    tag = "todos",
+    request_body = UpdateTodos,
+    responses(
+        (status = 200, description = "Updated todos and ids that were not found", body = UpdatedTodos),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 422, description = "Invalid fields or too many ids", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn update_todos<T: TodoRepository>(
+    user: AuthUser,
+    ValidatedJson(payload): ValidatedJson<UpdateTodos>,
+    limit: Option<Extension<TodoBatchLimit>>,
+    events: Option<Extension<TodoEvents>>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, AppError> {
+    check_batch_limit(limit, "ids", payload.ids.len())?;
+    let completed_now = payload.completes();
+    let updated = repository.update_many(user.id, payload).await?;
+    for todo in updated.todos.iter() {
+        publish(
+            &events,
+            user.id,
+            TodoEvent::Updated {
+                todo: todo.clone(),
+                completed_now,
+            },
+        );
+    }
+    Ok((StatusCode::OK, Json(updated)))
+}
+
+// after_id・before_id・to_topのいずれか1つのみを受け付ける
+fn move_target(id: i64, payload: MoveTodo) -> Result<MoveTarget, AppError> {
+    let invalid = |message: &str| {
+        AppError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "validation_error",
+            message,
+        )
+    };
+    let (target, anchor) = match (payload.after_id, payload.before_id, payload.to_top) {
+        (Some(after_id), None, None) => (MoveTarget::After(after_id), Some(("after_id", after_id))),
+        (None, Some(before_id), None) => (
+            MoveTarget::Before(before_id),
+            Some(("before_id", before_id)),
+        ),
+        (None, None, Some(true)) => (MoveTarget::Top, None),
+        _ => {
+            return Err(invalid(
+                "Specify exactly one of after_id, before_id or to_top: true",
+            ))
+        }
+    };
+    if let Some((field, anchor)) = anchor.filter(|(_, anchor)| *anchor == id) {
+        return Err(invalid("Validation error").with_field(
+            field,
+            format!("Can not move relative to itself ({})", anchor),
+        ));
+    }
+    Ok(target)
 }
 
+#[utoipa::path(
+    post,
+    path = "/todos/{id}/move",
+    tag = "todos",
+    params(("id" = i64, Path, description = "Todo id")),
+    request_body = MoveTodo,
+    responses(
+        (status = 200, description = "Moved todo. GET /todos returns todos in the new order", body = TodoEntity),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "Todo or after_id/before_id todo not found", body = ErrorBody),
+        (status = 422, description = "Not exactly one of after_id, before_id or to_top", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn move_todo<T: TodoRepository>(
+    user: AuthUser,
+    ParsedPath(id): ParsedPath<i64>,
+    ValidatedJson(payload): ValidatedJson<MoveTodo>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, AppError> {
+    let target = move_target(id, payload)?;
+    let todo = repository.move_todo(user.id, id, target).await?;
+    Ok((StatusCode::OK, Json(todo)))
+}
+
+// 既定ではゴミ箱へ移し、permanent=trueの場合のみ行を削除する
+#[utoipa::path(
+    delete,
+    path = "/todos/{id}",
+    tag = "todos",
+    params(
+        ("id" = i64, Path, description = "Todo id"),
+        DeleteTodoQuery,
+        ("If-Match" = Option<String>, Header, description = "ETag from GET /todos/{id}; delete only if the todo has not changed since"),
+        ("If-Unmodified-Since" = Option<String>, Header, description = "HTTP-date; delete only if the todo has not changed since. Ignored with If-Match"),
+    ),
+    responses(
+        (status = 204, description = "Moved to trash or deleted"),
+        (status = 400, description = "Malformed If-Match, or a precondition with permanent=true", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "Todo not found", body = ErrorBody),
+        (status = 412, description = "Modified since If-Match or If-Unmodified-Since, current holds the latest todo", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
 pub async fn delete_todo<T: TodoRepository>(
-    Path(id): Path<i32>,
-    Extension(repository): Extension<Arc<T>>,
-) -> StatusCode {
-    repository
-        .delete(id)
-        .await
-        .map(|_| StatusCode::NO_CONTENT)
-        .unwrap_or(StatusCode::NOT_FOUND)
+    user: AuthUser,
+    ParsedPath(id): ParsedPath<i64>,
+    ParsedQuery(query): ParsedQuery<DeleteTodoQuery>,
+    IfUnmodified(precondition): IfUnmodified,
+    events: Option<Extension<TodoEvents>>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<StatusCode, AppError> {
+    if query.permanent.unwrap_or(false) {
+        // ゴミ箱内のTodoはETagを返さないため、前提条件は付けられない
+        if !precondition.is_none() {
+            return Err(AppError::bad_request(
+                "If-Match and If-Unmodified-Since are not supported with permanent=true",
+            ));
+        }
+        repository.delete_permanently(user.id, id).await?;
+    } else {
+        let result = repository.delete(user.id, id, precondition).await;
+        with_current_on_conflict(repository.as_ref(), user.id, id, result).await?;
+    }
+    publish(&events, user.id, TodoEvent::Deleted { id });
+    Ok(StatusCode::NO_CONTENT)
+}
+
+// /exportと異なり全件をバッファしないため、件数の多いユーザーでもメモリを使い切らない
+#[utoipa::path(
+    get,
+    path = "/todos/export",
+    tag = "todos",
+    params(ExportTodosQuery),
+    responses(
+        (status = 200, description = "One todo as JSON per line, in id order",
+            content_type = "application/x-ndjson", body = TodoEntity),
+        (status = 400, description = "Unsupported format", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn export_todos<T: TodoRepository>(
+    user: AuthUser,
+    ParsedQuery(query): ParsedQuery<ExportTodosQuery>,
+    Extension(repository): Extension<Arc<T>>,
+) -> impl IntoResponse {
+    let ExportFormat::Ndjson = query.format.unwrap_or_default();
+    (
+        Headers(vec![(CONTENT_TYPE, NDJSON_CONTENT_TYPE)]),
+        ndjson_body(repository.stream_all(user.id)),
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/stats",
+    tag = "todos",
+    responses(
+        (status = 200, description = "Counts of todos not in trash", body = TodoStats),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn todo_stats<T: TodoRepository>(
+    user: AuthUser,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, AppError> {
+    let stats = repository.stats(user.id).await?;
+    Ok((StatusCode::OK, Json(stats)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/todos/{id}/activity",
+    tag = "todos",
+    params(("id" = i64, Path, description = "Todo id"), PageQuery, EnvelopeQuery),
+    responses(
+        (status = 200, description = "Page of changes to the todo, newest first", body = [TodoActivity],
+            headers(
+                ("x-total-count" = i64, description = "Number of recorded changes"),
+                ("etag" = String, description = "Weak ETag of the page"),
+            )),
+        (status = 304, description = "Not modified since If-None-Match"),
+        (status = 400, description = "Unparsable query parameter", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "Todo not found", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn todo_activity<T: TodoRepository>(
+    user: AuthUser,
+    ParsedPath(id): ParsedPath<i64>,
+    ParsedQuery(query): ParsedQuery<PageQuery>,
+    ParsedQuery(envelope): ParsedQuery<EnvelopeQuery>,
+    headers: HeaderMap,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<Response, AppError> {
+    let (limit, offset) = (query.limit(), query.offset());
+    let page = repository.activity(user.id, id, query).await?;
+    let meta = ListMeta::page(page.total, limit, offset);
+    etagged_json(
+        &headers,
+        &envelope.wrap(&page.activities, meta)?,
+        vec![(TOTAL_COUNT_HEADER, page.total.to_string())],
+    )
+}
+
+#[utoipa::path(
+    get,
+    path = "/todos/trash",
+    tag = "todos",
+    params(EnvelopeQuery),
+    responses(
+        (status = 200, description = "Todos in trash", body = [TodoEntity]),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn trash_todos<T: TodoRepository>(
+    user: AuthUser,
+    ParsedQuery(envelope): ParsedQuery<EnvelopeQuery>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, AppError> {
+    let todos = repository.trash(user.id).await?;
+    let meta = ListMeta::all(todos.len());
+    Ok((StatusCode::OK, Json(envelope.wrap(todos, meta)?)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/todos/{id}/restore",
+    tag = "todos",
+    params(("id" = i64, Path, description = "Todo id")),
+    responses(
+        (status = 200, description = "Restored todo", body = TodoEntity),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "Todo not in trash", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn restore_todo<T: TodoRepository>(
+    user: AuthUser,
+    ParsedPath(id): ParsedPath<i64>,
+    events: Option<Extension<TodoEvents>>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, AppError> {
+    let todo = repository.restore(user.id, id).await?;
+    // ゴミ箱から戻したTodoは一覧に再び現れるため、作成として通知する
+    publish(&events, user.id, TodoEvent::Created { todo: todo.clone() });
+    Ok((StatusCode::OK, Json(todo)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/todos/purge_completed",
+    tag = "todos",
+    responses(
+        (status = 200, description = "Number of deleted todos", body = Object,
+            example = json!({ "deleted": 3 })),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn purge_completed_todos<T: TodoRepository>(
+    user: AuthUser,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, AppError> {
+    let deleted = repository.delete_completed(user.id).await?;
+    Ok((StatusCode::OK, Json(json!({ "deleted": deleted }))))
+}
+
+// bodyは省略できる。省略した場合は期限も複製元から引き継ぐ
+fn parse_duplicate_body(body: &Bytes, strict: StrictRequests) -> Result<DuplicateTodo, AppError> {
+    if body.iter().all(u8::is_ascii_whitespace) {
+        return Ok(DuplicateTodo::default());
+    }
+    let value: Value = serde_json::from_slice(body).map_err(|e| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_json",
+            format!("Json parse error: [{}]", e),
+        )
+    })?;
+    parse_json_value(value, strict)
+}
+
+#[utoipa::path(
+    post,
+    path = "/todos/{id}/duplicate",
+    tag = "todos",
+    params(("id" = i64, Path, description = "Source todo id")),
+    request_body(content = DuplicateTodo, description = "Optional due_date override"),
+    responses(
+        (status = 201, description = "New incomplete todo with the text, priority, due date and labels of the source", body = TodoEntity),
+        (status = 400, description = "Unparsable body", body = ErrorBody),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "Source todo not found", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn duplicate_todo<T: TodoRepository>(
+    user: AuthUser,
+    ParsedPath(id): ParsedPath<i64>,
+    strict: StrictRequests,
+    body: Bytes,
+    events: Option<Extension<TodoEvents>>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, AppError> {
+    let payload = parse_duplicate_body(&body, strict)?;
+    let todo = repository.duplicate(user.id, id, payload).await?;
+    publish(&events, user.id, TodoEvent::Created { todo: todo.clone() });
+    Ok((StatusCode::CREATED, Json(todo)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/todos/{id}/archive",
+    tag = "todos",
+    params(("id" = i64, Path, description = "Todo id")),
+    responses(
+        (status = 200, description = "Archived todo. Archiving an archived todo changes nothing", body = TodoEntity),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "Todo not found", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn archive_todo<T: TodoRepository>(
+    user: AuthUser,
+    ParsedPath(id): ParsedPath<i64>,
+    events: Option<Extension<TodoEvents>>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, AppError> {
+    let todo = repository.archive(user.id, id).await?;
+    publish(
+        &events,
+        user.id,
+        TodoEvent::Updated {
+            todo: todo.clone(),
+            completed_now: false,
+        },
+    );
+    Ok((StatusCode::OK, Json(todo)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/todos/{id}/unarchive",
+    tag = "todos",
+    params(("id" = i64, Path, description = "Todo id")),
+    responses(
+        (status = 200, description = "Todo back in the default list", body = TodoEntity),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "Todo not found", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn unarchive_todo<T: TodoRepository>(
+    user: AuthUser,
+    ParsedPath(id): ParsedPath<i64>,
+    events: Option<Extension<TodoEvents>>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, AppError> {
+    let todo = repository.unarchive(user.id, id).await?;
+    publish(
+        &events,
+        user.id,
+        TodoEvent::Updated {
+            todo: todo.clone(),
+            completed_now: false,
+        },
+    );
+    Ok((StatusCode::OK, Json(todo)))
+}
+
+#[utoipa::path(
+    post,
+    path = "/todos/archive_completed",
+    tag = "todos",
+    responses(
+        (status = 200, description = "Number of archived todos", body = Object,
+            example = json!({ "archived": 3 })),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn archive_completed_todos<T: TodoRepository>(
+    user: AuthUser,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, AppError> {
+    let archived = repository.archive_completed(user.id).await?;
+    Ok((StatusCode::OK, Json(json!({ "archived": archived }))))
+}
+
+#[utoipa::path(
+    post,
+    path = "/todos/{id}/labels/{label_id}",
+    tag = "todos",
+    params(
+        ("id" = i64, Path, description = "Todo id"),
+        ("label_id" = i32, Path, description = "Label id"),
+    ),
+    responses(
+        (status = 200, description = "Todo with the label attached", body = TodoEntity),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "Todo or label not found", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn attach_todo_label<T: TodoRepository>(
+    user: AuthUser,
+    ParsedPath((id, label_id)): ParsedPath<(i64, i32)>,
+    events: Option<Extension<TodoEvents>>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, AppError> {
+    let todo = repository.attach_label(user.id, id, label_id).await?;
+    publish(
+        &events,
+        user.id,
+        TodoEvent::Updated {
+            todo: todo.clone(),
+            completed_now: false,
+        },
+    );
+    Ok((StatusCode::OK, Json(todo)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/todos/{id}/labels/{label_id}",
+    tag = "todos",
+    params(
+        ("id" = i64, Path, description = "Todo id"),
+        ("label_id" = i32, Path, description = "Label id"),
+    ),
+    responses(
+        (status = 200, description = "Todo with the label detached", body = TodoEntity),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "Todo or label not found", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn detach_todo_label<T: TodoRepository>(
+    user: AuthUser,
+    ParsedPath((id, label_id)): ParsedPath<(i64, i32)>,
+    events: Option<Extension<TodoEvents>>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, AppError> {
+    let todo = repository.detach_label(user.id, id, label_id).await?;
+    publish(
+        &events,
+        user.id,
+        TodoEvent::Updated {
+            todo: todo.clone(),
+            completed_now: false,
+        },
+    );
+    Ok((StatusCode::OK, Json(todo)))
 }
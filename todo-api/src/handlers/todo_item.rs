@@ -0,0 +1,90 @@
+use std::sync::Arc;
+
+use axum::extract::Extension;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+
+use crate::auth::AuthUser;
+use crate::error::AppError;
+use crate::repositories::todo::TodoRepository;
+use crate::repositories::todo_item::{CreateTodoItem, UpdateTodoItem};
+
+use super::{ParsedPath, ValidatedJson};
+
+#[utoipa::path(
+    post,
+    path = "/todos/{id}/items",
+    tag = "todos",
+    params(("id" = i64, Path, description = "Todo id")),
+    request_body = CreateTodoItem,
+    responses(
+        (status = 201, description = "Created item, appended to the end of the checklist", body = TodoItem),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "Todo not found", body = ErrorBody),
+        (status = 422, description = "Invalid fields", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn create_todo_item<T: TodoRepository>(
+    user: AuthUser,
+    ParsedPath(id): ParsedPath<i64>,
+    ValidatedJson(payload): ValidatedJson<CreateTodoItem>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, AppError> {
+    let item = repository.create_item(user.id, id, payload).await?;
+    Ok((StatusCode::CREATED, Json(item)))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/todos/{id}/items/{item_id}",
+    tag = "todos",
+    params(
+        ("id" = i64, Path, description = "Todo id"),
+        ("item_id" = i32, Path, description = "Item id"),
+    ),
+    request_body = UpdateTodoItem,
+    responses(
+        (status = 200, description = "Updated item", body = TodoItem),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "Todo or item not found", body = ErrorBody),
+        (status = 422, description = "Invalid fields", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn update_todo_item<T: TodoRepository>(
+    user: AuthUser,
+    ParsedPath((id, item_id)): ParsedPath<(i64, i32)>,
+    ValidatedJson(payload): ValidatedJson<UpdateTodoItem>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, AppError> {
+    let item = repository
+        .update_item(user.id, id, item_id, payload)
+        .await?;
+    Ok((StatusCode::OK, Json(item)))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/todos/{id}/items/{item_id}",
+    tag = "todos",
+    params(
+        ("id" = i64, Path, description = "Todo id"),
+        ("item_id" = i32, Path, description = "Item id"),
+    ),
+    responses(
+        (status = 204, description = "Deleted item"),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "Todo or item not found", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn delete_todo_item<T: TodoRepository>(
+    user: AuthUser,
+    ParsedPath((id, item_id)): ParsedPath<(i64, i32)>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<StatusCode, AppError> {
+    repository.delete_item(user.id, id, item_id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,22 @@
+use std::sync::Arc;
+
+use axum::{extract::Extension, http::StatusCode, response::IntoResponse, Json};
+
+use crate::auth::AuthUser;
+use crate::error::AppError;
+use crate::repositories::{user::UserRepository, RepositoryError};
+
+use super::ParsedPath;
+
+pub async fn find_user<T: UserRepository>(
+    user: AuthUser,
+    ParsedPath(id): ParsedPath<i32>,
+    Extension(repository): Extension<Arc<T>>,
+) -> Result<impl IntoResponse, AppError> {
+    // 他のユーザーは存在も明かさないよう、見つからない場合と同じ404にする
+    if id != user.id {
+        return Err(RepositoryError::NotFound(Some(id.into())).into());
+    }
+    let user = repository.find(id).await?;
+    Ok((StatusCode::OK, Json(user)))
+}
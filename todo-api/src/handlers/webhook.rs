@@ -0,0 +1,120 @@
+use std::sync::Arc;
+
+use axum::extract::Extension;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::Json;
+
+use crate::auth::AuthUser;
+use crate::error::AppError;
+use crate::repositories::webhook::{CreateWebhook, UpdateWebhook, WebhookRepository};
+
+use super::{EnvelopeQuery, ListMeta, ParsedPath, ParsedQuery, ValidatedJson};
+
+#[utoipa::path(
+    post,
+    path = "/webhooks",
+    tag = "webhooks",
+    request_body = CreateWebhook,
+    responses(
+        (status = 201, description = "Registered webhook", body = Webhook),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 422, description = "Invalid fields", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn create_webhook<W: WebhookRepository>(
+    user: AuthUser,
+    ValidatedJson(payload): ValidatedJson<CreateWebhook>,
+    Extension(repository): Extension<Arc<W>>,
+) -> Result<impl IntoResponse, AppError> {
+    let webhook = repository.create(user.id, payload).await?;
+    Ok((StatusCode::CREATED, Json(webhook)))
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhooks",
+    tag = "webhooks",
+    params(EnvelopeQuery),
+    responses(
+        (status = 200, description = "Webhooks of the user", body = [Webhook]),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn all_webhooks<W: WebhookRepository>(
+    user: AuthUser,
+    ParsedQuery(envelope): ParsedQuery<EnvelopeQuery>,
+    Extension(repository): Extension<Arc<W>>,
+) -> Result<impl IntoResponse, AppError> {
+    let webhooks = repository.all(user.id).await?;
+    let meta = ListMeta::all(webhooks.len());
+    Ok(Json(envelope.wrap(webhooks, meta)?))
+}
+
+#[utoipa::path(
+    get,
+    path = "/webhooks/{id}",
+    tag = "webhooks",
+    params(("id" = i32, Path, description = "Webhook id")),
+    responses(
+        (status = 200, description = "Webhook with its last delivery result", body = Webhook),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "Webhook not found", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn find_webhook<W: WebhookRepository>(
+    user: AuthUser,
+    ParsedPath(id): ParsedPath<i32>,
+    Extension(repository): Extension<Arc<W>>,
+) -> Result<impl IntoResponse, AppError> {
+    let webhook = repository.find(user.id, id).await?;
+    Ok(Json(webhook))
+}
+
+#[utoipa::path(
+    patch,
+    path = "/webhooks/{id}",
+    tag = "webhooks",
+    params(("id" = i32, Path, description = "Webhook id")),
+    request_body = UpdateWebhook,
+    responses(
+        (status = 200, description = "Updated webhook", body = Webhook),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "Webhook not found", body = ErrorBody),
+        (status = 422, description = "Invalid fields", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn update_webhook<W: WebhookRepository>(
+    user: AuthUser,
+    ParsedPath(id): ParsedPath<i32>,
+    ValidatedJson(payload): ValidatedJson<UpdateWebhook>,
+    Extension(repository): Extension<Arc<W>>,
+) -> Result<impl IntoResponse, AppError> {
+    let webhook = repository.update(user.id, id, payload).await?;
+    Ok(Json(webhook))
+}
+
+#[utoipa::path(
+    delete,
+    path = "/webhooks/{id}",
+    tag = "webhooks",
+    params(("id" = i32, Path, description = "Webhook id")),
+    responses(
+        (status = 204, description = "Deleted webhook"),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+        (status = 404, description = "Webhook not found", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn delete_webhook<W: WebhookRepository>(
+    user: AuthUser,
+    ParsedPath(id): ParsedPath<i32>,
+    Extension(repository): Extension<Arc<W>>,
+) -> Result<StatusCode, AppError> {
+    repository.delete(user.id, id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
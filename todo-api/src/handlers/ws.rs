@@ -0,0 +1,244 @@
+use std::sync::Arc;
+
+use axum::extract::ws::{CloseFrame, Message, WebSocket, WebSocketUpgrade};
+use axum::extract::Extension;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use validator::Validate;
+
+use crate::auth::AuthUser;
+use crate::error::{AppError, ErrorBody};
+use crate::events::{Subscription, TodoEvent, TodoEvents};
+use crate::repositories::todo::{CreateTodo, Precondition, TodoRepository, UpdateTodo};
+
+use super::todo::update_or_conflict;
+use super::{parse_json_value, StrictRequests};
+
+// 1001 Going Away
+const CLOSE_GOING_AWAY: u16 = 1001;
+
+// クライアントから送るフレーム。{"type":"create","text":"..."}の形式で送る
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Command {
+    Create(CreateTodo),
+    Update {
+        id: i64,
+        #[serde(flatten)]
+        payload: UpdateTodo,
+    },
+    Delete {
+        id: i64,
+    },
+}
+
+impl Command {
+    fn validate(&self) -> Result<(), AppError> {
+        match self {
+            Command::Create(payload) => payload.validate(),
+            Command::Update { payload, .. } => payload.validate(),
+            Command::Delete { .. } => Ok(()),
+        }
+        .map_err(AppError::validation)
+    }
+}
+
+// エラーはHTTPのレスポンスと同じbodyにtypeを付けて返す
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ErrorFrame {
+    Error(ErrorBody),
+}
+
+#[utoipa::path(
+    get,
+    path = "/ws",
+    tag = "todos",
+    responses(
+        (status = 101, description = "Switched to WebSocket. Sends created/updated/deleted/reminded events, \
+            label_created/label_updated/label_deleted/labels_merged events shared by all users, \
+            and accepts create/update/delete commands as JSON text frames"),
+        (status = 401, description = "Missing or invalid token", body = ErrorBody),
+    ),
+    security(("bearer" = []))
+)]
+pub async fn sync_todos<T: TodoRepository>(
+    user: AuthUser,
+    upgrade: WebSocketUpgrade,
+    strict: StrictRequests,
+    Extension(events): Extension<TodoEvents>,
+    Extension(repository): Extension<Arc<T>>,
+) -> impl IntoResponse {
+    let subscription = events.subscribe(user.id);
+    upgrade.on_upgrade(move |socket| handle_socket(socket, user, subscription, repository, strict))
+}
+
+async fn handle_socket<T: TodoRepository>(
+    mut socket: WebSocket,
+    user: AuthUser,
+    mut subscription: Subscription,
+    repository: Arc<T>,
+    strict: StrictRequests,
+) {
+    loop {
+        let frame = tokio::select! {
+            event = subscription.next() => match event {
+                Some(event) => to_text(&event),
+                // サーバーの終了時はCloseを送ってから切断する
+                None => break,
+            },
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    match dispatch(&text, user.id, repository.as_ref(), strict).await {
+                        Ok(event) => {
+                            subscription.publish(event.clone());
+                            to_text(&event)
+                        }
+                        Err(e) => to_text(&ErrorFrame::Error(e.into_body())),
+                    }
+                }
+                Some(Ok(Message::Binary(_))) => to_text(&ErrorFrame::Error(
+                    AppError::bad_request("Binary frames are not supported").into_body(),
+                )),
+                // Pingへの応答はライブラリ側で行われる
+                Some(Ok(Message::Ping(_) | Message::Pong(_))) => continue,
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+            },
+        };
+        if socket.send(Message::Text(frame)).await.is_err() {
+            return;
+        }
+    }
+    let _ = socket
+        .send(Message::Close(Some(CloseFrame {
+            code: CLOSE_GOING_AWAY,
+            reason: "server shutting down".into(),
+        })))
+        .await;
+}
+
+async fn dispatch<T: TodoRepository>(
+    text: &str,
+    user_id: i32,
+    repository: &T,
+    strict: StrictRequests,
+) -> Result<TodoEvent, AppError> {
+    let value: Value = serde_json::from_str(text).map_err(|e| {
+        AppError::new(
+            StatusCode::BAD_REQUEST,
+            "invalid_json",
+            format!("Json parse error: [{}]", e),
+        )
+    })?;
+    let command: Command = parse_json_value(value, strict)?;
+    command.validate()?;
+
+    let event = match command {
+        Command::Create(payload) => TodoEvent::Created {
+            todo: repository.create(user_id, payload).await?,
+        },
+        Command::Update { id, payload } => TodoEvent::Updated {
+            completed_now: payload.completes(),
+            todo: update_or_conflict(repository, user_id, id, payload).await?,
+        },
+        Command::Delete { id } => {
+            repository
+                .delete(user_id, id, Precondition::default())
+                .await?;
+            TodoEvent::Deleted { id }
+        }
+    };
+    Ok(event)
+}
+
+fn to_text(frame: &impl Serialize) -> String {
+    serde_json::to_string(frame).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use crate::repositories::todo::TodoRepositoryForMemory;
+
+    use super::*;
+
+    async fn dispatch_json(text: &str, repository: &TodoRepositoryForMemory) -> Value {
+        match dispatch(text, 1, repository, StrictRequests::default()).await {
+            Ok(event) => serde_json::to_value(event).unwrap(),
+            Err(e) => serde_json::to_value(ErrorFrame::Error(e.into_body())).unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn dispatch_commands_test() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+
+        let created = dispatch_json(
+            r#"{"type":"create","text":"ws todo","labels":[]}"#,
+            &repository,
+        )
+        .await;
+        assert_eq!("created", created["type"]);
+        assert_eq!("ws todo", created["todo"]["text"]);
+
+        let id = created["todo"]["id"].as_i64().unwrap();
+        let updated = dispatch_json(
+            &json!({ "type": "update", "id": id, "completed": true }).to_string(),
+            &repository,
+        )
+        .await;
+        assert_eq!("updated", updated["type"]);
+        assert_eq!(true, updated["todo"]["completed"]);
+
+        let deleted = dispatch_json(
+            &json!({ "type": "delete", "id": id }).to_string(),
+            &repository,
+        )
+        .await;
+        assert_eq!(json!({ "type": "deleted", "id": id }), deleted);
+    }
+
+    #[tokio::test]
+    async fn dispatch_error_frames_test() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+
+        let error = dispatch_json("{", &repository).await;
+        assert_eq!("error", error["type"]);
+        assert_eq!("invalid_json", error["error"]["code"]);
+
+        let error = dispatch_json(r#"{"type":"archive","id":1}"#, &repository).await;
+        assert_eq!("invalid_json", error["error"]["code"]);
+
+        let error = dispatch_json(r#"{"type":"create","text":"","labels":[]}"#, &repository).await;
+        assert_eq!("validation_error", error["error"]["code"]);
+        assert_eq!("text", error["error"]["fields"][0]["field"]);
+
+        let error = dispatch_json(
+            r#"{"type":"create","txt":"typo","text":"ws todo","labels":[]}"#,
+            &repository,
+        )
+        .await;
+        assert_eq!("unknown_field", error["error"]["code"]);
+        assert_eq!("txt", error["error"]["fields"][0]["field"]);
+        // STRICT_REQUESTSが無効な場合は無視して作成する
+        let lenient = StrictRequests(false);
+        let created = dispatch(
+            r#"{"type":"create","txt":"typo","text":"ws todo","labels":[]}"#,
+            1,
+            &repository,
+            lenient,
+        )
+        .await;
+        assert!(created.is_ok());
+
+        let error = dispatch_json(
+            r#"{"type":"update","id":999,"completed":true}"#,
+            &repository,
+        )
+        .await;
+        assert_eq!("not_found", error["error"]["code"]);
+    }
+}
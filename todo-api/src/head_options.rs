@@ -0,0 +1,134 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use axum::body::{boxed, Body, Empty};
+use axum::http::header::{ALLOW, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE};
+use axum::http::{HeaderValue, Method, Request, StatusCode};
+use axum::response::Response;
+use axum::Router;
+use http_body::Body as _;
+use tower::{Layer, Service};
+
+// axumはGETのルートでHEADに答える際に本文を捨て、Content-Lengthもわからなくなるため、捨てる前の長さを残す
+// 圧縮した場合はGETと同じくContent-Lengthを外させるため、compressionより内側に置く
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeadContentLengthLayer;
+
+impl<S> Layer<S> for HeadContentLengthLayer {
+    type Service = HeadContentLength<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        HeadContentLength { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct HeadContentLength<S> {
+    inner: S,
+}
+
+impl<S> Service<Request<Body>> for HeadContentLength<S>
+where
+    S: Service<Request<Body>, Response = Response>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let head = req.method() == Method::HEAD;
+        let future = self.inner.call(req);
+        Box::pin(async move {
+            let mut res = future.await?;
+            // ストリームの本文は長さがわからないため、GETと同じく付けない
+            if let Some(length) = res.body().size_hint().exact().filter(|_| head) {
+                res.headers_mut()
+                    .entry(CONTENT_LENGTH)
+                    .or_insert_with(|| HeaderValue::from(length));
+            }
+            Ok(res)
+        })
+    }
+}
+
+// どのパスへのOPTIONSにも、そのパスに登録したメソッドをAllowで返す
+// axumはAllowをルートごとのlayerの外側で付けるため、ルーター全体を包む。MatchedPathを使うmetricsなどの
+// layerをすべて適用した後に呼ぶ
+pub fn with_allowed_methods(app: Router) -> Router {
+    Router::new().fallback(app).layer(AllowedMethodsLayer)
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct AllowedMethodsLayer;
+
+impl<S> Layer<S> for AllowedMethodsLayer {
+    type Service = AllowedMethods<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AllowedMethods { inner }
+    }
+}
+
+#[derive(Clone)]
+struct AllowedMethods<S> {
+    inner: S,
+}
+
+impl<S> Service<Request<Body>> for AllowedMethods<S>
+where
+    S: Service<Request<Body>, Response = Response>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let options = req.method() == Method::OPTIONS;
+        let future = self.inner.call(req);
+        Box::pin(async move {
+            let res = with_options_allowed(future.await?);
+            if options && res.status() == StatusCode::METHOD_NOT_ALLOWED {
+                return Ok(no_content(res));
+            }
+            Ok(res)
+        })
+    }
+}
+
+// 405のAllowに、すべてのパスで受け付けるOPTIONSを加える
+fn with_options_allowed(mut res: Response) -> Response {
+    if res.status() != StatusCode::METHOD_NOT_ALLOWED {
+        return res;
+    }
+    let allow = res
+        .headers()
+        .get(ALLOW)
+        .and_then(|value| value.to_str().ok())
+        .map(|allow| format!("{},OPTIONS", allow))
+        .and_then(|allow| HeaderValue::from_str(&allow).ok());
+    if let Some(allow) = allow {
+        res.headers_mut().insert(ALLOW, allow);
+    }
+    res
+}
+
+// 非推奨のルートを示すヘッダーなどは残し、エラーの本文に関するヘッダーのみ除く
+fn no_content(res: Response) -> Response {
+    let (mut parts, _) = res.into_parts();
+    parts.status = StatusCode::NO_CONTENT;
+    for name in [CONTENT_TYPE, CONTENT_LENGTH, CONTENT_ENCODING] {
+        parts.headers.remove(name);
+    }
+    Response::from_parts(parts, boxed(Empty::new()))
+}
@@ -0,0 +1,317 @@
+use std::future::Future;
+use std::time::Duration;
+
+use axum::async_trait;
+use axum::extract::{FromRequest, RequestParts};
+use axum::http::StatusCode;
+use axum::response::{Headers, IntoResponse, Response};
+use axum::Json;
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tokio::task::JoinHandle;
+
+use crate::clock::Clock;
+use crate::error::AppError;
+use crate::periodic::spawn_periodic;
+use crate::repositories::todo::{IdempotencyRecord, TodoRepository};
+use crate::trash::cutoff;
+
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+// 再送への応答であることをクライアントへ知らせる
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "idempotent-replayed";
+// 期限切れのキーは参照されないため、削除は頻繁でなくてよい
+pub const IDEMPOTENCY_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const MAX_KEY_LEN: usize = 255;
+
+// Idempotency-Keyヘッダーの値。ヘッダーがない場合はNone
+#[derive(Debug)]
+pub struct IdempotencyKey(pub Option<String>);
+
+#[async_trait]
+impl<B> FromRequest<B> for IdempotencyKey
+where
+    B: Send,
+{
+    type Rejection = AppError;
+
+    async fn from_request(req: &mut RequestParts<B>) -> Result<Self, Self::Rejection> {
+        let value = match req
+            .headers()
+            .and_then(|headers| headers.get(IDEMPOTENCY_KEY_HEADER))
+        {
+            Some(value) => value,
+            None => return Ok(IdempotencyKey(None)),
+        };
+        // UUIDなどを想定し、表示可能なASCIIの文字列のみ受け付ける
+        match value.to_str() {
+            Ok(key)
+                if !key.is_empty()
+                    && key.len() <= MAX_KEY_LEN
+                    && key.bytes().all(|b| b.is_ascii_graphic()) =>
+            {
+                Ok(IdempotencyKey(Some(key.to_string())))
+            }
+            _ => Err(AppError::bad_request(format!(
+                "Idempotency-Key must be 1 to {} visible ASCII characters",
+                MAX_KEY_LEN
+            ))),
+        }
+    }
+}
+
+// 作成した結果か、同じキーで作成済みだった場合に記録したbodyか
+#[derive(Debug)]
+pub enum Idempotent<R> {
+    Created(R),
+    Replayed(Value),
+}
+
+// キーがない場合はそのまま作成する。キーがある場合は作成を1回に限り、再送には最初のbodyを返す
+// 作成に失敗した場合はキーを取り消し、同じキーで再送できるようにする
+// 作成したTodoのbodyはcreateの中で同じトランザクションで記録する。既存のTodoを返した場合はここで記録する
+// 記録できなかった場合も作成済みのため結果は返す。記録のないキーは作成済みのTodoがありうるため、期限まで409を返す
+pub async fn create_once<T, R, F>(
+    repository: &T,
+    user_id: i32,
+    IdempotencyKey(key): IdempotencyKey,
+    fingerprint: String,
+    create: F,
+) -> Result<Idempotent<R>, AppError>
+where
+    T: TodoRepository,
+    R: Serialize,
+    F: Future<Output = Result<R, AppError>>,
+{
+    let key = match key {
+        Some(key) => key,
+        None => return create.await.map(Idempotent::Created),
+    };
+    match repository
+        .claim_idempotency_key(user_id, &key, &fingerprint)
+        .await?
+    {
+        None => {}
+        Some(record) => return replay(record, &fingerprint),
+    }
+
+    let created = match create.await {
+        Ok(created) => created,
+        Err(e) => {
+            if let Err(release) = repository.release_idempotency_key(user_id, &key).await {
+                tracing::warn!("fail release idempotency key: {:#}", release);
+            }
+            return Err(e);
+        }
+    };
+    let response = serde_json::to_value(&created).map_err(anyhow::Error::from)?;
+    if let Err(e) = repository
+        .complete_idempotency_key(user_id, &key, response)
+        .await
+    {
+        tracing::warn!("fail complete idempotency key: {:#}", e);
+    }
+    Ok(Idempotent::Created(created))
+}
+
+// 最初のリクエストと同じ201とbodyを返す
+pub fn replayed(body: Value) -> Response {
+    (
+        StatusCode::CREATED,
+        Headers([(IDEMPOTENT_REPLAYED_HEADER, "true")]),
+        Json(body),
+    )
+        .into_response()
+}
+
+fn replay<R>(record: IdempotencyRecord, fingerprint: &str) -> Result<Idempotent<R>, AppError> {
+    if record.fingerprint != fingerprint {
+        return Err(AppError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "idempotency_key_reused",
+            "Idempotency-Key was already used for a different request",
+        ));
+    }
+    match record.response {
+        Some(response) => Ok(Idempotent::Replayed(response)),
+        // 最初のリクエストを処理し終える前に再送された場合
+        None => Err(AppError::new(
+            StatusCode::CONFLICT,
+            "idempotency_key_in_progress",
+            "A request with this Idempotency-Key is still being processed",
+        )),
+    }
+}
+
+// 同じキーでもエンドポイントが異なれば別のリクエストとして扱う
+pub fn fingerprint(endpoint: &str, payload: &impl Serialize) -> Result<String, AppError> {
+    let payload = serde_json::to_vec(payload).map_err(anyhow::Error::from)?;
+    let mut hasher = Sha256::new();
+    hasher.update(endpoint.as_bytes());
+    hasher.update([0]);
+    hasher.update(&payload);
+    Ok(hex::encode(hasher.finalize()))
+}
+
+// 有効期限を過ぎたキーが残らないよう、一定間隔で削除し続ける
+pub fn spawn_purger<T: TodoRepository, C: Clock>(
+    repository: T,
+    clock: C,
+    ttl: Duration,
+    interval: Duration,
+) -> JoinHandle<()> {
+    spawn_periodic(
+        "purge idempotency keys",
+        interval,
+        (repository, clock),
+        move |state| async move {
+            let (repository, clock) = &*state;
+            let purged = repository
+                .purge_idempotency_keys_before(cutoff(clock.now(), ttl))
+                .await?;
+            if purged > 0 {
+                tracing::info!("purged {} expired idempotency keys", purged);
+            }
+            Ok(false)
+        },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::test_utils::MockClock;
+    use crate::repositories::test_utils::FailingTodoRepository;
+    use crate::repositories::todo::{CreateTodo, TodoRepositoryForMemory};
+
+    #[test]
+    fn should_fingerprint_endpoint_and_payload() {
+        let payload = serde_json::json!({ "text": "todo" });
+        let base = fingerprint("POST /todos", &payload).unwrap();
+        assert_eq!(base, fingerprint("POST /todos", &payload).unwrap());
+        assert_ne!(base, fingerprint("POST /todos/batch", &payload).unwrap());
+        assert_ne!(
+            base,
+            fingerprint("POST /todos", &serde_json::json!({ "text": "other" })).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn should_purge_expired_keys_in_background() {
+        let clock = MockClock::default();
+        let repository = TodoRepositoryForMemory::new(vec![]).with_clock(clock.clone());
+        repository
+            .claim_idempotency_key(1, "key", "fingerprint")
+            .await
+            .expect("failed claim key");
+
+        let purger = spawn_purger(
+            repository.clone(),
+            clock.clone(),
+            Duration::from_secs(60),
+            Duration::from_millis(10),
+        );
+        // 有効期限が過ぎるまでは削除しない
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(repository
+            .claim_idempotency_key(1, "key", "fingerprint")
+            .await
+            .unwrap()
+            .is_some());
+
+        clock.advance(chrono::Duration::minutes(2));
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while repository
+                .claim_idempotency_key(1, "key", "fingerprint")
+                .await
+                .unwrap()
+                .is_some()
+            {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("idempotency key was not purged");
+        purger.abort();
+    }
+
+    #[tokio::test]
+    async fn should_keep_key_left_without_response_until_expired() {
+        let clock = MockClock::default();
+        let repository = FailingTodoRepository::new(
+            TodoRepositoryForMemory::new(vec![]).with_clock(clock.clone()),
+        )
+        .fail("complete_idempotency_key");
+        let key = || IdempotencyKey(Some("key".to_string()));
+
+        // 作成済みのため、bodyを記録できなくても結果を返す
+        let created = create_once(&repository, 1, key(), "fingerprint".to_string(), async {
+            Ok(1)
+        })
+        .await
+        .unwrap();
+        assert!(matches!(created, Idempotent::Created(1)));
+
+        // 作成済みのTodoがありうるため、時間が経っても引き継がず、内容も書き換えない
+        repository.recover("complete_idempotency_key");
+        clock.advance(chrono::Duration::minutes(30));
+        let err = create_once(&repository, 1, key(), "fingerprint".to_string(), async {
+            Ok(2)
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(StatusCode::CONFLICT, err.into_response().status());
+        let err = create_once(&repository, 1, key(), "other".to_string(), async { Ok(3) })
+            .await
+            .unwrap_err();
+        assert_eq!(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            err.into_response().status()
+        );
+
+        // 期限を過ぎて削除された後は、同じキーで作成できる
+        repository
+            .purge_idempotency_keys_before(clock.now())
+            .await
+            .unwrap();
+        let created = create_once(&repository, 1, key(), "fingerprint".to_string(), async {
+            Ok(4)
+        })
+        .await
+        .unwrap();
+        assert!(matches!(created, Idempotent::Created(4)));
+    }
+
+    #[tokio::test]
+    async fn should_record_response_with_created_todo() {
+        let repository = FailingTodoRepository::new(TodoRepositoryForMemory::new(vec![]))
+            .fail("complete_idempotency_key");
+        repository
+            .claim_idempotency_key(1, "key", "fingerprint")
+            .await
+            .unwrap();
+        let todo = repository
+            .create_idempotent(1, CreateTodo::new("todo".to_string(), vec![]), Some("key"))
+            .await
+            .unwrap();
+
+        // 後から記録できなくても、作成と同時に記録したbodyを返す
+        let record = repository
+            .claim_idempotency_key(1, "key", "fingerprint")
+            .await
+            .unwrap()
+            .expect("key was not claimed");
+        assert_eq!(
+            Some(todo.id as i64),
+            record.response.and_then(|r| r["id"].as_i64())
+        );
+
+        // 記録済みのキーは取り消さない
+        repository.release_idempotency_key(1, "key").await.unwrap();
+        assert!(repository
+            .claim_idempotency_key(1, "key", "fingerprint")
+            .await
+            .unwrap()
+            .is_some());
+    }
+}
@@ -0,0 +1,6239 @@
+use std::future::Future;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use futures_util::FutureExt;
+use axum::extract::Extension;
+use axum::handler::Handler;
+use axum::Router;
+use axum::routing::{delete, get, post};
+use hyper::header::{
+    HeaderName, HeaderValue, AUTHORIZATION, CONTENT_TYPE, ETAG, IF_MATCH, IF_MODIFIED_SINCE,
+    IF_NONE_MATCH, IF_UNMODIFIED_SINCE,
+};
+use hyper::Method;
+use sqlx::PgPool;
+use tower::util::MapResponseLayer;
+use tower_http::compression::predicate::{DefaultPredicate, NotForContentType, Predicate};
+use tower_http::compression::CompressionLayer;
+use tower_http::cors::{CorsLayer, Origin};
+use tower_http::request_id::{PropagateRequestIdLayer, SetRequestIdLayer};
+use tower_http::set_header::SetResponseHeaderLayer;
+
+use crate::auth::AuthKeys;
+use crate::body_limit::{with_body_limits, BodyLimits};
+use crate::body_log::{BodyLogLayer, LogBodies};
+use crate::cache_control::MutationCacheControlLayer;
+use crate::clock::SystemClock;
+use crate::config::{Config, Storage};
+use crate::error_reporting::{ReportErrorsLayer, Reporter, SentryReporter};
+use crate::events::TodoEvents;
+use crate::grpc::TodoGrpcService;
+use crate::idempotency::{
+    IDEMPOTENCY_KEY_HEADER, IDEMPOTENCY_PURGE_INTERVAL, IDEMPOTENT_REPLAYED_HEADER,
+};
+use crate::handlers::admin::{reset_data, seed_data, AdminToken, ADMIN_TOKEN_HEADER};
+use crate::handlers::auth::{login, register};
+use crate::handlers::backup::{export_backup, import_backup, import_csv};
+use crate::handlers::comment::{all_comments, create_comment, delete_comment};
+use crate::handlers::docs::{openapi_spec, swagger_ui, ApiPrefix, OPENAPI_PATH};
+use crate::handlers::fallback::{method_not_allowed, not_found, StaticFiles};
+use crate::handlers::graphql::{
+    execute_graphql, graphql_playground, GRAPHQL_PATH, GRAPHQL_PLAYGROUND_PATH,
+};
+use crate::handlers::health::{healthz, readyz};
+use crate::handlers::metrics::metrics;
+use crate::handlers::StrictRequests;
+use crate::handlers::label::{all_label, create_label, delete_label, merge_label, update_label};
+use crate::handlers::todo::{
+    all_todo, archive_completed_todos, archive_todo, attach_todo_label, create_todo,
+    create_todo_batch, delete_todo, detach_todo_label, duplicate_todo, export_todos, find_todo,
+    find_todo_by_client_id, move_todo, purge_completed_todos, replace_todo, restore_todo,
+    todo_activity, todo_stats, trash_todos, unarchive_todo, update_todo, update_todos,
+    SkipDuplicates, TodoBatchLimit, DUPLICATE_OF_HEADER, TOTAL_COUNT_HEADER,
+};
+use crate::limits::with_request_limits;
+use crate::metrics::{Metrics, MetricsLayer};
+use crate::naming::{NamingLayer, NAMING_HEADER};
+use crate::outbox::{BroadcastSink, OutboxPublisher, OUTBOX_POLL_INTERVAL};
+use crate::persist::{SnapshotWriter, PERSIST_INTERVAL};
+use crate::rate_limit::{with_rate_limit, RateLimitPolicy, RateLimiter};
+use crate::reminders::TodoNotifier;
+use crate::repositories::cache::{CachePolicy, CachedLabelRepository, CachedTodoRepository};
+use crate::repositories::comment::{
+    CommentRepository, CommentRepositoryForDb, CommentRepositoryForMemory,
+};
+use crate::repositories::health::{
+    HealthRepository, HealthRepositoryForDb, HealthRepositoryForMemory,
+};
+use crate::repositories::label::{LabelRepository, LabelRepositoryForDb, LabelRepositoryForMemory};
+use crate::repositories::outbox::OutboxRepositoryForDb;
+use crate::handlers::todo_item::{create_todo_item, delete_todo_item, update_todo_item};
+use crate::handlers::user::find_user;
+use crate::handlers::webhook::{
+    all_webhooks, create_webhook, delete_webhook, find_webhook, update_webhook,
+};
+use crate::handlers::ws::sync_todos;
+use crate::head_options::{with_allowed_methods, HeadContentLengthLayer};
+use crate::repositories::todo::{TodoRepository, TodoRepositoryForDb, TodoRepositoryForMemory};
+use crate::repositories::user::{UserRepository, UserRepositoryForDb, UserRepositoryForMemory};
+use crate::repositories::webhook::{
+    WebhookRepository, WebhookRepositoryForDb, WebhookRepositoryForMemory,
+};
+use crate::telemetry::{trace_layer, MakeRandomRequestId, REQUEST_ID_HEADER};
+use crate::webhooks::{RetryPolicy, WebhookSink};
+#[cfg(feature = "sqlite")]
+use crate::repositories::{
+    comment::CommentRepositoryForSqlite, health::HealthRepositoryForSqlite,
+    label::LabelRepositoryForSqlite, todo::TodoRepositoryForSqlite, user::UserRepositoryForSqlite,
+    webhook::WebhookRepositoryForSqlite,
+};
+
+pub mod auth;
+mod body_limit;
+mod body_log;
+pub mod cache_control;
+pub mod clock;
+pub mod config;
+mod csv_import;
+mod database;
+pub mod error;
+pub mod error_reporting;
+pub mod events;
+pub mod graphql;
+pub mod grpc;
+pub mod handlers;
+mod head_options;
+mod idempotency;
+mod limits;
+mod metrics;
+mod migration;
+pub mod naming;
+pub mod openapi;
+pub mod outbox;
+mod periodic;
+mod persist;
+mod rate_limit;
+mod reminders;
+pub mod repositories;
+pub mod telemetry;
+mod trash;
+pub mod webhooks;
+
+// 設定に応じた保存先でアプリを組み立てて起動する。終了のシグナルを受けるまで戻らない
+pub async fn run(config: Config, migrate_only: bool) -> anyhow::Result<()> {
+    if config.storage == Storage::Memory {
+        return run_memory(config, migrate_only).await;
+    }
+    // DATABASE_URLのスキームで保存先を選ぶ
+    if config.database_url.starts_with("sqlite:") {
+        return run_sqlite(config, migrate_only).await;
+    }
+
+    let pool = database::connect(&config).await?;
+    tracing::info!("storage is postgres, data is persisted in the database");
+
+    // --migrate-onlyはJobコンテナ用に、マイグレーションのみ適用して終了する
+    if migrate_only || config.run_migrations {
+        migration::run(&pool).await.context("fail run migrations")?;
+    }
+    if migrate_only {
+        return Ok(());
+    }
+
+    let todo_repository = CachedTodoRepository::new(
+        TodoRepositoryForDb::new(pool.clone(), SystemClock),
+        cache_policy(&config),
+    );
+    let webhook_repository = WebhookRepositoryForDb::new(pool.clone());
+    let app = create_app(
+        todo_repository.clone(),
+        CachedLabelRepository::new(
+            LabelRepositoryForDb::new(pool.clone()),
+            cache_policy(&config),
+        )
+        .with_todo_cache(&todo_repository),
+        UserRepositoryForDb::new(pool.clone()),
+        CommentRepositoryForDb::new(pool.clone()),
+        webhook_repository.clone(),
+        HealthRepositoryForDb::new(pool.clone()),
+        AuthKeys::new(config.jwt_secret.as_bytes()),
+        &config.api_prefix,
+    );
+    start(config, todo_repository, webhook_repository, app, Some(pool)).await
+}
+
+// デモ用に外部の依存なしで起動する。データはプロセス内にのみ保持する
+async fn run_memory(config: Config, migrate_only: bool) -> anyhow::Result<()> {
+    if migrate_only {
+        tracing::info!("storage is memory, no migrations to apply");
+        return Ok(());
+    }
+    let (todo_repository, label_repository) = memory_repositories();
+    let user_repository = UserRepositoryForMemory::new();
+    let snapshots = match &config.persist_path {
+        Some(path) => {
+            tracing::warn!(
+                "storage is memory, todos, labels and users are saved to [{}] \
+                 but comments and webhooks are lost on restart",
+                path.display()
+            );
+            if let Some(snapshot) = persist::load(path) {
+                snapshot.restore(&todo_repository, &user_repository).await;
+            }
+            let snapshots = SnapshotWriter::new(
+                todo_repository.clone(),
+                user_repository.clone(),
+                path.clone(),
+            );
+            snapshots.spawn(PERSIST_INTERVAL);
+            Some(snapshots)
+        }
+        None => {
+            tracing::warn!(
+                "storage is memory, no database is required but all data is lost on restart \
+                 and label usage counts are not tracked"
+            );
+            None
+        }
+    };
+
+    let todo_repository = CachedTodoRepository::new(todo_repository, cache_policy(&config));
+    let webhook_repository = WebhookRepositoryForMemory::new();
+    let app = create_app(
+        todo_repository.clone(),
+        CachedLabelRepository::new(label_repository, cache_policy(&config))
+            .with_todo_cache(&todo_repository),
+        user_repository,
+        CommentRepositoryForMemory::new(),
+        webhook_repository.clone(),
+        HealthRepositoryForMemory::new(),
+        AuthKeys::new(config.jwt_secret.as_bytes()),
+        &config.api_prefix,
+    );
+    let result = start(config, todo_repository, webhook_repository, app, None).await;
+    // 最後の変更から書き込みまでの間に終了しても失われないよう、終了時にも書き込む
+    if let Some(snapshots) = snapshots {
+        snapshots.write().await?;
+    }
+    result
+}
+
+// CACHE_TTL_SECSが未設定の場合はキャッシュしない
+fn cache_policy(config: &Config) -> Option<CachePolicy> {
+    config.cache_ttl.map(|ttl| CachePolicy {
+        ttl,
+        max_entries: config.cache_max_entries,
+    })
+}
+
+// RATE_LIMIT_PER_MINUTEが未設定の場合は制限しない
+fn rate_limit_policy(config: &Config) -> Option<RateLimitPolicy> {
+    config.rate_limit_per_minute.map(|per_minute| RateLimitPolicy {
+        per_minute,
+        burst: config.rate_limit_burst,
+    })
+}
+
+// ラベルの定義をTodoと共有し、/labelsで作成したラベルをTodoへ付けられるようにする
+fn memory_repositories() -> (TodoRepositoryForMemory, LabelRepositoryForMemory) {
+    let label_repository = LabelRepositoryForMemory::new();
+    let todo_repository = TodoRepositoryForMemory::new(vec![])
+        .with_labels(&label_repository)
+        .with_clock(SystemClock);
+    (todo_repository, label_repository)
+}
+
+#[cfg(feature = "sqlite")]
+async fn run_sqlite(config: Config, migrate_only: bool) -> anyhow::Result<()> {
+    let pool = database::connect_sqlite(&config.database_url).await?;
+    tracing::info!("storage is sqlite, data is persisted in [{}]", config.database_url);
+
+    if migrate_only || config.run_migrations {
+        migration::run_sqlite(&pool)
+            .await
+            .context("fail run migrations")?;
+    }
+    if migrate_only {
+        return Ok(());
+    }
+
+    let todo_repository = CachedTodoRepository::new(
+        TodoRepositoryForSqlite::new(pool.clone(), SystemClock),
+        cache_policy(&config),
+    );
+    let webhook_repository = WebhookRepositoryForSqlite::new(pool.clone());
+    let app = create_app(
+        todo_repository.clone(),
+        CachedLabelRepository::new(
+            LabelRepositoryForSqlite::new(pool.clone()),
+            cache_policy(&config),
+        )
+        .with_todo_cache(&todo_repository),
+        UserRepositoryForSqlite::new(pool.clone()),
+        CommentRepositoryForSqlite::new(pool.clone()),
+        webhook_repository.clone(),
+        HealthRepositoryForSqlite::new(pool),
+        AuthKeys::new(config.jwt_secret.as_bytes()),
+        &config.api_prefix,
+    );
+    // 接続プールのメトリクスはPostgresの場合のみ出力する
+    start(config, todo_repository, webhook_repository, app, None).await
+}
+
+#[cfg(not(feature = "sqlite"))]
+async fn run_sqlite(config: Config, _migrate_only: bool) -> anyhow::Result<()> {
+    anyhow::bail!(
+        "sqlite is not supported in this build, rebuild with --features sqlite to use [{}]",
+        config.database_url
+    )
+}
+
+// 保存先によらない起動処理。バックグラウンドのタスクを起動し、appを公開する
+async fn start<Todo: TodoRepository, Webhook: WebhookRepository>(
+    config: Config,
+    todo_repository: Todo,
+    webhook_repository: Webhook,
+    mut app: Router,
+    pool: Option<PgPool>,
+) -> anyhow::Result<()> {
+    if let Some(retention) = config.trash_retention {
+        trash::spawn_purger(
+            todo_repository.clone(),
+            SystemClock,
+            retention,
+            config.trash_purge_interval,
+        );
+    }
+
+    idempotency::spawn_purger(
+        todo_repository.clone(),
+        SystemClock,
+        config.idempotency_key_ttl,
+        IDEMPOTENCY_PURGE_INTERVAL,
+    );
+
+    // 配信は購読した変更について行うため、リクエストの応答には影響しない
+    let policy = RetryPolicy {
+        max_attempts: config.webhook_max_attempts,
+        base_delay: config.webhook_retry_delay,
+    };
+    let events = match &pool {
+        // Postgresでは変更と同じトランザクションでeventsへ記録されるため、Webhookへも
+        // WebSocket・gRPCの購読者へもそこから送る。取り消された変更は流さない
+        Some(pool) => {
+            let events = TodoEvents::new().with_outbox();
+            let publisher =
+                OutboxPublisher::new(OutboxRepositoryForDb::new(pool.clone()), policy, SystemClock)
+                    .with_sink(BroadcastSink(events.clone()))
+                    .with_sink(WebhookSink::new(webhook_repository));
+            outbox::spawn_publisher(publisher, OUTBOX_POLL_INTERVAL);
+            events
+        }
+        None => {
+            let events = TodoEvents::new();
+            webhooks::spawn_dispatcher(webhook_repository, &events, policy);
+            events
+        }
+    };
+    // gRPCのサービスはHTTPのアプリと同じリポジトリ・イベントを使う
+    let grpc = match config.grpc_addr() {
+        Some(addr) => {
+            let listener = TcpListener::bind(addr)
+                .with_context(|| format!("fail bind grpc address [{}]", addr))?;
+            let service = TodoGrpcService::new(
+                Arc::new(todo_repository.clone()),
+                events.clone(),
+                Arc::new(AuthKeys::new(config.jwt_secret.as_bytes())),
+            );
+            Some((listener, service))
+        }
+        None => None,
+    };
+    reminders::spawn_reminder(
+        todo_repository.clone(),
+        TodoNotifier::new(Some(events.clone())),
+        SystemClock,
+        config.reminder_interval,
+    );
+
+    // 誤って公開しないよう、ADMIN_TOKENを設定した場合のみルート自体を組み込む
+    if let Some(token) = &config.admin_token {
+        tracing::warn!("admin routes are enabled, all todos and labels can be reset");
+        app = with_admin(app, todo_repository, AdminToken(token.clone()));
+    }
+    // sentry::initはmainで行い、ここではレスポンスから送るイベントの送信先のみ渡す
+    if config.sentry_dsn.is_some() {
+        app = app.layer(Extension(Reporter(Arc::new(SentryReporter))));
+    }
+    if config.log_bodies {
+        tracing::warn!("request and response bodies are logged at debug level");
+    }
+    // 上限で返した503もメトリクスに記録されるよう、metricsより内側に置く
+    app = with_body_limits(
+        app,
+        BodyLimits {
+            api: config.body_limit,
+            bulk: config.bulk_body_limit,
+        },
+    );
+    app = with_request_limits(app, config.request_timeout, config.max_concurrent_requests);
+    // 制限を超えたリクエストは同時実行数の枠を使わずに断る
+    if let Some(policy) = rate_limit_policy(&config) {
+        let keys = AuthKeys::new(config.jwt_secret.as_bytes());
+        app = with_rate_limit(
+            app,
+            Arc::new(RateLimiter::new(policy)),
+            keys,
+            config.trusted_proxies.clone(),
+        );
+    }
+    if config.metrics_enabled {
+        app = with_metrics(app, Metrics::new(pool)?);
+    }
+    if config.graphql_playground {
+        app = app.route(GRAPHQL_PLAYGROUND_PATH, get(graphql_playground));
+    }
+    // layerはfallbackにも適用されるため、not_foundからStaticFilesを取り出せる
+    if let Some(dir) = &config.static_dir {
+        tracing::info!("serving static files from [{}]", dir.display());
+        app = app.layer(Extension(StaticFiles::new(dir.clone())));
+    }
+    let app = app
+        .layer(Extension(TodoBatchLimit(config.todo_batch_limit)))
+        .layer(Extension(StrictRequests(config.strict_requests)))
+        .layer(Extension(SkipDuplicates(config.skip_duplicate_todos)))
+        .layer(Extension(LogBodies(config.log_bodies)))
+        .layer(Extension(config.cache_policy.clone()))
+        .layer(Extension(config.json_naming))
+        .layer(Extension(events.clone()))
+        .layer(cors_layer(config.cors_origins.clone(), config.cors_max_age));
+    let app = with_allowed_methods(app);
+
+    let listener = TcpListener::bind(config.addr())
+        .with_context(|| format!("fail bind address [{}]", config.addr()))?;
+    // 1回のシグナルでHTTPとgRPCの両方を終了させる
+    let shutdown = async move {
+        shutdown_signal().await;
+        // upgrade済みのWebSocketとgRPCのストリームは終了待ちの対象外のため、先に購読を閉じる
+        events.shutdown(WS_CLOSE_TIMEOUT).await;
+    }
+    .shared();
+    match grpc {
+        Some((grpc_listener, service)) => {
+            tokio::try_join!(
+                serve(listener, app, shutdown.clone()),
+                grpc::serve(grpc_listener, service, shutdown),
+            )?;
+            Ok(())
+        }
+        None => serve(listener, app, shutdown).await,
+    }
+}
+
+const WS_CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+// プレフィックスなしのパスを非推奨にした日時(2026-11-01T00:00:00Z)と、削除する予定の日時
+const LEGACY_DEPRECATED_AT: &str = "@1793491200";
+const LEGACY_SUNSET: &str = "Sat, 01 May 2027 00:00:00 GMT";
+
+// 呼び出し側でbindしたlistenerを受け取るため、テストではポート0で起動できる
+async fn serve(
+    listener: TcpListener,
+    app: Router,
+    shutdown: impl Future<Output = ()>,
+) -> anyhow::Result<()> {
+    tracing::debug!("listening on {}", listener.local_addr()?);
+    axum::Server::from_tcp(listener)?
+        // X-Forwarded-Forがない場合に、接続元のIPでレートリミットをかけられるようにする
+        .serve(app.into_make_service_with_connect_info::<SocketAddr, _>())
+        .with_graceful_shutdown(shutdown)
+        .await?;
+    Ok(())
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("fail install Ctrl+C handler");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("fail install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    tracing::info!("shutting down");
+}
+
+// リポジトリごとに型引数を分けてハンドラを単相化するため、引数をまとめずに受け取る
+#[allow(clippy::too_many_arguments)]
+pub fn create_app<
+    Todo: TodoRepository,
+    Label: LabelRepository,
+    User: UserRepository,
+    Comment: CommentRepository,
+    Webhook: WebhookRepository,
+    Health: HealthRepository,
+>(
+    todo_repository: Todo,
+    label_repository: Label,
+    user_repository: User,
+    comment_repository: Comment,
+    webhook_repository: Webhook,
+    health_repository: Health,
+    auth_keys: AuthKeys,
+    api_prefix: &str,
+) -> Router {
+    let routes = api_routes::<Todo, Label, User, Comment, Webhook>;
+    let schema = graphql::schema(
+        todo_repository.clone(),
+        label_repository.clone(),
+        comment_repository.clone(),
+    );
+    // ヘルスチェックとドキュメントはバージョンによらないため、プレフィックスの外に置く
+    Router::new()
+        .nest(api_prefix, routes())
+        // 従来のパスは移行期間のみ残し、レスポンスで非推奨と廃止日を知らせる
+        .merge(with_deprecation(routes()))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz::<Health>))
+        .route(OPENAPI_PATH, get(openapi_spec))
+        // スキーマでバージョンを管理するため、GraphQLもプレフィックスの外に置く
+        .route(GRAPHQL_PATH, post(execute_graphql::<Todo, Label, Comment>))
+        .route("/swagger-ui", get(swagger_ui))
+        .fallback(not_found.into_service())
+        .layer(MapResponseLayer::new(method_not_allowed))
+        .layer(BodyLogLayer)
+        .layer(HeadContentLengthLayer)
+        .layer(MutationCacheControlLayer)
+        .layer(compression_layer())
+        // panicを500に変えたレスポンスも、トレースとリクエストIDの付与の対象にする
+        .layer(ReportErrorsLayer)
+        // 後から追加したlayerほど外側になるため、採番→トレース→レスポンスへの付与の順に処理される
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(trace_layer())
+        .layer(SetRequestIdLayer::x_request_id(MakeRandomRequestId))
+        .layer(Extension(schema))
+        .layer(Extension(Arc::new(todo_repository)))
+        .layer(Extension(Arc::new(label_repository)))
+        .layer(Extension(Arc::new(user_repository)))
+        .layer(Extension(Arc::new(comment_repository)))
+        .layer(Extension(Arc::new(webhook_repository)))
+        .layer(Extension(Arc::new(health_repository)))
+        .layer(Extension(Arc::new(auth_keys)))
+        .layer(Extension(ApiPrefix(api_prefix.into())))
+}
+
+// 既存のパスと同じルートをプレフィックスの下とプレフィックスなしの両方に置くため、ルートの定義を共有する
+fn api_routes<
+    Todo: TodoRepository,
+    Label: LabelRepository,
+    User: UserRepository,
+    Comment: CommentRepository,
+    Webhook: WebhookRepository,
+>() -> Router {
+    Router::new()
+        .route(
+            "/todos",
+            post(create_todo::<Todo>)
+                .get(all_todo::<Todo>)
+                .patch(update_todos::<Todo>),
+        )
+        .route("/todos/batch", post(create_todo_batch::<Todo>))
+        .route(
+            "/todos/purge_completed",
+            post(purge_completed_todos::<Todo>),
+        )
+        .route(
+            "/todos/archive_completed",
+            post(archive_completed_todos::<Todo>),
+        )
+        .route("/todos/trash", get(trash_todos::<Todo>))
+        .route("/todos/export", get(export_todos::<Todo>))
+        .route(
+            "/todos/by-client-id/:client_id",
+            get(find_todo_by_client_id::<Todo>),
+        )
+        .route(
+            "/todos/:id",
+            get(find_todo::<Todo>)
+                .delete(delete_todo::<Todo>)
+                .put(replace_todo::<Todo>)
+                .patch(update_todo::<Todo>),
+        )
+        .route("/todos/:id/restore", post(restore_todo::<Todo>))
+        .route("/todos/:id/move", post(move_todo::<Todo>))
+        .route("/todos/:id/duplicate", post(duplicate_todo::<Todo>))
+        .route("/todos/:id/archive", post(archive_todo::<Todo>))
+        .route("/todos/:id/unarchive", post(unarchive_todo::<Todo>))
+        .route("/todos/:id/activity", get(todo_activity::<Todo>))
+        .route("/todos/:id/items", post(create_todo_item::<Todo>))
+        .route(
+            "/todos/:id/items/:item_id",
+            delete(delete_todo_item::<Todo>).patch(update_todo_item::<Todo>),
+        )
+        .route(
+            "/todos/:id/comments",
+            post(create_comment::<Todo, Comment>).get(all_comments::<Todo, Comment>),
+        )
+        .route(
+            "/todos/:id/comments/:comment_id",
+            delete(delete_comment::<Todo, Comment>),
+        )
+        .route(
+            "/todos/:id/labels/:label_id",
+            post(attach_todo_label::<Todo>).delete(detach_todo_label::<Todo>),
+        )
+        .route(
+            "/labels",
+            post(create_label::<Label>).get(all_label::<Label>),
+        )
+        .route(
+            "/labels/:id",
+            delete(delete_label::<Label>).patch(update_label::<Label>),
+        )
+        .route("/labels/:id/merge", post(merge_label::<Label>))
+        .route("/stats", get(todo_stats::<Todo>))
+        .route("/export", get(export_backup::<Todo>))
+        .route("/import", post(import_backup::<Todo>))
+        .route("/import/csv", post(import_csv::<Todo>))
+        .route("/auth/register", post(register::<User>))
+        .route("/auth/login", post(login::<User>))
+        .route("/users/:id", get(find_user::<User>))
+        .route(
+            "/webhooks",
+            post(create_webhook::<Webhook>).get(all_webhooks::<Webhook>),
+        )
+        .route(
+            "/webhooks/:id",
+            get(find_webhook::<Webhook>)
+                .patch(update_webhook::<Webhook>)
+                .delete(delete_webhook::<Webhook>),
+        )
+        .route("/ws", get(sync_todos::<Todo>))
+        // GraphQLは独自にcamelCaseで返すため、RESTのルートのみ対象にする
+        .layer(NamingLayer)
+}
+
+// 全ルートのリクエスト数とレイテンシを記録し、/metricsで公開する
+fn with_metrics(app: Router, metrics_registry: Metrics) -> Router {
+    let metrics_registry = Arc::new(metrics_registry);
+    app.route("/metrics", get(metrics))
+        .layer(MetricsLayer::new(metrics_registry.clone()))
+        .layer(Extension(metrics_registry))
+}
+
+// デモ・E2Eテストの前にデータを初期化する。create_appのExtensionは後から追加したルートに届かないため、ここで渡す
+fn with_admin<Todo: TodoRepository>(
+    app: Router,
+    todo_repository: Todo,
+    token: AdminToken,
+) -> Router {
+    app.route("/admin/reset", post(reset_data::<Todo>))
+        .route("/admin/seed", post(seed_data::<Todo>))
+        .layer(Extension(Arc::new(todo_repository)))
+        .layer(Extension(token))
+}
+
+// Accept-Encodingに応じてgzipかbrotliで圧縮する。ストリーミングのexportは圧縮しながら送る
+// SSEは圧縮するとイベントがバッファされて届かなくなるため対象外にする
+fn compression_layer() -> CompressionLayer<impl Predicate> {
+    CompressionLayer::new()
+        .no_deflate()
+        .compress_when(DefaultPredicate::new().and(NotForContentType::const_new("text/event-stream")))
+}
+
+// RFC 9745のDeprecation(非推奨になった日時)とRFC 8594のSunset(廃止する日時)を付ける
+fn with_deprecation(app: Router) -> Router {
+    app.layer(SetResponseHeaderLayer::overriding(
+        HeaderName::from_static("deprecation"),
+        HeaderValue::from_static(LEGACY_DEPRECATED_AT),
+    ))
+    .layer(SetResponseHeaderLayer::overriding(
+        HeaderName::from_static("sunset"),
+        HeaderValue::from_static(LEGACY_SUNSET),
+    ))
+}
+
+// Cookieを送れるようにするため、Access-Control-Allow-Originには*ではなく一致したオリジンを返す
+fn cors_layer(origins: Vec<HeaderValue>, max_age: Duration) -> CorsLayer {
+    CorsLayer::new()
+        .allow_origin(Origin::list(origins))
+        .allow_credentials(true)
+        .max_age(max_age)
+        // ルーティングしているメソッドのみ。それ以外のメソッドのpreflightには許可を返さない
+        .allow_methods(vec![
+            Method::GET,
+            Method::HEAD,
+            Method::POST,
+            Method::PUT,
+            Method::PATCH,
+            Method::DELETE,
+        ])
+        .allow_headers(vec![
+            CONTENT_TYPE,
+            AUTHORIZATION,
+            IF_NONE_MATCH,
+            IF_MODIFIED_SINCE,
+            IF_MATCH,
+            IF_UNMODIFIED_SINCE,
+            HeaderName::from_static(IDEMPOTENCY_KEY_HEADER),
+            HeaderName::from_static(ADMIN_TOKEN_HEADER),
+            HeaderName::from_static(NAMING_HEADER),
+        ])
+        .expose_headers(vec![
+            HeaderName::from_static(TOTAL_COUNT_HEADER),
+            HeaderName::from_static(REQUEST_ID_HEADER),
+            HeaderName::from_static(IDEMPOTENT_REPLAYED_HEADER),
+            HeaderName::from_static(DUPLICATE_OF_HEADER),
+            ETAG,
+        ])
+}
+
+#[cfg(test)]
+mod test {
+    use axum::{
+        body::Body,
+        http::{header, Method, Request},
+    };
+    use axum::http::StatusCode;
+    use axum::response::Response;
+    use tower::ServiceExt;
+
+    use crate::repositories::label::{CreateLabel, Label};
+    use crate::config::DEFAULT_API_PREFIX;
+    use crate::clock::test_utils::MockClock;
+    use crate::clock::Clock;
+    use crate::error_reporting::install_panic_hook;
+    use crate::error_reporting::test_utils::RecordingReporter;
+    use crate::handlers::todo::TODO_FIELDS;
+    use crate::handlers::{http_date, MERGE_PATCH_CONTENT_TYPE};
+    use crate::naming::Naming;
+    use crate::auth::test_utils::{test_keys, test_token};
+    use crate::cache_control::CachePolicy;
+    use crate::repositories::health::test_utils::HealthRepositoryForUnavailable;
+    use crate::repositories::test_utils::fixtures::{scenario, TodoFixture};
+    use crate::repositories::test_utils::{FailingLabelRepository, FailingTodoRepository};
+    use crate::repositories::todo::{CreateTodo, Precondition, Priority, TodoEntity, TodoListQuery};
+    use crate::repositories::todo_item::CreateTodoItem;
+
+    use super::*;
+
+    fn build_req_with_json(path: &str, method: Method, json_body: String) -> Request<Body> {
+        Request::builder()
+            .uri(path)
+            .method(method)
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .header(header::AUTHORIZATION, format!("Bearer {}", test_token(1)))
+            .body(Body::from(json_body))
+            .unwrap()
+    }
+
+    fn build_todo_req_with_empty(method: Method, path: &str) -> Request<Body> {
+        Request::builder()
+            .uri(path)
+            .method(method)
+            .header(header::AUTHORIZATION, format!("Bearer {}", test_token(1)))
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    async fn res_to_todo(res: Response) -> TodoEntity {
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: String = String::from_utf8(bytes.to_vec()).unwrap();
+        let todo: TodoEntity = serde_json::from_str(&body)
+            .unwrap_or_else(|_| panic!("cannot convert Todo instance. body: {}", body));
+        todo
+    }
+
+    async fn res_to_label(res: Response) -> Label {
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: String = String::from_utf8(bytes.to_vec()).unwrap();
+        let label: Label = serde_json::from_str(&body)
+            .unwrap_or_else(|_| panic!("cannot convert Label instance. body: {}", body));
+        label
+    }
+
+    fn label_fixture() -> (Vec<Label>, Vec<i32>) {
+        let id = 999;
+        (
+            vec![Label::new(id, String::from("test label"))],
+            vec![id],
+        )
+    }
+
+    #[tokio::test]
+    async fn should_created_todo() {
+        let (labels, _label_ids) = label_fixture();
+        let expected = TodoEntity::new(1, "should_return_created_todo".to_string(), labels.clone());
+
+        let req = build_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "should_return_created_todo", "labels": [999] }"#.to_string(),
+        );
+        let res = create_app(
+            TodoRepositoryForMemory::new(labels),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        let todo = res_to_todo(res).await;
+        assert_eq!(expected, todo.without_timestamps());
+    }
+
+    #[tokio::test]
+    async fn should_reject_todo_with_unknown_label() {
+        let (labels, label_ids) = label_fixture();
+        let todo_repository = TodoRepositoryForMemory::new(labels.clone());
+        let app = create_app(
+            todo_repository.clone(),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+
+        let req = build_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "unknown label", "labels": [999, 1000] }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+        let body = res_to_error(res).await;
+        assert_eq!("labels", body["error"]["fields"][0]["field"]);
+        assert_eq!("Label not found, id is 1000", body["error"]["fields"][0]["message"]);
+        let page = todo_repository.all(1, Default::default()).await.unwrap();
+        assert_eq!(0, page.total);
+
+        let todo = todo_repository
+            .create(1, CreateTodo::new("known label".to_string(), label_ids))
+            .await
+            .expect("failed create todo");
+        let req = build_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            r#"{ "labels": [1000] }"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+        assert_eq!(todo, todo_repository.find(1, todo.id).await.unwrap());
+    }
+
+    async fn post_todo_expect_validation_error(json_body: String) -> serde_json::Value {
+        let req = build_req_with_json("/todos", Method::POST, json_body);
+        let res = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+        res_to_error(res).await
+    }
+
+    #[tokio::test]
+    async fn should_reject_empty_todo_text() {
+        let body =
+            post_todo_expect_validation_error(r#"{ "text": "", "labels": [] }"#.to_string()).await;
+        assert_eq!("validation_error", body["error"]["code"]);
+        assert_eq!(
+            serde_json::json!([{ "field": "text", "message": "Can not be empty" }]),
+            body["error"]["fields"]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_reject_whitespace_only_todo_text() {
+        let body =
+            post_todo_expect_validation_error(r#"{ "text": "  \t ", "labels": [] }"#.to_string())
+                .await;
+        assert_eq!(
+            serde_json::json!([{ "field": "text", "message": "Can not be empty" }]),
+            body["error"]["fields"]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_reject_invisible_only_todo_text() {
+        let json_body =
+            serde_json::json!({ "text": "\u{200B}\r\n\u{FEFF}", "labels": [] }).to_string();
+        let body = post_todo_expect_validation_error(json_body).await;
+        assert_eq!(
+            serde_json::json!([{ "field": "text", "message": "Can not be empty" }]),
+            body["error"]["fields"]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_save_normalized_todo_text() {
+        let app = memory_app();
+        let json_body =
+            serde_json::json!({ "text": "  buy\t\tmilk \u{1F95B}\r\n", "labels": [] }).to_string();
+        let res = app
+            .clone()
+            .oneshot(build_req_with_json("/todos", Method::POST, json_body))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        assert_eq!("buy milk \u{1F95B}", res_to_todo(res).await.text);
+
+        let json_body = serde_json::json!({ "text": "cafe\u{301}\n" }).to_string();
+        let res = app
+            .oneshot(build_req_with_json("/todos/1", Method::PATCH, json_body))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        assert_eq!("caf\u{E9}", res_to_todo(res).await.text);
+    }
+
+    #[tokio::test]
+    async fn should_return_open_duplicate_when_skip_duplicates() {
+        let app = memory_app();
+        let post = |path: &str, text: &str| {
+            let json_body = serde_json::json!({ "text": text, "labels": [] }).to_string();
+            build_req_with_json(path, Method::POST, json_body)
+        };
+        let res = app
+            .clone()
+            .oneshot(post("/todos", "pay rent"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let first = res_to_todo(res).await;
+
+        // 正規化した後のテキストで比べる
+        let res = app
+            .clone()
+            .oneshot(post("/todos?skip_duplicates=true", " pay\trent\n"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(first.id.to_string(), res.headers()[DUPLICATE_OF_HEADER]);
+        assert_eq!(first, res_to_todo(res).await);
+
+        // 完了済みのTodoは重複として扱わない
+        let json_body = serde_json::json!({ "completed": true }).to_string();
+        let path = format!("/todos/{}", first.id);
+        let res = app
+            .clone()
+            .oneshot(build_req_with_json(&path, Method::PATCH, json_body))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let res = app
+            .oneshot(post("/todos?skip_duplicates=true", "pay rent"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        assert!(res.headers().get(DUPLICATE_OF_HEADER).is_none());
+        assert_ne!(first.id, res_to_todo(res).await.id);
+    }
+
+    #[tokio::test]
+    async fn should_return_existing_todo_for_used_client_id() {
+        let app = memory_app();
+        let client_id = "6f1c2a9e-3b4d-4e5f-8a7b-9c0d1e2f3a4b";
+        let post = |text: &str| {
+            let json_body =
+                serde_json::json!({ "text": text, "labels": [], "client_id": client_id })
+                    .to_string();
+            build_req_with_json("/todos", Method::POST, json_body)
+        };
+        let res = app.clone().oneshot(post("offline")).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let first = res_to_todo(res).await;
+        assert_eq!(client_id, first.client_id.unwrap().to_string());
+
+        // 同期の再送では本文が変わっていても作成せず、作成済みのTodoを返す
+        let res = app.clone().oneshot(post("offline, edited")).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert!(res.headers().get(DUPLICATE_OF_HEADER).is_none());
+        assert_eq!(first, res_to_todo(res).await);
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!("1", res.headers()[TOTAL_COUNT_HEADER]);
+
+        let path = format!("/todos/by-client-id/{}", client_id);
+        let req = build_todo_req_with_empty(Method::GET, &path);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(first.id, res_to_json(res).await["id"]);
+
+        let req = build_todo_req_with_empty(
+            Method::GET,
+            "/todos/by-client-id/00000000-0000-0000-0000-000000000000",
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let req = build_todo_req_with_empty(Method::GET, "/todos/by-client-id/not-a-uuid");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        assert_eq!("bad_request", res_to_error(res).await["error"]["code"]);
+    }
+
+    #[tokio::test]
+    async fn should_create_duplicate_unless_skip_duplicates() {
+        let post = |path: &str| {
+            let json_body = serde_json::json!({ "text": "pay rent", "labels": [] }).to_string();
+            build_req_with_json(path, Method::POST, json_body)
+        };
+        // 設定を省略した場合は従来どおり作成する
+        let app = memory_app();
+        for _ in 0..2 {
+            let res = app.clone().oneshot(post("/todos")).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+
+        // 設定で有効にしても、?skip_duplicates=falseで明示的に作成できる
+        let app = memory_app().layer(Extension(SkipDuplicates(true)));
+        let res = app.clone().oneshot(post("/todos")).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let first = res_to_todo(res).await;
+        let res = app.clone().oneshot(post("/todos")).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(first.id.to_string(), res.headers()[DUPLICATE_OF_HEADER]);
+        let res = app
+            .oneshot(post("/todos?skip_duplicates=false"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        assert_ne!(first.id, res_to_todo(res).await.id);
+    }
+
+    #[tokio::test]
+    async fn should_not_mount_admin_routes_without_token() {
+        let req = build_req_with_json("/admin/reset", Method::POST, "".to_string());
+        let res = memory_app().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_reset_and_seed_with_admin_token() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        let app = with_admin(
+            create_app(
+                repository.clone(),
+                LabelRepositoryForMemory::new(),
+                UserRepositoryForMemory::new(),
+                CommentRepositoryForMemory::new(),
+                WebhookRepositoryForMemory::new(),
+                HealthRepositoryForMemory::new(),
+                test_keys(),
+                DEFAULT_API_PREFIX,
+            ),
+            repository,
+            AdminToken("secret".to_string()),
+        );
+        let seed = |token: Option<&str>| {
+            let req = build_req_with_json(
+                "/admin/seed",
+                Method::POST,
+                serde_json::json!({ "user_id": 1 }).to_string(),
+            );
+            match token {
+                Some(token) => with_header(req, HeaderName::from_static(ADMIN_TOKEN_HEADER), token),
+                None => req,
+            }
+        };
+
+        for (token, message) in [
+            (None, "Missing X-Admin-Token header"),
+            (Some("secreT"), "Invalid admin token"),
+        ] {
+            let res = app.clone().oneshot(seed(token)).await.unwrap();
+            assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+            assert_eq!(message, res_to_error(res).await["error"]["message"]);
+        }
+
+        let json_body = serde_json::json!({ "text": "before seed", "labels": [] }).to_string();
+        let req = build_req_with_json("/todos", Method::POST, json_body);
+        assert_eq!(
+            StatusCode::CREATED,
+            app.clone().oneshot(req).await.unwrap().status()
+        );
+
+        // 何度seedしても同じidで同じデータになる
+        for _ in 0..2 {
+            let res = app.clone().oneshot(seed(Some("secret"))).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+            let summary = res_to_json(res).await;
+            assert_eq!(5, summary["todos"]);
+            assert_eq!(3, summary["labels"]);
+
+            let req = build_todo_req_with_empty(Method::GET, "/todos/1");
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            assert_eq!("write the quarterly report", res_to_todo(res).await.text);
+            let req = build_todo_req_with_empty(Method::GET, "/todos/6");
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::NOT_FOUND, res.status());
+        }
+
+        let req = with_header(
+            build_req_with_json("/admin/reset", Method::POST, "".to_string()),
+            HeaderName::from_static(ADMIN_TOKEN_HEADER),
+            "secret",
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!("0", res.headers()[TOTAL_COUNT_HEADER]);
+    }
+
+    #[tokio::test]
+    async fn should_reject_overlong_todo_text() {
+        let json_body = serde_json::json!({ "text": "a".repeat(101), "labels": [] }).to_string();
+        let body = post_todo_expect_validation_error(json_body).await;
+        assert_eq!(
+            serde_json::json!([{ "field": "text", "message": "Over text length" }]),
+            body["error"]["fields"]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_reject_whitespace_only_update_text() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        todo_repository
+            .create(1, CreateTodo::new("before_update_todo".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        let req = build_req_with_json("/todos/1", Method::PATCH, r#"{"text": "   "}"#.to_string());
+        let res = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+        let body = res_to_error(res).await;
+        assert_eq!("text", body["error"]["fields"][0]["field"]);
+    }
+
+    #[tokio::test]
+    async fn should_reject_truncated_json() {
+        let req = build_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "truncated", "lab"#.to_string(),
+        );
+        let res = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        let body = res_to_error(res).await;
+        assert_eq!("invalid_json", body["error"]["code"]);
+    }
+
+    #[tokio::test]
+    async fn should_reject_wrong_field_type() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        todo_repository
+            .create(1, CreateTodo::new("wrong type".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        let req = build_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            r#"{"completed": "yes"}"#.to_string(),
+        );
+        let res = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        let body = res_to_error(res).await;
+        assert_eq!("invalid_json", body["error"]["code"]);
+        assert_eq!("completed", body["error"]["fields"][0]["field"]);
+    }
+
+    #[tokio::test]
+    async fn should_reject_missing_content_type() {
+        let req = Request::builder()
+            .uri("/labels")
+            .method(Method::POST)
+            .header(header::AUTHORIZATION, format!("Bearer {}", test_token(1)))
+            .body(Body::from(r#"{ "name": "no content type" }"#))
+            .unwrap();
+        let res = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::UNSUPPORTED_MEDIA_TYPE, res.status());
+        let body = res_to_error(res).await;
+        assert_eq!("unsupported_media_type", body["error"]["code"]);
+    }
+
+    #[tokio::test]
+    async fn should_create_todo_batch_in_order() {
+        let (labels, _label_ids) = label_fixture();
+        let req = build_req_with_json(
+            "/todos/batch",
+            Method::POST,
+            r#"{ "todos": [
+                { "text": "first", "labels": [999] },
+                { "text": "second", "labels": [] }
+            ] }"#
+                .to_string(),
+        );
+        let res = create_app(
+            TodoRepositoryForMemory::new(labels.clone()),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todos: Vec<TodoEntity> = serde_json::from_slice(&bytes).unwrap();
+        let todos: Vec<TodoEntity> = todos
+            .into_iter()
+            .map(TodoEntity::without_timestamps)
+            .collect();
+        assert_eq!(
+            vec![
+                TodoEntity::new(1, "first".to_string(), labels),
+                TodoEntity::new(2, "second".to_string(), vec![]),
+            ],
+            todos
+        );
+    }
+
+    fn with_idempotency_key(mut req: Request<Body>, key: &str) -> Request<Body> {
+        req.headers_mut()
+            .insert(IDEMPOTENCY_KEY_HEADER, key.parse().unwrap());
+        req
+    }
+
+    #[tokio::test]
+    async fn should_replay_todo_created_with_same_idempotency_key() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        let app = failing_app(
+            FailingTodoRepository::new(repository.clone()),
+            FailingLabelRepository::new(LabelRepositoryForMemory::new()),
+        );
+        let body = r#"{ "text": "once", "labels": [] }"#;
+
+        let req = with_idempotency_key(
+            build_req_with_json("/todos", Method::POST, body.to_string()),
+            "key-1",
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        assert!(res.headers().get(IDEMPOTENT_REPLAYED_HEADER).is_none());
+        let created = res_to_todo(res).await;
+
+        let req = with_idempotency_key(
+            build_req_with_json("/todos", Method::POST, body.to_string()),
+            "key-1",
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        assert_eq!("true", res.headers()[IDEMPOTENT_REPLAYED_HEADER]);
+        assert_eq!(created, res_to_todo(res).await);
+        assert_eq!(
+            1,
+            repository
+                .all(1, TodoListQuery::default())
+                .await
+                .unwrap()
+                .total
+        );
+
+        // 同じキーを別の内容に使い回すと、作成せずに拒否する
+        let req = with_idempotency_key(
+            build_req_with_json(
+                "/todos",
+                Method::POST,
+                r#"{ "text": "other", "labels": [] }"#.to_string(),
+            ),
+            "key-1",
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+        assert_eq!(
+            "idempotency_key_reused",
+            res_to_error(res).await["error"]["code"]
+        );
+
+        // キーがなければ、同じ内容でも毎回作成する
+        let req = build_req_with_json("/todos", Method::POST, body.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        assert_eq!(
+            2,
+            repository
+                .all(1, TodoListQuery::default())
+                .await
+                .unwrap()
+                .total
+        );
+    }
+
+    #[tokio::test]
+    async fn should_replay_todo_batch_with_same_idempotency_key() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        let app = failing_app(
+            FailingTodoRepository::new(repository.clone()),
+            FailingLabelRepository::new(LabelRepositoryForMemory::new()),
+        );
+        let body = r#"{ "todos": [
+            { "text": "first", "labels": [] },
+            { "text": "second", "labels": [] }
+        ] }"#;
+
+        let mut responses = vec![];
+        for _ in 0..2 {
+            let req = with_idempotency_key(
+                build_req_with_json("/todos/batch", Method::POST, body.to_string()),
+                "batch-1",
+            );
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            responses.push(serde_json::from_slice::<serde_json::Value>(&bytes).unwrap());
+        }
+        assert_eq!(responses[0], responses[1]);
+        assert_eq!(
+            2,
+            repository
+                .all(1, TodoListQuery::default())
+                .await
+                .unwrap()
+                .total
+        );
+
+        // エンドポイントが異なれば、同じキーでも別のリクエストとして扱う
+        let req = with_idempotency_key(
+            build_req_with_json(
+                "/todos",
+                Method::POST,
+                r#"{ "text": "first", "labels": [] }"#.to_string(),
+            ),
+            "batch-1",
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+        assert_eq!(
+            "idempotency_key_reused",
+            res_to_error(res).await["error"]["code"]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_release_idempotency_key_when_create_fails() {
+        let repository = FailingTodoRepository::new(TodoRepositoryForMemory::new(vec![]));
+        let app = failing_app(
+            repository.clone().fail("create_idempotent"),
+            FailingLabelRepository::new(LabelRepositoryForMemory::new()),
+        );
+        let body = r#"{ "text": "retry", "labels": [] }"#;
+
+        let req = with_idempotency_key(
+            build_req_with_json("/todos", Method::POST, body.to_string()),
+            "retry-1",
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, res.status());
+
+        // 失敗した作成は記録しないため、同じキーで再送できる
+        repository.recover("create_idempotent");
+        assert_eq!(1, repository.calls("release_idempotency_key"));
+        let req = with_idempotency_key(
+            build_req_with_json("/todos", Method::POST, body.to_string()),
+            "retry-1",
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        assert!(res.headers().get(IDEMPOTENT_REPLAYED_HEADER).is_none());
+    }
+
+    #[tokio::test]
+    async fn should_reject_retry_while_idempotent_create_in_progress() {
+        let app = failing_app(
+            FailingTodoRepository::new(TodoRepositoryForMemory::new(vec![]))
+                .delay("create_idempotent", Duration::from_millis(200)),
+            FailingLabelRepository::new(LabelRepositoryForMemory::new()),
+        );
+        let request = || {
+            with_idempotency_key(
+                build_req_with_json(
+                    "/todos",
+                    Method::POST,
+                    r#"{ "text": "slow", "labels": [] }"#.to_string(),
+                ),
+                "slow-1",
+            )
+        };
+
+        let first = tokio::spawn(app.clone().oneshot(request()));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let res = app.clone().oneshot(request()).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+        assert_eq!(
+            "idempotency_key_in_progress",
+            res_to_error(res).await["error"]["code"]
+        );
+        assert_eq!(StatusCode::CREATED, first.await.unwrap().unwrap().status());
+    }
+
+    #[tokio::test]
+    async fn should_reject_malformed_idempotency_key() {
+        let app = failing_app(
+            FailingTodoRepository::new(TodoRepositoryForMemory::new(vec![])),
+            FailingLabelRepository::new(LabelRepositoryForMemory::new()),
+        );
+        let too_long = "k".repeat(256);
+        for key in ["", "has space", too_long.as_str()] {
+            let req = with_idempotency_key(
+                build_req_with_json(
+                    "/todos",
+                    Method::POST,
+                    r#"{ "text": "x", "labels": [] }"#.to_string(),
+                ),
+                key,
+            );
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        }
+    }
+
+    #[tokio::test]
+    async fn should_reject_invalid_todo_batch() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .layer(Extension(TodoBatchLimit(2)));
+
+        let cases = [
+            (r#"{ "todos": [] }"#, "todos", "Can not be empty"),
+            (
+                r#"{ "todos": [{ "text": "ok", "labels": [] }, { "text": " ", "labels": [] }] }"#,
+                "todos[1].text",
+                "Can not be empty",
+            ),
+            (
+                r#"{ "todos": [
+                    { "text": "1", "labels": [] },
+                    { "text": "2", "labels": [] },
+                    { "text": "3", "labels": [] }
+                ] }"#,
+                "todos",
+                "Over max batch size 2",
+            ),
+        ];
+        for (json_body, field, message) in cases {
+            let req = build_req_with_json("/todos/batch", Method::POST, json_body.to_string());
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+            let body = res_to_error(res).await;
+            assert_eq!(
+                serde_json::json!([{ "field": field, "message": message }]),
+                body["error"]["fields"]
+            );
+        }
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!("0", res.headers()[TOTAL_COUNT_HEADER]);
+    }
+
+    fn memory_app() -> Router {
+        create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+    }
+
+    #[tokio::test]
+    async fn should_reject_unknown_fields_in_strict_mode() {
+        let app = memory_app();
+        let req = build_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "original", "labels": [] }"#.to_string(),
+        );
+        assert_eq!(
+            StatusCode::CREATED,
+            app.clone().oneshot(req).await.unwrap().status()
+        );
+
+        let cases = [
+            ("/todos/1", Method::PATCH, r#"{ "txt": "typo" }"#, "txt"),
+            (
+                "/todos",
+                Method::POST,
+                r#"{ "text": "a", "labels": [], "done": true }"#,
+                "done",
+            ),
+            (
+                "/todos/batch",
+                Method::POST,
+                r#"{ "todos": [{ "text": "a", "labels": [], "label": 1 }] }"#,
+                "todos[0].label",
+            ),
+            (
+                "/labels",
+                Method::POST,
+                r#"{ "name": "work", "colour": "red" }"#,
+                "colour",
+            ),
+            (
+                "/auth/register",
+                Method::POST,
+                r#"{ "username": "alice", "password": "password", "email": "a@example.com" }"#,
+                "email",
+            ),
+        ];
+        for (path, method, json_body, field) in cases {
+            let req = build_req_with_json(path, method, json_body.to_string());
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status(), "{}", path);
+            let body = res_to_error(res).await;
+            assert_eq!("unknown_field", body["error"]["code"]);
+            assert_eq!(field, body["error"]["fields"][0]["field"]);
+            // 受け付けるフィールドの一覧を返す
+            let message = body["error"]["fields"][0]["message"].as_str().unwrap();
+            assert!(message.contains(", expected "), "{}", message);
+        }
+
+        let res = app
+            .oneshot(build_todo_req_with_empty(Method::GET, "/todos/1"))
+            .await
+            .unwrap();
+        assert_eq!("original", res_to_todo(res).await.text);
+    }
+
+    #[tokio::test]
+    async fn should_ignore_unknown_fields_unless_strict() {
+        let app = memory_app().layer(Extension(StrictRequests(false)));
+        let req = build_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "original", "labels": [], "done": true }"#.to_string(),
+        );
+        assert_eq!(
+            StatusCode::CREATED,
+            app.clone().oneshot(req).await.unwrap().status()
+        );
+
+        let req = build_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            r#"{ "txt": "typo", "text": "fixed" }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        assert_eq!("fixed", res_to_todo(res).await.text);
+
+        let req = build_req_with_json(
+            "/todos/batch",
+            Method::POST,
+            r#"{ "todos": [{ "text": "a", "labels": [], "label": 1 }] }"#.to_string(),
+        );
+        assert_eq!(
+            StatusCode::CREATED,
+            app.clone().oneshot(req).await.unwrap().status()
+        );
+
+        // 未知のフィールド以外の不備は、従来どおり拒否する
+        let req = build_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            r#"{ "txt": "typo", "completed": "yes" }"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        assert_eq!(
+            "completed",
+            res_to_error(res).await["error"]["fields"][0]["field"]
+        );
+    }
+
+    fn build_conditional_req(path: &str, etag: &HeaderValue) -> Request<Body> {
+        let mut req = build_todo_req_with_empty(Method::GET, path);
+        req.headers_mut().insert(header::IF_NONE_MATCH, etag.clone());
+        req
+    }
+
+    #[tokio::test]
+    async fn should_return_not_modified_for_matching_etag() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        todo_repository
+            .create(1, CreateTodo::new("first".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        let app = create_app(
+            todo_repository.clone(),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+
+        for path in ["/todos", "/todos/1"] {
+            let req = build_todo_req_with_empty(Method::GET, path);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            let etag = res.headers()[header::ETAG].clone();
+            assert!(etag.to_str().unwrap().starts_with("W/"));
+
+            let res = app
+                .clone()
+                .oneshot(build_conditional_req(path, &etag))
+                .await
+                .unwrap();
+            assert_eq!(StatusCode::NOT_MODIFIED, res.status());
+            assert_eq!(etag, res.headers()[header::ETAG]);
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            assert!(bytes.is_empty());
+
+            // 更新後は同じETagでも本文を返す
+            let req = build_req_with_json(
+                "/todos/1",
+                Method::PATCH,
+                r#"{ "completed": true }"#.to_string(),
+            );
+            app.clone().oneshot(req).await.unwrap();
+            let res = app
+                .clone()
+                .oneshot(build_conditional_req(path, &etag))
+                .await
+                .unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            assert_ne!(etag, res.headers()[header::ETAG]);
+        }
+
+        // 一覧は作成・削除でも変わる
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let etag = res.headers()[header::ETAG].clone();
+        todo_repository
+            .create(1, CreateTodo::new("second".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        let res = app
+            .clone()
+            .oneshot(build_conditional_req("/todos", &etag))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let etag = res.headers()[header::ETAG].clone();
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/2");
+        app.clone().oneshot(req).await.unwrap();
+        let res = app
+            .oneshot(build_conditional_req("/todos", &etag))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+    }
+
+    fn with_header(mut req: Request<Body>, name: header::HeaderName, value: &str) -> Request<Body> {
+        req.headers_mut().insert(name, value.parse().unwrap());
+        req
+    }
+
+    #[tokio::test]
+    async fn should_update_and_delete_only_with_matching_if_match() {
+        let app = failing_app(
+            FailingTodoRepository::new(TodoRepositoryForMemory::new(vec![])),
+            FailingLabelRepository::new(LabelRepositoryForMemory::new()),
+        );
+        let req = build_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "original", "labels": [] }"#.to_string(),
+        );
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        let res = app
+            .clone()
+            .oneshot(build_todo_req_with_empty(Method::GET, "/todos/1"))
+            .await
+            .unwrap();
+        let etag = res.headers()[header::ETAG].to_str().unwrap().to_string();
+        assert!(etag.starts_with(&format!("W/\"{}-", todo.version)));
+
+        let patch = |text: &str| {
+            build_req_with_json(
+                "/todos/1",
+                Method::PATCH,
+                format!(r#"{{ "text": "{}" }}"#, text),
+            )
+        };
+        let res = app
+            .clone()
+            .oneshot(with_header(patch("first"), header::IF_MATCH, &etag))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+
+        // 同じETagは更新後のTodoと一致しないため、上書きせずに最新のTodoを返す
+        let res = app
+            .clone()
+            .oneshot(with_header(patch("second"), header::IF_MATCH, &etag))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::PRECONDITION_FAILED, res.status());
+        let body = res_to_error(res).await;
+        assert_eq!("precondition_failed", body["error"]["code"]);
+        assert_eq!("first", body["current"]["text"]);
+
+        let delete = build_todo_req_with_empty(Method::DELETE, "/todos/1");
+        let res = app
+            .clone()
+            .oneshot(with_header(delete, header::IF_MATCH, &etag))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::PRECONDITION_FAILED, res.status());
+        assert_eq!("first", res_to_error(res).await["current"]["text"]);
+
+        let res = app
+            .clone()
+            .oneshot(build_todo_req_with_empty(Method::GET, "/todos/1"))
+            .await
+            .unwrap();
+        let etag = res.headers()[header::ETAG].to_str().unwrap().to_string();
+        let delete = build_todo_req_with_empty(Method::DELETE, "/todos/1");
+        let res = app
+            .clone()
+            .oneshot(with_header(delete, header::IF_MATCH, &etag))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_update_and_delete_only_when_unmodified_since() {
+        let app = failing_app(
+            FailingTodoRepository::new(TodoRepositoryForMemory::new(vec![])),
+            FailingLabelRepository::new(LabelRepositoryForMemory::new()),
+        );
+        let req = build_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "original", "labels": [] }"#.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+        let past = "Sun, 06 Nov 1994 08:49:37 GMT";
+        let future = "Fri, 01 Jan 2100 00:00:00 GMT";
+
+        let patch = || {
+            build_req_with_json(
+                "/todos/1",
+                Method::PATCH,
+                r#"{ "text": "updated" }"#.to_string(),
+            )
+        };
+        let res = app
+            .clone()
+            .oneshot(with_header(patch(), header::IF_UNMODIFIED_SINCE, past))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::PRECONDITION_FAILED, res.status());
+        assert_eq!("original", res_to_error(res).await["current"]["text"]);
+        let res = app
+            .clone()
+            .oneshot(with_header(patch(), header::IF_UNMODIFIED_SINCE, future))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+
+        let delete = || build_todo_req_with_empty(Method::DELETE, "/todos/1");
+        let res = app
+            .clone()
+            .oneshot(with_header(delete(), header::IF_UNMODIFIED_SINCE, past))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::PRECONDITION_FAILED, res.status());
+        assert_eq!("updated", res_to_error(res).await["current"]["text"]);
+        // 日時として読めない値は無視する
+        let res = app
+            .clone()
+            .oneshot(with_header(
+                delete(),
+                header::IF_UNMODIFIED_SINCE,
+                "yesterday",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_reject_unsupported_if_match() {
+        let app = failing_app(
+            FailingTodoRepository::new(TodoRepositoryForMemory::new(vec![])),
+            FailingLabelRepository::new(LabelRepositoryForMemory::new()),
+        );
+        let req = build_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "original", "labels": [] }"#.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+
+        // 一覧のETagなど、versionを含まないETag
+        let req = build_req_with_json("/todos/1", Method::PATCH, r#"{ "text": "x" }"#.to_string());
+        let res = app
+            .clone()
+            .oneshot(with_header(
+                req,
+                header::IF_MATCH,
+                r#"W/"00000000000000ab""#,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1?permanent=true");
+        let res = app
+            .clone()
+            .oneshot(with_header(
+                req,
+                header::IF_MATCH,
+                r#"W/"1-00000000000000ab""#,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_find_todo() {
+        let (labels, label_ids) = label_fixture();
+        let expected = TodoEntity::new(1, "should_find_todo".to_string(), labels.clone());
+
+        let todo_repository = TodoRepositoryForMemory::new(labels.clone());
+        todo_repository
+            .create(1, CreateTodo::new("should_find_todo".to_string(), label_ids))
+            .await
+            .expect("failed create todo");
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1");
+        let res = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        let todo = res_to_todo(res).await;
+        assert_eq!(expected, todo.without_timestamps());
+    }
+
+    async fn res_to_json(res: Response) -> serde_json::Value {
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        serde_json::from_slice(&bytes).unwrap()
+    }
+
+    async fn todo_ids(app: &Router) -> Vec<i64> {
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todos: Vec<TodoEntity> = serde_json::from_slice(&bytes).unwrap();
+        todos.iter().map(|todo| todo.id).collect()
+    }
+
+    #[tokio::test]
+    async fn should_move_todos() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        for text in ["first", "second", "third", "fourth"] {
+            todo_repository
+                .create(1, CreateTodo::new(text.to_string(), vec![]))
+                .await
+                .expect("failed create todo");
+        }
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+        let move_todo = |id: i32, body: &'static str| {
+            let app = app.clone();
+            async move {
+                let req = build_req_with_json(
+                    &format!("/todos/{}/move", id),
+                    Method::POST,
+                    body.to_string(),
+                );
+                app.oneshot(req).await.unwrap()
+            }
+        };
+
+        // 新しいTodoが先頭になる
+        assert_eq!(vec![4, 3, 2, 1], todo_ids(&app).await);
+
+        let res = move_todo(1, r#"{ "after_id": 4 }"#).await;
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(1, res_to_todo(res).await.id);
+        assert_eq!(vec![4, 1, 3, 2], todo_ids(&app).await);
+
+        move_todo(4, r#"{ "before_id": 2 }"#).await;
+        assert_eq!(vec![1, 3, 4, 2], todo_ids(&app).await);
+
+        move_todo(2, r#"{ "to_top": true }"#).await;
+        assert_eq!(vec![2, 1, 3, 4], todo_ids(&app).await);
+
+        // 同じ隙間へ交互に移動し続け、間隔を使い切っても順序が崩れない
+        for i in 0..20 {
+            let id = if i % 2 == 0 { 3 } else { 4 };
+            move_todo(id, r#"{ "after_id": 2 }"#).await;
+        }
+        assert_eq!(vec![2, 4, 3, 1], todo_ids(&app).await);
+
+        let res = move_todo(1, r#"{ "after_id": 2, "to_top": true }"#).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+        let res = move_todo(1, r#"{ "before_id": 1 }"#).await;
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+        let res = move_todo(1, r#"{ "after_id": 99 }"#).await;
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let res = move_todo(99, r#"{ "to_top": true }"#).await;
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_create_complete_and_delete_todo_items() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        todo_repository
+            .create(1, CreateTodo::new("with items".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+
+        for text in ["first", "second", "third"] {
+            let req = build_req_with_json(
+                "/todos/1/items",
+                Method::POST,
+                serde_json::json!({ "text": text }).to_string(),
+            );
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+
+        let req = build_req_with_json(
+            "/todos/1/items/2",
+            Method::PATCH,
+            r#"{ "completed": true }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(
+            serde_json::json!({
+                "id": 2, "todo_id": 1, "text": "second", "completed": true, "position": 1,
+            }),
+            res_to_json(res).await
+        );
+
+        // 末尾の項目を先頭へ移動する
+        let req = build_req_with_json(
+            "/todos/1/items/3",
+            Method::PATCH,
+            r#"{ "position": 0 }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1/items/1");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let body = res_to_json(res).await;
+        assert_eq!("with items", body["text"]);
+        let items: Vec<(i64, i64, bool)> = body["items"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| {
+                (
+                    item["id"].as_i64().unwrap(),
+                    item["position"].as_i64().unwrap(),
+                    item["completed"].as_bool().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(vec![(3, 0, false), (2, 1, true)], items);
+
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1/items/1");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        let req = build_req_with_json(
+            "/todos/1/items",
+            Method::POST,
+            r#"{ "text": " " }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+
+        let req = build_req_with_json(
+            "/todos/2/items",
+            Method::POST,
+            r#"{ "text": "missing todo" }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_hide_items_of_trashed_todo() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        let todo = todo_repository
+            .create(1, CreateTodo::new("cascade".to_string(), vec![]))
+            .await
+            .unwrap();
+        todo_repository
+            .create_item(1, todo.id, CreateTodoItem { text: "step".to_string() })
+            .await
+            .unwrap();
+        let app = create_app(
+            todo_repository.clone(),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+
+        // ゴミ箱内のTodoの項目は参照できないが、復元すると元に戻る
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1");
+        app.clone().oneshot(req).await.unwrap();
+        let req = build_req_with_json(
+            "/todos/1/items/1",
+            Method::PATCH,
+            r#"{ "completed": true }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        todo_repository.restore(1, todo.id).await.unwrap();
+        assert_eq!(1, todo_repository.items(1, todo.id).await.unwrap().len());
+
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1?permanent=true");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1/items/1");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_record_todo_activity() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+        let req = build_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "before", "labels": [] }"#.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+        let req = build_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            r#"{ "text": "after", "priority": "high" }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let req = build_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            r#"{ "completed": true }"#.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1");
+        app.clone().oneshot(req).await.unwrap();
+
+        // ゴミ箱内のTodoの履歴も新しい順に返す
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1/activity");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("4", res.headers()[TOTAL_COUNT_HEADER]);
+        let activities = res_to_json(res).await;
+        let actions: Vec<&str> = activities
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|activity| activity["action"].as_str().unwrap())
+            .collect();
+        assert_eq!(vec!["deleted", "completed", "updated", "created"], actions);
+        let updated = &activities[2];
+        assert_eq!(1, updated["actor_id"]);
+        assert_eq!(
+            serde_json::json!({ "text": "before", "priority": "medium" }),
+            updated["old_value"]
+        );
+        assert_eq!(
+            serde_json::json!({ "text": "after", "priority": "high" }),
+            updated["new_value"]
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1/activity?limit=1&offset=3");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!("created", res_to_json(res).await[0]["action"]);
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/2/activity");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_create_list_and_delete_comments() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        todo_repository
+            .create(1, CreateTodo::new("commented".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+
+        for body in ["first", "second", "third"] {
+            let req = build_req_with_json(
+                "/todos/1/comments",
+                Method::POST,
+                serde_json::json!({ "body": body }).to_string(),
+            );
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+            let comment = res_to_json(res).await;
+            assert_eq!(1, comment["todo_id"]);
+            assert_eq!("user1", comment["author"]);
+            assert_eq!(body, comment["body"]);
+        }
+
+        // 新しい順に返す
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1/comments?limit=2");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("3", res.headers()[TOTAL_COUNT_HEADER]);
+        let bodies: Vec<String> = res_to_json(res)
+            .await
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|comment| comment["body"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(vec!["third", "second"], bodies);
+
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1/comments/3");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1/comments/3");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        let req = build_req_with_json(
+            "/todos/1/comments",
+            Method::POST,
+            r#"{ "body": "" }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+
+        let req = build_req_with_json(
+            "/todos/2/comments",
+            Method::POST,
+            r#"{ "body": "missing todo" }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        // 削除したTodoのコメントは参照できない
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1?permanent=true");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1/comments");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_manage_webhooks() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+
+        let req = build_req_with_json(
+            "/webhooks",
+            Method::POST,
+            serde_json::json!({
+                "url": "http://127.0.0.1:1/hook",
+                "secret": "secret",
+                "events": ["completed"],
+            })
+            .to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let webhook = res_to_json(res).await;
+        assert_eq!(serde_json::json!(["completed"]), webhook["events"]);
+        // 署名の鍵は応答に含めない
+        assert!(webhook.get("secret").is_none());
+
+        let req = build_req_with_json(
+            "/webhooks",
+            Method::POST,
+            r#"{ "url": "ftp://example.com", "secret": "secret" }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+
+        let req = build_req_with_json(
+            "/webhooks/1",
+            Method::PATCH,
+            r#"{ "events": [] }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(serde_json::json!([]), res_to_json(res).await["events"]);
+
+        let req = build_todo_req_with_empty(Method::GET, "/webhooks");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(1, res_to_json(res).await.as_array().unwrap().len());
+
+        // 他のユーザーの登録先は参照できない
+        let req = Request::builder()
+            .uri("/webhooks/1")
+            .header(header::AUTHORIZATION, format!("Bearer {}", test_token(2)))
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        let req = build_todo_req_with_empty(Method::DELETE, "/webhooks/1");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let req = build_todo_req_with_empty(Method::GET, "/webhooks/1");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_respond_even_if_webhook_is_unreachable() {
+        let webhook_repository = WebhookRepositoryForMemory::new();
+        let events = TodoEvents::new();
+        webhooks::spawn_dispatcher(
+            webhook_repository.clone(),
+            &events,
+            RetryPolicy {
+                max_attempts: 2,
+                base_delay: Duration::from_millis(10),
+            },
+        );
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            webhook_repository,
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .layer(Extension(events));
+
+        let req = build_req_with_json(
+            "/webhooks",
+            Method::POST,
+            r#"{ "url": "http://127.0.0.1:1/hook", "secret": "secret" }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+
+        let req = build_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "should_respond", "labels": [] }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+
+        // 配信の失敗は登録先の最後の結果として記録される
+        for _ in 0..100 {
+            let req = build_todo_req_with_empty(Method::GET, "/webhooks/1");
+            let webhook = res_to_json(app.clone().oneshot(req).await.unwrap()).await;
+            if !webhook["last_delivered_at"].is_null() {
+                assert!(webhook["last_status"].is_null());
+                assert!(webhook["last_error"].is_string());
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("webhook delivery not recorded");
+    }
+
+    async fn res_to_error(res: Response) -> serde_json::Value {
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: String = String::from_utf8(bytes.to_vec()).unwrap();
+        serde_json::from_str(&body)
+            .unwrap_or_else(|_| panic!("cannot convert error body. body: {}", body))
+    }
+
+    #[tokio::test]
+    async fn should_not_found_todo() {
+        let req = build_todo_req_with_empty(Method::GET, "/todos/999");
+        let res = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let body = res_to_error(res).await;
+        assert_eq!("not_found", body["error"]["code"]);
+        assert!(body["error"]["message"].is_string());
+    }
+
+    #[tokio::test]
+    async fn should_reject_unparsable_todo_id() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+
+        // 数値でない値・i64に収まらない値は平文ではなくJSONの400で返す
+        for (method, path) in [
+            (Method::GET, "/todos/abc"),
+            (Method::GET, "/todos/99999999999999999999"),
+            (Method::DELETE, "/todos/1/comments/abc"),
+        ] {
+            let req = build_todo_req_with_empty(method, path);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::BAD_REQUEST, res.status(), "{}", path);
+            let body = res_to_error(res).await;
+            assert_eq!("bad_request", body["error"]["code"], "{}", path);
+        }
+
+        // i32を超えるidも受け付け、存在しなければ404を返す
+        let req = build_todo_req_with_empty(Method::GET, "/todos/9007199254740993");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let body = res_to_error(res).await;
+        assert_eq!("not_found", body["error"]["code"]);
+    }
+
+    #[tokio::test]
+    async fn should_not_found_other_users_todo() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        todo_repository
+            .create(1, CreateTodo::new("private".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+
+        // 存在を漏らさないよう、403ではなく404を返す
+        for method in [Method::GET, Method::DELETE] {
+            let req = Request::builder()
+                .uri("/todos/1")
+                .method(method)
+                .header(header::AUTHORIZATION, format!("Bearer {}", test_token(2)))
+                .body(Body::empty())
+                .unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::NOT_FOUND, res.status());
+        }
+
+        let req = Request::builder()
+            .uri("/todos")
+            .method(Method::GET)
+            .header(header::AUTHORIZATION, format!("Bearer {}", test_token(2)))
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!("0", res.headers()[TOTAL_COUNT_HEADER]);
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_not_found_on_delete_missing_todo() {
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/999");
+        let res = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let body = res_to_error(res).await;
+        assert_eq!("not_found", body["error"]["code"]);
+    }
+
+    fn failing_app(
+        todo_repository: FailingTodoRepository<TodoRepositoryForMemory>,
+        label_repository: FailingLabelRepository<LabelRepositoryForMemory>,
+    ) -> Router {
+        create_app(
+            todo_repository,
+            label_repository,
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+    }
+
+    #[tokio::test]
+    async fn should_return_internal_error_when_repository_fails() {
+        let todo_repository = FailingTodoRepository::new(TodoRepositoryForMemory::new(vec![]));
+        let label_repository = FailingLabelRepository::new(LabelRepositoryForMemory::new());
+        let app = failing_app(
+            todo_repository.clone().fail("all"),
+            label_repository.clone().fail("all"),
+        );
+
+        let paths = ["/todos", "/labels?include_counts=false"];
+        for path in paths {
+            let req = build_todo_req_with_empty(Method::GET, path);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, res.status());
+            let body = res_to_error(res).await;
+            assert_eq!("internal_error", body["error"]["code"]);
+            // 内部のエラーの内容はクライアントへ返さない
+            assert_eq!("Internal server error", body["error"]["message"]);
+        }
+
+        todo_repository.recover("all");
+        label_repository.recover("all");
+        for path in paths {
+            let req = build_todo_req_with_empty(Method::GET, path);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+        }
+    }
+
+    #[tokio::test]
+    async fn should_report_unexpected_error_once() {
+        let reporter = Arc::new(RecordingReporter::default());
+        let todo_repository = FailingTodoRepository::new(TodoRepositoryForMemory::new(vec![]));
+        let app = failing_app(
+            todo_repository.clone().fail("all"),
+            FailingLabelRepository::new(LabelRepositoryForMemory::new()),
+        )
+        .layer(Extension(Reporter(reporter.clone())));
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, res.status());
+        let request_id = res.headers()[REQUEST_ID_HEADER]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let events = reporter.events();
+        assert_eq!(1, events.len());
+        assert_eq!("GET", events[0].method);
+        assert_eq!("/todos", events[0].path);
+        assert_eq!(Some(request_id), events[0].request_id);
+        // クライアントへ返さない詳細も送る
+        assert!(events[0].message.contains("all"), "{}", events[0].message);
+
+        // 5xx以外は送らない
+        todo_repository.recover("all");
+        for path in ["/todos", "/todos/999"] {
+            let req = build_todo_req_with_empty(Method::GET, path);
+            app.clone().oneshot(req).await.unwrap();
+        }
+        assert_eq!(1, reporter.events().len());
+    }
+
+    #[tokio::test]
+    async fn should_return_internal_error_when_handler_panics() {
+        let reporter = Arc::new(RecordingReporter::default());
+        let clock = MockClock::default();
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]).with_clock(clock.clone()),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .layer(Extension(Reporter(reporter.clone())));
+        let body = r#"{ "text": "panics", "labels": [] }"#;
+
+        // 接続を切らず、他のエラーと同じ形式の500を返す
+        clock.panic_on_next_now();
+        let req = build_req_with_json("/todos", Method::POST, body.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, res.status());
+        assert!(res.headers().contains_key(REQUEST_ID_HEADER));
+        let error = res_to_error(res).await;
+        assert_eq!("internal_error", error["error"]["code"]);
+        let events = reporter.events();
+        assert_eq!(1, events.len());
+        assert_eq!("POST", events[0].method);
+        assert_eq!("/todos", events[0].path);
+        assert!(events[0].message.starts_with("handler panicked: "));
+
+        let req = build_req_with_json("/todos", Method::POST, body.to_string());
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        assert_eq!(1, reporter.events().len());
+    }
+
+    #[tokio::test]
+    async fn should_keep_serving_after_repository_panics() {
+        install_panic_hook();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let reporter = Arc::new(RecordingReporter::default());
+        let todo_repository = FailingTodoRepository::new(TodoRepositoryForMemory::new(vec![]));
+        let label_repository = FailingLabelRepository::new(LabelRepositoryForMemory::new());
+        let app = failing_app(todo_repository.clone().panic("all"), label_repository)
+            .layer(Extension(Reporter(reporter.clone())));
+        tokio::spawn(serve(listener, app, std::future::pending()));
+        // 同じ接続を使い回し、panicの後も接続が切れていないことを確かめる
+        let client = hyper::Client::new();
+        let get_todos = || {
+            Request::builder()
+                .uri(format!("http://{}/todos", addr))
+                .method(Method::GET)
+                .header(header::AUTHORIZATION, format!("Bearer {}", test_token(1)))
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let res = client.request(get_todos()).await.unwrap();
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, res.status());
+        let request_id = res.headers()[REQUEST_ID_HEADER].to_str().unwrap().to_string();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let error: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!("internal_error", error["error"]["code"]);
+        assert_eq!("Internal server error", error["error"]["message"]);
+        let events = reporter.events();
+        assert_eq!(1, events.len());
+        assert_eq!("handler panicked: injected panic in all", events[0].message);
+        assert_eq!(Some(request_id), events[0].request_id);
+
+        todo_repository.recover("all");
+        let res = client.request(get_todos()).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(1, reporter.events().len());
+    }
+
+    #[tokio::test]
+    async fn should_return_internal_error_when_find_fails() {
+        let inner = TodoRepositoryForMemory::new(vec![]);
+        inner
+            .create(1, CreateTodo::new("first".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        let label_repository = FailingLabelRepository::new(LabelRepositoryForMemory::new());
+        let app = failing_app(
+            FailingTodoRepository::new(inner).fail("find").fail("exists"),
+            label_repository.fail("find"),
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/1");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, res.status());
+        assert_eq!("internal_error", res_to_error(res).await["error"]["code"]);
+
+        // 存在確認のためにexistsを呼ぶハンドラも、パニックせずに500を返す
+        let req = build_req_with_json(
+            "/todos/1/comments",
+            Method::POST,
+            r#"{ "body": "comment" }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, res.status());
+
+        // 重複時に既存のラベルを読めなくても、500を返す
+        for expected in [StatusCode::CREATED, StatusCode::INTERNAL_SERVER_ERROR] {
+            let req = build_req_with_json(
+                "/labels",
+                Method::POST,
+                r#"{ "name": "work" }"#.to_string(),
+            );
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(expected, res.status());
+        }
+    }
+
+    #[tokio::test]
+    async fn should_time_out_slow_repository() {
+        let delay = Duration::from_secs(5);
+        let app = failing_app(
+            FailingTodoRepository::new(TodoRepositoryForMemory::new(vec![])).delay("all", delay),
+            FailingLabelRepository::new(LabelRepositoryForMemory::new())
+                .delay("all_with_counts", delay),
+        );
+        let app = with_request_limits(app, Duration::from_millis(50), 10);
+
+        for path in ["/todos", "/labels"] {
+            let started = std::time::Instant::now();
+            let req = build_todo_req_with_empty(Method::GET, path);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert!(started.elapsed() < delay);
+            assert_eq!(StatusCode::SERVICE_UNAVAILABLE, res.status());
+            assert_eq!("5", res.headers()[header::RETRY_AFTER]);
+            let body = res_to_error(res).await;
+            assert_eq!("timeout", body["error"]["code"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_reject_requests_over_concurrency_limit() {
+        let todo_repository = FailingTodoRepository::new(TodoRepositoryForMemory::new(vec![]));
+        let app = failing_app(
+            todo_repository.clone().delay("all", Duration::from_millis(200)),
+            FailingLabelRepository::new(LabelRepositoryForMemory::new()),
+        );
+        let app = with_request_limits(app, Duration::from_secs(5), 1);
+
+        let slow = tokio::spawn(
+            app.clone()
+                .oneshot(build_todo_req_with_empty(Method::GET, "/todos")),
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        // 別のルートでも同じ上限を共有し、空くまで待たせずに503を返す
+        let res = app
+            .clone()
+            .oneshot(build_todo_req_with_empty(Method::GET, "/labels"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, res.status());
+        assert_eq!("5", res.headers()[header::RETRY_AFTER]);
+        assert_eq!("overloaded", res_to_error(res).await["error"]["code"]);
+
+        assert_eq!(StatusCode::OK, slow.await.unwrap().unwrap().status());
+        todo_repository.recover("all");
+        let res = app
+            .oneshot(build_todo_req_with_empty(Method::GET, "/labels"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_not_time_out_streaming_export() {
+        let inner = TodoRepositoryForMemory::new(vec![]);
+        inner
+            .create(1, CreateTodo::new("first".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        let app = failing_app(
+            FailingTodoRepository::new(inner).delay("stream_all", Duration::from_millis(100)),
+            FailingLabelRepository::new(LabelRepositoryForMemory::new()),
+        );
+        let app = with_request_limits(app, Duration::from_millis(50), 10);
+
+        // ヘッダーはすぐに返すため、bodyの送信に時間がかかっても打ち切らない
+        let req = build_todo_req_with_empty(Method::GET, "/todos/export");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todo: TodoEntity = serde_json::from_slice(bytes.trim_ascii_end()).unwrap();
+        assert_eq!("first", todo.text);
+    }
+
+    const TRUSTED_PROXY: SocketAddr =
+        SocketAddr::new(std::net::IpAddr::V4(std::net::Ipv4Addr::LOCALHOST), 8080);
+
+    fn rate_limited_app(burst: u32) -> Router {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+        let policy = RateLimitPolicy {
+            per_minute: 1,
+            burst,
+        };
+        with_rate_limit(
+            app,
+            Arc::new(RateLimiter::new(policy)),
+            test_keys(),
+            vec![TRUSTED_PROXY.ip()],
+        )
+    }
+
+    #[tokio::test]
+    async fn should_reject_requests_over_rate_limit() {
+        let app = rate_limited_app(2);
+        for _ in 0..2 {
+            let req = build_todo_req_with_empty(Method::GET, "/todos");
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+        }
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, res.status());
+        // 1分に1回のため、次の1回までは最大60秒
+        let retry_after: u64 = res.headers()[header::RETRY_AFTER]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert!((1..=60).contains(&retry_after));
+        let body = res_to_error(res).await;
+        assert_eq!("rate_limited", body["error"]["code"]);
+
+        // 別のユーザーは別に数える
+        let req = Request::builder()
+            .uri("/todos")
+            .header(header::AUTHORIZATION, format!("Bearer {}", test_token(2)))
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+
+        // ヘルスチェックは制限しない
+        let req = Request::builder()
+            .uri("/healthz")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_rate_limit_anonymous_requests_by_forwarded_ip() {
+        let app = rate_limited_app(1);
+        let login = |peer: SocketAddr, ip: &'static str| {
+            Request::builder()
+                .uri("/auth/login")
+                .method(Method::POST)
+                .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+                .header("x-forwarded-for", ip)
+                .extension(axum::extract::ConnectInfo(peer))
+                .body(Body::from(r#"{ "username": "user", "password": "password" }"#))
+                .unwrap()
+        };
+
+        let res = app
+            .clone()
+            .oneshot(login(TRUSTED_PROXY, "203.0.113.1"))
+            .await
+            .unwrap();
+        assert_ne!(StatusCode::TOO_MANY_REQUESTS, res.status());
+        // 先頭のアドレスを偽っても、プロキシが付けたアドレスで数える
+        let res = app
+            .clone()
+            .oneshot(login(TRUSTED_PROXY, "203.0.113.9, 203.0.113.1"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, res.status());
+        let res = app
+            .clone()
+            .oneshot(login(TRUSTED_PROXY, "203.0.113.2"))
+            .await
+            .unwrap();
+        assert_ne!(StatusCode::TOO_MANY_REQUESTS, res.status());
+
+        // プロキシを通さない接続は、X-Forwarded-Forを変えても接続元のIPで数える
+        let direct = SocketAddr::from(([198, 51, 100, 1], 40000));
+        let res = app
+            .clone()
+            .oneshot(login(direct, "203.0.113.3"))
+            .await
+            .unwrap();
+        assert_ne!(StatusCode::TOO_MANY_REQUESTS, res.status());
+        let res = app.oneshot(login(direct, "203.0.113.4")).await.unwrap();
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_get_all_todos() {
+        let (labels, label_ids) = label_fixture();
+        let expected = TodoEntity::new(1, "should_get_all_todos".to_string(), labels.clone());
+
+        let todo_repository = TodoRepositoryForMemory::new(labels.clone());
+        todo_repository
+            .create(1, CreateTodo::new(
+                "should_get_all_todos".to_string(),
+                label_ids,
+            ))
+            .await
+            .expect("failed create todo");
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!("1", res.headers()[TOTAL_COUNT_HEADER]);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: String = String::from_utf8(bytes.to_vec()).unwrap();
+        let todos: Vec<TodoEntity> = serde_json::from_str(&body)
+            .unwrap_or_else(|_| panic!("cannot convert Todo lis instance. body: {}", body));
+        let todos: Vec<TodoEntity> = todos
+            .into_iter()
+            .map(TodoEntity::without_timestamps)
+            .collect();
+        assert_eq!(vec![expected], todos);
+    }
+
+    #[tokio::test]
+    async fn should_sort_todos_by_updated_at() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        for text in ["first", "second", "third"] {
+            todo_repository
+                .create(1, CreateTodo::new(text.to_string(), vec![]))
+                .await
+                .expect("failed create todo");
+        }
+        let req = build_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            r#"{"completed": true}"#.to_string(),
+        );
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+        app.clone().oneshot(req).await.unwrap();
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos?sort=updated_at&order=desc");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        let ids: Vec<i64> = body
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|todo| todo["id"].as_i64().unwrap())
+            .collect();
+        assert_eq!(vec![1, 3, 2], ids);
+        assert!(body[0]["created_at"].is_string());
+        assert!(body[0]["updated_at"].is_string());
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos?sort=due_date");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        let body = res_to_error(res).await;
+        assert_eq!("bad_request", body["error"]["code"]);
+        assert_eq!("sort", body["error"]["fields"][0]["field"]);
+        let message = body["error"]["fields"][0]["message"].as_str().unwrap();
+        for allowed in ["id", "text", "created_at", "updated_at", "completed", "priority"] {
+            assert!(message.contains(&format!("`{}`", allowed)), "{}", message);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_sort_filtered_page_by_text() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        for fixture in [
+            TodoFixture::new("delta"),
+            TodoFixture::new("alpha"),
+            TodoFixture::new("charlie").completed(),
+            TodoFixture::new("bravo"),
+        ] {
+            fixture.insert(&todo_repository).await;
+        }
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+
+        let req = build_todo_req_with_empty(
+            Method::GET,
+            "/todos?sort=text&order=asc&completed=false&limit=2&offset=1",
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("3", res.headers()[TOTAL_COUNT_HEADER]);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todos: Vec<TodoEntity> = serde_json::from_slice(&bytes).unwrap();
+        let texts: Vec<&str> = todos.iter().map(|todo| todo.text.as_str()).collect();
+        assert_eq!(vec!["bravo", "delta"], texts);
+    }
+
+    #[tokio::test]
+    async fn should_filter_todos_by_due_date() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+        for body in [
+            r#"{"text": "past", "labels": [], "due_date": "2000-01-01T00:00:00Z"}"#,
+            r#"{"text": "done", "labels": [], "due_date": "2000-01-02T00:00:00+09:00"}"#,
+            r#"{"text": "future", "labels": [], "due_date": "2999-01-01T00:00:00Z"}"#,
+            r#"{"text": "someday", "labels": []}"#,
+        ] {
+            let req = build_req_with_json("/todos", Method::POST, body.to_string());
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+        let req = build_req_with_json("/todos/2", Method::PATCH, r#"{"completed": true}"#.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        let todo = res_to_todo(res).await;
+        assert_eq!(
+            "2000-01-01T15:00:00Z".parse::<chrono::DateTime<chrono::Utc>>().ok(),
+            todo.due_date
+        );
+
+        for (query, expected) in [
+            ("overdue=true", vec!["past"]),
+            ("overdue=false", vec!["done", "future", "someday"]),
+            ("due_before=2000-01-01T12:00:00Z", vec!["past"]),
+            ("due_after=2000-01-01T12:00:00Z", vec!["done", "future"]),
+        ] {
+            let uri = format!("/todos?sort=id&order=asc&{}", query);
+            let req = build_todo_req_with_empty(Method::GET, &uri);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let todos: Vec<TodoEntity> = serde_json::from_slice(&bytes).unwrap();
+            let texts: Vec<&str> = todos.iter().map(|todo| todo.text.as_str()).collect();
+            assert_eq!(expected, texts, "query: {}", query);
+        }
+
+        // nullを指定すると期限を外せる
+        let req = build_req_with_json("/todos/1", Method::PATCH, r#"{"due_date": null}"#.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(None, res_to_todo(res).await.due_date);
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos?due_before=tomorrow");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!("due_before", body["error"]["fields"][0]["field"]);
+
+        let req = build_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{"text": "bad", "labels": [], "due_date": "2024-13-01"}"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!("due_date", body["error"]["fields"][0]["field"]);
+    }
+
+    #[tokio::test]
+    async fn should_filter_and_sort_todos_by_priority() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+        for body in [
+            r#"{"text": "normal", "labels": []}"#,
+            r#"{"text": "urgent", "labels": [], "priority": "high"}"#,
+            r#"{"text": "later", "labels": [], "priority": "low"}"#,
+        ] {
+            let req = build_req_with_json("/todos", Method::POST, body.to_string());
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+        let req = build_req_with_json("/todos/1", Method::PATCH, r#"{"priority": "high"}"#.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        let body: serde_json::Value =
+            serde_json::from_slice(&hyper::body::to_bytes(res.into_body()).await.unwrap()).unwrap();
+        assert_eq!("high", body["priority"]);
+
+        for (query, expected) in [
+            ("priority=high&sort=id&order=asc", vec!["normal", "urgent"]),
+            ("sort=priority&order=asc", vec!["later", "normal", "urgent"]),
+        ] {
+            let req = build_todo_req_with_empty(Method::GET, &format!("/todos?{}", query));
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let todos: Vec<TodoEntity> = serde_json::from_slice(&bytes).unwrap();
+            let texts: Vec<&str> = todos.iter().map(|todo| todo.text.as_str()).collect();
+            assert_eq!(expected, texts, "query: {}", query);
+        }
+
+        let reqs = [
+            build_todo_req_with_empty(Method::GET, "/todos?priority=urgent"),
+            build_req_with_json(
+                "/todos",
+                Method::POST,
+                r#"{"text": "bad", "labels": [], "priority": "urgent"}"#.to_string(),
+            ),
+            build_req_with_json("/todos/1", Method::PATCH, r#"{"priority": "HIGH"}"#.to_string()),
+        ];
+        for req in reqs {
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+            let body = res_to_error(res).await;
+            assert_eq!("priority", body["error"]["fields"][0]["field"]);
+            assert_eq!(
+                "Must be one of low, medium, high",
+                body["error"]["fields"][0]["message"]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn should_get_paginated_todos() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        for text in ["first", "second", "third"] {
+            todo_repository
+                .create(1, CreateTodo::new(text.to_string(), vec![]))
+                .await
+                .expect("failed create todo");
+        }
+        let req = build_todo_req_with_empty(Method::GET, "/todos?limit=1&offset=1");
+        let res = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("3", res.headers()[TOTAL_COUNT_HEADER]);
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: String = String::from_utf8(bytes.to_vec()).unwrap();
+        let todos: Vec<TodoEntity> = serde_json::from_str(&body)
+            .unwrap_or_else(|_| panic!("cannot convert Todo list instance. body: {}", body));
+        assert_eq!(1, todos.len());
+        assert_eq!("second", todos[0].text);
+    }
+
+    #[tokio::test]
+    async fn should_filter_todos_by_completed() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        let scenario = scenario(&todo_repository, 1).await;
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+        // アーカイブ済み・ゴミ箱内のTodoは含めない
+        for (query, expected) in [
+            ("completed=true", vec![scenario.milk.id]),
+            (
+                "completed=false",
+                vec![scenario.report.id, scenario.rent.id, scenario.dentist.id],
+            ),
+        ] {
+            let uri = format!("/todos?sort=id&order=asc&{}", query);
+            let req = build_todo_req_with_empty(Method::GET, &uri);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            assert_eq!(
+                expected.len().to_string(),
+                res.headers()[TOTAL_COUNT_HEADER]
+            );
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let todos: Vec<TodoEntity> = serde_json::from_slice(&bytes).unwrap();
+            let ids: Vec<i64> = todos.iter().map(|todo| todo.id).collect();
+            assert_eq!(expected, ids, "query: {}", query);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_search_todos() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        for fixture in [
+            TodoFixture::new("buy groceries"),
+            TodoFixture::new("walk the dog"),
+            TodoFixture::new("buy more groceries").completed(),
+        ] {
+            fixture.insert(&todo_repository).await;
+        }
+        let req = build_todo_req_with_empty(Method::GET, "/todos?q=Groceries&completed=false");
+        let res = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todos: Vec<TodoEntity> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(1, todos.len());
+        assert_eq!("buy groceries", todos[0].text);
+    }
+
+    #[tokio::test]
+    async fn should_reject_invalid_completed_filter() {
+        let req = build_todo_req_with_empty(Method::GET, "/todos?completed=banana");
+        let res = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        let body = res_to_error(res).await;
+        assert_eq!("bad_request", body["error"]["code"]);
+    }
+
+    #[tokio::test]
+    async fn should_update_todo() {
+        let (labels, label_ids) = label_fixture();
+        let expected = TodoEntity {
+            version: 2,
+            ..TodoEntity::new(1, "should_update_todo".to_string(), labels.clone())
+        };
+
+        let todo_repository = TodoRepositoryForMemory::new(labels);
+        todo_repository
+            .create(1, CreateTodo::new("before_update_todo".to_string(), label_ids))
+            .await
+            .expect("failed create todo");
+        let req = build_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            r#"{"text": "should_update_todo","completed": false}"#.to_string(),
+        );
+        let res = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        let todo = res_to_todo(res).await;
+        assert_eq!(expected, todo.without_timestamps());
+    }
+
+    #[tokio::test]
+    async fn should_replace_todo_with_put_unlike_patch() {
+        let (labels, label_ids) = label_fixture();
+        let due_date = "2030-01-01T00:00:00Z"
+            .parse::<chrono::DateTime<chrono::Utc>>()
+            .unwrap();
+        let app = || {
+            let todo_repository = TodoRepositoryForMemory::new(labels.clone());
+            let payload = CreateTodo::new("before".to_string(), label_ids.clone())
+                .with_due_date(due_date)
+                .with_priority(Priority::High);
+            async move {
+                todo_repository
+                    .create(1, payload)
+                    .await
+                    .expect("failed create todo");
+                create_app(
+                    todo_repository,
+                    LabelRepositoryForMemory::new(),
+                    UserRepositoryForMemory::new(),
+                    CommentRepositoryForMemory::new(),
+                    WebhookRepositoryForMemory::new(),
+                    HealthRepositoryForMemory::new(),
+                    test_keys(),
+                    DEFAULT_API_PREFIX,
+                )
+            }
+        };
+        let body = r#"{"text": "after", "completed": true}"#;
+
+        // 同じ状態のTodoに同じbodyを送っても、PATCHは省略した項目を残す
+        let res = app()
+            .await
+            .oneshot(build_req_with_json(
+                "/todos/1",
+                Method::PATCH,
+                body.to_string(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let patched = res_to_todo(res).await;
+        assert_eq!(("after", true), (patched.text.as_str(), patched.completed));
+        assert_eq!(labels, patched.labels);
+        assert_eq!(Some(due_date), patched.due_date);
+        assert_eq!(Priority::High, patched.priority);
+
+        // PUTは省略した項目を消すか既定値に戻す
+        let app = app().await;
+        let res = app
+            .clone()
+            .oneshot(build_req_with_json(
+                "/todos/1",
+                Method::PUT,
+                body.to_string(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let replaced = res_to_todo(res).await;
+        assert_eq!(
+            ("after", true),
+            (replaced.text.as_str(), replaced.completed)
+        );
+        assert!(replaced.labels.is_empty());
+        assert_eq!(None, replaced.due_date);
+        assert_eq!(Priority::Medium, replaced.priority);
+        assert_eq!(2, replaced.version);
+
+        // textとcompletedは省略できない
+        let res = app
+            .clone()
+            .oneshot(build_req_with_json(
+                "/todos/1",
+                Method::PUT,
+                r#"{"text": "after"}"#.to_string(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        let error = res_to_error(res).await;
+        assert_eq!("invalid_json", error["error"]["code"]);
+
+        // 存在しないidでは作成せずに404を返す
+        let res = app
+            .clone()
+            .oneshot(build_req_with_json(
+                "/todos/999",
+                Method::PUT,
+                body.to_string(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let res = app
+            .oneshot(build_todo_req_with_empty(Method::GET, "/todos/999"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_clear_fields_with_merge_patch() {
+        let (labels, label_ids) = label_fixture();
+        let app = || {
+            let todo_repository = TodoRepositoryForMemory::new(labels.clone());
+            let payload: CreateTodo = serde_json::from_value(serde_json::json!({
+                "text": "merge patch",
+                "labels": label_ids.clone(),
+                "due_date": "2030-01-01T00:00:00Z",
+                "priority": "high",
+                "recurrence": {"type": "weekly"},
+                "remind_at": "2029-12-31T00:00:00Z",
+            }))
+            .unwrap();
+            async move {
+                todo_repository
+                    .create(1, payload)
+                    .await
+                    .expect("failed create todo");
+                create_app(
+                    todo_repository,
+                    LabelRepositoryForMemory::new(),
+                    UserRepositoryForMemory::new(),
+                    CommentRepositoryForMemory::new(),
+                    WebhookRepositoryForMemory::new(),
+                    HealthRepositoryForMemory::new(),
+                    test_keys(),
+                    DEFAULT_API_PREFIX,
+                )
+            }
+        };
+        let patch = |content_type: &str, body: &str| {
+            Request::builder()
+                .uri("/todos/1")
+                .method(Method::PATCH)
+                .header(header::CONTENT_TYPE, content_type)
+                .header(header::AUTHORIZATION, format!("Bearer {}", test_token(1)))
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        // 省略した項目は変更しない
+        let res = app()
+            .await
+            .oneshot(patch(MERGE_PATCH_CONTENT_TYPE, "{}"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let untouched = res_to_todo(res).await;
+        assert_eq!(labels, untouched.labels);
+        assert!(untouched.due_date.is_some());
+        assert_eq!(Priority::High, untouched.priority);
+        assert!(untouched.recurrence.is_some());
+        assert!(untouched.remind_at.is_some());
+
+        // nullを指定した項目のみを消し、他の項目は残す
+        for field in ["labels", "due_date", "priority", "recurrence", "remind_at"] {
+            let res = app()
+                .await
+                .oneshot(patch(
+                    MERGE_PATCH_CONTENT_TYPE,
+                    &format!(r#"{{"{}": null}}"#, field),
+                ))
+                .await
+                .unwrap();
+            assert_eq!(StatusCode::CREATED, res.status(), "{}", field);
+            let todo = res_to_todo(res).await;
+            assert_eq!(
+                [
+                    ("labels", todo.labels.is_empty()),
+                    ("due_date", todo.due_date.is_none()),
+                    ("priority", todo.priority == Priority::Medium),
+                    ("recurrence", todo.recurrence.is_none()),
+                    ("remind_at", todo.remind_at.is_none()),
+                ]
+                .into_iter()
+                .filter(|(_, cleared)| *cleared)
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>(),
+                vec![field],
+                "{}",
+                field
+            );
+        }
+
+        // application/jsonでは、消すことのできない項目へのnullは従来どおり変更しない
+        let res = app()
+            .await
+            .oneshot(patch(
+                mime::APPLICATION_JSON.as_ref(),
+                r#"{"labels": null, "priority": null}"#,
+            ))
+            .await
+            .unwrap();
+        let todo = res_to_todo(res).await;
+        assert_eq!(labels, todo.labels);
+        assert_eq!(Priority::High, todo.priority);
+
+        // textとcompletedは消せない
+        let res = app()
+            .await
+            .oneshot(patch(
+                "application/merge-patch+json; charset=utf-8",
+                r#"{"text": null, "completed": null, "due_date": null}"#,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+        let body = res_to_error(res).await;
+        assert_eq!("completed", body["error"]["fields"][0]["field"]);
+        assert_eq!("text", body["error"]["fields"][1]["field"]);
+    }
+
+    #[tokio::test]
+    async fn should_reject_stale_todo_update() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        todo_repository
+            .create(1, CreateTodo::new("original".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+
+        let req = build_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            r#"{"text": "first", "version": 1}"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let todo = res_to_todo(res).await;
+        assert_eq!(2, todo.version);
+
+        // 古いversionでの更新は409となり、最新のTodoが返る
+        let req = build_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            r#"{"text": "second", "version": 1}"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!("version_conflict", body["error"]["code"]);
+        let current: TodoEntity = serde_json::from_value(body["current"].clone()).unwrap();
+        assert_eq!(todo, current);
+
+        let req = build_req_with_json(
+            "/todos/999",
+            Method::PATCH,
+            r#"{"text": "second", "version": 1}"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_delete_todo() {
+        let (labels, label_ids) = label_fixture();
+        let todo_repository = TodoRepositoryForMemory::new(labels);
+        todo_repository
+            .create(1, CreateTodo::new("should_delete_todo".to_string(), label_ids))
+            .await
+            .expect("failed create todo");
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1");
+        let res = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_update_many_todos() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        for text in ["first", "second", "third"] {
+            todo_repository
+                .create(1, CreateTodo::new(text.to_string(), vec![]))
+                .await
+                .expect("failed create todo");
+        }
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+
+        let req = build_req_with_json(
+            "/todos",
+            Method::PATCH,
+            r#"{ "ids": [3, 1, 42], "completed": true }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(serde_json::json!([42]), body["missing"]);
+        let todos: Vec<TodoEntity> = serde_json::from_value(body["todos"].clone()).unwrap();
+        let ids: Vec<i64> = todos.iter().map(|todo| todo.id).collect();
+        assert_eq!(vec![1, 3], ids);
+        assert!(todos.iter().all(|todo| todo.completed));
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos?completed=false");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!("1", res.headers()[TOTAL_COUNT_HEADER]);
+
+        let req = build_req_with_json(
+            "/todos",
+            Method::PATCH,
+            r#"{ "ids": [], "completed": true }"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+        let body = res_to_error(res).await;
+        assert_eq!("ids", body["error"]["fields"][0]["field"]);
+    }
+
+    #[tokio::test]
+    async fn should_purge_completed_todos() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        for text in ["open", "done"] {
+            todo_repository
+                .create(1, CreateTodo::new(text.to_string(), vec![]))
+                .await
+                .expect("failed create todo");
+        }
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+        let req = build_req_with_json("/todos/2", Method::PATCH, r#"{"completed": true}"#.to_string());
+        app.clone().oneshot(req).await.unwrap();
+
+        for expected in [1, 0] {
+            let req = build_todo_req_with_empty(Method::POST, "/todos/purge_completed");
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            assert_eq!(serde_json::json!({ "deleted": expected }), body);
+        }
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!("1", res.headers()[TOTAL_COUNT_HEADER]);
+    }
+
+    #[tokio::test]
+    async fn should_duplicate_todo_with_labels() {
+        let (labels, label_ids) = label_fixture();
+        let todo_repository = TodoRepositoryForMemory::new(labels.clone());
+        let source = todo_repository
+            .create(
+                1,
+                CreateTodo::new("weekly report".to_string(), label_ids)
+                    .with_priority(Priority::High)
+                    .with_due_date(chrono::Utc::now() + chrono::Duration::days(7)),
+            )
+            .await
+            .expect("failed create todo");
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+        let req = build_req_with_json("/todos/1", Method::PATCH, r#"{"completed": true}"#.to_string());
+        app.clone().oneshot(req).await.unwrap();
+
+        let req = build_todo_req_with_empty(Method::POST, "/todos/1/duplicate");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let copy = res_to_todo(res).await;
+        assert_eq!(2, copy.id);
+        assert_eq!("weekly report", copy.text);
+        assert!(!copy.completed);
+        assert_eq!(Priority::High, copy.priority);
+        assert_eq!(labels, copy.labels);
+        // 作成日時から期限までの間隔を引き継ぐ
+        assert_eq!(
+            source.due_date.unwrap() - source.created_at,
+            copy.due_date.unwrap() - copy.created_at
+        );
+
+        let req = build_req_with_json(
+            "/todos/1/duplicate",
+            Method::POST,
+            r#"{"due_date": "2030-01-31T00:00:00Z"}"#.to_string(),
+        );
+        let copy = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(
+            "2030-01-31T00:00:00Z".parse::<chrono::DateTime<chrono::Utc>>().ok(),
+            copy.due_date
+        );
+        let req = build_req_with_json("/todos/1/duplicate", Method::POST, r#"{"due_date": null}"#.to_string());
+        let copy = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(None, copy.due_date);
+        assert_eq!(labels, copy.labels);
+
+        let req = build_req_with_json("/todos/1/duplicate", Method::POST, "{".to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        let req = build_todo_req_with_empty(Method::POST, "/todos/999/duplicate");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_spawn_next_occurrence_of_recurring_todo() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+        let req = build_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{"text": "water plants", "labels": [], "due_date": "2030-01-01T00:00:00Z",
+                "recurrence": {"type": "every_n_days", "days": 3}}"#
+                .to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+        let res = app
+            .clone()
+            .oneshot(build_todo_req_with_empty(Method::GET, "/todos/1"))
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::json!({"type": "every_n_days", "days": 3}),
+            res_to_json(res).await["recurrence"]
+        );
+
+        let req = build_req_with_json("/todos/1", Method::PATCH, r#"{"completed": true}"#.to_string());
+        let body = res_to_json(app.clone().oneshot(req).await.unwrap()).await;
+        assert_eq!(true, body["completed"]);
+        assert_eq!(2, body["next_occurrence_id"]);
+        assert_eq!(2, body["next_occurrence"]["id"]);
+        assert_eq!(false, body["next_occurrence"]["completed"]);
+        assert_eq!("2030-01-04T00:00:00Z", body["next_occurrence"]["due_date"]);
+
+        // 作成済みの場合は再度完了しても次の回を返さない
+        let req = build_req_with_json("/todos/1", Method::PATCH, r#"{"completed": true}"#.to_string());
+        let body = res_to_json(app.clone().oneshot(req).await.unwrap()).await;
+        assert!(body.get("next_occurrence").is_none());
+        assert_eq!(vec![2, 1], todo_ids(&app).await);
+
+        let req = build_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{"text": "never", "labels": [], "recurrence": {"type": "every_n_days", "days": 0}}"#
+                .to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_archive_and_unarchive_todos() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        for text in ["open", "done", "archived", "done later"] {
+            todo_repository
+                .create(1, CreateTodo::new(text.to_string(), vec![]))
+                .await
+                .expect("failed create todo");
+        }
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+        let post = |uri: &'static str| {
+            let app = app.clone();
+            async move {
+                let req = build_todo_req_with_empty(Method::POST, uri);
+                app.oneshot(req).await.unwrap()
+            }
+        };
+
+        // 2回目のアーカイブは何も変更しない
+        let res = post("/todos/3/archive").await;
+        assert_eq!(StatusCode::OK, res.status());
+        let archived = res_to_todo(res).await;
+        assert!(archived.archived_at.is_some());
+        let res = post("/todos/3/archive").await;
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(archived, res_to_todo(res).await);
+        assert_eq!(StatusCode::NOT_FOUND, post("/todos/999/archive").await.status());
+
+        assert_eq!(vec![4, 2, 1], todo_ids(&app).await);
+        let req = build_todo_req_with_empty(Method::GET, "/todos?include_archived=true");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!("4", res.headers()[TOTAL_COUNT_HEADER]);
+
+        // アーカイブ済みのTodoは完了済みでも数えない
+        for id in [2, 3, 4] {
+            let req = build_req_with_json(
+                &format!("/todos/{}", id),
+                Method::PATCH,
+                r#"{"completed": true}"#.to_string(),
+            );
+            app.clone().oneshot(req).await.unwrap();
+        }
+        let res = post("/todos/archive_completed").await;
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(serde_json::json!({ "archived": 2 }), res_to_json(res).await);
+        assert_eq!(vec![1], todo_ids(&app).await);
+
+        let res = post("/todos/2/unarchive").await;
+        assert_eq!(StatusCode::OK, res.status());
+        let todo = res_to_todo(res).await;
+        assert!(todo.archived_at.is_none());
+        assert!(todo.completed);
+        assert_eq!(vec![2, 1], todo_ids(&app).await);
+        assert_eq!(StatusCode::NOT_FOUND, post("/todos/999/unarchive").await.status());
+    }
+
+    #[tokio::test]
+    async fn should_trash_and_restore_todo() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        for text in ["keep", "trash"] {
+            todo_repository
+                .create(1, CreateTodo::new(text.to_string(), vec![]))
+                .await
+                .expect("failed create todo");
+        }
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/2");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let req = build_todo_req_with_empty(Method::GET, "/todos/2");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!("1", res.headers()[TOTAL_COUNT_HEADER]);
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/trash");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let trash: Vec<TodoEntity> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(vec![2], trash.iter().map(|t| t.id).collect::<Vec<_>>());
+        assert!(trash[0].deleted_at.is_some());
+
+        let req = build_todo_req_with_empty(Method::POST, "/todos/2/restore");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let todo = res_to_todo(res).await;
+        assert_eq!(
+            TodoEntity {
+                version: 2,
+                ..TodoEntity::new(2, "trash".to_string(), vec![])
+            },
+            todo.without_timestamps()
+        );
+
+        // ゴミ箱にないTodoは復元できない
+        for uri in ["/todos/1/restore", "/todos/2/restore", "/todos/999/restore"] {
+            let req = build_todo_req_with_empty(Method::POST, uri);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::NOT_FOUND, res.status());
+        }
+
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/2?permanent=true");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let req = build_todo_req_with_empty(Method::GET, "/todos/trash");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(b"[]", &bytes[..]);
+        let req = build_todo_req_with_empty(Method::POST, "/todos/2/restore");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1?permanent=maybe");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_attach_label_to_todo() {
+        let (labels, _label_ids) = label_fixture();
+        let expected = TodoEntity::new(1, "should_attach_label".to_string(), labels.clone());
+
+        let todo_repository = TodoRepositoryForMemory::new(labels);
+        todo_repository
+            .create(1, CreateTodo::new("should_attach_label".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+
+        for version in [2, 3] {
+            let req = build_todo_req_with_empty(Method::POST, "/todos/1/labels/999");
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            let todo = res_to_todo(res).await;
+            assert_eq!(
+                TodoEntity {
+                    version,
+                    ..expected.clone()
+                },
+                todo.without_timestamps()
+            );
+        }
+
+        let req = build_todo_req_with_empty(Method::POST, "/todos/1/labels/1");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        let req = build_todo_req_with_empty(Method::POST, "/todos/2/labels/999");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_detach_label_from_todo() {
+        let (labels, label_ids) = label_fixture();
+        let expected = TodoEntity {
+            version: 2,
+            ..TodoEntity::new(1, "should_detach_label".to_string(), vec![])
+        };
+
+        let todo_repository = TodoRepositoryForMemory::new(labels);
+        todo_repository
+            .create(1, CreateTodo::new(
+                "should_detach_label".to_string(),
+                label_ids,
+            ))
+            .await
+            .expect("failed create todo");
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1/labels/999");
+        let res = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let todo = res_to_todo(res).await;
+        assert_eq!(expected, todo.without_timestamps());
+    }
+
+    // STORAGE=memoryと同じ組み合わせで、/labelsで作成したラベルをTodoへ付けられる
+    #[tokio::test]
+    async fn should_attach_created_label_in_memory_storage() {
+        let (todo_repository, label_repository) = memory_repositories();
+        let app = create_app(
+            todo_repository,
+            label_repository,
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+
+        let req = build_req_with_json(
+            "/labels",
+            Method::POST,
+            r#"{ "name": "created label" }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let label = res_to_label(res).await;
+
+        let req = build_req_with_json(
+            "/todos",
+            Method::POST,
+            format!(r#"{{ "text": "labeled", "labels": [{}] }}"#, label.id),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let todo = res_to_todo(res).await;
+        assert_eq!(vec![label.clone()], todo.labels);
+
+        let req = build_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "not labeled", "labels": [] }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        let todo = res_to_todo(res).await;
+        let req = build_todo_req_with_empty(
+            Method::POST,
+            &format!("/todos/{}/labels/{}", todo.id, label.id),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(vec![label], res_to_todo(res).await.labels);
+    }
+
+    #[tokio::test]
+    async fn should_created_label() {
+        let (labels, _label_ids) = label_fixture();
+        let expected = Label::new(1, "should_created_label".to_string());
+
+        let req = build_req_with_json(
+            "/labels",
+            Method::POST,
+            r#"{ "name": "should_created_label" }"#.to_string(),
+        );
+        let res = create_app(
+            TodoRepositoryForMemory::new(labels),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        let label = res_to_label(res).await;
+        assert_eq!(expected, label);
+    }
+
+    #[tokio::test]
+    async fn should_all_label_readed() {
+        let expected = Label::new(1, "should_all_label_readed".to_string());
+        let label_repository = LabelRepositoryForMemory::new();
+        let label = label_repository
+            .create(CreateLabel::new("should_all_label_readed".to_string()))
+            .await
+            .expect("failed create label");
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels");
+        let res = create_app(
+            TodoRepositoryForMemory::new(vec![label]),
+            label_repository,
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: String = String::from_utf8(bytes.to_vec()).unwrap();
+        let labels: Vec<Label> = serde_json::from_str(&body)
+            .unwrap_or_else(|_| panic!("cannot convert Label list instance. body: {}", body));
+        assert_eq!(vec![expected], labels);
+    }
+
+    #[tokio::test]
+    async fn should_all_label_with_todo_count() {
+        let label_repository = LabelRepositoryForMemory::new();
+        for name in ["Work", "Home"] {
+            label_repository
+                .create(CreateLabel::new(name.to_string()))
+                .await
+                .expect("failed create label");
+        }
+        label_repository.attach(1, 1).await;
+        label_repository.attach(2, 1).await;
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            label_repository,
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!("Work", body[0]["name"]);
+        assert_eq!(2, body[0]["todo_count"]);
+        assert_eq!("Home", body[1]["name"]);
+        assert_eq!(0, body[1]["todo_count"]);
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels?include_counts=false");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(2, body.as_array().unwrap().len());
+        assert!(body[0].get("todo_count").is_none());
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels?include_counts=maybe");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        let body = res_to_error(res).await;
+        assert_eq!("include_counts", body["error"]["fields"][0]["field"]);
+    }
+
+    #[tokio::test]
+    async fn should_update_label() {
+        let expected = Label::new(1, "should_update_label".to_string());
+        let label_repository = LabelRepositoryForMemory::new();
+        label_repository
+            .create(CreateLabel::new("before_update_label".to_string()))
+            .await
+            .expect("failed create label");
+
+        let req = build_req_with_json(
+            "/labels/1",
+            Method::PATCH,
+            r#"{ "name": "should_update_label" }"#.to_string(),
+        );
+        let res = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            label_repository,
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let label = res_to_label(res).await;
+        assert_eq!(expected, label);
+    }
+
+    #[tokio::test]
+    async fn should_create_and_update_label_color() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+        let req = build_req_with_json(
+            "/labels",
+            Method::POST,
+            r##"{ "name": "urgent", "color": "#FF0000", "description": "today" }"##.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let label = res_to_label(res).await;
+        assert_eq!("#FF0000", label.color);
+        assert_eq!(Some("today".to_string()), label.description);
+
+        // 省略したフィールドは変更せず、nullを指定したdescriptionは消える
+        let req = build_req_with_json(
+            "/labels/1",
+            Method::PATCH,
+            r#"{ "name": "later", "description": null }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let label = res_to_label(res).await;
+        assert_eq!("#FF0000", label.color);
+        assert_eq!(None, label.description);
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            serde_json::json!([{ "id": 1, "name": "later", "color": "#FF0000", "description": null, "todo_count": 0 }]),
+            body
+        );
+
+        let description = "a".repeat(501);
+        for (method, uri, body, field, message) in [
+            (Method::POST, "/labels", r#"{ "name": "bad", "color": "red" }"#.to_string(), "color", "Must be #RRGGBB"),
+            (Method::PATCH, "/labels/1", r##"{ "name": "bad", "color": "#12345G" }"##.to_string(), "color", "Must be #RRGGBB"),
+            (
+                Method::POST,
+                "/labels",
+                format!(r#"{{ "name": "bad", "description": "{}" }}"#, description),
+                "description",
+                "Over description length",
+            ),
+            (
+                Method::PATCH,
+                "/labels/1",
+                format!(r#"{{ "name": "bad", "description": "{}" }}"#, description),
+                "description",
+                "Over description length",
+            ),
+        ] {
+            let req = build_req_with_json(uri, method, body);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+            let body = res_to_error(res).await;
+            assert_eq!(field, body["error"]["fields"][0]["field"]);
+            assert_eq!(message, body["error"]["fields"][0]["message"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_conflict_on_duplicate_label_name() {
+        let label_repository = LabelRepositoryForMemory::new();
+        for name in ["first", "second"] {
+            label_repository
+                .create(CreateLabel::new(name.to_string()))
+                .await
+                .expect("failed create label");
+        }
+
+        let req = build_req_with_json(
+            "/labels/2",
+            Method::PATCH,
+            r#"{ "name": "first" }"#.to_string(),
+        );
+        let res = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            label_repository,
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+        let body = res_to_error(res).await;
+        assert_eq!("conflict", body["error"]["code"]);
+    }
+
+    #[tokio::test]
+    async fn should_conflict_on_duplicate_label_create() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+        let req = build_req_with_json("/labels", Method::POST, r#"{ "name": "Work" }"#.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let existing = res_to_label(res).await;
+
+        // 大文字小文字だけが違う名前も重複として扱う
+        for name in ["Work", "work"] {
+            let req = build_req_with_json(
+                "/labels",
+                Method::POST,
+                format!(r#"{{ "name": "{}" }}"#, name),
+            );
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CONFLICT, res.status());
+            let body = res_to_error(res).await;
+            assert_eq!("conflict", body["error"]["code"]);
+            assert_eq!(existing.id, body["current"]["id"]);
+            assert_eq!("Work", body["current"]["name"]);
+        }
+
+        let req = build_todo_req_with_empty(Method::GET, "/labels");
+        let res = app.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let labels: Vec<Label> = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(vec![existing], labels);
+    }
+
+    #[tokio::test]
+    async fn should_merge_label() {
+        let label_repository = LabelRepositoryForMemory::new();
+        for name in ["Work", "Job"] {
+            label_repository
+                .create(CreateLabel::new(name.to_string()))
+                .await
+                .expect("failed create label");
+        }
+        label_repository.attach(1, 1).await;
+        label_repository.attach(1, 2).await;
+        label_repository.attach(2, 2).await;
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            label_repository,
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+
+        let req = build_req_with_json("/labels/2/merge", Method::POST, r#"{ "into": 1 }"#.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(1, body["id"]);
+        assert_eq!("Work", body["name"]);
+        assert_eq!(2, body["todo_count"]);
+
+        for (uri, into) in [("/labels/2/merge", 1), ("/labels/1/merge", 2)] {
+            let req = build_req_with_json(uri, Method::POST, format!(r#"{{ "into": {} }}"#, into));
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::NOT_FOUND, res.status());
+        }
+
+        let req = build_req_with_json("/labels/1/merge", Method::POST, r#"{ "into": 1 }"#.to_string());
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        let body = res_to_error(res).await;
+        assert_eq!("into", body["error"]["fields"][0]["field"]);
+    }
+
+    #[tokio::test]
+    async fn should_not_found_on_update_missing_label() {
+        let req = build_req_with_json(
+            "/labels/999",
+            Method::PATCH,
+            r#"{ "name": "missing" }"#.to_string(),
+        );
+        let res = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_export_and_import_backup() {
+        let (labels, label_ids) = label_fixture();
+        let app = create_app(
+            TodoRepositoryForMemory::new(labels),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+        let req = build_req_with_json(
+            "/todos",
+            Method::POST,
+            serde_json::to_string(&CreateTodo::new("backup".to_string(), label_ids.clone()))
+                .unwrap(),
+        );
+        let todo = res_to_todo(app.clone().oneshot(req).await.unwrap()).await;
+
+        let req = build_todo_req_with_empty(Method::GET, "/export");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let mut backup: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(todo.id, backup["todos"][0]["id"]);
+        assert_eq!(
+            serde_json::json!([{ "todo_id": todo.id, "label_id": label_ids[0] }]),
+            backup["associations"]
+        );
+
+        // 不正なレコードがあれば何も取り込まず、その位置を返す
+        let mut invalid = backup.clone();
+        invalid["todos"][0]["text"] = serde_json::json!("");
+        let req = build_req_with_json("/import", Method::POST, invalid.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+        let body = res_to_error(res).await;
+        assert_eq!("todos[0].text", body["error"]["fields"][0]["field"]);
+
+        backup["todos"][0]["text"] = serde_json::json!("restored");
+        let req = build_req_with_json("/import", Method::POST, backup.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let summary: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(serde_json::json!({ "todos": 1, "labels": 0 }), summary);
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!("2", res.headers()[TOTAL_COUNT_HEADER]);
+    }
+
+    #[tokio::test]
+    async fn should_stream_todos_as_ndjson() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        // チャンクの境界をまたぐ件数を登録し、1件はゴミ箱へ移す
+        for i in 0..100 {
+            repository
+                .create(1, CreateTodo::new(format!("todo {}", i), vec![]))
+                .await
+                .expect("failed create todo");
+        }
+        repository
+            .delete(1, 50, Precondition::default())
+            .await
+            .expect("failed delete todo");
+        let app = create_app(
+            repository,
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/export?format=ndjson");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(
+            "application/x-ndjson",
+            res.headers()[header::CONTENT_TYPE]
+        );
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(body.ends_with('\n'));
+        let todos: Vec<TodoEntity> = body
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("each line must be a todo"))
+            .collect();
+        assert_eq!(99, todos.len());
+        assert!(todos.windows(2).all(|pair| pair[0].id < pair[1].id));
+        assert!(todos.iter().all(|todo| todo.id != 50));
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos/export?format=csv");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+    }
+
+    fn gunzip(bytes: &[u8]) -> Vec<u8> {
+        use std::io::Read;
+        let mut body = vec![];
+        flate2::read::GzDecoder::new(bytes)
+            .read_to_end(&mut body)
+            .expect("body must be gzip");
+        body
+    }
+
+    #[tokio::test]
+    async fn should_compress_responses_for_accept_encoding() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        for i in 0..100 {
+            repository
+                .create(1, CreateTodo::new(format!("todo {}", i), vec![]))
+                .await
+                .expect("failed create todo");
+        }
+        let app = create_app(
+            repository,
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+
+        for path in ["/todos", "/todos/export"] {
+            let req = build_todo_req_with_empty(Method::GET, path);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert!(!res.headers().contains_key(header::CONTENT_ENCODING));
+            let plain = hyper::body::to_bytes(res.into_body()).await.unwrap();
+
+            let mut req = build_todo_req_with_empty(Method::GET, path);
+            req.headers_mut()
+                .insert(header::ACCEPT_ENCODING, HeaderValue::from_static("gzip"));
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            assert_eq!("gzip", res.headers()[header::CONTENT_ENCODING]);
+            let compressed = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            assert!(compressed.len() < plain.len());
+            assert_eq!(plain.to_vec(), gunzip(&compressed));
+        }
+
+        let mut req = build_todo_req_with_empty(Method::GET, "/todos");
+        req.headers_mut()
+            .insert(header::ACCEPT_ENCODING, HeaderValue::from_static("br"));
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!("br", res.headers()[header::CONTENT_ENCODING]);
+    }
+
+    #[tokio::test]
+    async fn should_serve_static_files_with_spa_fallback() {
+        let dir = std::env::temp_dir().join(format!("rust-todo-{}-static", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("assets")).unwrap();
+        std::fs::write(dir.join("index.html"), "<div id=\"app\"></div>").unwrap();
+        std::fs::write(dir.join("assets/app.1a2b3c.js"), "console.log(1)").unwrap();
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .layer(Extension(StaticFiles::new(dir)));
+
+        let req = build_todo_req_with_empty(Method::GET, "/assets/app.1a2b3c.js");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(
+            "public, max-age=31536000, immutable",
+            res.headers()[header::CACHE_CONTROL]
+        );
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!("console.log(1)", body);
+
+        for path in ["/", "/some/client/route"] {
+            let req = build_todo_req_with_empty(Method::GET, path);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            assert_eq!("no-cache", res.headers()[header::CACHE_CONTROL]);
+            assert!(res.headers()[header::CONTENT_TYPE]
+                .to_str()
+                .unwrap()
+                .starts_with("text/html"));
+            let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            assert_eq!("<div id=\"app\"></div>", body);
+        }
+
+        // APIのルートはそのまま
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("application/json", res.headers()[header::CONTENT_TYPE]);
+
+        // APIの下の存在しないパス、存在しないアセット、GET以外はJSONの404
+        for (method, path) in [
+            (Method::GET, "/todos/1/unknown"),
+            (Method::GET, "/api/v1/unknown"),
+            (Method::GET, "/assets/missing.js"),
+            (Method::POST, "/some/client/route"),
+        ] {
+            let req = build_todo_req_with_empty(method, path);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::NOT_FOUND, res.status());
+            let body = res_to_error(res).await;
+            assert_eq!("not_found", body["error"]["code"]);
+            assert_eq!(path, body["error"]["path"]);
+        }
+    }
+
+    fn build_req_with_body(path: &str, content_type: &str, body: String) -> Request<Body> {
+        Request::builder()
+            .uri(path)
+            .method(Method::POST)
+            .header(header::CONTENT_TYPE, content_type)
+            .header(header::AUTHORIZATION, format!("Bearer {}", test_token(1)))
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_import_todos_from_csv() {
+        let (labels, _) = label_fixture();
+        let app = create_app(
+            TodoRepositoryForMemory::new(labels),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+
+        let csv = "text,labels\nfirst,\"Test Label, new label\"\n,\nsecond,new label\n";
+        let req = build_req_with_body("/import/csv", "text/csv", csv.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let summary: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "imported": 2,
+                "skipped": [{ "line": 3, "reason": "text: Can not be empty" }],
+            }),
+            summary
+        );
+
+        // 既存のラベルは名前で引き当て、存在しないラベルは1度だけ作成する
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let todos: Vec<TodoEntity> = serde_json::from_slice(&bytes).unwrap();
+        let label_names = |text: &str| -> Vec<(i32, String)> {
+            todos
+                .iter()
+                .find(|todo| todo.text == text)
+                .unwrap()
+                .labels
+                .iter()
+                .map(|label| (label.id, label.name.clone()))
+                .collect()
+        };
+        assert_eq!(
+            vec![(999, "test label".to_string()), (1000, "new label".to_string())],
+            label_names("first")
+        );
+        assert_eq!(vec![(1000, "new label".to_string())], label_names("second"));
+
+        let boundary = "todo-boundary";
+        let multipart = format!(
+            "--{b}\r\nContent-Disposition: form-data; name=\"file\"; filename=\"todoist.csv\"\r\n\
+            Content-Type: text/csv\r\n\r\nTYPE,CONTENT,PRIORITY\r\ntask,Call mom @family,4\r\n\r\n--{b}--\r\n",
+            b = boundary
+        );
+        let req = build_req_with_body(
+            "/import/csv",
+            &format!("multipart/form-data; boundary={}", boundary),
+            multipart,
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let summary: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(1, summary["imported"]);
+
+        let req = build_req_with_body("/import/csv", "text/csv", "name\nwork\n".to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNPROCESSABLE_ENTITY, res.status());
+        let body = res_to_error(res).await;
+        assert_eq!("invalid_csv", body["error"]["code"]);
+
+        let req = build_req_with_body("/import/csv", "text/plain", "text\nfirst\n".to_string());
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNSUPPORTED_MEDIA_TYPE, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_limit_request_body_size() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+        let app = with_body_limits(
+            app,
+            BodyLimits {
+                api: 1024,
+                bulk: 16 * 1024,
+            },
+        );
+
+        let oversized = format!(r#"{{ "text": "{}", "labels": [] }}"#, "a".repeat(2000));
+        // 長さを宣言しない本文は、読んだ分が上限を超えた時点で打ち切る
+        let req = build_req_with_json("/todos", Method::POST, oversized.clone());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, res.status());
+        let body = res_to_error(res).await;
+        assert_eq!("payload_too_large", body["error"]["code"]);
+        assert_eq!(
+            "Request body exceeds the limit of 1024 bytes",
+            body["error"]["message"]
+        );
+
+        let mut req = build_req_with_json("/todos", Method::POST, oversized.clone());
+        req.headers_mut()
+            .insert(header::CONTENT_LENGTH, oversized.len().into());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, res.status());
+
+        // 一括登録のルートは上限が大きい
+        let rows: String = (0..500).map(|i| format!("todo {}\n", i)).collect();
+        let csv = format!("text\n{}", rows);
+        assert!(csv.len() > 1024);
+        let req = build_req_with_body("/import/csv", "text/csv", csv);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let summary: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(500, summary["imported"]);
+
+        let csv = format!("text\n{}", "todo\n".repeat(4000));
+        let req = build_req_with_body("/import/csv", "text/csv", csv);
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, res.status());
+        let body = res_to_error(res).await;
+        assert_eq!(
+            "Request body exceeds the limit of 16384 bytes",
+            body["error"]["message"]
+        );
+    }
+
+    #[tokio::test]
+    async fn should_pass_logged_bodies_through() {
+        use http_body::Body as _;
+
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .layer(Extension(LogBodies(true)));
+
+        // 記録のために読んだ本文を、ハンドラも最後まで受け取れる
+        let req = build_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "logged todo", "labels": [] }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let todo = res_to_todo(res).await;
+        assert_eq!("logged todo", todo.text);
+
+        // 上限を超える本文は読まずにそのまま渡す
+        let csv = format!("text\n{}", "todo\n".repeat(4000));
+        let req = build_req_with_body("/import/csv", "text/csv", csv);
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let summary: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(4000, summary["imported"]);
+
+        // ストリーミングのレスポンスはバッファせずに返す
+        let req = build_todo_req_with_empty(Method::GET, "/todos/export");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(None, res.body().size_hint().exact());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = String::from_utf8(bytes.to_vec()).unwrap();
+        assert_eq!(4001, body.lines().count());
+    }
+
+    #[tokio::test]
+    async fn should_return_todo_stats() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        scenario(&repository, 1).await;
+        TodoFixture::new("other user")
+            .owned_by(2)
+            .with_label("work")
+            .insert(&repository)
+            .await;
+
+        let req = build_todo_req_with_empty(Method::GET, "/stats");
+        let res = create_app(
+            repository,
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let stats: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "total": 5,
+                "open": 3,
+                "completed": 2,
+                "created_last_7_days": 4,
+                "labels": [
+                    { "id": 1, "name": "work", "count": 2 },
+                    { "id": 2, "name": "home", "count": 2 },
+                    { "id": 3, "name": "errand", "count": 1 },
+                ],
+            }),
+            stats
+        );
+    }
+
+    #[tokio::test]
+    async fn should_register_and_login_user() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+
+        let credentials = r#"{ "username": "alice", "password": "correct horse" }"#;
+        let req = build_req_with_json("/auth/register", Method::POST, credentials.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(1, body["user"]["id"]);
+        assert_eq!("alice", body["user"]["username"]);
+        assert!(body["user"].get("password_hash").is_none());
+
+        let req = build_req_with_json("/auth/register", Method::POST, credentials.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CONFLICT, res.status());
+
+        let req = build_req_with_json("/auth/login", Method::POST, credentials.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        let token = body["token"].as_str().unwrap().to_string();
+
+        // 発行されたトークンで保護されたルートにアクセスできる
+        let req = Request::builder()
+            .uri("/users/1")
+            .method(Method::GET)
+            .header(header::AUTHORIZATION, format!("Bearer {}", token))
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+
+        let req = build_req_with_json(
+            "/auth/login",
+            Method::POST,
+            r#"{ "username": "alice", "password": "wrong password" }"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        let body = res_to_error(res).await;
+        assert_eq!("invalid_credentials", body["error"]["code"]);
+    }
+
+    #[tokio::test]
+    async fn should_not_find_other_user() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+
+        let mut tokens = vec![];
+        for username in ["alice", "bob"] {
+            let credentials = format!(
+                r#"{{ "username": "{}", "password": "correct horse" }}"#,
+                username
+            );
+            let req = build_req_with_json("/auth/register", Method::POST, credentials);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+            let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+            tokens.push(body["token"].as_str().unwrap().to_string());
+        }
+
+        // bobのトークンではbob自身だけが見え、aliceは存在しない扱いになる
+        for (path, status) in [
+            ("/users/2", StatusCode::OK),
+            ("/users/1", StatusCode::NOT_FOUND),
+        ] {
+            let req = Request::builder()
+                .uri(path)
+                .method(Method::GET)
+                .header(header::AUTHORIZATION, format!("Bearer {}", tokens[1]))
+                .body(Body::empty())
+                .unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(status, res.status(), "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_reject_request_without_token() {
+        let req = Request::builder()
+            .uri("/todos")
+            .method(Method::GET)
+            .body(Body::empty())
+            .unwrap();
+        let res = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        let body = res_to_error(res).await;
+        assert_eq!("unauthorized", body["error"]["code"]);
+    }
+
+    #[tokio::test]
+    async fn should_reject_malformed_token() {
+        let req = Request::builder()
+            .uri("/labels")
+            .method(Method::GET)
+            .header(header::AUTHORIZATION, "Bearer not.a.token")
+            .body(Body::empty())
+            .unwrap();
+        let res = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+        let body = res_to_error(res).await;
+        assert_eq!("unauthorized", body["error"]["code"]);
+    }
+
+    #[tokio::test]
+    async fn should_serve_on_ephemeral_port() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let origin = HeaderValue::from_static("http://localhost:3000");
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .layer(cors_layer(vec![origin.clone()], Duration::from_secs(600)));
+        tokio::spawn(serve(listener, app, std::future::pending()));
+
+        let req = Request::builder()
+            .uri(format!("http://{}/todos", addr))
+            .method(Method::GET)
+            .header(header::ORIGIN, origin.clone())
+            .header(header::AUTHORIZATION, format!("Bearer {}", test_token(1)))
+            .body(Body::empty())
+            .unwrap();
+        let res = hyper::Client::new().request(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(origin, res.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN]);
+    }
+
+    #[tokio::test]
+    async fn should_answer_preflight_for_each_allowed_origin() {
+        let origins = [
+            "https://todo.example.com",
+            "https://staging.todo.example.com",
+        ];
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .layer(cors_layer(
+            origins
+                .iter()
+                .map(|origin| HeaderValue::from_static(origin))
+                .collect(),
+            Duration::from_secs(600),
+        ));
+        let preflight = |origin: &str, method: Method| {
+            Request::builder()
+                .uri("/todos/1")
+                .method(Method::OPTIONS)
+                .header(header::ORIGIN, origin)
+                .header(header::ACCESS_CONTROL_REQUEST_METHOD, method.as_str())
+                .header(
+                    header::ACCESS_CONTROL_REQUEST_HEADERS,
+                    "authorization,content-type",
+                )
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        for origin in origins {
+            let res = app
+                .clone()
+                .oneshot(preflight(origin, Method::PATCH))
+                .await
+                .unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            let headers = res.headers();
+            // *ではなく、リクエストのオリジンをそのまま返す
+            assert_eq!(origin, headers[header::ACCESS_CONTROL_ALLOW_ORIGIN]);
+            assert_eq!("true", headers[header::ACCESS_CONTROL_ALLOW_CREDENTIALS]);
+            assert_eq!("600", headers[header::ACCESS_CONTROL_MAX_AGE]);
+            assert_eq!(
+                "GET,HEAD,POST,PUT,PATCH,DELETE",
+                headers[header::ACCESS_CONTROL_ALLOW_METHODS]
+            );
+            assert!(headers
+                .get_all(header::VARY)
+                .iter()
+                .any(|value| value == "origin"));
+        }
+
+        // 許可していないオリジンには、CORSのヘッダーを何も返さない
+        let res = app
+            .clone()
+            .oneshot(preflight("https://evil.example.com", Method::PATCH))
+            .await
+            .unwrap();
+        assert!(res
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none());
+        assert!(res
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_CREDENTIALS)
+            .is_none());
+        // ルーティングしていないメソッドも許可しない
+        let res = app
+            .oneshot(preflight(origins[0], Method::TRACE))
+            .await
+            .unwrap();
+        assert!(res
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .is_none());
+    }
+
+    async fn connect_ws(
+        addr: std::net::SocketAddr,
+    ) -> tokio_tungstenite::WebSocketStream<tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>>
+    {
+        use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+        let mut req = format!("ws://{}/ws", addr).into_client_request().unwrap();
+        req.headers_mut().insert(
+            header::AUTHORIZATION,
+            format!("Bearer {}", test_token(1)).parse().unwrap(),
+        );
+        let (socket, _) = tokio_tungstenite::connect_async(req).await.unwrap();
+        socket
+    }
+
+    async fn next_frame<S>(socket: &mut S) -> tokio_tungstenite::tungstenite::Message
+    where
+        S: futures_util::Stream<
+                Item = Result<
+                    tokio_tungstenite::tungstenite::Message,
+                    tokio_tungstenite::tungstenite::Error,
+                >,
+            > + Unpin,
+    {
+        use futures_util::StreamExt;
+
+        tokio::time::timeout(Duration::from_secs(5), socket.next())
+            .await
+            .expect("no frame within timeout")
+            .unwrap()
+            .unwrap()
+    }
+
+    async fn next_json<S>(socket: &mut S) -> serde_json::Value
+    where
+        S: futures_util::Stream<
+                Item = Result<
+                    tokio_tungstenite::tungstenite::Message,
+                    tokio_tungstenite::tungstenite::Error,
+                >,
+            > + Unpin,
+    {
+        let frame = next_frame(socket).await;
+        serde_json::from_str(frame.to_text().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_sync_todos_over_websocket() {
+        use futures_util::SinkExt;
+        use tokio_tungstenite::tungstenite::Message;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let events = TodoEvents::new();
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .layer(Extension(events.clone()));
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        let server = tokio::spawn(serve(listener, app, async move {
+            stopped.await.ok();
+            events.shutdown(Duration::from_secs(5)).await;
+        }));
+
+        let mut sender = connect_ws(addr).await;
+        let mut watcher = connect_ws(addr).await;
+
+        // 送信した接続には作成結果が、他の接続にはイベントが届く
+        sender
+            .send(Message::Text(
+                r#"{"type":"create","text":"from ws","labels":[]}"#.to_string(),
+            ))
+            .await
+            .unwrap();
+        let created = next_json(&mut sender).await;
+        assert_eq!("created", created["type"]);
+        assert_eq!("from ws", created["todo"]["text"]);
+        assert_eq!(created, next_json(&mut watcher).await);
+
+        // 不正なフレームはエラーを返すのみで、接続は維持する
+        sender
+            .send(Message::Text("not json".to_string()))
+            .await
+            .unwrap();
+        let error = next_json(&mut sender).await;
+        assert_eq!("error", error["type"]);
+        assert_eq!("invalid_json", error["error"]["code"]);
+
+        // HTTP経由の変更も配信される
+        let id = created["todo"]["id"].as_i64().unwrap();
+        let req = Request::builder()
+            .uri(format!("http://{}/todos/{}", addr, id))
+            .method(Method::PATCH)
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .header(header::AUTHORIZATION, format!("Bearer {}", test_token(1)))
+            .body(Body::from(r#"{"completed":true}"#))
+            .unwrap();
+        let res = hyper::Client::new().request(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        for socket in [&mut sender, &mut watcher] {
+            let updated = next_json(socket).await;
+            assert_eq!("updated", updated["type"]);
+            assert_eq!(true, updated["todo"]["completed"]);
+        }
+
+        // サーバーの終了時はCloseフレームを受け取る
+        stop.send(()).unwrap();
+        for socket in [&mut sender, &mut watcher] {
+            match next_frame(socket).await {
+                Message::Close(Some(frame)) => assert_eq!(1001, u16::from(frame.code)),
+                frame => panic!("expected close frame, got {:?}", frame),
+            }
+        }
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn should_report_healthy_and_ready() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+
+        // プローブは認証なしで呼び出せる
+        for path in ["/healthz", "/readyz"] {
+            let req = Request::builder()
+                .uri(path)
+                .method(Method::GET)
+                .body(Body::empty())
+                .unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+        }
+    }
+
+    #[tokio::test]
+    async fn should_not_ready_when_database_is_unavailable() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForUnavailable,
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+
+        let req = Request::builder()
+            .uri("/readyz")
+            .method(Method::GET)
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, res.status());
+        let body = res_to_error(res).await;
+        assert_eq!("not_ready", body["error"]["code"]);
+        assert_eq!("Database is unavailable", body["error"]["message"]);
+
+        // livenessはデータベースの状態に依存しない
+        let req = Request::builder()
+            .uri("/healthz")
+            .method(Method::GET)
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_return_request_id() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert!(!res.headers()[REQUEST_ID_HEADER].is_empty());
+
+        // クライアントが指定したidはそのまま返す
+        let req = Request::builder()
+            .uri("/todoz")
+            .method(Method::GET)
+            .header(REQUEST_ID_HEADER, "client-request-id")
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        assert_eq!("client-request-id", res.headers()[REQUEST_ID_HEADER]);
+    }
+
+    #[tokio::test]
+    async fn should_count_requests_in_metrics() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+        // 設定で有効にしない限り/metricsは公開しない
+        let req = build_todo_req_with_empty(Method::GET, "/metrics");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        let app = with_metrics(app, Metrics::new(None).unwrap());
+        for uri in ["/todos", "/todos", "/todos/1", "/todoz"] {
+            let req = build_todo_req_with_empty(Method::GET, uri);
+            app.clone().oneshot(req).await.unwrap();
+        }
+
+        let req = build_todo_req_with_empty(Method::GET, "/metrics");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = String::from_utf8(bytes.to_vec()).unwrap();
+        for expected in [
+            r#"http_requests_total{method="GET",route="/todos",status="200"} 2"#,
+            r#"http_requests_total{method="GET",route="/todos/:id",status="404"} 1"#,
+            r#"http_requests_total{method="GET",route="unmatched",status="404"} 1"#,
+            r#"http_request_duration_seconds_count{method="GET",route="/todos"} 2"#,
+        ] {
+            assert!(body.contains(expected), "missing [{}] in\n{}", expected, body);
+        }
+    }
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn should_return_service_unavailable_when_pool_is_exhausted() {
+        dotenv::dotenv().ok();
+        let database_url = std::env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = sqlx::postgres::PgPoolOptions::new()
+            .max_connections(1)
+            .connect_timeout(Duration::from_millis(200))
+            .connect(&database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+        let app = create_app(
+            TodoRepositoryForDb::new(pool.clone(), SystemClock),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+        let app = with_metrics(app, Metrics::new(Some(pool.clone())).unwrap());
+
+        // 唯一の接続を握ったままにし、リクエストが接続を待ってタイムアウトするようにする
+        let held = pool.acquire().await.unwrap();
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, res.status());
+        let error = res_to_error(res).await;
+        assert_eq!("database_unavailable", error["error"]["code"]);
+
+        let req = build_todo_req_with_empty(Method::GET, "/metrics");
+        let res = app.clone().oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = String::from_utf8(bytes.to_vec()).unwrap();
+        for expected in [
+            "db_pool_connections 1",
+            "db_pool_idle_connections 0",
+            "db_pool_acquire_timeouts_total 1",
+        ] {
+            assert!(body.contains(expected), "missing [{}] in\n{}", expected, body);
+        }
+
+        drop(held);
+        let req = build_todo_req_with_empty(Method::GET, "/todos");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_serve_legacy_paths_as_deprecated_aliases() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+        let req = build_req_with_json(
+            "/api/v1/todos",
+            Method::POST,
+            r#"{ "text": "should_serve_versioned_routes", "labels": [] }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        assert!(!res.headers().contains_key("deprecation"));
+
+        for path in ["/todos", "/todos/1", "/stats"] {
+            let req = build_todo_req_with_empty(Method::GET, &format!("/api/v1{}", path));
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            assert!(!res.headers().contains_key("deprecation"));
+            assert!(!res.headers().contains_key("sunset"));
+            let current = hyper::body::to_bytes(res.into_body()).await.unwrap();
+
+            let req = build_todo_req_with_empty(Method::GET, path);
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::OK, res.status());
+            assert_eq!("@1793491200", res.headers()["deprecation"]);
+            assert_eq!("Sat, 01 May 2027 00:00:00 GMT", res.headers()["sunset"]);
+            let legacy = hyper::body::to_bytes(res.into_body()).await.unwrap();
+            assert_eq!(current, legacy);
+        }
+
+        // エラーも同じ形式で返し、非推奨のパスにはヘッダーを付ける
+        let req = build_todo_req_with_empty(Method::GET, "/todos/99");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        assert!(res.headers().contains_key("deprecation"));
+
+        // プレフィックスの下の存在しないパスはJSONの404
+        let req = build_todo_req_with_empty(Method::GET, "/api/v1/todoz");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let body = res_to_error(res).await;
+        assert_eq!("/api/v1/todoz", body["error"]["path"]);
+
+        // ヘルスチェックはバージョンによらない
+        let req = build_todo_req_with_empty(Method::GET, "/healthz");
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert!(!res.headers().contains_key("deprecation"));
+    }
+
+    #[tokio::test]
+    async fn should_mount_routes_under_configured_prefix() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            "/todo-api/v2",
+        );
+        let req = build_todo_req_with_empty(Method::GET, "/todo-api/v2/todos");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let req = build_todo_req_with_empty(Method::GET, "/api/v1/todos");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+
+        let req = build_todo_req_with_empty(Method::GET, OPENAPI_PATH);
+        let res = app.oneshot(req).await.unwrap();
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let spec: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!("/todo-api/v2", spec["servers"][0]["url"]);
+    }
+
+    #[tokio::test]
+    async fn should_serve_openapi_spec() {
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+
+        // ドキュメントは認証なしで参照できる
+        let req = Request::builder()
+            .uri("/api-docs/openapi.json")
+            .method(Method::GET)
+            .body(Body::empty())
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let spec: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!("/api/v1", spec["servers"][0]["url"]);
+        let paths = &spec["paths"];
+        assert!(paths["/todos"]["get"].is_object());
+        assert!(paths["/todos"]["post"].is_object());
+        let update = &paths["/todos/{id}"]["patch"];
+        assert!(update["responses"]["404"].is_object());
+        assert!(update["responses"]["409"].is_object());
+        assert!(paths["/labels"]["post"]["responses"]["409"].is_object());
+        // X-Namingは全ての操作に載せる
+        for operation in [&paths["/todos"]["get"], &paths["/labels"]["post"], update] {
+            assert!(
+                operation["parameters"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .any(|parameter| parameter["name"] == NAMING_HEADER
+                        && parameter["in"] == "header")
+            );
+        }
+        let schemas = &spec["components"]["schemas"];
+        for name in ["TodoEntity", "CreateTodo", "UpdateTodo", "Label", "CreateLabel", "ErrorBody"] {
+            assert!(schemas[name].is_object(), "missing schema {}", name);
+        }
+
+        let req = Request::builder()
+            .uri("/swagger-ui")
+            .method(Method::GET)
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        let body = String::from_utf8(bytes.to_vec()).unwrap();
+        assert!(body.contains("/api-docs/openapi.json"));
+    }
+
+    #[tokio::test]
+    async fn should_execute_graphql_query() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        todo_repository
+            .create(1, CreateTodo::new("mine".to_string(), vec![]))
+            .await
+            .unwrap();
+        todo_repository
+            .create(2, CreateTodo::new("theirs".to_string(), vec![]))
+            .await
+            .unwrap();
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+        let query = r#"{"query": "{ todos { text } }"}"#;
+
+        // RESTと同じく、トークンがなければ401を返す
+        let req = Request::builder()
+            .uri(GRAPHQL_PATH)
+            .method(Method::POST)
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(Body::from(query))
+            .unwrap();
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::UNAUTHORIZED, res.status());
+
+        let req = build_req_with_json(GRAPHQL_PATH, Method::POST, query.to_string());
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let body = res_to_json(res).await;
+        assert_eq!(serde_json::json!({ "todos": [{ "text": "mine" }] }), body["data"]);
+
+        // クエリの誤りはerrorsとして返す
+        let req = build_req_with_json(
+            GRAPHQL_PATH,
+            Method::POST,
+            r#"{"query": "{ todos { unknown } }"}"#.to_string(),
+        );
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let body = res_to_json(res).await;
+        assert_eq!(1, body["errors"].as_array().unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn should_return_json_for_unknown_route() {
+        let req = build_todo_req_with_empty(Method::GET, "/todoz");
+        let res = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        let body = res_to_error(res).await;
+        assert_eq!("not_found", body["error"]["code"]);
+        assert_eq!("/todoz", body["error"]["path"]);
+    }
+
+    #[tokio::test]
+    async fn should_return_json_and_allow_for_wrong_method() {
+        let req = build_todo_req_with_empty(Method::POST, "/todos/1");
+        let res = create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::METHOD_NOT_ALLOWED, res.status());
+        let allow = res.headers()[header::ALLOW].to_str().unwrap().to_string();
+        let mut methods: Vec<&str> = allow.split(',').collect();
+        methods.sort_unstable();
+        assert_eq!(vec!["DELETE", "GET", "HEAD", "PATCH", "PUT"], methods);
+        let body = res_to_error(res).await;
+        assert_eq!("method_not_allowed", body["error"]["code"]);
+    }
+
+    #[tokio::test]
+    async fn should_answer_head_like_get() {
+        let todo_repository = TodoRepositoryForMemory::new(vec![]);
+        todo_repository
+            .create(1, CreateTodo::new("head".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        let app = create_app(
+            todo_repository,
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+
+        for path in ["/todos", "/todos/1"] {
+            let get = app
+                .clone()
+                .oneshot(build_todo_req_with_empty(Method::GET, path))
+                .await
+                .unwrap();
+            let head = app
+                .clone()
+                .oneshot(build_todo_req_with_empty(Method::HEAD, path))
+                .await
+                .unwrap();
+            assert_eq!(StatusCode::OK, head.status(), "{}", path);
+            for name in [header::CONTENT_TYPE, header::ETAG] {
+                assert_eq!(get.headers()[&name], head.headers()[&name], "{}", path);
+            }
+            let body = hyper::body::to_bytes(get.into_body()).await.unwrap();
+            assert_eq!(
+                body.len().to_string(),
+                head.headers()[header::CONTENT_LENGTH],
+                "{}",
+                path
+            );
+            let head_body = hyper::body::to_bytes(head.into_body()).await.unwrap();
+            assert!(head_body.is_empty(), "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_answer_options_with_routed_methods() {
+        let app = with_allowed_methods(create_app(
+            TodoRepositoryForMemory::new(vec![]),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        ));
+
+        for (path, expected) in [
+            ("/todos", vec!["GET", "HEAD", "OPTIONS", "PATCH", "POST"]),
+            (
+                "/api/v1/todos",
+                vec!["GET", "HEAD", "OPTIONS", "PATCH", "POST"],
+            ),
+            (
+                "/todos/1",
+                vec!["DELETE", "GET", "HEAD", "OPTIONS", "PATCH", "PUT"],
+            ),
+        ] {
+            // 認証なしで答える
+            let req = Request::builder()
+                .uri(path)
+                .method(Method::OPTIONS)
+                .body(Body::empty())
+                .unwrap();
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::NO_CONTENT, res.status(), "{}", path);
+            let allow = res.headers()[header::ALLOW].to_str().unwrap().to_string();
+            let mut methods: Vec<&str> = allow.split(',').collect();
+            methods.sort_unstable();
+            assert_eq!(expected, methods, "{}", path);
+
+            // Allowに含むメソッドのみルーティングされている
+            for method in [
+                Method::GET,
+                Method::HEAD,
+                Method::POST,
+                Method::PUT,
+                Method::PATCH,
+                Method::DELETE,
+            ] {
+                let res = app
+                    .clone()
+                    .oneshot(build_todo_req_with_empty(method.clone(), path))
+                    .await
+                    .unwrap();
+                assert_eq!(
+                    methods.contains(&method.as_str()),
+                    res.status() != StatusCode::METHOD_NOT_ALLOWED,
+                    "{} {}",
+                    method,
+                    path
+                );
+                // 405のAllowもOPTIONSを含めて同じ
+                if res.status() == StatusCode::METHOD_NOT_ALLOWED {
+                    assert_eq!(allow, res.headers()[header::ALLOW], "{} {}", method, path);
+                }
+            }
+        }
+
+        // 存在しないパスは404のまま
+        let req = Request::builder()
+            .uri("/unknown")
+            .method(Method::OPTIONS)
+            .body(Body::empty())
+            .unwrap();
+        let res = app.oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_revalidate_todos_with_last_modified() {
+        let clock = MockClock::default();
+        let app = create_app(
+            TodoRepositoryForMemory::new(vec![]).with_clock(clock.clone()),
+            LabelRepositoryForMemory::new(),
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        );
+        let get_todos = |since: &str| {
+            with_header(
+                build_todo_req_with_empty(Method::GET, "/todos"),
+                header::IF_MODIFIED_SINCE,
+                since,
+            )
+        };
+
+        // Todoがない間はLast-Modifiedを返さない
+        let res = app
+            .clone()
+            .oneshot(build_todo_req_with_empty(Method::GET, "/todos"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("no-cache", res.headers()[header::CACHE_CONTROL]);
+        assert!(!res.headers().contains_key(header::LAST_MODIFIED));
+
+        let req = build_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "cached", "labels": [] }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        assert_eq!("no-store", res.headers()[header::CACHE_CONTROL]);
+
+        let created_at = http_date(clock.now());
+        let res = app
+            .clone()
+            .oneshot(build_todo_req_with_empty(Method::GET, "/todos"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(created_at, res.headers()[header::LAST_MODIFIED]);
+
+        let res = app.clone().oneshot(get_todos(&created_at)).await.unwrap();
+        assert_eq!(StatusCode::NOT_MODIFIED, res.status());
+        assert_eq!("no-cache", res.headers()[header::CACHE_CONTROL]);
+        assert_eq!(created_at, res.headers()[header::LAST_MODIFIED]);
+        assert!(hyper::body::to_bytes(res.into_body())
+            .await
+            .unwrap()
+            .is_empty());
+
+        // If-None-Matchがある場合はETagのみで判断する
+        let req = with_header(get_todos(&created_at), header::IF_NONE_MATCH, "W/\"stale\"");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+
+        // 更新後は同じIf-Modified-Sinceでも本文を返し、失敗した変更にもno-storeを付ける
+        clock.advance(chrono::Duration::seconds(1));
+        let req = build_req_with_json(
+            "/todos/1",
+            Method::PATCH,
+            r#"{ "completed": true }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        assert_eq!("no-store", res.headers()[header::CACHE_CONTROL]);
+        let req = build_req_with_json(
+            "/todos/999",
+            Method::PATCH,
+            r#"{ "completed": true }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, res.status());
+        assert_eq!("no-store", res.headers()[header::CACHE_CONTROL]);
+
+        let updated_at = http_date(clock.now());
+        let res = app.clone().oneshot(get_todos(&created_at)).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(updated_at, res.headers()[header::LAST_MODIFIED]);
+        assert_eq!(1, res_to_json(res).await.as_array().unwrap().len());
+        let res = app.clone().oneshot(get_todos(&updated_at)).await.unwrap();
+        assert_eq!(StatusCode::NOT_MODIFIED, res.status());
+
+        // ゴミ箱への移動もupdated_atは変えないが、一覧の変更として扱う
+        clock.advance(chrono::Duration::seconds(1));
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        assert_eq!("no-store", res.headers()[header::CACHE_CONTROL]);
+        let res = app.clone().oneshot(get_todos(&updated_at)).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(http_date(clock.now()), res.headers()[header::LAST_MODIFIED]);
+        assert_eq!(serde_json::json!([]), res_to_json(res).await);
+
+        // 完全な削除は行ごと消えるが、一覧の変更日時は進める
+        let trashed_at = http_date(clock.now());
+        clock.advance(chrono::Duration::seconds(1));
+        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1?permanent=true");
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+        let res = app.clone().oneshot(get_todos(&trashed_at)).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(http_date(clock.now()), res.headers()[header::LAST_MODIFIED]);
+
+        // 並べ替えもupdated_atを変えないが、一覧の順序が変わる
+        for text in ["first", "second"] {
+            let req = build_req_with_json(
+                "/todos",
+                Method::POST,
+                format!(r#"{{ "text": "{}", "labels": [] }}"#, text),
+            );
+            let res = app.clone().oneshot(req).await.unwrap();
+            assert_eq!(StatusCode::CREATED, res.status());
+        }
+        let created_at = http_date(clock.now());
+        clock.advance(chrono::Duration::seconds(1));
+        let req = build_req_with_json(
+            "/todos/2/move",
+            Method::POST,
+            r#"{ "to_top": true }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        let res = app.clone().oneshot(get_todos(&created_at)).await.unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!(http_date(clock.now()), res.headers()[header::LAST_MODIFIED]);
+    }
+
+    #[tokio::test]
+    async fn should_apply_configured_cache_policy() {
+        let app = memory_app().layer(Extension(CachePolicy {
+            labels: HeaderValue::from_static("public, max-age=300"),
+            todos: HeaderValue::from_static("private, no-cache"),
+            mutations: HeaderValue::from_static("no-store, private"),
+        }));
+
+        let res = app
+            .clone()
+            .oneshot(build_todo_req_with_empty(Method::GET, "/labels"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("public, max-age=300", res.headers()[header::CACHE_CONTROL]);
+        let res = app
+            .clone()
+            .oneshot(build_todo_req_with_empty(Method::GET, "/api/v1/todos"))
+            .await
+            .unwrap();
+        assert_eq!("private, no-cache", res.headers()[header::CACHE_CONTROL]);
+        let req = build_req_with_json(
+            "/labels",
+            Method::POST,
+            r#"{ "name": "cache" }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        assert_eq!("no-store, private", res.headers()[header::CACHE_CONTROL]);
+
+        // 既定の値
+        let res = memory_app()
+            .oneshot(build_todo_req_with_empty(Method::GET, "/labels"))
+            .await
+            .unwrap();
+        assert_eq!("private, max-age=60", res.headers()[header::CACHE_CONTROL]);
+    }
+
+    #[tokio::test]
+    async fn should_select_fields_and_wrap_lists_in_envelope() {
+        let app = memory_app();
+        for text in ["first", "second", "third"] {
+            let req = build_req_with_json(
+                "/todos",
+                Method::POST,
+                format!(r#"{{ "text": "{}", "labels": [] }}"#, text),
+            );
+            assert_eq!(
+                StatusCode::CREATED,
+                app.clone().oneshot(req).await.unwrap().status()
+            );
+        }
+        let get = |path: &str| build_todo_req_with_empty(Method::GET, path);
+
+        let res = app
+            .clone()
+            .oneshot(get("/todos?limit=2&offset=1"))
+            .await
+            .unwrap();
+        let page = res_to_json(res).await;
+        let res = app
+            .clone()
+            .oneshot(get(
+                "/todos?fields=id,text,completed&limit=2&offset=1&envelope=true",
+            ))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::OK, res.status());
+        assert_eq!("3", res.headers()[TOTAL_COUNT_HEADER]);
+        let expected: Vec<_> = page
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|todo| {
+                serde_json::json!({
+                    "id": todo["id"],
+                    "text": todo["text"],
+                    "completed": todo["completed"],
+                })
+            })
+            .collect();
+        assert_eq!(
+            serde_json::json!({
+                "data": expected,
+                "meta": { "total": 3, "limit": 2, "offset": 1 },
+            }),
+            res_to_json(res).await
+        );
+
+        // 選べる項目はTodoEntityの項目と揃える
+        let todo = serde_json::to_value(TodoEntity::new(1, String::new(), vec![])).unwrap();
+        let mut keys: Vec<&str> = todo
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        let mut fields = TODO_FIELDS.to_vec();
+        keys.sort_unstable();
+        fields.sort_unstable();
+        assert_eq!(keys, fields);
+
+        // envelopeを省略した場合は従来どおり配列のまま返す
+        let res = app
+            .clone()
+            .oneshot(get("/todos?fields=text"))
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::json!([{ "text": "third" }, { "text": "second" }, { "text": "first" }]),
+            res_to_json(res).await
+        );
+
+        for path in [
+            "/todos?fields=id,password",
+            "/todos?fields=",
+            "/todos?fields=id&envelope=yes",
+        ] {
+            let res = app.clone().oneshot(get(path)).await.unwrap();
+            assert_eq!(StatusCode::BAD_REQUEST, res.status(), "{}", path);
+            let error = res_to_error(res).await;
+            let field = &error["error"]["fields"][0]["field"];
+            assert!(field == "fields" || field == "envelope", "{}", path);
+        }
+
+        // ページングしない一覧はlimitをnullにする
+        let req = build_req_with_json(
+            "/labels",
+            Method::POST,
+            r#"{ "name": "envelope" }"#.to_string(),
+        );
+        app.clone().oneshot(req).await.unwrap();
+        let res = app
+            .clone()
+            .oneshot(get("/labels?envelope=true"))
+            .await
+            .unwrap();
+        let body = res_to_json(res).await;
+        assert_eq!("envelope", body["data"][0]["name"]);
+        assert_eq!(
+            serde_json::json!({ "total": 1, "limit": null, "offset": 0 }),
+            body["meta"]
+        );
+        let res = app
+            .clone()
+            .oneshot(get("/todos/1/comments?envelope=true&limit=5"))
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "data": [],
+                "meta": { "total": 0, "limit": 5, "offset": 0 },
+            }),
+            res_to_json(res).await
+        );
+    }
+
+    #[tokio::test]
+    async fn should_delete_label() {
+        let label_repository = LabelRepositoryForMemory::new();
+        let label = label_repository
+            .create(CreateLabel::new("should_delete_label".to_string()))
+            .await
+            .expect("failed create label");
+        let req = build_todo_req_with_empty(Method::DELETE, "/labels/1");
+        let res = create_app(
+            TodoRepositoryForMemory::new(vec![label]),
+            label_repository,
+            UserRepositoryForMemory::new(),
+            CommentRepositoryForMemory::new(),
+            WebhookRepositoryForMemory::new(),
+            HealthRepositoryForMemory::new(),
+            test_keys(),
+            DEFAULT_API_PREFIX,
+        )
+        .oneshot(req)
+        .await
+        .unwrap();
+        assert_eq!(StatusCode::NO_CONTENT, res.status());
+    }
+
+    #[tokio::test]
+    async fn should_accept_either_naming_and_respond_in_selected_one() {
+        let app = memory_app();
+        let with_naming = |mut req: Request<Body>, naming: &'static str| {
+            req.headers_mut()
+                .insert(NAMING_HEADER, HeaderValue::from_static(naming));
+            req
+        };
+
+        // bodyはどちらの命名規則でも受け付け、レスポンスはX-Namingに合わせる
+        let req = build_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "camel", "labels": [], "dueDate": "2030-01-01T00:00:00Z", "remindAt": "2029-12-31T00:00:00Z" }"#.to_string(),
+        );
+        let res = app
+            .clone()
+            .oneshot(with_naming(req, "camel"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        assert!(res
+            .headers()
+            .get_all(header::VARY)
+            .iter()
+            .any(|vary| vary == NAMING_HEADER));
+        let camel = res_to_json(res).await;
+        assert!(camel
+            .as_object()
+            .unwrap()
+            .keys()
+            .all(|key| !key.contains('_')));
+        assert_eq!("2030-01-01T00:00:00Z", camel["dueDate"]);
+        assert_eq!("2029-12-31T00:00:00Z", camel["remindAt"]);
+        assert!(camel["createdAt"].is_string());
+        let id = camel["id"].as_i64().unwrap();
+
+        let req = build_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "snake", "labels": [], "due_date": "2030-01-01T00:00:00Z", "remind_at": "2029-12-31T00:00:00Z" }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let snake = res_to_json(res).await;
+        assert_eq!("2030-01-01T00:00:00Z", snake["due_date"]);
+        assert_eq!("2029-12-31T00:00:00Z", snake["remind_at"]);
+        assert!(snake.get("dueDate").is_none());
+        assert_eq!(
+            camel.as_object().unwrap().len(),
+            snake.as_object().unwrap().len()
+        );
+
+        // Merge Patchのnullもどちらの命名規則でも消す指定になる
+        let req = Request::builder()
+            .uri(format!("/todos/{}", id))
+            .method(Method::PATCH)
+            .header(header::CONTENT_TYPE, MERGE_PATCH_CONTENT_TYPE)
+            .header(header::AUTHORIZATION, format!("Bearer {}", test_token(1)))
+            .body(Body::from(r#"{ "dueDate": null, "remindAt": null }"#))
+            .unwrap();
+        let res = app
+            .clone()
+            .oneshot(with_naming(req, "camel"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::CREATED, res.status());
+        let cleared = res_to_json(res).await;
+        assert!(cleared["dueDate"].is_null());
+        assert!(cleared["remindAt"].is_null());
+
+        // そろえると重なるキーは、どちらを使うか決められないため拒否する
+        let req = build_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "both", "labels": [], "dueDate": null, "due_date": null }"#.to_string(),
+        );
+        let res = app.clone().oneshot(req).await.unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        let body = res_to_error(res).await;
+        assert_eq!("invalid_json", body["error"]["code"]);
+        assert_eq!("due_date", body["error"]["fields"][0]["field"]);
+        // エラーのfieldsもレスポンスのキーと同じ命名規則で返す
+        let req = build_req_with_json(
+            "/todos",
+            Method::POST,
+            r#"{ "text": "both", "labels": [], "dueDate": null, "due_date": null }"#.to_string(),
+        );
+        let res = app
+            .clone()
+            .oneshot(with_naming(req, "camel"))
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::BAD_REQUEST, res.status());
+        let body = res_to_error(res).await;
+        assert_eq!("invalid_json", body["error"]["code"]);
+        assert_eq!("dueDate", body["error"]["fields"][0]["field"]);
+
+        // 設定でcamelCaseを既定にしても、X-Namingで戻せる
+        let app = app.layer(Extension(Naming::Camel));
+        let res = app
+            .clone()
+            .oneshot(build_todo_req_with_empty(
+                Method::GET,
+                "/todos?fields=id,dueDate",
+            ))
+            .await
+            .unwrap();
+        let todos = res_to_json(res).await;
+        assert_eq!(serde_json::json!({ "id": id, "dueDate": null }), todos[1]);
+        let req = build_todo_req_with_empty(Method::GET, "/todos?fields=id,due_date");
+        let res = app.oneshot(with_naming(req, "snake")).await.unwrap();
+        let todos = res_to_json(res).await;
+        assert_eq!(serde_json::json!({ "id": id, "due_date": null }), todos[1]);
+    }
+}
@@ -0,0 +1,54 @@
+use std::time::Duration;
+
+use axum::error_handling::HandleErrorLayer;
+use axum::http::header::RETRY_AFTER;
+use axum::http::{HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::{BoxError, Router};
+use tower::limit::GlobalConcurrencyLimitLayer;
+use tower::load_shed::error::Overloaded;
+use tower::timeout::error::Elapsed;
+use tower::ServiceBuilder;
+
+use crate::error::AppError;
+
+// 503を返す際に、クライアントへ再試行まで待たせる秒数
+const RETRY_AFTER_SECS: &str = "5";
+
+// Router::layerはルートごとにlayerを適用するため、同時実行数の上限は全ルートで1つのセマフォを共有する
+// タイムアウトはレスポンスのヘッダーを返すまでが対象のため、/todos/exportのようにbodyをストリーミングで返す
+// ルートやupgrade後のWebSocketは、bodyの送信中に打ち切られない
+pub fn with_request_limits(app: Router, timeout: Duration, max_concurrency: usize) -> Router {
+    app.layer(
+        ServiceBuilder::new()
+            .layer(HandleErrorLayer::new(handle_limit_error))
+            .load_shed()
+            .layer(GlobalConcurrencyLimitLayer::new(max_concurrency))
+            .timeout(timeout),
+    )
+}
+
+async fn handle_limit_error(e: BoxError) -> Response {
+    let error = if e.is::<Elapsed>() {
+        tracing::warn!("request timed out");
+        AppError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "timeout",
+            "Request timed out",
+        )
+    } else if e.is::<Overloaded>() {
+        tracing::warn!("request rejected, too many concurrent requests");
+        AppError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "overloaded",
+            "Too many concurrent requests",
+        )
+    } else {
+        tracing::error!("{}", e);
+        return AppError::internal("Internal server error").into_response();
+    };
+    let mut res = error.into_response();
+    res.headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from_static(RETRY_AFTER_SECS));
+    res
+}
@@ -1,300 +1,36 @@
 use std::env;
-use std::net::SocketAddr;
-use std::sync::Arc;
+use std::process;
 
-use axum::extract::Extension;
-use axum::Router;
-use axum::routing::{delete, get, post};
 use dotenv::dotenv;
-use hyper::header::CONTENT_TYPE;
-use sqlx::PgPool;
-use tower_http::cors::{Any, CorsLayer, Origin};
-
-use crate::handlers::label::{all_label, create_label, delete_label};
-use crate::handlers::todo::{all_todo, create_todo, delete_todo, find_todo, update_todo};
-use crate::repositories::label::{LabelRepository, LabelRepositoryForDb};
-use crate::repositories::todo::{TodoRepository, TodoRepositoryForDb};
-
-mod handlers;
-mod repositories;
+use rust_todo::config::{Config, LogFormat};
+use rust_todo::error_reporting::{init_sentry, install_panic_hook};
+use rust_todo::telemetry::{init_tracing, shutdown_tracing};
 
 #[tokio::main]
 async fn main() {
-    // loggingの初期化
-    let log_level = env::var("RUST_LOG").unwrap_or("info".to_string());
-    env::set_var("RUST_LOG", log_level);
-    tracing_subscriber::fmt::init();
     dotenv().ok();
 
-    let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
-    tracing::debug!("start connect database...");
-    let pool = PgPool::connect(database_url)
-        .await
-        .expect(&format!("fail connect database, url is [{}]", database_url));
-
-    let app = create_app(
-        TodoRepositoryForDb::new(pool.clone()),
-        LabelRepositoryForDb::new(pool.clone()),
-    );
-
-    // run our app with hyper, listening globally on port 3000
-    // let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    let addr = SocketAddr::from(([0, 0, 0, 0], 8000));
-    tracing::debug!("listening on {}", addr);
-    axum::Server::bind(&addr)
-        .serve(app.into_make_service())
-        .await
-        .unwrap();
-}
-
-fn create_app<Todo: TodoRepository, Label: LabelRepository>(
-    todo_repository: Todo,
-    label_repository: Label,
-) -> Router {
-    Router::new()
-        .route("/todos", post(create_todo::<Todo>).get(all_todo::<Todo>))
-        .route(
-            "/todos/:id",
-            get(find_todo::<Todo>)
-                .delete(delete_todo::<Todo>)
-                .patch(update_todo::<Todo>),
-        )
-        .route(
-            "/labels",
-            post(create_label::<Label>).get(all_label::<Label>),
-        )
-        .route("/labels/:id", delete(delete_label::<Label>))
-        .layer(Extension(Arc::new(todo_repository)))
-        .layer(Extension(Arc::new(label_repository)))
-        .layer(
-            CorsLayer::new()
-                .allow_origin(Origin::exact("http://localhost:3000".parse().unwrap()))
-                .allow_methods(Any)
-                .allow_headers(vec![CONTENT_TYPE]),
-        )
-}
-
-#[cfg(test)]
-mod test {
-    use axum::{
-        body::Body,
-        http::{header, Method, Request},
-    };
-    use axum::http::StatusCode;
-    use axum::response::Response;
-    use tower::ServiceExt;
-
-    use crate::repositories::label::Label;
-    use crate::repositories::label::test_utils::LabelRepositoryForMemory;
-    use crate::repositories::todo::{CreateTodo, TodoEntity};
-    use crate::repositories::todo::test_utils::TodoRepositoryForMemory;
-
-    use super::*;
-
-    fn build_req_with_json(path: &str, method: Method, json_body: String) -> Request<Body> {
-        Request::builder()
-            .uri(path)
-            .method(method)
-            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
-            .body(Body::from(json_body))
-            .unwrap()
-    }
-
-    fn build_todo_req_with_empty(method: Method, path: &str) -> Request<Body> {
-        Request::builder()
-            .uri(path)
-            .method(method)
-            .body(Body::empty())
-            .unwrap()
-    }
-
-    async fn res_to_todo(res: Response) -> TodoEntity {
-        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
-        let body: String = String::from_utf8(bytes.to_vec()).unwrap();
-        let todo: TodoEntity = serde_json::from_str(&body)
-            .expect(&format!("cannot convert Todo instance. body: {}", body));
-        todo
-    }
-
-    async fn res_to_label(res: Response) -> Label {
-        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
-        let body: String = String::from_utf8(bytes.to_vec()).unwrap();
-        let label: Label = serde_json::from_str(&body)
-            .expect(&format!("cannot convert Label instance. body: {}", body));
-        label
-    }
-
-    fn label_fixture() -> (Vec<Label>, Vec<i32>) {
-        let id = 999;
-        (
-            vec![Label {
-                id,
-                name: String::from("test label"),
-            }],
-            vec![id],
-        )
-    }
-
-    #[tokio::test]
-    async fn should_created_todo() {
-        let (labels, _label_ids) = label_fixture();
-        let expected = TodoEntity::new(1, "should_return_created_todo".to_string(), labels.clone());
-
-        let req = build_req_with_json(
-            "/todos",
-            Method::POST,
-            r#"{ "text": "should_return_created_todo", "labels": [999] }"#.to_string(),
-        );
-        let res = create_app(
-            TodoRepositoryForMemory::new(labels),
-            LabelRepositoryForMemory::new(),
-        )
-        .oneshot(req)
-        .await
-        .unwrap();
-        let todo = res_to_todo(res).await;
-        assert_eq!(expected, todo);
-    }
-
-    #[tokio::test]
-    async fn should_find_todo() {
-        let (labels, label_ids) = label_fixture();
-        let expected = TodoEntity::new(1, "should_find_todo".to_string(), labels.clone());
-
-        let todo_repository = TodoRepositoryForMemory::new(labels.clone());
-        todo_repository
-            .create(CreateTodo::new("should_find_todo".to_string(), label_ids))
-            .await
-            .expect("failed create todo");
-        let req = build_todo_req_with_empty(Method::GET, "/todos/1");
-        let res = create_app(todo_repository, LabelRepositoryForMemory::new())
-            .oneshot(req)
-            .await
-            .unwrap();
-        let todo = res_to_todo(res).await;
-        assert_eq!(expected, todo);
-    }
-
-    #[tokio::test]
-    async fn should_get_all_todos() {
-        let (labels, label_ids) = label_fixture();
-        let expected = TodoEntity::new(1, "should_get_all_todos".to_string(), labels.clone());
-
-        let todo_repository = TodoRepositoryForMemory::new(labels.clone());
-        todo_repository
-            .create(CreateTodo::new(
-                "should_get_all_todos".to_string(),
-                label_ids,
-            ))
-            .await
-            .expect("failed create todo");
-        let req = build_todo_req_with_empty(Method::GET, "/todos");
-        let res = create_app(todo_repository, LabelRepositoryForMemory::new())
-            .oneshot(req)
-            .await
-            .unwrap();
-        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
-        let body: String = String::from_utf8(bytes.to_vec()).unwrap();
-        let todos: Vec<TodoEntity> = serde_json::from_str(&body)
-            .expect(&format!("cannot convert Todo lis instance. body: {}", body));
-        assert_eq!(vec![expected], todos);
-    }
-
-    #[tokio::test]
-    async fn should_update_todo() {
-        let (labels, label_ids) = label_fixture();
-        let expected = TodoEntity::new(1, "should_update_todo".to_string(), labels.clone());
-
-        let todo_repository = TodoRepositoryForMemory::new(labels);
-        todo_repository
-            .create(CreateTodo::new("before_update_todo".to_string(), label_ids))
-            .await
-            .expect("failed create todo");
-        let req = build_req_with_json(
-            "/todos/1",
-            Method::PATCH,
-            r#"{"text": "should_update_todo","completed": false}"#.to_string(),
-        );
-        let res = create_app(todo_repository, LabelRepositoryForMemory::new())
-            .oneshot(req)
-            .await
-            .unwrap();
-        let todo = res_to_todo(res).await;
-        assert_eq!(expected, todo);
-    }
-
-    #[tokio::test]
-    async fn should_delete_todo() {
-        let (labels, label_ids) = label_fixture();
-        let todo_repository = TodoRepositoryForMemory::new(labels);
-        todo_repository
-            .create(CreateTodo::new("should_delete_todo".to_string(), label_ids))
-            .await
-            .expect("failed create todo");
-        let req = build_todo_req_with_empty(Method::DELETE, "/todos/1");
-        let res = create_app(todo_repository, LabelRepositoryForMemory::new())
-            .oneshot(req)
-            .await
-            .unwrap();
-        assert_eq!(StatusCode::NO_CONTENT, res.status());
-    }
-
-    #[tokio::test]
-    async fn should_created_label() {
-        let (labels, _label_ids) = label_fixture();
-        let expected = Label::new(1, "should_created_label".to_string());
-
-        let req = build_req_with_json(
-            "/labels",
-            Method::POST,
-            r#"{ "name": "should_created_label" }"#.to_string(),
-        );
-        let res = create_app(
-            TodoRepositoryForMemory::new(labels),
-            LabelRepositoryForMemory::new(),
-        )
-        .oneshot(req)
-        .await
-        .unwrap();
-        let label = res_to_label(res).await;
-        assert_eq!(expected, label);
-    }
-
-    #[tokio::test]
-    async fn should_all_label_readed() {
-        let expected = Label::new(1, "should_all_label_readed".to_string());
-        let label_repository = LabelRepositoryForMemory::new();
-        let label = label_repository
-            .create("should_all_label_readed".to_string())
-            .await
-            .expect("failed create label");
-
-        let req = build_todo_req_with_empty(Method::GET, "/labels");
-        let res = create_app(TodoRepositoryForMemory::new(vec![label]), label_repository)
-            .oneshot(req)
-            .await
-            .unwrap();
-        let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
-        let body: String = String::from_utf8(bytes.to_vec()).unwrap();
-        let labels: Vec<Label> = serde_json::from_str(&body).expect(&format!(
-            "cannot convert Label list instance. body: {}",
-            body
-        ));
-        assert_eq!(vec![expected], labels);
-    }
-
-    #[tokio::test]
-    async fn should_delete_label() {
-        let label_repository = LabelRepositoryForMemory::new();
-        let label = label_repository
-            .create("should_delete_label".to_string())
-            .await
-            .expect("failed create label");
-        let req = build_todo_req_with_empty(Method::DELETE, "/labels/1");
-        let res = create_app(TodoRepositoryForMemory::new(vec![label]), label_repository)
-            .oneshot(req)
-            .await
-            .unwrap();
-        assert_eq!(StatusCode::NO_CONTENT, res.status());
+    let config = Config::from_env().unwrap_or_else(|e| {
+        // 設定を読めない場合も既定の形式でログを出力してから終了する
+        init_tracing(LogFormat::default());
+        tracing::error!("{}", e);
+        process::exit(1);
+    });
+    // loggingの初期化
+    init_tracing(config.log_format);
+    // ハンドラのpanicをbacktrace付きでログに出す
+    install_panic_hook();
+    // 未設定の場合、Sentryへは何も送らない
+    let sentry = config.sentry_dsn.as_deref().map(init_sentry);
+    let migrate_only = env::args().any(|arg| arg == "--migrate-only");
+    let result = rust_todo::run(config, migrate_only).await;
+    if let Err(e) = &result {
+        tracing::error!("{:#}", e);
+    }
+    // process::exitはdropを待たないため、終了前に送信待ちのspanとイベントを書き出す
+    shutdown_tracing().await;
+    drop(sentry);
+    if result.is_err() {
+        process::exit(1);
     }
 }
@@ -0,0 +1,169 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use axum::extract::MatchedPath;
+use axum::http::{Request, Response};
+use prometheus::{
+    Encoder, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge, Opts, Registry,
+    TextEncoder,
+};
+use sqlx::PgPool;
+use tower::{Layer, Service};
+
+// ルートに一致しないリクエストはパスごとに系列を増やさないよう1つにまとめる
+const UNMATCHED_ROUTE: &str = "unmatched";
+
+// コネクションプールの接続待ちがタイムアウトしたレスポンスに付け、待ちの回数として数える
+#[derive(Debug, Clone, Copy)]
+pub struct DatabaseUnavailable;
+
+pub struct Metrics {
+    registry: Registry,
+    requests: IntCounterVec,
+    request_duration: HistogramVec,
+    pool_size: IntGauge,
+    pool_idle: IntGauge,
+    pool_timeouts: IntCounter,
+    pool: Option<PgPool>,
+}
+
+impl Metrics {
+    pub fn new(pool: Option<PgPool>) -> anyhow::Result<Self> {
+        let registry = Registry::new();
+        let requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "Number of HTTP requests"),
+            &["method", "route", "status"],
+        )?;
+        let request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "HTTP request latency in seconds",
+            ),
+            &["method", "route"],
+        )?;
+        let pool_size = IntGauge::new("db_pool_connections", "Open database connections")?;
+        let pool_idle = IntGauge::new("db_pool_idle_connections", "Idle database connections")?;
+        let pool_timeouts = IntCounter::new(
+            "db_pool_acquire_timeouts_total",
+            "Requests that timed out waiting for a database connection",
+        )?;
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(request_duration.clone()))?;
+        registry.register(Box::new(pool_size.clone()))?;
+        registry.register(Box::new(pool_idle.clone()))?;
+        registry.register(Box::new(pool_timeouts.clone()))?;
+
+        Ok(Self {
+            registry,
+            requests,
+            request_duration,
+            pool_size,
+            pool_idle,
+            pool_timeouts,
+            pool,
+        })
+    }
+
+    fn record(&self, method: &str, route: &str, status: u16, started: Instant) {
+        self.requests
+            .with_label_values(&[method, route, &status.to_string()])
+            .inc();
+        self.request_duration
+            .with_label_values(&[method, route])
+            .observe(started.elapsed().as_secs_f64());
+    }
+
+    // コネクションプールの状態は収集時点の値を読む
+    pub fn render(&self) -> anyhow::Result<String> {
+        if let Some(pool) = &self.pool {
+            self.pool_size.set(pool.size().into());
+            self.pool_idle.set(pool.num_idle() as i64);
+        }
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
+
+#[derive(Clone)]
+pub struct MetricsLayer {
+    metrics: Arc<Metrics>,
+}
+
+impl MetricsLayer {
+    pub fn new(metrics: Arc<Metrics>) -> Self {
+        Self { metrics }
+    }
+}
+
+impl<S> Layer<S> for MetricsLayer {
+    type Service = RecordMetrics<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RecordMetrics {
+            inner,
+            metrics: self.metrics.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RecordMetrics<S> {
+    inner: S,
+    metrics: Arc<Metrics>,
+}
+
+impl<S, ReqBody, ResBody> Service<Request<ReqBody>> for RecordMetrics<S>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        // 実際のパスではなくルート定義(/todos/:id)を使い、系列数を抑える
+        let route = req
+            .extensions()
+            .get::<MatchedPath>()
+            .map(|path| path.as_str().to_string())
+            .unwrap_or_else(|| UNMATCHED_ROUTE.to_string());
+        let method = req.method().to_string();
+        let started = Instant::now();
+        let metrics = self.metrics.clone();
+        let future = self.inner.call(req);
+        Box::pin(async move {
+            let res = future.await?;
+            metrics.record(&method, &route, res.status().as_u16(), started);
+            if res.extensions().get::<DatabaseUnavailable>().is_some() {
+                metrics.pool_timeouts.inc();
+            }
+            Ok(res)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_render_recorded_requests() {
+        let metrics = Metrics::new(None).unwrap();
+        metrics.record("GET", "/todos", 200, Instant::now());
+        metrics.record("GET", "/todos", 200, Instant::now());
+        let body = metrics.render().unwrap();
+        assert!(body.contains(r#"http_requests_total{method="GET",route="/todos",status="200"} 2"#));
+        assert!(
+            body.contains(r#"http_request_duration_seconds_count{method="GET",route="/todos"} 2"#)
+        );
+    }
+}
@@ -0,0 +1,57 @@
+use std::collections::HashSet;
+
+use sqlx::migrate::{Migrate, Migrator};
+#[cfg(feature = "sqlite")]
+use sqlx::SqlitePool;
+use sqlx::{Database, PgPool, Pool};
+
+// migrationsディレクトリをバイナリへ埋め込み、デプロイ時にsqlx-cliを不要にする
+static MIGRATOR: Migrator = sqlx::migrate!();
+// SQLiteは型や制約の書き方が異なるため、同じスキーマを別のディレクトリで管理する
+#[cfg(feature = "sqlite")]
+static SQLITE_MIGRATOR: Migrator = sqlx::migrate!("./migrations_sqlite");
+
+pub async fn run(pool: &PgPool) -> anyhow::Result<()> {
+    apply(&MIGRATOR, pool).await
+}
+
+#[cfg(feature = "sqlite")]
+pub async fn run_sqlite(pool: &SqlitePool) -> anyhow::Result<()> {
+    apply(&SQLITE_MIGRATOR, pool).await
+}
+
+async fn apply<DB>(migrator: &Migrator, pool: &Pool<DB>) -> anyhow::Result<()>
+where
+    DB: Database,
+    DB::Connection: Migrate,
+{
+    // Migrator::runは適用したマイグレーションを返さないため、事前に適用済みのものを控えておく
+    let applied: HashSet<i64> = {
+        let mut conn = pool.acquire().await?;
+        conn.ensure_migrations_table().await?;
+        conn.list_applied_migrations()
+            .await?
+            .into_iter()
+            .map(|migration| migration.version)
+            .collect()
+    };
+
+    migrator.run(pool).await?;
+
+    let mut count = 0;
+    for migration in migrator.iter() {
+        if migration.migration_type.is_down_migration() || applied.contains(&migration.version) {
+            continue;
+        }
+        tracing::info!(
+            "applied migration {} {}",
+            migration.version,
+            migration.description
+        );
+        count += 1;
+    }
+    if count == 0 {
+        tracing::info!("database is up to date, no migrations applied");
+    }
+    Ok(())
+}
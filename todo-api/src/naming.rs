@@ -0,0 +1,290 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::task::{Context, Poll};
+
+use axum::body::{boxed, Body, Full};
+use axum::http::header::{CONTENT_LENGTH, CONTENT_TYPE, VARY};
+use axum::http::{HeaderMap, HeaderValue, Request};
+use axum::response::Response;
+use serde_json::{Map, Value};
+use tower::{Layer, Service};
+
+use crate::body_log::failed;
+
+// リクエストごとにJSONのキーの命名規則を選ぶヘッダー。値はsnakeまたはcamel
+pub const NAMING_HEADER: &str = "x-naming";
+
+// レスポンスのJSONのキーの命名規則。Extensionが未設定で、X-Namingもない場合はsnake_caseで返す
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Naming {
+    #[default]
+    Snake,
+    Camel,
+}
+
+impl FromStr for Naming {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "snake" => Ok(Naming::Snake),
+            "camel" => Ok(Naming::Camel),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Naming {
+    // 読めないX-Namingは無視し、設定の値を使う
+    fn requested(req: &Request<Body>) -> Self {
+        req.headers()
+            .get(NAMING_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse().ok())
+            .or_else(|| req.extensions().get::<Naming>().copied())
+            .unwrap_or_default()
+    }
+}
+
+// 保存用の型はsnake_caseのまま、JSONのレスポンスのみキーをcamelCaseへ書き換える
+// 書き換えた本文の長さでContent-Lengthを付け直させるため、HeadContentLengthLayerより内側に置く
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NamingLayer;
+
+impl<S> Layer<S> for NamingLayer {
+    type Service = JsonNaming<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        JsonNaming { inner }
+    }
+}
+
+#[derive(Clone)]
+pub struct JsonNaming<S> {
+    inner: S,
+}
+
+impl<S> Service<Request<Body>> for JsonNaming<S>
+where
+    S: Service<Request<Body>, Response = Response>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let naming = Naming::requested(&req);
+        let future = self.inner.call(req);
+        Box::pin(async move {
+            let mut res = future.await?;
+            if !is_json(res.headers()) {
+                return Ok(res);
+            }
+            // 同じURLでもX-Namingで本文が変わるため、キャッシュに区別させる
+            res.headers_mut()
+                .append(VARY, HeaderValue::from_static(NAMING_HEADER));
+            if naming == Naming::Snake {
+                return Ok(res);
+            }
+            let (mut parts, body) = res.into_parts();
+            let bytes = match hyper::body::to_bytes(body).await {
+                Ok(bytes) => bytes,
+                Err(e) => return Ok(Response::from_parts(parts, boxed(failed(e)))),
+            };
+            let body = match serde_json::from_slice::<Value>(&bytes) {
+                Ok(value) => Full::from(camel_case_body(value).to_string()),
+                Err(_) => Full::from(bytes),
+            };
+            parts.headers.remove(CONTENT_LENGTH);
+            Ok(Response::from_parts(parts, boxed(body)))
+        })
+    }
+}
+
+// application/x-ndjsonなどのストリームは対象外にする
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<mime::Mime>().ok())
+        .is_some_and(|mime| mime.essence_str() == mime::APPLICATION_JSON.essence_str())
+}
+
+fn camel_case_keys(value: Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| (camel_case(&key), camel_case_keys(value)))
+                .collect(),
+        ),
+        Value::Array(values) => Value::Array(values.into_iter().map(camel_case_keys).collect()),
+        value => value,
+    }
+}
+
+// エラーのfields[].fieldはリクエストのキーを指すため、キーと同じくcamelCaseで返す
+fn camel_case_body(value: Value) -> Value {
+    let mut value = camel_case_keys(value);
+    if let Some(fields) = value
+        .pointer_mut("/error/fields")
+        .and_then(Value::as_array_mut)
+    {
+        for field in fields {
+            if let Some(Value::String(name)) = field.get_mut("field") {
+                *name = camel_case(name);
+            }
+        }
+    }
+    value
+}
+
+// リクエストはどちらの命名規則でも受け付けるため、キーをsnake_caseへそろえる
+// dueDateとdue_dateのように、そろえた後に重なるキーはErrでそのキーを返す
+pub fn snake_case_keys(value: Value) -> Result<Value, String> {
+    match value {
+        Value::Object(map) => {
+            let mut snake = Map::with_capacity(map.len());
+            for (key, value) in map {
+                let key = snake_case(&key);
+                let value = snake_case_keys(value)?;
+                if snake.insert(key.clone(), value).is_some() {
+                    return Err(key);
+                }
+            }
+            Ok(Value::Object(snake))
+        }
+        Value::Array(values) => values
+            .into_iter()
+            .map(snake_case_keys)
+            .collect::<Result<_, _>>()
+            .map(Value::Array),
+        value => Ok(value),
+    }
+}
+
+// 先頭の_は区切りではないため残す
+fn camel_case(key: &str) -> String {
+    let body = key.trim_start_matches('_');
+    let mut camel = key[..key.len() - body.len()].to_string();
+    for (i, word) in body.split('_').filter(|word| !word.is_empty()).enumerate() {
+        let mut chars = word.chars();
+        if let (true, Some(first)) = (i > 0, chars.next()) {
+            camel.extend(first.to_uppercase());
+            camel.push_str(chars.as_str());
+        } else {
+            camel.push_str(word);
+        }
+    }
+    camel
+}
+
+// camel_caseと対になるよう、英字に続く数字の並びも1語として区切る(createdLast7Days -> created_last_7_days)
+pub fn snake_case(key: &str) -> String {
+    let mut snake = String::with_capacity(key.len() + 4);
+    let mut prev: Option<char> = None;
+    for c in key.chars() {
+        if c.is_ascii_uppercase() {
+            if prev.is_some() {
+                snake.push('_');
+            }
+            snake.push(c.to_ascii_lowercase());
+        } else {
+            if c.is_ascii_digit() && prev.is_some_and(|prev| prev.is_ascii_alphabetic()) {
+                snake.push('_');
+            }
+            snake.push(c);
+        }
+        prev = Some(c);
+    }
+    snake
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+    use utoipa::OpenApi;
+
+    use crate::openapi::ApiDoc;
+
+    use super::*;
+
+    #[test]
+    fn should_convert_keys_between_cases() {
+        for (snake, camel) in [
+            ("id", "id"),
+            ("due_date", "dueDate"),
+            ("next_occurrence_id", "nextOccurrenceId"),
+            ("created_last_7_days", "createdLast7Days"),
+        ] {
+            assert_eq!(camel, camel_case(snake));
+            assert_eq!(snake, snake_case(camel));
+            assert_eq!(snake, snake_case(snake));
+        }
+        assert_eq!("__typename", camel_case("__typename"));
+
+        let value = json!({ "due_date": null, "labels": [{ "todo_count": 1 }] });
+        let camel = camel_case_keys(value.clone());
+        assert_eq!(
+            json!({ "dueDate": null, "labels": [{ "todoCount": 1 }] }),
+            camel
+        );
+        assert_eq!(Ok(value), snake_case_keys(camel));
+        assert_eq!(
+            Err("due_date".to_string()),
+            snake_case_keys(json!({ "dueDate": null, "due_date": null }))
+        );
+    }
+
+    // ドキュメントに載る全てのスキーマのキーが、camelCaseを経て元のキーへ戻ることを確かめる
+    #[test]
+    fn should_round_trip_every_documented_key() {
+        fn collect(value: &Value, keys: &mut Vec<String>) {
+            match value {
+                Value::Object(map) => {
+                    if let Some(Value::Object(properties)) = map.get("properties") {
+                        keys.extend(properties.keys().cloned());
+                    }
+                    map.values().for_each(|value| collect(value, keys));
+                }
+                Value::Array(values) => values.iter().for_each(|value| collect(value, keys)),
+                _ => {}
+            }
+        }
+        let mut keys = vec![];
+        collect(&serde_json::to_value(ApiDoc::openapi()).unwrap(), &mut keys);
+        assert!(keys.iter().any(|key| key == "created_last_7_days"));
+
+        for key in keys {
+            let camel = camel_case(&key);
+            assert_eq!(key, snake_case(&camel), "{}", key);
+            assert_eq!(camel, camel_case(&snake_case(&camel)), "{}", key);
+        }
+    }
+
+    #[test]
+    fn should_convert_error_fields_to_camel_case() {
+        let body = json!({
+            "error": {
+                "code": "validation_failed",
+                "fields": [{ "field": "due_date", "message": "Invalid" }],
+            },
+            "current": { "remind_at": null },
+        });
+        assert_eq!(
+            json!({
+                "error": {
+                    "code": "validation_failed",
+                    "fields": [{ "field": "dueDate", "message": "Invalid" }],
+                },
+                "current": { "remindAt": null },
+            }),
+            camel_case_body(body)
+        );
+    }
+}
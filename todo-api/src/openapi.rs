@@ -0,0 +1,188 @@
+use utoipa::openapi::path::{ParameterBuilder, ParameterIn};
+use utoipa::openapi::security::{HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::openapi::server::Server;
+use utoipa::openapi::{ObjectBuilder, Required, SchemaType};
+use utoipa::{Modify, OpenApi};
+
+use crate::csv_import::{CsvImportSummary, SkippedRow};
+use crate::error::{ErrorBody, ErrorDetail, FieldError};
+use crate::handlers::{backup, comment, label, todo, todo_item, webhook, ws};
+use crate::naming::NAMING_HEADER;
+use crate::repositories::backup::{
+    Backup, BackupAssociation, BackupLabel, BackupTodo, ImportSummary,
+};
+use crate::repositories::comment::{Comment, CreateComment};
+use crate::repositories::label::{CreateLabel, Label, LabelWithUsage, UpdateLabel};
+use crate::repositories::todo::{
+    CreateTodo, CreateTodoBatch, DuplicateTodo, LabelCount, MoveTodo, Priority, Recurrence,
+    ReplaceTodo, SortField, SortOrder, TodoEntity, TodoStats, UpdateTodo, UpdateTodos,
+    UpdatedTodos,
+};
+use crate::repositories::todo_activity::{TodoAction, TodoActivity};
+use crate::repositories::todo_item::{CreateTodoItem, TodoItem, TodoWithItems, UpdateTodoItem};
+use crate::repositories::webhook::{CreateWebhook, UpdateWebhook, Webhook, WebhookEvent};
+
+// ハンドラの型から生成し、フロントエンド向けのドキュメントと実装がずれないようにする
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "rust-todo API",
+        description = "JSON keys are snake_case by default. Send `X-Naming: camel` to get camelCase keys, \
+            including the names in `error.fields[].field`, or `X-Naming: snake` to override a camelCase \
+            default set with JSON_NAMING. Request bodies are accepted in either case."
+    ),
+    paths(
+        todo::create_todo,
+        todo::create_todo_batch,
+        todo::find_todo,
+        todo::find_todo_by_client_id,
+        todo::all_todo,
+        todo::update_todo,
+        todo::replace_todo,
+        todo::update_todos,
+        todo::delete_todo,
+        todo::export_todos,
+        todo::todo_stats,
+        todo::trash_todos,
+        todo::restore_todo,
+        todo::move_todo,
+        todo::purge_completed_todos,
+        todo::duplicate_todo,
+        todo::archive_todo,
+        todo::unarchive_todo,
+        todo::archive_completed_todos,
+        todo::todo_activity,
+        todo::attach_todo_label,
+        todo::detach_todo_label,
+        todo_item::create_todo_item,
+        todo_item::update_todo_item,
+        todo_item::delete_todo_item,
+        comment::create_comment,
+        comment::all_comments,
+        comment::delete_comment,
+        label::create_label,
+        label::all_label,
+        label::update_label,
+        label::delete_label,
+        label::merge_label,
+        backup::export_backup,
+        backup::import_backup,
+        backup::import_csv,
+        webhook::create_webhook,
+        webhook::all_webhooks,
+        webhook::find_webhook,
+        webhook::update_webhook,
+        webhook::delete_webhook,
+        ws::sync_todos,
+    ),
+    components(schemas(
+        TodoEntity,
+        CreateTodo,
+        CreateTodoBatch,
+        UpdateTodo,
+        ReplaceTodo,
+        UpdateTodos,
+        UpdatedTodos,
+        todo::UpdatedTodo,
+        MoveTodo,
+        DuplicateTodo,
+        TodoStats,
+        LabelCount,
+        TodoWithItems,
+        TodoItem,
+        TodoActivity,
+        TodoAction,
+        CreateTodoItem,
+        UpdateTodoItem,
+        Comment,
+        CreateComment,
+        Priority,
+        Recurrence,
+        todo::ExportFormat,
+        SortField,
+        SortOrder,
+        Label,
+        LabelWithUsage,
+        CreateLabel,
+        UpdateLabel,
+        label::MergeLabel,
+        Backup,
+        BackupTodo,
+        BackupLabel,
+        BackupAssociation,
+        ImportSummary,
+        CsvImportSummary,
+        Webhook,
+        WebhookEvent,
+        CreateWebhook,
+        UpdateWebhook,
+        SkippedRow,
+        ErrorBody,
+        ErrorDetail,
+        FieldError,
+    )),
+    modifiers(&BearerAuth, &NamingHeader),
+    tags(
+        (name = "todos", description = "Todos of the logged in user"),
+        (name = "labels", description = "Labels shared by todos"),
+        (name = "backup", description = "JSON export and import for moving data between environments"),
+        (name = "webhooks", description = "Signed HTTP callbacks on todo changes"),
+    )
+)]
+pub struct ApiDoc;
+
+// pathsにはプレフィックスを含めず、serversで示す。Swagger UIはこの下のパスへリクエストする
+pub fn api_doc(prefix: &str) -> utoipa::openapi::OpenApi {
+    let mut openapi = ApiDoc::openapi();
+    openapi.servers = Some(vec![Server::new(prefix)]);
+    openapi
+}
+
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .bearer_format("JWT")
+                    .build(),
+            ),
+        );
+    }
+}
+
+// X-Namingは全てのエンドポイントで受け付けるため、個々のpathではなくまとめて追加する
+struct NamingHeader;
+
+impl Modify for NamingHeader {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let parameter = ParameterBuilder::new()
+            .name(NAMING_HEADER)
+            .parameter_in(ParameterIn::Header)
+            .required(Required::False)
+            .description(Some(
+                "Naming of JSON keys in the response, defaults to JSON_NAMING (snake unless configured)",
+            ))
+            .schema(Some(
+                ObjectBuilder::new()
+                    .schema_type(SchemaType::String)
+                    .enum_values(Some(["snake", "camel"])),
+            ))
+            .build();
+        for operation in openapi
+            .paths
+            .paths
+            .values_mut()
+            .flat_map(|item| item.operations.values_mut())
+        {
+            operation
+                .parameters
+                .get_or_insert_with(Vec::new)
+                .push(parameter.clone());
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use tokio::task::JoinHandle;
+
+use crate::clock::Clock;
+use crate::events::TodoEvents;
+use crate::periodic::spawn_periodic;
+use crate::repositories::outbox::{OutboxEvent, OutboxRepositoryForDb};
+use crate::repositories::RepositoryError;
+use crate::webhooks::RetryPolicy;
+
+// 記録から配信までの遅れはこの間隔に収まる
+pub const OUTBOX_POLL_INTERVAL: Duration = Duration::from_secs(1);
+// 1回の周期で取り出すイベントの上限
+const BATCH_SIZE: i64 = 100;
+// 取り出したイベントは、この間は他のインスタンスから取り出さない
+// 上限の件数を送り終えられるよう、送信のタイムアウトより十分長くする
+const CLAIM_LEASE_SECS: i64 = 10 * 60;
+
+// イベントの配信先。Errを返したイベントは後で送り直すが、送り終えた配信先へは送らない
+#[async_trait]
+pub trait EventSink: Send + Sync + 'static {
+    // 送り終えたことの記録に使う名前。配信先ごとに異なる名前にする
+    fn name(&self) -> &'static str;
+    // 複数の宛先へ送る場合は、送れた宛先の名前をsentへ加え、送り直す際はevent.deliveredにある宛先を飛ばす
+    async fn deliver(&self, event: &OutboxEvent, sent: &mut Vec<String>) -> anyhow::Result<()>;
+}
+
+// WebSocket・gRPCの購読者へ流す。接続中の購読者がいない場合も配信済みとする
+pub struct BroadcastSink(pub TodoEvents);
+
+#[async_trait]
+impl EventSink for BroadcastSink {
+    fn name(&self) -> &'static str {
+        "broadcast"
+    }
+
+    async fn deliver(&self, event: &OutboxEvent, _sent: &mut Vec<String>) -> anyhow::Result<()> {
+        self.0.broadcast(event.user_id, event.event.clone());
+        Ok(())
+    }
+}
+
+// commit済みのイベントを配信先へ送り、送れたものから配信済みにする
+// 送った後に記録する前に落ちた場合は、取り出した期間を過ぎた後に同じイベントをもう一度送る
+pub struct OutboxPublisher<C> {
+    repository: OutboxRepositoryForDb,
+    sinks: Vec<Arc<dyn EventSink>>,
+    policy: RetryPolicy,
+    clock: C,
+}
+
+impl<C: Clock> OutboxPublisher<C> {
+    pub fn new(repository: OutboxRepositoryForDb, policy: RetryPolicy, clock: C) -> Self {
+        OutboxPublisher {
+            repository,
+            sinks: vec![],
+            policy,
+            clock,
+        }
+    }
+
+    pub fn with_sink(mut self, sink: impl EventSink) -> Self {
+        self.sinks.push(Arc::new(sink));
+        self
+    }
+
+    // 配信時刻を過ぎたイベントを1回分送り、取り出した件数を返す
+    pub async fn publish_pending(&self) -> Result<usize, RepositoryError> {
+        let now = self.clock.now();
+        let locked_until = now + chrono::Duration::seconds(CLAIM_LEASE_SECS);
+        let events = self.repository.claim(now, locked_until, BATCH_SIZE).await?;
+        for event in events.iter() {
+            // 期間を過ぎた残りは他のインスタンスが取り出しうるため、次の周期に任せる
+            if self.clock.now() >= locked_until {
+                break;
+            }
+            match self.deliver(event, now).await? {
+                None => self.repository.published(event.id, now).await?,
+                Some(message) => {
+                    let retry_at = self.retry_at(event, now);
+                    if retry_at.is_none() {
+                        tracing::error!(
+                            "give up event {} after {} attempts: {}",
+                            event.id,
+                            event.attempts + 1,
+                            message
+                        );
+                    } else {
+                        tracing::warn!("fail publish event {}: {}", event.id, message);
+                    }
+                    self.repository
+                        .failed(event.id, &message, retry_at, now)
+                        .await?;
+                }
+            }
+        }
+        Ok(events.len())
+    }
+
+    // 送り終えていない配信先へ送り、送れた配信先・宛先を記録する。送れなかった配信先がある場合はその理由を返す
+    async fn deliver(
+        &self,
+        event: &OutboxEvent,
+        now: DateTime<Utc>,
+    ) -> Result<Option<String>, RepositoryError> {
+        let mut failures = vec![];
+        for sink in self.sinks.iter() {
+            if event.delivered.iter().any(|name| name == sink.name()) {
+                continue;
+            }
+            let mut sent = vec![];
+            match sink.deliver(event, &mut sent).await {
+                Ok(()) => sent = vec![sink.name().to_string()],
+                Err(e) => failures.push(format!("{}: {:#}", sink.name(), e)),
+            }
+            self.repository.delivered(event.id, &sent, now).await?;
+        }
+        if failures.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(failures.join(", ")))
+    }
+
+    // 今回の失敗で上限に達した場合はNone
+    fn retry_at(&self, event: &OutboxEvent, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let attempts = u32::try_from(event.attempts).unwrap_or_default() + 1;
+        if attempts >= self.policy.max_attempts {
+            return None;
+        }
+        let retry_at = chrono::Duration::from_std(self.policy.delay(attempts))
+            .ok()
+            .and_then(|delay| now.checked_add_signed(delay))
+            .unwrap_or(DateTime::<Utc>::MAX_UTC);
+        Some(retry_at)
+    }
+}
+
+// 配信待ちのイベントを一定間隔で送り続ける。取り出した件数が上限に達した場合は待たずに続ける
+pub fn spawn_publisher<C: Clock>(
+    publisher: OutboxPublisher<C>,
+    interval: Duration,
+) -> JoinHandle<()> {
+    spawn_periodic(
+        "publish events",
+        interval,
+        publisher,
+        |publisher| async move {
+            let published = publisher.publish_pending().await?;
+            Ok(published as i64 == BATCH_SIZE)
+        },
+    )
+}
@@ -0,0 +1,70 @@
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+
+// stateを渡してtaskを一定間隔で実行し続ける。Ok(true)は続きが残っていることを表し、待たずに再度実行する
+// DBの一時的なエラーではタスクを止めず、次の周期で再試行する。nameはエラーのログに使う
+pub fn spawn_periodic<S, F, Fut>(
+    name: &'static str,
+    interval: Duration,
+    state: S,
+    task: F,
+) -> JoinHandle<()>
+where
+    S: Send + Sync + 'static,
+    F: Fn(Arc<S>) -> Fut + Send + 'static,
+    Fut: Future<Output = anyhow::Result<bool>> + Send + 'static,
+{
+    let state = Arc::new(state);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            loop {
+                match task(state.clone()).await {
+                    Ok(true) => continue,
+                    Ok(false) => break,
+                    Err(e) => {
+                        tracing::warn!("fail {}, retry next tick: {:#}", name, e);
+                        break;
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn should_retry_next_tick_after_error_and_drain_without_waiting() {
+        let interval = Duration::from_secs(60 * 60);
+        let calls = Arc::new(AtomicUsize::new(0));
+
+        // 失敗した場合は、最初のtickの後は次の周期まで呼ばない
+        let failing = spawn_periodic("fail", interval, calls.clone(), |calls| async move {
+            calls.fetch_add(1, Ordering::SeqCst);
+            anyhow::bail!("temporary error")
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+        failing.abort();
+
+        // 続きがある間は、次の周期を待たずに呼び続ける
+        calls.store(0, Ordering::SeqCst);
+        let draining = spawn_periodic("drain", interval, calls.clone(), |calls| async move {
+            Ok(calls.fetch_add(1, Ordering::SeqCst) < 2)
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(3, calls.load(Ordering::SeqCst));
+        draining.abort();
+    }
+}
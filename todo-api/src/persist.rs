@@ -0,0 +1,339 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+use crate::repositories::todo::{TodoRepositoryForMemory, TodoSnapshot};
+use crate::repositories::user::{UserRepositoryForMemory, UserSnapshot};
+
+// 変更が続いても、書き込みはこの間隔に1回までにまとめる
+pub const PERSIST_INTERVAL: Duration = Duration::from_secs(1);
+
+// Todoの所有者を再起動後も同じユーザーに保つため、ユーザーも合わせて保存する
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub todos: TodoSnapshot,
+    pub users: UserSnapshot,
+}
+
+impl Snapshot {
+    async fn take(todos: &TodoRepositoryForMemory, users: &UserRepositoryForMemory) -> Self {
+        Self {
+            todos: todos.snapshot().await,
+            users: users.snapshot().await,
+        }
+    }
+
+    // 所有者を復元できないTodoは、同じidで後から作られた別のユーザーに見えないよう読み込まない
+    pub async fn restore(self, todos: &TodoRepositoryForMemory, users: &UserRepositoryForMemory) {
+        let Snapshot {
+            todos: mut todo_snapshot,
+            users: user_snapshot,
+        } = self;
+        let owners = user_snapshot.ids();
+        let ignored = todo_snapshot.retain_owners(|owner| owners.contains(&owner));
+        if ignored > 0 {
+            tracing::warn!(
+                "ignore {} todos whose owners are not in the snapshot",
+                ignored
+            );
+        }
+        users.restore_snapshot(user_snapshot).await;
+        todos.restore_snapshot(todo_snapshot).await;
+    }
+}
+
+// 壊れたスナップショットを読んだ場合は、1世代前の.bakから復元する。どちらも読めなければ空で起動する
+pub fn load(path: &Path) -> Option<Snapshot> {
+    for candidate in [path.to_path_buf(), backup_path(path)] {
+        match read(&candidate) {
+            Ok(Some(snapshot)) => {
+                tracing::info!("restored todos from snapshot [{}]", candidate.display());
+                return Some(snapshot);
+            }
+            Ok(None) => {}
+            Err(e) => tracing::warn!(
+                "ignore corrupted snapshot [{}]: {:#}",
+                candidate.display(),
+                e
+            ),
+        }
+    }
+    None
+}
+
+fn read(path: &Path) -> anyhow::Result<Option<Snapshot>> {
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let value: serde_json::Value = serde_json::from_slice(&bytes)?;
+    // ユーザーを保存する前のファイルはTodoの写しのみを持つ
+    if value.get("users").is_none() {
+        return Ok(Some(Snapshot {
+            todos: serde_json::from_value(value)?,
+            users: UserSnapshot::default(),
+        }));
+    }
+    Ok(Some(serde_json::from_value(value)?))
+}
+
+// 一時ファイルへ書き切ってからrenameするため、書き込み中に落ちても既存のファイルは壊れない
+fn save(path: &Path, snapshot: &Snapshot) -> anyhow::Result<()> {
+    let temp = sibling(path, "tmp");
+    let mut writer = BufWriter::new(File::create(&temp)?);
+    serde_json::to_writer(&mut writer, snapshot)?;
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    if path.exists() {
+        fs::rename(path, backup_path(path))?;
+    }
+    fs::rename(&temp, path)?;
+    Ok(())
+}
+
+fn backup_path(path: &Path) -> PathBuf {
+    sibling(path, "bak")
+}
+
+fn sibling(path: &Path, extension: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+#[derive(Debug, Clone)]
+pub struct SnapshotWriter {
+    todos: TodoRepositoryForMemory,
+    users: UserRepositoryForMemory,
+    path: PathBuf,
+    // 定期的な書き込みと終了時の書き込みが同じ一時ファイルを奪い合わないよう、直列にする
+    lock: Arc<Mutex<()>>,
+}
+
+impl SnapshotWriter {
+    pub fn new(
+        todos: TodoRepositoryForMemory,
+        users: UserRepositoryForMemory,
+        path: PathBuf,
+    ) -> Self {
+        Self {
+            todos,
+            users,
+            path,
+            lock: Arc::default(),
+        }
+    }
+
+    pub async fn write(&self) -> anyhow::Result<()> {
+        let _guard = self.lock.lock().await;
+        let snapshot = Snapshot::take(&self.todos, &self.users).await;
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || save(&path, &snapshot))
+            .await?
+            .with_context(|| format!("fail write snapshot [{}]", self.path.display()))
+    }
+
+    // 変更を検知したらintervalだけ待ち、その間の変更をまとめて書き込む
+    pub fn spawn(&self, interval: Duration) -> JoinHandle<()> {
+        let writer = self.clone();
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = writer.todos.changed() => {}
+                    _ = writer.users.changed() => {}
+                }
+                tokio::time::sleep(interval).await;
+                // 書き込めなかった場合も、次の変更で改めて全体を書き込む
+                if let Err(e) = writer.write().await {
+                    tracing::warn!("{:#}", e);
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::repositories::label::Label;
+    use crate::repositories::todo::{CreateTodo, TodoRepository};
+    use crate::repositories::todo_item::CreateTodoItem;
+    use crate::repositories::user::UserRepository;
+
+    use super::*;
+
+    const USER_ID: i32 = 1;
+
+    // テストごとに別のディレクトリを使い、並列に実行しても衝突しないようにする
+    fn snapshot_path(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rust-todo-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("todos.json")
+    }
+
+    // USER_IDのユーザーを作成したリポジトリを用意する
+    async fn repositories() -> (TodoRepositoryForMemory, UserRepositoryForMemory) {
+        let users = UserRepositoryForMemory::new();
+        let owner = users
+            .create("owner".to_string(), "hash".to_string())
+            .await
+            .expect("failed create user");
+        assert_eq!(USER_ID, owner.id);
+        (
+            TodoRepositoryForMemory::new(vec![Label::new(1, "work".to_string())]),
+            users,
+        )
+    }
+
+    async fn create_todo(repository: &TodoRepositoryForMemory, text: &str) {
+        repository
+            .create(USER_ID, CreateTodo::new(text.to_string(), vec![1]))
+            .await
+            .expect("failed create todo");
+    }
+
+    async fn restored(path: &Path) -> (TodoRepositoryForMemory, UserRepositoryForMemory) {
+        let todos = TodoRepositoryForMemory::new(vec![]);
+        let users = UserRepositoryForMemory::new();
+        if let Some(snapshot) = load(path) {
+            snapshot.restore(&todos, &users).await;
+        }
+        (todos, users)
+    }
+
+    async fn snapshot_of(
+        (todos, users): &(TodoRepositoryForMemory, UserRepositoryForMemory),
+    ) -> Snapshot {
+        Snapshot::take(todos, users).await
+    }
+
+    #[tokio::test]
+    async fn should_recover_data_after_restart() {
+        let path = snapshot_path("restart");
+        let (repository, users) = repositories().await;
+        create_todo(&repository, "first").await;
+        create_todo(&repository, "second").await;
+        repository
+            .create_item(
+                USER_ID,
+                1,
+                CreateTodoItem {
+                    text: "step".to_string(),
+                },
+            )
+            .await
+            .unwrap();
+        SnapshotWriter::new(repository.clone(), users.clone(), path.clone())
+            .write()
+            .await
+            .expect("failed write snapshot");
+
+        let restarted = restored(&path).await;
+        assert_eq!(
+            Snapshot::take(&repository, &users).await,
+            snapshot_of(&restarted).await
+        );
+        let (restarted, restarted_users) = restarted;
+        let page = restarted.all(USER_ID, Default::default()).await.unwrap();
+        assert_eq!(2, page.total);
+        assert_eq!(1, restarted.items(USER_ID, 1).await.unwrap().len());
+        // パスワードのハッシュも復元し、再起動前と同じユーザーでログインできる
+        let owner = restarted_users.find(USER_ID).await.unwrap();
+        assert_eq!(
+            ("owner", "hash"),
+            (owner.username.as_str(), owner.password_hash.as_str())
+        );
+
+        // 採番も引き継ぎ、再起動前のidを再利用しない
+        let todo = restarted
+            .create(USER_ID, CreateTodo::new("third".to_string(), vec![1]))
+            .await
+            .unwrap();
+        assert_eq!(3, todo.id);
+        let user = restarted_users
+            .create("other".to_string(), String::new())
+            .await
+            .unwrap();
+        assert_eq!(USER_ID + 1, user.id);
+    }
+
+    #[tokio::test]
+    async fn should_ignore_todos_whose_owners_are_missing() {
+        let path = snapshot_path("ownerless");
+        let (repository, _) = repositories().await;
+        create_todo(&repository, "first").await;
+        // ユーザーを保存する前の形式で書き込む
+        fs::write(
+            &path,
+            serde_json::to_vec(&repository.snapshot().await).unwrap(),
+        )
+        .unwrap();
+
+        // 再起動後に同じidで作られたユーザーには、以前の所有者のTodoを見せない
+        let (restarted, users) = restored(&path).await;
+        let user = users
+            .create("newcomer".to_string(), String::new())
+            .await
+            .unwrap();
+        assert_eq!(USER_ID, user.id);
+        let page = restarted.all(user.id, Default::default()).await.unwrap();
+        assert_eq!(0, page.total);
+    }
+
+    #[tokio::test]
+    async fn should_fall_back_to_backup_when_snapshot_is_corrupted() {
+        let path = snapshot_path("corrupted");
+        let (repository, users) = repositories().await;
+        let writer = SnapshotWriter::new(repository.clone(), users.clone(), path.clone());
+        create_todo(&repository, "first").await;
+        writer.write().await.unwrap();
+        let first = Snapshot::take(&repository, &users).await;
+        create_todo(&repository, "second").await;
+        writer.write().await.unwrap();
+
+        fs::write(&path, b"{ \"todos\": [").unwrap();
+        assert_eq!(first, snapshot_of(&restored(&path).await).await);
+
+        // .bakも読めない場合は空で起動する
+        fs::write(backup_path(&path), b"").unwrap();
+        assert_eq!(
+            Snapshot::default(),
+            snapshot_of(&restored(&path).await).await
+        );
+        assert!(load(&snapshot_path("missing")).is_none());
+    }
+
+    #[tokio::test]
+    async fn should_write_snapshot_after_mutation() {
+        let path = snapshot_path("debounce");
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        let users = UserRepositoryForMemory::new();
+        let handle = SnapshotWriter::new(repository.clone(), users.clone(), path.clone())
+            .spawn(Duration::from_millis(10));
+        // ユーザーの作成だけでも書き込む
+        users
+            .create("owner".to_string(), String::new())
+            .await
+            .unwrap();
+
+        let mut written = None;
+        for _ in 0..200 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            written = load(&path);
+            if written.is_some() {
+                break;
+            }
+        }
+        handle.abort();
+        assert_eq!(Some(Snapshot::take(&repository, &users).await), written);
+    }
+}
@@ -0,0 +1,312 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use axum::extract::ConnectInfo;
+use axum::http::header::{AUTHORIZATION, RETRY_AFTER};
+use axum::http::{HeaderMap, HeaderValue, Request, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Router;
+use tower::{Layer, Service};
+
+use crate::auth::AuthKeys;
+use crate::error::AppError;
+
+// ヘルスチェックはオーケストレーターから頻繁に呼ばれるため制限しない
+const EXEMPT_PATHS: [&str; 2] = ["/healthz", "/readyz"];
+// これを超えたら、満杯まで回復したバケツを捨てる。満杯のバケツは新しく作った場合と変わらない
+// それでも超える場合は、最も長く使われていないバケツを捨てる
+const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitPolicy {
+    pub per_minute: u32,
+    pub burst: u32,
+}
+
+impl RateLimitPolicy {
+    fn tokens_per_sec(&self) -> f64 {
+        f64::from(self.per_minute) / 60.0
+    }
+}
+
+// 認証済みのリクエストはユーザーごと、それ以外は接続元のIPごとに数える
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ClientKey {
+    User(i32),
+    Ip(IpAddr),
+    Unknown,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+// トークンバケット。burstまでの連続したリクエストを許し、その後はper_minuteの速さで回復する
+#[derive(Debug)]
+pub struct RateLimiter {
+    policy: RateLimitPolicy,
+    buckets: Mutex<HashMap<ClientKey, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(policy: RateLimitPolicy) -> Self {
+        Self {
+            policy,
+            buckets: Mutex::default(),
+        }
+    }
+
+    // 超えた場合は、次の1回分が回復するまでの時間を返す
+    fn acquire(&self, key: ClientKey, now: Instant) -> Result<(), Duration> {
+        let capacity = f64::from(self.policy.burst);
+        let rate = self.policy.tokens_per_sec();
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.len() >= MAX_TRACKED_CLIENTS && !buckets.contains_key(&key) {
+            buckets.retain(|_, bucket| {
+                let elapsed = now.saturating_duration_since(bucket.updated);
+                bucket.tokens + elapsed.as_secs_f64() * rate < capacity
+            });
+            if buckets.len() >= MAX_TRACKED_CLIENTS {
+                let oldest = buckets
+                    .iter()
+                    .min_by_key(|(_, bucket)| bucket.updated)
+                    .map(|(key, _)| *key);
+                if let Some(oldest) = oldest {
+                    buckets.remove(&oldest);
+                }
+            }
+        }
+
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * rate).min(capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / rate))
+        }
+    }
+}
+
+// Router::layerはルートごとにlayerを適用するため、RateLimiterはArcで全ルートから共有する
+// X-Forwarded-Forは、trusted_proxiesから接続された場合のみ読む
+pub fn with_rate_limit(
+    app: Router,
+    limiter: Arc<RateLimiter>,
+    keys: AuthKeys,
+    trusted_proxies: Vec<IpAddr>,
+) -> Router {
+    app.layer(RateLimitLayer {
+        limiter,
+        keys: Arc::new(keys),
+        trusted_proxies: Arc::new(trusted_proxies),
+    })
+}
+
+#[derive(Clone)]
+struct RateLimitLayer {
+    limiter: Arc<RateLimiter>,
+    keys: Arc<AuthKeys>,
+    trusted_proxies: Arc<Vec<IpAddr>>,
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimit<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimit {
+            inner,
+            limiter: self.limiter.clone(),
+            keys: self.keys.clone(),
+            trusted_proxies: self.trusted_proxies.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+struct RateLimit<S> {
+    inner: S,
+    limiter: Arc<RateLimiter>,
+    keys: Arc<AuthKeys>,
+    trusted_proxies: Arc<Vec<IpAddr>>,
+}
+
+impl<S> RateLimit<S> {
+    fn client_key<B>(&self, req: &Request<B>) -> ClientKey {
+        let user_id = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .and_then(|token| self.keys.user_id(token));
+        if let Some(user_id) = user_id {
+            return ClientKey::User(user_id);
+        }
+        req.extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| {
+                ClientKey::Ip(client_ip(addr.ip(), req.headers(), &self.trusted_proxies))
+            })
+            .unwrap_or(ClientKey::Unknown)
+    }
+}
+
+// X-Forwarded-Forの先頭はクライアントが自由に付けられるため、信頼するプロキシから接続された場合のみ、
+// 右から順にプロキシを飛ばし、最初に現れた信頼しないアドレスを元のクライアントとする
+fn client_ip(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpAddr]) -> IpAddr {
+    if !trusted_proxies.contains(&peer) {
+        return peer;
+    }
+    let hops: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect();
+    let mut client = peer;
+    for hop in hops.into_iter().rev() {
+        // 読めない値より左は、どのプロキシが付けたか確かめられない
+        match hop.trim().parse() {
+            Ok(ip) if trusted_proxies.contains(&ip) => client = ip,
+            Ok(ip) => return ip,
+            Err(_) => break,
+        }
+    }
+    client
+}
+
+fn too_many_requests(retry_after: Duration) -> Response {
+    let mut res = AppError::new(
+        StatusCode::TOO_MANY_REQUESTS,
+        "rate_limited",
+        "Too many requests",
+    )
+    .into_response();
+    // Retry-Afterは秒単位のため切り上げる
+    let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    res.headers_mut()
+        .insert(RETRY_AFTER, HeaderValue::from(secs.max(1)));
+    res
+}
+
+impl<S, ReqBody> Service<Request<ReqBody>> for RateLimit<S>
+where
+    S: Service<Request<ReqBody>, Response = Response>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<ReqBody>) -> Self::Future {
+        if !EXEMPT_PATHS.contains(&req.uri().path()) {
+            let key = self.client_key(&req);
+            if let Err(retry_after) = self.limiter.acquire(key, Instant::now()) {
+                tracing::warn!("rate limited {:?}", key);
+                return Box::pin(async move { Ok(too_many_requests(retry_after)) });
+            }
+        }
+        Box::pin(self.inner.call(req))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const POLICY: RateLimitPolicy = RateLimitPolicy {
+        per_minute: 60,
+        burst: 2,
+    };
+
+    #[test]
+    fn should_refill_tokens_over_time() {
+        let limiter = RateLimiter::new(POLICY);
+        let key = ClientKey::User(1);
+        let now = Instant::now();
+        assert_eq!(Ok(()), limiter.acquire(key, now));
+        assert_eq!(Ok(()), limiter.acquire(key, now));
+        assert_eq!(Err(Duration::from_secs(1)), limiter.acquire(key, now));
+        // 別のクライアントは別に数える
+        assert_eq!(Ok(()), limiter.acquire(ClientKey::User(2), now));
+
+        let later = now + Duration::from_millis(500);
+        let retry_after = limiter.acquire(key, later).unwrap_err();
+        assert!(retry_after <= Duration::from_millis(500));
+        assert_eq!(Ok(()), limiter.acquire(key, now + Duration::from_secs(1)));
+
+        // 長く空いても、回復するのはburstまで
+        let idle = now + Duration::from_secs(60);
+        assert_eq!(Ok(()), limiter.acquire(key, idle));
+        assert_eq!(Ok(()), limiter.acquire(key, idle));
+        assert!(limiter.acquire(key, idle).is_err());
+    }
+
+    #[test]
+    fn should_evict_least_recently_updated_bucket_over_limit() {
+        let limiter = RateLimiter::new(POLICY);
+        let now = Instant::now();
+        // 使ったばかりで満杯でないバケツだけでも、上限を超えて増やさない
+        for id in 0..=MAX_TRACKED_CLIENTS as i32 {
+            let at = now + Duration::from_micros(id as u64);
+            assert_eq!(Ok(()), limiter.acquire(ClientKey::User(id), at));
+        }
+        let buckets = limiter.buckets.lock().unwrap();
+        assert_eq!(MAX_TRACKED_CLIENTS, buckets.len());
+        assert!(!buckets.contains_key(&ClientKey::User(0)));
+        assert!(buckets.contains_key(&ClientKey::User(MAX_TRACKED_CLIENTS as i32)));
+    }
+
+    #[test]
+    fn should_read_forwarded_address_only_from_trusted_proxies() {
+        let proxy: IpAddr = [10, 0, 0, 1].into();
+        let client: IpAddr = [203, 0, 113, 7].into();
+        let trusted = [proxy, [10, 0, 0, 2].into()];
+        let mut headers = HeaderMap::new();
+        assert_eq!(proxy, client_ip(proxy, &headers, &trusted));
+
+        // 先頭に偽のアドレスを付けても、プロキシが付けた右端のアドレスを使う
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("198.51.100.9, 203.0.113.7, 10.0.0.2"),
+        );
+        assert_eq!(client, client_ip(proxy, &headers, &trusted));
+        // 信頼しない接続元のヘッダーは読まない
+        let peer: IpAddr = [192, 0, 2, 1].into();
+        assert_eq!(peer, client_ip(peer, &headers, &trusted));
+        assert_eq!(proxy, client_ip(proxy, &headers, &[]));
+
+        headers.insert(
+            "x-forwarded-for",
+            HeaderValue::from_static("203.0.113.7, unknown"),
+        );
+        assert_eq!(proxy, client_ip(proxy, &headers, &trusted));
+    }
+
+    #[test]
+    fn should_round_retry_after_up_to_seconds() {
+        let res = too_many_requests(Duration::from_millis(1500));
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, res.status());
+        assert_eq!("2", res.headers()[RETRY_AFTER]);
+        assert_eq!(
+            "1",
+            too_many_requests(Duration::ZERO).headers()[RETRY_AFTER]
+        );
+    }
+}
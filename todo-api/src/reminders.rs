@@ -0,0 +1,173 @@
+use std::time::Duration;
+
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use tokio::task::JoinHandle;
+
+use crate::clock::Clock;
+use crate::events::{TodoEvent, TodoEvents};
+use crate::periodic::spawn_periodic;
+use crate::repositories::todo::{DueReminder, TodoRepository};
+
+// 通知済みへの変更は通知より先に確定するため、通知に失敗しても再送はしない
+#[async_trait]
+pub trait Notifier: Send + Sync + 'static {
+    async fn notify(&self, reminder: &DueReminder) -> anyhow::Result<()>;
+}
+
+// ログへ出力し、イベントの配信先があればWebSocketとWebhookへも流す
+#[derive(Debug, Clone, Default)]
+pub struct TodoNotifier {
+    events: Option<TodoEvents>,
+}
+
+impl TodoNotifier {
+    pub fn new(events: Option<TodoEvents>) -> Self {
+        Self { events }
+    }
+}
+
+#[async_trait]
+impl Notifier for TodoNotifier {
+    async fn notify(&self, reminder: &DueReminder) -> anyhow::Result<()> {
+        tracing::info!(
+            "reminder for todo {} of user {}",
+            reminder.todo.id,
+            reminder.user_id
+        );
+        if let Some(events) = &self.events {
+            events.publish(
+                reminder.user_id,
+                TodoEvent::Reminded {
+                    todo: reminder.todo.clone(),
+                },
+            );
+        }
+        Ok(())
+    }
+}
+
+// 通知日時を過ぎたTodoを一定間隔で探し、notifierへ渡し続ける
+pub fn spawn_reminder<T: TodoRepository, N: Notifier, C: Clock>(
+    repository: T,
+    notifier: N,
+    clock: C,
+    interval: Duration,
+) -> JoinHandle<()> {
+    spawn_periodic(
+        "load due reminders",
+        interval,
+        (repository, notifier, clock),
+        |state| async move {
+            let (repository, notifier, clock) = &*state;
+            remind_due(repository, notifier, clock.now()).await?;
+            Ok(false)
+        },
+    )
+}
+
+async fn remind_due<T: TodoRepository, N: Notifier>(
+    repository: &T,
+    notifier: &N,
+    now: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    for reminder in repository.due_reminders(now).await? {
+        if let Err(e) = notifier.notify(&reminder).await {
+            tracing::warn!(
+                "fail notify reminder for todo {}: {:#}",
+                reminder.todo.id,
+                e
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Duration as ChronoDuration;
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::clock::test_utils::MockClock;
+    use crate::repositories::todo::TodoRepositoryForMemory;
+    use crate::repositories::todo::{CreateTodo, TodoEntity};
+
+    struct ChannelNotifier(mpsc::UnboundedSender<DueReminder>);
+
+    #[async_trait]
+    impl Notifier for ChannelNotifier {
+        async fn notify(&self, reminder: &DueReminder) -> anyhow::Result<()> {
+            self.0.send(reminder.clone())?;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn should_notify_due_reminders_once_in_background() {
+        let clock = MockClock::default();
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        let due = repository
+            .create(
+                1,
+                CreateTodo::new("due".to_string(), vec![])
+                    .with_remind_at(clock.now() - ChronoDuration::minutes(1)),
+            )
+            .await
+            .expect("failed create todo");
+        let later = repository
+            .create(
+                1,
+                CreateTodo::new("later".to_string(), vec![])
+                    .with_remind_at(clock.now() + ChronoDuration::days(1)),
+            )
+            .await
+            .expect("failed create todo");
+
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        let reminder = spawn_reminder(
+            repository.clone(),
+            ChannelNotifier(sender),
+            clock.clone(),
+            Duration::from_millis(10),
+        );
+        let notified = tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .expect("reminder was not notified")
+            .unwrap();
+        assert_eq!(1, notified.user_id);
+        assert_eq!(due.id, notified.todo.id);
+        assert_eq!(Some(clock.now()), notified.todo.reminded_at);
+
+        // 通知済みのTodoは次の周期で再度通知されない
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(receiver.try_recv().is_err());
+
+        clock.advance(ChronoDuration::days(1));
+        let notified = tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .expect("reminder was not notified")
+            .unwrap();
+        assert_eq!(later.id, notified.todo.id);
+        assert_eq!(Some(clock.now()), notified.todo.reminded_at);
+        reminder.abort();
+    }
+
+    #[tokio::test]
+    async fn should_publish_reminded_event() {
+        let events = TodoEvents::new();
+        let mut subscription = events.subscribe_all();
+        let todo = TodoEntity::new(1, "remind".to_string(), vec![]);
+        TodoNotifier::new(Some(events.clone()))
+            .notify(&DueReminder {
+                user_id: 2,
+                todo: todo.clone(),
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            Some((Some(2), TodoEvent::Reminded { todo })),
+            subscription.next().await
+        );
+    }
+}
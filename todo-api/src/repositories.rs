@@ -1,14 +1,209 @@
+use std::fmt::Display;
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use serde::{Deserialize, Deserializer};
 use thiserror::Error;
+use utoipa::IntoParams;
+
+use self::todo::{DEFAULT_LIST_LIMIT, MAX_LIST_LIMIT};
 
+pub mod backup;
+pub mod cache;
+pub mod comment;
+pub mod health;
 pub mod label;
+pub mod outbox;
+#[cfg(test)]
+pub mod test_utils;
 pub mod todo;
+pub mod todo_activity;
+pub mod todo_item;
+pub mod user;
+pub mod webhook;
 
+// PostgresのSQLSTATEと、SQLiteの拡張エラーコード(UNIQUE・PRIMARY KEY・FOREIGN KEY)
+const UNIQUE_VIOLATIONS: [&str; 3] = ["23505", "2067", "1555"];
+const FOREIGN_KEY_VIOLATIONS: [&str; 2] = ["23503", "787"];
+
+// リポジトリのトレイトが返すエラー。ハンドラは種類で分岐し、ステータスコードへ変換する
+// idは呼び出し側で分かる場合のみ入る。sqlxのエラーから変換した場合はNone
+// NotFoundはTodoのidに合わせてi64で持つ。ラベルなど32bitのidはintoで変換する
 #[derive(Debug, Error)]
-enum RepositoryError {
+pub enum RepositoryError {
     #[error("Unexpected Error: [{0}]")]
-    Unexpected(String),
-    #[error("NotFound, id is {0}")]
-    NotFound(i32),
-    #[error("Duplicate data, id is {0}")]
-    Duplicate(i32),
+    Unexpected(#[from] anyhow::Error),
+    #[error("NotFound{}", describe_id(.0))]
+    NotFound(Option<i64>),
+    #[error("Duplicate data{}", describe_id(.0))]
+    Duplicate(Option<i32>),
+    #[error("Validation error: {0}")]
+    Validation(String),
+    #[error("Conflict, id is {0} was modified by another request")]
+    Conflict(i64),
+    #[error("Precondition failed, id is {0} was modified after the client read it")]
+    PreconditionFailed(i64),
+    #[error("Label not found, id is {0}")]
+    InvalidLabel(i32),
+    #[error("Timed out waiting for a database connection")]
+    PoolTimedOut,
+}
+
+fn describe_id<T: Display>(id: &Option<T>) -> String {
+    id.as_ref()
+        .map(|id| format!(", id is {}", id))
+        .unwrap_or_default()
+}
+
+impl RepositoryError {
+    pub fn unexpected(message: impl Into<String>) -> Self {
+        Self::Unexpected(anyhow::anyhow!(message.into()))
+    }
+}
+
+// 行が見つからない・一意制約・外部キー制約の違反・接続待ちのタイムアウトはエラーの種類で返し、それ以外は想定外として扱う
+impl From<sqlx::Error> for RepositoryError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => Self::NotFound(None),
+            sqlx::Error::Database(ref db) => match db.code() {
+                Some(code) if UNIQUE_VIOLATIONS.contains(&code.as_ref()) => Self::Duplicate(None),
+                Some(code) if FOREIGN_KEY_VIOLATIONS.contains(&code.as_ref()) => {
+                    Self::Validation("Referenced resource does not exist".to_string())
+                }
+                _ => Self::Unexpected(e.into()),
+            },
+            sqlx::Error::PoolTimedOut => Self::PoolTimedOut,
+            e => Self::Unexpected(e.into()),
+        }
+    }
+}
+
+// メモリ上の実装のロックは、保持したままpanicしても毒状態から回復して使い続ける
+// 1つのテストのpanicが、同じリポジトリを共有する無関係なテストへ連鎖して失敗させないようにする
+pub(crate) fn read_lock<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+pub(crate) fn write_lock<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(PoisonError::into_inner)
+}
+
+// キーの省略(変更しない)とnull(値を消す)を区別するため、指定された値はSomeで包む
+fn deserialize_present<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+// Todo以外の一覧で共通のページング指定
+#[derive(Debug, Deserialize, Clone, Default, PartialEq, Eq, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PageQuery {
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+impl PageQuery {
+    pub fn limit(&self) -> i64 {
+        self.limit
+            .map(|limit| i64::from(limit).min(MAX_LIST_LIMIT))
+            .unwrap_or(DEFAULT_LIST_LIMIT)
+    }
+
+    pub fn offset(&self) -> i64 {
+        self.offset.map(i64::from).unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn repository_error_message_test() {
+        assert_eq!(
+            "NotFound, id is 1",
+            RepositoryError::NotFound(Some(1)).to_string()
+        );
+        assert_eq!("NotFound", RepositoryError::NotFound(None).to_string());
+        assert_eq!(
+            "Duplicate data, id is 2",
+            RepositoryError::Duplicate(Some(2)).to_string()
+        );
+        assert_eq!(
+            "Duplicate data",
+            RepositoryError::Duplicate(None).to_string()
+        );
+    }
+
+    #[test]
+    fn sqlx_error_without_database_test() {
+        assert!(matches!(
+            RepositoryError::from(sqlx::Error::RowNotFound),
+            RepositoryError::NotFound(None)
+        ));
+        assert!(matches!(
+            RepositoryError::from(sqlx::Error::PoolTimedOut),
+            RepositoryError::PoolTimedOut
+        ));
+        assert!(matches!(
+            RepositoryError::from(sqlx::Error::PoolClosed),
+            RepositoryError::Unexpected(_)
+        ));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn sqlx_error_from_sqlite_test() {
+        let pool = crate::database::connect_sqlite("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::query(
+            r#"
+create table parents (id integer primary key, name text not null unique);
+create table children (id integer primary key, parent_id integer not null references parents (id));
+insert into parents (id, name) values (1, 'first');
+"#,
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let execute = |sql: &'static str| {
+            let pool = pool.clone();
+            async move {
+                let e = sqlx::query(sql).execute(&pool).await.expect_err(sql);
+                RepositoryError::from(e)
+            }
+        };
+
+        // 一意制約・主キーの重複
+        assert!(matches!(
+            execute("insert into parents (id, name) values (2, 'first')").await,
+            RepositoryError::Duplicate(None)
+        ));
+        assert!(matches!(
+            execute("insert into parents (id, name) values (1, 'second')").await,
+            RepositoryError::Duplicate(None)
+        ));
+        // 存在しない行を参照する外部キー
+        assert!(matches!(
+            execute("insert into children (id, parent_id) values (1, 999)").await,
+            RepositoryError::Validation(_)
+        ));
+        assert!(matches!(
+            execute("select id from missing_table").await,
+            RepositoryError::Unexpected(_)
+        ));
+        let e = sqlx::query("select id from parents where id = 999")
+            .fetch_one(&pool)
+            .await
+            .map(|_| ())
+            .unwrap_err();
+        assert!(matches!(
+            RepositoryError::from(e),
+            RepositoryError::NotFound(None)
+        ));
+    }
 }
@@ -0,0 +1,231 @@
+use std::collections::HashSet;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+use validator::{Validate, ValidationError, ValidationErrors};
+
+use crate::repositories::label::{validate_color, Label};
+use crate::repositories::todo::{validate_not_blank, Priority, TodoEntity};
+
+// 環境間の移行用に、ユーザーのTodoと全ラベルを1つのドキュメントにまとめる
+// idはエクスポート元のものであり、インポート時に採番し直す
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
+pub struct Backup {
+    pub todos: Vec<BackupTodo>,
+    pub labels: Vec<BackupLabel>,
+    pub associations: Vec<BackupAssociation>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate, ToSchema)]
+pub struct BackupTodo {
+    pub id: i64,
+    #[validate(custom = "validate_not_blank")]
+    #[validate(length(max = 100, message = "Over text length"))]
+    pub text: String,
+    pub completed: bool,
+    pub priority: Priority,
+    pub due_date: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate, ToSchema)]
+pub struct BackupLabel {
+    pub id: i32,
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 100, message = "Over text length"))]
+    pub name: String,
+    #[validate(custom = "validate_color")]
+    #[schema(example = "#808080")]
+    pub color: String,
+    #[validate(length(max = 500, message = "Over description length"))]
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+pub struct BackupAssociation {
+    pub todo_id: i64,
+    pub label_id: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, ToSchema)]
+pub struct ImportSummary {
+    pub todos: usize,
+    // 新たに作成したラベルの数。名前が一致した既存のラベルへ統合したものは含まない
+    pub labels: usize,
+}
+
+impl Backup {
+    pub fn new(todos: Vec<TodoEntity>, labels: Vec<Label>) -> Self {
+        let associations = todos
+            .iter()
+            .flat_map(|todo| {
+                todo.labels.iter().map(|label| BackupAssociation {
+                    todo_id: todo.id,
+                    label_id: label.id,
+                })
+            })
+            .collect();
+        Self {
+            todos: todos.into_iter().map(BackupTodo::from).collect(),
+            labels: labels.into_iter().map(BackupLabel::from).collect(),
+            associations,
+        }
+    }
+}
+
+impl From<TodoEntity> for BackupTodo {
+    fn from(todo: TodoEntity) -> Self {
+        Self {
+            id: todo.id,
+            text: todo.text,
+            completed: todo.completed,
+            priority: todo.priority,
+            due_date: todo.due_date,
+            created_at: todo.created_at,
+            updated_at: todo.updated_at,
+        }
+    }
+}
+
+impl From<Label> for BackupLabel {
+    fn from(label: Label) -> Self {
+        Self {
+            id: label.id,
+            name: label.name,
+            color: label.color,
+            description: label.description,
+        }
+    }
+}
+
+// 一部のみ取り込まれることのないよう、書き込む前にドキュメント全体を検証する
+// 大きなドキュメントでもエラーが膨らまないよう、最初に見つかった不正なレコードのみを返す
+impl Validate for Backup {
+    fn validate(&self) -> Result<(), ValidationErrors> {
+        let mut label_ids = HashSet::new();
+        first_invalid("labels", &self.labels, |label| {
+            label.validate()?;
+            check(label_ids.insert(label.id), "id", "Duplicate id")
+        })?;
+
+        let mut todo_ids = HashSet::new();
+        first_invalid("todos", &self.todos, |todo| {
+            todo.validate()?;
+            check(todo_ids.insert(todo.id), "id", "Duplicate id")
+        })?;
+
+        first_invalid("associations", &self.associations, |association| {
+            check(
+                todo_ids.contains(&association.todo_id),
+                "todo_id",
+                "Todo not found in document",
+            )?;
+            check(
+                label_ids.contains(&association.label_id),
+                "label_id",
+                "Label not found in document",
+            )
+        })
+    }
+}
+
+fn check(valid: bool, field: &'static str, message: &'static str) -> Result<(), ValidationErrors> {
+    if valid {
+        return Ok(());
+    }
+    let mut error = ValidationError::new("invalid");
+    error.message = Some(message.into());
+    let mut errors = ValidationErrors::new();
+    errors.add(field, error);
+    Err(errors)
+}
+
+fn first_invalid<T>(
+    field: &'static str,
+    records: &[T],
+    mut validate: impl FnMut(&T) -> Result<(), ValidationErrors>,
+) -> Result<(), ValidationErrors> {
+    for (index, record) in records.iter().enumerate() {
+        if let Err(errors) = validate(record) {
+            // merge_allは位置からインデックスを決めるため、手前のレコードはOkで埋める
+            let mut children: Vec<_> = (0..index).map(|_| Ok(())).collect();
+            children.push(ValidationErrors::merge(Ok(()), field, Err(errors)));
+            return ValidationErrors::merge_all(Ok(()), field, children);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use crate::error::AppError;
+
+    use super::*;
+
+    fn backup() -> Backup {
+        serde_json::from_value(json!({
+            "todos": [
+                { "id": 1, "text": "first", "completed": false, "priority": "high",
+                  "due_date": null, "created_at": "2024-01-01T00:00:00Z",
+                  "updated_at": "2024-01-01T00:00:00Z" },
+                { "id": 2, "text": "second", "completed": true, "priority": "low",
+                  "due_date": null, "created_at": "2024-01-01T00:00:00Z",
+                  "updated_at": "2024-01-01T00:00:00Z" },
+            ],
+            "labels": [
+                { "id": 10, "name": "work", "color": "#808080", "description": null },
+            ],
+            "associations": [{ "todo_id": 2, "label_id": 10 }],
+        }))
+        .unwrap()
+    }
+
+    fn first_field(backup: &Backup) -> (String, String) {
+        let errors = backup.validate().unwrap_err();
+        let body = serde_json::to_value(AppError::validation(errors).into_body()).unwrap();
+        let fields = body["error"]["fields"].as_array().unwrap();
+        assert_eq!(1, fields.len());
+        (
+            fields[0]["field"].as_str().unwrap().to_string(),
+            fields[0]["message"].as_str().unwrap().to_string(),
+        )
+    }
+
+    #[test]
+    fn validate_backup_test() {
+        assert!(backup().validate().is_ok());
+
+        let mut invalid = backup();
+        invalid.todos[1].text = " ".to_string();
+        invalid.todos.push(invalid.todos[1].clone());
+        assert_eq!(
+            ("todos[1].text".to_string(), "Can not be empty".to_string()),
+            first_field(&invalid)
+        );
+
+        let mut invalid = backup();
+        invalid.todos[1].id = 1;
+        assert_eq!(
+            ("todos[1].id".to_string(), "Duplicate id".to_string()),
+            first_field(&invalid)
+        );
+
+        let mut invalid = backup();
+        invalid.labels[0].color = "red".to_string();
+        assert_eq!("labels[0].color", first_field(&invalid).0);
+
+        let mut invalid = backup();
+        invalid.associations[0].label_id = 11;
+        assert_eq!(
+            (
+                "associations[0].label_id".to_string(),
+                "Label not found in document".to_string()
+            ),
+            first_field(&invalid)
+        );
+    }
+}
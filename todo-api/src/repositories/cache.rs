@@ -0,0 +1,740 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::stream::BoxStream;
+use uuid::Uuid;
+
+use super::backup::{Backup, ImportSummary};
+use super::label::{CreateLabel, Label, LabelRepository, LabelWithUsage, UpdateLabel};
+use super::todo::{
+    CreateTodo, CreateTodoWithLabelNames, DueReminder, DuplicateTodo, FoundTodos,
+    IdempotencyRecord, MoveTarget, Precondition, ReplaceTodo, TodoEntity, TodoListQuery, TodoPage,
+    TodoRepository, TodoStats, UpdateTodo, UpdateTodos, UpdatedTodos,
+};
+use super::todo_activity::TodoActivityPage;
+use super::todo_item::{CreateTodoItem, TodoItem, UpdateTodoItem};
+use super::{PageQuery, RepositoryError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CachePolicy {
+    pub ttl: Duration,
+    pub max_entries: usize,
+}
+
+// 読み込み結果をTTLの間だけ保持する。上限に達した場合は最も古いものから捨てる
+#[derive(Debug)]
+struct TtlCache<K, V> {
+    policy: CachePolicy,
+    state: Mutex<CacheState<K, V>>,
+}
+
+#[derive(Debug)]
+struct CacheState<K, V> {
+    entries: HashMap<K, (Instant, V)>,
+    // clearのたびに進める。読み込み中に書き込まれた場合、古い結果を保持しないために使う
+    generation: u64,
+}
+
+impl<K: Eq + Hash + Clone, V: Clone> TtlCache<K, V> {
+    fn new(policy: CachePolicy) -> Self {
+        Self {
+            policy,
+            state: Mutex::new(CacheState {
+                entries: HashMap::new(),
+                generation: 0,
+            }),
+        }
+    }
+
+    // キャッシュにない場合は、insertに渡す現在の世代を返す
+    fn get(&self, key: &K) -> Result<V, u64> {
+        let state = self.state.lock().unwrap();
+        match state.entries.get(key) {
+            Some((inserted_at, value)) if inserted_at.elapsed() < self.policy.ttl => {
+                Ok(value.clone())
+            }
+            _ => Err(state.generation),
+        }
+    }
+
+    fn insert(&self, key: K, value: V, generation: u64) {
+        let mut state = self.state.lock().unwrap();
+        if state.generation != generation || self.policy.max_entries == 0 {
+            return;
+        }
+        if state.entries.len() >= self.policy.max_entries && !state.entries.contains_key(&key) {
+            let ttl = self.policy.ttl;
+            state
+                .entries
+                .retain(|_, (inserted_at, _)| inserted_at.elapsed() < ttl);
+            if state.entries.len() >= self.policy.max_entries {
+                let oldest = state
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, (inserted_at, _))| *inserted_at)
+                    .map(|(key, _)| key.clone());
+                if let Some(oldest) = oldest {
+                    state.entries.remove(&oldest);
+                }
+            }
+        }
+        state.entries.insert(key, (Instant::now(), value));
+    }
+
+    fn clear(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.entries.clear();
+        state.generation += 1;
+    }
+
+    async fn get_or_load<F>(&self, key: K, load: F) -> Result<V, RepositoryError>
+    where
+        F: std::future::Future<Output = Result<V, RepositoryError>>,
+    {
+        match self.get(&key) {
+            Ok(value) => Ok(value),
+            Err(generation) => {
+                let value = load.await?;
+                self.insert(key, value.clone(), generation);
+                Ok(value)
+            }
+        }
+    }
+}
+
+// 一覧の読み込みのみをキャッシュし、それ以外は内側のリポジトリへそのまま渡す
+// 書き込みは結果によらずキャッシュ全体を破棄する。別のインスタンスでの変更はTTLの経過まで反映されない
+// ラベルの変更は、CachedLabelRepository::with_todo_cacheで共有した場合にあわせて破棄される
+#[derive(Debug, Clone)]
+pub struct CachedTodoRepository<R> {
+    inner: R,
+    // policyが未指定の場合はキャッシュしない
+    lists: Option<Arc<TtlCache<(i32, TodoListQuery), TodoPage>>>,
+}
+
+impl<R: TodoRepository> CachedTodoRepository<R> {
+    pub fn new(inner: R, policy: Option<CachePolicy>) -> Self {
+        Self {
+            inner,
+            lists: policy.map(|policy| Arc::new(TtlCache::new(policy))),
+        }
+    }
+
+    fn invalidate<T>(&self, result: T) -> T {
+        if let Some(lists) = &self.lists {
+            lists.clear();
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl<R: TodoRepository> TodoRepository for CachedTodoRepository<R> {
+    async fn create(
+        &self,
+        user_id: i32,
+        payload: CreateTodo,
+    ) -> Result<TodoEntity, RepositoryError> {
+        self.invalidate(self.inner.create(user_id, payload).await)
+    }
+
+    async fn create_many(
+        &self,
+        user_id: i32,
+        payloads: Vec<CreateTodo>,
+    ) -> Result<Vec<TodoEntity>, RepositoryError> {
+        self.invalidate(self.inner.create_many(user_id, payloads).await)
+    }
+
+    async fn create_idempotent(
+        &self,
+        user_id: i32,
+        payload: CreateTodo,
+        key: Option<&str>,
+    ) -> Result<TodoEntity, RepositoryError> {
+        self.invalidate(self.inner.create_idempotent(user_id, payload, key).await)
+    }
+
+    async fn create_many_idempotent(
+        &self,
+        user_id: i32,
+        payloads: Vec<CreateTodo>,
+        key: Option<&str>,
+    ) -> Result<Vec<TodoEntity>, RepositoryError> {
+        self.invalidate(
+            self.inner
+                .create_many_idempotent(user_id, payloads, key)
+                .await,
+        )
+    }
+
+    async fn create_many_with_label_names(
+        &self,
+        user_id: i32,
+        payloads: Vec<CreateTodoWithLabelNames>,
+    ) -> Result<Vec<TodoEntity>, RepositoryError> {
+        self.invalidate(
+            self.inner
+                .create_many_with_label_names(user_id, payloads)
+                .await,
+        )
+    }
+
+    async fn find(&self, user_id: i32, id: i64) -> Result<TodoEntity, RepositoryError> {
+        self.inner.find(user_id, id).await
+    }
+
+    async fn find_many(&self, user_id: i32, ids: &[i64]) -> Result<FoundTodos, RepositoryError> {
+        self.inner.find_many(user_id, ids).await
+    }
+
+    async fn exists(&self, user_id: i32, id: i64) -> Result<bool, RepositoryError> {
+        self.inner.exists(user_id, id).await
+    }
+
+    async fn find_open_by_text(
+        &self,
+        user_id: i32,
+        text: &str,
+    ) -> Result<Option<TodoEntity>, RepositoryError> {
+        self.inner.find_open_by_text(user_id, text).await
+    }
+
+    async fn find_by_client_id(
+        &self,
+        user_id: i32,
+        client_id: Uuid,
+    ) -> Result<Option<TodoEntity>, RepositoryError> {
+        self.inner.find_by_client_id(user_id, client_id).await
+    }
+
+    async fn labels_for_todos(
+        &self,
+        user_id: i32,
+        ids: Vec<i64>,
+    ) -> Result<HashMap<i64, Vec<Label>>, RepositoryError> {
+        self.inner.labels_for_todos(user_id, ids).await
+    }
+
+    async fn all(&self, user_id: i32, query: TodoListQuery) -> Result<TodoPage, RepositoryError> {
+        match &self.lists {
+            Some(lists) => {
+                lists
+                    .get_or_load((user_id, query.clone()), self.inner.all(user_id, query))
+                    .await
+            }
+            None => self.inner.all(user_id, query).await,
+        }
+    }
+
+    async fn last_modified(&self, user_id: i32) -> Result<Option<DateTime<Utc>>, RepositoryError> {
+        self.inner.last_modified(user_id).await
+    }
+
+    async fn update(
+        &self,
+        user_id: i32,
+        id: i64,
+        payload: UpdateTodo,
+    ) -> Result<TodoEntity, RepositoryError> {
+        self.invalidate(self.inner.update(user_id, id, payload).await)
+    }
+
+    async fn replace(
+        &self,
+        user_id: i32,
+        id: i64,
+        payload: ReplaceTodo,
+    ) -> Result<TodoEntity, RepositoryError> {
+        self.invalidate(self.inner.replace(user_id, id, payload).await)
+    }
+
+    async fn update_many(
+        &self,
+        user_id: i32,
+        payload: UpdateTodos,
+    ) -> Result<UpdatedTodos, RepositoryError> {
+        self.invalidate(self.inner.update_many(user_id, payload).await)
+    }
+
+    async fn delete(
+        &self,
+        user_id: i32,
+        id: i64,
+        precondition: Precondition,
+    ) -> Result<(), RepositoryError> {
+        self.invalidate(self.inner.delete(user_id, id, precondition).await)
+    }
+
+    async fn delete_permanently(&self, user_id: i32, id: i64) -> Result<(), RepositoryError> {
+        self.invalidate(self.inner.delete_permanently(user_id, id).await)
+    }
+
+    async fn trash(&self, user_id: i32) -> Result<Vec<TodoEntity>, RepositoryError> {
+        self.inner.trash(user_id).await
+    }
+
+    async fn restore(&self, user_id: i32, id: i64) -> Result<TodoEntity, RepositoryError> {
+        self.invalidate(self.inner.restore(user_id, id).await)
+    }
+
+    async fn delete_completed(&self, user_id: i32) -> Result<u64, RepositoryError> {
+        self.invalidate(self.inner.delete_completed(user_id).await)
+    }
+
+    async fn purge_deleted_before(&self, cutoff: DateTime<Utc>) -> Result<u64, RepositoryError> {
+        self.invalidate(self.inner.purge_deleted_before(cutoff).await)
+    }
+
+    async fn claim_idempotency_key(
+        &self,
+        user_id: i32,
+        key: &str,
+        fingerprint: &str,
+    ) -> Result<Option<IdempotencyRecord>, RepositoryError> {
+        self.inner
+            .claim_idempotency_key(user_id, key, fingerprint)
+            .await
+    }
+
+    async fn complete_idempotency_key(
+        &self,
+        user_id: i32,
+        key: &str,
+        response: serde_json::Value,
+    ) -> Result<(), RepositoryError> {
+        self.inner
+            .complete_idempotency_key(user_id, key, response)
+            .await
+    }
+
+    async fn release_idempotency_key(
+        &self,
+        user_id: i32,
+        key: &str,
+    ) -> Result<(), RepositoryError> {
+        self.inner.release_idempotency_key(user_id, key).await
+    }
+
+    async fn purge_idempotency_keys_before(
+        &self,
+        cutoff: DateTime<Utc>,
+    ) -> Result<u64, RepositoryError> {
+        self.inner.purge_idempotency_keys_before(cutoff).await
+    }
+
+    async fn attach_label(
+        &self,
+        user_id: i32,
+        id: i64,
+        label_id: i32,
+    ) -> Result<TodoEntity, RepositoryError> {
+        self.invalidate(self.inner.attach_label(user_id, id, label_id).await)
+    }
+
+    async fn detach_label(
+        &self,
+        user_id: i32,
+        id: i64,
+        label_id: i32,
+    ) -> Result<TodoEntity, RepositoryError> {
+        self.invalidate(self.inner.detach_label(user_id, id, label_id).await)
+    }
+
+    async fn export(&self, user_id: i32) -> Result<Backup, RepositoryError> {
+        self.inner.export(user_id).await
+    }
+
+    async fn import(&self, user_id: i32, backup: Backup) -> Result<ImportSummary, RepositoryError> {
+        self.invalidate(self.inner.import(user_id, backup).await)
+    }
+
+    async fn reset(&self) -> Result<(), RepositoryError> {
+        self.invalidate(self.inner.reset().await)
+    }
+
+    async fn seed(&self, user_id: i32, fixtures: Backup) -> Result<ImportSummary, RepositoryError> {
+        self.invalidate(self.inner.seed(user_id, fixtures).await)
+    }
+
+    fn stream_all(&self, user_id: i32) -> BoxStream<'static, Result<TodoEntity, RepositoryError>> {
+        self.inner.stream_all(user_id)
+    }
+
+    async fn stats(&self, user_id: i32) -> Result<TodoStats, RepositoryError> {
+        self.inner.stats(user_id).await
+    }
+
+    async fn items(&self, user_id: i32, id: i64) -> Result<Vec<TodoItem>, RepositoryError> {
+        self.inner.items(user_id, id).await
+    }
+
+    async fn create_item(
+        &self,
+        user_id: i32,
+        id: i64,
+        payload: CreateTodoItem,
+    ) -> Result<TodoItem, RepositoryError> {
+        self.inner.create_item(user_id, id, payload).await
+    }
+
+    async fn update_item(
+        &self,
+        user_id: i32,
+        id: i64,
+        item_id: i32,
+        payload: UpdateTodoItem,
+    ) -> Result<TodoItem, RepositoryError> {
+        self.inner.update_item(user_id, id, item_id, payload).await
+    }
+
+    async fn delete_item(
+        &self,
+        user_id: i32,
+        id: i64,
+        item_id: i32,
+    ) -> Result<(), RepositoryError> {
+        self.inner.delete_item(user_id, id, item_id).await
+    }
+
+    async fn duplicate(
+        &self,
+        user_id: i32,
+        id: i64,
+        payload: DuplicateTodo,
+    ) -> Result<TodoEntity, RepositoryError> {
+        self.invalidate(self.inner.duplicate(user_id, id, payload).await)
+    }
+
+    async fn archive(&self, user_id: i32, id: i64) -> Result<TodoEntity, RepositoryError> {
+        self.invalidate(self.inner.archive(user_id, id).await)
+    }
+
+    async fn unarchive(&self, user_id: i32, id: i64) -> Result<TodoEntity, RepositoryError> {
+        self.invalidate(self.inner.unarchive(user_id, id).await)
+    }
+
+    async fn archive_completed(&self, user_id: i32) -> Result<u64, RepositoryError> {
+        self.invalidate(self.inner.archive_completed(user_id).await)
+    }
+
+    async fn move_todo(
+        &self,
+        user_id: i32,
+        id: i64,
+        target: MoveTarget,
+    ) -> Result<TodoEntity, RepositoryError> {
+        self.invalidate(self.inner.move_todo(user_id, id, target).await)
+    }
+
+    async fn activity(
+        &self,
+        user_id: i32,
+        id: i64,
+        query: PageQuery,
+    ) -> Result<TodoActivityPage, RepositoryError> {
+        self.inner.activity(user_id, id, query).await
+    }
+
+    async fn due_reminders(&self, now: DateTime<Utc>) -> Result<Vec<DueReminder>, RepositoryError> {
+        self.invalidate(self.inner.due_reminders(now).await)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum LabelList {
+    All,
+    WithCounts,
+}
+
+// 件数はTodoの変更では破棄されないため、TTLの間は古い値を返す場合がある
+#[derive(Debug, Clone)]
+pub struct CachedLabelRepository<R> {
+    inner: R,
+    lists: Option<Arc<TtlCache<LabelList, Vec<LabelWithUsage>>>>,
+    // with_todo_cacheで共有したTodoの一覧。ラベルの書き込みのたびにあわせて破棄する
+    todo_lists: Option<Arc<TtlCache<(i32, TodoListQuery), TodoPage>>>,
+}
+
+impl<R: LabelRepository> CachedLabelRepository<R> {
+    pub fn new(inner: R, policy: Option<CachePolicy>) -> Self {
+        Self {
+            inner,
+            lists: policy.map(|policy| Arc::new(TtlCache::new(policy))),
+            todo_lists: None,
+        }
+    }
+
+    // Todoの一覧はラベルの名前を含み、ラベルの変更で変更日時も進むため、古い一覧を返さないよう共有する
+    pub fn with_todo_cache<T: TodoRepository>(self, todos: &CachedTodoRepository<T>) -> Self {
+        Self {
+            todo_lists: todos.lists.clone(),
+            ..self
+        }
+    }
+
+    fn invalidate<T>(&self, result: T) -> T {
+        for lists in self.todo_lists.iter() {
+            lists.clear();
+        }
+        if let Some(lists) = &self.lists {
+            lists.clear();
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl<R: LabelRepository> LabelRepository for CachedLabelRepository<R> {
+    async fn create(&self, payload: CreateLabel) -> Result<Label, RepositoryError> {
+        self.invalidate(self.inner.create(payload).await)
+    }
+
+    async fn find(&self, id: i32) -> Result<Label, RepositoryError> {
+        self.inner.find(id).await
+    }
+
+    async fn all(&self) -> Result<Vec<Label>, RepositoryError> {
+        let lists = match &self.lists {
+            Some(lists) => lists,
+            None => return self.inner.all().await,
+        };
+        // 件数なしの一覧も同じ型で保持し、取り出すときに件数を外す
+        let load = async {
+            let labels = self.inner.all().await?;
+            Ok(labels.into_iter().map(LabelWithUsage::from).collect())
+        };
+        let labels = lists.get_or_load(LabelList::All, load).await?;
+        Ok(labels.into_iter().map(|label| label.label).collect())
+    }
+
+    async fn all_with_counts(&self) -> Result<Vec<LabelWithUsage>, RepositoryError> {
+        match &self.lists {
+            Some(lists) => {
+                lists
+                    .get_or_load(LabelList::WithCounts, self.inner.all_with_counts())
+                    .await
+            }
+            None => self.inner.all_with_counts().await,
+        }
+    }
+
+    async fn update(&self, id: i32, payload: UpdateLabel) -> Result<Label, RepositoryError> {
+        self.invalidate(self.inner.update(id, payload).await)
+    }
+
+    async fn delete(&self, id: i32) -> Result<(), RepositoryError> {
+        self.invalidate(self.inner.delete(id).await)
+    }
+
+    async fn merge(&self, from: i32, into: i32) -> Result<LabelWithUsage, RepositoryError> {
+        self.invalidate(self.inner.merge(from, into).await)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::repositories::label::LabelRepositoryForMemory;
+    use crate::repositories::test_utils::conformance::run_todo_repository_suite;
+    use crate::repositories::todo::TodoRepositoryForMemory;
+
+    use super::*;
+
+    const USER_ID: i32 = 1;
+    const POLICY: CachePolicy = CachePolicy {
+        ttl: Duration::from_secs(60),
+        max_entries: 10,
+    };
+
+    // キャッシュを挟んでも、更新の直後から内側のリポジトリと同じ結果を返す
+    #[tokio::test]
+    async fn conformance_suite() {
+        run_todo_repository_suite(
+            || CachedTodoRepository::new(TodoRepositoryForMemory::new(vec![]), Some(POLICY)),
+            USER_ID,
+        )
+        .await;
+    }
+
+    fn todo(text: &str) -> CreateTodo {
+        CreateTodo::new(text.to_string(), vec![])
+    }
+
+    async fn total(repository: &impl TodoRepository, query: TodoListQuery) -> i64 {
+        repository.all(USER_ID, query).await.unwrap().total
+    }
+
+    #[tokio::test]
+    async fn should_invalidate_todo_lists_on_write() {
+        let inner = TodoRepositoryForMemory::new(vec![]);
+        let repository = CachedTodoRepository::new(inner.clone(), Some(POLICY));
+        repository.create(USER_ID, todo("first")).await.unwrap();
+        assert_eq!(1, total(&repository, Default::default()).await);
+
+        // 内側へ直接書き込んだ変更は、キャッシュが破棄されるまで見えない
+        inner.create(USER_ID, todo("bypass")).await.unwrap();
+        assert_eq!(1, total(&repository, Default::default()).await);
+
+        repository
+            .delete(USER_ID, 1, Precondition::default())
+            .await
+            .unwrap();
+        assert_eq!(1, total(&repository, Default::default()).await);
+        let page = repository.all(USER_ID, Default::default()).await.unwrap();
+        assert_eq!("bypass", page.todos[0].text);
+    }
+
+    #[tokio::test]
+    async fn should_cache_filtered_and_paginated_lists_per_key() {
+        let inner = TodoRepositoryForMemory::new(vec![]);
+        let repository = CachedTodoRepository::new(inner.clone(), Some(POLICY));
+        for text in ["first", "second", "third"] {
+            repository.create(USER_ID, todo(text)).await.unwrap();
+        }
+        let first_page = TodoListQuery {
+            limit: Some(1),
+            ..Default::default()
+        };
+        let completed = TodoListQuery {
+            completed: Some(true),
+            ..Default::default()
+        };
+        let page = repository.all(USER_ID, first_page.clone()).await.unwrap();
+        assert_eq!(1, page.todos.len());
+        assert_eq!(3, page.total);
+        assert_eq!(0, total(&repository, completed.clone()).await);
+        assert_eq!(3, total(&repository, Default::default()).await);
+
+        inner.create(USER_ID, todo("bypass")).await.unwrap();
+        assert_eq!(3, total(&repository, first_page.clone()).await);
+        assert_eq!(3, total(&repository, Default::default()).await);
+        // 初めて使う条件は、その時点の内容を読み込む
+        let second_page = TodoListQuery {
+            limit: Some(1),
+            offset: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(4, total(&repository, second_page).await);
+        // 別のユーザーの一覧も別に保持する
+        assert_eq!(
+            0,
+            repository.all(2, Default::default()).await.unwrap().total
+        );
+
+        // 1件の書き込みで、全ての条件の一覧が破棄される
+        repository
+            .delete(USER_ID, 1, Precondition::default())
+            .await
+            .unwrap();
+        assert_eq!(3, total(&repository, first_page).await);
+        assert_eq!(3, total(&repository, Default::default()).await);
+        assert_eq!(0, total(&repository, completed).await);
+    }
+
+    #[tokio::test]
+    async fn should_reload_after_ttl() {
+        let inner = TodoRepositoryForMemory::new(vec![]);
+        let policy = CachePolicy {
+            ttl: Duration::from_millis(20),
+            ..POLICY
+        };
+        let repository = CachedTodoRepository::new(inner.clone(), Some(policy));
+        assert_eq!(0, total(&repository, Default::default()).await);
+        inner.create(USER_ID, todo("bypass")).await.unwrap();
+        assert_eq!(0, total(&repository, Default::default()).await);
+
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(1, total(&repository, Default::default()).await);
+    }
+
+    #[tokio::test]
+    async fn should_pass_through_without_policy() {
+        let inner = TodoRepositoryForMemory::new(vec![]);
+        let repository = CachedTodoRepository::new(inner.clone(), None);
+        assert_eq!(0, total(&repository, Default::default()).await);
+        inner.create(USER_ID, todo("bypass")).await.unwrap();
+        assert_eq!(1, total(&repository, Default::default()).await);
+    }
+
+    #[tokio::test]
+    async fn should_invalidate_todo_lists_on_label_write() {
+        let inner = TodoRepositoryForMemory::new(vec![]);
+        let todos = CachedTodoRepository::new(inner.clone(), Some(POLICY));
+        let labels = CachedLabelRepository::new(LabelRepositoryForMemory::new(), Some(POLICY))
+            .with_todo_cache(&todos);
+        let work = labels
+            .create(CreateLabel::new("work".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(0, total(&todos, Default::default()).await);
+        inner.create(USER_ID, todo("bypass")).await.unwrap();
+        assert_eq!(0, total(&todos, Default::default()).await);
+
+        // ラベルの変更・削除で、Todoの一覧も読み込み直す
+        labels
+            .update(work.id, UpdateLabel::new("job".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(1, total(&todos, Default::default()).await);
+        inner.create(USER_ID, todo("bypass")).await.unwrap();
+        labels.delete(work.id).await.unwrap();
+        assert_eq!(2, total(&todos, Default::default()).await);
+    }
+
+    #[tokio::test]
+    async fn should_invalidate_label_lists_on_write() {
+        let inner = LabelRepositoryForMemory::new();
+        let repository = CachedLabelRepository::new(inner.clone(), Some(POLICY));
+        let work = repository
+            .create(CreateLabel::new("work".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(vec![work.clone()], repository.all().await.unwrap());
+        assert_eq!(1, repository.all_with_counts().await.unwrap().len());
+
+        inner
+            .create(CreateLabel::new("bypass".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(vec![work.clone()], repository.all().await.unwrap());
+        assert_eq!(1, repository.all_with_counts().await.unwrap().len());
+
+        let renamed = repository
+            .update(work.id, UpdateLabel::new("job".to_string()))
+            .await
+            .unwrap();
+        let labels = repository.all().await.unwrap();
+        assert_eq!(2, labels.len());
+        assert_eq!(renamed, labels[0]);
+        assert_eq!(2, repository.all_with_counts().await.unwrap().len());
+    }
+
+    #[test]
+    fn should_evict_oldest_entry_when_full() {
+        let cache = TtlCache::new(CachePolicy {
+            max_entries: 2,
+            ..POLICY
+        });
+        for key in 1..=3 {
+            let generation = cache.get(&key).unwrap_err();
+            cache.insert(key, key * 10, generation);
+        }
+        assert_eq!(Err(0), cache.get(&1));
+        assert_eq!(Ok(20), cache.get(&2));
+        assert_eq!(Ok(30), cache.get(&3));
+    }
+
+    #[test]
+    fn should_not_keep_value_loaded_before_clear() {
+        let cache = TtlCache::new(POLICY);
+        let generation = cache.get(&1).unwrap_err();
+        // 読み込み中に書き込まれた
+        cache.clear();
+        cache.insert(1, 10, generation);
+        assert_eq!(Err(1), cache.get(&1));
+    }
+}
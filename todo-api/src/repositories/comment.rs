@@ -0,0 +1,230 @@
+use async_graphql::SimpleObject;
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use tracing::instrument;
+use utoipa::ToSchema;
+use validator::Validate;
+
+use super::todo::validate_not_blank;
+use super::{PageQuery, RepositoryError};
+
+mod memory;
+pub use memory::CommentRepositoryForMemory;
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "sqlite")]
+pub use sqlite::CommentRepositoryForSqlite;
+
+// Todoの存在確認と所有者の確認はハンドラ側でTodoRepositoryを使って行う
+#[async_trait]
+pub trait CommentRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
+    async fn create(
+        &self,
+        todo_id: i64,
+        author: String,
+        payload: CreateComment,
+    ) -> Result<Comment, RepositoryError>;
+    async fn all(&self, todo_id: i64, query: PageQuery) -> Result<CommentPage, RepositoryError>;
+    async fn delete(&self, todo_id: i64, id: i32) -> Result<(), RepositoryError>;
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, FromRow, ToSchema, SimpleObject)]
+pub struct Comment {
+    pub id: i32,
+    pub todo_id: i64,
+    // 投稿したユーザーのusername
+    pub author: String,
+    pub body: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate, ToSchema)]
+pub struct CreateComment {
+    #[validate(custom = "validate_not_blank")]
+    #[validate(length(max = 1000, message = "Over body length"))]
+    pub body: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommentPage {
+    pub comments: Vec<Comment>,
+    pub total: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct CommentRepositoryForDb {
+    pool: PgPool,
+}
+
+impl CommentRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        CommentRepositoryForDb { pool }
+    }
+}
+
+#[async_trait]
+impl CommentRepository for CommentRepositoryForDb {
+    #[instrument(name = "comment.create", skip_all, fields(db.operation = "INSERT"))]
+    async fn create(
+        &self,
+        todo_id: i64,
+        author: String,
+        payload: CreateComment,
+    ) -> Result<Comment, RepositoryError> {
+        let comment = sqlx::query_as::<_, Comment>(
+            r#"
+insert into comments (todo_id, author, body)
+values ($1, $2, $3)
+returning *
+            "#,
+        )
+        .bind(todo_id)
+        .bind(author)
+        .bind(payload.body)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(comment)
+    }
+
+    #[instrument(name = "comment.all", skip_all, fields(db.operation = "SELECT"))]
+    async fn all(&self, todo_id: i64, query: PageQuery) -> Result<CommentPage, RepositoryError> {
+        let comments = sqlx::query_as::<_, Comment>(
+            r#"
+select * from comments
+where todo_id = $1
+order by created_at desc, id desc
+limit $2 offset $3
+            "#,
+        )
+        .bind(todo_id)
+        .bind(query.limit())
+        .bind(query.offset())
+        .fetch_all(&self.pool)
+        .await?;
+        let total: i64 = sqlx::query_scalar("select count(*) from comments where todo_id = $1")
+            .bind(todo_id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(CommentPage { comments, total })
+    }
+
+    #[instrument(name = "comment.delete", skip_all, fields(db.operation = "DELETE"))]
+    async fn delete(&self, todo_id: i64, id: i32) -> Result<(), RepositoryError> {
+        let result = sqlx::query("delete from comments where todo_id = $1 and id = $2")
+            .bind(todo_id)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound(Some(id.into())));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use std::env;
+
+    use dotenv::dotenv;
+    use sqlx::PgPool;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn crud_scenario() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+
+        // todo data prepare
+        let todo_id: i64 = sqlx::query_scalar(
+            "insert into todos ( text ) values ( 'commented todo' ) returning id",
+        )
+        .fetch_one(&pool)
+        .await
+        .expect("Failed to insert todo data.");
+        let repository = CommentRepositoryForDb::new(pool.clone());
+
+        // create
+        let first = repository
+            .create(todo_id, "author".to_string(), CreateComment::new("first"))
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(todo_id, first.todo_id);
+        assert_eq!("author", first.author);
+        assert_eq!("first", first.body);
+        let second = repository
+            .create(todo_id, "author".to_string(), CreateComment::new("second"))
+            .await
+            .expect("[create] returned Err");
+
+        // all
+        let page = repository
+            .all(todo_id, PageQuery::default())
+            .await
+            .expect("[all] returned Err");
+        assert_eq!(2, page.total);
+        assert_eq!(vec![second.clone(), first.clone()], page.comments);
+        let page = repository
+            .all(
+                todo_id,
+                PageQuery {
+                    limit: Some(1),
+                    offset: Some(1),
+                },
+            )
+            .await
+            .expect("[all] returned Err");
+        assert_eq!(2, page.total);
+        assert_eq!(vec![first], page.comments);
+
+        // delete
+        repository
+            .delete(todo_id, second.id)
+            .await
+            .expect("[delete] returned Err");
+        let res = repository
+            .delete(todo_id, second.id)
+            .await
+            .expect_err("[delete] deleted comment returned Ok");
+        assert!(matches!(
+            &res,
+            RepositoryError::NotFound(Some(id)) if *id == i64::from(second.id)
+        ));
+
+        // Todoを削除するとコメントも削除される
+        sqlx::query("delete from todos where id = $1")
+            .bind(todo_id)
+            .execute(&pool)
+            .await
+            .expect("[delete todo] returned Err");
+        let page = repository
+            .all(todo_id, PageQuery::default())
+            .await
+            .expect("[all] returned Err");
+        assert_eq!(0, page.total);
+    }
+}
+
+#[cfg(test)]
+pub mod test_utils {
+    use super::CreateComment;
+
+    impl CreateComment {
+        pub fn new(body: &str) -> Self {
+            Self {
+                body: body.to_string(),
+            }
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Arc;
+
+use axum::async_trait;
+use chrono::Utc;
+use tokio::sync::RwLock;
+
+use super::{Comment, CommentPage, CommentRepository, CreateComment, PageQuery, RepositoryError};
+
+// todosを参照できないため、Todoを削除しても残るが、ハンドラがTodoの存在を確認するため参照されることはない
+#[derive(Debug, Clone, Default)]
+pub struct CommentRepositoryForMemory {
+    store: Arc<RwLock<HashMap<i32, Comment>>>,
+    last_id: Arc<AtomicI32>,
+}
+
+impl CommentRepositoryForMemory {
+    pub fn new() -> Self {
+        CommentRepositoryForMemory {
+            store: Arc::default(),
+            last_id: Arc::default(),
+        }
+    }
+
+    fn next_id(&self) -> i32 {
+        self.last_id.fetch_add(1, Ordering::SeqCst) + 1
+    }
+}
+
+#[async_trait]
+impl CommentRepository for CommentRepositoryForMemory {
+    async fn create(
+        &self,
+        todo_id: i64,
+        author: String,
+        payload: CreateComment,
+    ) -> Result<Comment, RepositoryError> {
+        let comment = Comment {
+            id: self.next_id(),
+            todo_id,
+            author,
+            body: payload.body,
+            created_at: Utc::now(),
+        };
+        self.store.write().await.insert(comment.id, comment.clone());
+        Ok(comment)
+    }
+
+    async fn all(&self, todo_id: i64, query: PageQuery) -> Result<CommentPage, RepositoryError> {
+        let store = self.store.read().await;
+        let mut comments: Vec<Comment> = store
+            .values()
+            .filter(|comment| comment.todo_id == todo_id)
+            .cloned()
+            .collect();
+        comments.sort_by_key(|comment| std::cmp::Reverse((comment.created_at, comment.id)));
+        let total = comments.len() as i64;
+        let comments = comments
+            .into_iter()
+            .skip(query.offset() as usize)
+            .take(query.limit() as usize)
+            .collect();
+        Ok(CommentPage { comments, total })
+    }
+
+    async fn delete(&self, todo_id: i64, id: i32) -> Result<(), RepositoryError> {
+        let mut store = self.store.write().await;
+        match store.get(&id) {
+            Some(comment) if comment.todo_id == todo_id => {
+                store.remove(&id);
+                Ok(())
+            }
+            _ => Err(RepositoryError::NotFound(Some(id.into()))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::repositories::comment::{CommentRepository, CreateComment};
+    use crate::repositories::{PageQuery, RepositoryError};
+
+    use super::CommentRepositoryForMemory;
+
+    #[tokio::test]
+    async fn comment_crud_scenario() {
+        let repository = CommentRepositoryForMemory::new();
+        let first = repository
+            .create(1, "author".to_string(), CreateComment::new("first"))
+            .await
+            .expect("failed comment create");
+        let second = repository
+            .create(1, "author".to_string(), CreateComment::new("second"))
+            .await
+            .expect("failed comment create");
+        repository
+            .create(2, "author".to_string(), CreateComment::new("other todo"))
+            .await
+            .expect("failed comment create");
+
+        // 新しい順に返す
+        let page = repository.all(1, PageQuery::default()).await.unwrap();
+        assert_eq!(2, page.total);
+        assert_eq!(vec![second.clone(), first.clone()], page.comments);
+        let page = repository
+            .all(
+                1,
+                PageQuery {
+                    limit: Some(1),
+                    offset: Some(1),
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(vec![first.clone()], page.comments);
+
+        // 別のTodoのコメントは削除できない
+        let res = repository.delete(2, first.id).await.unwrap_err();
+        assert!(matches!(
+            &res,
+            RepositoryError::NotFound(Some(id)) if *id == i64::from(first.id)
+        ));
+        repository.delete(1, first.id).await.unwrap();
+        let page = repository.all(1, PageQuery::default()).await.unwrap();
+        assert_eq!(vec![second], page.comments);
+    }
+}
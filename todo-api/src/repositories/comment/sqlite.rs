@@ -0,0 +1,80 @@
+use axum::async_trait;
+use sqlx::SqlitePool;
+use tracing::instrument;
+
+use super::{Comment, CommentPage, CommentRepository, CreateComment};
+use crate::repositories::{PageQuery, RepositoryError};
+
+#[derive(Debug, Clone)]
+pub struct CommentRepositoryForSqlite {
+    pool: SqlitePool,
+}
+
+impl CommentRepositoryForSqlite {
+    pub fn new(pool: SqlitePool) -> Self {
+        CommentRepositoryForSqlite { pool }
+    }
+}
+
+#[async_trait]
+impl CommentRepository for CommentRepositoryForSqlite {
+    #[instrument(name = "comment.create", skip_all, fields(db.operation = "INSERT"))]
+    async fn create(
+        &self,
+        todo_id: i64,
+        author: String,
+        payload: CreateComment,
+    ) -> Result<Comment, RepositoryError> {
+        let comment = sqlx::query_as::<_, Comment>(
+            r#"
+insert into comments (todo_id, author, body)
+values ($1, $2, $3)
+returning *
+            "#,
+        )
+        .bind(todo_id)
+        .bind(author)
+        .bind(payload.body)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(comment)
+    }
+
+    #[instrument(name = "comment.all", skip_all, fields(db.operation = "SELECT"))]
+    async fn all(&self, todo_id: i64, query: PageQuery) -> Result<CommentPage, RepositoryError> {
+        let comments = sqlx::query_as::<_, Comment>(
+            r#"
+select * from comments
+where todo_id = $1
+order by created_at desc, id desc
+limit $2 offset $3
+            "#,
+        )
+        .bind(todo_id)
+        .bind(query.limit())
+        .bind(query.offset())
+        .fetch_all(&self.pool)
+        .await?;
+        let total: i64 = sqlx::query_scalar("select count(*) from comments where todo_id = $1")
+            .bind(todo_id)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(CommentPage { comments, total })
+    }
+
+    #[instrument(name = "comment.delete", skip_all, fields(db.operation = "DELETE"))]
+    async fn delete(&self, todo_id: i64, id: i32) -> Result<(), RepositoryError> {
+        let result = sqlx::query("delete from comments where todo_id = $1 and id = $2")
+            .bind(todo_id)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound(Some(id.into())));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,63 @@
+use std::time::Duration;
+
+use axum::async_trait;
+use sqlx::PgPool;
+use tracing::instrument;
+
+use super::RepositoryError;
+
+mod memory;
+pub use memory::HealthRepositoryForMemory;
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "sqlite")]
+pub use sqlite::HealthRepositoryForSqlite;
+
+// プローブのタイムアウトより先に応答できるよう、プールの取得待ちを打ち切る
+const PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[async_trait]
+pub trait HealthRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
+    async fn ping(&self) -> Result<(), RepositoryError>;
+}
+
+#[derive(Debug, Clone)]
+pub struct HealthRepositoryForDb {
+    pool: PgPool,
+}
+
+impl HealthRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl HealthRepository for HealthRepositoryForDb {
+    #[instrument(name = "health.ping", skip_all, fields(db.operation = "SELECT"))]
+    async fn ping(&self) -> Result<(), RepositoryError> {
+        tokio::time::timeout(PING_TIMEOUT, sqlx::query("select 1").execute(&self.pool))
+            .await
+            .map_err(|_| RepositoryError::unexpected("database ping timed out"))??;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub mod test_utils {
+    use axum::async_trait;
+
+    use super::{HealthRepository, RepositoryError};
+
+    // データベースが落ちている状態を再現する
+    #[derive(Debug, Clone)]
+    pub struct HealthRepositoryForUnavailable;
+
+    #[async_trait]
+    impl HealthRepository for HealthRepositoryForUnavailable {
+        async fn ping(&self) -> Result<(), RepositoryError> {
+            Err(RepositoryError::unexpected("connection refused"))
+        }
+    }
+}
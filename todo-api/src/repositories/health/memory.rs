@@ -0,0 +1,20 @@
+use axum::async_trait;
+
+use super::{HealthRepository, RepositoryError};
+
+// 外部の依存がないため、常に応答できる
+#[derive(Debug, Clone, Default)]
+pub struct HealthRepositoryForMemory;
+
+impl HealthRepositoryForMemory {
+    pub fn new() -> Self {
+        HealthRepositoryForMemory
+    }
+}
+
+#[async_trait]
+impl HealthRepository for HealthRepositoryForMemory {
+    async fn ping(&self) -> Result<(), RepositoryError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,27 @@
+use axum::async_trait;
+use sqlx::SqlitePool;
+use tracing::instrument;
+
+use super::{HealthRepository, RepositoryError, PING_TIMEOUT};
+
+#[derive(Debug, Clone)]
+pub struct HealthRepositoryForSqlite {
+    pool: SqlitePool,
+}
+
+impl HealthRepositoryForSqlite {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl HealthRepository for HealthRepositoryForSqlite {
+    #[instrument(name = "health.ping", skip_all, fields(db.operation = "SELECT"))]
+    async fn ping(&self) -> Result<(), RepositoryError> {
+        tokio::time::timeout(PING_TIMEOUT, sqlx::query("select 1").execute(&self.pool))
+            .await
+            .map_err(|_| RepositoryError::unexpected("database ping timed out"))??;
+        Ok(())
+    }
+}
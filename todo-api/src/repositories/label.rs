@@ -1,212 +1,569 @@
+use std::sync::Arc;
+
+use async_graphql::SimpleObject;
 use axum::async_trait;
 use serde::{Deserialize, Serialize};
-use sqlx::PgPool;
+use sqlx::{PgPool, Postgres, Transaction};
+use tracing::instrument;
+use utoipa::ToSchema;
+use validator::{Validate, ValidationError};
+
+use crate::clock::{Clock, SharedClock, SystemClock};
+use crate::events::TodoEvent;
+
+use super::outbox::record_shared_events;
+use super::todo::TodoRepositoryForDb;
+use super::{deserialize_present, RepositoryError};
+
+mod memory;
+pub use memory::LabelRepositoryForMemory;
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "sqlite")]
+pub use sqlite::LabelRepositoryForSqlite;
 
-use super::RepositoryError;
+pub const DEFAULT_LABEL_COLOR: &str = "#808080";
+const UNIQUE_VIOLATION: &str = "23505";
 
 #[async_trait]
 pub trait LabelRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
-    async fn create(&self, name: String) -> anyhow::Result<Label>;
-    async fn all(&self) -> anyhow::Result<Vec<Label>>;
-    async fn delete(&self, id: i32) -> anyhow::Result<()>;
+    async fn create(&self, payload: CreateLabel) -> Result<Label, RepositoryError>;
+    async fn find(&self, id: i32) -> Result<Label, RepositoryError>;
+    async fn all(&self) -> Result<Vec<Label>, RepositoryError>;
+    async fn all_with_counts(&self) -> Result<Vec<LabelWithUsage>, RepositoryError>;
+    async fn update(&self, id: i32, payload: UpdateLabel) -> Result<Label, RepositoryError>;
+    async fn delete(&self, id: i32) -> Result<(), RepositoryError>;
+    // fromに紐づくTodoをすべてintoへ付け替え、fromを削除する
+    async fn merge(&self, from: i32, into: i32) -> Result<LabelWithUsage, RepositoryError>;
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, sqlx::FromRow)]
+#[derive(
+    Debug, Serialize, Deserialize, Clone, PartialEq, Eq, sqlx::FromRow, ToSchema, SimpleObject,
+)]
 pub struct Label {
     pub id: i32,
     pub name: String,
+    pub color: String,
+    pub description: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
-pub struct UpdateLabel {
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
+pub struct LabelWithUsage {
+    #[serde(flatten)]
+    pub label: Label,
+    // 件数の集計を省略した場合はNone
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub todo_count: Option<i64>,
+}
+
+impl From<Label> for LabelWithUsage {
+    fn from(label: Label) -> Self {
+        Self {
+            label,
+            todo_count: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+struct LabelWithUsageFromRow {
     id: i32,
     name: String,
+    color: String,
+    description: Option<String>,
+    todo_count: i64,
+}
+
+impl From<LabelWithUsageFromRow> for LabelWithUsage {
+    fn from(row: LabelWithUsageFromRow) -> Self {
+        Self {
+            label: Label {
+                id: row.id,
+                name: row.name,
+                color: row.color,
+                description: row.description,
+            },
+            todo_count: Some(row.todo_count),
+        }
+    }
+}
+
+// ゴミ箱内のTodoは件数に含めない
+const SELECT_LABELS_WITH_USAGE: &str = r#"
+select labels.*, count(todos.id) as todo_count
+from labels
+left outer join todo_labels tl on tl.label_id = labels.id
+left outer join todos on todos.id = tl.todo_id and todos.deleted_at is null
+"#;
+
+// #RRGGBB形式のみ受け付ける
+pub fn validate_color(color: &str) -> Result<(), ValidationError> {
+    let valid = color.len() == 7
+        && color.starts_with('#')
+        && color[1..].chars().all(|c| c.is_ascii_hexdigit());
+    if !valid {
+        let mut error = ValidationError::new("color");
+        error.message = Some("Must be #RRGGBB".into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Validate, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CreateLabel {
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 100, message = "Over text length"))]
+    name: String,
+    #[validate(custom = "validate_color")]
+    #[schema(example = "#808080")]
+    color: Option<String>,
+    #[validate(length(max = 500, message = "Over description length"))]
+    description: Option<String>,
+}
+
+impl CreateLabel {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            color: None,
+            description: None,
+        }
+    }
+
+    pub fn with_color(self, color: String) -> Self {
+        Self {
+            color: Some(color),
+            ..self
+        }
+    }
+
+    pub fn with_description(self, description: String) -> Self {
+        Self {
+            description: Some(description),
+            ..self
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Validate, ToSchema)]
+pub struct UpdateLabel {
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 100, message = "Over text length"))]
+    name: String,
+    #[validate(custom = "validate_color")]
+    #[schema(example = "#808080")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    color: Option<String>,
+    // nullを指定すると説明を消す
+    #[serde(
+        default,
+        deserialize_with = "deserialize_present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[validate(length(max = 500, message = "Over description length"))]
+    #[schema(value_type = Option<String>, nullable)]
+    description: Option<Option<String>>,
+}
+
+impl UpdateLabel {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            color: None,
+            description: None,
+        }
+    }
+
+    pub fn with_color(self, color: String) -> Self {
+        Self {
+            color: Some(color),
+            ..self
+        }
+    }
+
+    // Noneを渡すと説明を消す
+    pub fn with_description(self, description: Option<String>) -> Self {
+        Self {
+            description: Some(description),
+            ..self
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct LabelRepositoryForDb {
     pool: PgPool,
+    clock: SharedClock,
 }
 
 impl LabelRepositoryForDb {
     pub fn new(pool: PgPool) -> Self {
-        Self { pool }
+        Self {
+            pool,
+            clock: Arc::new(SystemClock),
+        }
     }
-}
 
-#[async_trait]
-impl LabelRepository for LabelRepositoryForDb {
-    async fn create(&self, name: String) -> anyhow::Result<Label> {
-        let optional_label = sqlx::query_as::<_, Label>("select * from labels where name = $1")
-            .bind(name.clone())
-            .fetch_optional(&self.pool)
-            .await?;
-
-        if let Some(label) = optional_label {
-            return Err(RepositoryError::Duplicate(label.id).into());
+    pub fn with_clock(self, clock: impl Clock) -> Self {
+        Self {
+            clock: Arc::new(clock),
+            ..self
         }
+    }
 
-        let label =
-            sqlx::query_as::<_, Label>("insert into labels ( name ) values ( $1 ) returning *")
-                .bind(name.clone())
+    // ラベルの変更はTodoのupdated_atを変えないため、付いているTodoの所有者の一覧の変更日時を進める
+    async fn touch_owners(
+        &self,
+        tx: &mut Transaction<'_, Postgres>,
+        label_ids: &[i32],
+    ) -> Result<(), RepositoryError> {
+        let user_ids: Vec<i32> = sqlx::query_scalar(
+            r#"
+select distinct todos.user_id from todos
+join todo_labels tl on tl.todo_id = todos.id
+where tl.label_id = any($1) and todos.user_id is not null;
+"#,
+        )
+        .bind(label_ids)
+        .fetch_all(&mut *tx)
+        .await?;
+        TodoRepositoryForDb::touch_lists(tx, &user_ids, self.clock.now()).await
+    }
+
+    // 名前の一意制約(大文字小文字を区別しない)に違反した場合は、既存ラベルのidを返す
+    async fn map_unique_violation<T>(
+        &self,
+        result: Result<T, sqlx::Error>,
+        name: &str,
+    ) -> Result<T, RepositoryError> {
+        match result {
+            Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some(UNIQUE_VIOLATION) => {
+                let (id,) = sqlx::query_as::<_, (i32,)>(
+                    "select id from labels where lower(name) = lower($1)",
+                )
+                .bind(name)
                 .fetch_one(&self.pool)
                 .await?;
+                Err(RepositoryError::Duplicate(Some(id)))
+            }
+            result => Ok(result?),
+        }
+    }
+}
+
+#[async_trait]
+impl LabelRepository for LabelRepositoryForDb {
+    #[instrument(name = "label.create", skip_all, fields(db.operation = "INSERT"))]
+    async fn create(&self, payload: CreateLabel) -> Result<Label, RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        let name = payload.name.clone();
+        let label = sqlx::query_as::<_, Label>(
+            "insert into labels ( name, color, description ) values ( $1, $2, $3 ) returning *",
+        )
+        .bind(payload.name)
+        .bind(
+            payload
+                .color
+                .unwrap_or_else(|| DEFAULT_LABEL_COLOR.to_string()),
+        )
+        .bind(payload.description)
+        .fetch_one(&mut tx)
+        .await;
+        let label = self.map_unique_violation(label, &name).await?;
+
+        let events = vec![TodoEvent::LabelCreated {
+            label: label.clone(),
+        }];
+        record_shared_events(&mut tx, events, self.clock.now()).await?;
+        tx.commit().await?;
 
         Ok(label)
     }
 
-    async fn all(&self) -> anyhow::Result<Vec<Label>> {
+    #[instrument(name = "label.find", skip_all, fields(db.operation = "SELECT"))]
+    async fn find(&self, id: i32) -> Result<Label, RepositoryError> {
+        let label = sqlx::query_as::<_, Label>("select * from labels where id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or(RepositoryError::NotFound(Some(id.into())))?;
+        Ok(label)
+    }
+
+    #[instrument(name = "label.all", skip_all, fields(db.operation = "SELECT"))]
+    async fn all(&self) -> Result<Vec<Label>, RepositoryError> {
         let labels = sqlx::query_as::<_, Label>("select * from labels order by labels.id asc")
             .fetch_all(&self.pool)
             .await?;
         Ok(labels)
     }
 
-    async fn delete(&self, id: i32) -> anyhow::Result<()> {
-        sqlx::query("delete from labels where id=$1 ")
+    #[instrument(name = "label.all_with_counts", skip_all, fields(db.operation = "SELECT"))]
+    async fn all_with_counts(&self) -> Result<Vec<LabelWithUsage>, RepositoryError> {
+        // ラベルごとにN+1で数えず、1回のgroup byで集計する
+        let sql = format!(
+            "{} group by labels.id order by labels.id asc",
+            SELECT_LABELS_WITH_USAGE
+        );
+        let labels = sqlx::query_as::<_, LabelWithUsageFromRow>(&sql)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(labels.into_iter().map(LabelWithUsage::from).collect())
+    }
+
+    #[instrument(name = "label.update", skip_all, fields(db.operation = "UPDATE"))]
+    async fn update(&self, id: i32, payload: UpdateLabel) -> Result<Label, RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        let name = payload.name.clone();
+        let label = sqlx::query_as::<_, Label>(
+            r#"
+update labels
+set name = $1, color = coalesce($2, color),
+    description = case when $3 then $4 else description end
+where id = $5
+returning *;
+"#,
+        )
+        .bind(payload.name)
+        .bind(payload.color)
+        .bind(payload.description.is_some())
+        .bind(payload.description.flatten())
+        .bind(id)
+        .fetch_optional(&mut tx)
+        .await;
+        let label = self
+            .map_unique_violation(label, &name)
+            .await?
+            .ok_or_else(|| RepositoryError::NotFound(Some(id.into())))?;
+        self.touch_owners(&mut tx, &[id]).await?;
+
+        let events = vec![TodoEvent::LabelUpdated {
+            label: label.clone(),
+        }];
+        record_shared_events(&mut tx, events, self.clock.now()).await?;
+        tx.commit().await?;
+
+        Ok(label)
+    }
+
+    #[instrument(name = "label.delete", skip_all, fields(db.operation = "DELETE"))]
+    async fn delete(&self, id: i32) -> Result<(), RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        self.touch_owners(&mut tx, &[id]).await?;
+
+        // 外部キーにcascadeがないため、Todoとの関連を先に外す
+        sqlx::query("delete from todo_labels where label_id = $1")
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+        let result = sqlx::query("delete from labels where id=$1 ")
             .bind(id)
-            .execute(&self.pool)
+            .execute(&mut tx)
             .await
             .map_err(|e| match e {
-                sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
-                _ => RepositoryError::Unexpected(e.to_string()),
+                sqlx::Error::RowNotFound => RepositoryError::NotFound(Some(id.into())),
+                _ => e.into(),
             })?;
+        if result.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound(Some(id.into())));
+        }
+        let events = vec![TodoEvent::LabelDeleted { id }];
+        record_shared_events(&mut tx, events, self.clock.now()).await?;
+
+        tx.commit().await?;
 
         Ok(())
     }
+
+    #[instrument(name = "label.merge", skip_all, fields(db.operation = "INSERT"))]
+    async fn merge(&self, from: i32, into: i32) -> Result<LabelWithUsage, RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+
+        let found: Vec<i32> =
+            sqlx::query_as::<_, (i32,)>("select id from labels where id = any($1) for update")
+                .bind(vec![from, into])
+                .fetch_all(&mut tx)
+                .await?
+                .into_iter()
+                .map(|(id,)| id)
+                .collect();
+        if let Some(missing) = [from, into].into_iter().find(|id| !found.contains(id)) {
+            return Err(RepositoryError::NotFound(Some(missing.into())));
+        }
+        self.touch_owners(&mut tx, &[from]).await?;
+
+        // 既にintoが付いているTodoは重複させずに付け替える
+        sqlx::query(
+            r#"
+insert into todo_labels (todo_id, label_id)
+select todo_id, $2 from todo_labels where label_id = $1
+on conflict (todo_id, label_id) do nothing;
+"#,
+        )
+        .bind(from)
+        .bind(into)
+        .execute(&mut tx)
+        .await?;
+        sqlx::query("delete from todo_labels where label_id = $1")
+            .bind(from)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query("delete from labels where id = $1")
+            .bind(from)
+            .execute(&mut tx)
+            .await?;
+
+        let sql = format!(
+            "{} where labels.id = $1 group by labels.id",
+            SELECT_LABELS_WITH_USAGE
+        );
+        let label = sqlx::query_as::<_, LabelWithUsageFromRow>(&sql)
+            .bind(into)
+            .fetch_one(&mut tx)
+            .await?;
+        let label = LabelWithUsage::from(label);
+        let events = vec![TodoEvent::LabelsMerged {
+            from,
+            into: label.label.clone(),
+        }];
+        record_shared_events(&mut tx, events, self.clock.now()).await?;
+
+        tx.commit().await?;
+
+        Ok(label)
+    }
 }
 
 #[cfg(test)]
-#[cfg(feature = "database-test")]
+#[cfg(any(feature = "database-test", feature = "sqlite"))]
 mod test {
+    #[cfg(feature = "database-test")]
     use std::env;
 
+    #[cfg(feature = "database-test")]
     use dotenv::dotenv;
-    use sqlx::PgPool;
 
     use super::*;
 
+    #[cfg(feature = "database-test")]
     #[tokio::test]
     async fn crud_scenario() {
         dotenv().ok();
         let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
         let pool = PgPool::connect(database_url)
             .await
-            .expect(&format!("fail connect database, url is [{}]", database_url));
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+
+        run_crud_scenario(LabelRepositoryForDb::new(pool)).await;
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn crud_scenario_sqlite() {
+        let pool = crate::database::connect_sqlite("sqlite::memory:")
+            .await
+            .expect("fail connect sqlite");
+        crate::migration::run_sqlite(&pool)
+            .await
+            .expect("fail run sqlite migrations");
+
+        run_crud_scenario(LabelRepositoryForSqlite::new(pool)).await;
+    }
 
-        let repository = LabelRepositoryForDb::new(pool);
+    async fn run_crud_scenario(repository: impl LabelRepository) {
         let label_text = "test_label";
 
         // create
         let label = repository
-            .create(label_text.to_string())
+            .create(CreateLabel::new(label_text.to_string()))
             .await
             .expect("[create] returned Err");
         assert_eq!(label.name, label_text);
+        assert_eq!(label.color, DEFAULT_LABEL_COLOR);
+        assert_eq!(label.description, None);
+        let res = repository
+            .create(CreateLabel::new(label_text.to_uppercase()))
+            .await
+            .expect_err("[create] duplicate returned Ok");
+        assert!(matches!(
+            &res,
+            RepositoryError::Duplicate(Some(id)) if *id == label.id
+        ));
+        assert_eq!(
+            label,
+            repository
+                .find(label.id)
+                .await
+                .expect("[find] returned Err")
+        );
+
+        // update
+        let renamed_text = "renamed_test_label";
+        let label = repository
+            .update(
+                label.id,
+                UpdateLabel {
+                    name: renamed_text.to_string(),
+                    color: Some("#FF0000".to_string()),
+                    description: Some(Some("renamed".to_string())),
+                },
+            )
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(label.name, renamed_text);
+        assert_eq!(label.color, "#FF0000");
+        assert_eq!(label.description.as_deref(), Some("renamed"));
+        let label = repository
+            .update(label.id, UpdateLabel::new(renamed_text.to_string()))
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(label.color, "#FF0000");
+        assert_eq!(label.description.as_deref(), Some("renamed"));
+
+        // merge
+        let source = repository
+            .create(CreateLabel::new("merged_test_label".to_string()))
+            .await
+            .expect("[create] returned Err");
+        let merged = repository
+            .merge(source.id, label.id)
+            .await
+            .expect("[merge] returned Err");
+        assert_eq!(label, merged.label);
+        assert!(repository.find(source.id).await.is_err());
+        assert!(repository.merge(source.id, label.id).await.is_err());
+
+        // all_with_counts
+        let labels = repository
+            .all_with_counts()
+            .await
+            .expect("[all_with_counts] returned Err");
+        assert!(labels.contains(&merged));
 
         // delete
         repository
             .delete(label.id)
             .await
             .expect("[delete] returned Err");
+        let res = repository.delete(label.id).await; // expect not found err
+        assert!(res.is_err());
     }
 }
 
 #[cfg(test)]
 pub mod test_utils {
-    use std::collections::HashMap;
-    use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
-
-    use axum::async_trait;
-
-    use crate::repositories::label::{LabelRepository, RepositoryError};
-
-    use super::Label;
+    use super::{Label, DEFAULT_LABEL_COLOR};
 
     impl Label {
         pub fn new(id: i32, name: String) -> Self {
-            Label { id, name }
-        }
-    }
-
-    type LabelData = HashMap<i32, Label>;
-
-    #[derive(Debug, Clone)]
-    pub struct LabelRepositoryForMemory {
-        store: Arc<RwLock<LabelData>>,
-    }
-
-    impl LabelRepositoryForMemory {
-        pub fn new() -> Self {
-            LabelRepositoryForMemory {
-                store: Arc::default(),
+            Label {
+                id,
+                name,
+                color: DEFAULT_LABEL_COLOR.to_string(),
+                description: None,
             }
         }
-
-        fn write_store_ref(&self) -> RwLockWriteGuard<LabelData> {
-            self.store.write().unwrap()
-        }
-
-        fn read_store_ref(&self) -> RwLockReadGuard<LabelData> {
-            self.store.read().unwrap()
-        }
-    }
-
-    #[async_trait]
-    impl LabelRepository for LabelRepositoryForMemory {
-        async fn create(&self, name: String) -> anyhow::Result<Label> {
-            let mut store = self.write_store_ref();
-            if let Some((_key, label)) = store.iter().find(|(_key, label)| label.name == name) {
-                return Ok(label.clone());
-            };
-
-            let id = (store.len() + 1) as i32;
-            let label = Label::new(id, name.clone());
-            store.insert(id, label.clone());
-            Ok(label)
-        }
-
-        async fn all(&self) -> anyhow::Result<Vec<Label>> {
-            let store = self.read_store_ref();
-            let labels = Vec::from_iter(store.values().map(|label| label.clone()));
-            Ok(labels)
-        }
-
-        async fn delete(&self, id: i32) -> anyhow::Result<()> {
-            let mut store = self.write_store_ref();
-            store.remove(&id).ok_or(RepositoryError::NotFound(id))?;
-            Ok(())
-        }
-    }
-
-    mod test {
-        use std::vec;
-
-        use crate::repositories::label::Label;
-
-        use super::{LabelRepository, LabelRepositoryForMemory};
-
-        #[tokio::test]
-        async fn label_crud_scenario() {
-            let text = "label text".to_string();
-            let id = 1;
-            let expected = Label::new(id, text.clone());
-
-            // create
-            let repository = LabelRepositoryForMemory::new();
-            let label = repository
-                .create(text.clone())
-                .await
-                .expect("failed label create");
-            assert_eq!(expected, label);
-
-            // all
-            let label = repository.all().await.unwrap();
-            assert_eq!(vec![expected], label);
-
-            // delete
-            let res = repository.delete(id).await;
-            assert!(res.is_ok())
-        }
     }
 }
@@ -0,0 +1,320 @@
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, RwLock};
+
+use axum::async_trait;
+
+use super::{
+    CreateLabel, Label, LabelRepository, LabelWithUsage, RepositoryError, UpdateLabel,
+    DEFAULT_LABEL_COLOR,
+};
+use crate::repositories::{read_lock, write_lock};
+
+// TodoRepositoryForMemoryと共有できるよう、ロック中にawaitしない標準のRwLockで保持する
+pub type SharedLabels = Arc<RwLock<Vec<Label>>>;
+
+#[derive(Debug, Clone, Default)]
+pub struct LabelRepositoryForMemory {
+    store: SharedLabels,
+    // todo_labelsテーブルに相当する(todo_id, label_id)の組
+    todo_labels: Arc<RwLock<Vec<(i32, i32)>>>,
+    last_id: Arc<AtomicI32>,
+}
+
+impl LabelRepositoryForMemory {
+    pub fn new() -> Self {
+        LabelRepositoryForMemory {
+            store: Arc::default(),
+            todo_labels: Arc::default(),
+            last_id: Arc::default(),
+        }
+    }
+
+    // TodoRepositoryForMemoryに渡し、作成したラベルをTodoへ付けられるようにする
+    pub fn shared_labels(&self) -> SharedLabels {
+        self.store.clone()
+    }
+
+    #[cfg(test)]
+    pub async fn attach(&self, todo_id: i32, label_id: i32) {
+        let mut todo_labels = write_lock(&self.todo_labels);
+        if !todo_labels.contains(&(todo_id, label_id)) {
+            todo_labels.push((todo_id, label_id));
+        }
+    }
+
+    #[cfg(test)]
+    pub async fn todo_ids(&self, label_id: i32) -> Vec<i32> {
+        let mut ids: Vec<i32> = read_lock(&self.todo_labels)
+            .iter()
+            .filter(|(_, id)| *id == label_id)
+            .map(|(todo_id, _)| *todo_id)
+            .collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    // Todoのインポートで追加されたラベルとも重ならないよう、既存の最大値より後から採番する
+    fn next_id(&self, store: &[Label]) -> i32 {
+        let max = store.iter().map(|label| label.id).max().unwrap_or(0);
+        let id = self.last_id.load(Ordering::SeqCst).max(max) + 1;
+        self.last_id.store(id, Ordering::SeqCst);
+        id
+    }
+
+    fn count(todo_labels: &[(i32, i32)], label_id: i32) -> i64 {
+        todo_labels.iter().filter(|(_, id)| *id == label_id).count() as i64
+    }
+
+    fn position(store: &[Label], id: i32) -> Result<usize, RepositoryError> {
+        store
+            .iter()
+            .position(|label| label.id == id)
+            .ok_or(RepositoryError::NotFound(Some(id.into())))
+    }
+
+    // DBの一意制約と同じく大文字小文字を区別しない
+    fn find_by_name(store: &[Label], name: &str) -> Option<i32> {
+        store
+            .iter()
+            .find(|label| label.name.to_lowercase() == name.to_lowercase())
+            .map(|label| label.id)
+    }
+}
+
+#[async_trait]
+impl LabelRepository for LabelRepositoryForMemory {
+    async fn create(&self, payload: CreateLabel) -> Result<Label, RepositoryError> {
+        let mut store = write_lock(&self.store);
+        if let Some(id) = Self::find_by_name(&store, &payload.name) {
+            return Err(RepositoryError::Duplicate(Some(id)));
+        }
+
+        let label = Label {
+            id: self.next_id(&store),
+            name: payload.name,
+            color: payload
+                .color
+                .unwrap_or_else(|| DEFAULT_LABEL_COLOR.to_string()),
+            description: payload.description,
+        };
+        store.push(label.clone());
+        Ok(label)
+    }
+
+    async fn find(&self, id: i32) -> Result<Label, RepositoryError> {
+        let store = read_lock(&self.store);
+        let label = store
+            .iter()
+            .find(|label| label.id == id)
+            .cloned()
+            .ok_or(RepositoryError::NotFound(Some(id.into())))?;
+        Ok(label)
+    }
+
+    async fn all(&self) -> Result<Vec<Label>, RepositoryError> {
+        let mut labels = read_lock(&self.store).clone();
+        labels.sort_by_key(|label| label.id);
+        Ok(labels)
+    }
+
+    async fn all_with_counts(&self) -> Result<Vec<LabelWithUsage>, RepositoryError> {
+        let store = read_lock(&self.store);
+        let todo_labels = read_lock(&self.todo_labels);
+        let mut labels: Vec<LabelWithUsage> = store
+            .iter()
+            .map(|label| LabelWithUsage {
+                label: label.clone(),
+                todo_count: Some(Self::count(&todo_labels, label.id)),
+            })
+            .collect();
+        labels.sort_by_key(|label| label.label.id);
+        Ok(labels)
+    }
+
+    async fn update(&self, id: i32, payload: UpdateLabel) -> Result<Label, RepositoryError> {
+        let mut store = write_lock(&self.store);
+        let index = Self::position(&store, id)?;
+        if let Some(key) = Self::find_by_name(&store, &payload.name).filter(|key| *key != id) {
+            return Err(RepositoryError::Duplicate(Some(key)));
+        }
+
+        let current = store[index].clone();
+        let label = Label {
+            id,
+            name: payload.name,
+            color: payload.color.unwrap_or(current.color),
+            description: payload.description.unwrap_or(current.description),
+        };
+        store[index] = label.clone();
+        Ok(label)
+    }
+
+    async fn delete(&self, id: i32) -> Result<(), RepositoryError> {
+        let mut store = write_lock(&self.store);
+        let index = Self::position(&store, id)?;
+        store.remove(index);
+        write_lock(&self.todo_labels).retain(|(_, label_id)| *label_id != id);
+        Ok(())
+    }
+
+    async fn merge(&self, from: i32, into: i32) -> Result<LabelWithUsage, RepositoryError> {
+        let mut store = write_lock(&self.store);
+        let index = Self::position(&store, from)?;
+        let target = store[Self::position(&store, into)?].clone();
+
+        let mut todo_labels = write_lock(&self.todo_labels);
+        let moved: Vec<i32> = todo_labels
+            .iter()
+            .filter(|(_, label_id)| *label_id == from)
+            .map(|(todo_id, _)| *todo_id)
+            .collect();
+        todo_labels.retain(|(_, label_id)| *label_id != from);
+        for todo_id in moved {
+            if !todo_labels.contains(&(todo_id, into)) {
+                todo_labels.push((todo_id, into));
+            }
+        }
+        store.remove(index);
+
+        Ok(LabelWithUsage {
+            label: target,
+            todo_count: Some(Self::count(&todo_labels, into)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::vec;
+
+    use crate::repositories::label::{CreateLabel, Label, LabelWithUsage, UpdateLabel};
+    use crate::repositories::RepositoryError;
+
+    use super::{LabelRepository, LabelRepositoryForMemory};
+
+    #[tokio::test]
+    async fn label_crud_scenario() {
+        let text = "label text".to_string();
+        let id = 1;
+        let expected = Label::new(id, text.clone());
+
+        // create
+        let repository = LabelRepositoryForMemory::new();
+        let label = repository
+            .create(CreateLabel::new(text.clone()))
+            .await
+            .expect("failed label create");
+        assert_eq!(expected, label);
+
+        // all
+        let label = repository.all().await.unwrap();
+        assert_eq!(vec![expected], label);
+
+        // update
+        let text = "renamed label text".to_string();
+        let label = repository
+            .update(id, UpdateLabel::new(text.clone()))
+            .await
+            .expect("failed label update");
+        assert_eq!(Label::new(id, text), label);
+
+        // delete
+        let res = repository.delete(id).await;
+        assert!(res.is_ok())
+    }
+
+    #[tokio::test]
+    async fn should_merge_labels_without_duplicate_associations() {
+        let repository = LabelRepositoryForMemory::new();
+        let work = repository
+            .create(CreateLabel::new("Work".to_string()))
+            .await
+            .unwrap();
+        let job = repository
+            .create(CreateLabel::new("job".to_string()))
+            .await
+            .unwrap();
+        repository.attach(1, work.id).await;
+        repository.attach(2, work.id).await;
+        repository.attach(2, job.id).await;
+        repository.attach(3, job.id).await;
+
+        let merged = repository
+            .merge(job.id, work.id)
+            .await
+            .expect("failed label merge");
+        assert_eq!(
+            LabelWithUsage {
+                label: work.clone(),
+                todo_count: Some(3),
+            },
+            merged
+        );
+        assert_eq!(vec![1, 2, 3], repository.todo_ids(work.id).await);
+        assert!(repository.todo_ids(job.id).await.is_empty());
+        assert_eq!(vec![work.clone()], repository.all().await.unwrap());
+        assert_eq!(
+            vec![merged.clone()],
+            repository.all_with_counts().await.unwrap()
+        );
+
+        let err = repository
+            .merge(job.id, work.id)
+            .await
+            .expect_err("merge of deleted label returned Ok");
+        assert!(matches!(
+            &err,
+            RepositoryError::NotFound(Some(id)) if *id == i64::from(job.id)
+        ));
+        let err = repository
+            .merge(work.id, 999)
+            .await
+            .expect_err("merge into missing label returned Ok");
+        assert!(matches!(&err, RepositoryError::NotFound(Some(999))));
+    }
+
+    #[tokio::test]
+    async fn should_reject_label_update_conflicts() {
+        let repository = LabelRepositoryForMemory::new();
+        let first = repository
+            .create(CreateLabel::new("first".to_string()))
+            .await
+            .unwrap();
+        let second = repository
+            .create(CreateLabel::new("second".to_string()))
+            .await
+            .unwrap();
+
+        let err = repository
+            .update(second.id, UpdateLabel::new("first".to_string()))
+            .await
+            .expect_err("duplicate rename returned Ok");
+        assert!(matches!(
+            &err,
+            RepositoryError::Duplicate(Some(id)) if *id == first.id
+        ));
+
+        let err = repository
+            .create(CreateLabel::new("FIRST".to_string()))
+            .await
+            .expect_err("duplicate create returned Ok");
+        assert!(matches!(
+            &err,
+            RepositoryError::Duplicate(Some(id)) if *id == first.id
+        ));
+        assert_eq!(2, repository.all().await.unwrap().len());
+
+        let err = repository
+            .update(999, UpdateLabel::new("third".to_string()))
+            .await
+            .expect_err("rename of missing label returned Ok");
+        assert!(matches!(&err, RepositoryError::NotFound(Some(999))));
+
+        // 同じ名前への変更は重複扱いしない
+        let label = repository
+            .update(first.id, UpdateLabel::new("first".to_string()))
+            .await
+            .expect("failed label update");
+        assert_eq!(first, label);
+    }
+}
@@ -0,0 +1,231 @@
+use std::sync::Arc;
+
+use axum::async_trait;
+use sqlx::types::Json;
+use sqlx::{Sqlite, SqlitePool, Transaction};
+use tracing::instrument;
+
+use super::{
+    CreateLabel, Label, LabelRepository, LabelWithUsage, LabelWithUsageFromRow, UpdateLabel,
+    DEFAULT_LABEL_COLOR, SELECT_LABELS_WITH_USAGE,
+};
+use crate::clock::{Clock, SharedClock, SystemClock};
+use crate::repositories::todo::TodoRepositoryForSqlite;
+use crate::repositories::RepositoryError;
+
+// SQLITE_CONSTRAINT_UNIQUE
+const UNIQUE_VIOLATION: &str = "2067";
+
+#[derive(Debug, Clone)]
+pub struct LabelRepositoryForSqlite {
+    pool: SqlitePool,
+    clock: SharedClock,
+}
+
+impl LabelRepositoryForSqlite {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self {
+            pool,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    pub fn with_clock(self, clock: impl Clock) -> Self {
+        Self {
+            clock: Arc::new(clock),
+            ..self
+        }
+    }
+
+    // ラベルの変更はTodoのupdated_atを変えないため、付いているTodoの所有者の一覧の変更日時を進める
+    async fn touch_owners(
+        &self,
+        tx: &mut Transaction<'_, Sqlite>,
+        label_ids: &[i32],
+    ) -> Result<(), RepositoryError> {
+        let user_ids: Vec<i32> = sqlx::query_scalar(
+            r#"
+select distinct todos.user_id from todos
+join todo_labels tl on tl.todo_id = todos.id
+where tl.label_id in (select value from json_each($1)) and todos.user_id is not null;
+"#,
+        )
+        .bind(Json(label_ids))
+        .fetch_all(&mut *tx)
+        .await?;
+        TodoRepositoryForSqlite::touch_lists(tx, &user_ids, self.clock.now()).await
+    }
+
+    // 名前の一意制約(大文字小文字を区別しない)に違反した場合は、既存ラベルのidを返す
+    async fn map_unique_violation<T>(
+        &self,
+        result: Result<T, sqlx::Error>,
+        name: &str,
+    ) -> Result<T, RepositoryError> {
+        match result {
+            Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some(UNIQUE_VIOLATION) => {
+                let (id,) = sqlx::query_as::<_, (i32,)>(
+                    "select id from labels where lower(name) = lower($1)",
+                )
+                .bind(name)
+                .fetch_one(&self.pool)
+                .await?;
+                Err(RepositoryError::Duplicate(Some(id)))
+            }
+            result => Ok(result?),
+        }
+    }
+}
+
+#[async_trait]
+impl LabelRepository for LabelRepositoryForSqlite {
+    #[instrument(name = "label.create", skip_all, fields(db.operation = "INSERT"))]
+    async fn create(&self, payload: CreateLabel) -> Result<Label, RepositoryError> {
+        let name = payload.name.clone();
+        let label = sqlx::query_as::<_, Label>(
+            "insert into labels ( name, color, description ) values ( $1, $2, $3 ) returning *",
+        )
+        .bind(payload.name)
+        .bind(
+            payload
+                .color
+                .unwrap_or_else(|| DEFAULT_LABEL_COLOR.to_string()),
+        )
+        .bind(payload.description)
+        .fetch_one(&self.pool)
+        .await;
+
+        self.map_unique_violation(label, &name).await
+    }
+
+    #[instrument(name = "label.find", skip_all, fields(db.operation = "SELECT"))]
+    async fn find(&self, id: i32) -> Result<Label, RepositoryError> {
+        let label = sqlx::query_as::<_, Label>("select * from labels where id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or(RepositoryError::NotFound(Some(id.into())))?;
+        Ok(label)
+    }
+
+    #[instrument(name = "label.all", skip_all, fields(db.operation = "SELECT"))]
+    async fn all(&self) -> Result<Vec<Label>, RepositoryError> {
+        let labels = sqlx::query_as::<_, Label>("select * from labels order by labels.id asc")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(labels)
+    }
+
+    #[instrument(name = "label.all_with_counts", skip_all, fields(db.operation = "SELECT"))]
+    async fn all_with_counts(&self) -> Result<Vec<LabelWithUsage>, RepositoryError> {
+        let sql = format!(
+            "{} group by labels.id order by labels.id asc",
+            SELECT_LABELS_WITH_USAGE
+        );
+        let labels = sqlx::query_as::<_, LabelWithUsageFromRow>(&sql)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(labels.into_iter().map(LabelWithUsage::from).collect())
+    }
+
+    #[instrument(name = "label.update", skip_all, fields(db.operation = "UPDATE"))]
+    async fn update(&self, id: i32, payload: UpdateLabel) -> Result<Label, RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        let name = payload.name.clone();
+        let label = sqlx::query_as::<_, Label>(
+            r#"
+update labels
+set name = $1, color = coalesce($2, color),
+    description = case when $3 then $4 else description end
+where id = $5
+returning *;
+"#,
+        )
+        .bind(payload.name)
+        .bind(payload.color)
+        .bind(payload.description.is_some())
+        .bind(payload.description.flatten())
+        .bind(id)
+        .fetch_optional(&mut tx)
+        .await;
+        let label = self
+            .map_unique_violation(label, &name)
+            .await?
+            .ok_or_else(|| RepositoryError::NotFound(Some(id.into())))?;
+        self.touch_owners(&mut tx, &[id]).await?;
+        tx.commit().await?;
+
+        Ok(label)
+    }
+
+    #[instrument(name = "label.delete", skip_all, fields(db.operation = "DELETE"))]
+    async fn delete(&self, id: i32) -> Result<(), RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        // 削除した後は関連から所有者を辿れないため、先に一覧の変更日時を進める
+        self.touch_owners(&mut tx, &[id]).await?;
+        let result = sqlx::query("delete from labels where id=$1")
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound(Some(id.into())));
+        }
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    #[instrument(name = "label.merge", skip_all, fields(db.operation = "INSERT"))]
+    async fn merge(&self, from: i32, into: i32) -> Result<LabelWithUsage, RepositoryError> {
+        // 書き込みは接続ごとに直列化されるため、行のロックは不要
+        let mut tx = self.pool.begin().await?;
+
+        let found: Vec<i32> =
+            sqlx::query_as::<_, (i32,)>("select id from labels where id in ($1, $2)")
+                .bind(from)
+                .bind(into)
+                .fetch_all(&mut tx)
+                .await?
+                .into_iter()
+                .map(|(id,)| id)
+                .collect();
+        if let Some(missing) = [from, into].into_iter().find(|id| !found.contains(id)) {
+            return Err(RepositoryError::NotFound(Some(missing.into())));
+        }
+        self.touch_owners(&mut tx, &[from]).await?;
+
+        // 既にintoが付いているTodoは重複させずに付け替える
+        sqlx::query(
+            r#"
+insert into todo_labels (todo_id, label_id)
+select todo_id, $2 from todo_labels where label_id = $1
+on conflict (todo_id, label_id) do nothing;
+"#,
+        )
+        .bind(from)
+        .bind(into)
+        .execute(&mut tx)
+        .await?;
+        sqlx::query("delete from todo_labels where label_id = $1")
+            .bind(from)
+            .execute(&mut tx)
+            .await?;
+        sqlx::query("delete from labels where id = $1")
+            .bind(from)
+            .execute(&mut tx)
+            .await?;
+
+        let sql = format!(
+            "{} where labels.id = $1 group by labels.id",
+            SELECT_LABELS_WITH_USAGE
+        );
+        let label = sqlx::query_as::<_, LabelWithUsageFromRow>(&sql)
+            .bind(into)
+            .fetch_one(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+
+        Ok(label.into())
+    }
+}
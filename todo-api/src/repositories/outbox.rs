@@ -0,0 +1,218 @@
+use chrono::{DateTime, Utc};
+use sqlx::types::Json;
+use sqlx::{FromRow, PgPool, Postgres, Transaction};
+use tracing::instrument;
+
+use crate::events::TodoEvent;
+
+use super::RepositoryError;
+
+// eventsテーブルに記録した、配信待ちのイベント
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutboxEvent {
+    pub id: i64,
+    // ラベルの変更など、ユーザーに属さないイベントではNone
+    pub user_id: Option<i32>,
+    pub event: TodoEvent,
+    // これまでに配信に失敗した回数
+    pub attempts: i32,
+    // 前回までに送り終えた配信先・宛先の名前
+    pub delivered: Vec<String>,
+}
+
+#[derive(FromRow)]
+struct OutboxEventFromRow {
+    id: i64,
+    user_id: Option<i32>,
+    kind: String,
+    payload: Json<TodoEvent>,
+    attempts: i32,
+    delivered: Vec<String>,
+}
+
+impl From<OutboxEventFromRow> for OutboxEvent {
+    fn from(row: OutboxEventFromRow) -> Self {
+        let mut event = row.payload.0;
+        // completed_nowは本文に含めないため、種類から戻す
+        if let TodoEvent::Updated { completed_now, .. } = &mut event {
+            *completed_now = row.kind == "completed";
+        }
+        OutboxEvent {
+            id: row.id,
+            user_id: row.user_id,
+            event,
+            attempts: row.attempts,
+            delivered: row.delivered,
+        }
+    }
+}
+
+fn event_type(event: &TodoEvent) -> &'static str {
+    match event {
+        TodoEvent::Created { .. } => "created",
+        TodoEvent::Updated {
+            completed_now: true,
+            ..
+        } => "completed",
+        TodoEvent::Updated { .. } => "updated",
+        TodoEvent::Deleted { .. } => "deleted",
+        TodoEvent::Reminded { .. } => "reminded",
+        TodoEvent::LabelCreated { .. } => "label_created",
+        TodoEvent::LabelUpdated { .. } => "label_updated",
+        TodoEvent::LabelDeleted { .. } => "label_deleted",
+        TodoEvent::LabelsMerged { .. } => "labels_merged",
+    }
+}
+
+// 変更と同じトランザクションで書き込み、取り消された変更のイベントは配信されないようにする
+pub(crate) async fn record_events(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: i32,
+    events: Vec<TodoEvent>,
+    now: DateTime<Utc>,
+) -> Result<(), RepositoryError> {
+    insert_events(tx, Some(user_id), events, now).await
+}
+
+// ラベルはユーザーに属さないため、user_idをNULLで記録して全てのユーザーへ配信する
+pub(crate) async fn record_shared_events(
+    tx: &mut Transaction<'_, Postgres>,
+    events: Vec<TodoEvent>,
+    now: DateTime<Utc>,
+) -> Result<(), RepositoryError> {
+    insert_events(tx, None, events, now).await
+}
+
+async fn insert_events(
+    tx: &mut Transaction<'_, Postgres>,
+    user_id: Option<i32>,
+    events: Vec<TodoEvent>,
+    now: DateTime<Utc>,
+) -> Result<(), RepositoryError> {
+    for event in events {
+        sqlx::query(
+            r#"
+insert into events (user_id, type, payload, created_at, next_attempt_at)
+values ($1, $2, $3, $4, $4);
+"#,
+        )
+        .bind(user_id)
+        .bind(event_type(&event))
+        .bind(Json(&event))
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct OutboxRepositoryForDb {
+    pool: PgPool,
+}
+
+impl OutboxRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        OutboxRepositoryForDb { pool }
+    }
+
+    // 配信時刻を過ぎたイベントを古い順に取り出し、locked_untilまで他のインスタンスから取り出されないようにする
+    // 取り出した時点でcommitするため、配信の間は行のロックも接続も持たない
+    #[instrument(name = "outbox.claim", skip_all, fields(db.operation = "UPDATE"))]
+    pub async fn claim(
+        &self,
+        now: DateTime<Utc>,
+        locked_until: DateTime<Utc>,
+        limit: i64,
+    ) -> Result<Vec<OutboxEvent>, RepositoryError> {
+        let mut events: Vec<OutboxEvent> = sqlx::query_as::<_, OutboxEventFromRow>(
+            r#"
+update events set locked_until = $2
+where id in (
+  select id from events
+  where published_at is null and failed_at is null and next_attempt_at <= $1
+    and (locked_until is null or locked_until <= $1)
+  order by id asc
+  limit $3
+  for update skip locked
+)
+returning id, user_id, type as kind, payload, attempts,
+  array(select sink from event_deliveries where event_id = events.id) as delivered;
+"#,
+        )
+        .bind(now)
+        .bind(locked_until)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?
+        .into_iter()
+        .map(OutboxEvent::from)
+        .collect();
+        // returningは順序を保証しないため、記録した順に並べ直す
+        events.sort_by_key(|event| event.id);
+        Ok(events)
+    }
+
+    // 送り終えた配信先・宛先を記録する。送り直す際はこれらへは送らない
+    #[instrument(name = "outbox.delivered", skip_all, fields(db.operation = "INSERT"))]
+    pub async fn delivered(
+        &self,
+        id: i64,
+        sinks: &[String],
+        now: DateTime<Utc>,
+    ) -> Result<(), RepositoryError> {
+        if sinks.is_empty() {
+            return Ok(());
+        }
+        sqlx::query(
+            r#"
+insert into event_deliveries (event_id, sink, delivered_at)
+select $1, sink, $3 from unnest($2::text[]) as sink
+on conflict do nothing;
+"#,
+        )
+        .bind(id)
+        .bind(sinks)
+        .bind(now)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    #[instrument(name = "outbox.published", skip_all, fields(db.operation = "UPDATE"))]
+    pub async fn published(&self, id: i64, now: DateTime<Utc>) -> Result<(), RepositoryError> {
+        sqlx::query("update events set published_at = $2, locked_until = null where id = $1")
+            .bind(id)
+            .bind(now)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    // retry_atがNoneの場合は再送の上限に達したものとして、以降は取り出さない
+    #[instrument(name = "outbox.failed", skip_all, fields(db.operation = "UPDATE"))]
+    pub async fn failed(
+        &self,
+        id: i64,
+        error: &str,
+        retry_at: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query(
+            r#"
+update events
+set attempts = attempts + 1, last_error = $2, locked_until = null,
+    next_attempt_at = coalesce($3::timestamptz, next_attempt_at),
+    failed_at = case when $3::timestamptz is null then $4 else null end
+where id = $1;
+"#,
+        )
+        .bind(id)
+        .bind(error)
+        .bind(retry_at)
+        .bind(now)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,548 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::stream::{self, BoxStream, StreamExt};
+use uuid::Uuid;
+
+use super::backup::{Backup, ImportSummary};
+use super::label::{CreateLabel, Label, LabelRepository, LabelWithUsage, UpdateLabel};
+use super::todo::{
+    CreateTodo, CreateTodoWithLabelNames, DueReminder, DuplicateTodo, FoundTodos,
+    IdempotencyRecord, MoveTarget, Precondition, ReplaceTodo, TodoEntity, TodoListQuery, TodoPage,
+    TodoRepository, TodoStats, UpdateTodo, UpdateTodos, UpdatedTodos,
+};
+use super::todo_activity::TodoActivityPage;
+use super::todo_item::{CreateTodoItem, TodoItem, UpdateTodoItem};
+use super::{PageQuery, RepositoryError};
+
+pub mod conformance;
+pub mod fixtures;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    // データベースのエラーと同じくRepositoryError::Unexpectedを返す
+    Fail,
+    // 待ってから内側のリポジトリへ渡す
+    Delay(Duration),
+    // ハンドラの中でpanicさせる
+    Panic,
+}
+
+// メソッド名ごとの障害と呼び出し回数。クローンしたリポジトリ間で共有し、アプリに渡した後でも切り替えられる
+#[derive(Debug, Clone, Default)]
+struct Faults {
+    faults: Arc<Mutex<HashMap<&'static str, Fault>>>,
+    calls: Arc<Mutex<HashMap<&'static str, usize>>>,
+}
+
+impl Faults {
+    fn set(&self, method: &'static str, fault: Option<Fault>) {
+        let mut faults = self.faults.lock().unwrap();
+        match fault {
+            Some(fault) => faults.insert(method, fault),
+            None => faults.remove(method),
+        };
+    }
+
+    fn get(&self, method: &'static str) -> Option<Fault> {
+        self.faults.lock().unwrap().get(method).copied()
+    }
+
+    fn calls(&self, method: &'static str) -> usize {
+        self.calls.lock().unwrap().get(method).copied().unwrap_or(0)
+    }
+
+    async fn inject(&self, method: &'static str) -> Result<(), RepositoryError> {
+        *self.calls.lock().unwrap().entry(method).or_default() += 1;
+        match self.get(method) {
+            Some(Fault::Fail) => Err(Self::error(method)),
+            Some(Fault::Delay(duration)) => {
+                tokio::time::sleep(duration).await;
+                Ok(())
+            }
+            Some(Fault::Panic) => Self::panic(method),
+            None => Ok(()),
+        }
+    }
+
+    fn error(method: &'static str) -> RepositoryError {
+        RepositoryError::unexpected(format!("injected failure in {}", method))
+    }
+
+    fn panic(method: &'static str) -> ! {
+        panic!("injected panic in {}", method)
+    }
+}
+
+// データベースを止めずに、リポジトリが失敗・遅延した場合のハンドラの挙動を確かめる
+#[derive(Debug, Clone)]
+pub struct FailingTodoRepository<R> {
+    inner: R,
+    faults: Faults,
+}
+
+impl<R: TodoRepository> FailingTodoRepository<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            faults: Faults::default(),
+        }
+    }
+
+    pub fn fail(self, method: &'static str) -> Self {
+        self.faults.set(method, Some(Fault::Fail));
+        self
+    }
+
+    pub fn delay(self, method: &'static str, duration: Duration) -> Self {
+        self.faults.set(method, Some(Fault::Delay(duration)));
+        self
+    }
+
+    pub fn panic(self, method: &'static str) -> Self {
+        self.faults.set(method, Some(Fault::Panic));
+        self
+    }
+
+    pub fn recover(&self, method: &'static str) {
+        self.faults.set(method, None);
+    }
+
+    // 障害の有無によらず、メソッドが呼ばれた回数を返す
+    pub fn calls(&self, method: &'static str) -> usize {
+        self.faults.calls(method)
+    }
+}
+
+#[async_trait]
+impl<R: TodoRepository> TodoRepository for FailingTodoRepository<R> {
+    async fn create(
+        &self,
+        user_id: i32,
+        payload: CreateTodo,
+    ) -> Result<TodoEntity, RepositoryError> {
+        self.faults.inject("create").await?;
+        self.inner.create(user_id, payload).await
+    }
+
+    async fn create_many(
+        &self,
+        user_id: i32,
+        payloads: Vec<CreateTodo>,
+    ) -> Result<Vec<TodoEntity>, RepositoryError> {
+        self.faults.inject("create_many").await?;
+        self.inner.create_many(user_id, payloads).await
+    }
+
+    async fn create_idempotent(
+        &self,
+        user_id: i32,
+        payload: CreateTodo,
+        key: Option<&str>,
+    ) -> Result<TodoEntity, RepositoryError> {
+        self.faults.inject("create_idempotent").await?;
+        self.inner.create_idempotent(user_id, payload, key).await
+    }
+
+    async fn create_many_idempotent(
+        &self,
+        user_id: i32,
+        payloads: Vec<CreateTodo>,
+        key: Option<&str>,
+    ) -> Result<Vec<TodoEntity>, RepositoryError> {
+        self.faults.inject("create_many_idempotent").await?;
+        self.inner
+            .create_many_idempotent(user_id, payloads, key)
+            .await
+    }
+
+    async fn create_many_with_label_names(
+        &self,
+        user_id: i32,
+        payloads: Vec<CreateTodoWithLabelNames>,
+    ) -> Result<Vec<TodoEntity>, RepositoryError> {
+        self.faults.inject("create_many_with_label_names").await?;
+        self.inner
+            .create_many_with_label_names(user_id, payloads)
+            .await
+    }
+
+    async fn find(&self, user_id: i32, id: i64) -> Result<TodoEntity, RepositoryError> {
+        self.faults.inject("find").await?;
+        self.inner.find(user_id, id).await
+    }
+
+    async fn find_many(&self, user_id: i32, ids: &[i64]) -> Result<FoundTodos, RepositoryError> {
+        self.faults.inject("find_many").await?;
+        self.inner.find_many(user_id, ids).await
+    }
+
+    async fn exists(&self, user_id: i32, id: i64) -> Result<bool, RepositoryError> {
+        self.faults.inject("exists").await?;
+        self.inner.exists(user_id, id).await
+    }
+
+    async fn find_open_by_text(
+        &self,
+        user_id: i32,
+        text: &str,
+    ) -> Result<Option<TodoEntity>, RepositoryError> {
+        self.faults.inject("find_open_by_text").await?;
+        self.inner.find_open_by_text(user_id, text).await
+    }
+
+    async fn find_by_client_id(
+        &self,
+        user_id: i32,
+        client_id: Uuid,
+    ) -> Result<Option<TodoEntity>, RepositoryError> {
+        self.faults.inject("find_by_client_id").await?;
+        self.inner.find_by_client_id(user_id, client_id).await
+    }
+
+    async fn labels_for_todos(
+        &self,
+        user_id: i32,
+        ids: Vec<i64>,
+    ) -> Result<HashMap<i64, Vec<Label>>, RepositoryError> {
+        self.faults.inject("labels_for_todos").await?;
+        self.inner.labels_for_todos(user_id, ids).await
+    }
+
+    async fn all(&self, user_id: i32, query: TodoListQuery) -> Result<TodoPage, RepositoryError> {
+        self.faults.inject("all").await?;
+        self.inner.all(user_id, query).await
+    }
+
+    async fn last_modified(&self, user_id: i32) -> Result<Option<DateTime<Utc>>, RepositoryError> {
+        self.faults.inject("last_modified").await?;
+        self.inner.last_modified(user_id).await
+    }
+
+    async fn update(
+        &self,
+        user_id: i32,
+        id: i64,
+        payload: UpdateTodo,
+    ) -> Result<TodoEntity, RepositoryError> {
+        self.faults.inject("update").await?;
+        self.inner.update(user_id, id, payload).await
+    }
+
+    async fn replace(
+        &self,
+        user_id: i32,
+        id: i64,
+        payload: ReplaceTodo,
+    ) -> Result<TodoEntity, RepositoryError> {
+        self.faults.inject("replace").await?;
+        self.inner.replace(user_id, id, payload).await
+    }
+
+    async fn update_many(
+        &self,
+        user_id: i32,
+        payload: UpdateTodos,
+    ) -> Result<UpdatedTodos, RepositoryError> {
+        self.faults.inject("update_many").await?;
+        self.inner.update_many(user_id, payload).await
+    }
+
+    async fn delete(
+        &self,
+        user_id: i32,
+        id: i64,
+        precondition: Precondition,
+    ) -> Result<(), RepositoryError> {
+        self.faults.inject("delete").await?;
+        self.inner.delete(user_id, id, precondition).await
+    }
+
+    async fn delete_permanently(&self, user_id: i32, id: i64) -> Result<(), RepositoryError> {
+        self.faults.inject("delete_permanently").await?;
+        self.inner.delete_permanently(user_id, id).await
+    }
+
+    async fn trash(&self, user_id: i32) -> Result<Vec<TodoEntity>, RepositoryError> {
+        self.faults.inject("trash").await?;
+        self.inner.trash(user_id).await
+    }
+
+    async fn restore(&self, user_id: i32, id: i64) -> Result<TodoEntity, RepositoryError> {
+        self.faults.inject("restore").await?;
+        self.inner.restore(user_id, id).await
+    }
+
+    async fn delete_completed(&self, user_id: i32) -> Result<u64, RepositoryError> {
+        self.faults.inject("delete_completed").await?;
+        self.inner.delete_completed(user_id).await
+    }
+
+    async fn purge_deleted_before(&self, cutoff: DateTime<Utc>) -> Result<u64, RepositoryError> {
+        self.faults.inject("purge_deleted_before").await?;
+        self.inner.purge_deleted_before(cutoff).await
+    }
+
+    async fn claim_idempotency_key(
+        &self,
+        user_id: i32,
+        key: &str,
+        fingerprint: &str,
+    ) -> Result<Option<IdempotencyRecord>, RepositoryError> {
+        self.faults.inject("claim_idempotency_key").await?;
+        self.inner
+            .claim_idempotency_key(user_id, key, fingerprint)
+            .await
+    }
+
+    async fn complete_idempotency_key(
+        &self,
+        user_id: i32,
+        key: &str,
+        response: serde_json::Value,
+    ) -> Result<(), RepositoryError> {
+        self.faults.inject("complete_idempotency_key").await?;
+        self.inner
+            .complete_idempotency_key(user_id, key, response)
+            .await
+    }
+
+    async fn release_idempotency_key(
+        &self,
+        user_id: i32,
+        key: &str,
+    ) -> Result<(), RepositoryError> {
+        self.faults.inject("release_idempotency_key").await?;
+        self.inner.release_idempotency_key(user_id, key).await
+    }
+
+    async fn purge_idempotency_keys_before(
+        &self,
+        cutoff: DateTime<Utc>,
+    ) -> Result<u64, RepositoryError> {
+        self.faults.inject("purge_idempotency_keys_before").await?;
+        self.inner.purge_idempotency_keys_before(cutoff).await
+    }
+
+    async fn attach_label(
+        &self,
+        user_id: i32,
+        id: i64,
+        label_id: i32,
+    ) -> Result<TodoEntity, RepositoryError> {
+        self.faults.inject("attach_label").await?;
+        self.inner.attach_label(user_id, id, label_id).await
+    }
+
+    async fn detach_label(
+        &self,
+        user_id: i32,
+        id: i64,
+        label_id: i32,
+    ) -> Result<TodoEntity, RepositoryError> {
+        self.faults.inject("detach_label").await?;
+        self.inner.detach_label(user_id, id, label_id).await
+    }
+
+    async fn export(&self, user_id: i32) -> Result<Backup, RepositoryError> {
+        self.faults.inject("export").await?;
+        self.inner.export(user_id).await
+    }
+
+    async fn import(&self, user_id: i32, backup: Backup) -> Result<ImportSummary, RepositoryError> {
+        self.faults.inject("import").await?;
+        self.inner.import(user_id, backup).await
+    }
+
+    async fn reset(&self) -> Result<(), RepositoryError> {
+        self.faults.inject("reset").await?;
+        self.inner.reset().await
+    }
+
+    async fn seed(&self, user_id: i32, fixtures: Backup) -> Result<ImportSummary, RepositoryError> {
+        self.faults.inject("seed").await?;
+        self.inner.seed(user_id, fixtures).await
+    }
+
+    // 失敗とpanicは最初の要素として返し、遅延は最初の要素の前に挟む
+    fn stream_all(&self, user_id: i32) -> BoxStream<'static, Result<TodoEntity, RepositoryError>> {
+        let todos = self.inner.stream_all(user_id);
+        match self.faults.get("stream_all") {
+            Some(Fault::Fail) => stream::once(async { Err(Faults::error("stream_all")) }).boxed(),
+            Some(Fault::Panic) => stream::poll_fn(|_| Faults::panic("stream_all")).boxed(),
+            Some(Fault::Delay(duration)) => stream::once(async move {
+                tokio::time::sleep(duration).await;
+                todos
+            })
+            .flatten()
+            .boxed(),
+            None => todos,
+        }
+    }
+
+    async fn stats(&self, user_id: i32) -> Result<TodoStats, RepositoryError> {
+        self.faults.inject("stats").await?;
+        self.inner.stats(user_id).await
+    }
+
+    async fn items(&self, user_id: i32, id: i64) -> Result<Vec<TodoItem>, RepositoryError> {
+        self.faults.inject("items").await?;
+        self.inner.items(user_id, id).await
+    }
+
+    async fn create_item(
+        &self,
+        user_id: i32,
+        id: i64,
+        payload: CreateTodoItem,
+    ) -> Result<TodoItem, RepositoryError> {
+        self.faults.inject("create_item").await?;
+        self.inner.create_item(user_id, id, payload).await
+    }
+
+    async fn update_item(
+        &self,
+        user_id: i32,
+        id: i64,
+        item_id: i32,
+        payload: UpdateTodoItem,
+    ) -> Result<TodoItem, RepositoryError> {
+        self.faults.inject("update_item").await?;
+        self.inner.update_item(user_id, id, item_id, payload).await
+    }
+
+    async fn delete_item(
+        &self,
+        user_id: i32,
+        id: i64,
+        item_id: i32,
+    ) -> Result<(), RepositoryError> {
+        self.faults.inject("delete_item").await?;
+        self.inner.delete_item(user_id, id, item_id).await
+    }
+
+    async fn duplicate(
+        &self,
+        user_id: i32,
+        id: i64,
+        payload: DuplicateTodo,
+    ) -> Result<TodoEntity, RepositoryError> {
+        self.faults.inject("duplicate").await?;
+        self.inner.duplicate(user_id, id, payload).await
+    }
+
+    async fn archive(&self, user_id: i32, id: i64) -> Result<TodoEntity, RepositoryError> {
+        self.faults.inject("archive").await?;
+        self.inner.archive(user_id, id).await
+    }
+
+    async fn unarchive(&self, user_id: i32, id: i64) -> Result<TodoEntity, RepositoryError> {
+        self.faults.inject("unarchive").await?;
+        self.inner.unarchive(user_id, id).await
+    }
+
+    async fn archive_completed(&self, user_id: i32) -> Result<u64, RepositoryError> {
+        self.faults.inject("archive_completed").await?;
+        self.inner.archive_completed(user_id).await
+    }
+
+    async fn move_todo(
+        &self,
+        user_id: i32,
+        id: i64,
+        target: MoveTarget,
+    ) -> Result<TodoEntity, RepositoryError> {
+        self.faults.inject("move_todo").await?;
+        self.inner.move_todo(user_id, id, target).await
+    }
+
+    async fn activity(
+        &self,
+        user_id: i32,
+        id: i64,
+        query: PageQuery,
+    ) -> Result<TodoActivityPage, RepositoryError> {
+        self.faults.inject("activity").await?;
+        self.inner.activity(user_id, id, query).await
+    }
+
+    async fn due_reminders(&self, now: DateTime<Utc>) -> Result<Vec<DueReminder>, RepositoryError> {
+        self.faults.inject("due_reminders").await?;
+        self.inner.due_reminders(now).await
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FailingLabelRepository<R> {
+    inner: R,
+    faults: Faults,
+}
+
+impl<R: LabelRepository> FailingLabelRepository<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            faults: Faults::default(),
+        }
+    }
+
+    pub fn fail(self, method: &'static str) -> Self {
+        self.faults.set(method, Some(Fault::Fail));
+        self
+    }
+
+    pub fn delay(self, method: &'static str, duration: Duration) -> Self {
+        self.faults.set(method, Some(Fault::Delay(duration)));
+        self
+    }
+
+    pub fn panic(self, method: &'static str) -> Self {
+        self.faults.set(method, Some(Fault::Panic));
+        self
+    }
+
+    pub fn recover(&self, method: &'static str) {
+        self.faults.set(method, None);
+    }
+}
+
+#[async_trait]
+impl<R: LabelRepository> LabelRepository for FailingLabelRepository<R> {
+    async fn create(&self, payload: CreateLabel) -> Result<Label, RepositoryError> {
+        self.faults.inject("create").await?;
+        self.inner.create(payload).await
+    }
+
+    async fn find(&self, id: i32) -> Result<Label, RepositoryError> {
+        self.faults.inject("find").await?;
+        self.inner.find(id).await
+    }
+
+    async fn all(&self) -> Result<Vec<Label>, RepositoryError> {
+        self.faults.inject("all").await?;
+        self.inner.all().await
+    }
+
+    async fn all_with_counts(&self) -> Result<Vec<LabelWithUsage>, RepositoryError> {
+        self.faults.inject("all_with_counts").await?;
+        self.inner.all_with_counts().await
+    }
+
+    async fn update(&self, id: i32, payload: UpdateLabel) -> Result<Label, RepositoryError> {
+        self.faults.inject("update").await?;
+        self.inner.update(id, payload).await
+    }
+
+    async fn delete(&self, id: i32) -> Result<(), RepositoryError> {
+        self.faults.inject("delete").await?;
+        self.inner.delete(id).await
+    }
+
+    async fn merge(&self, from: i32, into: i32) -> Result<LabelWithUsage, RepositoryError> {
+        self.faults.inject("merge").await?;
+        self.inner.merge(from, into).await
+    }
+}
@@ -0,0 +1,1204 @@
+use std::fmt::Debug;
+
+use chrono::{Duration, Utc};
+use uuid::Uuid;
+
+use crate::repositories::label::Label;
+use crate::repositories::todo::{
+    CreateTodo, DuplicateTodo, FoundTodos, MoveTarget, Precondition, Priority, ReplaceTodo,
+    SortField, SortOrder, TodoEntity, TodoListQuery, TodoRepository, UpdateTodo, UpdateTodos,
+};
+use crate::repositories::todo_item::{CreateTodoItem, UpdateTodoItem};
+use crate::repositories::RepositoryError;
+
+use super::fixtures::TodoFixture;
+
+// どの実装にも存在しないid。Todoのidのみ64bit
+const MISSING: i32 = i32::MAX;
+const MISSING_TODO: i64 = i64::MAX;
+
+// TodoRepositoryの実装間で振る舞いが揃っていることを確かめる
+// makeはケースごとに呼ぶ。DBの実装ではuser_idのユーザーを事前に作成しておく必要があり、
+// 共有のDBで前回までのデータと混ざらないよう、一覧のケースはTodoの本文の接頭辞で絞り込む
+pub async fn run_todo_repository_suite<R: TodoRepository>(make: impl Fn() -> R, user_id: i32) {
+    create_and_find(&make(), user_id).await;
+    find_errors(&make(), user_id).await;
+    find_many_reports_missing(&make(), user_id).await;
+    client_id_is_unique_per_user(&make(), user_id).await;
+    update_merges_fields(&make(), user_id).await;
+    update_clears_nullable_fields(&make(), user_id).await;
+    update_errors(&make(), user_id).await;
+    replace_resets_fields(&make(), user_id).await;
+    replace_errors(&make(), user_id).await;
+    unknown_label_creates_nothing(&make(), user_id).await;
+    update_many_reports_missing(&make(), user_id).await;
+    delete_and_restore(&make(), user_id).await;
+    delete_permanently(&make(), user_id).await;
+    last_modified_includes_trash(&make(), user_id).await;
+    last_modified_advances_without_updated_at(&make(), user_id).await;
+    paginate(&make(), user_id).await;
+    filter_and_sort(&make(), user_id).await;
+    attach_and_detach_labels(&make(), user_id).await;
+    archive_is_idempotent(&make(), user_id).await;
+    checklist_item_errors(&make(), user_id).await;
+    duplicate_and_move_errors(&make(), user_id).await;
+}
+
+fn assert_error<T: Debug>(
+    result: Result<T, RepositoryError>,
+    expected: RepositoryError,
+    case: &str,
+) {
+    // RepositoryErrorはPartialEqを実装しないため、種類とidを含むメッセージで比べる
+    let actual = result.expect_err(case);
+    assert_eq!(expected.to_string(), actual.to_string(), "{}", case);
+}
+
+// 実装ごとに採番されるラベルのidを得るため、fixtureで登録して取り出す
+async fn labels<R: TodoRepository>(repository: &R, user_id: i32) -> (Label, Label) {
+    let todo = TodoFixture::new("[conformance] labels")
+        .owned_by(user_id)
+        .with_label("conformance-a")
+        .with_label("conformance-b")
+        .insert(repository)
+        .await;
+    repository
+        .delete_permanently(user_id, todo.id)
+        .await
+        .expect("failed delete label fixture");
+    let mut labels = todo.labels.into_iter();
+    (labels.next().unwrap(), labels.next().unwrap())
+}
+
+fn ids(todos: &[TodoEntity]) -> Vec<i64> {
+    todos.iter().map(|todo| todo.id).collect()
+}
+
+fn tagged(tag: &str) -> TodoListQuery {
+    TodoListQuery {
+        q: Some(tag.to_string()),
+        sort: Some(SortField::Id),
+        order: Some(SortOrder::Asc),
+        ..TodoListQuery::default()
+    }
+}
+
+async fn create_and_find<R: TodoRepository>(repository: &R, user_id: i32) {
+    let (a, b) = labels(repository, user_id).await;
+    let due_date = Utc::now() + Duration::days(1);
+    let created = repository
+        .create(
+            user_id,
+            CreateTodo::new("  [create] text\n".to_string(), vec![b.id, a.id])
+                .with_due_date(due_date)
+                .with_priority(Priority::High),
+        )
+        .await
+        .expect("[create] returned Err");
+    assert_eq!("[create] text", created.text);
+    assert!(!created.completed);
+    // ラベルは指定した順ではなくidの順に並ぶ
+    assert_eq!(vec![a.clone(), b.clone()], created.labels);
+    assert_eq!(1, created.version);
+    assert_eq!(Priority::High, created.priority);
+    assert!(created.due_date.is_some());
+    assert_eq!(created.created_at, created.updated_at);
+    assert_eq!(None, created.deleted_at);
+
+    let found = repository
+        .find(user_id, created.id)
+        .await
+        .expect("[find] returned Err");
+    assert_eq!(created, found);
+}
+
+async fn find_errors<R: TodoRepository>(repository: &R, user_id: i32) {
+    let todo = repository
+        .create(user_id, CreateTodo::new("[find] owned".to_string(), vec![]))
+        .await
+        .unwrap();
+    assert_error(
+        repository.find(user_id, MISSING_TODO).await,
+        RepositoryError::NotFound(Some(MISSING_TODO)),
+        "[find] missing todo",
+    );
+    // 他のユーザーのTodoは存在しないものとして扱う
+    assert_error(
+        repository.find(user_id + 1, todo.id).await,
+        RepositoryError::NotFound(Some(todo.id)),
+        "[find] other user's todo",
+    );
+}
+
+async fn find_many_reports_missing<R: TodoRepository>(repository: &R, user_id: i32) {
+    let created = repository
+        .create_many(
+            user_id,
+            vec![
+                CreateTodo::new("[find_many] first".to_string(), vec![]),
+                CreateTodo::new("[find_many] second".to_string(), vec![]),
+            ],
+        )
+        .await
+        .expect("[create_many] returned Err");
+    let trashed = TodoFixture::new("[find_many] trashed")
+        .owned_by(user_id)
+        .deleted()
+        .insert(repository)
+        .await;
+
+    // 指定した順・重複によらずidの順に返し、見つからないidは指定した順に一度だけ返す
+    let found = repository
+        .find_many(
+            user_id,
+            &[
+                MISSING_TODO,
+                created[1].id,
+                trashed.id,
+                created[0].id,
+                created[1].id,
+            ],
+        )
+        .await
+        .expect("[find_many] returned Err");
+    assert_eq!(created, found.todos);
+    assert_eq!(vec![MISSING_TODO, trashed.id], found.missing);
+
+    let found = repository
+        .find_many(user_id + 1, &[created[0].id])
+        .await
+        .unwrap();
+    assert!(found.todos.is_empty());
+    assert_eq!(vec![created[0].id], found.missing);
+
+    let found = repository.find_many(user_id, &[]).await.unwrap();
+    assert_eq!(FoundTodos::default(), found);
+
+    assert!(repository.exists(user_id, created[0].id).await.unwrap());
+    assert!(!repository.exists(user_id, MISSING_TODO).await.unwrap());
+    assert!(!repository.exists(user_id, trashed.id).await.unwrap());
+    assert!(!repository.exists(user_id + 1, created[0].id).await.unwrap());
+    // 共有のDBでは後のケースのゴミ箱の一覧に混ざるため、完全に削除しておく
+    repository
+        .delete_permanently(user_id, trashed.id)
+        .await
+        .expect("failed purge trashed fixture");
+}
+
+async fn client_id_is_unique_per_user<R: TodoRepository>(repository: &R, user_id: i32) {
+    // 共有のDBで前回までの実行と重ならないよう、毎回ランダムに作る
+    let client_id = Uuid::from_u128(rand::random());
+    let with_client_id =
+        |text: &str, client_id| CreateTodo::new(text.to_string(), vec![]).with_client_id(client_id);
+    let created = repository
+        .create(user_id, with_client_id("[client_id] synced", client_id))
+        .await
+        .expect("[create] returned Err");
+    assert_eq!(Some(client_id), created.client_id);
+    assert_eq!(
+        Some(created.clone()),
+        repository
+            .find_by_client_id(user_id, client_id)
+            .await
+            .unwrap()
+    );
+    assert_eq!(
+        None,
+        repository
+            .find_by_client_id(user_id + 1, client_id)
+            .await
+            .unwrap()
+    );
+
+    assert_error(
+        repository
+            .create(user_id, with_client_id("[client_id] resent", client_id))
+            .await,
+        RepositoryError::Duplicate(None),
+        "[create] used client_id",
+    );
+    // 一括登録では1件でも重複すれば何も登録しない
+    let fresh = Uuid::from_u128(rand::random());
+    assert_error(
+        repository
+            .create_many(
+                user_id,
+                vec![
+                    with_client_id("[client_id] fresh", fresh),
+                    with_client_id("[client_id] resent", client_id),
+                ],
+            )
+            .await,
+        RepositoryError::Duplicate(None),
+        "[create_many] used client_id",
+    );
+    assert_eq!(
+        None,
+        repository.find_by_client_id(user_id, fresh).await.unwrap()
+    );
+
+    // ゴミ箱内のTodoは見つからないが、client_idは使用済みのまま
+    repository
+        .delete(user_id, created.id, Precondition::default())
+        .await
+        .expect("[delete] returned Err");
+    assert_eq!(
+        None,
+        repository
+            .find_by_client_id(user_id, client_id)
+            .await
+            .unwrap()
+    );
+    assert_error(
+        repository
+            .create(user_id, with_client_id("[client_id] resent", client_id))
+            .await,
+        RepositoryError::Duplicate(None),
+        "[create] client_id of trashed todo",
+    );
+    repository
+        .delete_permanently(user_id, created.id)
+        .await
+        .expect("failed purge trashed todo");
+}
+
+async fn update_merges_fields<R: TodoRepository>(repository: &R, user_id: i32) {
+    let (a, b) = labels(repository, user_id).await;
+    let created = repository
+        .create(
+            user_id,
+            CreateTodo::new("[update] text".to_string(), vec![a.id])
+                .with_due_date(Utc::now() + Duration::days(1))
+                .with_priority(Priority::Low),
+        )
+        .await
+        .unwrap();
+
+    // 指定しなかった項目は変更しない
+    let completed = repository
+        .update(
+            user_id,
+            created.id,
+            UpdateTodo::default().with_completed(true),
+        )
+        .await
+        .expect("[update] completed returned Err");
+    assert!(completed.completed);
+    assert_eq!(
+        (
+            &created.text,
+            &created.labels,
+            created.priority,
+            created.due_date
+        ),
+        (
+            &completed.text,
+            &completed.labels,
+            completed.priority,
+            completed.due_date
+        )
+    );
+    assert_eq!(created.created_at, completed.created_at);
+    assert_eq!(2, completed.version);
+
+    let updated = repository
+        .update(
+            user_id,
+            created.id,
+            UpdateTodo::default()
+                .with_text(" [update] new text ".to_string())
+                .with_labels(vec![b.id])
+                .with_due_date(None),
+        )
+        .await
+        .expect("[update] text returned Err");
+    assert_eq!("[update] new text", updated.text);
+    assert!(updated.completed);
+    assert_eq!(vec![b], updated.labels);
+    assert_eq!(None, updated.due_date);
+    assert_eq!(Priority::Low, updated.priority);
+    assert_eq!(3, updated.version);
+    assert_eq!(
+        updated,
+        repository.find(user_id, created.id).await.unwrap(),
+        "[update] find after update"
+    );
+
+    let cleared = repository
+        .update(
+            user_id,
+            created.id,
+            UpdateTodo::default().with_labels(vec![]),
+        )
+        .await
+        .unwrap();
+    assert!(cleared.labels.is_empty());
+}
+
+async fn update_clears_nullable_fields<R: TodoRepository>(repository: &R, user_id: i32) {
+    let (a, _) = labels(repository, user_id).await;
+    let payload: CreateTodo = serde_json::from_value(serde_json::json!({
+        "text": "[update] clear",
+        "labels": [a.id],
+        "due_date": Utc::now() + Duration::days(7),
+        "priority": "high",
+        "recurrence": {"type": "weekly"},
+        "remind_at": Utc::now() + Duration::days(6),
+    }))
+    .unwrap();
+    let created = repository.create(user_id, payload).await.unwrap();
+
+    // JSON Merge Patchのnullを読み替えた後の値
+    let cleared: UpdateTodo = serde_json::from_value(serde_json::json!({
+        "labels": [],
+        "due_date": null,
+        "priority": "medium",
+        "recurrence": null,
+        "remind_at": null,
+    }))
+    .unwrap();
+    let updated = repository
+        .update(user_id, created.id, cleared)
+        .await
+        .expect("[update] clear returned Err");
+    assert_eq!(
+        (true, None, Priority::Medium, None, None),
+        (
+            updated.labels.is_empty(),
+            updated.due_date,
+            updated.priority,
+            updated.recurrence,
+            updated.remind_at
+        ),
+        "[update] clear"
+    );
+    assert_eq!(created.text, updated.text);
+    assert_eq!(
+        updated,
+        repository.find(user_id, created.id).await.unwrap(),
+        "[update] find after clear"
+    );
+}
+
+async fn update_errors<R: TodoRepository>(repository: &R, user_id: i32) {
+    let (a, _) = labels(repository, user_id).await;
+    let todo = repository
+        .create(
+            user_id,
+            CreateTodo::new("[update] errors".to_string(), vec![a.id]),
+        )
+        .await
+        .unwrap();
+    let payload = UpdateTodo::default().with_completed(true);
+
+    assert_error(
+        repository
+            .update(user_id, MISSING_TODO, payload.clone())
+            .await,
+        RepositoryError::NotFound(Some(MISSING_TODO)),
+        "[update] missing todo",
+    );
+    assert_error(
+        repository
+            .update(user_id + 1, todo.id, payload.clone())
+            .await,
+        RepositoryError::NotFound(Some(todo.id)),
+        "[update] other user's todo",
+    );
+    assert_error(
+        repository
+            .update(
+                user_id,
+                todo.id,
+                payload.clone().with_version(todo.version + 1),
+            )
+            .await,
+        RepositoryError::Conflict(todo.id),
+        "[update] stale version",
+    );
+    assert_error(
+        repository
+            .update(
+                user_id,
+                todo.id,
+                payload
+                    .clone()
+                    .with_precondition(Precondition::default().with_version(todo.version + 1)),
+            )
+            .await,
+        RepositoryError::PreconditionFailed(todo.id),
+        "[update] precondition",
+    );
+    assert_error(
+        repository
+            .update(user_id, todo.id, payload.with_labels(vec![MISSING]))
+            .await,
+        RepositoryError::InvalidLabel(MISSING),
+        "[update] unknown label",
+    );
+    // 失敗した更新は何も変更しない
+    assert_eq!(todo, repository.find(user_id, todo.id).await.unwrap());
+}
+
+async fn replace_resets_fields<R: TodoRepository>(repository: &R, user_id: i32) {
+    let (a, b) = labels(repository, user_id).await;
+    let created = repository
+        .create(
+            user_id,
+            CreateTodo::new("[replace] text".to_string(), vec![a.id])
+                .with_due_date(Utc::now() + Duration::days(1))
+                .with_priority(Priority::High),
+        )
+        .await
+        .unwrap();
+
+    // 指定しなかった項目は消すか既定値に戻す
+    let replaced = repository
+        .replace(
+            user_id,
+            created.id,
+            ReplaceTodo::new(" [replace] new text ".to_string(), true).with_labels(vec![b.id]),
+        )
+        .await
+        .expect("[replace] returned Err");
+    assert_eq!("[replace] new text", replaced.text);
+    assert!(replaced.completed);
+    assert_eq!(vec![b.clone()], replaced.labels);
+    assert_eq!(None, replaced.due_date);
+    assert_eq!(Priority::Medium, replaced.priority);
+    assert_eq!(created.created_at, replaced.created_at);
+    assert_eq!(2, replaced.version);
+    assert_eq!(
+        replaced,
+        repository.find(user_id, created.id).await.unwrap(),
+        "[replace] find after replace"
+    );
+
+    // 残すラベルと加えるラベルを同時に指定できる
+    let relabeled = repository
+        .replace(
+            user_id,
+            created.id,
+            ReplaceTodo::new("[replace] new text".to_string(), true)
+                .with_labels(vec![a.id, b.id])
+                .with_version(replaced.version),
+        )
+        .await
+        .unwrap();
+    assert_eq!(vec![a, b], relabeled.labels);
+
+    let cleared = repository
+        .replace(
+            user_id,
+            created.id,
+            ReplaceTodo::new("[replace] new text".to_string(), false),
+        )
+        .await
+        .unwrap();
+    assert!(!cleared.completed);
+    assert!(cleared.labels.is_empty());
+    assert_eq!(4, cleared.version);
+}
+
+async fn replace_errors<R: TodoRepository>(repository: &R, user_id: i32) {
+    let (a, _) = labels(repository, user_id).await;
+    let todo = repository
+        .create(
+            user_id,
+            CreateTodo::new("[replace] errors".to_string(), vec![a.id]),
+        )
+        .await
+        .unwrap();
+    let payload = ReplaceTodo::new("[replace] errors".to_string(), true);
+
+    // 存在しないidでは作成しない
+    assert_error(
+        repository
+            .replace(user_id, MISSING_TODO, payload.clone())
+            .await,
+        RepositoryError::NotFound(Some(MISSING_TODO)),
+        "[replace] missing todo",
+    );
+    assert_error(
+        repository.find(user_id, MISSING_TODO).await,
+        RepositoryError::NotFound(Some(MISSING_TODO)),
+        "[replace] missing todo stays missing",
+    );
+    assert_error(
+        repository
+            .replace(user_id + 1, todo.id, payload.clone())
+            .await,
+        RepositoryError::NotFound(Some(todo.id)),
+        "[replace] other user's todo",
+    );
+    assert_error(
+        repository
+            .replace(
+                user_id,
+                todo.id,
+                payload.clone().with_version(todo.version + 1),
+            )
+            .await,
+        RepositoryError::Conflict(todo.id),
+        "[replace] stale version",
+    );
+    assert_error(
+        repository
+            .replace(
+                user_id,
+                todo.id,
+                payload
+                    .clone()
+                    .with_precondition(Precondition::default().with_version(todo.version + 1)),
+            )
+            .await,
+        RepositoryError::PreconditionFailed(todo.id),
+        "[replace] precondition",
+    );
+    assert_error(
+        repository
+            .replace(user_id, todo.id, payload.with_labels(vec![MISSING]))
+            .await,
+        RepositoryError::InvalidLabel(MISSING),
+        "[replace] unknown label",
+    );
+    // 失敗した置き換えは何も変更しない
+    assert_eq!(todo, repository.find(user_id, todo.id).await.unwrap());
+}
+
+async fn unknown_label_creates_nothing<R: TodoRepository>(repository: &R, user_id: i32) {
+    let (a, _) = labels(repository, user_id).await;
+    assert_error(
+        repository
+            .create(
+                user_id,
+                CreateTodo::new("[unknown label] create".to_string(), vec![a.id, MISSING]),
+            )
+            .await,
+        RepositoryError::InvalidLabel(MISSING),
+        "[create] unknown label",
+    );
+    assert_error(
+        repository
+            .create_many(
+                user_id,
+                vec![
+                    CreateTodo::new("[unknown label] first".to_string(), vec![a.id]),
+                    CreateTodo::new("[unknown label] second".to_string(), vec![MISSING]),
+                ],
+            )
+            .await,
+        // 一括作成ではラベルの不正を404として返す
+        RepositoryError::NotFound(Some(MISSING.into())),
+        "[create_many] unknown label",
+    );
+    let page = repository
+        .all(user_id, tagged("[unknown label]"))
+        .await
+        .unwrap();
+    assert_eq!(0, page.total);
+}
+
+async fn update_many_reports_missing<R: TodoRepository>(repository: &R, user_id: i32) {
+    let created = repository
+        .create_many(
+            user_id,
+            vec![
+                CreateTodo::new("[update_many] first".to_string(), vec![]),
+                CreateTodo::new("[update_many] second".to_string(), vec![]),
+            ],
+        )
+        .await
+        .expect("[create_many] returned Err");
+    assert_eq!(2, created.len());
+
+    let updated = repository
+        .update_many(
+            user_id,
+            UpdateTodos::new(
+                vec![MISSING_TODO, created[1].id, created[0].id],
+                None,
+                Some(true),
+            ),
+        )
+        .await
+        .expect("[update_many] returned Err");
+    assert_eq!(ids(&created), ids(&updated.todos));
+    assert!(updated
+        .todos
+        .iter()
+        .all(|todo| todo.completed && todo.version == 2));
+    assert_eq!(vec![MISSING_TODO], updated.missing);
+
+    let updated = repository
+        .update_many(
+            user_id + 1,
+            UpdateTodos::new(vec![created[0].id], Some("stolen".to_string()), None),
+        )
+        .await
+        .unwrap();
+    assert!(updated.todos.is_empty());
+    assert_eq!(vec![created[0].id], updated.missing);
+}
+
+async fn delete_and_restore<R: TodoRepository>(repository: &R, user_id: i32) {
+    let todo = repository
+        .create(
+            user_id,
+            CreateTodo::new("[delete] text".to_string(), vec![]),
+        )
+        .await
+        .unwrap();
+    assert_error(
+        repository
+            .delete(user_id, todo.id, Precondition::default().with_version(2))
+            .await,
+        RepositoryError::PreconditionFailed(todo.id),
+        "[delete] precondition",
+    );
+    assert_error(
+        repository
+            .delete(user_id, MISSING_TODO, Precondition::default())
+            .await,
+        RepositoryError::NotFound(Some(MISSING_TODO)),
+        "[delete] missing todo",
+    );
+    repository
+        .delete(user_id, todo.id, Precondition::default().with_version(1))
+        .await
+        .expect("[delete] returned Err");
+    assert_error(
+        repository.find(user_id, todo.id).await,
+        RepositoryError::NotFound(Some(todo.id)),
+        "[find] deleted todo",
+    );
+    // ゴミ箱内のTodoを再び削除することはできない
+    assert_error(
+        repository
+            .delete(user_id, todo.id, Precondition::default())
+            .await,
+        RepositoryError::NotFound(Some(todo.id)),
+        "[delete] deleted todo",
+    );
+    assert_eq!(
+        vec![todo.id],
+        ids(&repository.trash(user_id).await.unwrap())
+    );
+
+    assert_error(
+        repository.restore(user_id + 1, todo.id).await,
+        RepositoryError::NotFound(Some(todo.id)),
+        "[restore] other user's todo",
+    );
+    let restored = repository
+        .restore(user_id, todo.id)
+        .await
+        .expect("[restore] returned Err");
+    assert_eq!(None, restored.deleted_at);
+    assert_eq!(2, restored.version);
+    assert_eq!(restored, repository.find(user_id, todo.id).await.unwrap());
+    assert_error(
+        repository.restore(user_id, todo.id).await,
+        RepositoryError::NotFound(Some(todo.id)),
+        "[restore] todo not in trash",
+    );
+    assert!(repository.trash(user_id).await.unwrap().is_empty());
+}
+
+async fn delete_permanently<R: TodoRepository>(repository: &R, user_id: i32) {
+    let (a, _) = labels(repository, user_id).await;
+    let todo = repository
+        .create(
+            user_id,
+            CreateTodo::new("[purge] text".to_string(), vec![a.id]),
+        )
+        .await
+        .unwrap();
+    assert_error(
+        repository.delete_permanently(user_id + 1, todo.id).await,
+        RepositoryError::NotFound(Some(todo.id)),
+        "[delete_permanently] other user's todo",
+    );
+    // ゴミ箱内のTodoも完全に削除できる
+    repository
+        .delete(user_id, todo.id, Precondition::default())
+        .await
+        .unwrap();
+    repository
+        .delete_permanently(user_id, todo.id)
+        .await
+        .expect("[delete_permanently] returned Err");
+    assert!(repository.trash(user_id).await.unwrap().is_empty());
+    assert_error(
+        repository.delete_permanently(user_id, todo.id).await,
+        RepositoryError::NotFound(Some(todo.id)),
+        "[delete_permanently] twice",
+    );
+    assert_error(
+        repository.restore(user_id, todo.id).await,
+        RepositoryError::NotFound(Some(todo.id)),
+        "[restore] purged todo",
+    );
+}
+
+// DBの実装は実際の時刻を使うため、値そのものではなく変更した日時を下回らないことを確かめる
+async fn last_modified_includes_trash<R: TodoRepository>(repository: &R, user_id: i32) {
+    assert_eq!(None, repository.last_modified(MISSING).await.unwrap());
+    let todo = repository
+        .create(
+            user_id,
+            CreateTodo::new("[last_modified] text".to_string(), vec![]),
+        )
+        .await
+        .unwrap();
+    let created = repository.last_modified(user_id).await.unwrap().unwrap();
+    assert!(created >= todo.updated_at, "[last_modified] create");
+
+    let updated = repository
+        .update(user_id, todo.id, UpdateTodo::default().with_completed(true))
+        .await
+        .unwrap();
+    let last_modified = repository.last_modified(user_id).await.unwrap().unwrap();
+    assert!(
+        last_modified >= updated.updated_at,
+        "[last_modified] update"
+    );
+    assert!(last_modified >= created, "[last_modified] update");
+
+    repository
+        .delete(user_id, todo.id, Precondition::default())
+        .await
+        .unwrap();
+    let trashed = repository.trash(user_id).await.unwrap();
+    let deleted_at = trashed
+        .iter()
+        .find(|trashed| trashed.id == todo.id)
+        .and_then(|trashed| trashed.deleted_at)
+        .unwrap();
+    let last_modified = repository.last_modified(user_id).await.unwrap().unwrap();
+    assert!(last_modified >= deleted_at, "[last_modified] delete");
+}
+
+// 完全な削除や並べ替えはupdated_atを変えないが、一覧は変わるため変更日時を進める
+// 実際の時刻で前後を比べられるよう、変更する前に少し待つ
+async fn last_modified_advances_without_updated_at<R: TodoRepository>(
+    repository: &R,
+    user_id: i32,
+) {
+    let mut todos = vec![];
+    for n in 0..2 {
+        let todo = repository
+            .create(user_id, CreateTodo::new(format!("[advance] {}", n), vec![]))
+            .await
+            .unwrap();
+        todos.push(todo);
+    }
+    let pause = || tokio::time::sleep(std::time::Duration::from_millis(10));
+
+    let before = repository.last_modified(user_id).await.unwrap().unwrap();
+    pause().await;
+    repository
+        .delete_permanently(user_id, todos[1].id)
+        .await
+        .unwrap();
+    let deleted = repository.last_modified(user_id).await.unwrap().unwrap();
+    assert!(deleted > before, "[last_modified] delete_permanently");
+
+    pause().await;
+    repository
+        .move_todo(user_id, todos[0].id, MoveTarget::Top)
+        .await
+        .unwrap();
+    let moved = repository.last_modified(user_id).await.unwrap().unwrap();
+    assert!(moved > deleted, "[last_modified] move_todo");
+}
+
+async fn paginate<R: TodoRepository>(repository: &R, user_id: i32) {
+    let mut created = vec![];
+    for n in 0..5 {
+        let todo = repository
+            .create(user_id, CreateTodo::new(format!("[page] {}", n), vec![]))
+            .await
+            .unwrap();
+        created.push(todo.id);
+    }
+
+    let mut seen = vec![];
+    for offset in [0, 2, 4] {
+        let page = repository
+            .all(
+                user_id,
+                TodoListQuery {
+                    limit: Some(2),
+                    offset: Some(offset),
+                    ..tagged("[page]")
+                },
+            )
+            .await
+            .expect("[all] returned Err");
+        // totalはページングする前の件数
+        assert_eq!(5, page.total, "offset {}", offset);
+        seen.extend(ids(&page.todos));
+    }
+    assert_eq!(created, seen);
+
+    let page = repository
+        .all(
+            user_id,
+            TodoListQuery {
+                offset: Some(5),
+                ..tagged("[page]")
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!((5, vec![]), (page.total, ids(&page.todos)));
+
+    // 並び順を指定しない場合は新しいものが先頭
+    let page = repository
+        .all(
+            user_id,
+            TodoListQuery {
+                q: Some("[page]".to_string()),
+                ..TodoListQuery::default()
+            },
+        )
+        .await
+        .unwrap();
+    created.reverse();
+    assert_eq!(created, ids(&page.todos));
+}
+
+async fn filter_and_sort<R: TodoRepository>(repository: &R, user_id: i32) {
+    let now = Utc::now();
+    let overdue = repository
+        .create(
+            user_id,
+            CreateTodo::new("[filter] bravo overdue".to_string(), vec![])
+                .with_due_date(now - Duration::days(1))
+                .with_priority(Priority::High),
+        )
+        .await
+        .unwrap();
+    let upcoming = repository
+        .create(
+            user_id,
+            CreateTodo::new("[filter] alpha upcoming".to_string(), vec![])
+                .with_due_date(now + Duration::days(1))
+                .with_priority(Priority::Low),
+        )
+        .await
+        .unwrap();
+    let done = repository
+        .create(
+            user_id,
+            CreateTodo::new("[filter] charlie done".to_string(), vec![]),
+        )
+        .await
+        .unwrap();
+    repository
+        .update(user_id, done.id, UpdateTodo::default().with_completed(true))
+        .await
+        .unwrap();
+    let archived = repository
+        .create(
+            user_id,
+            CreateTodo::new("[filter] delta archived".to_string(), vec![]),
+        )
+        .await
+        .unwrap();
+    repository.archive(user_id, archived.id).await.unwrap();
+
+    // 指定しなかった場合のみ、このケースのTodoへの絞り込みとid順を補う
+    let query = |query: TodoListQuery| TodoListQuery {
+        q: query.q.clone().or_else(|| Some("[filter]".to_string())),
+        sort: query.sort.or(Some(SortField::Id)),
+        order: query.order.or(Some(SortOrder::Asc)),
+        ..query
+    };
+    for (case, query, expected) in [
+        (
+            "all",
+            query(TodoListQuery::default()),
+            vec![overdue.id, upcoming.id, done.id],
+        ),
+        (
+            "include archived",
+            query(TodoListQuery {
+                include_archived: Some(true),
+                ..TodoListQuery::default()
+            }),
+            vec![overdue.id, upcoming.id, done.id, archived.id],
+        ),
+        (
+            "completed",
+            query(TodoListQuery {
+                completed: Some(true),
+                ..TodoListQuery::default()
+            }),
+            vec![done.id],
+        ),
+        (
+            "open",
+            query(TodoListQuery {
+                completed: Some(false),
+                ..TodoListQuery::default()
+            }),
+            vec![overdue.id, upcoming.id],
+        ),
+        (
+            "priority",
+            query(TodoListQuery {
+                priority: Some("high".to_string()),
+                ..TodoListQuery::default()
+            }),
+            vec![overdue.id],
+        ),
+        (
+            "overdue",
+            query(TodoListQuery {
+                overdue: Some(true),
+                ..TodoListQuery::default()
+            }),
+            vec![overdue.id],
+        ),
+        (
+            "due after",
+            query(TodoListQuery {
+                due_after: Some(now),
+                ..TodoListQuery::default()
+            }),
+            vec![upcoming.id],
+        ),
+        (
+            "due before",
+            query(TodoListQuery {
+                due_before: Some(now),
+                ..TodoListQuery::default()
+            }),
+            vec![overdue.id],
+        ),
+        (
+            // 検索は大文字小文字を区別しない
+            "search",
+            query(TodoListQuery {
+                q: Some("[FILTER] BRAVO".to_string()),
+                ..TodoListQuery::default()
+            }),
+            vec![overdue.id],
+        ),
+        (
+            "sort by text",
+            query(TodoListQuery {
+                sort: Some(SortField::Text),
+                order: Some(SortOrder::Desc),
+                ..TodoListQuery::default()
+            }),
+            vec![done.id, overdue.id, upcoming.id],
+        ),
+        (
+            "sort by priority",
+            query(TodoListQuery {
+                sort: Some(SortField::Priority),
+                order: Some(SortOrder::Desc),
+                ..TodoListQuery::default()
+            }),
+            vec![overdue.id, done.id, upcoming.id],
+        ),
+    ] {
+        let page = repository
+            .all(user_id, query)
+            .await
+            .unwrap_or_else(|e| panic!("[all] {} returned Err: {:#}", case, e));
+        assert_eq!(expected, ids(&page.todos), "[all] {}", case);
+        assert_eq!(expected.len() as i64, page.total, "[all] {} total", case);
+    }
+}
+
+async fn attach_and_detach_labels<R: TodoRepository>(repository: &R, user_id: i32) {
+    let (a, b) = labels(repository, user_id).await;
+    let todo = repository
+        .create(
+            user_id,
+            CreateTodo::new("[attach] text".to_string(), vec![b.id]),
+        )
+        .await
+        .unwrap();
+
+    let attached = repository
+        .attach_label(user_id, todo.id, a.id)
+        .await
+        .expect("[attach_label] returned Err");
+    assert_eq!(vec![a.clone(), b.clone()], attached.labels);
+    assert_eq!(2, attached.version);
+    // 付与済みのラベルは重複させない
+    let attached = repository
+        .attach_label(user_id, todo.id, a.id)
+        .await
+        .unwrap();
+    assert_eq!(vec![a.clone(), b.clone()], attached.labels);
+
+    let detached = repository
+        .detach_label(user_id, todo.id, b.id)
+        .await
+        .expect("[detach_label] returned Err");
+    assert_eq!(vec![a.clone()], detached.labels);
+    assert_eq!(
+        detached,
+        repository.find(user_id, todo.id).await.unwrap(),
+        "[detach_label] find after detach"
+    );
+
+    assert_error(
+        repository.attach_label(user_id, todo.id, MISSING).await,
+        RepositoryError::NotFound(Some(MISSING.into())),
+        "[attach_label] missing label",
+    );
+    assert_error(
+        repository.attach_label(user_id + 1, todo.id, a.id).await,
+        RepositoryError::NotFound(Some(todo.id)),
+        "[attach_label] other user's todo",
+    );
+    assert_error(
+        repository.detach_label(user_id, MISSING_TODO, a.id).await,
+        RepositoryError::NotFound(Some(MISSING_TODO)),
+        "[detach_label] missing todo",
+    );
+    assert_error(
+        repository.detach_label(user_id, todo.id, MISSING).await,
+        RepositoryError::NotFound(Some(MISSING.into())),
+        "[detach_label] missing label",
+    );
+}
+
+async fn archive_is_idempotent<R: TodoRepository>(repository: &R, user_id: i32) {
+    let todo = repository
+        .create(
+            user_id,
+            CreateTodo::new("[archive] text".to_string(), vec![]),
+        )
+        .await
+        .unwrap();
+    let archived = repository
+        .archive(user_id, todo.id)
+        .await
+        .expect("[archive] returned Err");
+    assert!(archived.archived_at.is_some());
+    assert_eq!(
+        archived,
+        repository.archive(user_id, todo.id).await.unwrap(),
+        "[archive] twice"
+    );
+    // アーカイブしたTodoも一覧以外からは参照できる
+    assert_eq!(archived, repository.find(user_id, todo.id).await.unwrap());
+
+    let unarchived = repository
+        .unarchive(user_id, todo.id)
+        .await
+        .expect("[unarchive] returned Err");
+    assert_eq!(None, unarchived.archived_at);
+    assert_eq!(
+        unarchived,
+        repository.unarchive(user_id, todo.id).await.unwrap(),
+        "[unarchive] twice"
+    );
+    assert_error(
+        repository.archive(user_id, MISSING_TODO).await,
+        RepositoryError::NotFound(Some(MISSING_TODO)),
+        "[archive] missing todo",
+    );
+    assert_error(
+        repository.unarchive(user_id + 1, todo.id).await,
+        RepositoryError::NotFound(Some(todo.id)),
+        "[unarchive] other user's todo",
+    );
+}
+
+async fn checklist_item_errors<R: TodoRepository>(repository: &R, user_id: i32) {
+    let todo = repository
+        .create(user_id, CreateTodo::new("[items] text".to_string(), vec![]))
+        .await
+        .unwrap();
+    let item = repository
+        .create_item(
+            user_id,
+            todo.id,
+            CreateTodoItem {
+                text: "first".to_string(),
+            },
+        )
+        .await
+        .expect("[create_item] returned Err");
+    assert_eq!(
+        vec![item.clone()],
+        repository.items(user_id, todo.id).await.unwrap()
+    );
+
+    assert_error(
+        repository.items(user_id + 1, todo.id).await,
+        RepositoryError::NotFound(Some(todo.id)),
+        "[items] other user's todo",
+    );
+    assert_error(
+        repository
+            .create_item(
+                user_id,
+                MISSING_TODO,
+                CreateTodoItem {
+                    text: "orphan".to_string(),
+                },
+            )
+            .await,
+        RepositoryError::NotFound(Some(MISSING_TODO)),
+        "[create_item] missing todo",
+    );
+    assert_error(
+        repository
+            .update_item(user_id, todo.id, MISSING, UpdateTodoItem::default())
+            .await,
+        RepositoryError::NotFound(Some(MISSING.into())),
+        "[update_item] missing item",
+    );
+    repository
+        .delete_item(user_id, todo.id, item.id)
+        .await
+        .expect("[delete_item] returned Err");
+    assert_error(
+        repository.delete_item(user_id, todo.id, item.id).await,
+        RepositoryError::NotFound(Some(item.id.into())),
+        "[delete_item] twice",
+    );
+}
+
+async fn duplicate_and_move_errors<R: TodoRepository>(repository: &R, user_id: i32) {
+    let todo = repository
+        .create(user_id, CreateTodo::new("[move] text".to_string(), vec![]))
+        .await
+        .unwrap();
+    assert_error(
+        repository
+            .duplicate(user_id, MISSING_TODO, DuplicateTodo::default())
+            .await,
+        RepositoryError::NotFound(Some(MISSING_TODO)),
+        "[duplicate] missing todo",
+    );
+    assert_error(
+        repository
+            .move_todo(user_id, MISSING_TODO, MoveTarget::Top)
+            .await,
+        RepositoryError::NotFound(Some(MISSING_TODO)),
+        "[move_todo] missing todo",
+    );
+    assert_error(
+        repository
+            .move_todo(user_id, todo.id, MoveTarget::After(MISSING_TODO))
+            .await,
+        RepositoryError::NotFound(Some(MISSING_TODO)),
+        "[move_todo] missing anchor",
+    );
+    let moved = repository
+        .move_todo(user_id, todo.id, MoveTarget::Top)
+        .await
+        .expect("[move_todo] returned Err");
+    assert_eq!(todo.id, moved.id);
+}
@@ -0,0 +1,229 @@
+use chrono::{DateTime, Duration, DurationRound, Utc};
+
+use crate::repositories::backup::{Backup, BackupAssociation, BackupLabel, BackupTodo};
+use crate::repositories::label::DEFAULT_LABEL_COLOR;
+use crate::repositories::todo::{
+    Precondition, Priority, SortField, SortOrder, TodoEntity, TodoListQuery, TodoRepository,
+};
+
+// テスト用のTodoを完了状態・ラベル・日時まで指定して登録する
+// createでは作成日時を指定できないため、importで取り込む。ラベルは名前で指定し、なければ作成する
+#[derive(Debug, Clone)]
+pub struct TodoFixture {
+    user_id: i32,
+    text: String,
+    completed: bool,
+    priority: Priority,
+    labels: Vec<String>,
+    due_in_days: Option<i64>,
+    created_days_ago: i64,
+    archived: bool,
+    deleted: bool,
+}
+
+impl TodoFixture {
+    pub fn new(text: &str) -> Self {
+        Self {
+            user_id: 1,
+            text: text.to_string(),
+            completed: false,
+            priority: Priority::default(),
+            labels: vec![],
+            due_in_days: None,
+            created_days_ago: 0,
+            archived: false,
+            deleted: false,
+        }
+    }
+
+    // 省略した場合はuser_id 1(ルーターのテストのトークンと同じユーザー)
+    pub fn owned_by(self, user_id: i32) -> Self {
+        Self { user_id, ..self }
+    }
+
+    pub fn completed(self) -> Self {
+        Self {
+            completed: true,
+            ..self
+        }
+    }
+
+    pub fn priority(self, priority: Priority) -> Self {
+        Self { priority, ..self }
+    }
+
+    pub fn with_label(mut self, name: &str) -> Self {
+        self.labels.push(name.to_string());
+        self
+    }
+
+    // 負の値を指定すると期限切れになる
+    pub fn due_in_days(self, days: i64) -> Self {
+        Self {
+            due_in_days: Some(days),
+            ..self
+        }
+    }
+
+    pub fn created_days_ago(self, days: i64) -> Self {
+        Self {
+            created_days_ago: days,
+            ..self
+        }
+    }
+
+    pub fn archived(self) -> Self {
+        Self {
+            archived: true,
+            ..self
+        }
+    }
+
+    pub fn deleted(self) -> Self {
+        Self {
+            deleted: true,
+            ..self
+        }
+    }
+
+    // 登録したTodoを返す。ゴミ箱へ移した場合はゴミ箱内のTodoを返す
+    pub async fn insert<T: TodoRepository>(self, repository: &T) -> TodoEntity {
+        let now = now();
+        let created_at = now - Duration::days(self.created_days_ago);
+        let backup = Backup {
+            labels: self
+                .labels
+                .iter()
+                .zip(1..)
+                .map(|(name, id)| BackupLabel {
+                    id,
+                    name: name.clone(),
+                    color: DEFAULT_LABEL_COLOR.to_string(),
+                    description: None,
+                })
+                .collect(),
+            associations: (1..=self.labels.len() as i32)
+                .map(|label_id| BackupAssociation {
+                    todo_id: 1,
+                    label_id,
+                })
+                .collect(),
+            todos: vec![BackupTodo {
+                id: 1,
+                text: self.text.clone(),
+                completed: self.completed,
+                priority: self.priority,
+                due_date: self.due_in_days.map(|days| now + Duration::days(days)),
+                created_at,
+                updated_at: created_at,
+            }],
+        };
+        repository
+            .import(self.user_id, backup)
+            .await
+            .unwrap_or_else(|e| panic!("failed insert fixture [{}]: {:#}", self.text, e));
+
+        // importはidを返さないため、採番されたばかりの最大のidを引く
+        let query = TodoListQuery {
+            limit: Some(1),
+            sort: Some(SortField::Id),
+            order: Some(SortOrder::Desc),
+            ..TodoListQuery::default()
+        };
+        let todo = repository
+            .all(self.user_id, query)
+            .await
+            .expect("failed find inserted fixture")
+            .todos
+            .remove(0);
+        if self.archived {
+            repository
+                .archive(self.user_id, todo.id)
+                .await
+                .expect("failed archive fixture");
+        }
+        if !self.deleted {
+            return repository
+                .find(self.user_id, todo.id)
+                .await
+                .expect("failed find inserted fixture");
+        }
+        repository
+            .delete(self.user_id, todo.id, Precondition::default())
+            .await
+            .expect("failed delete fixture");
+        repository
+            .trash(self.user_id)
+            .await
+            .expect("failed find trashed fixture")
+            .into_iter()
+            .find(|trashed| trashed.id == todo.id)
+            .expect("trashed fixture not found")
+    }
+}
+
+// DBに保存しても精度が落ちないよう秒未満を切り捨てる
+fn now() -> DateTime<Utc> {
+    Utc::now().duration_trunc(Duration::seconds(1)).unwrap()
+}
+
+// 一覧・絞り込み・集計のテストで共通に使うデータ。状態・ラベル・期限が一通りそろう
+#[derive(Debug)]
+pub struct Scenario {
+    // 未完了・work・高優先度・3日後が期限
+    pub report: TodoEntity,
+    // 未完了・home・高優先度・期限切れ
+    pub rent: TodoEntity,
+    // 完了済み・homeとerrand・低優先度
+    pub milk: TodoEntity,
+    // 未完了・ラベルなし・30日前に作成
+    pub dentist: TodoEntity,
+    // 完了済み・work・アーカイブ済み
+    pub receipts: TodoEntity,
+    // ゴミ箱内
+    pub gym: TodoEntity,
+}
+
+pub async fn scenario<T: TodoRepository>(repository: &T, user_id: i32) -> Scenario {
+    Scenario {
+        report: TodoFixture::new("write the quarterly report")
+            .owned_by(user_id)
+            .with_label("work")
+            .priority(Priority::High)
+            .due_in_days(3)
+            .insert(repository)
+            .await,
+        rent: TodoFixture::new("pay rent")
+            .owned_by(user_id)
+            .with_label("home")
+            .priority(Priority::High)
+            .due_in_days(-1)
+            .insert(repository)
+            .await,
+        milk: TodoFixture::new("buy milk")
+            .owned_by(user_id)
+            .completed()
+            .with_label("home")
+            .with_label("errand")
+            .priority(Priority::Low)
+            .insert(repository)
+            .await,
+        dentist: TodoFixture::new("call the dentist")
+            .owned_by(user_id)
+            .created_days_ago(30)
+            .insert(repository)
+            .await,
+        receipts: TodoFixture::new("file old receipts")
+            .owned_by(user_id)
+            .completed()
+            .with_label("work")
+            .archived()
+            .insert(repository)
+            .await,
+        gym: TodoFixture::new("cancel gym membership")
+            .owned_by(user_id)
+            .deleted()
+            .insert(repository)
+            .await,
+    }
+}
@@ -1,565 +1,5420 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use async_graphql::Enum;
 use axum::async_trait;
-use serde::{Deserialize, Serialize};
-use sqlx::{FromRow, PgPool};
-use validator::Validate;
+use chrono::{DateTime, Duration, Utc};
+use futures_util::future::BoxFuture;
+use futures_util::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use serde::{Deserialize, Deserializer, Serialize};
+use sqlx::types::Json;
+use sqlx::{FromRow, PgPool, Postgres, Transaction};
+use tokio::sync::mpsc;
+use tracing::instrument;
+use unicode_normalization::UnicodeNormalization;
+use utoipa::{IntoParams, ToSchema};
+use uuid::Uuid;
+use validator::{Validate, ValidationError};
+
+use crate::clock::{Clock, SharedClock};
+use crate::events::TodoEvent;
+use crate::repositories::backup::{Backup, ImportSummary};
+use crate::repositories::label::{Label, DEFAULT_LABEL_COLOR};
+use crate::repositories::outbox::record_events;
+use crate::repositories::todo_activity::{TodoActivity, TodoActivityPage, TodoChange};
+use crate::repositories::todo_item::{move_item, CreateTodoItem, TodoItem, UpdateTodoItem};
+
+use super::{deserialize_present, PageQuery, RepositoryError};
+
+mod memory;
+pub use memory::{TodoRepositoryForMemory, TodoSnapshot};
 
-use crate::repositories::label::Label;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "sqlite")]
+pub use sqlite::TodoRepositoryForSqlite;
 
-use super::RepositoryError;
+const FOREIGN_KEY_VIOLATION: &str = "23503";
+// ストリーミング時に先読みするTodoの件数。受信側が遅い場合はここで読み込みが止まる
+const STREAM_BUFFER: usize = 64;
+// 新しいTodoはユーザーのTodoの先頭に置く
+const INSERT_TODO: &str = r#"
+insert into todos (text, completed, user_id, due_date, priority, recurrence, remind_at,
+                   created_at, updated_at, position, client_id)
+values ($1, false, $2, $3, $4, $6, $7, $8, $8,
+        coalesce((select max(position) from todos where user_id = $2), 0) + $5, $9)
+returning *
+"#;
 
 #[derive(Debug, Clone, PartialEq, Eq, FromRow)]
 struct TodoFromRow {
-    id: i32,
+    id: i64,
     text: String,
     completed: bool,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, FromRow)]
 pub struct TodoWithLabelFromRow {
-    id: i32,
+    id: i64,
     text: String,
     completed: bool,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    deleted_at: Option<DateTime<Utc>>,
+    archived_at: Option<DateTime<Utc>>,
+    version: i32,
+    due_date: Option<DateTime<Utc>>,
+    priority: Priority,
+    recurrence: Option<Json<Recurrence>>,
+    next_occurrence_id: Option<i64>,
+    remind_at: Option<DateTime<Utc>>,
+    reminded_at: Option<DateTime<Utc>>,
+    client_id: Option<Uuid>,
     label_id: Option<i32>,
     label_name: Option<String>,
+    label_color: Option<String>,
+    label_description: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
 pub struct TodoEntity {
-    pub id: i32,
+    pub id: i64,
+    // オフラインのクライアントが採番したid。指定せずに作成した場合はnull
+    #[schema(value_type = Option<String>, format = "uuid")]
+    pub client_id: Option<Uuid>,
     pub text: String,
     pub completed: bool,
     pub labels: Vec<Label>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    // アーカイブしたTodoは既定の一覧に現れないが、完了状態やゴミ箱とは独立している
+    pub archived_at: Option<DateTime<Utc>>,
+    pub version: i32,
+    pub due_date: Option<DateTime<Utc>>,
+    pub priority: Priority,
+    pub recurrence: Option<Recurrence>,
+    // 完了時に作成された次の回のid
+    pub next_occurrence_id: Option<i64>,
+    pub remind_at: Option<DateTime<Utc>>,
+    // 通知済みの場合に通知した日時。remind_atを変更すると消える
+    pub reminded_at: Option<DateTime<Utc>>,
+}
+
+impl TodoEntity {
+    pub fn new(id: i64, text: String, labels: Vec<Label>) -> Self {
+        Self {
+            id,
+            client_id: None,
+            text,
+            completed: false,
+            labels,
+            created_at: DateTime::<Utc>::MIN_UTC,
+            updated_at: DateTime::<Utc>::MIN_UTC,
+            deleted_at: None,
+            archived_at: None,
+            version: 1,
+            due_date: None,
+            priority: Priority::Medium,
+            recurrence: None,
+            next_occurrence_id: None,
+            remind_at: None,
+            reminded_at: None,
+        }
+    }
+}
+
+impl TodoWithLabelFromRow {
+    // left outer joinのため、ラベルが紐づかない行ではlabel_*がすべてnullになる
+    fn label(&self) -> Option<Label> {
+        Some(Label {
+            id: self.label_id?,
+            name: self.label_name.clone()?,
+            color: self.label_color.clone()?,
+            description: self.label_description.clone(),
+        })
+    }
 }
 
 fn fold_entities(rows: Vec<TodoWithLabelFromRow>) -> Vec<TodoEntity> {
-    let mut rows = rows.iter();
+    merge_todo_rows(rows.into_iter().map(TodoEntity::from))
+}
+
+// ラベルとjoinした行から作った、ラベルを高々1つ持つTodoをTodoごとに1件へまとめる
+// 行の型から切り離しているため、DBなしでもベンチマークできる
+// Todo件数分の線形探索を避けるため、idから位置を引いて1パスで組み立てる
+pub fn merge_todo_rows(todos: impl IntoIterator<Item = TodoEntity>) -> Vec<TodoEntity> {
     let mut accum: Vec<TodoEntity> = vec![];
-    'outer: while let Some(row) = rows.next() {
-        let mut todos = accum.iter_mut();
-        while let Some(todo) = todos.next() {
-            // idが一致＝Todoに紐づくラベルが複数存在している
-            if todo.id == row.id {
-                todo.labels.push(Label {
-                    id: row.label_id.unwrap(),
-                    name: row.label_name.clone().unwrap(),
-                });
-                continue 'outer;
-            }
+    let mut positions: HashMap<i64, usize> = HashMap::new();
+    for todo in todos {
+        // idが一致＝Todoに紐づくラベルが複数存在している
+        if let Some(&position) = positions.get(&todo.id) {
+            accum[position].labels.extend(todo.labels);
+            continue;
         }
 
-        // Todoのidに一致がなかった時のみ到達、TodoEntityを作成
-        let labels = if row.label_id.is_some() {
-            vec![Label {
-                id: row.label_id.unwrap(),
-                name: row.label_name.clone().unwrap(),
-            }]
-        } else {
-            vec![]
-        };
+        // 初めて現れたTodoのみ残し、行の並び順を保つ
+        positions.insert(todo.id, accum.len());
+        accum.push(todo);
+    }
+    // joinの順序は保証されないため、ラベルはどの問い合わせでもidの順に揃える
+    for todo in accum.iter_mut() {
+        todo.labels.sort_by_key(|label| label.id);
+    }
+    accum
+}
 
-        accum.push(TodoEntity {
+impl From<TodoWithLabelFromRow> for TodoEntity {
+    fn from(row: TodoWithLabelFromRow) -> Self {
+        Self {
+            labels: row.label().into_iter().collect(),
             id: row.id,
-            text: row.text.clone(),
+            client_id: row.client_id,
+            text: row.text,
             completed: row.completed,
-            labels,
-        });
+            created_at: row.created_at,
+            updated_at: row.updated_at,
+            deleted_at: row.deleted_at,
+            archived_at: row.archived_at,
+            version: row.version,
+            due_date: row.due_date,
+            priority: row.priority,
+            recurrence: row.recurrence.map(|Json(recurrence)| recurrence),
+            next_occurrence_id: row.next_occurrence_id,
+            remind_at: row.remind_at,
+            reminded_at: row.reminded_at,
+        }
     }
-    accum
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
-pub struct CreateTodo {
-    #[validate(length(min = 1, message = "Can not be empty"))]
-    #[validate(length(max = 100, message = "Over text length"))]
-    text: String,
-    labels: Vec<i32>,
-}
+// 見た目では区別できず、貼り付けたテキストに紛れ込みやすい文字。絵文字の結合に使うZWJは残す
+const INVISIBLE_CHARS: [char; 3] = ['\u{200B}', '\u{2060}', '\u{FEFF}'];
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate)]
-pub struct UpdateTodo {
-    #[validate(length(min = 1, message = "Can not be empty"))]
-    #[validate(length(max = 100, message = "Over text length"))]
-    text: Option<String>,
-    completed: Option<bool>,
-    labels: Option<Vec<i32>>,
+// 前後の空白を除き、改行・タブを含む連続した空白を1つの半角スペースにまとめ、NFCへ正規化する
+// 全角スペースは意図して入力されることが多いため、1文字だけの場合はそのまま残す
+pub fn normalize_text(text: &str) -> String {
+    let mut normalized = String::with_capacity(text.len());
+    let mut blank = String::new();
+    for c in text.nfc().filter(|c| !INVISIBLE_CHARS.contains(c)) {
+        if c.is_whitespace() {
+            blank.push(c);
+            continue;
+        }
+        if !blank.is_empty() {
+            if !normalized.is_empty() {
+                if blank == "\u{3000}" {
+                    normalized.push('\u{3000}');
+                } else {
+                    normalized.push(' ');
+                }
+            }
+            blank.clear();
+        }
+        normalized.push(c);
+    }
+    normalized
 }
 
-#[async_trait]
-pub trait TodoRepository: Clone + Send + Sync + 'static {
-    async fn create(&self, payload: CreateTodo) -> anyhow::Result<TodoEntity>;
-    async fn find(&self, id: i32) -> anyhow::Result<TodoEntity>;
-    async fn all(&self) -> anyhow::Result<Vec<TodoEntity>>;
-    async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<TodoEntity>;
-    async fn delete(&self, id: i32) -> anyhow::Result<()>;
+// 検証の前に正規化し、空白のみのテキストや文字数の上限を正規化後の値で判定する
+fn deserialize_text<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    String::deserialize(deserializer).map(|text| normalize_text(&text))
 }
 
-#[derive(Debug, Clone)]
-pub struct TodoRepositoryForDb {
-    pool: PgPool,
+fn deserialize_optional_text<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer).map(|text| text.map(|text| normalize_text(&text)))
 }
 
-impl TodoRepositoryForDb {
-    pub fn new(pool: PgPool) -> Self {
-        TodoRepositoryForDb { pool }
+// 空白のみのテキストも空文字として扱う
+pub fn validate_not_blank(text: &str) -> Result<(), ValidationError> {
+    if text.trim().is_empty() {
+        let mut error = ValidationError::new("blank");
+        error.message = Some("Can not be empty".into());
+        return Err(error);
     }
+    Ok(())
 }
 
-#[async_trait]
-impl TodoRepository for TodoRepositoryForDb {
-    async fn create(&self, payload: CreateTodo) -> anyhow::Result<TodoEntity> {
-        let tx = self.pool.begin().await?;
-        let row = sqlx::query_as::<_, TodoFromRow>(
-            "insert into todos (text, completed) values ($1, false) returning *",
-        )
-        .bind(payload.text.clone())
-        .fetch_one(&self.pool)
-        .await?;
+#[derive(
+    Debug,
+    Serialize,
+    Deserialize,
+    Clone,
+    Copy,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    sqlx::Type,
+    ToSchema,
+    Enum,
+)]
+#[serde(rename_all = "lowercase")]
+#[repr(i16)]
+pub enum Priority {
+    Low = 1,
+    #[default]
+    Medium = 2,
+    High = 3,
+}
 
-        sqlx::query(
-            "insert into todo_labels (todo_id, label_id) select $1, id from unnest($2) as t(id)",
-        )
-        .bind(row.id)
-        .bind(payload.labels)
-        .execute(&self.pool)
-        .await?;
+impl Priority {
+    pub const ALL: [Priority; 3] = [Priority::Low, Priority::Medium, Priority::High];
 
-        tx.commit().await?;
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Medium => "medium",
+            Priority::High => "high",
+        }
+    }
+}
 
-        let todo = self.find(row.id).await?;
-        Ok(todo)
+impl fmt::Display for Priority {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
     }
+}
 
-    async fn find(&self, id: i32) -> anyhow::Result<TodoEntity> {
-        let items = sqlx::query_as::<_, TodoWithLabelFromRow>(
-            r#"
-select todos.*, labels.id as label_id, labels.name as label_name
-from todos
-left outer join todo_labels tl on todos.id = tl.todo_id
-left outer join labels on labels.id = tl.label_id
-where todos.id=$1;
-"#,
-        )
-        .bind(id)
-        .fetch_all(&self.pool)
-        .await
-        .map_err(|e| match e {
-            sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
-            _ => RepositoryError::Unexpected(e.to_string()),
-        })?;
+impl FromStr for Priority {
+    type Err = ();
 
-        let todos = fold_entities(items);
-        let todo = todos.first().ok_or(RepositoryError::NotFound(id))?;
-        Ok(todo.clone())
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Priority::ALL
+            .into_iter()
+            .find(|priority| priority.as_str() == s)
+            .ok_or(())
     }
+}
 
-    async fn all(&self) -> anyhow::Result<Vec<TodoEntity>> {
-        let items = sqlx::query_as::<_, TodoWithLabelFromRow>(
-            r#"
-select todos.*, labels.id as label_id, labels.name as label_name
-from todos
-left outer join todo_labels tl on todos.id = tl.todo_id
-left outer join labels on labels.id = tl.label_id
-order by todos.id desc;
-"#,
-        )
-        .fetch_all(&self.pool)
-        .await?;
-        Ok(fold_entities(items))
+// 不正な値を400ではなく422として返すため、文字列で受け取ってからvalidatorで検証する
+fn validate_priority(priority: &str) -> Result<(), ValidationError> {
+    if priority.parse::<Priority>().is_err() {
+        let allowed: Vec<&str> = Priority::ALL.iter().map(Priority::as_str).collect();
+        let mut error = ValidationError::new("priority");
+        error.message = Some(format!("Must be one of {}", allowed.join(", ")).into());
+        return Err(error);
     }
+    Ok(())
+}
 
-    async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<TodoEntity> {
-        let tx = self.pool.begin().await?;
-
-        let old_todo = self.find(id).await?;
-        sqlx::query("update todos set text = $1, completed = $2 where id = $3 returning *")
-            .bind(payload.text.unwrap_or(old_todo.text))
-            .bind(payload.completed.unwrap_or(old_todo.completed))
-            .bind(id)
-            .fetch_one(&self.pool)
-            .await?;
-
-        if let Some(labels) = payload.labels {
-            // 一度関連するレコードを削除
-            sqlx::query("delete from todo_labels where todo_id=$1")
-                .bind(id)
-                .execute(&self.pool)
-                .await?;
+fn parse_priority(priority: Option<&str>) -> Option<Priority> {
+    priority.and_then(|priority| priority.parse().ok())
+}
 
-            sqlx::query(" insert into todo_labels (todo_id, label_id) select $1, id from unnest($2) as t(id)")
-            .bind(id)
-            .bind(labels)
-            .execute(&self.pool)
-            .await?;
-        };
+// 完了すると、期限を進めた次の回を作成する
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Recurrence {
+    Daily,
+    Weekly,
+    EveryNDays { days: u16 },
+}
 
-        tx.commit().await?;
-        let todo = self.find(id).await?;
+impl Recurrence {
+    pub const MAX_DAYS: u16 = 365;
 
-        Ok(todo)
+    fn interval(&self) -> Duration {
+        match self {
+            Recurrence::Daily => Duration::days(1),
+            Recurrence::Weekly => Duration::weeks(1),
+            Recurrence::EveryNDays { days } => Duration::days(i64::from(*days)),
+        }
     }
 
-    async fn delete(&self, id: i32) -> anyhow::Result<()> {
-        let tx = self.pool.begin().await?;
-        sqlx::query("delete from todo_labels where todo_id=$1")
-            .bind(id)
-            .execute(&self.pool)
-            .await
-            .map_err(|e| match e {
-                sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
-                _ => RepositoryError::Unexpected(e.to_string()),
-            })?;
-
-        sqlx::query("delete from todos where id=$1")
-            .bind(id)
-            .execute(&self.pool)
-            .await
-            .map_err(|e| match e {
-                sqlx::Error::RowNotFound => RepositoryError::NotFound(id),
-                _ => RepositoryError::Unexpected(e.to_string()),
-            })?;
+    // 期限のないTodoは完了した時点から数える
+    pub fn next_due_date(
+        &self,
+        due_date: Option<DateTime<Utc>>,
+        completed_at: DateTime<Utc>,
+    ) -> DateTime<Utc> {
+        due_date.unwrap_or(completed_at) + self.interval()
+    }
+}
 
-        tx.commit().await?;
+// 未完了から完了へ変更した繰り返しのTodoのうち、次の回が未作成のものの繰り返しを返す
+// 完了を取り消して再度完了した場合も、作成済みであれば次の回を増やさない
+fn recurrence_to_spawn(old: &TodoEntity, new: &TodoEntity) -> Option<Recurrence> {
+    if old.completed || !new.completed || new.next_occurrence_id.is_some() {
+        return None;
+    }
+    new.recurrence
+}
 
-        Ok(())
+fn validate_recurrence(recurrence: &Recurrence) -> Result<(), ValidationError> {
+    if let Recurrence::EveryNDays { days } = recurrence {
+        if *days == 0 || *days > Recurrence::MAX_DAYS {
+            let mut error = ValidationError::new("recurrence");
+            error.message =
+                Some(format!("Days must be between 1 and {}", Recurrence::MAX_DAYS).into());
+            return Err(error);
+        }
     }
+    Ok(())
 }
 
-#[cfg(test)]
-#[cfg(feature = "database-test")]
-mod test {
-    use std::env;
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct CreateTodo {
+    #[validate(custom = "validate_not_blank")]
+    #[validate(length(max = 100, message = "Over text length"))]
+    #[serde(deserialize_with = "deserialize_text")]
+    text: String,
+    labels: Vec<i32>,
+    due_date: Option<DateTime<Utc>>,
+    #[validate(custom = "validate_priority")]
+    #[schema(value_type = Option<Priority>)]
+    priority: Option<String>,
+    #[serde(default)]
+    #[validate(custom = "validate_recurrence")]
+    recurrence: Option<Recurrence>,
+    remind_at: Option<DateTime<Utc>>,
+    // 同じユーザーで使用済みの場合は作成せず、既存のTodoを返す
+    #[serde(default)]
+    #[schema(value_type = Option<String>, format = "uuid")]
+    client_id: Option<Uuid>,
+}
 
-    use dotenv::dotenv;
-    use sqlx::PgPool;
+impl CreateTodo {
+    pub fn new(text: String, labels: Vec<i32>) -> Self {
+        Self {
+            text: normalize_text(&text),
+            labels,
+            due_date: None,
+            priority: None,
+            recurrence: None,
+            remind_at: None,
+            client_id: None,
+        }
+    }
 
-    use super::*;
+    pub fn with_client_id(self, client_id: Uuid) -> Self {
+        Self {
+            client_id: Some(client_id),
+            ..self
+        }
+    }
 
-    #[test]
-    fn fold_entities_test() {
-        let label_1 = Label {
-            id: 1,
-            name: String::from("label 1"),
-        };
-        let label_2 = Label {
-            id: 2,
-            name: String::from("label 2"),
-        };
-        let rows = vec![
-            TodoWithLabelFromRow {
-                id: 1,
-                text: String::from("todo 1"),
-                completed: false,
-                label_id: Some(label_1.id),
-                label_name: Some(label_1.name.clone()),
-            },
-            TodoWithLabelFromRow {
-                id: 1,
-                text: String::from("todo 1"),
-                completed: false,
-                label_id: Some(label_2.id),
-                label_name: Some(label_2.name.clone()),
-            },
-            TodoWithLabelFromRow {
-                id: 2,
-                text: String::from("todo 2"),
-                completed: false,
-                label_id: Some(label_1.id),
-                label_name: Some(label_1.name.clone()),
-            },
-        ];
-        let res = fold_entities(rows);
-        assert_eq!(
-            res,
-            vec![
-                TodoEntity {
-                    id: 1,
-                    text: String::from("todo 1"),
-                    completed: false,
-                    labels: vec![label_1.clone(), label_2.clone()],
-                },
-                TodoEntity {
-                    id: 2,
-                    text: String::from("todo 2"),
-                    completed: false,
-                    labels: vec![label_1.clone()],
-                },
-            ]
-        );
+    pub fn with_due_date(self, due_date: DateTime<Utc>) -> Self {
+        Self {
+            due_date: Some(due_date),
+            ..self
+        }
     }
 
-    #[tokio::test]
-    async fn crud_scenario() {
-        dotenv().ok();
-        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
-        let pool = PgPool::connect(database_url)
-            .await
-            .expect(&format!("fail connect database, url is [{}]", database_url));
+    pub fn with_priority(self, priority: Priority) -> Self {
+        Self {
+            priority: Some(priority.to_string()),
+            ..self
+        }
+    }
 
-        // label data prepare
-        let label_name = String::from("test label");
-        let optional_label = sqlx::query_as::<_, Label>("select * from labels where name = $1")
-            .bind(label_name.clone())
-            .fetch_optional(&pool)
-            .await
-            .expect("Failed to prepare label data.");
-        let label_1 = if let Some(label) = optional_label {
-            label
-        } else {
-            let label =
-                sqlx::query_as::<_, Label>("insert into labels ( name ) values ( $1 ) returning *")
-                    .bind(label_name)
-                    .fetch_one(&pool)
-                    .await
-                    .expect("Failed to insert label data.");
-            label
-        };
+    pub fn text(&self) -> &str {
+        &self.text
+    }
 
-        let repository = TodoRepositoryForDb::new(pool.clone());
-        let todo_text = "[crud_scenario] text";
+    pub fn client_id(&self) -> Option<Uuid> {
+        self.client_id
+    }
 
-        // create
-        let created = repository
-            .create(CreateTodo::new(todo_text.to_string(), vec![label_1.id]))
-            .await
-            .expect("[create] returned Err");
-        assert_eq!(created.text, todo_text);
-        assert!(!created.completed);
-        assert_eq!(*created.labels.first().unwrap(), label_1);
+    pub fn priority(&self) -> Priority {
+        parse_priority(self.priority.as_deref()).unwrap_or_default()
+    }
+}
 
-        // find
-        let todo = repository
-            .find(created.id)
-            .await
-            .expect("[find] returned Err");
-        assert_eq!(created, todo);
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CreateTodoWithLabelNames {
+    pub todo: CreateTodo,
+    pub label_names: Vec<String>,
+}
 
-        // all
-        let todos = repository.all().await.expect("[all] returned Err");
-        let todo = todos.first().unwrap();
-        assert_eq!(created, *todo);
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate, ToSchema)]
+pub struct CreateTodoBatch {
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate]
+    pub todos: Vec<CreateTodo>,
+}
 
-        // update
-        let updated_text = "[crud_scenario] updated text";
-        let todo = repository
-            .update(
-                todo.id,
-                UpdateTodo {
-                    text: Some(updated_text.to_string()),
-                    completed: Some(true),
-                    labels: Some(vec![]),
-                },
-            )
-            .await
-            .expect("[update] returned Err");
-        assert_eq!(created.id, todo.id);
-        assert_eq!(todo.text, updated_text);
-        assert_eq!(todo.labels.len(), 0);
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq, Validate, ToSchema)]
+// クライアントから送る際は、指定しなかった項目を送らない(nullは消す指定になるため)
+// 消すことのできないtext・completed・labels・version・priorityへのnullは、省略と同じく変更しない
+// ただしapplication/merge-patch+jsonのbodyでは、labels・priorityへのnullを空・既定値に読み替えてから受け取る
+#[serde(deny_unknown_fields)]
+pub struct UpdateTodo {
+    #[validate(custom = "validate_not_blank")]
+    #[validate(length(max = 100, message = "Over text length"))]
+    #[serde(
+        default,
+        deserialize_with = "deserialize_optional_text",
+        skip_serializing_if = "Option::is_none"
+    )]
+    text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    completed: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    labels: Option<Vec<i32>>,
+    // 指定した場合、保存済みのversionと一致するときのみ更新する
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<i32>,
+    // nullを指定すると期限を消す
+    #[serde(
+        default,
+        deserialize_with = "deserialize_present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[schema(value_type = Option<DateTime<Utc>>, nullable)]
+    due_date: Option<Option<DateTime<Utc>>>,
+    #[validate(custom = "validate_priority")]
+    #[schema(value_type = Option<Priority>)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    priority: Option<String>,
+    // nullを指定すると繰り返しをやめる
+    #[serde(
+        default,
+        deserialize_with = "deserialize_present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[validate(custom = "validate_recurrence")]
+    #[schema(value_type = Option<Recurrence>, nullable)]
+    recurrence: Option<Option<Recurrence>>,
+    // nullを指定すると通知をやめる。変更した場合は通知済みでも改めて通知する
+    #[serde(
+        default,
+        deserialize_with = "deserialize_present",
+        skip_serializing_if = "Option::is_none"
+    )]
+    #[schema(value_type = Option<DateTime<Utc>>, nullable)]
+    remind_at: Option<Option<DateTime<Utc>>>,
+    // bodyではなくIf-Match・If-Unmodified-Sinceから受け取る
+    #[serde(skip)]
+    precondition: Precondition,
+}
 
-        // delete
-        let _ = repository
-            .delete(todo.id)
-            .await
-            .expect("[delete] returned Err");
-        let res = repository.find(created.id).await; // expect not found err
-        assert!(res.is_err());
+impl UpdateTodo {
+    pub fn with_text(self, text: String) -> Self {
+        Self {
+            text: Some(normalize_text(&text)),
+            ..self
+        }
+    }
 
-        let todo_rows = sqlx::query("select * from todos where id=$1")
-            .bind(todo.id)
-            .fetch_all(&pool)
-            .await
-            .expect("[delete] todo_labels fetch error");
-        assert_eq!(todo_rows.len(), 0);
+    pub fn with_completed(self, completed: bool) -> Self {
+        Self {
+            completed: Some(completed),
+            ..self
+        }
+    }
 
-        let rows = sqlx::query("select * from todo_labels where todo_id=$1")
-            .bind(todo.id)
-            .fetch_all(&pool)
-            .await
-            .expect("[delete] todo_labels fetch error");
-        assert_eq!(rows.len(), 0);
+    pub fn with_labels(self, labels: Vec<i32>) -> Self {
+        Self {
+            labels: Some(labels),
+            ..self
+        }
     }
-}
 
-#[cfg(test)]
-pub mod test_utils {
-    use std::{
-        collections::HashMap,
-        sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
-    };
+    pub fn with_version(self, version: i32) -> Self {
+        Self {
+            version: Some(version),
+            ..self
+        }
+    }
 
-    use anyhow::Context;
-    use axum::async_trait;
+    // Noneを渡すと期限を消す
+    pub fn with_due_date(self, due_date: Option<DateTime<Utc>>) -> Self {
+        Self {
+            due_date: Some(due_date),
+            ..self
+        }
+    }
 
-    use super::*;
+    pub fn with_priority(self, priority: Priority) -> Self {
+        Self {
+            priority: Some(priority.to_string()),
+            ..self
+        }
+    }
 
-    impl TodoEntity {
-        pub fn new(id: i32, text: String, labels: Vec<Label>) -> Self {
-            Self {
-                id,
-                text,
-                completed: false,
-                labels,
-            }
+    pub fn with_precondition(self, precondition: Precondition) -> Self {
+        Self {
+            precondition,
+            ..self
         }
     }
 
-    impl CreateTodo {
-        pub fn new(text: String, labels: Vec<i32>) -> Self {
-            Self { text, labels }
+    pub fn priority(&self) -> Option<Priority> {
+        parse_priority(self.priority.as_deref())
+    }
+
+    pub fn completes(&self) -> bool {
+        self.completed == Some(true)
+    }
+}
+
+// PUTで受け取る置き換え後のTodo。指定しなかった期限・ラベル・繰り返し・通知は消し、優先度は既定に戻す
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate, ToSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ReplaceTodo {
+    #[validate(custom = "validate_not_blank")]
+    #[validate(length(max = 100, message = "Over text length"))]
+    #[serde(deserialize_with = "deserialize_text")]
+    text: String,
+    completed: bool,
+    #[serde(default)]
+    labels: Vec<i32>,
+    due_date: Option<DateTime<Utc>>,
+    #[validate(custom = "validate_priority")]
+    #[schema(value_type = Option<Priority>)]
+    priority: Option<String>,
+    #[serde(default)]
+    #[validate(custom = "validate_recurrence")]
+    recurrence: Option<Recurrence>,
+    remind_at: Option<DateTime<Utc>>,
+    // 指定した場合、保存済みのversionと一致するときのみ置き換える
+    version: Option<i32>,
+    // bodyではなくIf-Match・If-Unmodified-Sinceから受け取る
+    #[serde(skip)]
+    precondition: Precondition,
+}
+
+impl ReplaceTodo {
+    pub fn new(text: String, completed: bool) -> Self {
+        Self {
+            text: normalize_text(&text),
+            completed,
+            labels: vec![],
+            due_date: None,
+            priority: None,
+            recurrence: None,
+            remind_at: None,
+            version: None,
+            precondition: Precondition::default(),
         }
     }
 
-    type TodoDatas = HashMap<i32, TodoEntity>;
+    pub fn with_labels(self, labels: Vec<i32>) -> Self {
+        Self { labels, ..self }
+    }
 
-    #[derive(Debug, Clone)]
-    pub struct TodoRepositoryForMemory {
-        store: Arc<RwLock<TodoDatas>>,
-        labels: Vec<Label>,
+    pub fn with_due_date(self, due_date: DateTime<Utc>) -> Self {
+        Self {
+            due_date: Some(due_date),
+            ..self
+        }
     }
 
-    impl TodoRepositoryForMemory {
-        pub fn new(labels: Vec<Label>) -> Self {
-            TodoRepositoryForMemory {
-                store: Arc::default(),
-                labels,
-            }
+    pub fn with_priority(self, priority: Priority) -> Self {
+        Self {
+            priority: Some(priority.to_string()),
+            ..self
         }
+    }
 
-        fn write_store_ref(&self) -> RwLockWriteGuard<TodoDatas> {
-            self.store.write().unwrap()
+    pub fn with_version(self, version: i32) -> Self {
+        Self {
+            version: Some(version),
+            ..self
         }
+    }
 
-        fn read_store_ref(&self) -> RwLockReadGuard<TodoDatas> {
-            self.store.read().unwrap()
+    pub fn with_precondition(self, precondition: Precondition) -> Self {
+        Self {
+            precondition,
+            ..self
         }
+    }
 
-        fn resolve_labels(&self, labels: Vec<i32>) -> Vec<Label> {
-            let mut label_list = self.labels.iter().cloned();
-            let labels = labels
-                .iter()
-                .map(|id| label_list.find(|label| label.id == *id).unwrap())
-                .collect();
-            labels
+    pub fn priority(&self) -> Priority {
+        parse_priority(self.priority.as_deref()).unwrap_or_default()
+    }
+
+    pub fn completes(&self) -> bool {
+        self.completed
+    }
+}
+
+// すべての項目を指定した更新として扱う。1文で書き込めないストアはupdateで置き換える
+impl From<ReplaceTodo> for UpdateTodo {
+    fn from(payload: ReplaceTodo) -> Self {
+        UpdateTodo {
+            priority: Some(payload.priority().to_string()),
+            text: Some(payload.text),
+            completed: Some(payload.completed),
+            labels: Some(payload.labels),
+            version: payload.version,
+            due_date: Some(payload.due_date),
+            recurrence: Some(payload.recurrence),
+            remind_at: Some(payload.remind_at),
+            precondition: payload.precondition,
         }
     }
+}
+
+// 更新・削除の前提条件。満たさない場合はRepositoryError::PreconditionFailedを返す
+// bodyのversionと異なり、クライアントが読んだ時点から変更されていないことを確かめる
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Precondition {
+    version: Option<i32>,
+    modified_before: Option<DateTime<Utc>>,
+}
 
-    #[async_trait]
-    impl TodoRepository for TodoRepositoryForMemory {
-        async fn create(&self, payload: CreateTodo) -> anyhow::Result<TodoEntity> {
-            let mut store = self.write_store_ref();
-            let id = (store.len() + 1) as i32;
-            let labels = self.resolve_labels(payload.labels);
-            let todo = TodoEntity::new(id, payload.text.clone(), labels);
-            store.insert(id, todo.clone());
-            Ok(todo)
+impl Precondition {
+    pub fn with_version(self, version: i32) -> Self {
+        Self {
+            version: Some(version),
+            ..self
         }
+    }
 
-        async fn find(&self, id: i32) -> anyhow::Result<TodoEntity> {
-            let store = self.read_store_ref();
-            let todo = store
-                .get(&id)
-                .map(|todo| todo.clone())
-                .ok_or(RepositoryError::NotFound(id))?;
-            Ok(todo)
+    // HTTP-dateは秒単位のため、指定した秒の終わりまでに更新されていれば満たす
+    pub fn with_unmodified_since(self, since: DateTime<Utc>) -> Self {
+        Self {
+            modified_before: Some(since + Duration::seconds(1)),
+            ..self
         }
+    }
+
+    pub fn is_none(&self) -> bool {
+        self.version.is_none() && self.modified_before.is_none()
+    }
+
+    pub fn matches(&self, todo: &TodoEntity) -> bool {
+        self.version.is_none_or(|version| version == todo.version)
+            && self
+                .modified_before
+                .is_none_or(|before| todo.updated_at < before)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate, ToSchema)]
+pub struct UpdateTodos {
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    pub ids: Vec<i64>,
+    #[validate(custom = "validate_not_blank")]
+    #[validate(length(max = 100, message = "Over text length"))]
+    #[serde(default, deserialize_with = "deserialize_optional_text")]
+    text: Option<String>,
+    completed: Option<bool>,
+}
+
+impl UpdateTodos {
+    pub fn completes(&self) -> bool {
+        self.completed == Some(true)
+    }
+}
+
+pub const DEFAULT_LIST_LIMIT: i64 = 50;
+pub const MAX_LIST_LIMIT: i64 = 100;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Hash, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    // POST /todos/:id/moveで並べ替えた順。新しく作成したTodoは先頭になる
+    #[default]
+    Position,
+    Id,
+    Text,
+    CreatedAt,
+    UpdatedAt,
+    Completed,
+    Priority,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default, PartialEq, Eq, Hash, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+// Serializeはクライアントがクエリ文字列を組み立てるために使う。Noneの項目は出力されない
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq, Hash, Validate, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TodoListQuery {
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+    pub completed: Option<bool>,
+    pub q: Option<String>,
+    pub sort: Option<SortField>,
+    pub order: Option<SortOrder>,
+    pub due_before: Option<DateTime<Utc>>,
+    pub due_after: Option<DateTime<Utc>>,
+    // trueの場合は期限切れの未完了Todoのみ、falseの場合はそれ以外を返す
+    pub overdue: Option<bool>,
+    #[validate(custom = "validate_priority")]
+    #[param(value_type = Option<Priority>)]
+    pub priority: Option<String>,
+    // trueの場合はアーカイブしたTodoも含める
+    pub include_archived: Option<bool>,
+}
 
-        async fn all(&self) -> anyhow::Result<Vec<TodoEntity>> {
-            let store = self.read_store_ref();
-            Ok(Vec::from_iter(store.values().map(|todo| todo.clone())))
+impl TodoListQuery {
+    pub fn limit(&self) -> i64 {
+        self.limit
+            .map(|limit| i64::from(limit).min(MAX_LIST_LIMIT))
+            .unwrap_or(DEFAULT_LIST_LIMIT)
+    }
+
+    pub fn priority(&self) -> Option<Priority> {
+        parse_priority(self.priority.as_deref())
+    }
+
+    pub fn offset(&self) -> i64 {
+        self.offset.map(i64::from).unwrap_or(0)
+    }
+
+    // 空文字のqは未指定として扱う
+    pub fn search_text(&self) -> Option<&str> {
+        self.q.as_deref().map(str::trim).filter(|q| !q.is_empty())
+    }
+
+    fn search_pattern(&self) -> Option<String> {
+        self.search_text().map(|q| {
+            let escaped = q
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_");
+            format!("%{}%", escaped)
+        })
+    }
+
+    // ソート列はenumで限定しているため、SQLへそのまま埋め込んでも問題ない
+    fn order_by_clause(&self) -> String {
+        let order = match self.order.unwrap_or_default() {
+            SortOrder::Asc => "asc",
+            SortOrder::Desc => "desc",
+        };
+        // 同値の並びが実行ごとに揺れないよう、idを第2キーにする
+        match self.sort.unwrap_or_default() {
+            SortField::Position => format!("todos.position {0}, todos.id {0}", order),
+            SortField::Id => format!("todos.id {}", order),
+            SortField::Text => format!("todos.text {0}, todos.id {0}", order),
+            SortField::CreatedAt => format!("todos.created_at {0}, todos.id {0}", order),
+            SortField::UpdatedAt => format!("todos.updated_at {0}, todos.id {0}", order),
+            SortField::Completed => format!("todos.completed {0}, todos.id {0}", order),
+            SortField::Priority => format!("todos.priority {0}, todos.id {0}", order),
         }
+    }
+}
 
-        async fn update(&self, id: i32, payload: UpdateTodo) -> anyhow::Result<TodoEntity> {
-            let mut store = self.write_store_ref();
-            let todo = store.get(&id).context(RepositoryError::NotFound(id))?;
-            let text = payload.text.unwrap_or(todo.text.clone());
-            let completed = payload.completed.unwrap_or(todo.completed);
-            let labels = match payload.labels {
-                Some(label_ids) => self.resolve_labels(label_ids),
-                None => todo.labels.clone(),
-            };
-            let todo = TodoEntity {
-                id,
-                text,
-                completed,
-                labels,
-            };
-            store.insert(id, todo.clone());
-            Ok(todo)
+// インメモリの実装の一覧。SQLと同じ条件で絞り込み、並べ替えてからページングする
+// positionはidから表示順の値を返す。DBなしでもベンチマークできるよう独立させている
+pub fn list_todos<'a>(
+    todos: impl IntoIterator<Item = &'a TodoEntity>,
+    query: &TodoListQuery,
+    now: DateTime<Utc>,
+    position: impl Fn(i64) -> i64,
+) -> TodoPage {
+    let search_text = query.search_text().map(str::to_lowercase);
+    let priority = query.priority();
+    let mut todos: Vec<TodoEntity> = todos
+        .into_iter()
+        .filter(|todo| query.completed.is_none_or(|c| todo.completed == c))
+        .filter(|todo| {
+            search_text
+                .as_ref()
+                .is_none_or(|q| todo.text.to_lowercase().contains(q))
+        })
+        .filter(|todo| {
+            query
+                .due_before
+                .is_none_or(|before| todo.due_date.is_some_and(|due| due < before))
+        })
+        .filter(|todo| {
+            query
+                .due_after
+                .is_none_or(|after| todo.due_date.is_some_and(|due| due > after))
+        })
+        .filter(|todo| {
+            let overdue = todo.due_date.is_some_and(|due| due < now) && !todo.completed;
+            query.overdue.is_none_or(|o| overdue == o)
+        })
+        .filter(|todo| priority.is_none_or(|p| todo.priority == p))
+        .filter(|todo| query.include_archived == Some(true) || todo.archived_at.is_none())
+        .cloned()
+        .collect();
+    todos.sort_by(|a, b| {
+        let ordering = match query.sort.unwrap_or_default() {
+            SortField::Position => position(a.id).cmp(&position(b.id)).then(a.id.cmp(&b.id)),
+            SortField::Id => a.id.cmp(&b.id),
+            SortField::Text => a.text.cmp(&b.text).then(a.id.cmp(&b.id)),
+            SortField::CreatedAt => a.created_at.cmp(&b.created_at).then(a.id.cmp(&b.id)),
+            SortField::UpdatedAt => a.updated_at.cmp(&b.updated_at).then(a.id.cmp(&b.id)),
+            SortField::Completed => a.completed.cmp(&b.completed).then(a.id.cmp(&b.id)),
+            SortField::Priority => a.priority.cmp(&b.priority).then(a.id.cmp(&b.id)),
+        };
+        match query.order.unwrap_or_default() {
+            SortOrder::Asc => ordering,
+            SortOrder::Desc => ordering.reverse(),
         }
+    });
+    let total = todos.len() as i64;
+    let todos = todos
+        .into_iter()
+        .skip(query.offset() as usize)
+        .take(query.limit() as usize)
+        .collect();
+    TodoPage { todos, total }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq, ToSchema)]
+pub struct DuplicateTodo {
+    // 省略時は複製元の作成日時から期限までの間隔を保ち、nullを指定すると期限なしにする
+    #[serde(default, deserialize_with = "deserialize_present")]
+    #[schema(value_type = Option<DateTime<Utc>>, nullable)]
+    pub due_date: Option<Option<DateTime<Utc>>>,
+}
 
-        async fn delete(&self, id: i32) -> anyhow::Result<()> {
-            let mut store = self.write_store_ref();
-            store.remove(&id).ok_or(RepositoryError::NotFound(id))?;
-            Ok(())
+impl DuplicateTodo {
+    pub fn due_date(
+        &self,
+        source_created_at: DateTime<Utc>,
+        source_due_date: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        match self.due_date {
+            Some(due_date) => due_date,
+            None => source_due_date.map(|due_date| now + (due_date - source_created_at)),
         }
     }
+}
 
-    #[cfg(test)]
-    mod test {
-        use super::*;
+// 表示順の間隔。移動時は前後の中間に置き、間隔がなくなった場合のみ全体を振り直す
+pub const POSITION_GAP: i64 = 1024;
 
-        #[tokio::test]
-        async fn todo_crud_scenario() {
-            let text = "todo text".to_string();
-            let id = 1;
-            let label_data = Label {
-                id: 1,
-                name: String::from("test label"),
-            };
-            let labels = vec![label_data.clone()];
-            let expected = TodoEntity {
-                id,
-                text: text.clone(),
-                completed: false,
-                labels: labels.clone(),
-            };
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq, Validate, ToSchema)]
+pub struct MoveTodo {
+    pub after_id: Option<i64>,
+    pub before_id: Option<i64>,
+    pub to_top: Option<bool>,
+}
 
-            // create
-            let label_data = Label {
-                id: 1,
-                name: String::from("test label"),
-            };
-            let labels = vec![label_data.clone()];
-            let repository = TodoRepositoryForMemory::new(labels.clone());
-            let todo = repository
-                .create(CreateTodo::new(text, vec![label_data.id]))
-                .await
-                .expect("failed create todo");
-            assert_eq!(expected, todo);
-
-            // find
-            let todo = repository.find(todo.id).await.unwrap();
-            assert_eq!(expected, todo);
-
-            // all
-            let todo = repository.all().await.expect("failed get all todo");
-            assert_eq!(vec![expected], todo);
-
-            // update
-            let text = "update todo text".to_string();
-            let todo = repository
-                .update(
-                    1,
-                    UpdateTodo {
-                        text: Some(text.clone()),
-                        completed: Some(true),
-                        labels: Some(vec![]),
-                    },
-                )
-                .await
-                .expect("failed update todo.");
-            assert_eq!(
-                TodoEntity {
-                    id,
-                    text,
-                    completed: true,
-                    labels: vec![],
-                },
-                todo
-            );
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveTarget {
+    Top,
+    After(i64),
+    Before(i64),
+}
+
+// 表示順(positionの降順、同値はidの降順)に並んだorderedの中でidのTodoを移動し、
+// positionを変更するTodoとその値を返す
+fn reposition(
+    mut ordered: Vec<(i64, i64)>,
+    id: i64,
+    target: MoveTarget,
+) -> Result<Vec<(i64, i64)>, RepositoryError> {
+    let current = ordered
+        .iter()
+        .position(|(todo_id, _)| *todo_id == id)
+        .ok_or(RepositoryError::NotFound(Some(id)))?;
+    ordered.remove(current);
+
+    let index_of = |anchor: i64| {
+        ordered
+            .iter()
+            .position(|(todo_id, _)| *todo_id == anchor)
+            .ok_or(RepositoryError::NotFound(Some(anchor)))
+    };
+    let index = match target {
+        MoveTarget::Top => 0,
+        MoveTarget::After(anchor) => index_of(anchor)? + 1,
+        MoveTarget::Before(anchor) => index_of(anchor)?,
+    };
+
+    let above = index.checked_sub(1).map(|i| ordered[i].1);
+    let below = ordered.get(index).map(|(_, position)| *position);
+    let position = match (above, below) {
+        (None, None) => Some(POSITION_GAP),
+        (None, Some(below)) => below.checked_add(POSITION_GAP),
+        (Some(above), None) => above.checked_sub(POSITION_GAP),
+        (Some(above), Some(below)) if above - below >= 2 => Some(below + (above - below) / 2),
+        _ => None,
+    };
+    if let Some(position) = position {
+        return Ok(vec![(id, position)]);
+    }
+
+    ordered.insert(index, (id, 0));
+    let len = ordered.len() as i64;
+    Ok(ordered
+        .into_iter()
+        .enumerate()
+        .map(|(i, (todo_id, position))| (todo_id, position, (len - i as i64) * POSITION_GAP))
+        .filter(|(_, position, rebalanced)| position != rebalanced)
+        .map(|(todo_id, _, rebalanced)| (todo_id, rebalanced))
+        .collect())
+}
+
+// 通知の対象となったTodoと、その所有者のユーザーid
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DueReminder {
+    pub user_id: i32,
+    pub todo: TodoEntity,
+}
+
+// Idempotency-Keyを付けて受け付けた作成のリクエスト
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IdempotencyRecord {
+    // リクエストの内容のハッシュ。同じキーで内容の異なるリクエストを見分けるために使う
+    pub fingerprint: String,
+    // 作成を終えるまではNone。終えた後は201で返したbodyが入る
+    pub response: Option<serde_json::Value>,
+}
+
+// 応答を記録済みのキーは書き換えない。作成と同じトランザクションで記録した後の再記録は何もしない
+const COMPLETE_IDEMPOTENCY_KEY: &str =
+    "update idempotency_keys set response = $3 where user_id = $1 and key = $2 and response is null";
+// 応答を記録済みのキーは、作成したTodoを指すため取り消さない
+const RELEASE_IDEMPOTENCY_KEY: &str =
+    "delete from idempotency_keys where user_id = $1 and key = $2 and response is null";
+
+// キーへ記録する応答。201で返すbodyと同じ形にする
+pub(crate) fn idempotent_response(
+    created: &impl Serialize,
+) -> Result<serde_json::Value, RepositoryError> {
+    serde_json::to_value(created).map_err(|e| RepositoryError::unexpected(e.to_string()))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TodoPage {
+    pub todos: Vec<TodoEntity>,
+    pub total: i64,
+}
+
+// 一括更新の結果。存在しない(他のユーザーの)idはmissingに入る
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
+pub struct UpdatedTodos {
+    pub todos: Vec<TodoEntity>,
+    pub missing: Vec<i64>,
+}
+
+impl UpdatedTodos {
+    fn new(ids: &[i64], todos: Vec<TodoEntity>) -> Self {
+        let missing = missing_ids(ids, &todos);
+        Self { todos, missing }
+    }
+}
+
+// idを指定して複数件引いた結果。存在しない(他のユーザーの・ゴミ箱内の)idはmissingに入る
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FoundTodos {
+    pub todos: Vec<TodoEntity>,
+    pub missing: Vec<i64>,
+}
+
+impl FoundTodos {
+    fn new(ids: &[i64], todos: Vec<TodoEntity>) -> Self {
+        let missing = missing_ids(ids, &todos);
+        Self { todos, missing }
+    }
+}
+
+// 指定された順に、重複を除いて返す
+fn missing_ids(ids: &[i64], todos: &[TodoEntity]) -> Vec<i64> {
+    let mut missing: Vec<i64> = vec![];
+    for id in ids {
+        if !missing.contains(id) && todos.iter().all(|todo| todo.id != *id) {
+            missing.push(*id);
+        }
+    }
+    missing
+}
+
+// ゴミ箱内のTodoは集計に含めない
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq, ToSchema)]
+pub struct TodoStats {
+    pub total: i64,
+    pub open: i64,
+    pub completed: i64,
+    // 直近7日間に作成された件数
+    pub created_last_7_days: i64,
+    // Todoが1件以上紐づくラベルのみ、id順に返す
+    pub labels: Vec<LabelCount>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
+pub struct LabelCount {
+    pub id: i32,
+    pub name: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+struct TodoStatsFromRow {
+    is_total: bool,
+    label_id: Option<i32>,
+    label_name: Option<String>,
+    total: i64,
+    completed: i64,
+    created_last_7_days: i64,
+}
+
+#[async_trait]
+pub trait TodoRepository: Clone + Send + Sync + 'static {
+    async fn create(
+        &self,
+        user_id: i32,
+        payload: CreateTodo,
+    ) -> Result<TodoEntity, RepositoryError>;
+    async fn create_many(
+        &self,
+        user_id: i32,
+        payloads: Vec<CreateTodo>,
+    ) -> Result<Vec<TodoEntity>, RepositoryError>;
+    // keyがある場合は、作成したTodoを同じトランザクションで登録済みのキーの応答として記録する
+    // 作成と記録の間で止まっても、Todoだけが作成されて応答のないキーが残ることはない
+    async fn create_idempotent(
+        &self,
+        user_id: i32,
+        payload: CreateTodo,
+        key: Option<&str>,
+    ) -> Result<TodoEntity, RepositoryError>;
+    async fn create_many_idempotent(
+        &self,
+        user_id: i32,
+        payloads: Vec<CreateTodo>,
+        key: Option<&str>,
+    ) -> Result<Vec<TodoEntity>, RepositoryError>;
+    // ラベルを名前で指定して一括登録する。存在しないラベルは作成する
+    async fn create_many_with_label_names(
+        &self,
+        user_id: i32,
+        payloads: Vec<CreateTodoWithLabelNames>,
+    ) -> Result<Vec<TodoEntity>, RepositoryError>;
+    async fn find(&self, user_id: i32, id: i64) -> Result<TodoEntity, RepositoryError>;
+    // 1回の問い合わせでid順に返す。見つからないidはエラーにせずmissingに入れ、空の場合はDBに問い合わせない
+    async fn find_many(&self, user_id: i32, ids: &[i64]) -> Result<FoundTodos, RepositoryError>;
+    // ラベルを読まずに、findで見つかるTodoかのみを返す
+    async fn exists(&self, user_id: i32, id: i64) -> Result<bool, RepositoryError>;
+    // 未完了・未アーカイブのTodoから、正規化したテキストが一致するものを探す。複数ある場合は最も古いもの
+    async fn find_open_by_text(
+        &self,
+        user_id: i32,
+        text: &str,
+    ) -> Result<Option<TodoEntity>, RepositoryError>;
+    // クライアントが採番したidで探す。findと同じくゴミ箱内のTodoは含めない
+    async fn find_by_client_id(
+        &self,
+        user_id: i32,
+        client_id: Uuid,
+    ) -> Result<Option<TodoEntity>, RepositoryError>;
+    // 複数のTodoのラベルをtodo_idごとにまとめて返す。GraphQLのDataLoaderから呼ぶ
+    // 他のユーザーのTodo・ゴミ箱内のTodoのidは結果に含めない
+    async fn labels_for_todos(
+        &self,
+        user_id: i32,
+        ids: Vec<i64>,
+    ) -> Result<HashMap<i64, Vec<Label>>, RepositoryError>;
+    async fn all(&self, user_id: i32, query: TodoListQuery) -> Result<TodoPage, RepositoryError>;
+    // 一覧の内容が最後に変わった日時。ゴミ箱への移動も含め、Todoが1件もない場合はNone
+    async fn last_modified(&self, user_id: i32) -> Result<Option<DateTime<Utc>>, RepositoryError>;
+    async fn update(
+        &self,
+        user_id: i32,
+        id: i64,
+        payload: UpdateTodo,
+    ) -> Result<TodoEntity, RepositoryError>;
+    // 指定しなかった項目も既定値で上書きする。存在しないidでは作成せずNotFoundを返す
+    async fn replace(
+        &self,
+        user_id: i32,
+        id: i64,
+        payload: ReplaceTodo,
+    ) -> Result<TodoEntity, RepositoryError>;
+    async fn update_many(
+        &self,
+        user_id: i32,
+        payload: UpdateTodos,
+    ) -> Result<UpdatedTodos, RepositoryError>;
+    async fn delete(
+        &self,
+        user_id: i32,
+        id: i64,
+        precondition: Precondition,
+    ) -> Result<(), RepositoryError>;
+    async fn delete_permanently(&self, user_id: i32, id: i64) -> Result<(), RepositoryError>;
+    async fn trash(&self, user_id: i32) -> Result<Vec<TodoEntity>, RepositoryError>;
+    async fn restore(&self, user_id: i32, id: i64) -> Result<TodoEntity, RepositoryError>;
+    async fn delete_completed(&self, user_id: i32) -> Result<u64, RepositoryError>;
+    // ユーザーを問わず、cutoffより前にゴミ箱へ移したTodoを削除する
+    async fn purge_deleted_before(&self, cutoff: DateTime<Utc>) -> Result<u64, RepositoryError>;
+    // キーを登録できた場合はNone、登録済みの場合はその記録を返す
+    // 同じキーを同時に登録した場合も、一意制約によって一方のみが登録できる
+    // 応答のない登録は作成済みのTodoがありうるため、期限が過ぎて削除されるまで引き継がない
+    async fn claim_idempotency_key(
+        &self,
+        user_id: i32,
+        key: &str,
+        fingerprint: &str,
+    ) -> Result<Option<IdempotencyRecord>, RepositoryError>;
+    // 以降の再送にはresponseを返す
+    async fn complete_idempotency_key(
+        &self,
+        user_id: i32,
+        key: &str,
+        response: serde_json::Value,
+    ) -> Result<(), RepositoryError>;
+    // 作成に失敗した場合に登録を取り消し、同じキーで再送できるようにする
+    async fn release_idempotency_key(&self, user_id: i32, key: &str)
+        -> Result<(), RepositoryError>;
+    // ユーザーを問わず、cutoffより前に登録したキーを削除する
+    async fn purge_idempotency_keys_before(
+        &self,
+        cutoff: DateTime<Utc>,
+    ) -> Result<u64, RepositoryError>;
+    async fn attach_label(
+        &self,
+        user_id: i32,
+        id: i64,
+        label_id: i32,
+    ) -> Result<TodoEntity, RepositoryError>;
+    async fn detach_label(
+        &self,
+        user_id: i32,
+        id: i64,
+        label_id: i32,
+    ) -> Result<TodoEntity, RepositoryError>;
+    // ゴミ箱内を除くユーザーのTodoと、全てのラベルを書き出す
+    async fn export(&self, user_id: i32) -> Result<Backup, RepositoryError>;
+    // idを採番し直して取り込む。同名(大文字小文字を区別しない)のラベルは既存のものへ統合する
+    async fn import(&self, user_id: i32, backup: Backup) -> Result<ImportSummary, RepositoryError>;
+    // ユーザーを問わず、Todo・ラベルとそれらに紐づくデータをすべて削除し、idを1から採番し直す
+    async fn reset(&self) -> Result<(), RepositoryError>;
+    // resetした上でfixturesをユーザーのTodoとして取り込む。失敗した場合はresetも取り消す
+    async fn seed(&self, user_id: i32, fixtures: Backup) -> Result<ImportSummary, RepositoryError>;
+    // 全件をメモリに載せないよう、ゴミ箱内を除くユーザーのTodoをid順に1件ずつ返す
+    fn stream_all(&self, user_id: i32) -> BoxStream<'static, Result<TodoEntity, RepositoryError>>;
+    async fn stats(&self, user_id: i32) -> Result<TodoStats, RepositoryError>;
+    // チェックリストの項目はposition順に返す。ゴミ箱内のTodoの項目は参照・変更できない
+    async fn items(&self, user_id: i32, id: i64) -> Result<Vec<TodoItem>, RepositoryError>;
+    async fn create_item(
+        &self,
+        user_id: i32,
+        id: i64,
+        payload: CreateTodoItem,
+    ) -> Result<TodoItem, RepositoryError>;
+    async fn update_item(
+        &self,
+        user_id: i32,
+        id: i64,
+        item_id: i32,
+        payload: UpdateTodoItem,
+    ) -> Result<TodoItem, RepositoryError>;
+    async fn delete_item(&self, user_id: i32, id: i64, item_id: i32)
+        -> Result<(), RepositoryError>;
+    // テキスト・優先度・期限・ラベルを引き継いだ未完了のTodoを作成する
+    async fn duplicate(
+        &self,
+        user_id: i32,
+        id: i64,
+        payload: DuplicateTodo,
+    ) -> Result<TodoEntity, RepositoryError>;
+    // アーカイブ済みのTodoへのarchive、未アーカイブのTodoへのunarchiveは何も変更せずに返す
+    async fn archive(&self, user_id: i32, id: i64) -> Result<TodoEntity, RepositoryError>;
+    async fn unarchive(&self, user_id: i32, id: i64) -> Result<TodoEntity, RepositoryError>;
+    // 完了済みで未アーカイブのTodoをすべてアーカイブし、件数を返す
+    async fn archive_completed(&self, user_id: i32) -> Result<u64, RepositoryError>;
+    // ゴミ箱内を除くユーザーのTodoの表示順を変更する
+    async fn move_todo(
+        &self,
+        user_id: i32,
+        id: i64,
+        target: MoveTarget,
+    ) -> Result<TodoEntity, RepositoryError>;
+    // 作成・更新・完了・削除の履歴を新しい順に返す。ゴミ箱内のTodoの履歴も参照できる
+    async fn activity(
+        &self,
+        user_id: i32,
+        id: i64,
+        query: PageQuery,
+    ) -> Result<TodoActivityPage, RepositoryError>;
+    // ユーザーを問わず、通知日時がnow以前で未通知のTodoを通知済みにして返す
+    // 完了済み・ゴミ箱内のTodoは通知しない
+    async fn due_reminders(&self, now: DateTime<Utc>) -> Result<Vec<DueReminder>, RepositoryError>;
+}
+
+#[derive(Debug, Clone)]
+pub struct TodoRepositoryForDb {
+    pool: PgPool,
+    // DBのnow()は使わず、タイムスタンプはすべてこの時計から与える
+    clock: SharedClock,
+}
+
+impl TodoRepositoryForDb {
+    pub fn new(pool: PgPool, clock: impl Clock) -> Self {
+        TodoRepositoryForDb {
+            pool,
+            clock: Arc::new(clock),
+        }
+    }
+
+    // クロージャ内の書き込みを1つのトランザクションで行い、Errを返した場合はすべて取り消す
+    // 外部キー違反はcommit時に検出されるため、関連付けたラベルのうち存在しないものを返す
+    pub async fn with_transaction<T, F>(&self, f: F) -> Result<T, RepositoryError>
+    where
+        T: Send,
+        F: for<'t> FnOnce(&'t mut TodoTransaction) -> BoxFuture<'t, Result<T, RepositoryError>>
+            + Send,
+    {
+        let mut tx = TodoTransaction {
+            tx: self.pool.begin().await?,
+            now: self.clock.now(),
+            labels: vec![],
+        };
+        let value = f(&mut tx).await?;
+        let TodoTransaction { tx, labels, .. } = tx;
+        self.map_label_violation(tx.commit().await, &labels).await?;
+        Ok(value)
+    }
+
+    async fn find_label(&self, label_id: i32) -> Result<Label, RepositoryError> {
+        let label = sqlx::query_as::<_, Label>("select * from labels where id = $1")
+            .bind(label_id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or(RepositoryError::NotFound(Some(label_id.into())))?;
+        Ok(label)
+    }
+
+    // todo_labelsの外部キー制約は遅延評価のためcommit時に違反となる
+    // 違反した場合はトランザクション全体が取り消されるので、不正なラベルidを特定して返す
+    async fn map_label_violation<T>(
+        &self,
+        result: Result<T, sqlx::Error>,
+        labels: &[i32],
+    ) -> Result<T, RepositoryError> {
+        match result {
+            Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some(FOREIGN_KEY_VIOLATION) => {
+                let found: Vec<i32> =
+                    sqlx::query_as::<_, (i32,)>("select id from labels where id = any($1)")
+                        .bind(labels)
+                        .fetch_all(&self.pool)
+                        .await?
+                        .into_iter()
+                        .map(|(id,)| id)
+                        .collect();
+                match labels.iter().find(|id| !found.contains(id)) {
+                    Some(id) => Err(RepositoryError::InvalidLabel(*id)),
+                    None => Err(sqlx::Error::Database(e).into()),
+                }
+            }
+            result => Ok(result?),
+        }
+    }
+
+    // 1件でも存在しないラベルがあれば、何も登録せずに失敗させる
+    async fn insert_many(
+        tx: &mut Transaction<'_, Postgres>,
+        user_id: i32,
+        payloads: Vec<CreateTodo>,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<i64>, RepositoryError> {
+        let label_ids: Vec<i32> = payloads
+            .iter()
+            .flat_map(|payload| payload.labels.iter().copied())
+            .collect();
+        let found: Vec<i32> =
+            sqlx::query_as::<_, (i32,)>("select id from labels where id = any($1)")
+                .bind(label_ids.clone())
+                .fetch_all(&mut *tx)
+                .await?
+                .into_iter()
+                .map(|(id,)| id)
+                .collect();
+        if let Some(missing) = label_ids.iter().find(|id| !found.contains(id)) {
+            return Err(RepositoryError::NotFound(Some((*missing).into())));
+        }
+
+        let mut ids = Vec::with_capacity(payloads.len());
+        for payload in payloads {
+            let priority = payload.priority();
+            let row = sqlx::query_as::<_, TodoFromRow>(INSERT_TODO)
+                .bind(payload.text)
+                .bind(user_id)
+                .bind(payload.due_date)
+                .bind(priority)
+                .bind(POSITION_GAP)
+                .bind(payload.recurrence.map(Json))
+                .bind(payload.remind_at)
+                .bind(now)
+                .bind(payload.client_id)
+                .fetch_one(&mut *tx)
+                .await?;
+
+            sqlx::query(
+                r#"
+insert into todo_labels (todo_id, label_id) select $1, id from unnest($2) as t(id)
+on conflict (todo_id, label_id) do nothing;
+"#,
+            )
+            .bind(row.id)
+            .bind(payload.labels)
+            .execute(&mut *tx)
+            .await?;
+            ids.push(row.id);
+        }
+
+        let todos = Self::entities_in(tx, user_id, &ids).await?;
+        Self::record_activities(
+            tx,
+            user_id,
+            todos.iter().map(TodoChange::created).collect(),
+            now,
+        )
+        .await?;
+        Ok(ids)
+    }
+
+    // 所有者・ゴミ箱を問わずに引く。通知のように複数のユーザーのTodoをまとめて扱う場合のみ使う
+    async fn find_across_users(
+        tx: &mut Transaction<'_, Postgres>,
+        ids: Vec<i64>,
+    ) -> Result<Vec<TodoEntity>, RepositoryError> {
+        let items = sqlx::query_as::<_, TodoWithLabelFromRow>(
+            r#"
+select todos.*, labels.id as label_id, labels.name as label_name, labels.color as label_color,
+       labels.description as label_description
+from todos
+left outer join todo_labels tl on todos.id = tl.todo_id
+left outer join labels on labels.id = tl.label_id
+where todos.id = any($1)
+order by todos.id asc;
+"#,
+        )
+        .bind(ids)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        Ok(fold_entities(items))
+    }
+
+    // 同名(大文字小文字を区別しない)のラベルがあればそのidを、なければ作成したidを返す
+    // 同一文内のselectは挿入前のスナップショットを見るため、どちらか一方のみが返る
+    async fn upsert_label(
+        tx: &mut Transaction<'_, Postgres>,
+        name: &str,
+        color: &str,
+        description: Option<&str>,
+    ) -> Result<(i32, bool), RepositoryError> {
+        let (id, created) = sqlx::query_as::<_, (i32, bool)>(
+            r#"
+with inserted as (
+    insert into labels (name, color, description) values ($1, $2, $3)
+    on conflict ((lower(name))) do nothing
+    returning id
+)
+select id, true from inserted
+union all
+select id, false from labels where lower(name) = lower($1);
+"#,
+        )
+        .bind(name)
+        .bind(color)
+        .bind(description)
+        .fetch_one(&mut *tx)
+        .await?;
+        Ok((id, created))
+    }
+
+    // importとseedで共有する。commitは呼び出し側で行う
+    async fn import_in(
+        tx: &mut Transaction<'_, Postgres>,
+        user_id: i32,
+        backup: Backup,
+    ) -> Result<ImportSummary, RepositoryError> {
+        let mut summary = ImportSummary::default();
+
+        let mut label_ids = HashMap::with_capacity(backup.labels.len());
+        for label in backup.labels {
+            let (id, created) = Self::upsert_label(
+                tx,
+                &label.name,
+                &label.color,
+                label.description.as_deref(),
+            )
+            .await?;
+            if created {
+                summary.labels += 1;
+            }
+            label_ids.insert(label.id, id);
+        }
+
+        let mut todo_ids = HashMap::with_capacity(backup.todos.len());
+        for todo in backup.todos {
+            let (id,) = sqlx::query_as::<_, (i64,)>(
+                r#"
+insert into todos (text, completed, user_id, due_date, priority, created_at, updated_at, position)
+values ($1, $2, $3, $4, $5, $6, $7,
+        coalesce((select max(position) from todos where user_id = $3), 0) + $8)
+returning id
+"#,
+            )
+            .bind(todo.text)
+            .bind(todo.completed)
+            .bind(user_id)
+            .bind(todo.due_date)
+            .bind(todo.priority)
+            .bind(todo.created_at)
+            .bind(todo.updated_at)
+            .bind(POSITION_GAP)
+            .fetch_one(&mut *tx)
+            .await?;
+            todo_ids.insert(todo.id, id);
+        }
+        summary.todos = todo_ids.len();
+
+        // 参照先はBackup::validateで検証済みのため、見つからない組は存在しない
+        let (todos, labels): (Vec<i64>, Vec<i32>) = backup
+            .associations
+            .iter()
+            .filter_map(|association| {
+                Some((
+                    *todo_ids.get(&association.todo_id)?,
+                    *label_ids.get(&association.label_id)?,
+                ))
+            })
+            .unzip();
+        sqlx::query(
+            r#"
+insert into todo_labels (todo_id, label_id) select * from unnest($1, $2)
+on conflict (todo_id, label_id) do nothing;
+"#,
+        )
+        .bind(todos)
+        .bind(labels)
+        .execute(&mut *tx)
+        .await?;
+
+        Ok(summary)
+    }
+
+    // 参照しているテーブルも同時に空にしないと、外部キー制約によりtruncateできない
+    async fn reset_in(tx: &mut Transaction<'_, Postgres>) -> Result<(), RepositoryError> {
+        sqlx::query(
+            r#"
+truncate todos, labels, todo_labels, todo_items, comments, todo_activities, idempotency_keys,
+    todo_list_modifications
+restart identity;
+"#,
+        )
+        .execute(&mut *tx)
+        .await?;
+        Ok(())
+    }
+
+    // 同じTodoの項目への変更が並行してもpositionが重複しないよう、Todoの行をロックする
+    async fn lock_owned(
+        tx: &mut Transaction<'_, Postgres>,
+        user_id: i32,
+        id: i64,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query_as::<_, (i64,)>(
+            "select id from todos where id=$1 and user_id=$2 and deleted_at is null for update",
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(RepositoryError::NotFound(Some(id)))?;
+        Ok(())
+    }
+
+    async fn items_of(
+        tx: &mut Transaction<'_, Postgres>,
+        id: i64,
+    ) -> Result<Vec<TodoItem>, RepositoryError> {
+        let items = sqlx::query_as::<_, TodoItem>(
+            "select * from todo_items where todo_id=$1 order by position asc, id asc",
+        )
+        .bind(id)
+        .fetch_all(&mut *tx)
+        .await?;
+        Ok(items)
+    }
 
-            // delete
-            let res = repository.delete(id).await;
-            assert!(res.is_ok())
+    // 履歴に残す変更前後の状態を、変更と同じトランザクション内で読む
+    // 行をロックするため、読んでからcommitするまでに他の更新が割り込むことはない
+    async fn entities_in(
+        tx: &mut Transaction<'_, Postgres>,
+        user_id: i32,
+        ids: &[i64],
+    ) -> Result<Vec<TodoEntity>, RepositoryError> {
+        let items = sqlx::query_as::<_, TodoWithLabelFromRow>(
+            r#"
+select todos.*, labels.id as label_id, labels.name as label_name, labels.color as label_color,
+       labels.description as label_description
+from todos
+left outer join todo_labels tl on todos.id = tl.todo_id
+left outer join labels on labels.id = tl.label_id
+where todos.id = any($1) and todos.user_id = $2 and todos.deleted_at is null
+order by todos.id asc
+for update of todos;
+"#,
+        )
+        .bind(ids)
+        .bind(user_id)
+        .fetch_all(&mut *tx)
+        .await?;
+        Ok(fold_entities(items))
+    }
+
+    async fn record_activities(
+        tx: &mut Transaction<'_, Postgres>,
+        user_id: i32,
+        changes: Vec<TodoChange>,
+        now: DateTime<Utc>,
+    ) -> Result<(), RepositoryError> {
+        for change in changes {
+            sqlx::query(
+                r#"
+insert into todo_activities (todo_id, action, old_value, new_value, actor_id, created_at)
+values ($1, $2, $3, $4, $5, $6);
+"#,
+            )
+            .bind(change.todo_id)
+            .bind(change.action)
+            .bind(change.old_value)
+            .bind(change.new_value)
+            .bind(user_id)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        }
+        Ok(())
+    }
+
+    // 完了したTodoのテキスト・優先度・ラベル・繰り返しを引き継ぎ、期限と通知日時を進めた次の回を作成する
+    async fn spawn_next_occurrence(
+        tx: &mut Transaction<'_, Postgres>,
+        user_id: i32,
+        completed: &TodoEntity,
+        recurrence: Recurrence,
+    ) -> Result<TodoEntity, RepositoryError> {
+        // 通知日時も期限と同じ間隔だけ先へずらす
+        let remind_at = completed
+            .remind_at
+            .map(|remind_at| recurrence.next_due_date(Some(remind_at), completed.updated_at));
+        let row = sqlx::query_as::<_, TodoFromRow>(INSERT_TODO)
+            .bind(completed.text.clone())
+            .bind(user_id)
+            .bind(recurrence.next_due_date(completed.due_date, completed.updated_at))
+            .bind(completed.priority)
+            .bind(POSITION_GAP)
+            .bind(Json(recurrence))
+            .bind(remind_at)
+            .bind(completed.updated_at)
+            .bind(None::<Uuid>)
+            .fetch_one(&mut *tx)
+            .await?;
+        sqlx::query(
+            "insert into todo_labels (todo_id, label_id) select $1, label_id from todo_labels where todo_id = $2",
+        )
+        .bind(row.id)
+        .bind(completed.id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("update todos set next_occurrence_id = $1 where id = $2")
+            .bind(row.id)
+            .bind(completed.id)
+            .execute(&mut *tx)
+            .await?;
+
+        let next = Self::entities_in(tx, user_id, &[row.id])
+            .await?
+            .pop()
+            .ok_or(RepositoryError::NotFound(Some(row.id)))?;
+        Self::record_activities(
+            tx,
+            user_id,
+            vec![TodoChange::created(&next)],
+            completed.updated_at,
+        )
+        .await?;
+        Ok(next)
+    }
+
+    // 変更後の状態をUpdatedとして記録する。完了への変更はupdateのみが扱う
+    async fn record_updated(
+        tx: &mut Transaction<'_, Postgres>,
+        user_id: i32,
+        id: i64,
+        now: DateTime<Utc>,
+    ) -> Result<(), RepositoryError> {
+        let events = Self::entities_in(tx, user_id, &[id])
+            .await?
+            .into_iter()
+            .map(|todo| TodoEvent::Updated {
+                todo,
+                completed_now: false,
+            })
+            .collect();
+        record_events(tx, user_id, events, now).await
+    }
+
+    async fn touch(
+        tx: &mut Transaction<'_, Postgres>,
+        id: i64,
+        now: DateTime<Utc>,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query("update todos set updated_at = $2, version = version + 1 where id = $1")
+            .bind(id)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        Ok(())
+    }
+
+    // 完全な削除や並べ替えなど、updated_atやdeleted_atに残らない変更の後も一覧のLast-Modifiedを進める
+    pub(crate) async fn touch_lists(
+        tx: &mut Transaction<'_, Postgres>,
+        user_ids: &[i32],
+        now: DateTime<Utc>,
+    ) -> Result<(), RepositoryError> {
+        if user_ids.is_empty() {
+            return Ok(());
+        }
+        sqlx::query(
+            r#"
+insert into todo_list_modifications (user_id, modified_at)
+select distinct unnest($1::integer[]), $2::timestamptz
+on conflict (user_id) do update
+set modified_at = greatest(todo_list_modifications.modified_at, excluded.modified_at);
+"#,
+        )
+        .bind(user_ids)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+        Ok(())
+    }
+}
+
+// with_transactionのクロージャに渡す、1つのトランザクションに束ねたTodoの書き込み
+// 途中でErrを返すかdropされると、それまでの書き込みは履歴ごと取り消される
+pub struct TodoTransaction {
+    tx: Transaction<'static, Postgres>,
+    // 同じトランザクション内の書き込みは同じ時刻で記録する
+    now: DateTime<Utc>,
+    // commit時の外部キー違反から不正なラベルを特定するため、関連付けたラベルを覚えておく
+    labels: Vec<i32>,
+}
+
+impl TodoTransaction {
+    // 返すTodoはcommit前の状態。存在しないラベルはjoinされずに省かれ、commit時に違反となる
+    pub async fn create(
+        &mut self,
+        user_id: i32,
+        payload: CreateTodo,
+    ) -> Result<TodoEntity, RepositoryError> {
+        let row = sqlx::query_as::<_, TodoFromRow>(INSERT_TODO)
+            .bind(payload.text.clone())
+            .bind(user_id)
+            .bind(payload.due_date)
+            .bind(payload.priority())
+            .bind(POSITION_GAP)
+            .bind(payload.recurrence.map(Json))
+            .bind(payload.remind_at)
+            .bind(self.now)
+            .bind(payload.client_id)
+            .fetch_one(&mut self.tx)
+            .await?;
+        self.attach_labels(row.id, payload.labels).await?;
+
+        let todo = self.entity(user_id, row.id).await?;
+        self.record_activities(user_id, vec![TodoChange::created(&todo)])
+            .await?;
+        self.record_events(user_id, vec![TodoEvent::Created { todo: todo.clone() }])
+            .await?;
+        Ok(todo)
+    }
+
+    // 登録済みのキーへ、作成したものを応答として記録する
+    pub async fn complete_idempotency_key(
+        &mut self,
+        user_id: i32,
+        key: &str,
+        created: &impl Serialize,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query(COMPLETE_IDEMPOTENCY_KEY)
+            .bind(user_id)
+            .bind(key)
+            .bind(Json(idempotent_response(created)?))
+            .execute(&mut self.tx)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn update(
+        &mut self,
+        user_id: i32,
+        id: i64,
+        payload: UpdateTodo,
+    ) -> Result<TodoEntity, RepositoryError> {
+        let priority = payload.priority();
+        let completed_now = payload.completes();
+        let old = self.entity(user_id, id).await?;
+
+        // versionの比較と更新を1文で行い、同時更新による上書きを防ぐ
+        let updated = sqlx::query(
+            r#"
+update todos
+set text = coalesce($3, text), completed = coalesce($4, completed),
+    due_date = case when $6 then $7 else due_date end,
+    priority = coalesce($8, priority),
+    recurrence = case when $9 then $10 else recurrence end,
+    remind_at = case when $11 then $12 else remind_at end,
+    reminded_at = case when $11 then null else reminded_at end,
+    updated_at = $13, version = version + 1
+where id = $1 and user_id = $2 and deleted_at is null
+  and ($5::integer is null or version = $5)
+  and ($14::integer is null or version = $14)
+  and ($15::timestamptz is null or updated_at < $15);
+"#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(payload.text)
+        .bind(payload.completed)
+        .bind(payload.version)
+        .bind(payload.due_date.is_some())
+        .bind(payload.due_date.flatten())
+        .bind(priority)
+        .bind(payload.recurrence.is_some())
+        .bind(payload.recurrence.flatten().map(Json))
+        .bind(payload.remind_at.is_some())
+        .bind(payload.remind_at.flatten())
+        .bind(self.now)
+        .bind(payload.precondition.version)
+        .bind(payload.precondition.modified_before)
+        .execute(&mut self.tx)
+        .await?;
+        if updated.rows_affected() == 0 {
+            // 対象の存在はロック済みのため、前提条件かversionの不一致
+            if !payload.precondition.matches(&old) {
+                return Err(RepositoryError::PreconditionFailed(id));
+            }
+            return Err(RepositoryError::Conflict(id));
+        }
+
+        if let Some(labels) = payload.labels {
+            // 一度関連するレコードを削除
+            sqlx::query("delete from todo_labels where todo_id=$1")
+                .bind(id)
+                .execute(&mut self.tx)
+                .await?;
+            self.attach_labels(id, labels).await?;
+        };
+
+        self.updated(user_id, old, completed_now).await
+    }
+
+    // 置き換えとラベルの付け替えを1文で行う。存在しないラベルはcommit時に違反となる
+    pub async fn replace(
+        &mut self,
+        user_id: i32,
+        id: i64,
+        payload: ReplaceTodo,
+    ) -> Result<TodoEntity, RepositoryError> {
+        let priority = payload.priority();
+        let completed_now = payload.completes();
+        let old = self.entity(user_id, id).await?;
+
+        let (replaced,) = sqlx::query_as::<_, (i64,)>(
+            r#"
+with replaced as (
+    update todos
+    set text = $3, completed = $4, due_date = $5, priority = $6, recurrence = $7,
+        remind_at = $8, reminded_at = null, updated_at = $9, version = version + 1
+    where id = $1 and user_id = $2 and deleted_at is null
+      and ($10::integer is null or version = $10)
+      and ($11::integer is null or version = $11)
+      and ($12::timestamptz is null or updated_at < $12)
+    returning id
+), removed as (
+    delete from todo_labels
+    where todo_id in (select id from replaced) and label_id <> all($13::integer[])
+), added as (
+    insert into todo_labels (todo_id, label_id)
+    select replaced.id, t.label_id from replaced, unnest($13::integer[]) as t(label_id)
+    on conflict (todo_id, label_id) do nothing
+)
+select count(*) from replaced;
+"#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(payload.text)
+        .bind(payload.completed)
+        .bind(payload.due_date)
+        .bind(priority)
+        .bind(payload.recurrence.map(Json))
+        .bind(payload.remind_at)
+        .bind(self.now)
+        .bind(payload.version)
+        .bind(payload.precondition.version)
+        .bind(payload.precondition.modified_before)
+        .bind(payload.labels.clone())
+        .fetch_one(&mut self.tx)
+        .await?;
+        if replaced == 0 {
+            if !payload.precondition.matches(&old) {
+                return Err(RepositoryError::PreconditionFailed(id));
+            }
+            return Err(RepositoryError::Conflict(id));
+        }
+        self.labels.extend(payload.labels);
+
+        self.updated(user_id, old, completed_now).await
+    }
+
+    // 更新後の状態を読み直し、履歴とイベントを記録する。完了した繰り返しTodoは次の回を作成する
+    async fn updated(
+        &mut self,
+        user_id: i32,
+        old: TodoEntity,
+        completed_now: bool,
+    ) -> Result<TodoEntity, RepositoryError> {
+        let new = self.entity(user_id, old.id).await?;
+        let changes = TodoChange::updated(&old, &new).into_iter().collect();
+        self.record_activities(user_id, changes).await?;
+        let mut events = vec![TodoEvent::Updated {
+            todo: new.clone(),
+            completed_now,
+        }];
+        if let Some(recurrence) = recurrence_to_spawn(&old, &new) {
+            let next =
+                TodoRepositoryForDb::spawn_next_occurrence(&mut self.tx, user_id, &new, recurrence)
+                    .await?;
+            events.push(TodoEvent::Created { todo: next });
+        }
+        self.record_events(user_id, events).await?;
+        Ok(new)
+    }
+
+    pub async fn record_activities(
+        &mut self,
+        user_id: i32,
+        changes: Vec<TodoChange>,
+    ) -> Result<(), RepositoryError> {
+        TodoRepositoryForDb::record_activities(&mut self.tx, user_id, changes, self.now).await
+    }
+
+    // commitした後に配信タスクがWebhookへ送る
+    pub async fn record_events(
+        &mut self,
+        user_id: i32,
+        events: Vec<TodoEvent>,
+    ) -> Result<(), RepositoryError> {
+        record_events(&mut self.tx, user_id, events, self.now).await
+    }
+
+    async fn attach_labels(&mut self, id: i64, labels: Vec<i32>) -> Result<(), RepositoryError> {
+        sqlx::query(
+            r#"
+insert into todo_labels (todo_id, label_id) select $1, id from unnest($2) as t(id)
+on conflict (todo_id, label_id) do nothing;
+"#,
+        )
+        .bind(id)
+        .bind(labels.clone())
+        .execute(&mut self.tx)
+        .await?;
+        self.labels.extend(labels);
+        Ok(())
+    }
+
+    async fn entity(&mut self, user_id: i32, id: i64) -> Result<TodoEntity, RepositoryError> {
+        TodoRepositoryForDb::entities_in(&mut self.tx, user_id, &[id])
+            .await?
+            .pop()
+            .ok_or(RepositoryError::NotFound(Some(id)))
+    }
+}
+
+#[async_trait]
+impl TodoRepository for TodoRepositoryForDb {
+    #[instrument(name = "todo.create", skip_all, fields(db.operation = "INSERT"))]
+    async fn create(
+        &self,
+        user_id: i32,
+        payload: CreateTodo,
+    ) -> Result<TodoEntity, RepositoryError> {
+        self.create_idempotent(user_id, payload, None).await
+    }
+
+    #[instrument(name = "todo.create_many", skip_all, fields(db.operation = "INSERT"))]
+    async fn create_many(
+        &self,
+        user_id: i32,
+        payloads: Vec<CreateTodo>,
+    ) -> Result<Vec<TodoEntity>, RepositoryError> {
+        self.create_many_idempotent(user_id, payloads, None).await
+    }
+
+    #[instrument(name = "todo.create_idempotent", skip_all, fields(db.operation = "INSERT"))]
+    async fn create_idempotent(
+        &self,
+        user_id: i32,
+        payload: CreateTodo,
+        key: Option<&str>,
+    ) -> Result<TodoEntity, RepositoryError> {
+        let key = key.map(str::to_string);
+        let todo = self
+            .with_transaction(move |tx| {
+                Box::pin(async move {
+                    let todo = tx.create(user_id, payload).await?;
+                    if let Some(key) = key {
+                        tx.complete_idempotency_key(user_id, &key, &todo).await?;
+                    }
+                    Ok(todo)
+                })
+            })
+            .await?;
+        self.find(user_id, todo.id).await
+    }
+
+    #[instrument(name = "todo.create_many_idempotent", skip_all, fields(db.operation = "INSERT"))]
+    async fn create_many_idempotent(
+        &self,
+        user_id: i32,
+        payloads: Vec<CreateTodo>,
+        key: Option<&str>,
+    ) -> Result<Vec<TodoEntity>, RepositoryError> {
+        let now = self.clock.now();
+        let mut tx = self.pool.begin().await?;
+        let ids = Self::insert_many(&mut tx, user_id, payloads, now).await?;
+        let todos = Self::entities_in(&mut tx, user_id, &ids).await?;
+        if let Some(key) = key {
+            sqlx::query(COMPLETE_IDEMPOTENCY_KEY)
+                .bind(user_id)
+                .bind(key)
+                .bind(Json(idempotent_response(&todos)?))
+                .execute(&mut tx)
+                .await?;
+        }
+        let events = todos
+            .into_iter()
+            .map(|todo| TodoEvent::Created { todo })
+            .collect();
+        record_events(&mut tx, user_id, events, now).await?;
+        tx.commit().await?;
+        // 同一トランザクション内で順に採番しているため、id順が入力順と一致する
+        Ok(self.find_many(user_id, &ids).await?.todos)
+    }
+
+    #[instrument(name = "todo.create_many_with_label_names", skip_all, fields(db.operation = "INSERT"))]
+    async fn create_many_with_label_names(
+        &self,
+        user_id: i32,
+        payloads: Vec<CreateTodoWithLabelNames>,
+    ) -> Result<Vec<TodoEntity>, RepositoryError> {
+        // ラベルの作成もTodoの登録と同じトランザクションで行い、失敗時は両方取り消す
+        let mut tx = self.pool.begin().await?;
+        let mut label_ids: HashMap<String, i32> = HashMap::new();
+        let mut todos = Vec::with_capacity(payloads.len());
+        for payload in payloads {
+            let mut todo = payload.todo;
+            for name in payload.label_names {
+                let key = name.to_lowercase();
+                let id = match label_ids.get(&key) {
+                    Some(id) => *id,
+                    None => {
+                        let (id, _) =
+                            Self::upsert_label(&mut tx, &name, DEFAULT_LABEL_COLOR, None).await?;
+                        label_ids.insert(key, id);
+                        id
+                    }
+                };
+                if !todo.labels.contains(&id) {
+                    todo.labels.push(id);
+                }
+            }
+            todos.push(todo);
+        }
+        let ids = Self::insert_many(&mut tx, user_id, todos, self.clock.now()).await?;
+        tx.commit().await?;
+        Ok(self.find_many(user_id, &ids).await?.todos)
+    }
+
+    #[instrument(name = "todo.find", skip_all, fields(db.operation = "SELECT"))]
+    async fn find(&self, user_id: i32, id: i64) -> Result<TodoEntity, RepositoryError> {
+        let items = sqlx::query_as::<_, TodoWithLabelFromRow>(
+            r#"
+select todos.*, labels.id as label_id, labels.name as label_name, labels.color as label_color,
+       labels.description as label_description
+from todos
+left outer join todo_labels tl on todos.id = tl.todo_id
+left outer join labels on labels.id = tl.label_id
+where todos.id=$1 and todos.user_id=$2 and todos.deleted_at is null;
+"#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+        .map_err(|e| match e {
+            sqlx::Error::RowNotFound => RepositoryError::NotFound(Some(id)),
+            _ => e.into(),
+        })?;
+
+        let todos = fold_entities(items);
+        let todo = todos.first().ok_or(RepositoryError::NotFound(Some(id)))?;
+        Ok(todo.clone())
+    }
+
+    #[instrument(name = "todo.find_many", skip_all, fields(db.operation = "SELECT"))]
+    async fn find_many(&self, user_id: i32, ids: &[i64]) -> Result<FoundTodos, RepositoryError> {
+        if ids.is_empty() {
+            return Ok(FoundTodos::default());
+        }
+        let items = sqlx::query_as::<_, TodoWithLabelFromRow>(
+            r#"
+select todos.*, labels.id as label_id, labels.name as label_name, labels.color as label_color,
+       labels.description as label_description
+from todos
+left outer join todo_labels tl on todos.id = tl.todo_id
+left outer join labels on labels.id = tl.label_id
+where todos.id = any($1) and todos.user_id = $2 and todos.deleted_at is null
+order by todos.id asc;
+"#,
+        )
+        .bind(ids)
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(FoundTodos::new(ids, fold_entities(items)))
+    }
+
+    #[instrument(name = "todo.exists", skip_all, fields(db.operation = "SELECT"))]
+    async fn exists(&self, user_id: i32, id: i64) -> Result<bool, RepositoryError> {
+        let exists = sqlx::query_scalar(
+            "select exists(select 1 from todos where id=$1 and user_id=$2 and deleted_at is null)",
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(exists)
+    }
+
+    #[instrument(name = "todo.find_open_by_text", skip_all, fields(db.operation = "SELECT"))]
+    async fn find_open_by_text(
+        &self,
+        user_id: i32,
+        text: &str,
+    ) -> Result<Option<TodoEntity>, RepositoryError> {
+        let id: Option<i64> = sqlx::query_scalar(
+            r#"
+select id from todos
+where user_id = $1 and text = $2
+  and not completed and deleted_at is null and archived_at is null
+order by id asc
+limit 1
+"#,
+        )
+        .bind(user_id)
+        .bind(text)
+        .fetch_optional(&self.pool)
+        .await?;
+        match id {
+            Some(id) => Ok(Some(self.find(user_id, id).await?)),
+            None => Ok(None),
+        }
+    }
+
+    #[instrument(name = "todo.find_by_client_id", skip_all, fields(db.operation = "SELECT"))]
+    async fn find_by_client_id(
+        &self,
+        user_id: i32,
+        client_id: Uuid,
+    ) -> Result<Option<TodoEntity>, RepositoryError> {
+        let id: Option<i64> = sqlx::query_scalar(
+            "select id from todos where user_id = $1 and client_id = $2 and deleted_at is null",
+        )
+        .bind(user_id)
+        .bind(client_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        match id {
+            Some(id) => Ok(Some(self.find(user_id, id).await?)),
+            None => Ok(None),
+        }
+    }
+
+    #[instrument(name = "todo.labels_for_todos", skip_all, fields(db.operation = "SELECT"))]
+    async fn labels_for_todos(
+        &self,
+        user_id: i32,
+        ids: Vec<i64>,
+    ) -> Result<HashMap<i64, Vec<Label>>, RepositoryError> {
+        let rows = sqlx::query_as::<_, TodoWithLabelFromRow>(
+            r#"
+select todos.*, labels.id as label_id, labels.name as label_name, labels.color as label_color,
+       labels.description as label_description
+from todos
+left outer join todo_labels tl on todos.id = tl.todo_id
+left outer join labels on labels.id = tl.label_id
+where todos.id = any($1) and todos.user_id = $2 and todos.deleted_at is null
+order by todos.id asc, labels.id asc;
+"#,
+        )
+        .bind(ids)
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(fold_entities(rows)
+            .into_iter()
+            .map(|todo| (todo.id, todo.labels))
+            .collect())
+    }
+
+    #[instrument(name = "todo.all", skip_all, fields(db.operation = "SELECT"))]
+    async fn all(&self, user_id: i32, query: TodoListQuery) -> Result<TodoPage, RepositoryError> {
+        // 一覧と件数で期限切れの判定がずれないよう、同じ時刻で比較する
+        let now = self.clock.now();
+        // ラベルとjoinすると行数が増えるため、Todo単位でページングしてからjoinする
+        let sql = format!(
+            r#"
+select todos.*, labels.id as label_id, labels.name as label_name, labels.color as label_color,
+       labels.description as label_description
+from (
+    select * from todos
+    where user_id = $5
+      and deleted_at is null
+      and ($3::boolean is null or completed = $3)
+      and ($4::text is null or text ilike $4)
+      and ($6::timestamptz is null or due_date < $6)
+      and ($7::timestamptz is null or due_date > $7)
+      and ($8::boolean is null or (coalesce(due_date < $11, false) and not completed) = $8)
+      and ($9::smallint is null or priority = $9)
+      and ($10::boolean is true or archived_at is null)
+    order by {order_by} limit $1 offset $2
+) todos
+left outer join todo_labels tl on todos.id = tl.todo_id
+left outer join labels on labels.id = tl.label_id
+order by {order_by};
+"#,
+            order_by = query.order_by_clause()
+        );
+        let items = sqlx::query_as::<_, TodoWithLabelFromRow>(&sql)
+            .bind(query.limit())
+            .bind(query.offset())
+            .bind(query.completed)
+            .bind(query.search_pattern())
+            .bind(user_id)
+            .bind(query.due_before)
+            .bind(query.due_after)
+            .bind(query.overdue)
+            .bind(query.priority())
+            .bind(query.include_archived)
+            .bind(now)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let (total,) = sqlx::query_as::<_, (i64,)>(
+            r#"
+select count(*) from todos
+where user_id = $3
+  and deleted_at is null
+  and ($1::boolean is null or completed = $1)
+  and ($2::text is null or text ilike $2)
+  and ($4::timestamptz is null or due_date < $4)
+  and ($5::timestamptz is null or due_date > $5)
+  and ($6::boolean is null or (coalesce(due_date < $9, false) and not completed) = $6)
+  and ($7::smallint is null or priority = $7)
+  and ($8::boolean is true or archived_at is null);
+"#,
+        )
+        .bind(query.completed)
+        .bind(query.search_pattern())
+        .bind(user_id)
+        .bind(query.due_before)
+        .bind(query.due_after)
+        .bind(query.overdue)
+        .bind(query.priority())
+        .bind(query.include_archived)
+        .bind(now)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(TodoPage {
+            todos: fold_entities(items),
+            total,
+        })
+    }
+
+    #[instrument(name = "todo.last_modified", skip_all, fields(db.operation = "SELECT"))]
+    async fn last_modified(&self, user_id: i32) -> Result<Option<DateTime<Utc>>, RepositoryError> {
+        // ゴミ箱への移動はupdated_atを変えないため、deleted_atも見る
+        let last_modified = sqlx::query_scalar(
+            r#"
+select greatest(
+    (select max(greatest(updated_at, deleted_at)) from todos where user_id = $1),
+    (select modified_at from todo_list_modifications where user_id = $1)
+);
+"#,
+        )
+        .bind(user_id)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(last_modified)
+    }
+
+    #[instrument(name = "todo.update", skip_all, fields(db.operation = "UPDATE"))]
+    async fn update(
+        &self,
+        user_id: i32,
+        id: i64,
+        payload: UpdateTodo,
+    ) -> Result<TodoEntity, RepositoryError> {
+        self.with_transaction(move |tx| Box::pin(tx.update(user_id, id, payload)))
+            .await?;
+        self.find(user_id, id).await
+    }
+
+    #[instrument(name = "todo.replace", skip_all, fields(db.operation = "UPDATE"))]
+    async fn replace(
+        &self,
+        user_id: i32,
+        id: i64,
+        payload: ReplaceTodo,
+    ) -> Result<TodoEntity, RepositoryError> {
+        self.with_transaction(move |tx| Box::pin(tx.replace(user_id, id, payload)))
+            .await?;
+        self.find(user_id, id).await
+    }
+
+    #[instrument(name = "todo.update_many", skip_all, fields(db.operation = "UPDATE"))]
+    async fn update_many(
+        &self,
+        user_id: i32,
+        payload: UpdateTodos,
+    ) -> Result<UpdatedTodos, RepositoryError> {
+        let now = self.clock.now();
+        let completed_now = payload.completes();
+        let mut tx = self.pool.begin().await?;
+        let old = Self::entities_in(&mut tx, user_id, &payload.ids).await?;
+        let updated: Vec<i64> = sqlx::query_as::<_, (i64,)>(
+            r#"
+update todos
+set text = coalesce($2, text), completed = coalesce($3, completed),
+    updated_at = $5, version = version + 1
+where id = any($1) and user_id = $4 and deleted_at is null
+returning id;
+"#,
+        )
+        .bind(payload.ids.clone())
+        .bind(payload.text)
+        .bind(payload.completed)
+        .bind(user_id)
+        .bind(now)
+        .fetch_all(&mut tx)
+        .await?
+        .into_iter()
+        .map(|(id,)| id)
+        .collect();
+
+        let new = Self::entities_in(&mut tx, user_id, &updated).await?;
+        let changes = new
+            .iter()
+            .filter_map(|new| {
+                let old = old.iter().find(|old| old.id == new.id)?;
+                TodoChange::updated(old, new)
+            })
+            .collect();
+        Self::record_activities(&mut tx, user_id, changes, now).await?;
+        let events = new
+            .iter()
+            .map(|todo| TodoEvent::Updated {
+                todo: todo.clone(),
+                completed_now,
+            })
+            .collect();
+        record_events(&mut tx, user_id, events, now).await?;
+        tx.commit().await?;
+
+        // 更新後の状態はロックしたまま読んでいるため、commit後に読み直さない
+        Ok(UpdatedTodos::new(&payload.ids, new))
+    }
+
+    #[instrument(name = "todo.delete", skip_all, fields(db.operation = "UPDATE"))]
+    async fn delete(
+        &self,
+        user_id: i32,
+        id: i64,
+        precondition: Precondition,
+    ) -> Result<(), RepositoryError> {
+        // 行は残したままゴミ箱へ移す
+        let now = self.clock.now();
+        let mut tx = self.pool.begin().await?;
+        let result = sqlx::query(
+            r#"
+update todos set deleted_at = $3
+where id = $1 and user_id = $2 and deleted_at is null
+  and ($4::integer is null or version = $4)
+  and ($5::timestamptz is null or updated_at < $5);
+"#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(now)
+        .bind(precondition.version)
+        .bind(precondition.modified_before)
+        .execute(&mut tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            // 前提条件を満たさなかったのか、存在しないのかを区別する
+            let exists = !precondition.is_none()
+                && !Self::entities_in(&mut tx, user_id, &[id]).await?.is_empty();
+            if exists {
+                return Err(RepositoryError::PreconditionFailed(id));
+            }
+            return Err(RepositoryError::NotFound(Some(id)));
+        }
+        Self::record_activities(&mut tx, user_id, vec![TodoChange::deleted(id)], now).await?;
+        record_events(&mut tx, user_id, vec![TodoEvent::Deleted { id }], now).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    #[instrument(name = "todo.delete_permanently", skip_all, fields(db.operation = "DELETE"))]
+    async fn delete_permanently(&self, user_id: i32, id: i64) -> Result<(), RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "delete from todo_labels where todo_id = (select id from todos where id=$1 and user_id=$2)",
+        )
+            .bind(id)
+            .bind(user_id)
+            .execute(&mut tx)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => RepositoryError::NotFound(Some(id)),
+                _ => e.into(),
+            })?;
+
+        let result = sqlx::query("delete from todos where id=$1 and user_id=$2")
+            .bind(id)
+            .bind(user_id)
+            .execute(&mut tx)
+            .await
+            .map_err(|e| match e {
+                sqlx::Error::RowNotFound => RepositoryError::NotFound(Some(id)),
+                _ => e.into(),
+            })?;
+        if result.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound(Some(id)));
+        }
+        let now = self.clock.now();
+        Self::touch_lists(&mut tx, &[user_id], now).await?;
+        record_events(&mut tx, user_id, vec![TodoEvent::Deleted { id }], now).await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    #[instrument(name = "todo.trash", skip_all, fields(db.operation = "SELECT"))]
+    async fn trash(&self, user_id: i32) -> Result<Vec<TodoEntity>, RepositoryError> {
+        let items = sqlx::query_as::<_, TodoWithLabelFromRow>(
+            r#"
+select todos.*, labels.id as label_id, labels.name as label_name, labels.color as label_color,
+       labels.description as label_description
+from todos
+left outer join todo_labels tl on todos.id = tl.todo_id
+left outer join labels on labels.id = tl.label_id
+where todos.user_id = $1 and todos.deleted_at is not null
+order by todos.deleted_at desc, todos.id desc;
+"#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(fold_entities(items))
+    }
+
+    #[instrument(name = "todo.restore", skip_all, fields(db.operation = "UPDATE"))]
+    async fn restore(&self, user_id: i32, id: i64) -> Result<TodoEntity, RepositoryError> {
+        let now = self.clock.now();
+        let mut tx = self.pool.begin().await?;
+        let result = sqlx::query(
+            r#"
+update todos set deleted_at = null, updated_at = $3, version = version + 1
+where id = $1 and user_id = $2 and deleted_at is not null;
+"#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(now)
+        .execute(&mut tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound(Some(id)));
+        }
+        // 配信先からはゴミ箱の外へ戻ったTodoを新たに作成されたものとして扱う
+        let events = Self::entities_in(&mut tx, user_id, &[id])
+            .await?
+            .into_iter()
+            .map(|todo| TodoEvent::Created { todo })
+            .collect();
+        record_events(&mut tx, user_id, events, now).await?;
+        tx.commit().await?;
+        self.find(user_id, id).await
+    }
+
+    #[instrument(name = "todo.delete_completed", skip_all, fields(db.operation = "DELETE"))]
+    async fn delete_completed(&self, user_id: i32) -> Result<u64, RepositoryError> {
+        // todo_labelsの外部キーは遅延評価のため、1文でTodoと関連を同時に削除できる
+        let mut tx = self.pool.begin().await?;
+        let (deleted,) = sqlx::query_as::<_, (i64,)>(
+            r#"
+with purged as (
+    delete from todos
+    where user_id = $1 and completed = true and deleted_at is null
+    returning id
+), purged_labels as (
+    delete from todo_labels where todo_id in (select id from purged)
+)
+select count(*) from purged;
+"#,
+        )
+        .bind(user_id)
+        .fetch_one(&mut tx)
+        .await?;
+        if deleted > 0 {
+            Self::touch_lists(&mut tx, &[user_id], self.clock.now()).await?;
+        }
+        tx.commit().await?;
+        Ok(deleted as u64)
+    }
+
+    #[instrument(name = "todo.purge_deleted_before", skip_all, fields(db.operation = "DELETE"))]
+    async fn purge_deleted_before(&self, cutoff: DateTime<Utc>) -> Result<u64, RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        let owners: Vec<Option<i32>> = sqlx::query_scalar(
+            r#"
+with purged as (
+    delete from todos where deleted_at < $1 returning id, user_id
+), purged_labels as (
+    delete from todo_labels where todo_id in (select id from purged)
+)
+select user_id from purged;
+"#,
+        )
+        .bind(cutoff)
+        .fetch_all(&mut tx)
+        .await?;
+        let user_ids: Vec<i32> = owners.iter().flatten().copied().collect();
+        Self::touch_lists(&mut tx, &user_ids, self.clock.now()).await?;
+        tx.commit().await?;
+        Ok(owners.len() as u64)
+    }
+
+    #[instrument(name = "todo.claim_idempotency_key", skip_all, fields(db.operation = "INSERT"))]
+    async fn claim_idempotency_key(
+        &self,
+        user_id: i32,
+        key: &str,
+        fingerprint: &str,
+    ) -> Result<Option<IdempotencyRecord>, RepositoryError> {
+        // 同じ文のselectは挿入前のスナップショットを見るため、登録済みの記録は別の文で読む
+        // 読む前に取り消された場合は、改めて登録を試みる
+        loop {
+            let inserted = sqlx::query(
+                r#"
+insert into idempotency_keys (user_id, key, fingerprint, created_at) values ($1, $2, $3, $4)
+on conflict (user_id, key) do nothing;
+"#,
+            )
+            .bind(user_id)
+            .bind(key)
+            .bind(fingerprint)
+            .bind(self.clock.now())
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+            if inserted == 1 {
+                return Ok(None);
+            }
+            let record = sqlx::query_as::<_, (String, Option<Json<serde_json::Value>>)>(
+                "select fingerprint, response from idempotency_keys where user_id = $1 and key = $2",
+            )
+            .bind(user_id)
+            .bind(key)
+            .fetch_optional(&self.pool)
+            .await?;
+            if let Some((fingerprint, response)) = record {
+                return Ok(Some(IdempotencyRecord {
+                    fingerprint,
+                    response: response.map(|Json(response)| response),
+                }));
+            }
+        }
+    }
+
+    #[instrument(name = "todo.complete_idempotency_key", skip_all, fields(db.operation = "UPDATE"))]
+    async fn complete_idempotency_key(
+        &self,
+        user_id: i32,
+        key: &str,
+        response: serde_json::Value,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query(COMPLETE_IDEMPOTENCY_KEY)
+            .bind(user_id)
+            .bind(key)
+            .bind(Json(response))
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(name = "todo.release_idempotency_key", skip_all, fields(db.operation = "DELETE"))]
+    async fn release_idempotency_key(
+        &self,
+        user_id: i32,
+        key: &str,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query(RELEASE_IDEMPOTENCY_KEY)
+            .bind(user_id)
+            .bind(key)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(name = "todo.purge_idempotency_keys_before", skip_all, fields(db.operation = "DELETE"))]
+    async fn purge_idempotency_keys_before(
+        &self,
+        cutoff: DateTime<Utc>,
+    ) -> Result<u64, RepositoryError> {
+        let purged = sqlx::query("delete from idempotency_keys where created_at < $1")
+            .bind(cutoff)
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+        Ok(purged)
+    }
+
+    #[instrument(name = "todo.attach_label", skip_all, fields(db.operation = "INSERT"))]
+    async fn attach_label(
+        &self,
+        user_id: i32,
+        id: i64,
+        label_id: i32,
+    ) -> Result<TodoEntity, RepositoryError> {
+        if !self.exists(user_id, id).await? {
+            return Err(RepositoryError::NotFound(Some(id)));
+        }
+        self.find_label(label_id).await?;
+
+        let now = self.clock.now();
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            r#"
+insert into todo_labels (todo_id, label_id) values ($1, $2)
+on conflict (todo_id, label_id) do nothing;
+"#,
+        )
+        .bind(id)
+        .bind(label_id)
+        .execute(&mut tx)
+        .await?;
+        Self::touch(&mut tx, id, now).await?;
+        Self::record_updated(&mut tx, user_id, id, now).await?;
+        tx.commit().await?;
+
+        self.find(user_id, id).await
+    }
+
+    #[instrument(name = "todo.detach_label", skip_all, fields(db.operation = "DELETE"))]
+    async fn detach_label(
+        &self,
+        user_id: i32,
+        id: i64,
+        label_id: i32,
+    ) -> Result<TodoEntity, RepositoryError> {
+        if !self.exists(user_id, id).await? {
+            return Err(RepositoryError::NotFound(Some(id)));
+        }
+        self.find_label(label_id).await?;
+
+        let now = self.clock.now();
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("delete from todo_labels where todo_id = $1 and label_id = $2")
+            .bind(id)
+            .bind(label_id)
+            .execute(&mut tx)
+            .await?;
+        Self::touch(&mut tx, id, now).await?;
+        Self::record_updated(&mut tx, user_id, id, now).await?;
+        tx.commit().await?;
+
+        self.find(user_id, id).await
+    }
+
+    #[instrument(name = "todo.export", skip_all, fields(db.operation = "SELECT"))]
+    async fn export(&self, user_id: i32) -> Result<Backup, RepositoryError> {
+        let items = sqlx::query_as::<_, TodoWithLabelFromRow>(
+            r#"
+select todos.*, labels.id as label_id, labels.name as label_name, labels.color as label_color,
+       labels.description as label_description
+from todos
+left outer join todo_labels tl on todos.id = tl.todo_id
+left outer join labels on labels.id = tl.label_id
+where todos.user_id=$1 and todos.deleted_at is null
+order by todos.id asc;
+"#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+        let labels = sqlx::query_as::<_, Label>("select * from labels order by id asc")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(Backup::new(fold_entities(items), labels))
+    }
+
+    #[instrument(name = "todo.import", skip_all, fields(db.operation = "INSERT"))]
+    async fn import(&self, user_id: i32, backup: Backup) -> Result<ImportSummary, RepositoryError> {
+        // 途中で失敗した場合はtxがdropされ、ドキュメント全体がロールバックされる
+        let mut tx = self.pool.begin().await?;
+        let summary = Self::import_in(&mut tx, user_id, backup).await?;
+        // 取り込んだTodoはupdated_atを引き継ぐため、一覧の変更日時を別に進める
+        Self::touch_lists(&mut tx, &[user_id], self.clock.now()).await?;
+        tx.commit().await?;
+        Ok(summary)
+    }
+
+    #[instrument(name = "todo.reset", skip_all, fields(db.operation = "TRUNCATE"))]
+    async fn reset(&self) -> Result<(), RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        Self::reset_in(&mut tx).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    #[instrument(name = "todo.seed", skip_all, fields(db.operation = "INSERT"))]
+    async fn seed(&self, user_id: i32, fixtures: Backup) -> Result<ImportSummary, RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        let exists: bool = sqlx::query_scalar("select exists(select 1 from users where id = $1)")
+            .bind(user_id)
+            .fetch_one(&mut tx)
+            .await?;
+        if !exists {
+            return Err(RepositoryError::NotFound(Some(user_id.into())));
+        }
+        Self::reset_in(&mut tx).await?;
+        let summary = Self::import_in(&mut tx, user_id, fixtures).await?;
+        Self::touch_lists(&mut tx, &[user_id], self.clock.now()).await?;
+        tx.commit().await?;
+        Ok(summary)
+    }
+
+    fn stream_all(&self, user_id: i32) -> BoxStream<'static, Result<TodoEntity, RepositoryError>> {
+        // fetchのストリームはpoolを借用するため、別タスクで読み込んで有界チャネルで受け渡す
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+        let pool = self.pool.clone();
+        tokio::spawn(async move {
+            let mut rows = sqlx::query_as::<_, TodoWithLabelFromRow>(
+                r#"
+select todos.*, labels.id as label_id, labels.name as label_name, labels.color as label_color,
+       labels.description as label_description
+from todos
+left outer join todo_labels tl on todos.id = tl.todo_id
+left outer join labels on labels.id = tl.label_id
+where todos.user_id=$1 and todos.deleted_at is null
+order by todos.id asc, labels.id asc;
+"#,
+            )
+            .bind(user_id)
+            .fetch(&pool);
+
+            // id順のため、同じTodoのラベルの行は連続して現れる
+            let mut current: Option<TodoEntity> = None;
+            loop {
+                let row = match rows.try_next().await {
+                    Ok(Some(row)) => row,
+                    Ok(None) => break,
+                    Err(e) => {
+                        let _ = sender.send(Err(e.into())).await;
+                        return;
+                    }
+                };
+                match current.as_mut() {
+                    Some(todo) if todo.id == row.id => todo.labels.extend(row.label()),
+                    _ => {
+                        if let Some(todo) = current.replace(row.into()) {
+                            // 受信側が切断した場合はクエリを打ち切る
+                            if sender.send(Ok(todo)).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                }
+            }
+            if let Some(todo) = current {
+                let _ = sender.send(Ok(todo)).await;
+            }
+        });
+
+        stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|item| (item, receiver))
+        })
+        .boxed()
+    }
+
+    #[instrument(name = "todo.stats", skip_all, fields(db.operation = "SELECT"))]
+    async fn stats(&self, user_id: i32) -> Result<TodoStats, RepositoryError> {
+        // 1回の問い合わせで済むよう、全体の集計とラベルごとの集計をgrouping setsでまとめて行う
+        // ラベルとの結合で行が重複するため、Todoの件数はidのdistinctで数える
+        let rows = sqlx::query_as::<_, TodoStatsFromRow>(
+            r#"
+select grouping(tl.label_id) = 1 as is_total,
+       tl.label_id, labels.name as label_name,
+       count(distinct todos.id) as total,
+       count(distinct todos.id) filter (where todos.completed) as completed,
+       count(distinct todos.id) filter (where todos.created_at >= $2)
+           as created_last_7_days
+from todos
+left outer join todo_labels tl on todos.id = tl.todo_id
+left outer join labels on labels.id = tl.label_id
+where todos.user_id=$1 and todos.deleted_at is null
+group by grouping sets ((), (tl.label_id, labels.name))
+order by tl.label_id asc;
+"#,
+        )
+        .bind(user_id)
+        .bind(self.clock.now() - Duration::days(7))
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut stats = TodoStats::default();
+        for row in rows {
+            match (row.is_total, row.label_id, row.label_name) {
+                (true, _, _) => {
+                    stats.total = row.total;
+                    stats.open = row.total - row.completed;
+                    stats.completed = row.completed;
+                    stats.created_last_7_days = row.created_last_7_days;
+                }
+                (false, Some(id), Some(name)) => stats.labels.push(LabelCount {
+                    id,
+                    name,
+                    count: row.total,
+                }),
+                // ラベルのないTodoの行
+                _ => {}
+            }
+        }
+        Ok(stats)
+    }
+
+    #[instrument(name = "todo.items", skip_all, fields(db.operation = "SELECT"))]
+    async fn items(&self, user_id: i32, id: i64) -> Result<Vec<TodoItem>, RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query_as::<_, (i64,)>(
+            "select id from todos where id=$1 and user_id=$2 and deleted_at is null",
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or(RepositoryError::NotFound(Some(id)))?;
+        let items = Self::items_of(&mut tx, id).await?;
+        tx.commit().await?;
+        Ok(items)
+    }
+
+    #[instrument(name = "todo.create_item", skip_all, fields(db.operation = "INSERT"))]
+    async fn create_item(
+        &self,
+        user_id: i32,
+        id: i64,
+        payload: CreateTodoItem,
+    ) -> Result<TodoItem, RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        Self::lock_owned(&mut tx, user_id, id).await?;
+        let item = sqlx::query_as::<_, TodoItem>(
+            r#"
+insert into todo_items (todo_id, text, position)
+select $1, $2, coalesce(max(position) + 1, 0) from todo_items where todo_id = $1
+returning *;
+"#,
+        )
+        .bind(id)
+        .bind(payload.text)
+        .fetch_one(&mut tx)
+        .await?;
+        tx.commit().await?;
+        Ok(item)
+    }
+
+    #[instrument(name = "todo.update_item", skip_all, fields(db.operation = "UPDATE"))]
+    async fn update_item(
+        &self,
+        user_id: i32,
+        id: i64,
+        item_id: i32,
+        payload: UpdateTodoItem,
+    ) -> Result<TodoItem, RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        Self::lock_owned(&mut tx, user_id, id).await?;
+        let item = sqlx::query_as::<_, TodoItem>(
+            r#"
+update todo_items set text = coalesce($3, text), completed = coalesce($4, completed)
+where id=$1 and todo_id=$2
+returning *;
+"#,
+        )
+        .bind(item_id)
+        .bind(id)
+        .bind(payload.text)
+        .bind(payload.completed)
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or(RepositoryError::NotFound(Some(item_id.into())))?;
+
+        let item = match payload.position {
+            Some(position) => {
+                let mut items = Self::items_of(&mut tx, id).await?;
+                move_item(&mut items, item_id, position);
+                for moved in items.iter() {
+                    sqlx::query("update todo_items set position = $1 where id = $2")
+                        .bind(moved.position)
+                        .bind(moved.id)
+                        .execute(&mut tx)
+                        .await?;
+                }
+                items
+                    .into_iter()
+                    .find(|moved| moved.id == item_id)
+                    .unwrap_or(item)
+            }
+            None => item,
+        };
+        tx.commit().await?;
+        Ok(item)
+    }
+
+    #[instrument(name = "todo.delete_item", skip_all, fields(db.operation = "UPDATE"))]
+    async fn delete_item(
+        &self,
+        user_id: i32,
+        id: i64,
+        item_id: i32,
+    ) -> Result<(), RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        Self::lock_owned(&mut tx, user_id, id).await?;
+        let (position,) = sqlx::query_as::<_, (i32,)>(
+            "delete from todo_items where id=$1 and todo_id=$2 returning position",
+        )
+        .bind(item_id)
+        .bind(id)
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or(RepositoryError::NotFound(Some(item_id.into())))?;
+        // 後続の項目を詰める
+        sqlx::query(
+            "update todo_items set position = position - 1 where todo_id=$1 and position > $2",
+        )
+        .bind(id)
+        .bind(position)
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    #[instrument(name = "todo.duplicate", skip_all, fields(db.operation = "INSERT"))]
+    async fn duplicate(
+        &self,
+        user_id: i32,
+        id: i64,
+        payload: DuplicateTodo,
+    ) -> Result<TodoEntity, RepositoryError> {
+        let now = self.clock.now();
+        let mut tx = self.pool.begin().await?;
+        let (text, priority, created_at, due_date) =
+            sqlx::query_as::<_, (String, Priority, DateTime<Utc>, Option<DateTime<Utc>>)>(
+                r#"
+select text, priority, created_at, due_date from todos
+where id=$1 and user_id=$2 and deleted_at is null
+for share
+"#,
+            )
+            .bind(id)
+            .bind(user_id)
+            .fetch_optional(&mut tx)
+            .await?
+            .ok_or(RepositoryError::NotFound(Some(id)))?;
+
+        let row = sqlx::query_as::<_, TodoFromRow>(INSERT_TODO)
+            .bind(text)
+            .bind(user_id)
+            .bind(payload.due_date(created_at, due_date, now))
+            .bind(priority)
+            .bind(POSITION_GAP)
+            .bind(None::<Json<Recurrence>>)
+            .bind(None::<DateTime<Utc>>)
+            .bind(now)
+            .bind(None::<Uuid>)
+            .fetch_one(&mut tx)
+            .await?;
+        sqlx::query(
+            "insert into todo_labels (todo_id, label_id) select $1, label_id from todo_labels where todo_id = $2",
+        )
+        .bind(row.id)
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+        let todos = Self::entities_in(&mut tx, user_id, &[row.id]).await?;
+        Self::record_activities(
+            &mut tx,
+            user_id,
+            todos.iter().map(TodoChange::created).collect(),
+            now,
+        )
+        .await?;
+        let events = todos
+            .into_iter()
+            .map(|todo| TodoEvent::Created { todo })
+            .collect();
+        record_events(&mut tx, user_id, events, now).await?;
+        tx.commit().await?;
+
+        self.find(user_id, row.id).await
+    }
+
+    #[instrument(name = "todo.archive", skip_all, fields(db.operation = "UPDATE"))]
+    async fn archive(&self, user_id: i32, id: i64) -> Result<TodoEntity, RepositoryError> {
+        let now = self.clock.now();
+        let mut tx = self.pool.begin().await?;
+        let result = sqlx::query(
+            r#"
+update todos set archived_at = $3, updated_at = $3, version = version + 1
+where id=$1 and user_id=$2 and deleted_at is null and archived_at is null
+"#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(now)
+        .execute(&mut tx)
+        .await?;
+        // 既に変更後の状態だった場合は何も変わらないため、記録しない
+        if result.rows_affected() > 0 {
+            Self::record_updated(&mut tx, user_id, id, now).await?;
+        }
+        tx.commit().await?;
+        self.find(user_id, id).await
+    }
+
+    #[instrument(name = "todo.unarchive", skip_all, fields(db.operation = "UPDATE"))]
+    async fn unarchive(&self, user_id: i32, id: i64) -> Result<TodoEntity, RepositoryError> {
+        let now = self.clock.now();
+        let mut tx = self.pool.begin().await?;
+        let result = sqlx::query(
+            r#"
+update todos set archived_at = null, updated_at = $3, version = version + 1
+where id=$1 and user_id=$2 and deleted_at is null and archived_at is not null
+"#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(now)
+        .execute(&mut tx)
+        .await?;
+        if result.rows_affected() > 0 {
+            Self::record_updated(&mut tx, user_id, id, now).await?;
+        }
+        tx.commit().await?;
+        self.find(user_id, id).await
+    }
+
+    #[instrument(name = "todo.archive_completed", skip_all, fields(db.operation = "UPDATE"))]
+    async fn archive_completed(&self, user_id: i32) -> Result<u64, RepositoryError> {
+        let result = sqlx::query(
+            r#"
+update todos set archived_at = $2, updated_at = $2, version = version + 1
+where user_id=$1 and completed = true and deleted_at is null and archived_at is null
+"#,
+        )
+        .bind(user_id)
+        .bind(self.clock.now())
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    #[instrument(name = "todo.move_todo", skip_all, fields(db.operation = "UPDATE"))]
+    async fn move_todo(
+        &self,
+        user_id: i32,
+        id: i64,
+        target: MoveTarget,
+    ) -> Result<TodoEntity, RepositoryError> {
+        // 並行した移動が同じ隙間を使わないよう、ユーザーのTodoをすべてロックしてから計算する
+        let mut tx = self.pool.begin().await?;
+        let ordered = sqlx::query_as::<_, (i64, i64)>(
+            r#"
+select id, position from todos
+where user_id = $1 and deleted_at is null
+order by position desc, id desc
+for update;
+"#,
+        )
+        .bind(user_id)
+        .fetch_all(&mut tx)
+        .await?;
+        for (todo_id, position) in reposition(ordered, id, target)? {
+            sqlx::query("update todos set position = $1 where id = $2")
+                .bind(position)
+                .bind(todo_id)
+                .execute(&mut tx)
+                .await?;
+        }
+        // 表示順はupdated_atを変えないため、一覧の変更日時のみを進める
+        Self::touch_lists(&mut tx, &[user_id], self.clock.now()).await?;
+        tx.commit().await?;
+        self.find(user_id, id).await
+    }
+
+    #[instrument(name = "todo.activity", skip_all, fields(db.operation = "SELECT"))]
+    async fn activity(
+        &self,
+        user_id: i32,
+        id: i64,
+        query: PageQuery,
+    ) -> Result<TodoActivityPage, RepositoryError> {
+        sqlx::query_as::<_, (i64,)>("select id from todos where id=$1 and user_id=$2")
+            .bind(id)
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or(RepositoryError::NotFound(Some(id)))?;
+
+        // 同じトランザクション内の履歴は記録時刻が一致するため、idを第2キーにする
+        let activities = sqlx::query_as::<_, TodoActivity>(
+            r#"
+select * from todo_activities
+where todo_id = $1
+order by created_at desc, id desc
+limit $2 offset $3;
+"#,
+        )
+        .bind(id)
+        .bind(query.limit())
+        .bind(query.offset())
+        .fetch_all(&self.pool)
+        .await?;
+        let (total,) =
+            sqlx::query_as::<_, (i64,)>("select count(*) from todo_activities where todo_id = $1")
+                .bind(id)
+                .fetch_one(&self.pool)
+                .await?;
+
+        Ok(TodoActivityPage { activities, total })
+    }
+
+    #[instrument(name = "todo.due_reminders", skip_all, fields(db.operation = "UPDATE"))]
+    async fn due_reminders(&self, now: DateTime<Utc>) -> Result<Vec<DueReminder>, RepositoryError> {
+        // 取得と通知済みへの変更を1文で行う。他のインスタンスがロックした行は読み飛ばすため、
+        // 複数のインスタンスで実行しても同じTodoを重複して通知しない
+        let mut tx = self.pool.begin().await?;
+        let owners: HashMap<i64, i32> = sqlx::query_as::<_, (i64, i32)>(
+            r#"
+with due as (
+    select id from todos
+    where remind_at <= $1 and reminded_at is null and deleted_at is null and not completed
+    for update skip locked
+)
+update todos set reminded_at = $1
+from due
+where todos.id = due.id
+returning todos.id, todos.user_id;
+"#,
+        )
+        .bind(now)
+        .fetch_all(&mut tx)
+        .await?
+        .into_iter()
+        .collect();
+
+        let mut reminders: Vec<DueReminder> =
+            Self::find_across_users(&mut tx, owners.keys().copied().collect())
+                .await?
+                .into_iter()
+                .filter_map(|todo| {
+                    Some(DueReminder {
+                        user_id: *owners.get(&todo.id)?,
+                        todo,
+                    })
+                })
+                .collect();
+        reminders.sort_by_key(|reminder| (reminder.todo.remind_at, reminder.todo.id));
+        for reminder in reminders.iter() {
+            let events = vec![TodoEvent::Reminded {
+                todo: reminder.todo.clone(),
+            }];
+            record_events(&mut tx, reminder.user_id, events, now).await?;
+        }
+        // reminded_atはupdated_atを変えずに一覧の内容を変える
+        let user_ids: Vec<i32> = owners.values().copied().collect();
+        Self::touch_lists(&mut tx, &user_ids, now).await?;
+        tx.commit().await?;
+        Ok(reminders)
+    }
+}
+
+#[cfg(test)]
+#[cfg(any(feature = "database-test", feature = "sqlite"))]
+mod test {
+    #[cfg(feature = "database-test")]
+    use std::env;
+
+    use chrono::{Duration, TimeZone};
+    #[cfg(feature = "database-test")]
+    use dotenv::dotenv;
+    use proptest::prelude::*;
+    #[cfg(feature = "sqlite")]
+    use sqlx::SqlitePool;
+
+    use crate::clock::test_utils::MockClock;
+    use crate::clock::SystemClock;
+    #[cfg(feature = "database-test")]
+    use crate::repositories::label::LabelRepositoryForDb;
+    #[cfg(feature = "sqlite")]
+    use crate::repositories::label::LabelRepositoryForSqlite;
+    use crate::repositories::label::{CreateLabel, LabelRepository, UpdateLabel};
+    use crate::repositories::test_utils::conformance::run_todo_repository_suite;
+    use crate::repositories::test_utils::fixtures::{scenario, TodoFixture};
+    use crate::repositories::todo_activity::TodoAction;
+    use crate::repositories::user::UserRepository;
+    #[cfg(feature = "database-test")]
+    use crate::repositories::user::UserRepositoryForDb;
+    #[cfg(feature = "sqlite")]
+    use crate::repositories::user::UserRepositoryForSqlite;
+
+    use super::*;
+
+    // 後片付けの漏れはリポジトリを通さず、テーブルを直接数えて確かめる
+    enum TestPool {
+        #[cfg(feature = "database-test")]
+        Postgres(PgPool),
+        #[cfg(feature = "sqlite")]
+        Sqlite(SqlitePool),
+    }
+
+    impl TestPool {
+        async fn count_by_id(&self, sql: &str, id: i64) -> i64 {
+            match self {
+                #[cfg(feature = "database-test")]
+                TestPool::Postgres(pool) => sqlx::query_scalar(sql).bind(id).fetch_one(pool).await,
+                #[cfg(feature = "sqlite")]
+                TestPool::Sqlite(pool) => sqlx::query_scalar(sql).bind(id).fetch_one(pool).await,
+            }
+            .unwrap()
+        }
+
+        async fn count_by_text(&self, sql: &str, text: &str) -> i64 {
+            match self {
+                #[cfg(feature = "database-test")]
+                TestPool::Postgres(pool) => {
+                    sqlx::query_scalar(sql).bind(text).fetch_one(pool).await
+                }
+                #[cfg(feature = "sqlite")]
+                TestPool::Sqlite(pool) => sqlx::query_scalar(sql).bind(text).fetch_one(pool).await,
+            }
+            .unwrap()
+        }
+    }
+
+    #[test]
+    fn fold_entities_test() {
+        let timestamp = Utc::now();
+        let label_1 = Label::new(1, String::from("label 1"));
+        let label_2 = Label::new(2, String::from("label 2"));
+        let rows = vec![
+            TodoWithLabelFromRow {
+                id: 1,
+                client_id: None,
+                text: String::from("todo 1"),
+                completed: false,
+                created_at: timestamp,
+                updated_at: timestamp,
+                deleted_at: None,
+                archived_at: None,
+                version: 1,
+                due_date: None,
+                priority: Priority::Medium,
+                recurrence: None,
+                next_occurrence_id: None,
+                remind_at: None,
+                reminded_at: None,
+                label_id: Some(label_1.id),
+                label_name: Some(label_1.name.clone()),
+                label_color: Some(label_1.color.clone()),
+                label_description: None,
+            },
+            TodoWithLabelFromRow {
+                id: 1,
+                client_id: None,
+                text: String::from("todo 1"),
+                completed: false,
+                created_at: timestamp,
+                updated_at: timestamp,
+                deleted_at: None,
+                archived_at: None,
+                version: 1,
+                due_date: None,
+                priority: Priority::Medium,
+                recurrence: None,
+                next_occurrence_id: None,
+                remind_at: None,
+                reminded_at: None,
+                label_id: Some(label_2.id),
+                label_name: Some(label_2.name.clone()),
+                label_color: Some(label_2.color.clone()),
+                label_description: None,
+            },
+            TodoWithLabelFromRow {
+                id: 2,
+                client_id: None,
+                text: String::from("todo 2"),
+                completed: false,
+                created_at: timestamp,
+                updated_at: timestamp,
+                deleted_at: None,
+                archived_at: None,
+                version: 1,
+                due_date: None,
+                priority: Priority::Medium,
+                recurrence: None,
+                next_occurrence_id: None,
+                remind_at: None,
+                reminded_at: None,
+                label_id: Some(label_1.id),
+                label_name: Some(label_1.name.clone()),
+                label_color: Some(label_1.color.clone()),
+                label_description: None,
+            },
+        ];
+        let res = fold_entities(rows);
+        assert_eq!(
+            res,
+            vec![
+                TodoEntity {
+                    id: 1,
+                    client_id: None,
+                    text: String::from("todo 1"),
+                    completed: false,
+                    labels: vec![label_1.clone(), label_2.clone()],
+                    created_at: timestamp,
+                    updated_at: timestamp,
+                    deleted_at: None,
+                    archived_at: None,
+                    version: 1,
+                    due_date: None,
+                    priority: Priority::Medium,
+                    recurrence: None,
+                    next_occurrence_id: None,
+                    remind_at: None,
+                    reminded_at: None,
+                },
+                TodoEntity {
+                    id: 2,
+                    client_id: None,
+                    text: String::from("todo 2"),
+                    completed: false,
+                    labels: vec![label_1.clone()],
+                    created_at: timestamp,
+                    updated_at: timestamp,
+                    deleted_at: None,
+                    archived_at: None,
+                    version: 1,
+                    due_date: None,
+                    priority: Priority::Medium,
+                    recurrence: None,
+                    next_occurrence_id: None,
+                    remind_at: None,
+                    reminded_at: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn fold_entities_shared_labels_test() {
+        let timestamp = Utc::now();
+        let label = Label::new(1, String::from("shared"));
+        let row = |id: i64, label: Option<&Label>| TodoWithLabelFromRow {
+            id,
+            client_id: None,
+            text: format!("todo {}", id),
+            completed: false,
+            created_at: timestamp,
+            updated_at: timestamp,
+            deleted_at: None,
+            archived_at: None,
+            version: 1,
+            due_date: None,
+            priority: Priority::Medium,
+            recurrence: None,
+            next_occurrence_id: None,
+            remind_at: None,
+            reminded_at: None,
+            label_id: label.map(|l| l.id),
+            label_name: label.map(|l| l.name.clone()),
+            label_color: label.map(|l| l.color.clone()),
+            label_description: None,
+        };
+        // 並び替え結果によっては同じTodoの行が連続しない場合もある
+        let rows = vec![
+            row(3, Some(&label)),
+            row(1, Some(&label)),
+            row(2, None),
+            row(3, Some(&Label::new(2, String::from("other")))),
+            row(4, Some(&label)),
+        ];
+        let res = fold_entities(rows);
+        assert_eq!(
+            vec![3, 1, 2, 4],
+            res.iter().map(|todo| todo.id).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![label.clone(), Label::new(2, String::from("other"))],
+            res[0].labels
+        );
+        assert_eq!(vec![label.clone()], res[1].labels);
+        assert!(res[2].labels.is_empty());
+        assert_eq!(vec![label], res[3].labels);
+    }
+
+    #[test]
+    fn list_query_limit_test() {
+        assert_eq!(TodoListQuery::default().limit(), DEFAULT_LIST_LIMIT);
+        assert_eq!(TodoListQuery::default().offset(), 0);
+        let query = TodoListQuery {
+            limit: Some(10_000),
+            offset: Some(20),
+            ..TodoListQuery::default()
+        };
+        assert_eq!(query.limit(), MAX_LIST_LIMIT);
+        assert_eq!(query.offset(), 20);
+    }
+
+    #[test]
+    fn list_query_search_test() {
+        let query = TodoListQuery {
+            q: Some("  ".to_string()),
+            ..TodoListQuery::default()
+        };
+        assert_eq!(query.search_text(), None);
+        let query = TodoListQuery {
+            q: Some(" 100%_off ".to_string()),
+            ..TodoListQuery::default()
+        };
+        assert_eq!(query.search_text(), Some("100%_off"));
+        assert_eq!(query.search_pattern(), Some("%100\\%\\_off%".to_string()));
+    }
+
+    #[test]
+    fn list_query_order_by_test() {
+        assert_eq!(
+            TodoListQuery::default().order_by_clause(),
+            "todos.position desc, todos.id desc"
+        );
+        let query = TodoListQuery {
+            sort: Some(SortField::Completed),
+            order: Some(SortOrder::Asc),
+            ..TodoListQuery::default()
+        };
+        assert_eq!(query.order_by_clause(), "todos.completed asc, todos.id asc");
+    }
+
+    #[test]
+    fn duplicate_due_date_test() {
+        let created_at = Utc::now() - Duration::days(3);
+        let now = Utc::now();
+        let source_due = Some(created_at + Duration::days(1));
+
+        assert_eq!(
+            Some(now + Duration::days(1)),
+            DuplicateTodo::default().due_date(created_at, source_due, now)
+        );
+        assert_eq!(
+            None,
+            DuplicateTodo::default().due_date(created_at, None, now)
+        );
+        let overridden = DuplicateTodo {
+            due_date: Some(Some(now)),
+        };
+        assert_eq!(Some(now), overridden.due_date(created_at, source_due, now));
+        let cleared = DuplicateTodo {
+            due_date: Some(None),
+        };
+        assert_eq!(None, cleared.due_date(created_at, source_due, now));
+    }
+
+    #[test]
+    fn reposition_test() {
+        let ordered = vec![(3, 3072), (2, 2048), (1, 1024)];
+        assert_eq!(
+            vec![(1, 4096)],
+            reposition(ordered.clone(), 1, MoveTarget::Top).unwrap()
+        );
+        assert_eq!(
+            vec![(1, 2560)],
+            reposition(ordered.clone(), 1, MoveTarget::After(3)).unwrap()
+        );
+        assert_eq!(
+            vec![(3, 1536)],
+            reposition(ordered.clone(), 3, MoveTarget::Before(1)).unwrap()
+        );
+        assert_eq!(
+            vec![(3, 0)],
+            reposition(ordered.clone(), 3, MoveTarget::After(1)).unwrap()
+        );
+
+        // 隙間がない場合は全体を振り直し、値が変わるTodoのみ返す
+        let crowded = vec![(3, 1025), (2, 1024), (1, 1)];
+        assert_eq!(
+            vec![(3, 3072), (1, 2048)],
+            reposition(crowded, 1, MoveTarget::After(3)).unwrap()
+        );
+
+        assert!(matches!(
+            reposition(ordered.clone(), 9, MoveTarget::Top),
+            Err(RepositoryError::NotFound(Some(9)))
+        ));
+        assert!(matches!(
+            reposition(ordered, 1, MoveTarget::After(9)),
+            Err(RepositoryError::NotFound(Some(9)))
+        ));
+    }
+
+    #[test]
+    fn normalize_text_test() {
+        assert_eq!("buy milk", normalize_text("  buy milk  \n"));
+        assert_eq!("buy milk", normalize_text("buy\t\tmilk"));
+        assert_eq!("buy milk", normalize_text("buy\r\n\r\nmilk\r\n"));
+        assert_eq!(
+            "buy milk",
+            normalize_text("\u{FEFF}buy\u{200B} milk\u{2060}")
+        );
+        assert_eq!("", normalize_text(" \u{200B}\t\r\n\u{3000}"));
+        // 全角スペースは1文字だけなら残す
+        assert_eq!(
+            "牛乳\u{3000}買う",
+            normalize_text("\u{3000}牛乳\u{3000}買う\u{3000}")
+        );
+        assert_eq!("牛乳 買う", normalize_text("牛乳\u{3000}\u{3000}買う"));
+        // 結合文字はNFCで1文字にまとめ、絵文字のZWJ・異体字セレクタは残す
+        assert_eq!("caf\u{E9}", normalize_text("cafe\u{301}"));
+        assert_eq!(
+            "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467} \u{2764}\u{FE0F}",
+            normalize_text("\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}  \u{2764}\u{FE0F}")
+        );
+    }
+
+    #[test]
+    fn normalized_text_validation_test() {
+        let create = |text: &str| -> CreateTodo {
+            serde_json::from_value(serde_json::json!({ "text": text, "labels": [] })).unwrap()
+        };
+        assert_eq!(
+            CreateTodo::new("buy milk".to_string(), vec![]),
+            create(" buy\tmilk\r\n")
+        );
+        assert!(create("\u{200B}\u{FEFF}").validate().is_err());
+
+        // 上限はバイト数ではなく正規化後の文字数で数える
+        assert!(create(&"\u{1F600}".repeat(100)).validate().is_ok());
+        assert!(create(&"\u{1F600}".repeat(101)).validate().is_err());
+        assert!(create(&"e\u{301}".repeat(100)).validate().is_ok());
+        assert!(create(&format!("  {}  \n", "a".repeat(100)))
+            .validate()
+            .is_ok());
+
+        let update: UpdateTodo =
+            serde_json::from_value(serde_json::json!({ "text": "\tbuy  milk " })).unwrap();
+        assert_eq!(
+            UpdateTodo::default().with_text("buy milk".to_string()),
+            update
+        );
+        let update: UpdateTodo =
+            serde_json::from_value(serde_json::json!({ "text": " \r\n" })).unwrap();
+        assert!(update.validate().is_err());
+        let update: UpdateTodo = serde_json::from_value(serde_json::json!({})).unwrap();
+        assert_eq!(UpdateTodo::default(), update);
+    }
+
+    #[test]
+    fn update_todo_null_test() {
+        // 消せない項目のnullは省略と同じく何も変更しない
+        for field in ["text", "completed", "labels", "version", "priority"] {
+            let update: UpdateTodo =
+                serde_json::from_value(serde_json::json!({ field: null })).unwrap();
+            assert_eq!(UpdateTodo::default(), update, "{}", field);
+        }
+        // 消せる項目のnullは省略と区別し、値を消す指定になる
+        let update: UpdateTodo = serde_json::from_value(serde_json::json!({
+            "due_date": null,
+            "recurrence": null,
+            "remind_at": null,
+        }))
+        .unwrap();
+        assert_eq!(
+            UpdateTodo {
+                due_date: Some(None),
+                recurrence: Some(None),
+                remind_at: Some(None),
+                ..UpdateTodo::default()
+            },
+            update
+        );
+    }
+
+    fn timestamp() -> impl Strategy<Value = DateTime<Utc>> {
+        (0i64..4_000_000_000, 0u32..1_000_000_000)
+            .prop_map(|(secs, nanos)| Utc.timestamp_opt(secs, nanos).unwrap())
+    }
+
+    fn text() -> impl Strategy<Value = String> {
+        "[ \t]?[a-z\u{3042}-\u{3093}]{1,8}( [a-z]{1,8})?[ \n]?"
+    }
+
+    fn priority() -> impl Strategy<Value = Priority> {
+        proptest::sample::select(Priority::ALL.to_vec())
+    }
+
+    fn recurrence() -> impl Strategy<Value = Recurrence> {
+        prop_oneof![
+            Just(Recurrence::Daily),
+            Just(Recurrence::Weekly),
+            (1..=Recurrence::MAX_DAYS).prop_map(|days| Recurrence::EveryNDays { days }),
+        ]
+    }
+
+    fn label() -> impl Strategy<Value = Label> {
+        (
+            1..1000i32,
+            text(),
+            "#[0-9a-f]{6}",
+            proptest::option::of(text()),
+        )
+            .prop_map(|(id, name, color, description)| Label {
+                id,
+                name,
+                color,
+                description,
+            })
+    }
+
+    fn todo_entity() -> impl Strategy<Value = TodoEntity> {
+        (
+            (
+                any::<i64>(),
+                text(),
+                any::<bool>(),
+                proptest::collection::vec(label(), 0..3),
+                timestamp(),
+                timestamp(),
+                proptest::option::of(timestamp()),
+                proptest::option::of(timestamp()),
+            ),
+            (
+                any::<i32>(),
+                proptest::option::of(timestamp()),
+                priority(),
+                proptest::option::of(recurrence()),
+                proptest::option::of(any::<i64>()),
+                proptest::option::of(timestamp()),
+                proptest::option::of(timestamp()),
+                proptest::option::of(any::<u128>().prop_map(Uuid::from_u128)),
+            ),
+        )
+            .prop_map(
+                |(
+                    (id, text, completed, labels, created_at, updated_at, deleted_at, archived_at),
+                    (
+                        version,
+                        due_date,
+                        priority,
+                        recurrence,
+                        next_occurrence_id,
+                        remind_at,
+                        reminded_at,
+                        client_id,
+                    ),
+                )| TodoEntity {
+                    id,
+                    client_id,
+                    text,
+                    completed,
+                    labels,
+                    created_at,
+                    updated_at,
+                    deleted_at,
+                    archived_at,
+                    version,
+                    due_date,
+                    priority,
+                    recurrence,
+                    next_occurrence_id,
+                    remind_at,
+                    reminded_at,
+                },
+            )
+    }
+
+    // クライアントが組み立てる形。textは受信時に正規化されるため、正規化済みの値のみ生成する
+    fn update_todo() -> impl Strategy<Value = UpdateTodo> {
+        (
+            proptest::option::of(text().prop_map(|text| normalize_text(&text))),
+            proptest::option::of(any::<bool>()),
+            proptest::option::of(proptest::collection::vec(1..1000i32, 0..3)),
+            proptest::option::of(any::<i32>()),
+            proptest::option::of(proptest::option::of(timestamp())),
+            proptest::option::of(priority().prop_map(|priority| priority.to_string())),
+            proptest::option::of(proptest::option::of(recurrence())),
+            proptest::option::of(proptest::option::of(timestamp())),
+        )
+            .prop_map(
+                |(text, completed, labels, version, due_date, priority, recurrence, remind_at)| {
+                    UpdateTodo {
+                        text,
+                        completed,
+                        labels,
+                        version,
+                        due_date,
+                        priority,
+                        recurrence,
+                        remind_at,
+                        precondition: Precondition::default(),
+                    }
+                },
+            )
+    }
+
+    proptest! {
+        #[test]
+        fn todo_entity_round_trip_test(todo in todo_entity()) {
+            let json = serde_json::to_string(&todo).unwrap();
+            prop_assert_eq!(todo, serde_json::from_str::<TodoEntity>(&json).unwrap());
+        }
+
+        // 省略した項目とnullを送った項目が、受信後も区別されたまま復元される
+        #[test]
+        fn update_todo_round_trip_test(update in update_todo()) {
+            let json = serde_json::to_value(&update).unwrap();
+            prop_assert_eq!(update, serde_json::from_value::<UpdateTodo>(json).unwrap());
+        }
+    }
+
+    // 実装間で比べるため、ラベルはidではなく事前に作成した3件の添字で指定する。3は存在しないラベル
+    const PROPERTY_LABELS: usize = 4;
+
+    #[derive(Debug, Clone)]
+    enum Operation {
+        Create {
+            text: String,
+            labels: Vec<usize>,
+            priority: Option<Priority>,
+            due_in_days: Option<i64>,
+        },
+        // 対象は作成済みのTodoの中から添字で選ぶ。stale_versionの場合は古いversionを指定する
+        Update {
+            target: usize,
+            text: Option<String>,
+            completed: Option<bool>,
+            labels: Option<Vec<usize>>,
+            priority: Option<Priority>,
+            due_in_days: Option<Option<i64>>,
+            stale_version: bool,
+        },
+    }
+
+    fn label_indexes() -> impl Strategy<Value = Vec<usize>> {
+        proptest::sample::subsequence((0..PROPERTY_LABELS).collect::<Vec<_>>(), 0..=2)
+    }
+
+    fn operation() -> impl Strategy<Value = Operation> {
+        prop_oneof![
+            (
+                text(),
+                label_indexes(),
+                proptest::option::of(priority()),
+                proptest::option::of(-3..3i64),
+            )
+                .prop_map(|(text, labels, priority, due_in_days)| Operation::Create {
+                    text,
+                    labels,
+                    priority,
+                    due_in_days,
+                }),
+            (
+                any::<usize>(),
+                proptest::option::of(text()),
+                proptest::option::of(any::<bool>()),
+                proptest::option::of(label_indexes()),
+                proptest::option::of(priority()),
+                proptest::option::of(proptest::option::of(-3..3i64)),
+                proptest::bool::weighted(0.1),
+            )
+                .prop_map(
+                    |(target, text, completed, labels, priority, due_in_days, stale_version)| {
+                        Operation::Update {
+                            target,
+                            text,
+                            completed,
+                            labels,
+                            priority,
+                            due_in_days,
+                            stale_version,
+                        }
+                    },
+                ),
+        ]
+    }
+
+    // idや日時は実装ごとに異なるため、比べられる項目のみ取り出す
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct TodoState {
+        text: String,
+        completed: bool,
+        labels: Vec<String>,
+        priority: Priority,
+        due_date: Option<DateTime<Utc>>,
+        version: i32,
+    }
+
+    impl From<&TodoEntity> for TodoState {
+        fn from(todo: &TodoEntity) -> Self {
+            Self {
+                text: todo.text.clone(),
+                completed: todo.completed,
+                labels: todo.labels.iter().map(|label| label.name.clone()).collect(),
+                priority: todo.priority,
+                due_date: todo.due_date,
+                version: todo.version,
+            }
+        }
+    }
+
+    // 各操作の結果(エラーの種類)と最終的な状態を返す
+    // 更新に成功した場合は、指定しなかった項目が変わっていないことも確かめる
+    async fn apply_operations<T: TodoRepository>(
+        repository: &T,
+        user_id: i32,
+        operations: &[Operation],
+    ) -> (Vec<&'static str>, Vec<TodoState>) {
+        let fixture = TodoFixture::new("property labels")
+            .owned_by(user_id)
+            .with_label("property-a")
+            .with_label("property-b")
+            .with_label("property-c")
+            .insert(repository)
+            .await;
+        repository
+            .delete_permanently(user_id, fixture.id)
+            .await
+            .unwrap();
+        let mut label_ids: Vec<i32> = fixture.labels.iter().map(|label| label.id).collect();
+        label_ids.push(i32::MAX);
+        let label_ids =
+            |indexes: &[usize]| -> Vec<i32> { indexes.iter().map(|i| label_ids[*i]).collect() };
+        // 日時の精度が実装で変わらないよう、秒単位の固定の日時から数える
+        let base = Utc.with_ymd_and_hms(2026, 1, 1, 9, 0, 0).unwrap();
+
+        let mut outcomes = vec![];
+        let mut created: Vec<i64> = vec![];
+        for operation in operations {
+            let result = match operation.clone() {
+                Operation::Create {
+                    text,
+                    labels,
+                    priority,
+                    due_in_days,
+                } => {
+                    let mut payload = CreateTodo::new(text, label_ids(&labels));
+                    if let Some(priority) = priority {
+                        payload = payload.with_priority(priority);
+                    }
+                    if let Some(days) = due_in_days {
+                        payload = payload.with_due_date(base + Duration::days(days));
+                    }
+                    repository.create(user_id, payload).await.map(|todo| {
+                        created.push(todo.id);
+                    })
+                }
+                Operation::Update {
+                    target,
+                    text,
+                    completed,
+                    labels,
+                    priority,
+                    due_in_days,
+                    stale_version,
+                } => {
+                    if created.is_empty() {
+                        outcomes.push("skipped");
+                        continue;
+                    }
+                    let id = created[target % created.len()];
+                    let before = repository.find(user_id, id).await.unwrap();
+                    let payload = UpdateTodo {
+                        text: text.map(|text| normalize_text(&text)),
+                        completed,
+                        labels: labels.as_deref().map(label_ids),
+                        version: stale_version.then_some(before.version - 1),
+                        due_date: due_in_days
+                            .map(|days| days.map(|days| base + Duration::days(days))),
+                        priority: priority.map(|priority| priority.to_string()),
+                        ..UpdateTodo::default()
+                    };
+                    repository
+                        .update(user_id, id, payload.clone())
+                        .await
+                        .map(|after| {
+                            assert_eq!(payload.text.as_ref().unwrap_or(&before.text), &after.text);
+                            assert_eq!(
+                                payload.completed.unwrap_or(before.completed),
+                                after.completed
+                            );
+                            if payload.labels.is_none() {
+                                assert_eq!(before.labels, after.labels);
+                            }
+                            assert_eq!(
+                                payload.priority().unwrap_or(before.priority),
+                                after.priority
+                            );
+                            assert_eq!(payload.due_date.unwrap_or(before.due_date), after.due_date);
+                            assert_eq!(before.version + 1, after.version);
+                        })
+                }
+            };
+            outcomes.push(match result {
+                Ok(()) => "ok",
+                Err(RepositoryError::NotFound(_)) => "not_found",
+                Err(RepositoryError::Conflict(_)) => "conflict",
+                Err(RepositoryError::InvalidLabel(_)) => "invalid_label",
+                Err(e) => panic!("unexpected error: {:#}", e),
+            });
+        }
+
+        let query = TodoListQuery {
+            limit: Some(MAX_LIST_LIMIT as u32),
+            sort: Some(SortField::Id),
+            order: Some(SortOrder::Asc),
+            include_archived: Some(true),
+            ..TodoListQuery::default()
+        };
+        let todos = repository.all(user_id, query).await.unwrap().todos;
+        (outcomes, todos.iter().map(TodoState::from).collect())
+    }
+
+    #[cfg(feature = "sqlite")]
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        // 作成・部分更新の任意の列について、インメモリとSQLiteの結果が一致する
+        #[test]
+        fn memory_and_sqlite_agree_test(
+            operations in proptest::collection::vec(operation(), 1..12)
+        ) {
+            let runtime = tokio::runtime::Runtime::new().unwrap();
+            let (memory, sqlite) = runtime.block_on(async {
+                let memory = TodoRepositoryForMemory::new(vec![]);
+                let pool = crate::database::connect_sqlite("sqlite::memory:")
+                    .await
+                    .expect("fail connect sqlite");
+                crate::migration::run_sqlite(&pool)
+                    .await
+                    .expect("fail run sqlite migrations");
+                let user = UserRepositoryForSqlite::new(pool.clone())
+                    .create("property_owner".to_string(), String::new())
+                    .await
+                    .expect("failed create user");
+                let sqlite = TodoRepositoryForSqlite::new(pool, SystemClock);
+                (
+                    apply_operations(&memory, user.id, &operations).await,
+                    apply_operations(&sqlite, user.id, &operations).await,
+                )
+            });
+            prop_assert_eq!(memory, sqlite);
+        }
+    }
+
+    #[cfg(feature = "database-test")]
+    proptest! {
+        // 共有のDBへの接続を伴うため、SQLiteより少ない件数で確かめる
+        #![proptest_config(ProptestConfig::with_cases(8))]
+
+        #[test]
+        fn memory_and_postgres_agree_test(
+            operations in proptest::collection::vec(operation(), 1..12)
+        ) {
+            dotenv().ok();
+            let database_url = env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+            let runtime = tokio::runtime::Runtime::new().unwrap();
+            let (memory, postgres) = runtime.block_on(async {
+                let pool = PgPool::connect(&database_url)
+                    .await
+                    .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+                let username = format!("property_owner_{}", Utc::now().timestamp_micros());
+                let user = UserRepositoryForDb::new(pool.clone())
+                    .create(username, String::new())
+                    .await
+                    .expect("failed create user");
+                let postgres = TodoRepositoryForDb::new(pool, SystemClock);
+                (
+                    apply_operations(&TodoRepositoryForMemory::new(vec![]), user.id, &operations)
+                        .await,
+                    apply_operations(&postgres, user.id, &operations).await,
+                )
+            });
+            prop_assert_eq!(memory, postgres);
+        }
+    }
+
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn crud_scenario() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+
+        let clock = MockClock::default();
+        run_crud_scenario(
+            TodoRepositoryForDb::new(pool.clone(), clock.clone()),
+            LabelRepositoryForDb::new(pool.clone()).with_clock(clock.clone()),
+            UserRepositoryForDb::new(pool.clone()),
+            TestPool::Postgres(pool),
+            clock,
+        )
+        .await;
+    }
+
+    // Postgresを用意できない環境でも、インメモリのSQLiteで同じシナリオを確かめる
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn crud_scenario_sqlite() {
+        let pool = crate::database::connect_sqlite("sqlite::memory:")
+            .await
+            .expect("fail connect sqlite");
+        crate::migration::run_sqlite(&pool)
+            .await
+            .expect("fail run sqlite migrations");
+
+        let clock = MockClock::default();
+        run_crud_scenario(
+            TodoRepositoryForSqlite::new(pool.clone(), clock.clone()),
+            LabelRepositoryForSqlite::new(pool.clone()).with_clock(clock.clone()),
+            UserRepositoryForSqlite::new(pool.clone()),
+            TestPool::Sqlite(pool),
+            clock,
+        )
+        .await;
+    }
+
+    // 共有のPostgresでは他のテストのデータまで消えるため、resetはSQLiteでのみ確かめる
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn reset_and_seed_sqlite() {
+        let pool = crate::database::connect_sqlite("sqlite::memory:")
+            .await
+            .expect("fail connect sqlite");
+        crate::migration::run_sqlite(&pool)
+            .await
+            .expect("fail run sqlite migrations");
+        let repository = TodoRepositoryForSqlite::new(pool.clone(), MockClock::default());
+        let fixtures = crate::handlers::admin::default_fixtures();
+
+        // 存在しないユーザーへはseedせず、既存のデータも消さない
+        let user = UserRepositoryForSqlite::new(pool.clone())
+            .create("seed_owner".to_string(), String::new())
+            .await
+            .expect("failed create user");
+        repository
+            .create(user.id, CreateTodo::new("before seed".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        let e = repository
+            .seed(user.id + 1, fixtures.clone())
+            .await
+            .expect_err("seeded unknown user");
+        assert!(matches!(&e, RepositoryError::NotFound(_)));
+        assert_eq!(
+            1,
+            repository
+                .all(user.id, TodoListQuery::default())
+                .await
+                .unwrap()
+                .total
+        );
+
+        // 何度seedしても、idを採番し直して同じデータになる
+        for _ in 0..2 {
+            let summary = repository
+                .seed(user.id, fixtures.clone())
+                .await
+                .expect("failed seed");
+            assert_eq!(
+                ImportSummary {
+                    todos: 5,
+                    labels: 3
+                },
+                summary
+            );
+            let page = repository
+                .all(user.id, TodoListQuery::default())
+                .await
+                .unwrap();
+            assert_eq!(5, page.total);
+            let todo = repository.find(user.id, 1).await.expect("failed find todo");
+            assert_eq!("write the quarterly report", todo.text);
+            assert_eq!(
+                vec![1],
+                todo.labels.iter().map(|l| l.id).collect::<Vec<_>>()
+            );
+        }
+
+        repository.reset().await.expect("failed reset");
+        assert_eq!(
+            0,
+            repository
+                .all(user.id, TodoListQuery::default())
+                .await
+                .unwrap()
+                .total
+        );
+        let todo = repository
+            .create(user.id, CreateTodo::new("after reset".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        assert_eq!(1, todo.id);
+    }
+
+    // 実行のたびにTodoが増えるため、ユーザーを分けて前回までのデータと混ざらないようにする
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn fixture_scenario() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+        let username = format!("fixture_owner_{}", Utc::now().timestamp_micros());
+        let user = UserRepositoryForDb::new(pool.clone())
+            .create(username, String::new())
+            .await
+            .expect("failed create user");
+
+        run_fixture_scenario(TodoRepositoryForDb::new(pool, SystemClock), user.id).await;
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn fixture_scenario_sqlite() {
+        let pool = crate::database::connect_sqlite("sqlite::memory:")
+            .await
+            .expect("fail connect sqlite");
+        crate::migration::run_sqlite(&pool)
+            .await
+            .expect("fail run sqlite migrations");
+        let user = UserRepositoryForSqlite::new(pool.clone())
+            .create("fixture_owner".to_string(), String::new())
+            .await
+            .expect("failed create user");
+
+        run_fixture_scenario(TodoRepositoryForSqlite::new(pool, SystemClock), user.id).await;
+    }
+
+    // 共有のDBでは前回までのデータと混ざらないよう、実行ごとにユーザーを分ける
+    #[cfg(feature = "database-test")]
+    #[tokio::test]
+    async fn conformance_suite() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+        let username = format!("conformance_owner_{}", Utc::now().timestamp_micros());
+        let user = UserRepositoryForDb::new(pool.clone())
+            .create(username, String::new())
+            .await
+            .expect("failed create user");
+
+        run_todo_repository_suite(
+            || TodoRepositoryForDb::new(pool.clone(), SystemClock),
+            user.id,
+        )
+        .await;
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn conformance_suite_sqlite() {
+        let pool = crate::database::connect_sqlite("sqlite::memory:")
+            .await
+            .expect("fail connect sqlite");
+        crate::migration::run_sqlite(&pool)
+            .await
+            .expect("fail run sqlite migrations");
+        let user = UserRepositoryForSqlite::new(pool.clone())
+            .create("conformance_owner".to_string(), String::new())
+            .await
+            .expect("failed create user");
+
+        run_todo_repository_suite(
+            || TodoRepositoryForSqlite::new(pool.clone(), SystemClock),
+            user.id,
+        )
+        .await;
+    }
+
+    // 空のidではDBへ問い合わせないため、閉じたプールでも失敗しない
+    #[cfg(feature = "sqlite")]
+    #[tokio::test]
+    async fn find_many_with_no_ids_should_not_query_sqlite() {
+        let pool = crate::database::connect_sqlite("sqlite::memory:")
+            .await
+            .expect("fail connect sqlite");
+        let repository = TodoRepositoryForSqlite::new(pool.clone(), SystemClock);
+        pool.close().await;
+
+        assert_eq!(
+            FoundTodos::default(),
+            repository.find_many(1, &[]).await.unwrap()
+        );
+        assert!(repository.find_many(1, &[1]).await.is_err());
+    }
+
+    async fn run_fixture_scenario<T: TodoRepository>(repository: T, user_id: i32) {
+        let scenario = scenario(&repository, user_id).await;
+        // ラベルはユーザー間で共有され、既存のものへ統合されるとidが変わるため名前で比べる
+        let mut names: Vec<&str> = scenario
+            .milk
+            .labels
+            .iter()
+            .map(|label| label.name.as_str())
+            .collect();
+        names.sort_unstable();
+        assert_eq!(vec!["errand", "home"], names);
+        assert!(scenario.milk.completed);
+        assert!(scenario.receipts.archived_at.is_some());
+        assert!(scenario.gym.deleted_at.is_some());
+
+        let stats = repository.stats(user_id).await.expect("failed stats");
+        assert_eq!(
+            (5, 3, 2, 4),
+            (
+                stats.total,
+                stats.open,
+                stats.completed,
+                stats.created_last_7_days
+            )
+        );
+        let mut counts: Vec<(String, i64)> = stats
+            .labels
+            .into_iter()
+            .map(|label| (label.name, label.count))
+            .collect();
+        counts.sort();
+        assert_eq!(
+            vec![
+                ("errand".to_string(), 1),
+                ("home".to_string(), 2),
+                ("work".to_string(), 2)
+            ],
+            counts
+        );
+
+        for (query, expected) in [
+            (
+                TodoListQuery::default(),
+                vec![
+                    &scenario.report,
+                    &scenario.rent,
+                    &scenario.milk,
+                    &scenario.dentist,
+                ],
+            ),
+            (
+                TodoListQuery {
+                    overdue: Some(true),
+                    ..TodoListQuery::default()
+                },
+                vec![&scenario.rent],
+            ),
+            (
+                TodoListQuery {
+                    include_archived: Some(true),
+                    completed: Some(true),
+                    ..TodoListQuery::default()
+                },
+                vec![&scenario.milk, &scenario.receipts],
+            ),
+        ] {
+            let query = TodoListQuery {
+                sort: Some(SortField::Id),
+                order: Some(SortOrder::Asc),
+                ..query
+            };
+            let page = repository.all(user_id, query.clone()).await.unwrap();
+            let ids: Vec<i64> = page.todos.iter().map(|todo| todo.id).collect();
+            let expected: Vec<i64> = expected.iter().map(|todo| todo.id).collect();
+            assert_eq!(expected, ids, "query: {:?}", query);
+        }
+        let trash = repository.trash(user_id).await.unwrap();
+        assert_eq!(
+            vec![scenario.gym.id],
+            trash.iter().map(|todo| todo.id).collect::<Vec<_>>()
+        );
+    }
+
+    async fn run_crud_scenario<T: TodoRepository>(
+        repository: T,
+        labels: impl LabelRepository,
+        users: impl UserRepository,
+        pool: TestPool,
+        clock: MockClock,
+    ) {
+        // label data prepare
+        let label_1 = match labels
+            .create(CreateLabel::new("test label".to_string()))
+            .await
+        {
+            Ok(label) => label,
+            Err(RepositoryError::Duplicate(Some(id))) => labels
+                .find(id)
+                .await
+                .expect("Failed to prepare label data."),
+            Err(e) => panic!("Failed to insert label data. {:#}", e),
+        };
+
+        // user data prepare
+        // 共有のDBには前回の実行のTodoが残るため、実行ごとに別のユーザーで確かめる
+        let username = format!("todo_owner_{}", Utc::now().timestamp_micros());
+        let user = users
+            .create(username, String::new())
+            .await
+            .expect("Failed to insert user data.");
+
+        let todo_text = "[crud_scenario] text";
+
+        // create (前後の空白・改行は除いて保存する)
+        let created = repository
+            .create(
+                user.id,
+                CreateTodo::new(format!(" {}\r\n", todo_text), vec![label_1.id]),
+            )
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(created.text, todo_text);
+        assert!(!created.completed);
+        assert_eq!(*created.labels.first().unwrap(), label_1);
+        assert_eq!(clock.now(), created.created_at);
+        assert_eq!(clock.now(), created.updated_at);
+
+        // create (unknown label)
+        let invalid_text = "[crud_scenario] invalid label";
+        let res = repository
+            .create(
+                user.id,
+                CreateTodo::new(invalid_text.to_string(), vec![label_1.id, i32::MAX]),
+            )
+            .await
+            .expect_err("[create] unknown label returned Ok");
+        assert!(matches!(&res, RepositoryError::InvalidLabel(i32::MAX)));
+        let count = pool
+            .count_by_text("select count(*) from todos where text = $1", invalid_text)
+            .await;
+        assert_eq!(0, count);
+
+        // update (unknown label)
+        let res = repository
+            .update(
+                user.id,
+                created.id,
+                UpdateTodo {
+                    labels: Some(vec![i32::MAX]),
+                    ..UpdateTodo::default()
+                },
+            )
+            .await
+            .expect_err("[update] unknown label returned Ok");
+        assert!(matches!(&res, RepositoryError::InvalidLabel(i32::MAX)));
+
+        // find
+        let todo = repository
+            .find(user.id, created.id)
+            .await
+            .expect("[find] returned Err");
+        assert_eq!(created, todo);
+
+        // find (other user)
+        let res = repository.find(user.id + 1, created.id).await;
+        assert!(res.is_err());
+
+        // find_open_by_text (以前の実行で残ったTodoがあれば、そちらが最も古い)
+        let found = repository
+            .find_open_by_text(user.id, todo_text)
+            .await
+            .expect("[find_open_by_text] returned Err")
+            .expect("[find_open_by_text] returned None");
+        assert_eq!(todo_text, found.text);
+        assert!(!found.completed);
+        assert!(found.id <= created.id);
+        let found = repository
+            .find_open_by_text(user.id + 1, todo_text)
+            .await
+            .expect("[find_open_by_text] returned Err");
+        assert!(found.is_none());
+        let found = repository
+            .find_open_by_text(user.id, "[crud_scenario] missing")
+            .await
+            .expect("[find_open_by_text] returned Err");
+        assert!(found.is_none());
+
+        // labels_for_todos
+        let todo_labels = repository
+            .labels_for_todos(user.id, vec![created.id, i64::MAX])
+            .await
+            .expect("[labels_for_todos] returned Err");
+        assert_eq!(
+            HashMap::from([(created.id, vec![label_1.clone()])]),
+            todo_labels
+        );
+        let todo_labels = repository
+            .labels_for_todos(user.id + 1, vec![created.id])
+            .await
+            .expect("[labels_for_todos] returned Err");
+        assert!(todo_labels.is_empty());
+
+        // all
+        let page = repository
+            .all(user.id, TodoListQuery::default())
+            .await
+            .expect("[all] returned Err");
+        let todo = page.todos.first().unwrap();
+        assert_eq!(created, *todo);
+        assert!(page.total >= 1);
+
+        // all (out of range offset)
+        let page = repository
+            .all(
+                user.id,
+                TodoListQuery {
+                    limit: None,
+                    offset: Some(u32::MAX),
+                    ..TodoListQuery::default()
+                },
+            )
+            .await
+            .expect("[all] returned Err");
+        assert!(page.todos.is_empty());
+
+        // all (shared labels)
+        // ラベルは1回のjoinで取得し、同じラベルを持つTodoやラベルなしのTodoも正しく組み立てる
+        let shared_text = "[crud_scenario] shared label";
+        let shared = repository
+            .create_many(
+                user.id,
+                vec![
+                    CreateTodo::new(shared_text.to_string(), vec![label_1.id]),
+                    CreateTodo::new(shared_text.to_string(), vec![]),
+                    CreateTodo::new(shared_text.to_string(), vec![label_1.id]),
+                ],
+            )
+            .await
+            .expect("[create_many] returned Err");
+        let page = repository
+            .all(
+                user.id,
+                TodoListQuery {
+                    q: Some(shared_text.to_string()),
+                    ..TodoListQuery::default()
+                },
+            )
+            .await
+            .expect("[all] returned Err");
+        for todo in shared.iter() {
+            let listed = page.todos.iter().find(|t| t.id == todo.id).unwrap();
+            assert_eq!(todo.labels, listed.labels);
+            repository
+                .delete_permanently(user.id, todo.id)
+                .await
+                .expect("[delete_permanently] returned Err");
+        }
+
+        // update
+        let updated_text = "[crud_scenario] updated text";
+        let todo = repository
+            .update(
+                user.id,
+                todo.id,
+                UpdateTodo {
+                    text: Some(updated_text.to_string()),
+                    completed: Some(true),
+                    labels: Some(vec![]),
+                    version: None,
+                    due_date: None,
+                    priority: None,
+                    recurrence: None,
+                    remind_at: None,
+                    precondition: Precondition::default(),
+                },
+            )
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(created.id, todo.id);
+        assert_eq!(todo.text, updated_text);
+        assert_eq!(todo.labels.len(), 0);
+        assert_eq!(created.version + 1, todo.version);
+        let res = repository
+            .update(
+                user.id,
+                todo.id,
+                UpdateTodo {
+                    text: Some("[crud_scenario] stale".to_string()),
+                    completed: None,
+                    labels: None,
+                    version: Some(created.version),
+                    due_date: None,
+                    priority: None,
+                    recurrence: None,
+                    remind_at: None,
+                    precondition: Precondition::default(),
+                },
+            )
+            .await
+            .expect_err("[update] stale version returned Ok");
+        assert!(matches!(&res, RepositoryError::Conflict(_)));
+        assert_eq!(
+            updated_text,
+            repository.find(user.id, todo.id).await.unwrap().text
+        );
+        // update with precondition
+        let stale = UpdateTodo::default()
+            .with_text("[crud_scenario] stale".to_string())
+            .with_precondition(Precondition::default().with_version(created.version));
+        let res = repository
+            .update(user.id, todo.id, stale)
+            .await
+            .expect_err("[update] stale precondition returned Ok");
+        assert!(matches!(&res, RepositoryError::PreconditionFailed(_)));
+        let res = repository
+            .update(
+                user.id,
+                todo.id,
+                UpdateTodo::default().with_precondition(
+                    Precondition::default()
+                        .with_unmodified_since(todo.updated_at - Duration::days(1)),
+                ),
+            )
+            .await
+            .expect_err("[update] modified since returned Ok");
+        assert!(matches!(&res, RepositoryError::PreconditionFailed(_)));
+        let todo = repository
+            .update(
+                user.id,
+                todo.id,
+                UpdateTodo::default()
+                    .with_text(updated_text.to_string())
+                    .with_precondition(
+                        Precondition::default()
+                            .with_version(todo.version)
+                            .with_unmodified_since(todo.updated_at),
+                    ),
+            )
+            .await
+            .expect("[update] matching precondition returned Err");
+        assert_eq!(created.version + 2, todo.version);
+
+        // attach label (idempotent)
+        let todo = repository
+            .attach_label(user.id, todo.id, label_1.id)
+            .await
+            .expect("[attach_label] returned Err");
+        assert_eq!(todo.labels, vec![label_1.clone()]);
+        let todo = repository
+            .attach_label(user.id, todo.id, label_1.id)
+            .await
+            .expect("[attach_label] returned Err");
+        assert_eq!(todo.labels, vec![label_1.clone()]);
+        let res = repository.attach_label(user.id, todo.id, i32::MAX).await;
+        assert!(res.is_err());
+
+        // label update (Todoのupdated_atは変えないが、一覧の変更日時は進める)
+        clock.advance(Duration::seconds(1));
+        let label_1 = labels
+            .update(
+                label_1.id,
+                UpdateLabel::new(label_1.name.clone()).with_color("#123456".to_string()),
+            )
+            .await
+            .expect("[label update] returned Err");
+        let last_modified = repository
+            .last_modified(user.id)
+            .await
+            .expect("[last_modified] returned Err");
+        assert!(last_modified >= Some(clock.now()));
+        let todo = repository
+            .find(user.id, todo.id)
+            .await
+            .expect("[find] returned Err");
+        assert_eq!(todo.labels, vec![label_1.clone()]);
+
+        // detach label
+        let todo = repository
+            .detach_label(user.id, todo.id, label_1.id)
+            .await
+            .expect("[detach_label] returned Err");
+        assert_eq!(todo.labels.len(), 0);
+
+        // all (completed filter)
+        let page = repository
+            .all(
+                user.id,
+                TodoListQuery {
+                    completed: Some(false),
+                    ..TodoListQuery::default()
+                },
+            )
+            .await
+            .expect("[all] returned Err");
+        assert!(page.todos.iter().all(|t| t.id != todo.id));
+        let page = repository
+            .all(
+                user.id,
+                TodoListQuery {
+                    completed: Some(true),
+                    ..TodoListQuery::default()
+                },
+            )
+            .await
+            .expect("[all] returned Err");
+        assert!(page.todos.iter().all(|t| t.completed));
+        assert!(page.todos.iter().any(|t| t.id == todo.id));
+
+        // all (sort)
+        let page = repository
+            .all(
+                user.id,
+                TodoListQuery {
+                    sort: Some(SortField::UpdatedAt),
+                    order: Some(SortOrder::Desc),
+                    ..TodoListQuery::default()
+                },
+            )
+            .await
+            .expect("[all] returned Err");
+        assert_eq!(page.todos.first().map(|t| t.id), Some(todo.id));
+        assert!(todo.updated_at >= created.updated_at);
+
+        // all (search)
+        let page = repository
+            .all(
+                user.id,
+                TodoListQuery {
+                    q: Some("[CRUD_SCENARIO] UPDATED".to_string()),
+                    completed: Some(true),
+                    ..TodoListQuery::default()
+                },
+            )
+            .await
+            .expect("[all] returned Err");
+        assert!(page.todos.iter().any(|t| t.id == todo.id));
+        let page = repository
+            .all(
+                user.id,
+                TodoListQuery {
+                    q: Some("[crud_scenario] updated%".to_string()),
+                    ..TodoListQuery::default()
+                },
+            )
+            .await
+            .expect("[all] returned Err");
+        assert!(page.todos.iter().all(|t| t.id != todo.id));
+
+        // all (due date)
+        let overdue = repository
+            .create(
+                user.id,
+                CreateTodo {
+                    text: "[crud_scenario] overdue".to_string(),
+                    labels: vec![],
+                    due_date: Some(Utc::now() - Duration::days(1)),
+                    priority: None,
+                    recurrence: None,
+                    remind_at: None,
+                    client_id: None,
+                },
+            )
+            .await
+            .expect("[create] returned Err");
+        assert!(overdue.due_date.is_some());
+        assert_eq!(Priority::Medium, overdue.priority);
+        let page = repository
+            .all(
+                user.id,
+                TodoListQuery {
+                    priority: Some("medium".to_string()),
+                    sort: Some(SortField::Priority),
+                    ..TodoListQuery::default()
+                },
+            )
+            .await
+            .expect("[all] returned Err");
+        assert!(page.todos.iter().any(|t| t.id == overdue.id));
+        assert!(page.todos.iter().all(|t| t.priority == Priority::Medium));
+        let page = repository
+            .all(
+                user.id,
+                TodoListQuery {
+                    overdue: Some(true),
+                    ..TodoListQuery::default()
+                },
+            )
+            .await
+            .expect("[all] returned Err");
+        assert!(page.todos.iter().any(|t| t.id == overdue.id));
+        assert!(page.todos.iter().all(|t| t.id != todo.id));
+        let page = repository
+            .all(
+                user.id,
+                TodoListQuery {
+                    due_after: overdue.due_date,
+                    ..TodoListQuery::default()
+                },
+            )
+            .await
+            .expect("[all] returned Err");
+        assert!(page.todos.iter().all(|t| t.id != overdue.id));
+        repository
+            .delete_permanently(user.id, overdue.id)
+            .await
+            .expect("[delete_permanently] returned Err");
+
+        // create_many
+        let todos = repository
+            .create_many(
+                user.id,
+                vec![
+                    CreateTodo::new("[crud_scenario] batch 1".to_string(), vec![label_1.id]),
+                    CreateTodo::new("[crud_scenario] batch 2".to_string(), vec![]),
+                ],
+            )
+            .await
+            .expect("[create_many] returned Err");
+        assert_eq!(2, todos.len());
+        assert_eq!("[crud_scenario] batch 1", todos[0].text);
+        assert_eq!(vec![label_1.clone()], todos[0].labels);
+        assert_eq!("[crud_scenario] batch 2", todos[1].text);
+        let res = repository
+            .create_many(
+                user.id,
+                vec![CreateTodo::new(
+                    "[crud_scenario] batch 3".to_string(),
+                    vec![i32::MAX],
+                )],
+            )
+            .await;
+        assert!(res.is_err());
+        for todo in todos {
+            repository
+                .delete_permanently(user.id, todo.id)
+                .await
+                .expect("[delete_permanently] returned Err");
+        }
+
+        // delete
+        let current = repository
+            .find(user.id, todo.id)
+            .await
+            .expect("[find] returned Err");
+        let stale = Precondition::default().with_version(current.version - 1);
+        let res = repository
+            .delete(user.id, todo.id, stale)
+            .await
+            .expect_err("[delete] stale precondition returned Ok");
+        assert!(matches!(&res, RepositoryError::PreconditionFailed(_)));
+        assert!(repository.find(user.id, todo.id).await.is_ok());
+        let matching = Precondition::default()
+            .with_version(current.version)
+            .with_unmodified_since(current.updated_at);
+        repository
+            .delete(user.id, todo.id, matching)
+            .await
+            .expect("[delete] returned Err");
+        let res = repository.find(user.id, created.id).await; // expect not found err
+        assert!(res.is_err());
+        let res = repository
+            .delete(user.id, todo.id, matching)
+            .await
+            .expect_err("[delete] deleted todo returned Ok");
+        assert!(matches!(&res, RepositoryError::NotFound(_)));
+
+        // trash
+        let trashed = repository
+            .trash(user.id)
+            .await
+            .expect("[trash] returned Err");
+        assert_eq!(trashed.first().map(|t| t.id), Some(todo.id));
+        assert!(trashed[0].deleted_at.is_some());
+
+        // restore
+        let restored = repository
+            .restore(user.id, todo.id)
+            .await
+            .expect("[restore] returned Err");
+        assert_eq!(todo.id, restored.id);
+        assert!(restored.deleted_at.is_none());
+        assert!(repository.restore(user.id, todo.id).await.is_err());
+
+        // activity (失敗した変更は記録されない)
+        let page = repository
+            .activity(user.id, todo.id, PageQuery::default())
+            .await
+            .expect("[activity] returned Err");
+        assert_eq!(page.total, page.activities.len() as i64);
+        assert_eq!(TodoAction::Deleted, page.activities[0].action);
+        let completed = page
+            .activities
+            .iter()
+            .find(|activity| activity.action == TodoAction::Completed)
+            .expect("[activity] completed not recorded");
+        assert_eq!(
+            Some(serde_json::json!({
+                "text": updated_text, "completed": true, "labels": [],
+            })),
+            completed.new_value
+        );
+        let created_activity = page.activities.last().unwrap();
+        assert_eq!(TodoAction::Created, created_activity.action);
+        assert_eq!(user.id, created_activity.actor_id);
+        assert!(repository
+            .activity(user.id + 1, todo.id, PageQuery::default())
+            .await
+            .is_err());
+
+        // delete_permanently
+        repository
+            .delete(user.id, todo.id, Precondition::default())
+            .await
+            .expect("[delete] returned Err");
+        repository
+            .delete_permanently(user.id, todo.id)
+            .await
+            .expect("[delete_permanently] returned Err");
+        assert!(repository.restore(user.id, todo.id).await.is_err());
+
+        let todo_rows = pool
+            .count_by_id("select count(*) from todos where id=$1", todo.id)
+            .await;
+        assert_eq!(todo_rows, 0);
+
+        let rows = pool
+            .count_by_id("select count(*) from todo_labels where todo_id=$1", todo.id)
+            .await;
+        assert_eq!(rows, 0);
+
+        let done = repository
+            .create(
+                user.id,
+                CreateTodo::new("[crud_scenario] purge".to_string(), vec![label_1.id]),
+            )
+            .await
+            .expect("[create] returned Err");
+
+        // update_many
+        let res = repository
+            .update_many(
+                user.id,
+                UpdateTodos::new(vec![done.id, i64::MAX], None, Some(true)),
+            )
+            .await
+            .expect("[update_many] returned Err");
+        assert_eq!(
+            vec![done.id],
+            res.todos.iter().map(|t| t.id).collect::<Vec<_>>()
+        );
+        assert!(res.todos[0].completed);
+        assert_eq!(vec![i64::MAX], res.missing);
+
+        // delete_completed
+        let deleted = repository
+            .delete_completed(user.id)
+            .await
+            .expect("[delete_completed] returned Err");
+        assert!(deleted >= 1);
+        assert!(repository.find(user.id, done.id).await.is_err());
+        let rows = pool
+            .count_by_id("select count(*) from todo_labels where todo_id=$1", done.id)
+            .await;
+        assert_eq!(rows, 0);
+        let deleted = repository
+            .delete_completed(user.id)
+            .await
+            .expect("[delete_completed] returned Err");
+        assert_eq!(0, deleted);
+
+        // purge_deleted_before
+        let trashed = repository
+            .create(
+                user.id,
+                CreateTodo::new("[crud_scenario] trashed".to_string(), vec![label_1.id]),
+            )
+            .await
+            .expect("[create] returned Err");
+        repository
+            .delete(user.id, trashed.id, Precondition::default())
+            .await
+            .expect("[delete] returned Err");
+        repository
+            .purge_deleted_before(Utc::now() - Duration::days(1))
+            .await
+            .expect("[purge_deleted_before] returned Err");
+        let trash = repository
+            .trash(user.id)
+            .await
+            .expect("[trash] returned Err");
+        assert!(trash.iter().any(|t| t.id == trashed.id));
+        let purged = repository
+            .purge_deleted_before(Utc::now() + Duration::minutes(1))
+            .await
+            .expect("[purge_deleted_before] returned Err");
+        assert!(purged >= 1);
+        assert!(repository.restore(user.id, trashed.id).await.is_err());
+        let rows = pool
+            .count_by_id(
+                "select count(*) from todo_labels where todo_id=$1",
+                trashed.id,
+            )
+            .await;
+        assert_eq!(rows, 0);
+        // idempotency keys
+        let key = format!("crud-{}", trashed.id);
+        let claimed = repository
+            .claim_idempotency_key(user.id, &key, "fingerprint")
+            .await
+            .expect("[claim_idempotency_key] returned Err");
+        assert_eq!(None, claimed);
+        let pending = repository
+            .claim_idempotency_key(user.id, &key, "fingerprint")
+            .await
+            .expect("[claim_idempotency_key] returned Err");
+        assert_eq!(
+            Some(IdempotencyRecord {
+                fingerprint: "fingerprint".to_string(),
+                response: None,
+            }),
+            pending
+        );
+        repository
+            .complete_idempotency_key(user.id, &key, serde_json::json!({ "id": trashed.id }))
+            .await
+            .expect("[complete_idempotency_key] returned Err");
+        let completed = repository
+            .claim_idempotency_key(user.id, &key, "other")
+            .await
+            .expect("[claim_idempotency_key] returned Err")
+            .expect("completed key was claimed again");
+        assert_eq!("fingerprint", completed.fingerprint);
+        assert_eq!(
+            Some(serde_json::json!({ "id": trashed.id })),
+            completed.response
+        );
+        repository
+            .release_idempotency_key(user.id, &key)
+            .await
+            .expect("[release_idempotency_key] returned Err");
+        // 作成を終えたキーは取り消さない
+        let kept = repository
+            .claim_idempotency_key(user.id, &key, "fingerprint")
+            .await
+            .expect("[claim_idempotency_key] returned Err");
+        assert!(kept.is_some());
+        repository
+            .purge_idempotency_keys_before(clock.now() - Duration::days(1))
+            .await
+            .expect("[purge_idempotency_keys_before] returned Err");
+        assert!(repository
+            .claim_idempotency_key(user.id, &key, "fingerprint")
+            .await
+            .expect("[claim_idempotency_key] returned Err")
+            .is_some());
+        let purged = repository
+            .purge_idempotency_keys_before(clock.now() + Duration::minutes(1))
+            .await
+            .expect("[purge_idempotency_keys_before] returned Err");
+        assert!(purged >= 1);
+        let claimed = repository
+            .claim_idempotency_key(user.id, &key, "fingerprint")
+            .await
+            .expect("[claim_idempotency_key] returned Err");
+        assert_eq!(None, claimed);
+        // 作成したTodoは、同じトランザクションでキーの応答として記録する
+        let recorded = repository
+            .create_idempotent(
+                user.id,
+                CreateTodo::new("[crud_scenario] idempotent".to_string(), vec![]),
+                Some(&key),
+            )
+            .await
+            .expect("[create_idempotent] returned Err");
+        let replayed = repository
+            .claim_idempotency_key(user.id, &key, "fingerprint")
+            .await
+            .expect("[claim_idempotency_key] returned Err")
+            .expect("recorded key was claimed again");
+        assert_eq!(
+            Some(recorded.id),
+            replayed.response.and_then(|r| r["id"].as_i64())
+        );
+        repository
+            .delete(user.id, recorded.id, Precondition::default())
+            .await
+            .expect("[delete] returned Err");
+        // export / import
+        let backed_up = repository
+            .create(
+                user.id,
+                CreateTodo::new("[crud_scenario] backup".to_string(), vec![label_1.id]),
+            )
+            .await
+            .expect("[create] returned Err");
+        let exported = repository
+            .export(user.id)
+            .await
+            .expect("[export] returned Err");
+        assert!(exported.todos.iter().any(|t| t.id == backed_up.id));
+        assert!(exported
+            .associations
+            .iter()
+            .any(|a| a.todo_id == backed_up.id && a.label_id == label_1.id));
+        let summary = repository
+            .import(user.id, exported.clone())
+            .await
+            .expect("[import] returned Err");
+        // 既存のラベルと同名のため、すべて統合される
+        assert_eq!(0, summary.labels);
+        assert_eq!(exported.todos.len(), summary.todos);
+        let reexported = repository
+            .export(user.id)
+            .await
+            .expect("[export] returned Err");
+        assert_eq!(exported.todos.len() * 2, reexported.todos.len());
+
+        // create_many_with_label_names
+        let new_label_name = format!("[crud_scenario] {}", Utc::now().timestamp_millis());
+        let todos = repository
+            .create_many_with_label_names(
+                user.id,
+                vec![
+                    CreateTodoWithLabelNames {
+                        todo: CreateTodo::new("[crud_scenario] named 1".to_string(), vec![]),
+                        label_names: vec![label_1.name.to_uppercase(), new_label_name.clone()],
+                    },
+                    CreateTodoWithLabelNames {
+                        todo: CreateTodo::new("[crud_scenario] named 2".to_string(), vec![]),
+                        label_names: vec![new_label_name.clone()],
+                    },
+                ],
+            )
+            .await
+            .expect("[create_many_with_label_names] returned Err");
+        assert_eq!(label_1, todos[0].labels[0]);
+        assert_eq!(new_label_name, todos[0].labels[1].name);
+        assert_eq!(todos[0].labels[1], todos[1].labels[0]);
+
+        // stream_all
+        let exported = repository
+            .export(user.id)
+            .await
+            .expect("[export] returned Err");
+        let streamed: Vec<TodoEntity> = repository
+            .stream_all(user.id)
+            .try_collect()
+            .await
+            .expect("[stream_all] returned Err");
+        assert_eq!(
+            exported.todos.iter().map(|t| t.id).collect::<Vec<_>>(),
+            streamed.iter().map(|t| t.id).collect::<Vec<_>>()
+        );
+        let streamed_backup = streamed.iter().find(|t| t.id == backed_up.id).unwrap();
+        assert_eq!(vec![label_1.clone()], streamed_backup.labels);
+
+        // stats
+        let stats = repository
+            .stats(user.id)
+            .await
+            .expect("[stats] returned Err");
+        let completed = streamed.iter().filter(|t| t.completed).count() as i64;
+        assert_eq!(streamed.len() as i64, stats.total);
+        assert_eq!(completed, stats.completed);
+        assert_eq!(stats.total - completed, stats.open);
+        assert!(stats.created_last_7_days <= stats.total);
+        let label_1_count = streamed
+            .iter()
+            .filter(|t| t.labels.contains(&label_1))
+            .count() as i64;
+        assert_eq!(
+            Some(label_1_count),
+            stats
+                .labels
+                .iter()
+                .find(|label| label.id == label_1.id)
+                .map(|label| label.count)
+        );
+
+        // items
+        let mut steps = vec![];
+        for text in ["step 1", "step 2"] {
+            let step = repository
+                .create_item(
+                    user.id,
+                    backed_up.id,
+                    CreateTodoItem {
+                        text: text.to_string(),
+                    },
+                )
+                .await
+                .expect("[create_item] returned Err");
+            steps.push(step);
+        }
+        assert_eq!(
+            vec![0, 1],
+            steps.iter().map(|s| s.position).collect::<Vec<_>>()
+        );
+        let moved = repository
+            .update_item(
+                user.id,
+                backed_up.id,
+                steps[1].id,
+                UpdateTodoItem {
+                    completed: Some(true),
+                    position: Some(0),
+                    ..Default::default()
+                },
+            )
+            .await
+            .expect("[update_item] returned Err");
+        assert!(moved.completed);
+        assert_eq!(0, moved.position);
+        repository
+            .delete_item(user.id, backed_up.id, moved.id)
+            .await
+            .expect("[delete_item] returned Err");
+        let items = repository
+            .items(user.id, backed_up.id)
+            .await
+            .expect("[items] returned Err");
+        assert_eq!(
+            vec![(steps[0].id, 0)],
+            items.iter().map(|i| (i.id, i.position)).collect::<Vec<_>>()
+        );
+        assert!(repository
+            .delete_item(user.id, backed_up.id, moved.id)
+            .await
+            .is_err());
+
+        // Todoを完全に削除すると項目も削除される
+        repository.delete(user.id, backed_up.id, Precondition::default()).await.unwrap();
+        repository
+            .delete_permanently(user.id, backed_up.id)
+            .await
+            .expect("[delete_permanently] returned Err");
+        let remaining = pool
+            .count_by_id(
+                "select count(*) from todo_items where todo_id = $1",
+                backed_up.id,
+            )
+            .await;
+        assert_eq!(0, remaining);
+
+        // move_todo
+        let lower = repository
+            .create(user.id, CreateTodo::new("lower".to_string(), vec![]))
+            .await
+            .expect("[create] returned Err");
+        let upper = repository
+            .create(user.id, CreateTodo::new("upper".to_string(), vec![]))
+            .await
+            .expect("[create] returned Err");
+        let first_two =
+            |page: TodoPage| page.todos.iter().take(2).map(|t| t.id).collect::<Vec<_>>();
+        let page = repository
+            .all(user.id, TodoListQuery::default())
+            .await
+            .unwrap();
+        assert_eq!(vec![upper.id, lower.id], first_two(page));
+        repository
+            .move_todo(user.id, lower.id, MoveTarget::Top)
+            .await
+            .expect("[move_todo] returned Err");
+        let page = repository
+            .all(user.id, TodoListQuery::default())
+            .await
+            .unwrap();
+        assert_eq!(vec![lower.id, upper.id], first_two(page));
+        assert!(repository
+            .move_todo(user.id, lower.id, MoveTarget::After(-1))
+            .await
+            .is_err());
+
+        // archive
+        let archived = repository
+            .archive(user.id, lower.id)
+            .await
+            .expect("[archive] returned Err");
+        assert!(archived.archived_at.is_some());
+        assert_eq!(
+            archived,
+            repository
+                .archive(user.id, lower.id)
+                .await
+                .expect("[archive] returned Err")
+        );
+        let listed = repository
+            .all(user.id, TodoListQuery::default())
+            .await
+            .unwrap();
+        assert!(listed.todos.iter().all(|t| t.id != lower.id));
+        let listed = repository
+            .all(
+                user.id,
+                TodoListQuery {
+                    include_archived: Some(true),
+                    ..TodoListQuery::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert!(listed.todos.iter().any(|t| t.id == lower.id));
+        let unarchived = repository
+            .unarchive(user.id, lower.id)
+            .await
+            .expect("[unarchive] returned Err");
+        assert!(unarchived.archived_at.is_none());
+        assert!(repository.archive(user.id, -1).await.is_err());
+
+        repository
+            .update(
+                user.id,
+                upper.id,
+                UpdateTodo {
+                    completed: Some(true),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert!(
+            repository
+                .archive_completed(user.id)
+                .await
+                .expect("[archive_completed] returned Err")
+                >= 1
+        );
+        assert!(repository
+            .find(user.id, upper.id)
+            .await
+            .unwrap()
+            .archived_at
+            .is_some());
+
+        // duplicate
+        let source = repository
+            .create(
+                user.id,
+                CreateTodo::new("template".to_string(), vec![label_1.id])
+                    .with_priority(Priority::High)
+                    .with_due_date(Utc::now() + Duration::days(7)),
+            )
+            .await
+            .expect("[create] returned Err");
+        let copy = repository
+            .duplicate(user.id, source.id, DuplicateTodo::default())
+            .await
+            .expect("[duplicate] returned Err");
+        assert_ne!(source.id, copy.id);
+        assert_eq!(
+            (source.text.clone(), source.priority, source.labels.clone()),
+            (copy.text.clone(), copy.priority, copy.labels.clone())
+        );
+        assert!(copy.due_date.is_some());
+        let copy = repository
+            .duplicate(
+                user.id,
+                source.id,
+                DuplicateTodo {
+                    due_date: Some(None),
+                },
+            )
+            .await
+            .expect("[duplicate] returned Err");
+        assert_eq!(None, copy.due_date);
+        assert!(repository
+            .duplicate(user.id, -1, DuplicateTodo::default())
+            .await
+            .is_err());
+
+        // recurrence
+        let due_date = Utc::now() + Duration::days(1);
+        let recurring = repository
+            .create(
+                user.id,
+                CreateTodo::new("[crud_scenario] water plants".to_string(), vec![label_1.id])
+                    .with_due_date(due_date)
+                    .with_recurrence(Recurrence::EveryNDays { days: 3 }),
+            )
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(
+            Some(Recurrence::EveryNDays { days: 3 }),
+            repository
+                .find(user.id, recurring.id)
+                .await
+                .unwrap()
+                .recurrence
+        );
+        let complete = UpdateTodo {
+            completed: Some(true),
+            ..Default::default()
+        };
+        let completed = repository
+            .update(user.id, recurring.id, complete.clone())
+            .await
+            .expect("[update] returned Err");
+        let next_id = completed
+            .next_occurrence_id
+            .expect("[update] did not spawn next occurrence");
+        let next = repository.find(user.id, next_id).await.unwrap();
+        assert!(!next.completed);
+        assert_eq!(completed.recurrence, next.recurrence);
+        assert_eq!(completed.labels, next.labels);
+        assert_eq!(
+            (due_date + Duration::days(3)).timestamp(),
+            next.due_date.unwrap().timestamp()
+        );
+        let again = repository
+            .update(user.id, recurring.id, complete)
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(Some(next_id), again.next_occurrence_id);
+        repository
+            .delete_permanently(user.id, next_id)
+            .await
+            .expect("[delete_permanently] returned Err");
+        assert_eq!(
+            None,
+            repository
+                .find(user.id, recurring.id)
+                .await
+                .unwrap()
+                .next_occurrence_id
+        );
+
+        // reminder
+        let now = Utc::now();
+        let reminded = repository
+            .create(
+                user.id,
+                CreateTodo::new("[crud_scenario] reminder".to_string(), vec![])
+                    .with_remind_at(now - Duration::minutes(1)),
+            )
+            .await
+            .expect("[create] returned Err");
+        // 同時に実行しても、通知対象として返すのはどちらか一方だけ
+        let (first, second) =
+            tokio::join!(repository.due_reminders(now), repository.due_reminders(now));
+        let notified: Vec<DueReminder> = first
+            .expect("[due_reminders] returned Err")
+            .into_iter()
+            .chain(second.expect("[due_reminders] returned Err"))
+            .filter(|reminder| reminder.todo.id == reminded.id)
+            .collect();
+        assert_eq!(1, notified.len());
+        assert_eq!(user.id, notified[0].user_id);
+        assert!(notified[0].todo.reminded_at.is_some());
+        assert!(repository
+            .due_reminders(now)
+            .await
+            .expect("[due_reminders] returned Err")
+            .iter()
+            .all(|reminder| reminder.todo.id != reminded.id));
+        let rescheduled = repository
+            .update(
+                user.id,
+                reminded.id,
+                UpdateTodo {
+                    remind_at: Some(Some(now)),
+                    ..Default::default()
+                },
+            )
+            .await
+            .expect("[update] returned Err");
+        assert_eq!(None, rescheduled.reminded_at);
+    }
+}
+
+#[cfg(test)]
+pub mod test_utils {
+    use super::*;
+
+    impl TodoEntity {
+        // 作成・更新時刻は実行のたびに変わるため、比較用に固定値へ揃える
+        pub fn without_timestamps(self) -> Self {
+            Self {
+                created_at: DateTime::<Utc>::MIN_UTC,
+                updated_at: DateTime::<Utc>::MIN_UTC,
+                deleted_at: self.deleted_at.map(|_| DateTime::<Utc>::MIN_UTC),
+                archived_at: self.archived_at.map(|_| DateTime::<Utc>::MIN_UTC),
+                reminded_at: self.reminded_at.map(|_| DateTime::<Utc>::MIN_UTC),
+                ..self
+            }
+        }
+    }
+
+    impl CreateTodo {
+        pub fn with_recurrence(self, recurrence: Recurrence) -> Self {
+            Self {
+                recurrence: Some(recurrence),
+                ..self
+            }
+        }
+
+        pub fn with_remind_at(self, remind_at: DateTime<Utc>) -> Self {
+            Self {
+                remind_at: Some(remind_at),
+                ..self
+            }
+        }
+    }
+
+    impl UpdateTodos {
+        pub fn new(ids: Vec<i64>, text: Option<String>, completed: Option<bool>) -> Self {
+            Self {
+                ids,
+                text: text.map(|text| normalize_text(&text)),
+                completed,
+            }
         }
     }
 }
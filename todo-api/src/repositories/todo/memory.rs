@@ -0,0 +1,2802 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicI64, Ordering};
+use std::sync::Arc;
+
+use axum::async_trait;
+use tokio::sync::{Notify, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::clock::SystemClock;
+use crate::repositories::label::LabelRepositoryForMemory;
+use crate::repositories::{read_lock, write_lock};
+
+use super::*;
+
+// 所有者のユーザーidと組で保持する
+type TodoDatas = HashMap<i64, (i32, TodoEntity)>;
+
+// 再起動をまたいで復元するための、リポジトリが保持する内容の写し
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TodoSnapshot {
+    todos: Vec<(i32, TodoEntity)>,
+    last_id: i64,
+    labels: Vec<Label>,
+    items: Vec<TodoItem>,
+    last_item_id: i32,
+    positions: HashMap<i64, i64>,
+    activities: HashMap<i64, Vec<TodoActivity>>,
+    last_activity_id: i32,
+    // 追加する前に保存したスナップショットも読めるようにする
+    #[serde(default)]
+    list_modified: HashMap<i32, DateTime<Utc>>,
+}
+
+impl TodoSnapshot {
+    // 所有者のいないTodoを、付随する項目・履歴とともに除く。除いたTodoの件数を返す
+    pub fn retain_owners(&mut self, exists: impl Fn(i32) -> bool) -> usize {
+        let before = self.todos.len();
+        self.todos.retain(|(owner, _)| exists(*owner));
+        let ids: HashSet<i64> = self.todos.iter().map(|(_, todo)| todo.id).collect();
+        self.items.retain(|item| ids.contains(&item.todo_id));
+        self.positions.retain(|id, _| ids.contains(id));
+        self.activities.retain(|id, _| ids.contains(id));
+        self.list_modified.retain(|owner, _| exists(*owner));
+        before - self.todos.len()
+    }
+}
+
+type IdempotencyKeys = HashMap<(i32, String), (IdempotencyRecord, DateTime<Utc>)>;
+
+#[derive(Debug, Clone)]
+pub struct TodoRepositoryForMemory {
+    store: Arc<RwLock<TodoDatas>>,
+    last_id: Arc<AtomicI64>,
+    // インポート時に追加され、with_labelsで共有した場合はラベルの作成でも増える。
+    // ロック中にawaitしないため標準のRwLockで保持する
+    labels: Arc<std::sync::RwLock<Vec<Label>>>,
+    items: Arc<std::sync::RwLock<Vec<TodoItem>>>,
+    last_item_id: Arc<AtomicI32>,
+    // 表示順はTodoEntityに含めないため、idごとに別に保持する
+    positions: Arc<std::sync::RwLock<HashMap<i64, i64>>>,
+    // Todoごとの変更履歴。古い順に追加する
+    activities: Arc<std::sync::RwLock<HashMap<i64, Vec<TodoActivity>>>>,
+    last_activity_id: Arc<AtomicI32>,
+    // ユーザーとキーの組ごとに、登録した日時とともに保持する
+    idempotency_keys: Arc<std::sync::RwLock<IdempotencyKeys>>,
+    // updated_atを変えない変更(完全な削除や並べ替えなど)をした日時。ユーザーごとに保持する
+    list_modified: Arc<std::sync::RwLock<HashMap<i32, DateTime<Utc>>>>,
+    clock: SharedClock,
+    // 書き込みのロックを取るたびに通知する。スナップショットの保存に使う
+    changed: Arc<Notify>,
+    // 次に書き込みのロックを取った時点で1回だけpanicさせる。テストでロック中の失敗を再現する
+    panic_next_write: Arc<AtomicBool>,
+}
+
+impl TodoRepositoryForMemory {
+    pub fn new(labels: Vec<Label>) -> Self {
+        TodoRepositoryForMemory {
+            store: Arc::default(),
+            last_id: Arc::default(),
+            labels: Arc::new(std::sync::RwLock::new(labels)),
+            items: Arc::default(),
+            last_item_id: Arc::default(),
+            positions: Arc::default(),
+            activities: Arc::default(),
+            last_activity_id: Arc::default(),
+            idempotency_keys: Arc::default(),
+            list_modified: Arc::default(),
+            clock: Arc::new(SystemClock),
+            changed: Arc::default(),
+            panic_next_write: Arc::default(),
+        }
+    }
+
+    // LabelRepositoryForMemoryで作成したラベルをTodoへ付けられるよう、ラベルの定義を共有する
+    pub fn with_labels(self, labels: &LabelRepositoryForMemory) -> Self {
+        Self {
+            labels: labels.shared_labels(),
+            ..self
+        }
+    }
+
+    pub fn with_clock(self, clock: impl Clock) -> Self {
+        Self {
+            clock: Arc::new(clock),
+            ..self
+        }
+    }
+
+    // 前回の通知以降に変更されるまで待つ。待っていない間の変更も取りこぼさない
+    pub async fn changed(&self) {
+        self.changed.notified().await
+    }
+
+    pub async fn snapshot(&self) -> TodoSnapshot {
+        let store = self.read_store_ref().await;
+        let mut todos: Vec<(i32, TodoEntity)> = store.values().cloned().collect();
+        todos.sort_by_key(|(_, todo)| todo.id);
+        TodoSnapshot {
+            todos,
+            last_id: self.last_id.load(Ordering::SeqCst),
+            labels: read_lock(&self.labels).clone(),
+            items: read_lock(&self.items).clone(),
+            last_item_id: self.last_item_id.load(Ordering::SeqCst),
+            positions: read_lock(&self.positions).clone(),
+            activities: read_lock(&self.activities).clone(),
+            last_activity_id: self.last_activity_id.load(Ordering::SeqCst),
+            list_modified: read_lock(&self.list_modified).clone(),
+        }
+    }
+
+    // 保持している内容をすべて置き換える。復元自体は変更として通知しない
+    pub async fn restore_snapshot(&self, snapshot: TodoSnapshot) {
+        let mut store = self.store.write().await;
+        *store = snapshot
+            .todos
+            .into_iter()
+            .map(|(owner, todo)| (todo.id, (owner, todo)))
+            .collect();
+        self.last_id.store(snapshot.last_id, Ordering::SeqCst);
+        *self.write_lock(&self.labels) = snapshot.labels;
+        *self.write_lock(&self.items) = snapshot.items;
+        self.last_item_id
+            .store(snapshot.last_item_id, Ordering::SeqCst);
+        *self.write_lock(&self.positions) = snapshot.positions;
+        *self.write_lock(&self.activities) = snapshot.activities;
+        self.last_activity_id
+            .store(snapshot.last_activity_id, Ordering::SeqCst);
+        *self.write_lock(&self.list_modified) = snapshot.list_modified;
+    }
+
+    #[cfg(test)]
+    pub fn panic_on_next_write(&self) {
+        self.panic_next_write.store(true, Ordering::SeqCst);
+    }
+
+    // 標準のRwLockへの書き込みロックはここから取り、注入したpanicをロック中に起こせるようにする
+    fn write_lock<'a, T>(
+        &self,
+        lock: &'a std::sync::RwLock<T>,
+    ) -> std::sync::RwLockWriteGuard<'a, T> {
+        let guard = write_lock(lock);
+        if self.panic_next_write.swap(false, Ordering::SeqCst) {
+            panic!("injected panic while holding a write lock");
+        }
+        guard
+    }
+
+    async fn write_store_ref(&self) -> RwLockWriteGuard<'_, TodoDatas> {
+        self.changed.notify_one();
+        self.store.write().await
+    }
+
+    async fn read_store_ref(&self) -> RwLockReadGuard<'_, TodoDatas> {
+        self.store.read().await
+    }
+
+    // 削除済みのidを再利用しないよう、store.len()ではなくカウンタから採番する
+    // 上限に達した後に折り返して既存のidと重複しないよう、使い切った場合はエラーにする
+    fn next_id(&self) -> Result<i64, RepositoryError> {
+        self.last_id
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |id| id.checked_add(1))
+            .map(|id| id + 1)
+            .map_err(|_| RepositoryError::unexpected("Todo ids are exhausted"))
+    }
+
+    // ユーザーのTodoの先頭へ置くためのpositionを返す
+    fn next_position(&self, store: &TodoDatas, user_id: i32) -> i64 {
+        let positions = read_lock(&self.positions);
+        store
+            .iter()
+            .filter(|(_, (owner, _))| *owner == user_id)
+            .filter_map(|(id, _)| positions.get(id).copied())
+            .max()
+            .unwrap_or(0)
+            + POSITION_GAP
+    }
+
+    fn position(&self, id: i64) -> i64 {
+        read_lock(&self.positions)
+            .get(&id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    // 完全に削除されたTodoの項目と表示順を削除する。DBでは外部キーのON DELETE CASCADEにあたる
+    fn remove_orphans(&self, store: &mut TodoDatas) {
+        self.write_lock(&self.items)
+            .retain(|item| store.contains_key(&item.todo_id));
+        self.write_lock(&self.positions)
+            .retain(|id, _| store.contains_key(id));
+        self.write_lock(&self.activities)
+            .retain(|id, _| store.contains_key(id));
+        // 削除された次の回への参照を外す。DBでは外部キーのON DELETE SET NULLにあたる
+        let removed: Vec<i64> = store
+            .values()
+            .filter_map(|(_, todo)| todo.next_occurrence_id)
+            .filter(|next| !store.contains_key(next))
+            .collect();
+        for (_, todo) in store.values_mut() {
+            if todo
+                .next_occurrence_id
+                .is_some_and(|next| removed.contains(&next))
+            {
+                todo.next_occurrence_id = None;
+            }
+        }
+    }
+
+    // 登録済みのキーへ応答を記録する。ストアのロック中に呼び、作成と同時に記録したことにする
+    fn complete_locked(
+        &self,
+        user_id: i32,
+        key: &str,
+        created: &impl Serialize,
+    ) -> Result<(), RepositoryError> {
+        let response = idempotent_response(created)?;
+        if let Some((record, _)) = self
+            .write_lock(&self.idempotency_keys)
+            .get_mut(&(user_id, key.to_string()))
+        {
+            record.response.get_or_insert(response);
+        }
+        Ok(())
+    }
+
+    // 一覧の変更日時を進める。DBのtodo_list_modificationsにあたる
+    fn touch_list(&self, user_id: i32) {
+        let now = self.clock.now();
+        self.write_lock(&self.list_modified)
+            .entry(user_id)
+            .and_modify(|at| *at = (*at).max(now))
+            .or_insert(now);
+    }
+
+    fn record(&self, user_id: i32, change: TodoChange) {
+        let activity = TodoActivity {
+            id: self.last_activity_id.fetch_add(1, Ordering::SeqCst) + 1,
+            todo_id: change.todo_id,
+            action: change.action,
+            old_value: change.old_value,
+            new_value: change.new_value,
+            actor_id: user_id,
+            created_at: self.clock.now(),
+        };
+        self.write_lock(&self.activities)
+            .entry(change.todo_id)
+            .or_default()
+            .push(activity);
+    }
+
+    fn sorted_items(items: &[TodoItem], id: i64) -> Vec<TodoItem> {
+        let mut items: Vec<TodoItem> = items
+            .iter()
+            .filter(|item| item.todo_id == id)
+            .cloned()
+            .collect();
+        items.sort_by_key(|item| (item.position, item.id));
+        items
+    }
+
+    // 他のユーザーのTodoやゴミ箱内のTodoは存在しないものとして扱う
+    fn owned_mut(
+        store: &mut TodoDatas,
+        user_id: i32,
+        id: i64,
+    ) -> Result<&mut TodoEntity, RepositoryError> {
+        store
+            .get_mut(&id)
+            .filter(|(owner, todo)| *owner == user_id && todo.deleted_at.is_none())
+            .map(|(_, todo)| todo)
+            .ok_or(RepositoryError::NotFound(Some(id)))
+    }
+
+    fn find_label(&self, label_id: i32) -> Result<Label, RepositoryError> {
+        read_lock(&self.labels)
+            .iter()
+            .find(|label| label.id == label_id)
+            .cloned()
+            .ok_or(RepositoryError::NotFound(Some(label_id.into())))
+    }
+
+    // DBの一意制約と同じく、ゴミ箱内のTodoも含めてユーザーごとにclient_idの重複を防ぐ
+    fn check_client_ids<'a>(
+        store: &TodoDatas,
+        user_id: i32,
+        payloads: impl IntoIterator<Item = &'a CreateTodo>,
+    ) -> Result<(), RepositoryError> {
+        let mut seen = HashSet::new();
+        for client_id in payloads.into_iter().filter_map(CreateTodo::client_id) {
+            let used = store
+                .values()
+                .any(|(owner, todo)| *owner == user_id && todo.client_id == Some(client_id));
+            if used || !seen.insert(client_id) {
+                return Err(RepositoryError::Duplicate(None));
+            }
+        }
+        Ok(())
+    }
+
+    fn insert(
+        &self,
+        store: &mut TodoDatas,
+        user_id: i32,
+        payload: CreateTodo,
+    ) -> Result<TodoEntity, RepositoryError> {
+        let priority = payload.priority();
+        // 採番前に解決し、不正なラベルがあれば何も登録しない
+        let labels = self.resolve_labels(payload.labels)?;
+        let id = self.next_id()?;
+        let now = self.clock.now();
+        let todo = TodoEntity {
+            created_at: now,
+            updated_at: now,
+            due_date: payload.due_date,
+            priority,
+            recurrence: payload.recurrence,
+            remind_at: payload.remind_at,
+            client_id: payload.client_id,
+            ..TodoEntity::new(id, payload.text, labels)
+        };
+        let position = self.next_position(store, user_id);
+        self.write_lock(&self.positions).insert(id, position);
+        store.insert(id, (user_id, todo.clone()));
+        self.record(user_id, TodoChange::created(&todo));
+        Ok(todo)
+    }
+
+    // importとseedで共有する。ロックは呼び出し側で取る
+    fn import_locked(
+        &self,
+        store: &mut TodoDatas,
+        labels: &mut Vec<Label>,
+        user_id: i32,
+        backup: Backup,
+    ) -> Result<ImportSummary, RepositoryError> {
+        let mut summary = ImportSummary::default();
+
+        let mut label_map = HashMap::with_capacity(backup.labels.len());
+        for label in backup.labels {
+            let (resolved, created) =
+                Self::find_or_create_label(labels, label.name, label.color, label.description);
+            if created {
+                summary.labels += 1;
+            }
+            label_map.insert(label.id, resolved);
+        }
+
+        for todo in backup.todos {
+            let id = self.next_id()?;
+            let entity = TodoEntity {
+                completed: todo.completed,
+                created_at: todo.created_at,
+                updated_at: todo.updated_at,
+                due_date: todo.due_date,
+                priority: todo.priority,
+                labels: backup
+                    .associations
+                    .iter()
+                    .filter(|association| association.todo_id == todo.id)
+                    .filter_map(|association| label_map.get(&association.label_id))
+                    .fold(vec![], |mut labels: Vec<Label>, label| {
+                        // 統合により同じラベルへの紐付けが重複する場合がある
+                        if !labels.contains(label) {
+                            labels.push(label.clone());
+                            labels.sort_by_key(|label| label.id);
+                        }
+                        labels
+                    }),
+                ..TodoEntity::new(id, todo.text, vec![])
+            };
+            let position = self.next_position(store, user_id);
+            self.write_lock(&self.positions).insert(id, position);
+            store.insert(id, (user_id, entity));
+            summary.todos += 1;
+        }
+        Ok(summary)
+    }
+
+    fn reset_locked(&self, store: &mut TodoDatas, labels: &mut Vec<Label>) {
+        store.clear();
+        labels.clear();
+        self.write_lock(&self.items).clear();
+        self.write_lock(&self.positions).clear();
+        self.write_lock(&self.activities).clear();
+        self.write_lock(&self.idempotency_keys).clear();
+        self.write_lock(&self.list_modified).clear();
+        self.last_id.store(0, Ordering::SeqCst);
+        self.last_item_id.store(0, Ordering::SeqCst);
+        self.last_activity_id.store(0, Ordering::SeqCst);
+    }
+
+    // 同名(大文字小文字を区別しない)のラベルがあればそれを、なければ作成して返す
+    fn find_or_create_label(
+        labels: &mut Vec<Label>,
+        name: String,
+        color: String,
+        description: Option<String>,
+    ) -> (Label, bool) {
+        let lower = name.to_lowercase();
+        if let Some(existing) = labels
+            .iter()
+            .find(|label| label.name.to_lowercase() == lower)
+        {
+            return (existing.clone(), false);
+        }
+        let created = Label {
+            id: labels.iter().map(|label| label.id).max().unwrap_or(0) + 1,
+            name,
+            color,
+            description,
+        };
+        labels.push(created.clone());
+        (created, true)
+    }
+
+    // DBの実装と同じく、指定した順によらずidの順に並べる
+    fn resolve_labels(&self, labels: Vec<i32>) -> Result<Vec<Label>, RepositoryError> {
+        let mut labels = labels
+            .into_iter()
+            .map(|id| {
+                self.find_label(id)
+                    .map_err(|_| RepositoryError::InvalidLabel(id))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        labels.sort_by_key(|label| label.id);
+        Ok(labels)
+    }
+}
+
+#[async_trait]
+impl TodoRepository for TodoRepositoryForMemory {
+    async fn create(
+        &self,
+        user_id: i32,
+        payload: CreateTodo,
+    ) -> Result<TodoEntity, RepositoryError> {
+        self.create_idempotent(user_id, payload, None).await
+    }
+
+    async fn create_many(
+        &self,
+        user_id: i32,
+        payloads: Vec<CreateTodo>,
+    ) -> Result<Vec<TodoEntity>, RepositoryError> {
+        self.create_many_idempotent(user_id, payloads, None).await
+    }
+
+    async fn create_idempotent(
+        &self,
+        user_id: i32,
+        payload: CreateTodo,
+        key: Option<&str>,
+    ) -> Result<TodoEntity, RepositoryError> {
+        let mut store = self.write_store_ref().await;
+        Self::check_client_ids(&store, user_id, [&payload])?;
+        let todo = self.insert(&mut store, user_id, payload)?;
+        if let Some(key) = key {
+            self.complete_locked(user_id, key, &todo)?;
+        }
+        Ok(todo)
+    }
+
+    async fn create_many_idempotent(
+        &self,
+        user_id: i32,
+        payloads: Vec<CreateTodo>,
+        key: Option<&str>,
+    ) -> Result<Vec<TodoEntity>, RepositoryError> {
+        for payload in payloads.iter() {
+            for label_id in payload.labels.iter() {
+                self.find_label(*label_id)?;
+            }
+        }
+        let mut store = self.write_store_ref().await;
+        Self::check_client_ids(&store, user_id, &payloads)?;
+        let todos: Vec<TodoEntity> = payloads
+            .into_iter()
+            .map(|payload| self.insert(&mut store, user_id, payload))
+            .collect::<Result<_, _>>()?;
+        if let Some(key) = key {
+            self.complete_locked(user_id, key, &todos)?;
+        }
+        Ok(todos)
+    }
+
+    async fn create_many_with_label_names(
+        &self,
+        user_id: i32,
+        payloads: Vec<CreateTodoWithLabelNames>,
+    ) -> Result<Vec<TodoEntity>, RepositoryError> {
+        let payloads: Vec<CreateTodo> = {
+            let mut labels = self.write_lock(&self.labels);
+            payloads
+                .into_iter()
+                .map(|payload| {
+                    let mut todo = payload.todo;
+                    for name in payload.label_names {
+                        let (label, _) = Self::find_or_create_label(
+                            &mut labels,
+                            name,
+                            DEFAULT_LABEL_COLOR.to_string(),
+                            None,
+                        );
+                        if !todo.labels.contains(&label.id) {
+                            todo.labels.push(label.id);
+                        }
+                    }
+                    todo
+                })
+                .collect()
+        };
+        self.create_many(user_id, payloads).await
+    }
+
+    async fn find(&self, user_id: i32, id: i64) -> Result<TodoEntity, RepositoryError> {
+        let store = self.read_store_ref().await;
+        let todo = store
+            .get(&id)
+            .filter(|(owner, todo)| *owner == user_id && todo.deleted_at.is_none())
+            .map(|(_, todo)| todo.clone())
+            .ok_or(RepositoryError::NotFound(Some(id)))?;
+        Ok(todo)
+    }
+
+    async fn find_many(&self, user_id: i32, ids: &[i64]) -> Result<FoundTodos, RepositoryError> {
+        let store = self.read_store_ref().await;
+        let mut todos: Vec<TodoEntity> = ids
+            .iter()
+            .filter_map(|id| store.get(id))
+            .filter(|(owner, todo)| *owner == user_id && todo.deleted_at.is_none())
+            .map(|(_, todo)| todo.clone())
+            .collect();
+        todos.sort_by_key(|todo| todo.id);
+        todos.dedup_by_key(|todo| todo.id);
+        Ok(FoundTodos::new(ids, todos))
+    }
+
+    async fn exists(&self, user_id: i32, id: i64) -> Result<bool, RepositoryError> {
+        let store = self.read_store_ref().await;
+        Ok(store
+            .get(&id)
+            .is_some_and(|(owner, todo)| *owner == user_id && todo.deleted_at.is_none()))
+    }
+
+    async fn find_open_by_text(
+        &self,
+        user_id: i32,
+        text: &str,
+    ) -> Result<Option<TodoEntity>, RepositoryError> {
+        let store = self.read_store_ref().await;
+        Ok(store
+            .values()
+            .filter(|(owner, todo)| {
+                *owner == user_id
+                    && todo.text == text
+                    && !todo.completed
+                    && todo.deleted_at.is_none()
+                    && todo.archived_at.is_none()
+            })
+            .map(|(_, todo)| todo)
+            .min_by_key(|todo| todo.id)
+            .cloned())
+    }
+
+    async fn find_by_client_id(
+        &self,
+        user_id: i32,
+        client_id: Uuid,
+    ) -> Result<Option<TodoEntity>, RepositoryError> {
+        let store = self.read_store_ref().await;
+        Ok(store
+            .values()
+            .find(|(owner, todo)| {
+                *owner == user_id && todo.client_id == Some(client_id) && todo.deleted_at.is_none()
+            })
+            .map(|(_, todo)| todo.clone()))
+    }
+
+    async fn labels_for_todos(
+        &self,
+        user_id: i32,
+        ids: Vec<i64>,
+    ) -> Result<HashMap<i64, Vec<Label>>, RepositoryError> {
+        let store = self.read_store_ref().await;
+        Ok(ids
+            .into_iter()
+            .filter_map(|id| store.get(&id))
+            .filter(|(owner, todo)| *owner == user_id && todo.deleted_at.is_none())
+            .map(|(_, todo)| (todo.id, todo.labels.clone()))
+            .collect())
+    }
+
+    async fn all(&self, user_id: i32, query: TodoListQuery) -> Result<TodoPage, RepositoryError> {
+        let store = self.read_store_ref().await;
+        let todos = store
+            .values()
+            .filter(|(owner, todo)| *owner == user_id && todo.deleted_at.is_none())
+            .map(|(_, todo)| todo);
+        Ok(list_todos(todos, &query, self.clock.now(), |id| {
+            self.position(id)
+        }))
+    }
+
+    async fn last_modified(&self, user_id: i32) -> Result<Option<DateTime<Utc>>, RepositoryError> {
+        let store = self.read_store_ref().await;
+        let list_modified = read_lock(&self.list_modified).get(&user_id).copied();
+        Ok(store
+            .values()
+            .filter(|(owner, _)| *owner == user_id)
+            .map(|(_, todo)| {
+                todo.deleted_at
+                    .map_or(todo.updated_at, |at| at.max(todo.updated_at))
+            })
+            .chain(list_modified)
+            .max())
+    }
+
+    async fn update(
+        &self,
+        user_id: i32,
+        id: i64,
+        payload: UpdateTodo,
+    ) -> Result<TodoEntity, RepositoryError> {
+        let mut store = self.write_store_ref().await;
+        let todo = Self::owned_mut(&mut store, user_id, id)?;
+        if payload
+            .version
+            .is_some_and(|version| version != todo.version)
+        {
+            return Err(RepositoryError::Conflict(id));
+        }
+        if !payload.precondition.matches(todo) {
+            return Err(RepositoryError::PreconditionFailed(id));
+        }
+        let priority = payload.priority().unwrap_or(todo.priority);
+        let text = payload.text.unwrap_or(todo.text.clone());
+        let completed = payload.completed.unwrap_or(todo.completed);
+        let labels = match payload.labels {
+            Some(label_ids) => self.resolve_labels(label_ids)?,
+            None => todo.labels.clone(),
+        };
+        let old = todo.clone();
+        *todo = TodoEntity {
+            id,
+            client_id: todo.client_id,
+            text,
+            completed,
+            labels,
+            created_at: todo.created_at,
+            updated_at: self.clock.now(),
+            deleted_at: todo.deleted_at,
+            archived_at: todo.archived_at,
+            version: todo.version + 1,
+            due_date: payload.due_date.unwrap_or(todo.due_date),
+            priority,
+            recurrence: payload.recurrence.unwrap_or(todo.recurrence),
+            next_occurrence_id: todo.next_occurrence_id,
+            remind_at: payload.remind_at.unwrap_or(todo.remind_at),
+            reminded_at: match payload.remind_at {
+                Some(_) => None,
+                None => todo.reminded_at,
+            },
+        };
+        if let Some(change) = TodoChange::updated(&old, todo) {
+            self.record(user_id, change);
+        }
+        let mut updated = todo.clone();
+        if let Some(recurrence) = recurrence_to_spawn(&old, &updated) {
+            let next = CreateTodo {
+                due_date: Some(recurrence.next_due_date(updated.due_date, updated.updated_at)),
+                recurrence: Some(recurrence),
+                remind_at: updated
+                    .remind_at
+                    .map(|remind_at| recurrence.next_due_date(Some(remind_at), updated.updated_at)),
+                ..CreateTodo::new(
+                    updated.text.clone(),
+                    updated.labels.iter().map(|label| label.id).collect(),
+                )
+            }
+            .with_priority(updated.priority);
+            let next = self.insert(&mut store, user_id, next)?;
+            updated.next_occurrence_id = Some(next.id);
+            Self::owned_mut(&mut store, user_id, id)?.next_occurrence_id = Some(next.id);
+        }
+        Ok(updated)
+    }
+
+    async fn replace(
+        &self,
+        user_id: i32,
+        id: i64,
+        payload: ReplaceTodo,
+    ) -> Result<TodoEntity, RepositoryError> {
+        self.update(user_id, id, payload.into()).await
+    }
+
+    async fn update_many(
+        &self,
+        user_id: i32,
+        payload: UpdateTodos,
+    ) -> Result<UpdatedTodos, RepositoryError> {
+        let mut store = self.write_store_ref().await;
+        let now = self.clock.now();
+        let mut todos: Vec<TodoEntity> = store
+            .values_mut()
+            .filter(|(owner, todo)| {
+                *owner == user_id && todo.deleted_at.is_none() && payload.ids.contains(&todo.id)
+            })
+            .map(|(_, todo)| {
+                let old = todo.clone();
+                if let Some(text) = &payload.text {
+                    todo.text = text.clone();
+                }
+                if let Some(completed) = payload.completed {
+                    todo.completed = completed;
+                }
+                todo.updated_at = now;
+                todo.version += 1;
+                if let Some(change) = TodoChange::updated(&old, todo) {
+                    self.record(user_id, change);
+                }
+                todo.clone()
+            })
+            .collect();
+        todos.sort_by_key(|todo| todo.id);
+        Ok(UpdatedTodos::new(&payload.ids, todos))
+    }
+
+    async fn delete(
+        &self,
+        user_id: i32,
+        id: i64,
+        precondition: Precondition,
+    ) -> Result<(), RepositoryError> {
+        let mut store = self.write_store_ref().await;
+        let todo = Self::owned_mut(&mut store, user_id, id)?;
+        if !precondition.matches(todo) {
+            return Err(RepositoryError::PreconditionFailed(id));
+        }
+        todo.deleted_at = Some(self.clock.now());
+        self.record(user_id, TodoChange::deleted(id));
+        Ok(())
+    }
+
+    async fn delete_permanently(&self, user_id: i32, id: i64) -> Result<(), RepositoryError> {
+        let mut store = self.write_store_ref().await;
+        match store.get(&id) {
+            Some((owner, _)) if *owner == user_id => {
+                store.remove(&id);
+                self.remove_orphans(&mut store);
+                self.touch_list(user_id);
+                Ok(())
+            }
+            _ => Err(RepositoryError::NotFound(Some(id))),
+        }
+    }
+
+    async fn delete_completed(&self, user_id: i32) -> Result<u64, RepositoryError> {
+        let mut store = self.write_store_ref().await;
+        let before = store.len();
+        store.retain(|_, (owner, todo)| {
+            *owner != user_id || todo.deleted_at.is_some() || !todo.completed
+        });
+        self.remove_orphans(&mut store);
+        let deleted = (before - store.len()) as u64;
+        if deleted > 0 {
+            self.touch_list(user_id);
+        }
+        Ok(deleted)
+    }
+
+    async fn purge_deleted_before(&self, cutoff: DateTime<Utc>) -> Result<u64, RepositoryError> {
+        let mut store = self.write_store_ref().await;
+        let mut owners = Vec::new();
+        store.retain(|_, (owner, todo)| {
+            let keep = todo.deleted_at.is_none_or(|at| at >= cutoff);
+            if !keep {
+                owners.push(*owner);
+            }
+            keep
+        });
+        self.remove_orphans(&mut store);
+        for owner in owners.iter().collect::<HashSet<_>>() {
+            self.touch_list(*owner);
+        }
+        Ok(owners.len() as u64)
+    }
+
+    async fn claim_idempotency_key(
+        &self,
+        user_id: i32,
+        key: &str,
+        fingerprint: &str,
+    ) -> Result<Option<IdempotencyRecord>, RepositoryError> {
+        let mut keys = self.write_lock(&self.idempotency_keys);
+        if let Some((record, _)) = keys.get(&(user_id, key.to_string())) {
+            return Ok(Some(record.clone()));
+        }
+        let record = IdempotencyRecord {
+            fingerprint: fingerprint.to_string(),
+            response: None,
+        };
+        keys.insert((user_id, key.to_string()), (record, self.clock.now()));
+        Ok(None)
+    }
+
+    async fn complete_idempotency_key(
+        &self,
+        user_id: i32,
+        key: &str,
+        response: serde_json::Value,
+    ) -> Result<(), RepositoryError> {
+        let mut keys = self.write_lock(&self.idempotency_keys);
+        if let Some((record, _)) = keys.get_mut(&(user_id, key.to_string())) {
+            record.response.get_or_insert(response);
+        }
+        Ok(())
+    }
+
+    async fn release_idempotency_key(
+        &self,
+        user_id: i32,
+        key: &str,
+    ) -> Result<(), RepositoryError> {
+        let mut keys = self.write_lock(&self.idempotency_keys);
+        if let Some((IdempotencyRecord { response: None, .. }, _)) =
+            keys.get(&(user_id, key.to_string()))
+        {
+            keys.remove(&(user_id, key.to_string()));
+        }
+        Ok(())
+    }
+
+    async fn purge_idempotency_keys_before(
+        &self,
+        cutoff: DateTime<Utc>,
+    ) -> Result<u64, RepositoryError> {
+        let mut keys = self.write_lock(&self.idempotency_keys);
+        let before = keys.len();
+        keys.retain(|_, (_, created_at)| *created_at >= cutoff);
+        Ok((before - keys.len()) as u64)
+    }
+
+    async fn trash(&self, user_id: i32) -> Result<Vec<TodoEntity>, RepositoryError> {
+        let store = self.read_store_ref().await;
+        let mut todos: Vec<TodoEntity> = store
+            .values()
+            .filter(|(owner, todo)| *owner == user_id && todo.deleted_at.is_some())
+            .map(|(_, todo)| todo.clone())
+            .collect();
+        todos.sort_by(|a, b| b.deleted_at.cmp(&a.deleted_at).then(b.id.cmp(&a.id)));
+        Ok(todos)
+    }
+
+    async fn restore(&self, user_id: i32, id: i64) -> Result<TodoEntity, RepositoryError> {
+        let mut store = self.write_store_ref().await;
+        let todo = store
+            .get_mut(&id)
+            .filter(|(owner, todo)| *owner == user_id && todo.deleted_at.is_some())
+            .map(|(_, todo)| todo)
+            .ok_or(RepositoryError::NotFound(Some(id)))?;
+        todo.deleted_at = None;
+        todo.updated_at = self.clock.now();
+        todo.version += 1;
+        Ok(todo.clone())
+    }
+
+    async fn attach_label(
+        &self,
+        user_id: i32,
+        id: i64,
+        label_id: i32,
+    ) -> Result<TodoEntity, RepositoryError> {
+        let mut store = self.write_store_ref().await;
+        let todo = Self::owned_mut(&mut store, user_id, id)?;
+        let label = self.find_label(label_id)?;
+        if !todo.labels.contains(&label) {
+            todo.labels.push(label);
+            todo.labels.sort_by_key(|label| label.id);
+        }
+        todo.updated_at = self.clock.now();
+        todo.version += 1;
+        Ok(todo.clone())
+    }
+
+    async fn detach_label(
+        &self,
+        user_id: i32,
+        id: i64,
+        label_id: i32,
+    ) -> Result<TodoEntity, RepositoryError> {
+        let mut store = self.write_store_ref().await;
+        let todo = Self::owned_mut(&mut store, user_id, id)?;
+        self.find_label(label_id)?;
+        todo.labels.retain(|label| label.id != label_id);
+        todo.updated_at = self.clock.now();
+        todo.version += 1;
+        Ok(todo.clone())
+    }
+
+    async fn export(&self, user_id: i32) -> Result<Backup, RepositoryError> {
+        let store = self.read_store_ref().await;
+        let mut todos: Vec<TodoEntity> = store
+            .values()
+            .filter(|(owner, todo)| *owner == user_id && todo.deleted_at.is_none())
+            .map(|(_, todo)| todo.clone())
+            .collect();
+        todos.sort_by_key(|todo| todo.id);
+        let labels = read_lock(&self.labels).clone();
+        Ok(Backup::new(todos, labels))
+    }
+
+    async fn import(&self, user_id: i32, backup: Backup) -> Result<ImportSummary, RepositoryError> {
+        let mut store = self.write_store_ref().await;
+        let mut labels = self.write_lock(&self.labels);
+        let summary = self.import_locked(&mut store, &mut labels, user_id, backup)?;
+        // 復元したTodoはバックアップのupdated_atを引き継ぐため、一覧の変更日時を進める
+        self.touch_list(user_id);
+        Ok(summary)
+    }
+
+    async fn reset(&self) -> Result<(), RepositoryError> {
+        let mut store = self.write_store_ref().await;
+        self.reset_locked(&mut store, &mut self.write_lock(&self.labels));
+        Ok(())
+    }
+
+    async fn seed(&self, user_id: i32, fixtures: Backup) -> Result<ImportSummary, RepositoryError> {
+        let mut store = self.write_store_ref().await;
+        let mut labels = self.write_lock(&self.labels);
+        self.reset_locked(&mut store, &mut labels);
+        let summary = self.import_locked(&mut store, &mut labels, user_id, fixtures)?;
+        self.touch_list(user_id);
+        Ok(summary)
+    }
+
+    fn stream_all(&self, user_id: i32) -> BoxStream<'static, Result<TodoEntity, RepositoryError>> {
+        // 読み込み中も他の操作を止めないよう、idのみ先に集めてチャンクごとにロックを取る
+        let repository = self.clone();
+        stream::once(async move {
+            let mut ids: Vec<i64> = repository
+                .read_store_ref()
+                .await
+                .iter()
+                .filter(|(_, (owner, todo))| *owner == user_id && todo.deleted_at.is_none())
+                .map(|(id, _)| *id)
+                .collect();
+            ids.sort_unstable();
+            let chunks: Vec<Vec<i64>> = ids
+                .chunks(STREAM_BUFFER)
+                .map(|chunk| chunk.to_vec())
+                .collect();
+            stream::iter(chunks).then(move |chunk| {
+                let repository = repository.clone();
+                async move {
+                    let store = repository.read_store_ref().await;
+                    // チャンクの間に削除されたTodoは読み飛ばす
+                    chunk
+                        .iter()
+                        .filter_map(|id| store.get(id))
+                        .filter(|(_, todo)| todo.deleted_at.is_none())
+                        .map(|(_, todo)| Ok(todo.clone()))
+                        .collect::<Vec<_>>()
+                }
+            })
+        })
+        .flatten()
+        .flat_map(stream::iter)
+        .boxed()
+    }
+
+    async fn stats(&self, user_id: i32) -> Result<TodoStats, RepositoryError> {
+        let store = self.read_store_ref().await;
+        let since = self.clock.now() - chrono::Duration::days(7);
+        let mut stats = TodoStats::default();
+        let mut labels: BTreeMap<i32, LabelCount> = BTreeMap::new();
+        for (_, todo) in store
+            .values()
+            .filter(|(owner, todo)| *owner == user_id && todo.deleted_at.is_none())
+        {
+            stats.total += 1;
+            if todo.completed {
+                stats.completed += 1;
+            } else {
+                stats.open += 1;
+            }
+            if todo.created_at >= since {
+                stats.created_last_7_days += 1;
+            }
+            for label in todo.labels.iter() {
+                labels
+                    .entry(label.id)
+                    .or_insert_with(|| LabelCount {
+                        id: label.id,
+                        name: label.name.clone(),
+                        count: 0,
+                    })
+                    .count += 1;
+            }
+        }
+        stats.labels = labels.into_values().collect();
+        Ok(stats)
+    }
+
+    async fn items(&self, user_id: i32, id: i64) -> Result<Vec<TodoItem>, RepositoryError> {
+        let mut store = self.write_store_ref().await;
+        Self::owned_mut(&mut store, user_id, id)?;
+        Ok(Self::sorted_items(&read_lock(&self.items), id))
+    }
+
+    async fn create_item(
+        &self,
+        user_id: i32,
+        id: i64,
+        payload: CreateTodoItem,
+    ) -> Result<TodoItem, RepositoryError> {
+        let mut store = self.write_store_ref().await;
+        Self::owned_mut(&mut store, user_id, id)?;
+        let mut items = self.write_lock(&self.items);
+        let position = Self::sorted_items(&items, id)
+            .last()
+            .map_or(0, |item| item.position + 1);
+        let item = TodoItem {
+            id: self.last_item_id.fetch_add(1, Ordering::SeqCst) + 1,
+            todo_id: id,
+            text: payload.text,
+            completed: false,
+            position,
+        };
+        items.push(item.clone());
+        Ok(item)
+    }
+
+    async fn update_item(
+        &self,
+        user_id: i32,
+        id: i64,
+        item_id: i32,
+        payload: UpdateTodoItem,
+    ) -> Result<TodoItem, RepositoryError> {
+        let mut store = self.write_store_ref().await;
+        Self::owned_mut(&mut store, user_id, id)?;
+        let mut items = self.write_lock(&self.items);
+        let mut sorted = Self::sorted_items(&items, id);
+        let item = sorted
+            .iter_mut()
+            .find(|item| item.id == item_id)
+            .ok_or(RepositoryError::NotFound(Some(item_id.into())))?;
+        if let Some(text) = payload.text {
+            item.text = text;
+        }
+        if let Some(completed) = payload.completed {
+            item.completed = completed;
+        }
+        if let Some(position) = payload.position {
+            move_item(&mut sorted, item_id, position);
+        }
+        items.retain(|item| item.todo_id != id);
+        items.extend(sorted.iter().cloned());
+        sorted
+            .into_iter()
+            .find(|item| item.id == item_id)
+            .ok_or(RepositoryError::NotFound(Some(item_id.into())))
+    }
+
+    async fn delete_item(
+        &self,
+        user_id: i32,
+        id: i64,
+        item_id: i32,
+    ) -> Result<(), RepositoryError> {
+        let mut store = self.write_store_ref().await;
+        Self::owned_mut(&mut store, user_id, id)?;
+        let mut items = self.write_lock(&self.items);
+        let removed = items
+            .iter()
+            .position(|item| item.id == item_id && item.todo_id == id)
+            .map(|index| items.remove(index))
+            .ok_or(RepositoryError::NotFound(Some(item_id.into())))?;
+        for item in items.iter_mut() {
+            if item.todo_id == id && item.position > removed.position {
+                item.position -= 1;
+            }
+        }
+        Ok(())
+    }
+
+    async fn duplicate(
+        &self,
+        user_id: i32,
+        id: i64,
+        payload: DuplicateTodo,
+    ) -> Result<TodoEntity, RepositoryError> {
+        let mut store = self.write_store_ref().await;
+        let source = Self::owned_mut(&mut store, user_id, id)?.clone();
+        let now = self.clock.now();
+        let id = self.next_id()?;
+        let todo = TodoEntity {
+            created_at: now,
+            updated_at: now,
+            due_date: payload.due_date(source.created_at, source.due_date, now),
+            priority: source.priority,
+            ..TodoEntity::new(id, source.text, source.labels)
+        };
+        let position = self.next_position(&store, user_id);
+        self.write_lock(&self.positions).insert(id, position);
+        store.insert(id, (user_id, todo.clone()));
+        self.record(user_id, TodoChange::created(&todo));
+        Ok(todo)
+    }
+
+    async fn archive(&self, user_id: i32, id: i64) -> Result<TodoEntity, RepositoryError> {
+        let mut store = self.write_store_ref().await;
+        let todo = Self::owned_mut(&mut store, user_id, id)?;
+        if todo.archived_at.is_none() {
+            let now = self.clock.now();
+            todo.archived_at = Some(now);
+            todo.updated_at = now;
+            todo.version += 1;
+        }
+        Ok(todo.clone())
+    }
+
+    async fn unarchive(&self, user_id: i32, id: i64) -> Result<TodoEntity, RepositoryError> {
+        let mut store = self.write_store_ref().await;
+        let todo = Self::owned_mut(&mut store, user_id, id)?;
+        if todo.archived_at.is_some() {
+            todo.archived_at = None;
+            todo.updated_at = self.clock.now();
+            todo.version += 1;
+        }
+        Ok(todo.clone())
+    }
+
+    async fn archive_completed(&self, user_id: i32) -> Result<u64, RepositoryError> {
+        let mut store = self.write_store_ref().await;
+        let now = self.clock.now();
+        let mut archived = 0;
+        for (owner, todo) in store.values_mut() {
+            if *owner == user_id
+                && todo.completed
+                && todo.deleted_at.is_none()
+                && todo.archived_at.is_none()
+            {
+                todo.archived_at = Some(now);
+                todo.updated_at = now;
+                todo.version += 1;
+                archived += 1;
+            }
+        }
+        Ok(archived)
+    }
+
+    async fn move_todo(
+        &self,
+        user_id: i32,
+        id: i64,
+        target: MoveTarget,
+    ) -> Result<TodoEntity, RepositoryError> {
+        let store = self.write_store_ref().await;
+        let mut positions = self.write_lock(&self.positions);
+        let mut ordered: Vec<(i64, i64)> = store
+            .iter()
+            .filter(|(_, (owner, todo))| *owner == user_id && todo.deleted_at.is_none())
+            .map(|(id, _)| (*id, positions.get(id).copied().unwrap_or_default()))
+            .collect();
+        ordered.sort_by(|a, b| b.1.cmp(&a.1).then(b.0.cmp(&a.0)));
+        positions.extend(reposition(ordered, id, target)?);
+        // 表示順はupdated_atを変えないため、一覧の変更日時を進める
+        self.touch_list(user_id);
+        store
+            .get(&id)
+            .map(|(_, todo)| todo.clone())
+            .ok_or(RepositoryError::NotFound(Some(id)))
+    }
+
+    async fn activity(
+        &self,
+        user_id: i32,
+        id: i64,
+        query: PageQuery,
+    ) -> Result<TodoActivityPage, RepositoryError> {
+        let store = self.read_store_ref().await;
+        if !matches!(store.get(&id), Some((owner, _)) if *owner == user_id) {
+            return Err(RepositoryError::NotFound(Some(id)));
+        }
+        let activities = read_lock(&self.activities);
+        let activities = activities.get(&id).map(Vec::as_slice).unwrap_or_default();
+        Ok(TodoActivityPage {
+            activities: activities
+                .iter()
+                .rev()
+                .skip(query.offset() as usize)
+                .take(query.limit() as usize)
+                .cloned()
+                .collect(),
+            total: activities.len() as i64,
+        })
+    }
+
+    async fn due_reminders(&self, now: DateTime<Utc>) -> Result<Vec<DueReminder>, RepositoryError> {
+        let mut store = self.write_store_ref().await;
+        let mut reminders: Vec<DueReminder> = store
+            .values_mut()
+            .filter(|(_, todo)| {
+                todo.remind_at.is_some_and(|remind_at| remind_at <= now)
+                    && todo.reminded_at.is_none()
+                    && todo.deleted_at.is_none()
+                    && !todo.completed
+            })
+            .map(|(owner, todo)| {
+                todo.reminded_at = Some(now);
+                DueReminder {
+                    user_id: *owner,
+                    todo: todo.clone(),
+                }
+            })
+            .collect();
+        reminders.sort_by_key(|reminder| (reminder.todo.remind_at, reminder.todo.id));
+        for owner in reminders
+            .iter()
+            .map(|reminder| reminder.user_id)
+            .collect::<HashSet<_>>()
+        {
+            self.touch_list(owner);
+        }
+        Ok(reminders)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Duration;
+
+    use crate::clock::test_utils::MockClock;
+    use crate::repositories::backup::{BackupAssociation, BackupLabel};
+    use crate::repositories::test_utils::conformance::run_todo_repository_suite;
+
+    use super::*;
+
+    const USER_ID: i32 = 1;
+
+    #[tokio::test]
+    async fn conformance_suite() {
+        run_todo_repository_suite(|| TodoRepositoryForMemory::new(vec![]), USER_ID).await;
+    }
+
+    #[tokio::test]
+    async fn todo_crud_scenario() {
+        let text = "todo text".to_string();
+        let id = 1;
+        let label_data = Label::new(1, String::from("test label"));
+        let labels = vec![label_data.clone()];
+        let expected = TodoEntity::new(id, text.clone(), labels.clone());
+
+        // create
+        let label_data = Label::new(1, String::from("test label"));
+        let labels = vec![label_data.clone()];
+        let repository = TodoRepositoryForMemory::new(labels.clone());
+        let todo = repository
+            .create(USER_ID, CreateTodo::new(text, vec![label_data.id]))
+            .await
+            .expect("failed create todo");
+        assert_eq!(expected, todo.clone().without_timestamps());
+        assert_eq!(todo.created_at, todo.updated_at);
+        let created = todo;
+
+        // find
+        let todo = repository.find(USER_ID, created.id).await.unwrap();
+        assert_eq!(created, todo);
+
+        // all
+        let page = repository
+            .all(USER_ID, TodoListQuery::default())
+            .await
+            .expect("failed get all todo");
+        assert_eq!(vec![created.clone()], page.todos);
+
+        // update
+        let text = "update todo text".to_string();
+        let todo = repository
+            .update(
+                USER_ID,
+                1,
+                UpdateTodo {
+                    text: Some(text.clone()),
+                    completed: Some(true),
+                    labels: Some(vec![]),
+                    version: None,
+                    due_date: None,
+                    priority: None,
+                    recurrence: None,
+                    remind_at: None,
+                    precondition: Precondition::default(),
+                },
+            )
+            .await
+            .expect("failed update todo.");
+        assert_eq!(
+            TodoEntity {
+                id,
+                client_id: None,
+                text,
+                completed: true,
+                labels: vec![],
+                created_at: created.created_at,
+                updated_at: todo.updated_at,
+                deleted_at: None,
+                archived_at: None,
+                version: created.version + 1,
+                due_date: None,
+                priority: Priority::Medium,
+                recurrence: None,
+                next_occurrence_id: None,
+                remind_at: None,
+                reminded_at: None,
+            },
+            todo
+        );
+        assert!(todo.updated_at >= created.updated_at);
+
+        // delete
+        let res = repository
+            .delete(USER_ID, id, Precondition::default())
+            .await;
+        assert!(res.is_ok())
+    }
+
+    #[tokio::test]
+    async fn should_paginate_todos() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        for i in 1..=5 {
+            repository
+                .create(USER_ID, CreateTodo::new(format!("todo {}", i), vec![]))
+                .await
+                .expect("failed create todo");
+        }
+
+        let page = repository
+            .all(
+                USER_ID,
+                TodoListQuery {
+                    limit: Some(2),
+                    offset: Some(1),
+                    ..TodoListQuery::default()
+                },
+            )
+            .await
+            .expect("failed get all todo");
+        let ids: Vec<i64> = page.todos.iter().map(|todo| todo.id).collect();
+        assert_eq!(vec![4, 3], ids);
+        assert_eq!(5, page.total);
+
+        let page = repository
+            .all(
+                USER_ID,
+                TodoListQuery {
+                    limit: None,
+                    offset: Some(10),
+                    ..TodoListQuery::default()
+                },
+            )
+            .await
+            .expect("failed get all todo");
+        assert!(page.todos.is_empty());
+        assert_eq!(5, page.total);
+    }
+
+    #[tokio::test]
+    async fn should_filter_todos_by_completed() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        for text in ["open", "done"] {
+            repository
+                .create(USER_ID, CreateTodo::new(text.to_string(), vec![]))
+                .await
+                .expect("failed create todo");
+        }
+        repository
+            .update(
+                USER_ID,
+                2,
+                UpdateTodo {
+                    text: None,
+                    completed: Some(true),
+                    labels: None,
+                    version: None,
+                    due_date: None,
+                    priority: None,
+                    recurrence: None,
+                    remind_at: None,
+                    precondition: Precondition::default(),
+                },
+            )
+            .await
+            .expect("failed update todo");
+
+        let page = repository
+            .all(
+                USER_ID,
+                TodoListQuery {
+                    completed: Some(true),
+                    ..TodoListQuery::default()
+                },
+            )
+            .await
+            .expect("failed get all todo");
+        assert_eq!(1, page.total);
+        assert_eq!("done", page.todos[0].text);
+
+        let page = repository
+            .all(
+                USER_ID,
+                TodoListQuery {
+                    completed: Some(false),
+                    ..TodoListQuery::default()
+                },
+            )
+            .await
+            .expect("failed get all todo");
+        assert_eq!(1, page.total);
+        assert_eq!("open", page.todos[0].text);
+
+        let page = repository
+            .all(USER_ID, TodoListQuery::default())
+            .await
+            .expect("failed get all todo");
+        assert_eq!(2, page.total);
+    }
+
+    #[tokio::test]
+    async fn should_search_todos_by_text() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        for text in ["Buy Groceries", "groceries list", "walk the dog"] {
+            repository
+                .create(USER_ID, CreateTodo::new(text.to_string(), vec![]))
+                .await
+                .expect("failed create todo");
+        }
+        repository
+            .update(
+                USER_ID,
+                2,
+                UpdateTodo {
+                    text: None,
+                    completed: Some(true),
+                    labels: None,
+                    version: None,
+                    due_date: None,
+                    priority: None,
+                    recurrence: None,
+                    remind_at: None,
+                    precondition: Precondition::default(),
+                },
+            )
+            .await
+            .expect("failed update todo");
+
+        let page = repository
+            .all(
+                USER_ID,
+                TodoListQuery {
+                    q: Some("GROCERIES".to_string()),
+                    ..TodoListQuery::default()
+                },
+            )
+            .await
+            .expect("failed get all todo");
+        assert_eq!(2, page.total);
+
+        let page = repository
+            .all(
+                USER_ID,
+                TodoListQuery {
+                    q: Some("groceries".to_string()),
+                    completed: Some(false),
+                    ..TodoListQuery::default()
+                },
+            )
+            .await
+            .expect("failed get all todo");
+        assert_eq!(1, page.total);
+        assert_eq!("Buy Groceries", page.todos[0].text);
+
+        let page = repository
+            .all(
+                USER_ID,
+                TodoListQuery {
+                    q: Some(String::new()),
+                    ..TodoListQuery::default()
+                },
+            )
+            .await
+            .expect("failed get all todo");
+        assert_eq!(3, page.total);
+    }
+
+    #[tokio::test]
+    async fn should_not_save_todo_with_unknown_label() {
+        let label = Label::new(1, "known".to_string());
+        let repository = TodoRepositoryForMemory::new(vec![label.clone()]);
+
+        let err = repository
+            .create(1, CreateTodo::new("todo".to_string(), vec![1, 2]))
+            .await
+            .expect_err("unknown label returned Ok");
+        assert!(matches!(&err, RepositoryError::InvalidLabel(2)));
+        assert_eq!(
+            0,
+            repository
+                .all(1, TodoListQuery::default())
+                .await
+                .unwrap()
+                .total
+        );
+
+        let todo = repository
+            .create(1, CreateTodo::new("todo".to_string(), vec![1]))
+            .await
+            .expect("failed create todo");
+        assert_eq!(1, todo.id);
+        let err = repository
+            .update(
+                1,
+                todo.id,
+                UpdateTodo {
+                    labels: Some(vec![1, 2]),
+                    ..UpdateTodo::default()
+                },
+            )
+            .await
+            .expect_err("unknown label returned Ok");
+        assert!(matches!(&err, RepositoryError::InvalidLabel(2)));
+        assert_eq!(todo, repository.find(1, todo.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn should_attach_and_detach_label() {
+        let label = Label::new(1, String::from("test label"));
+        let repository = TodoRepositoryForMemory::new(vec![label.clone()]);
+        let created = repository
+            .create(USER_ID, CreateTodo::new("labeled".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+
+        // 二重に付与しても重複しない
+        for _ in 0..2 {
+            let todo = repository
+                .attach_label(USER_ID, created.id, label.id)
+                .await
+                .expect("failed attach label");
+            assert_eq!(vec![label.clone()], todo.labels);
+        }
+
+        let err = repository
+            .attach_label(USER_ID, created.id, 999)
+            .await
+            .expect_err("attach of missing label returned Ok");
+        assert!(matches!(&err, RepositoryError::NotFound(Some(999))));
+        let err = repository
+            .attach_label(USER_ID, 999, label.id)
+            .await
+            .expect_err("attach to missing todo returned Ok");
+        assert!(matches!(&err, RepositoryError::NotFound(Some(999))));
+
+        let todo = repository
+            .detach_label(USER_ID, created.id, label.id)
+            .await
+            .expect("failed detach label");
+        assert!(todo.labels.is_empty());
+        assert!(repository
+            .find(USER_ID, created.id)
+            .await
+            .unwrap()
+            .labels
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn should_load_labels_for_own_todos() {
+        let work = Label::new(1, String::from("work"));
+        let home = Label::new(2, String::from("home"));
+        let repository = TodoRepositoryForMemory::new(vec![work.clone(), home.clone()]);
+        let both = repository
+            .create(USER_ID, CreateTodo::new("both".to_string(), vec![1, 2]))
+            .await
+            .unwrap();
+        let none = repository
+            .create(USER_ID, CreateTodo::new("none".to_string(), vec![]))
+            .await
+            .unwrap();
+        let trashed = repository
+            .create(USER_ID, CreateTodo::new("trashed".to_string(), vec![1]))
+            .await
+            .unwrap();
+        repository
+            .delete(USER_ID, trashed.id, Precondition::default())
+            .await
+            .unwrap();
+        let theirs = repository
+            .create(USER_ID + 1, CreateTodo::new("theirs".to_string(), vec![1]))
+            .await
+            .unwrap();
+
+        let labels = repository
+            .labels_for_todos(USER_ID, vec![both.id, none.id, trashed.id, theirs.id, 999])
+            .await
+            .expect("failed load labels");
+        assert_eq!(
+            HashMap::from([(both.id, vec![work, home]), (none.id, vec![])]),
+            labels
+        );
+    }
+
+    #[tokio::test]
+    async fn should_scope_todos_to_owner() {
+        let other_user_id = USER_ID + 1;
+        let label = Label::new(1, String::from("test label"));
+        let repository = TodoRepositoryForMemory::new(vec![label.clone()]);
+        let mine = repository
+            .create(USER_ID, CreateTodo::new("mine".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        repository
+            .create(other_user_id, CreateTodo::new("theirs".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+
+        let page = repository
+            .all(USER_ID, TodoListQuery::default())
+            .await
+            .expect("failed get all todo");
+        assert_eq!(1, page.total);
+        assert_eq!(vec![mine.clone()], page.todos);
+
+        // 他のユーザーのTodoは存在しないものとして扱う
+        let update = UpdateTodo {
+            text: Some("hijacked".to_string()),
+            completed: None,
+            labels: None,
+            version: None,
+            due_date: None,
+            priority: None,
+            recurrence: None,
+            remind_at: None,
+            precondition: Precondition::default(),
+        };
+        assert!(repository.find(other_user_id, mine.id).await.is_err());
+        assert!(repository
+            .update(other_user_id, mine.id, update)
+            .await
+            .is_err());
+        assert!(repository
+            .attach_label(other_user_id, mine.id, label.id)
+            .await
+            .is_err());
+        assert!(repository
+            .detach_label(other_user_id, mine.id, label.id)
+            .await
+            .is_err());
+        let err = repository
+            .delete(other_user_id, mine.id, Precondition::default())
+            .await
+            .expect_err("delete by other user returned Ok");
+        assert!(matches!(
+            &err,
+            RepositoryError::NotFound(Some(id)) if *id == mine.id
+        ));
+        assert_eq!(mine, repository.find(USER_ID, mine.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn should_create_many_in_order() {
+        let label = Label::new(1, String::from("test label"));
+        let repository = TodoRepositoryForMemory::new(vec![label.clone()]);
+        let todos = repository
+            .create_many(
+                USER_ID,
+                vec![
+                    CreateTodo::new("first".to_string(), vec![label.id]),
+                    CreateTodo::new("second".to_string(), vec![]),
+                ],
+            )
+            .await
+            .expect("failed create todos");
+        let texts: Vec<&str> = todos.iter().map(|todo| todo.text.as_str()).collect();
+        assert_eq!(vec!["first", "second"], texts);
+        assert_eq!(vec![label.clone()], todos[0].labels);
+
+        // 存在しないラベルが1件でもあれば何も登録しない
+        let err = repository
+            .create_many(
+                USER_ID,
+                vec![
+                    CreateTodo::new("third".to_string(), vec![]),
+                    CreateTodo::new("fourth".to_string(), vec![999]),
+                ],
+            )
+            .await
+            .expect_err("create_many with missing label returned Ok");
+        assert!(matches!(&err, RepositoryError::NotFound(Some(999))));
+        let page = repository
+            .all(USER_ID, TodoListQuery::default())
+            .await
+            .expect("failed get all todo");
+        assert_eq!(2, page.total);
+    }
+
+    #[tokio::test]
+    async fn should_delete_only_own_completed_todos() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        for (user_id, text) in [
+            (USER_ID, "open"),
+            (USER_ID, "done"),
+            (USER_ID + 1, "theirs"),
+        ] {
+            let todo = repository
+                .create(user_id, CreateTodo::new(text.to_string(), vec![]))
+                .await
+                .expect("failed create todo");
+            if text != "open" {
+                repository
+                    .update(
+                        user_id,
+                        todo.id,
+                        UpdateTodo {
+                            text: None,
+                            completed: Some(true),
+                            labels: None,
+                            version: None,
+                            due_date: None,
+                            priority: None,
+                            recurrence: None,
+                            remind_at: None,
+                            precondition: Precondition::default(),
+                        },
+                    )
+                    .await
+                    .expect("failed update todo");
+            }
+        }
+
+        let deleted = repository
+            .delete_completed(USER_ID)
+            .await
+            .expect("failed delete completed todos");
+        assert_eq!(1, deleted);
+        let page = repository
+            .all(USER_ID, TodoListQuery::default())
+            .await
+            .expect("failed get all todo");
+        assert_eq!(1, page.total);
+        assert_eq!("open", page.todos[0].text);
+        assert_eq!(
+            1,
+            repository
+                .all(USER_ID + 1, TodoListQuery::default())
+                .await
+                .expect("failed get all todo")
+                .total
+        );
+
+        // 完了済みがなくてもエラーにしない
+        let deleted = repository
+            .delete_completed(USER_ID)
+            .await
+            .expect("failed delete completed todos");
+        assert_eq!(0, deleted);
+    }
+
+    #[tokio::test]
+    async fn should_update_many_and_report_missing() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        for (user_id, text) in [
+            (USER_ID, "first"),
+            (USER_ID, "second"),
+            (USER_ID + 1, "theirs"),
+        ] {
+            repository
+                .create(user_id, CreateTodo::new(text.to_string(), vec![]))
+                .await
+                .expect("failed create todo");
+        }
+
+        let res = repository
+            .update_many(
+                USER_ID,
+                UpdateTodos::new(vec![2, 999, 1, 3, 999], None, Some(true)),
+            )
+            .await
+            .expect("failed update todos");
+        let ids: Vec<i64> = res.todos.iter().map(|todo| todo.id).collect();
+        assert_eq!(vec![1, 2], ids);
+        assert!(res.todos.iter().all(|todo| todo.completed));
+        assert_eq!(vec![999, 3], res.missing);
+        assert!(!repository.find(USER_ID + 1, 3).await.unwrap().completed);
+    }
+
+    #[tokio::test]
+    async fn should_find_created_todo() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        let created = repository
+            .create(USER_ID, CreateTodo::new("find me".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+
+        let todo = repository
+            .find(USER_ID, created.id)
+            .await
+            .expect("failed find todo");
+        assert_eq!(created, todo);
+    }
+
+    #[tokio::test]
+    async fn should_find_oldest_open_todo_with_same_text() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        let mut ids = vec![];
+        for (user_id, text) in [
+            (USER_ID, "pay rent"),
+            (USER_ID, "pay rent"),
+            (USER_ID, "pay rent"),
+            (USER_ID + 1, "buy milk"),
+        ] {
+            let todo = repository
+                .create(user_id, CreateTodo::new(text.to_string(), vec![]))
+                .await
+                .expect("failed create todo");
+            ids.push(todo.id);
+        }
+        repository
+            .update(USER_ID, ids[0], UpdateTodo::default().with_completed(true))
+            .await
+            .expect("failed update todo");
+
+        // 完了済みのTodoは重複として扱わない
+        let found = repository
+            .find_open_by_text(USER_ID, "pay rent")
+            .await
+            .expect("failed find todo");
+        assert_eq!(Some(ids[1]), found.map(|todo| todo.id));
+
+        repository
+            .delete(USER_ID, ids[1], Precondition::default())
+            .await
+            .expect("failed delete todo");
+        repository
+            .archive(USER_ID, ids[2])
+            .await
+            .expect("failed archive todo");
+        let found = repository
+            .find_open_by_text(USER_ID, "pay rent")
+            .await
+            .expect("failed find todo");
+        assert_eq!(None, found);
+
+        // 他のユーザーのTodo・大文字小文字の異なるテキストは一致しない
+        for (user_id, text) in [(USER_ID, "buy milk"), (USER_ID + 1, "Buy milk")] {
+            let found = repository
+                .find_open_by_text(user_id, text)
+                .await
+                .expect("failed find todo");
+            assert_eq!(None, found);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_merge_update_fields() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        let created = repository
+            .create(
+                USER_ID,
+                CreateTodo::new("before update".to_string(), vec![]),
+            )
+            .await
+            .expect("failed create todo");
+
+        let todo = repository
+            .update(
+                USER_ID,
+                created.id,
+                UpdateTodo {
+                    text: None,
+                    completed: Some(true),
+                    labels: None,
+                    version: None,
+                    due_date: None,
+                    priority: None,
+                    recurrence: None,
+                    remind_at: None,
+                    precondition: Precondition::default(),
+                },
+            )
+            .await
+            .expect("failed update todo");
+        assert_eq!("before update", todo.text);
+        assert!(todo.completed);
+    }
+
+    #[tokio::test]
+    async fn should_not_found_on_update_missing_todo() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        let res = repository
+            .update(
+                USER_ID,
+                1,
+                UpdateTodo {
+                    text: Some("missing".to_string()),
+                    completed: None,
+                    labels: None,
+                    version: None,
+                    due_date: None,
+                    priority: None,
+                    recurrence: None,
+                    remind_at: None,
+                    precondition: Precondition::default(),
+                },
+            )
+            .await;
+        let err = res.expect_err("update of missing todo returned Ok");
+        assert!(matches!(&err, RepositoryError::NotFound(Some(1))));
+    }
+
+    #[tokio::test]
+    async fn should_stamp_times_from_clock() {
+        let clock = MockClock::default();
+        let repository = TodoRepositoryForMemory::new(vec![]).with_clock(clock.clone());
+        let created_at = clock.now();
+        let created = repository
+            .create(USER_ID, CreateTodo::new("pinned".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        assert_eq!(created_at, created.created_at);
+        assert_eq!(created_at, created.updated_at);
+
+        clock.advance(Duration::minutes(5));
+        let updated = repository
+            .update(
+                USER_ID,
+                created.id,
+                UpdateTodo {
+                    completed: Some(true),
+                    ..Default::default()
+                },
+            )
+            .await
+            .expect("failed update todo");
+        assert_eq!(created_at, updated.created_at);
+        assert_eq!(created_at + Duration::minutes(5), updated.updated_at);
+
+        clock.advance(Duration::minutes(5));
+        let archived = repository
+            .archive(USER_ID, created.id)
+            .await
+            .expect("failed archive todo");
+        assert_eq!(Some(clock.now()), archived.archived_at);
+        assert_eq!(clock.now(), archived.updated_at);
+
+        clock.advance(Duration::minutes(5));
+        repository
+            .delete(USER_ID, created.id, Precondition::default())
+            .await
+            .expect("failed delete todo");
+        let trashed = repository.trash(USER_ID).await.unwrap();
+        assert_eq!(Some(clock.now()), trashed[0].deleted_at);
+
+        let activities = repository
+            .activity(USER_ID, created.id, PageQuery::default())
+            .await
+            .expect("failed get activity");
+        assert_eq!(
+            Some(&clock.now()),
+            activities.activities.first().map(|a| &a.created_at)
+        );
+    }
+
+    #[tokio::test]
+    async fn should_filter_by_due_date() {
+        let clock = MockClock::default();
+        let repository = TodoRepositoryForMemory::new(vec![]).with_clock(clock.clone());
+        let now = clock.now();
+        let yesterday = repository
+            .create(
+                USER_ID,
+                CreateTodo::new("yesterday".to_string(), vec![])
+                    .with_due_date(now - Duration::days(1)),
+            )
+            .await
+            .expect("failed create todo");
+        let tomorrow = repository
+            .create(
+                USER_ID,
+                CreateTodo::new("tomorrow".to_string(), vec![])
+                    .with_due_date(now + Duration::days(1)),
+            )
+            .await
+            .expect("failed create todo");
+        repository
+            .create(USER_ID, CreateTodo::new("no due".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+
+        let ids = |page: TodoPage| page.todos.iter().map(|t| t.id).collect::<Vec<_>>();
+        let page = repository
+            .all(
+                USER_ID,
+                TodoListQuery {
+                    due_before: Some(now),
+                    ..TodoListQuery::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(vec![yesterday.id], ids(page));
+        let page = repository
+            .all(
+                USER_ID,
+                TodoListQuery {
+                    due_after: Some(now),
+                    ..TodoListQuery::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(vec![tomorrow.id], ids(page));
+        let page = repository
+            .all(
+                USER_ID,
+                TodoListQuery {
+                    overdue: Some(true),
+                    ..TodoListQuery::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(vec![yesterday.id], ids(page));
+
+        // 完了済みのTodoは期限を過ぎていても対象外
+        repository
+            .update_many(
+                USER_ID,
+                UpdateTodos::new(vec![yesterday.id], None, Some(true)),
+            )
+            .await
+            .unwrap();
+        let page = repository
+            .all(
+                USER_ID,
+                TodoListQuery {
+                    overdue: Some(true),
+                    ..TodoListQuery::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(0, page.total);
+
+        // 時計を進めると、明日が期限のTodoも期限切れになる
+        clock.advance(Duration::days(2));
+        let page = repository
+            .all(
+                USER_ID,
+                TodoListQuery {
+                    overdue: Some(true),
+                    ..TodoListQuery::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(vec![tomorrow.id], ids(page));
+    }
+
+    #[tokio::test]
+    async fn should_filter_and_sort_by_priority() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        for (text, priority) in [
+            ("high", Priority::High),
+            ("low", Priority::Low),
+            ("medium", Priority::Medium),
+        ] {
+            repository
+                .create(
+                    USER_ID,
+                    CreateTodo::new(text.to_string(), vec![]).with_priority(priority),
+                )
+                .await
+                .expect("failed create todo");
+        }
+
+        let page = repository
+            .all(
+                USER_ID,
+                TodoListQuery {
+                    sort: Some(SortField::Priority),
+                    ..TodoListQuery::default()
+                },
+            )
+            .await
+            .unwrap();
+        let texts: Vec<&str> = page.todos.iter().map(|t| t.text.as_str()).collect();
+        assert_eq!(vec!["high", "medium", "low"], texts);
+
+        let page = repository
+            .all(
+                USER_ID,
+                TodoListQuery {
+                    priority: Some("low".to_string()),
+                    ..TodoListQuery::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(1, page.total);
+        assert_eq!(Priority::Low, page.todos[0].priority);
+    }
+
+    #[test]
+    fn priority_validation_test() {
+        let query = TodoListQuery {
+            priority: Some("urgent".to_string()),
+            ..TodoListQuery::default()
+        };
+        assert!(query.validate().is_err());
+        assert_eq!(Ok(Priority::High), "high".parse::<Priority>());
+        assert_eq!(
+            Priority::Medium,
+            CreateTodo::new("text".to_string(), vec![]).priority()
+        );
+    }
+
+    #[tokio::test]
+    async fn should_update_only_matching_version() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        let created = repository
+            .create(USER_ID, CreateTodo::new("original".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        assert_eq!(1, created.version);
+
+        let update = |text: &str, version| UpdateTodo {
+            text: Some(text.to_string()),
+            completed: None,
+            labels: None,
+            version,
+            due_date: None,
+            priority: None,
+            recurrence: None,
+            remind_at: None,
+            precondition: Precondition::default(),
+        };
+        let updated = repository
+            .update(USER_ID, created.id, update("first", Some(1)))
+            .await
+            .expect("failed update todo");
+        assert_eq!(2, updated.version);
+
+        let err = repository
+            .update(USER_ID, created.id, update("stale", Some(1)))
+            .await
+            .expect_err("stale update returned Ok");
+        assert!(matches!(
+            &err,
+            RepositoryError::Conflict(id) if *id == created.id
+        ));
+        assert_eq!(updated, repository.find(USER_ID, created.id).await.unwrap());
+
+        // versionを省略した場合は無条件に更新する
+        let forced = repository
+            .update(USER_ID, created.id, update("forced", None))
+            .await
+            .expect("failed update todo");
+        assert_eq!(3, forced.version);
+    }
+
+    #[tokio::test]
+    async fn should_update_and_delete_only_when_precondition_holds() {
+        let clock = MockClock::default();
+        let repository = TodoRepositoryForMemory::new(vec![]).with_clock(clock.clone());
+        let created = repository
+            .create(USER_ID, CreateTodo::new("original".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        let read_at = clock.now();
+        clock.advance(chrono::Duration::minutes(1));
+        let updated = repository
+            .update(
+                USER_ID,
+                created.id,
+                UpdateTodo::default().with_text("other client".to_string()),
+            )
+            .await
+            .expect("failed update todo");
+
+        // 読んだ後に他のクライアントが更新したため、どちらの条件も満たさない
+        for precondition in [
+            Precondition::default().with_version(created.version),
+            Precondition::default().with_unmodified_since(read_at),
+        ] {
+            let err = repository
+                .update(
+                    USER_ID,
+                    created.id,
+                    UpdateTodo::default()
+                        .with_text("stale".to_string())
+                        .with_precondition(precondition),
+                )
+                .await
+                .expect_err("stale update returned Ok");
+            assert!(matches!(
+                &err,
+                RepositoryError::PreconditionFailed(id) if *id == created.id
+            ));
+            let err = repository
+                .delete(USER_ID, created.id, precondition)
+                .await
+                .expect_err("stale delete returned Ok");
+            assert!(matches!(&err, RepositoryError::PreconditionFailed(_)));
+        }
+        assert_eq!(updated, repository.find(USER_ID, created.id).await.unwrap());
+
+        repository
+            .delete(
+                USER_ID,
+                created.id,
+                Precondition::default().with_unmodified_since(clock.now()),
+            )
+            .await
+            .expect("failed delete todo");
+        assert!(repository.find(USER_ID, created.id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn should_spawn_next_occurrence_once() {
+        let label = Label::new(1, String::from("home"));
+        let repository = TodoRepositoryForMemory::new(vec![label.clone()]);
+        let due_date = Utc::now();
+        let created = repository
+            .create(
+                USER_ID,
+                CreateTodo::new("water plants".to_string(), vec![label.id])
+                    .with_due_date(due_date)
+                    .with_recurrence(Recurrence::EveryNDays { days: 3 }),
+            )
+            .await
+            .expect("failed create todo");
+        let complete = UpdateTodo {
+            completed: Some(true),
+            ..Default::default()
+        };
+
+        let completed = repository
+            .update(USER_ID, created.id, complete.clone())
+            .await
+            .expect("failed update todo");
+        let next = repository
+            .find(USER_ID, completed.next_occurrence_id.unwrap())
+            .await
+            .expect("failed find next occurrence");
+        assert!(!next.completed);
+        assert_eq!(Some(due_date + Duration::days(3)), next.due_date);
+        assert_eq!(vec![label], next.labels);
+        assert_eq!(created.recurrence, next.recurrence);
+        assert_eq!(None, next.next_occurrence_id);
+
+        // 完了を取り消して再度完了しても、次の回は増えない
+        for completed in [false, true] {
+            repository
+                .update(
+                    USER_ID,
+                    created.id,
+                    UpdateTodo {
+                        completed: Some(completed),
+                        ..Default::default()
+                    },
+                )
+                .await
+                .expect("failed update todo");
+        }
+        let page = repository
+            .all(USER_ID, TodoListQuery::default())
+            .await
+            .expect("failed get all todo");
+        assert_eq!(2, page.total);
+
+        // 次の回を完全に削除すると参照が外れる
+        repository
+            .delete_permanently(USER_ID, next.id)
+            .await
+            .expect("failed delete todo");
+        let todo = repository.find(USER_ID, created.id).await.unwrap();
+        assert_eq!(None, todo.next_occurrence_id);
+    }
+
+    #[tokio::test]
+    async fn should_not_spawn_after_recurrence_cleared() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        let created = repository
+            .create(
+                USER_ID,
+                CreateTodo::new("daily".to_string(), vec![]).with_recurrence(Recurrence::Daily),
+            )
+            .await
+            .expect("failed create todo");
+        let completed = repository
+            .update(
+                USER_ID,
+                created.id,
+                UpdateTodo {
+                    completed: Some(true),
+                    recurrence: Some(None),
+                    ..Default::default()
+                },
+            )
+            .await
+            .expect("failed update todo");
+        assert_eq!(None, completed.recurrence);
+        assert_eq!(None, completed.next_occurrence_id);
+    }
+
+    #[test]
+    fn recurrence_test() {
+        let now = Utc::now();
+        assert_eq!(
+            now + Duration::days(1),
+            Recurrence::Daily.next_due_date(None, now)
+        );
+        assert_eq!(
+            now + Duration::days(14),
+            Recurrence::Weekly.next_due_date(Some(now + Duration::days(7)), now)
+        );
+        assert!(CreateTodo::new("text".to_string(), vec![])
+            .with_recurrence(Recurrence::EveryNDays { days: 0 })
+            .validate()
+            .is_err());
+        assert_eq!(
+            Recurrence::EveryNDays { days: 3 },
+            serde_json::from_str::<Recurrence>(r#"{"type":"every_n_days","days":3}"#).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn should_mark_due_reminders_once() {
+        let clock = MockClock::default();
+        let repository = TodoRepositoryForMemory::new(vec![]).with_clock(clock.clone());
+        let now = clock.now();
+        let create = |text: &str, remind_at| {
+            repository.create(
+                USER_ID,
+                CreateTodo::new(text.to_string(), vec![]).with_remind_at(remind_at),
+            )
+        };
+        let late = create("late", now - Duration::minutes(1)).await.unwrap();
+        let earlier = create("earlier", now - Duration::hours(1)).await.unwrap();
+        let future = create("future", now + Duration::hours(1)).await.unwrap();
+        let completed = create("completed", now).await.unwrap();
+        repository
+            .update(
+                USER_ID,
+                completed.id,
+                UpdateTodo {
+                    completed: Some(true),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        let trashed = create("trashed", now).await.unwrap();
+        repository
+            .delete(USER_ID, trashed.id, Precondition::default())
+            .await
+            .unwrap();
+
+        let reminders = repository.due_reminders(now).await.unwrap();
+        assert_eq!(
+            vec![earlier.id, late.id],
+            reminders.iter().map(|r| r.todo.id).collect::<Vec<_>>()
+        );
+        assert!(reminders.iter().all(|r| r.user_id == USER_ID));
+        assert_eq!(Some(now), reminders[0].todo.reminded_at);
+        assert!(repository.due_reminders(now).await.unwrap().is_empty());
+
+        // 時刻が進むと未来の通知も対象になる
+        let later = now + Duration::hours(2);
+        let reminders = repository.due_reminders(later).await.unwrap();
+        assert_eq!(future.id, reminders[0].todo.id);
+        assert_eq!(1, reminders.len());
+
+        // 通知日時を変更すると、通知済みの状態は解除される
+        let updated = repository
+            .update(
+                USER_ID,
+                late.id,
+                UpdateTodo {
+                    remind_at: Some(Some(later)),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        assert_eq!(None, updated.reminded_at);
+        assert_eq!(1, repository.due_reminders(later).await.unwrap().len());
+    }
+
+    #[tokio::test]
+    async fn should_delete_todo_only_once() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        let created = repository
+            .create(USER_ID, CreateTodo::new("delete me".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+
+        repository
+            .delete(USER_ID, created.id, Precondition::default())
+            .await
+            .expect("failed delete todo");
+        assert!(repository.find(USER_ID, created.id).await.is_err());
+
+        let err = repository
+            .delete(USER_ID, created.id, Precondition::default())
+            .await
+            .expect_err("second delete returned Ok");
+        assert!(matches!(
+            &err,
+            RepositoryError::NotFound(Some(id)) if *id == created.id
+        ));
+    }
+
+    #[tokio::test]
+    async fn should_trash_and_restore_own_todos() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        let mine = repository
+            .create(USER_ID, CreateTodo::new("mine".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        let others = repository
+            .create(USER_ID + 1, CreateTodo::new("others".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        for (user_id, id) in [(USER_ID, mine.id), (USER_ID + 1, others.id)] {
+            repository
+                .delete(user_id, id, Precondition::default())
+                .await
+                .expect("failed delete todo");
+        }
+
+        let page = repository
+            .all(USER_ID, TodoListQuery::default())
+            .await
+            .expect("failed get all todo");
+        assert_eq!(0, page.total);
+        let trash = repository.trash(USER_ID).await.expect("failed get trash");
+        assert_eq!(
+            vec![mine.id],
+            trash.iter().map(|t| t.id).collect::<Vec<_>>()
+        );
+        assert!(repository
+            .update_many(USER_ID, UpdateTodos::new(vec![mine.id], None, Some(true)))
+            .await
+            .unwrap()
+            .todos
+            .is_empty());
+
+        assert!(repository.restore(USER_ID, others.id).await.is_err());
+        let restored = repository
+            .restore(USER_ID, mine.id)
+            .await
+            .expect("failed restore todo");
+        assert_eq!(None, restored.deleted_at);
+        assert_eq!(restored, repository.find(USER_ID, mine.id).await.unwrap());
+        assert!(repository.trash(USER_ID).await.unwrap().is_empty());
+
+        let err = repository
+            .restore(USER_ID, mine.id)
+            .await
+            .expect_err("restore outside trash returned Ok");
+        assert!(matches!(
+            &err,
+            RepositoryError::NotFound(Some(id)) if *id == mine.id
+        ));
+    }
+
+    #[tokio::test]
+    async fn should_purge_todos_trashed_before_cutoff() {
+        let clock = MockClock::default();
+        let repository = TodoRepositoryForMemory::new(vec![]).with_clock(clock.clone());
+        let mut ids = vec![];
+        for user_id in [USER_ID, USER_ID + 1] {
+            for text in ["open", "trashed"] {
+                let todo = repository
+                    .create(user_id, CreateTodo::new(text.to_string(), vec![]))
+                    .await
+                    .expect("failed create todo");
+                ids.push(todo.id);
+            }
+        }
+        let deleted_at = clock.now();
+        for (user_id, id) in [(USER_ID, ids[1]), (USER_ID + 1, ids[3])] {
+            repository
+                .delete(user_id, id, Precondition::default())
+                .await
+                .expect("failed delete todo");
+        }
+
+        let purged = repository
+            .purge_deleted_before(deleted_at)
+            .await
+            .expect("failed purge todos");
+        assert_eq!(0, purged);
+        let trash = repository.trash(USER_ID).await.unwrap();
+        assert_eq!(
+            vec![Some(deleted_at)],
+            trash.iter().map(|t| t.deleted_at).collect::<Vec<_>>()
+        );
+
+        let purged = repository
+            .purge_deleted_before(deleted_at + Duration::seconds(1))
+            .await
+            .expect("failed purge todos");
+        assert_eq!(2, purged);
+        for user_id in [USER_ID, USER_ID + 1] {
+            assert!(repository.trash(user_id).await.unwrap().is_empty());
+            let page = repository
+                .all(user_id, TodoListQuery::default())
+                .await
+                .expect("failed get all todo");
+            assert_eq!(1, page.total);
+        }
+    }
+
+    #[tokio::test]
+    async fn should_delete_trashed_todo_permanently() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        let created = repository
+            .create(USER_ID, CreateTodo::new("gone".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        repository
+            .delete(USER_ID, created.id, Precondition::default())
+            .await
+            .expect("failed delete todo");
+        assert!(repository
+            .delete_permanently(USER_ID + 1, created.id)
+            .await
+            .is_err());
+        repository
+            .delete_permanently(USER_ID, created.id)
+            .await
+            .expect("failed delete todo permanently");
+        assert!(repository.trash(USER_ID).await.unwrap().is_empty());
+        assert!(repository.restore(USER_ID, created.id).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn should_delete_items_with_todo() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        let mut ids = vec![];
+        for text in ["kept", "completed", "trashed"] {
+            let todo = repository
+                .create(USER_ID, CreateTodo::new(text.to_string(), vec![]))
+                .await
+                .expect("failed create todo");
+            repository
+                .create_item(
+                    USER_ID,
+                    todo.id,
+                    CreateTodoItem {
+                        text: format!("{} step", text),
+                    },
+                )
+                .await
+                .expect("failed create item");
+            ids.push(todo.id);
+        }
+        repository
+            .update_many(USER_ID, UpdateTodos::new(vec![ids[1]], None, Some(true)))
+            .await
+            .unwrap();
+        repository.delete_completed(USER_ID).await.unwrap();
+        repository
+            .delete(USER_ID, ids[2], Precondition::default())
+            .await
+            .unwrap();
+        repository
+            .delete_permanently(USER_ID, ids[2])
+            .await
+            .unwrap();
+
+        let remaining: Vec<i64> = repository
+            .items
+            .read()
+            .unwrap()
+            .iter()
+            .map(|item| item.todo_id)
+            .collect();
+        assert_eq!(vec![ids[0]], remaining);
+    }
+
+    #[tokio::test]
+    async fn should_not_reuse_deleted_id() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        let first = repository
+            .create(USER_ID, CreateTodo::new("first".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        let second = repository
+            .create(USER_ID, CreateTodo::new("second".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        repository
+            .delete_permanently(USER_ID, first.id)
+            .await
+            .expect("failed delete todo");
+
+        let third = repository
+            .create(USER_ID, CreateTodo::new("third".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        assert!(third.id > second.id);
+        assert_eq!(
+            "second",
+            repository.find(USER_ID, second.id).await.unwrap().text
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn should_assign_unique_ids_to_concurrent_creates() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        let tasks: Vec<_> = (0..100)
+            .map(|i| {
+                let repository = repository.clone();
+                tokio::spawn(async move {
+                    repository
+                        .create(USER_ID, CreateTodo::new(format!("todo {}", i), vec![]))
+                        .await
+                        .expect("failed create todo")
+                        .id
+                })
+            })
+            .collect();
+        let mut ids = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            ids.push(task.await.unwrap());
+        }
+
+        ids.sort_unstable();
+        assert_eq!((1..=100).collect::<Vec<i64>>(), ids);
+        let query = TodoListQuery {
+            limit: Some(100),
+            ..TodoListQuery::default()
+        };
+        assert_eq!(100, repository.all(USER_ID, query).await.unwrap().total);
+    }
+
+    #[tokio::test]
+    async fn should_keep_working_after_panic_while_holding_lock() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        let before = repository
+            .create(USER_ID, CreateTodo::new("before panic".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        // ストアとpositionsの書き込みロックを保持したまま作成が失敗する
+        repository.panic_on_next_write();
+        let panicked = {
+            let repository = repository.clone();
+            tokio::spawn(async move {
+                repository
+                    .create(USER_ID, CreateTodo::new("panics".to_string(), vec![]))
+                    .await
+            })
+        }
+        .await;
+        assert!(panicked.expect_err("expected panic").is_panic());
+        assert!(repository.positions.is_poisoned());
+
+        // 毒状態になったロックも、後続の操作では通常どおり使える
+        let todo = repository
+            .create(USER_ID, CreateTodo::new("after panic".to_string(), vec![]))
+            .await
+            .expect("failed create todo after panic");
+        assert_eq!(
+            todo,
+            repository
+                .find(USER_ID, todo.id)
+                .await
+                .expect("failed find todo")
+        );
+        let page = repository
+            .all(USER_ID, TodoListQuery::default())
+            .await
+            .expect("failed list todos after panic");
+        assert_eq!(
+            vec![todo.id, before.id],
+            page.todos.iter().map(|todo| todo.id).collect::<Vec<i64>>()
+        );
+        assert_eq!(
+            None,
+            repository
+                .claim_idempotency_key(USER_ID, "key", "fingerprint")
+                .await
+                .expect("failed claim key after panic")
+        );
+    }
+
+    #[tokio::test]
+    async fn should_not_wrap_around_exhausted_ids() {
+        let repository = TodoRepositoryForMemory::new(vec![]);
+        repository
+            .restore_snapshot(TodoSnapshot {
+                last_id: i64::MAX - 1,
+                ..TodoSnapshot::default()
+            })
+            .await;
+
+        let last = repository
+            .create(USER_ID, CreateTodo::new("last".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        assert_eq!(i64::MAX, last.id);
+
+        let res = repository
+            .create(USER_ID, CreateTodo::new("overflow".to_string(), vec![]))
+            .await;
+        assert!(matches!(res, Err(RepositoryError::Unexpected(_))));
+        assert_eq!(
+            last,
+            repository
+                .find(USER_ID, i64::MAX)
+                .await
+                .expect("failed find todo")
+        );
+    }
+
+    #[tokio::test]
+    async fn should_import_exported_todos_with_merged_labels() {
+        let label = Label::new(1, String::from("Work"));
+        let source = TodoRepositoryForMemory::new(vec![label.clone()]);
+        let labeled = source
+            .create(
+                USER_ID,
+                CreateTodo::new("labeled".to_string(), vec![label.id]),
+            )
+            .await
+            .expect("failed create todo");
+        source
+            .create(USER_ID, CreateTodo::new("plain".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        let backup = source.export(USER_ID).await.expect("failed export");
+        assert_eq!(2, backup.todos.len());
+        assert_eq!(1, backup.associations.len());
+        assert_eq!(labeled.id, backup.associations[0].todo_id);
+
+        // 大文字小文字のみ異なる既存のラベルへ統合し、idは採番し直す
+        let existing = Label::new(5, String::from("work"));
+        let target = TodoRepositoryForMemory::new(vec![existing.clone()]);
+        target
+            .create(2, CreateTodo::new("other user".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        let summary = target.import(USER_ID, backup).await.expect("failed import");
+        assert_eq!(
+            ImportSummary {
+                todos: 2,
+                labels: 0
+            },
+            summary
+        );
+
+        let imported = target.export(USER_ID).await.expect("failed export");
+        assert_eq!(
+            vec![2, 3],
+            imported.todos.iter().map(|t| t.id).collect::<Vec<_>>()
+        );
+        assert_eq!("labeled", imported.todos[0].text);
+        assert_eq!(labeled.created_at, imported.todos[0].created_at);
+        assert_eq!(
+            vec![BackupAssociation {
+                todo_id: 2,
+                label_id: existing.id
+            }],
+            imported.associations
+        );
+        assert_eq!(vec![BackupLabel::from(existing)], imported.labels);
+    }
+}
@@ -0,0 +1,1688 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use futures_util::stream::{self, BoxStream, StreamExt, TryStreamExt};
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::types::Json;
+use sqlx::{FromRow, Sqlite, SqlitePool, Transaction};
+use tokio::sync::mpsc;
+use tracing::instrument;
+use uuid::Uuid;
+
+use super::{
+    fold_entities, idempotent_response, recurrence_to_spawn, reposition, CreateTodo,
+    CreateTodoWithLabelNames, DueReminder, DuplicateTodo, FoundTodos, IdempotencyRecord,
+    LabelCount, MoveTarget, Precondition, Priority, Recurrence, ReplaceTodo, TodoEntity,
+    TodoFromRow, TodoListQuery, TodoPage, TodoRepository, TodoStats, TodoWithLabelFromRow,
+    UpdateTodo, UpdateTodos, UpdatedTodos, COMPLETE_IDEMPOTENCY_KEY, INSERT_TODO, POSITION_GAP,
+    RELEASE_IDEMPOTENCY_KEY, STREAM_BUFFER,
+};
+use crate::clock::{Clock, SharedClock};
+use crate::repositories::backup::{Backup, ImportSummary};
+use crate::repositories::label::{Label, DEFAULT_LABEL_COLOR};
+use crate::repositories::todo_activity::{TodoAction, TodoActivity, TodoActivityPage, TodoChange};
+use crate::repositories::todo_item::{move_item, CreateTodoItem, TodoItem, UpdateTodoItem};
+use crate::repositories::{PageQuery, RepositoryError};
+
+// SQLiteには配列型がないため、idの一覧はJSONの配列としてbindし、json_eachで展開する
+// insert ... selectの直後のon conflictは構文を区別できないため、where trueを挟む
+const INSERT_TODO_LABELS: &str = r#"
+insert into todo_labels (todo_id, label_id) select $1, value from json_each($2) where true
+on conflict (todo_id, label_id) do nothing;
+"#;
+
+const SELECT_TODOS_WITH_LABELS: &str = r#"
+select todos.*, labels.id as label_id, labels.name as label_name, labels.color as label_color,
+       labels.description as label_description
+from todos
+left outer join todo_labels tl on todos.id = tl.todo_id
+left outer join labels on labels.id = tl.label_id
+"#;
+
+// JSONの列はserde_json::Valueのまま読めないため、Jsonで包んで受け取る
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+struct TodoActivityFromRow {
+    id: i32,
+    todo_id: i64,
+    action: TodoAction,
+    old_value: Option<Json<Value>>,
+    new_value: Option<Json<Value>>,
+    actor_id: i32,
+    created_at: DateTime<Utc>,
+}
+
+impl From<TodoActivityFromRow> for TodoActivity {
+    fn from(row: TodoActivityFromRow) -> Self {
+        Self {
+            id: row.id,
+            todo_id: row.todo_id,
+            action: row.action,
+            old_value: row.old_value.map(|Json(value)| value),
+            new_value: row.new_value.map(|Json(value)| value),
+            actor_id: row.actor_id,
+            created_at: row.created_at,
+        }
+    }
+}
+
+// 書き込みは1本の接続で直列に行うため、Postgres版のような行ロックは使わない
+// トランザクションを開いている間にself.poolを使うと接続の空き待ちで止まるので、必ずtxを使う
+#[derive(Debug, Clone)]
+pub struct TodoRepositoryForSqlite {
+    pool: SqlitePool,
+    clock: SharedClock,
+}
+
+impl TodoRepositoryForSqlite {
+    pub fn new(pool: SqlitePool, clock: impl Clock) -> Self {
+        TodoRepositoryForSqlite {
+            pool,
+            clock: Arc::new(clock),
+        }
+    }
+
+    async fn find_label(&self, label_id: i32) -> Result<Label, RepositoryError> {
+        let label = sqlx::query_as::<_, Label>("select * from labels where id = $1")
+            .bind(label_id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or(RepositoryError::NotFound(Some(label_id.into())))?;
+        Ok(label)
+    }
+
+    async fn missing_label(
+        tx: &mut Transaction<'_, Sqlite>,
+        labels: &[i32],
+    ) -> Result<Option<i32>, RepositoryError> {
+        let found: Vec<i32> = sqlx::query_as::<_, (i32,)>(
+            "select id from labels where id in (select value from json_each($1))",
+        )
+        .bind(Json(labels))
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|(id,)| id)
+        .collect();
+        Ok(labels.iter().find(|id| !found.contains(id)).copied())
+    }
+
+    // 外部キーの違反はcommit時まで分からず、違反したidも特定できないため、先に存在を確かめる
+    async fn ensure_labels(
+        tx: &mut Transaction<'_, Sqlite>,
+        labels: &[i32],
+    ) -> Result<(), RepositoryError> {
+        match Self::missing_label(tx, labels).await? {
+            Some(id) => Err(RepositoryError::InvalidLabel(id)),
+            None => Ok(()),
+        }
+    }
+
+    // 1件でも存在しないラベルがあれば、何も登録せずに失敗させる
+    async fn insert_many(
+        tx: &mut Transaction<'_, Sqlite>,
+        user_id: i32,
+        payloads: Vec<CreateTodo>,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<i64>, RepositoryError> {
+        let label_ids: Vec<i32> = payloads
+            .iter()
+            .flat_map(|payload| payload.labels.iter().copied())
+            .collect();
+        if let Some(missing) = Self::missing_label(tx, &label_ids).await? {
+            return Err(RepositoryError::NotFound(Some(missing.into())));
+        }
+
+        let mut ids = Vec::with_capacity(payloads.len());
+        for payload in payloads {
+            let priority = payload.priority();
+            let row = sqlx::query_as::<_, TodoFromRow>(INSERT_TODO)
+                .bind(payload.text)
+                .bind(user_id)
+                .bind(payload.due_date)
+                .bind(priority)
+                .bind(POSITION_GAP)
+                .bind(payload.recurrence.map(Json))
+                .bind(payload.remind_at)
+                .bind(now)
+                .bind(payload.client_id)
+                .fetch_one(&mut *tx)
+                .await?;
+
+            sqlx::query(INSERT_TODO_LABELS)
+                .bind(row.id)
+                .bind(Json(payload.labels))
+                .execute(&mut *tx)
+                .await?;
+            ids.push(row.id);
+        }
+
+        let todos = Self::entities_in(tx, user_id, &ids).await?;
+        Self::record_activities(
+            tx,
+            user_id,
+            todos.iter().map(TodoChange::created).collect(),
+            now,
+        )
+        .await?;
+        Ok(ids)
+    }
+
+    // 所有者・ゴミ箱を問わずに引く。通知のように複数のユーザーのTodoをまとめて扱う場合のみ使う
+    async fn find_across_users(&self, ids: Vec<i64>) -> Result<Vec<TodoEntity>, RepositoryError> {
+        let sql = format!(
+            "{} where todos.id in (select value from json_each($1)) order by todos.id asc",
+            SELECT_TODOS_WITH_LABELS
+        );
+        let items = sqlx::query_as::<_, TodoWithLabelFromRow>(&sql)
+            .bind(Json(ids))
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(fold_entities(items))
+    }
+
+    // 同名(大文字小文字を区別しない)のラベルがあればそのidを、なければ作成したidを返す
+    async fn upsert_label(
+        tx: &mut Transaction<'_, Sqlite>,
+        name: &str,
+        color: &str,
+        description: Option<&str>,
+    ) -> Result<(i32, bool), RepositoryError> {
+        let inserted = sqlx::query_as::<_, (i32,)>(
+            r#"
+insert into labels (name, color, description) values ($1, $2, $3)
+on conflict do nothing
+returning id;
+"#,
+        )
+        .bind(name)
+        .bind(color)
+        .bind(description)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if let Some((id,)) = inserted {
+            return Ok((id, true));
+        }
+        let (id,) =
+            sqlx::query_as::<_, (i32,)>("select id from labels where lower(name) = lower($1)")
+                .bind(name)
+                .fetch_one(&mut *tx)
+                .await?;
+        Ok((id, false))
+    }
+
+    // importとseedで共有する。commitは呼び出し側で行う
+    async fn import_in(
+        tx: &mut Transaction<'_, Sqlite>,
+        user_id: i32,
+        backup: Backup,
+    ) -> Result<ImportSummary, RepositoryError> {
+        let mut summary = ImportSummary::default();
+
+        let mut label_ids = HashMap::with_capacity(backup.labels.len());
+        for label in backup.labels {
+            let (id, created) =
+                Self::upsert_label(tx, &label.name, &label.color, label.description.as_deref())
+                    .await?;
+            if created {
+                summary.labels += 1;
+            }
+            label_ids.insert(label.id, id);
+        }
+
+        let mut todo_ids = HashMap::with_capacity(backup.todos.len());
+        for todo in backup.todos {
+            let (id,) = sqlx::query_as::<_, (i64,)>(
+                r#"
+insert into todos (text, completed, user_id, due_date, priority, created_at, updated_at, position)
+values ($1, $2, $3, $4, $5, $6, $7,
+        coalesce((select max(position) from todos where user_id = $3), 0) + $8)
+returning id
+"#,
+            )
+            .bind(todo.text)
+            .bind(todo.completed)
+            .bind(user_id)
+            .bind(todo.due_date)
+            .bind(todo.priority)
+            .bind(todo.created_at)
+            .bind(todo.updated_at)
+            .bind(POSITION_GAP)
+            .fetch_one(&mut *tx)
+            .await?;
+            todo_ids.insert(todo.id, id);
+        }
+        summary.todos = todo_ids.len();
+
+        // 参照先はBackup::validateで検証済みのため、見つからない組は存在しない
+        let associations: Vec<(i64, i32)> = backup
+            .associations
+            .iter()
+            .filter_map(|association| {
+                Some((
+                    *todo_ids.get(&association.todo_id)?,
+                    *label_ids.get(&association.label_id)?,
+                ))
+            })
+            .collect();
+        for (todo_id, label_id) in associations {
+            sqlx::query(
+                r#"
+insert into todo_labels (todo_id, label_id) values ($1, $2)
+on conflict (todo_id, label_id) do nothing;
+"#,
+            )
+            .bind(todo_id)
+            .bind(label_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        Ok(summary)
+    }
+
+    // 登録済みのキーへ、作成したものを応答として記録する
+    async fn complete_in(
+        tx: &mut Transaction<'_, Sqlite>,
+        user_id: i32,
+        key: &str,
+        created: &impl Serialize,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query(COMPLETE_IDEMPOTENCY_KEY)
+            .bind(user_id)
+            .bind(key)
+            .bind(Json(idempotent_response(created)?))
+            .execute(&mut *tx)
+            .await?;
+        Ok(())
+    }
+
+    // 完全な削除や並べ替えなど、updated_atやdeleted_atに残らない変更の後も一覧のLast-Modifiedを進める
+    pub(crate) async fn touch_lists(
+        tx: &mut Transaction<'_, Sqlite>,
+        user_ids: &[i32],
+        now: DateTime<Utc>,
+    ) -> Result<(), RepositoryError> {
+        if user_ids.is_empty() {
+            return Ok(());
+        }
+        sqlx::query(
+            r#"
+insert into todo_list_modifications (user_id, modified_at)
+select distinct value, $2 from json_each($1) where true
+on conflict (user_id) do update
+set modified_at = max(todo_list_modifications.modified_at, excluded.modified_at);
+"#,
+        )
+        .bind(Json(user_ids))
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+        Ok(())
+    }
+
+    // truncateがないため、参照する側から順に削除し、AUTOINCREMENTの採番も消す
+    async fn reset_in(tx: &mut Transaction<'_, Sqlite>) -> Result<(), RepositoryError> {
+        for table in [
+            "todo_labels",
+            "todo_items",
+            "comments",
+            "todo_activities",
+            "todos",
+            "labels",
+            "idempotency_keys",
+            "todo_list_modifications",
+        ] {
+            sqlx::query(&format!("delete from {}", table))
+                .execute(&mut *tx)
+                .await?;
+        }
+        sqlx::query(
+            "delete from sqlite_sequence where name in \
+             ('todo_labels', 'todo_items', 'comments', 'todo_activities', 'todos', 'labels')",
+        )
+        .execute(&mut *tx)
+        .await?;
+        Ok(())
+    }
+
+    async fn find_owned(
+        tx: &mut Transaction<'_, Sqlite>,
+        user_id: i32,
+        id: i64,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query_as::<_, (i64,)>(
+            "select id from todos where id=$1 and user_id=$2 and deleted_at is null",
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(RepositoryError::NotFound(Some(id)))?;
+        Ok(())
+    }
+
+    async fn items_of(
+        tx: &mut Transaction<'_, Sqlite>,
+        id: i64,
+    ) -> Result<Vec<TodoItem>, RepositoryError> {
+        let items = sqlx::query_as::<_, TodoItem>(
+            "select * from todo_items where todo_id=$1 order by position asc, id asc",
+        )
+        .bind(id)
+        .fetch_all(&mut *tx)
+        .await?;
+        Ok(items)
+    }
+
+    // 履歴に残す変更前後の状態を、変更と同じトランザクション内で読む
+    async fn entities_in(
+        tx: &mut Transaction<'_, Sqlite>,
+        user_id: i32,
+        ids: &[i64],
+    ) -> Result<Vec<TodoEntity>, RepositoryError> {
+        let sql = format!(
+            r#"{}
+where todos.id in (select value from json_each($1)) and todos.user_id = $2
+  and todos.deleted_at is null
+order by todos.id asc"#,
+            SELECT_TODOS_WITH_LABELS
+        );
+        let items = sqlx::query_as::<_, TodoWithLabelFromRow>(&sql)
+            .bind(Json(ids))
+            .bind(user_id)
+            .fetch_all(&mut *tx)
+            .await?;
+        Ok(fold_entities(items))
+    }
+
+    async fn record_activities(
+        tx: &mut Transaction<'_, Sqlite>,
+        user_id: i32,
+        changes: Vec<TodoChange>,
+        now: DateTime<Utc>,
+    ) -> Result<(), RepositoryError> {
+        for change in changes {
+            sqlx::query(
+                r#"
+insert into todo_activities (todo_id, action, old_value, new_value, actor_id, created_at)
+values ($1, $2, $3, $4, $5, $6);
+"#,
+            )
+            .bind(change.todo_id)
+            .bind(change.action)
+            .bind(change.old_value.map(Json))
+            .bind(change.new_value.map(Json))
+            .bind(user_id)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        }
+        Ok(())
+    }
+
+    // 完了したTodoのテキスト・優先度・ラベル・繰り返しを引き継ぎ、期限と通知日時を進めた次の回を作成する
+    async fn spawn_next_occurrence(
+        tx: &mut Transaction<'_, Sqlite>,
+        user_id: i32,
+        completed: &TodoEntity,
+        recurrence: Recurrence,
+    ) -> Result<(), RepositoryError> {
+        // 通知日時も期限と同じ間隔だけ先へずらす
+        let remind_at = completed
+            .remind_at
+            .map(|remind_at| recurrence.next_due_date(Some(remind_at), completed.updated_at));
+        let row = sqlx::query_as::<_, TodoFromRow>(INSERT_TODO)
+            .bind(completed.text.clone())
+            .bind(user_id)
+            .bind(recurrence.next_due_date(completed.due_date, completed.updated_at))
+            .bind(completed.priority)
+            .bind(POSITION_GAP)
+            .bind(Json(recurrence))
+            .bind(remind_at)
+            .bind(completed.updated_at)
+            .bind(None::<Uuid>)
+            .fetch_one(&mut *tx)
+            .await?;
+        sqlx::query(
+            "insert into todo_labels (todo_id, label_id) select $1, label_id from todo_labels where todo_id = $2",
+        )
+        .bind(row.id)
+        .bind(completed.id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("update todos set next_occurrence_id = $1 where id = $2")
+            .bind(row.id)
+            .bind(completed.id)
+            .execute(&mut *tx)
+            .await?;
+
+        let todos = Self::entities_in(tx, user_id, &[row.id]).await?;
+        Self::record_activities(
+            tx,
+            user_id,
+            todos.iter().map(TodoChange::created).collect(),
+            completed.updated_at,
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn touch(&self, id: i64) -> Result<(), RepositoryError> {
+        sqlx::query("update todos set updated_at = $2, version = version + 1 where id = $1")
+            .bind(id)
+            .bind(self.clock.now())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl TodoRepository for TodoRepositoryForSqlite {
+    #[instrument(name = "todo.create", skip_all, fields(db.operation = "INSERT"))]
+    async fn create(
+        &self,
+        user_id: i32,
+        payload: CreateTodo,
+    ) -> Result<TodoEntity, RepositoryError> {
+        self.create_idempotent(user_id, payload, None).await
+    }
+
+    #[instrument(name = "todo.create_many", skip_all, fields(db.operation = "INSERT"))]
+    async fn create_many(
+        &self,
+        user_id: i32,
+        payloads: Vec<CreateTodo>,
+    ) -> Result<Vec<TodoEntity>, RepositoryError> {
+        self.create_many_idempotent(user_id, payloads, None).await
+    }
+
+    #[instrument(name = "todo.create_idempotent", skip_all, fields(db.operation = "INSERT"))]
+    async fn create_idempotent(
+        &self,
+        user_id: i32,
+        payload: CreateTodo,
+        key: Option<&str>,
+    ) -> Result<TodoEntity, RepositoryError> {
+        let now = self.clock.now();
+        let mut tx = self.pool.begin().await?;
+        Self::ensure_labels(&mut tx, &payload.labels).await?;
+        let row = sqlx::query_as::<_, TodoFromRow>(INSERT_TODO)
+            .bind(payload.text.clone())
+            .bind(user_id)
+            .bind(payload.due_date)
+            .bind(payload.priority())
+            .bind(POSITION_GAP)
+            .bind(payload.recurrence.map(Json))
+            .bind(payload.remind_at)
+            .bind(now)
+            .bind(payload.client_id)
+            .fetch_one(&mut tx)
+            .await?;
+
+        sqlx::query(INSERT_TODO_LABELS)
+            .bind(row.id)
+            .bind(Json(payload.labels))
+            .execute(&mut tx)
+            .await?;
+
+        let todos = Self::entities_in(&mut tx, user_id, &[row.id]).await?;
+        Self::record_activities(
+            &mut tx,
+            user_id,
+            todos.iter().map(TodoChange::created).collect(),
+            now,
+        )
+        .await?;
+        if let Some(key) = key {
+            let todo = todos
+                .first()
+                .ok_or(RepositoryError::NotFound(Some(row.id)))?;
+            Self::complete_in(&mut tx, user_id, key, todo).await?;
+        }
+        tx.commit().await?;
+
+        self.find(user_id, row.id).await
+    }
+
+    #[instrument(name = "todo.create_many_idempotent", skip_all, fields(db.operation = "INSERT"))]
+    async fn create_many_idempotent(
+        &self,
+        user_id: i32,
+        payloads: Vec<CreateTodo>,
+        key: Option<&str>,
+    ) -> Result<Vec<TodoEntity>, RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        let ids = Self::insert_many(&mut tx, user_id, payloads, self.clock.now()).await?;
+        if let Some(key) = key {
+            let todos = Self::entities_in(&mut tx, user_id, &ids).await?;
+            Self::complete_in(&mut tx, user_id, key, &todos).await?;
+        }
+        tx.commit().await?;
+        // 同一トランザクション内で順に採番しているため、id順が入力順と一致する
+        Ok(self.find_many(user_id, &ids).await?.todos)
+    }
+
+    #[instrument(name = "todo.create_many_with_label_names", skip_all, fields(db.operation = "INSERT"))]
+    async fn create_many_with_label_names(
+        &self,
+        user_id: i32,
+        payloads: Vec<CreateTodoWithLabelNames>,
+    ) -> Result<Vec<TodoEntity>, RepositoryError> {
+        // ラベルの作成もTodoの登録と同じトランザクションで行い、失敗時は両方取り消す
+        let mut tx = self.pool.begin().await?;
+        let mut label_ids: HashMap<String, i32> = HashMap::new();
+        let mut todos = Vec::with_capacity(payloads.len());
+        for payload in payloads {
+            let mut todo = payload.todo;
+            for name in payload.label_names {
+                let key = name.to_lowercase();
+                let id = match label_ids.get(&key) {
+                    Some(id) => *id,
+                    None => {
+                        let (id, _) =
+                            Self::upsert_label(&mut tx, &name, DEFAULT_LABEL_COLOR, None).await?;
+                        label_ids.insert(key, id);
+                        id
+                    }
+                };
+                if !todo.labels.contains(&id) {
+                    todo.labels.push(id);
+                }
+            }
+            todos.push(todo);
+        }
+        let ids = Self::insert_many(&mut tx, user_id, todos, self.clock.now()).await?;
+        tx.commit().await?;
+        Ok(self.find_many(user_id, &ids).await?.todos)
+    }
+
+    #[instrument(name = "todo.find", skip_all, fields(db.operation = "SELECT"))]
+    async fn find(&self, user_id: i32, id: i64) -> Result<TodoEntity, RepositoryError> {
+        let sql = format!(
+            "{} where todos.id=$1 and todos.user_id=$2 and todos.deleted_at is null",
+            SELECT_TODOS_WITH_LABELS
+        );
+        let items = sqlx::query_as::<_, TodoWithLabelFromRow>(&sql)
+            .bind(id)
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let todos = fold_entities(items);
+        let todo = todos.first().ok_or(RepositoryError::NotFound(Some(id)))?;
+        Ok(todo.clone())
+    }
+
+    #[instrument(name = "todo.find_many", skip_all, fields(db.operation = "SELECT"))]
+    async fn find_many(&self, user_id: i32, ids: &[i64]) -> Result<FoundTodos, RepositoryError> {
+        if ids.is_empty() {
+            return Ok(FoundTodos::default());
+        }
+        let sql = format!(
+            "{} where todos.id in (select value from json_each($1)) \
+             and todos.user_id = $2 and todos.deleted_at is null order by todos.id asc",
+            SELECT_TODOS_WITH_LABELS
+        );
+        let items = sqlx::query_as::<_, TodoWithLabelFromRow>(&sql)
+            .bind(Json(ids))
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(FoundTodos::new(ids, fold_entities(items)))
+    }
+
+    #[instrument(name = "todo.exists", skip_all, fields(db.operation = "SELECT"))]
+    async fn exists(&self, user_id: i32, id: i64) -> Result<bool, RepositoryError> {
+        let exists = sqlx::query_scalar(
+            "select exists(select 1 from todos where id=$1 and user_id=$2 and deleted_at is null)",
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(exists)
+    }
+
+    #[instrument(name = "todo.find_open_by_text", skip_all, fields(db.operation = "SELECT"))]
+    async fn find_open_by_text(
+        &self,
+        user_id: i32,
+        text: &str,
+    ) -> Result<Option<TodoEntity>, RepositoryError> {
+        let id: Option<i64> = sqlx::query_scalar(
+            "select id from todos where user_id = $1 and text = $2 \
+             and not completed and deleted_at is null and archived_at is null \
+             order by id asc limit 1",
+        )
+        .bind(user_id)
+        .bind(text)
+        .fetch_optional(&self.pool)
+        .await?;
+        match id {
+            Some(id) => Ok(Some(self.find(user_id, id).await?)),
+            None => Ok(None),
+        }
+    }
+
+    #[instrument(name = "todo.find_by_client_id", skip_all, fields(db.operation = "SELECT"))]
+    async fn find_by_client_id(
+        &self,
+        user_id: i32,
+        client_id: Uuid,
+    ) -> Result<Option<TodoEntity>, RepositoryError> {
+        let id: Option<i64> = sqlx::query_scalar(
+            "select id from todos where user_id = $1 and client_id = $2 and deleted_at is null",
+        )
+        .bind(user_id)
+        .bind(client_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        match id {
+            Some(id) => Ok(Some(self.find(user_id, id).await?)),
+            None => Ok(None),
+        }
+    }
+
+    #[instrument(name = "todo.labels_for_todos", skip_all, fields(db.operation = "SELECT"))]
+    async fn labels_for_todos(
+        &self,
+        user_id: i32,
+        ids: Vec<i64>,
+    ) -> Result<HashMap<i64, Vec<Label>>, RepositoryError> {
+        let sql = format!(
+            "{} where todos.id in (select value from json_each($1)) and todos.user_id=$2 \
+             and todos.deleted_at is null order by todos.id asc, labels.id asc",
+            SELECT_TODOS_WITH_LABELS
+        );
+        let rows = sqlx::query_as::<_, TodoWithLabelFromRow>(&sql)
+            .bind(Json(ids))
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(fold_entities(rows)
+            .into_iter()
+            .map(|todo| (todo.id, todo.labels))
+            .collect())
+    }
+
+    #[instrument(name = "todo.all", skip_all, fields(db.operation = "SELECT"))]
+    async fn all(&self, user_id: i32, query: TodoListQuery) -> Result<TodoPage, RepositoryError> {
+        // 一覧と件数で期限切れの判定がずれないよう、同じ時刻で比較する
+        let now = self.clock.now();
+        // ラベルとjoinすると行数が増えるため、Todo単位でページングしてからjoinする
+        let sql = format!(
+            r#"
+select todos.*, labels.id as label_id, labels.name as label_name, labels.color as label_color,
+       labels.description as label_description
+from (
+    select * from todos
+    where user_id = $5
+      and deleted_at is null
+      and ($3 is null or completed = $3)
+      and ($4 is null or text like $4 escape '\')
+      and ($6 is null or due_date < $6)
+      and ($7 is null or due_date > $7)
+      and ($8 is null or (coalesce(due_date < $11, false) and not completed) = $8)
+      and ($9 is null or priority = $9)
+      and ($10 is true or archived_at is null)
+    order by {order_by} limit $1 offset $2
+) todos
+left outer join todo_labels tl on todos.id = tl.todo_id
+left outer join labels on labels.id = tl.label_id
+order by {order_by};
+"#,
+            order_by = query.order_by_clause()
+        );
+        let items = sqlx::query_as::<_, TodoWithLabelFromRow>(&sql)
+            .bind(query.limit())
+            .bind(query.offset())
+            .bind(query.completed)
+            .bind(query.search_pattern())
+            .bind(user_id)
+            .bind(query.due_before)
+            .bind(query.due_after)
+            .bind(query.overdue)
+            .bind(query.priority())
+            .bind(query.include_archived)
+            .bind(now)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let (total,) = sqlx::query_as::<_, (i64,)>(
+            r#"
+select count(*) from todos
+where user_id = $3
+  and deleted_at is null
+  and ($1 is null or completed = $1)
+  and ($2 is null or text like $2 escape '\')
+  and ($4 is null or due_date < $4)
+  and ($5 is null or due_date > $5)
+  and ($6 is null or (coalesce(due_date < $9, false) and not completed) = $6)
+  and ($7 is null or priority = $7)
+  and ($8 is true or archived_at is null);
+"#,
+        )
+        .bind(query.completed)
+        .bind(query.search_pattern())
+        .bind(user_id)
+        .bind(query.due_before)
+        .bind(query.due_after)
+        .bind(query.overdue)
+        .bind(query.priority())
+        .bind(query.include_archived)
+        .bind(now)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(TodoPage {
+            todos: fold_entities(items),
+            total,
+        })
+    }
+
+    #[instrument(name = "todo.last_modified", skip_all, fields(db.operation = "SELECT"))]
+    async fn last_modified(&self, user_id: i32) -> Result<Option<DateTime<Utc>>, RepositoryError> {
+        // 引数が2つのmaxはnullがあるとnullを返すため、未削除の場合はupdated_atで補う
+        // 一覧の変更日時とは、nullを無視する集約のmaxで比べる
+        let last_modified = sqlx::query_scalar(
+            r#"
+select max(modified_at) from (
+    select max(updated_at, coalesce(deleted_at, updated_at)) as modified_at from todos
+    where user_id = $1
+    union all
+    select modified_at from todo_list_modifications where user_id = $1
+);
+"#,
+        )
+        .bind(user_id)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(last_modified)
+    }
+
+    #[instrument(name = "todo.update", skip_all, fields(db.operation = "UPDATE"))]
+    async fn update(
+        &self,
+        user_id: i32,
+        id: i64,
+        payload: UpdateTodo,
+    ) -> Result<TodoEntity, RepositoryError> {
+        let priority = payload.priority();
+        let now = self.clock.now();
+        let mut tx = self.pool.begin().await?;
+        let old = Self::entities_in(&mut tx, user_id, &[id])
+            .await?
+            .pop()
+            .ok_or(RepositoryError::NotFound(Some(id)))?;
+
+        let updated = sqlx::query(
+            r#"
+update todos
+set text = coalesce($3, text), completed = coalesce($4, completed),
+    due_date = case when $6 then $7 else due_date end,
+    priority = coalesce($8, priority),
+    recurrence = case when $9 then $10 else recurrence end,
+    remind_at = case when $11 then $12 else remind_at end,
+    reminded_at = case when $11 then null else reminded_at end,
+    updated_at = $13, version = version + 1
+where id = $1 and user_id = $2 and deleted_at is null
+  and ($5 is null or version = $5)
+  and ($14 is null or version = $14)
+  and ($15 is null or updated_at < $15);
+"#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(payload.text)
+        .bind(payload.completed)
+        .bind(payload.version)
+        .bind(payload.due_date.is_some())
+        .bind(payload.due_date.flatten())
+        .bind(priority)
+        .bind(payload.recurrence.is_some())
+        .bind(payload.recurrence.flatten().map(Json))
+        .bind(payload.remind_at.is_some())
+        .bind(payload.remind_at.flatten())
+        .bind(now)
+        .bind(payload.precondition.version)
+        .bind(payload.precondition.modified_before)
+        .execute(&mut tx)
+        .await?;
+        if updated.rows_affected() == 0 {
+            // 対象の存在は確認済みのため、前提条件かversionの不一致
+            if !payload.precondition.matches(&old) {
+                return Err(RepositoryError::PreconditionFailed(id));
+            }
+            return Err(RepositoryError::Conflict(id));
+        }
+
+        if let Some(labels) = payload.labels {
+            Self::ensure_labels(&mut tx, &labels).await?;
+            sqlx::query("delete from todo_labels where todo_id=$1")
+                .bind(id)
+                .execute(&mut tx)
+                .await?;
+            sqlx::query(INSERT_TODO_LABELS)
+                .bind(id)
+                .bind(Json(labels))
+                .execute(&mut tx)
+                .await?;
+        };
+
+        let new = Self::entities_in(&mut tx, user_id, &[id])
+            .await?
+            .pop()
+            .ok_or(RepositoryError::NotFound(Some(id)))?;
+        let changes = TodoChange::updated(&old, &new).into_iter().collect();
+        Self::record_activities(&mut tx, user_id, changes, now).await?;
+        if let Some(recurrence) = recurrence_to_spawn(&old, &new) {
+            Self::spawn_next_occurrence(&mut tx, user_id, &new, recurrence).await?;
+        }
+        tx.commit().await?;
+
+        self.find(user_id, id).await
+    }
+
+    #[instrument(name = "todo.replace", skip_all, fields(db.operation = "UPDATE"))]
+    async fn replace(
+        &self,
+        user_id: i32,
+        id: i64,
+        payload: ReplaceTodo,
+    ) -> Result<TodoEntity, RepositoryError> {
+        // ラベルの付け替えを含めて1つのトランザクションで行う
+        self.update(user_id, id, payload.into()).await
+    }
+
+    #[instrument(name = "todo.update_many", skip_all, fields(db.operation = "UPDATE"))]
+    async fn update_many(
+        &self,
+        user_id: i32,
+        payload: UpdateTodos,
+    ) -> Result<UpdatedTodos, RepositoryError> {
+        let now = self.clock.now();
+        let mut tx = self.pool.begin().await?;
+        let old = Self::entities_in(&mut tx, user_id, &payload.ids).await?;
+        let updated: Vec<i64> = sqlx::query_as::<_, (i64,)>(
+            r#"
+update todos
+set text = coalesce($2, text), completed = coalesce($3, completed),
+    updated_at = $5, version = version + 1
+where id in (select value from json_each($1)) and user_id = $4 and deleted_at is null
+returning id;
+"#,
+        )
+        .bind(Json(&payload.ids))
+        .bind(payload.text)
+        .bind(payload.completed)
+        .bind(user_id)
+        .bind(now)
+        .fetch_all(&mut tx)
+        .await?
+        .into_iter()
+        .map(|(id,)| id)
+        .collect();
+
+        let new = Self::entities_in(&mut tx, user_id, &updated).await?;
+        let changes = new
+            .iter()
+            .filter_map(|new| {
+                let old = old.iter().find(|old| old.id == new.id)?;
+                TodoChange::updated(old, new)
+            })
+            .collect();
+        Self::record_activities(&mut tx, user_id, changes, now).await?;
+        tx.commit().await?;
+
+        Ok(UpdatedTodos::new(&payload.ids, new))
+    }
+
+    #[instrument(name = "todo.delete", skip_all, fields(db.operation = "UPDATE"))]
+    async fn delete(
+        &self,
+        user_id: i32,
+        id: i64,
+        precondition: Precondition,
+    ) -> Result<(), RepositoryError> {
+        // 行は残したままゴミ箱へ移す
+        let now = self.clock.now();
+        let mut tx = self.pool.begin().await?;
+        let result = sqlx::query(
+            r#"
+update todos set deleted_at = $3
+where id = $1 and user_id = $2 and deleted_at is null
+  and ($4 is null or version = $4)
+  and ($5 is null or updated_at < $5);
+"#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(now)
+        .bind(precondition.version)
+        .bind(precondition.modified_before)
+        .execute(&mut tx)
+        .await?;
+        if result.rows_affected() == 0 {
+            // 前提条件を満たさなかったのか、存在しないのかを区別する
+            let exists = !precondition.is_none()
+                && !Self::entities_in(&mut tx, user_id, &[id]).await?.is_empty();
+            if exists {
+                return Err(RepositoryError::PreconditionFailed(id));
+            }
+            return Err(RepositoryError::NotFound(Some(id)));
+        }
+        Self::record_activities(&mut tx, user_id, vec![TodoChange::deleted(id)], now).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    #[instrument(name = "todo.delete_permanently", skip_all, fields(db.operation = "DELETE"))]
+    async fn delete_permanently(&self, user_id: i32, id: i64) -> Result<(), RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "delete from todo_labels where todo_id in (select id from todos where id=$1 and user_id=$2)",
+        )
+        .bind(id)
+        .bind(user_id)
+        .execute(&mut tx)
+        .await?;
+
+        let result = sqlx::query("delete from todos where id=$1 and user_id=$2")
+            .bind(id)
+            .bind(user_id)
+            .execute(&mut tx)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound(Some(id)));
+        }
+        Self::touch_lists(&mut tx, &[user_id], self.clock.now()).await?;
+
+        tx.commit().await?;
+
+        Ok(())
+    }
+
+    #[instrument(name = "todo.trash", skip_all, fields(db.operation = "SELECT"))]
+    async fn trash(&self, user_id: i32) -> Result<Vec<TodoEntity>, RepositoryError> {
+        let sql = format!(
+            r#"{}
+where todos.user_id = $1 and todos.deleted_at is not null
+order by todos.deleted_at desc, todos.id desc"#,
+            SELECT_TODOS_WITH_LABELS
+        );
+        let items = sqlx::query_as::<_, TodoWithLabelFromRow>(&sql)
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(fold_entities(items))
+    }
+
+    #[instrument(name = "todo.restore", skip_all, fields(db.operation = "UPDATE"))]
+    async fn restore(&self, user_id: i32, id: i64) -> Result<TodoEntity, RepositoryError> {
+        let result = sqlx::query(
+            r#"
+update todos set deleted_at = null, updated_at = $3, version = version + 1
+where id = $1 and user_id = $2 and deleted_at is not null;
+"#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(self.clock.now())
+        .execute(&self.pool)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound(Some(id)));
+        }
+        self.find(user_id, id).await
+    }
+
+    #[instrument(name = "todo.delete_completed", skip_all, fields(db.operation = "DELETE"))]
+    async fn delete_completed(&self, user_id: i32) -> Result<u64, RepositoryError> {
+        // CTEの中で削除できないため、関連を先に削除してからTodoを削除する
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            r#"
+delete from todo_labels where todo_id in (
+    select id from todos where user_id = $1 and completed = true and deleted_at is null
+);
+"#,
+        )
+        .bind(user_id)
+        .execute(&mut tx)
+        .await?;
+        let result = sqlx::query(
+            "delete from todos where user_id = $1 and completed = true and deleted_at is null",
+        )
+        .bind(user_id)
+        .execute(&mut tx)
+        .await?;
+        if result.rows_affected() > 0 {
+            Self::touch_lists(&mut tx, &[user_id], self.clock.now()).await?;
+        }
+        tx.commit().await?;
+        Ok(result.rows_affected())
+    }
+
+    #[instrument(name = "todo.purge_deleted_before", skip_all, fields(db.operation = "DELETE"))]
+    async fn purge_deleted_before(&self, cutoff: DateTime<Utc>) -> Result<u64, RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "delete from todo_labels where todo_id in (select id from todos where deleted_at < $1)",
+        )
+        .bind(cutoff)
+        .execute(&mut tx)
+        .await?;
+        let owners: Vec<Option<i32>> =
+            sqlx::query_scalar("delete from todos where deleted_at < $1 returning user_id")
+                .bind(cutoff)
+                .fetch_all(&mut tx)
+                .await?;
+        let user_ids: Vec<i32> = owners.iter().flatten().copied().collect();
+        Self::touch_lists(&mut tx, &user_ids, self.clock.now()).await?;
+        tx.commit().await?;
+        Ok(owners.len() as u64)
+    }
+
+    #[instrument(name = "todo.claim_idempotency_key", skip_all, fields(db.operation = "INSERT"))]
+    async fn claim_idempotency_key(
+        &self,
+        user_id: i32,
+        key: &str,
+        fingerprint: &str,
+    ) -> Result<Option<IdempotencyRecord>, RepositoryError> {
+        // 書き込みは直列に行われるが、読む前に取り消された場合に備えて登録からやり直す
+        loop {
+            let inserted = sqlx::query(
+                r#"
+insert into idempotency_keys (user_id, key, fingerprint, created_at) values ($1, $2, $3, $4)
+on conflict (user_id, key) do nothing;
+"#,
+            )
+            .bind(user_id)
+            .bind(key)
+            .bind(fingerprint)
+            .bind(self.clock.now())
+            .execute(&self.pool)
+            .await?
+            .rows_affected();
+            if inserted == 1 {
+                return Ok(None);
+            }
+            let record = sqlx::query_as::<_, (String, Option<Json<Value>>)>(
+                "select fingerprint, response from idempotency_keys where user_id = $1 and key = $2",
+            )
+            .bind(user_id)
+            .bind(key)
+            .fetch_optional(&self.pool)
+            .await?;
+            if let Some((fingerprint, response)) = record {
+                return Ok(Some(IdempotencyRecord {
+                    fingerprint,
+                    response: response.map(|Json(response)| response),
+                }));
+            }
+        }
+    }
+
+    #[instrument(name = "todo.complete_idempotency_key", skip_all, fields(db.operation = "UPDATE"))]
+    async fn complete_idempotency_key(
+        &self,
+        user_id: i32,
+        key: &str,
+        response: Value,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query(COMPLETE_IDEMPOTENCY_KEY)
+            .bind(user_id)
+            .bind(key)
+            .bind(Json(response))
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(name = "todo.release_idempotency_key", skip_all, fields(db.operation = "DELETE"))]
+    async fn release_idempotency_key(
+        &self,
+        user_id: i32,
+        key: &str,
+    ) -> Result<(), RepositoryError> {
+        sqlx::query(RELEASE_IDEMPOTENCY_KEY)
+            .bind(user_id)
+            .bind(key)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    #[instrument(name = "todo.purge_idempotency_keys_before", skip_all, fields(db.operation = "DELETE"))]
+    async fn purge_idempotency_keys_before(
+        &self,
+        cutoff: DateTime<Utc>,
+    ) -> Result<u64, RepositoryError> {
+        let result = sqlx::query("delete from idempotency_keys where created_at < $1")
+            .bind(cutoff)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    #[instrument(name = "todo.attach_label", skip_all, fields(db.operation = "INSERT"))]
+    async fn attach_label(
+        &self,
+        user_id: i32,
+        id: i64,
+        label_id: i32,
+    ) -> Result<TodoEntity, RepositoryError> {
+        if !self.exists(user_id, id).await? {
+            return Err(RepositoryError::NotFound(Some(id)));
+        }
+        self.find_label(label_id).await?;
+
+        sqlx::query(
+            r#"
+insert into todo_labels (todo_id, label_id) values ($1, $2)
+on conflict (todo_id, label_id) do nothing;
+"#,
+        )
+        .bind(id)
+        .bind(label_id)
+        .execute(&self.pool)
+        .await?;
+        self.touch(id).await?;
+
+        self.find(user_id, id).await
+    }
+
+    #[instrument(name = "todo.detach_label", skip_all, fields(db.operation = "DELETE"))]
+    async fn detach_label(
+        &self,
+        user_id: i32,
+        id: i64,
+        label_id: i32,
+    ) -> Result<TodoEntity, RepositoryError> {
+        if !self.exists(user_id, id).await? {
+            return Err(RepositoryError::NotFound(Some(id)));
+        }
+        self.find_label(label_id).await?;
+
+        sqlx::query("delete from todo_labels where todo_id = $1 and label_id = $2")
+            .bind(id)
+            .bind(label_id)
+            .execute(&self.pool)
+            .await?;
+        self.touch(id).await?;
+
+        self.find(user_id, id).await
+    }
+
+    #[instrument(name = "todo.export", skip_all, fields(db.operation = "SELECT"))]
+    async fn export(&self, user_id: i32) -> Result<Backup, RepositoryError> {
+        let sql = format!(
+            r#"{}
+where todos.user_id=$1 and todos.deleted_at is null
+order by todos.id asc"#,
+            SELECT_TODOS_WITH_LABELS
+        );
+        let items = sqlx::query_as::<_, TodoWithLabelFromRow>(&sql)
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await?;
+        let labels = sqlx::query_as::<_, Label>("select * from labels order by id asc")
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(Backup::new(fold_entities(items), labels))
+    }
+
+    #[instrument(name = "todo.import", skip_all, fields(db.operation = "INSERT"))]
+    async fn import(&self, user_id: i32, backup: Backup) -> Result<ImportSummary, RepositoryError> {
+        // 途中で失敗した場合はtxがdropされ、ドキュメント全体がロールバックされる
+        let mut tx = self.pool.begin().await?;
+        let summary = Self::import_in(&mut tx, user_id, backup).await?;
+        // 取り込んだTodoはupdated_atを引き継ぐため、一覧の変更日時を別に進める
+        Self::touch_lists(&mut tx, &[user_id], self.clock.now()).await?;
+        tx.commit().await?;
+        Ok(summary)
+    }
+
+    #[instrument(name = "todo.reset", skip_all, fields(db.operation = "DELETE"))]
+    async fn reset(&self) -> Result<(), RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        Self::reset_in(&mut tx).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    #[instrument(name = "todo.seed", skip_all, fields(db.operation = "INSERT"))]
+    async fn seed(&self, user_id: i32, fixtures: Backup) -> Result<ImportSummary, RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        let exists: bool = sqlx::query_scalar("select exists(select 1 from users where id = $1)")
+            .bind(user_id)
+            .fetch_one(&mut tx)
+            .await?;
+        if !exists {
+            return Err(RepositoryError::NotFound(Some(user_id.into())));
+        }
+        Self::reset_in(&mut tx).await?;
+        let summary = Self::import_in(&mut tx, user_id, fixtures).await?;
+        Self::touch_lists(&mut tx, &[user_id], self.clock.now()).await?;
+        tx.commit().await?;
+        Ok(summary)
+    }
+
+    fn stream_all(&self, user_id: i32) -> BoxStream<'static, Result<TodoEntity, RepositoryError>> {
+        // 読み込み中は接続を占有するため、受信側が読み終えるまで他の問い合わせは待たされる
+        let (sender, receiver) = mpsc::channel(STREAM_BUFFER);
+        let pool = self.pool.clone();
+        tokio::spawn(async move {
+            let sql = format!(
+                r#"{}
+where todos.user_id=$1 and todos.deleted_at is null
+order by todos.id asc, labels.id asc"#,
+                SELECT_TODOS_WITH_LABELS
+            );
+            let mut rows = sqlx::query_as::<_, TodoWithLabelFromRow>(&sql)
+                .bind(user_id)
+                .fetch(&pool);
+
+            // id順のため、同じTodoのラベルの行は連続して現れる
+            let mut current: Option<TodoEntity> = None;
+            loop {
+                let row = match rows.try_next().await {
+                    Ok(Some(row)) => row,
+                    Ok(None) => break,
+                    Err(e) => {
+                        let _ = sender.send(Err(e.into())).await;
+                        return;
+                    }
+                };
+                match current.as_mut() {
+                    Some(todo) if todo.id == row.id => todo.labels.extend(row.label()),
+                    _ => {
+                        if let Some(todo) = current.replace(row.into()) {
+                            // 受信側が切断した場合はクエリを打ち切る
+                            if sender.send(Ok(todo)).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                }
+            }
+            if let Some(todo) = current {
+                let _ = sender.send(Ok(todo)).await;
+            }
+        });
+
+        stream::unfold(receiver, |mut receiver| async move {
+            receiver.recv().await.map(|item| (item, receiver))
+        })
+        .boxed()
+    }
+
+    #[instrument(name = "todo.stats", skip_all, fields(db.operation = "SELECT"))]
+    async fn stats(&self, user_id: i32) -> Result<TodoStats, RepositoryError> {
+        // grouping setsがないため、全体の集計とラベルごとの集計を分けて問い合わせる
+        let (total, completed, created_last_7_days) = sqlx::query_as::<_, (i64, i64, i64)>(
+            r#"
+select count(*),
+       count(*) filter (where completed),
+       count(*) filter (where created_at >= $2)
+from todos
+where user_id=$1 and deleted_at is null;
+"#,
+        )
+        .bind(user_id)
+        .bind(self.clock.now() - Duration::days(7))
+        .fetch_one(&self.pool)
+        .await?;
+        let labels = sqlx::query_as::<_, (i32, String, i64)>(
+            r#"
+select labels.id, labels.name, count(*)
+from todos
+join todo_labels tl on todos.id = tl.todo_id
+join labels on labels.id = tl.label_id
+where todos.user_id=$1 and todos.deleted_at is null
+group by labels.id, labels.name
+order by labels.id asc;
+"#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(TodoStats {
+            total,
+            open: total - completed,
+            completed,
+            created_last_7_days,
+            labels: labels
+                .into_iter()
+                .map(|(id, name, count)| LabelCount { id, name, count })
+                .collect(),
+        })
+    }
+
+    #[instrument(name = "todo.items", skip_all, fields(db.operation = "SELECT"))]
+    async fn items(&self, user_id: i32, id: i64) -> Result<Vec<TodoItem>, RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        Self::find_owned(&mut tx, user_id, id).await?;
+        let items = Self::items_of(&mut tx, id).await?;
+        tx.commit().await?;
+        Ok(items)
+    }
+
+    #[instrument(name = "todo.create_item", skip_all, fields(db.operation = "INSERT"))]
+    async fn create_item(
+        &self,
+        user_id: i32,
+        id: i64,
+        payload: CreateTodoItem,
+    ) -> Result<TodoItem, RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        Self::find_owned(&mut tx, user_id, id).await?;
+        let item = sqlx::query_as::<_, TodoItem>(
+            r#"
+insert into todo_items (todo_id, text, position)
+select $1, $2, coalesce(max(position) + 1, 0) from todo_items where todo_id = $1
+returning *;
+"#,
+        )
+        .bind(id)
+        .bind(payload.text)
+        .fetch_one(&mut tx)
+        .await?;
+        tx.commit().await?;
+        Ok(item)
+    }
+
+    #[instrument(name = "todo.update_item", skip_all, fields(db.operation = "UPDATE"))]
+    async fn update_item(
+        &self,
+        user_id: i32,
+        id: i64,
+        item_id: i32,
+        payload: UpdateTodoItem,
+    ) -> Result<TodoItem, RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        Self::find_owned(&mut tx, user_id, id).await?;
+        let item = sqlx::query_as::<_, TodoItem>(
+            r#"
+update todo_items set text = coalesce($3, text), completed = coalesce($4, completed)
+where id=$1 and todo_id=$2
+returning *;
+"#,
+        )
+        .bind(item_id)
+        .bind(id)
+        .bind(payload.text)
+        .bind(payload.completed)
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or(RepositoryError::NotFound(Some(item_id.into())))?;
+
+        let item = match payload.position {
+            Some(position) => {
+                let mut items = Self::items_of(&mut tx, id).await?;
+                move_item(&mut items, item_id, position);
+                for moved in items.iter() {
+                    sqlx::query("update todo_items set position = $1 where id = $2")
+                        .bind(moved.position)
+                        .bind(moved.id)
+                        .execute(&mut tx)
+                        .await?;
+                }
+                items
+                    .into_iter()
+                    .find(|moved| moved.id == item_id)
+                    .unwrap_or(item)
+            }
+            None => item,
+        };
+        tx.commit().await?;
+        Ok(item)
+    }
+
+    #[instrument(name = "todo.delete_item", skip_all, fields(db.operation = "UPDATE"))]
+    async fn delete_item(
+        &self,
+        user_id: i32,
+        id: i64,
+        item_id: i32,
+    ) -> Result<(), RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        Self::find_owned(&mut tx, user_id, id).await?;
+        let (position,) = sqlx::query_as::<_, (i32,)>(
+            "delete from todo_items where id=$1 and todo_id=$2 returning position",
+        )
+        .bind(item_id)
+        .bind(id)
+        .fetch_optional(&mut tx)
+        .await?
+        .ok_or(RepositoryError::NotFound(Some(item_id.into())))?;
+        // 後続の項目を詰める
+        sqlx::query(
+            "update todo_items set position = position - 1 where todo_id=$1 and position > $2",
+        )
+        .bind(id)
+        .bind(position)
+        .execute(&mut tx)
+        .await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    #[instrument(name = "todo.duplicate", skip_all, fields(db.operation = "INSERT"))]
+    async fn duplicate(
+        &self,
+        user_id: i32,
+        id: i64,
+        payload: DuplicateTodo,
+    ) -> Result<TodoEntity, RepositoryError> {
+        let now = self.clock.now();
+        let mut tx = self.pool.begin().await?;
+        let (text, priority, created_at, due_date) =
+            sqlx::query_as::<_, (String, Priority, DateTime<Utc>, Option<DateTime<Utc>>)>(
+                r#"
+select text, priority, created_at, due_date from todos
+where id=$1 and user_id=$2 and deleted_at is null
+"#,
+            )
+            .bind(id)
+            .bind(user_id)
+            .fetch_optional(&mut tx)
+            .await?
+            .ok_or(RepositoryError::NotFound(Some(id)))?;
+
+        let row = sqlx::query_as::<_, TodoFromRow>(INSERT_TODO)
+            .bind(text)
+            .bind(user_id)
+            .bind(payload.due_date(created_at, due_date, now))
+            .bind(priority)
+            .bind(POSITION_GAP)
+            .bind(None::<Json<Recurrence>>)
+            .bind(None::<DateTime<Utc>>)
+            .bind(now)
+            .bind(None::<Uuid>)
+            .fetch_one(&mut tx)
+            .await?;
+        sqlx::query(
+            "insert into todo_labels (todo_id, label_id) select $1, label_id from todo_labels where todo_id = $2",
+        )
+        .bind(row.id)
+        .bind(id)
+        .execute(&mut tx)
+        .await?;
+        let todos = Self::entities_in(&mut tx, user_id, &[row.id]).await?;
+        Self::record_activities(
+            &mut tx,
+            user_id,
+            todos.iter().map(TodoChange::created).collect(),
+            now,
+        )
+        .await?;
+        tx.commit().await?;
+
+        self.find(user_id, row.id).await
+    }
+
+    #[instrument(name = "todo.archive", skip_all, fields(db.operation = "UPDATE"))]
+    async fn archive(&self, user_id: i32, id: i64) -> Result<TodoEntity, RepositoryError> {
+        sqlx::query(
+            r#"
+update todos set archived_at = $3, updated_at = $3, version = version + 1
+where id=$1 and user_id=$2 and deleted_at is null and archived_at is null
+"#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(self.clock.now())
+        .execute(&self.pool)
+        .await?;
+        self.find(user_id, id).await
+    }
+
+    #[instrument(name = "todo.unarchive", skip_all, fields(db.operation = "UPDATE"))]
+    async fn unarchive(&self, user_id: i32, id: i64) -> Result<TodoEntity, RepositoryError> {
+        sqlx::query(
+            r#"
+update todos set archived_at = null, updated_at = $3, version = version + 1
+where id=$1 and user_id=$2 and deleted_at is null and archived_at is not null
+"#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(self.clock.now())
+        .execute(&self.pool)
+        .await?;
+        self.find(user_id, id).await
+    }
+
+    #[instrument(name = "todo.archive_completed", skip_all, fields(db.operation = "UPDATE"))]
+    async fn archive_completed(&self, user_id: i32) -> Result<u64, RepositoryError> {
+        let result = sqlx::query(
+            r#"
+update todos set archived_at = $2, updated_at = $2, version = version + 1
+where user_id=$1 and completed = true and deleted_at is null and archived_at is null
+"#,
+        )
+        .bind(user_id)
+        .bind(self.clock.now())
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    #[instrument(name = "todo.move_todo", skip_all, fields(db.operation = "UPDATE"))]
+    async fn move_todo(
+        &self,
+        user_id: i32,
+        id: i64,
+        target: MoveTarget,
+    ) -> Result<TodoEntity, RepositoryError> {
+        let mut tx = self.pool.begin().await?;
+        let ordered = sqlx::query_as::<_, (i64, i64)>(
+            r#"
+select id, position from todos
+where user_id = $1 and deleted_at is null
+order by position desc, id desc;
+"#,
+        )
+        .bind(user_id)
+        .fetch_all(&mut tx)
+        .await?;
+        for (todo_id, position) in reposition(ordered, id, target)? {
+            sqlx::query("update todos set position = $1 where id = $2")
+                .bind(position)
+                .bind(todo_id)
+                .execute(&mut tx)
+                .await?;
+        }
+        // 表示順はupdated_atを変えないため、一覧の変更日時のみを進める
+        Self::touch_lists(&mut tx, &[user_id], self.clock.now()).await?;
+        tx.commit().await?;
+        self.find(user_id, id).await
+    }
+
+    #[instrument(name = "todo.activity", skip_all, fields(db.operation = "SELECT"))]
+    async fn activity(
+        &self,
+        user_id: i32,
+        id: i64,
+        query: PageQuery,
+    ) -> Result<TodoActivityPage, RepositoryError> {
+        sqlx::query_as::<_, (i64,)>("select id from todos where id=$1 and user_id=$2")
+            .bind(id)
+            .bind(user_id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or(RepositoryError::NotFound(Some(id)))?;
+
+        // 同じトランザクション内の履歴は記録時刻が一致するため、idを第2キーにする
+        let activities = sqlx::query_as::<_, TodoActivityFromRow>(
+            r#"
+select * from todo_activities
+where todo_id = $1
+order by created_at desc, id desc
+limit $2 offset $3;
+"#,
+        )
+        .bind(id)
+        .bind(query.limit())
+        .bind(query.offset())
+        .fetch_all(&self.pool)
+        .await?;
+        let (total,) =
+            sqlx::query_as::<_, (i64,)>("select count(*) from todo_activities where todo_id = $1")
+                .bind(id)
+                .fetch_one(&self.pool)
+                .await?;
+
+        Ok(TodoActivityPage {
+            activities: activities.into_iter().map(TodoActivity::from).collect(),
+            total,
+        })
+    }
+
+    #[instrument(name = "todo.due_reminders", skip_all, fields(db.operation = "UPDATE"))]
+    async fn due_reminders(&self, now: DateTime<Utc>) -> Result<Vec<DueReminder>, RepositoryError> {
+        // 取得と通知済みへの変更を1文で行うため、同時に呼ばれても同じTodoを重複して返さない
+        let mut tx = self.pool.begin().await?;
+        let owners: HashMap<i64, i32> = sqlx::query_as::<_, (i64, i32)>(
+            r#"
+update todos set reminded_at = $1
+where remind_at <= $1 and reminded_at is null and deleted_at is null and not completed
+returning id, user_id;
+"#,
+        )
+        .bind(now)
+        .fetch_all(&mut tx)
+        .await?
+        .into_iter()
+        .collect();
+        // reminded_atはupdated_atを変えずに一覧の内容を変える
+        let user_ids: Vec<i32> = owners.values().copied().collect();
+        Self::touch_lists(&mut tx, &user_ids, now).await?;
+        tx.commit().await?;
+
+        let mut reminders: Vec<DueReminder> = self
+            .find_across_users(owners.keys().copied().collect())
+            .await?
+            .into_iter()
+            .filter_map(|todo| {
+                Some(DueReminder {
+                    user_id: *owners.get(&todo.id)?,
+                    todo,
+                })
+            })
+            .collect();
+        reminders.sort_by_key(|reminder| (reminder.todo.remind_at, reminder.todo.id));
+        Ok(reminders)
+    }
+}
@@ -0,0 +1,164 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+
+use crate::repositories::todo::TodoEntity;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, sqlx::Type, ToSchema)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum TodoAction {
+    Created,
+    Updated,
+    // 未完了から完了への変更。他の項目を同時に変更した場合もこちらにまとめる
+    Completed,
+    // ゴミ箱への移動。完全に削除した場合は履歴ごと削除される
+    Deleted,
+}
+
+// old_value・new_valueには変更された項目のみを含める
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, FromRow, ToSchema)]
+pub struct TodoActivity {
+    pub id: i32,
+    pub todo_id: i64,
+    pub action: TodoAction,
+    pub old_value: Option<Value>,
+    pub new_value: Option<Value>,
+    // 変更したユーザーのid
+    pub actor_id: i32,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TodoActivityPage {
+    pub activities: Vec<TodoActivity>,
+    pub total: i64,
+}
+
+// 保存前の履歴。idと記録時刻は保存先で採番する
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TodoChange {
+    pub todo_id: i64,
+    pub action: TodoAction,
+    pub old_value: Option<Value>,
+    pub new_value: Option<Value>,
+}
+
+impl TodoChange {
+    pub fn created(todo: &TodoEntity) -> Self {
+        Self {
+            todo_id: todo.id,
+            action: TodoAction::Created,
+            old_value: None,
+            new_value: Some(Value::Object(tracked_fields(todo))),
+        }
+    }
+
+    // 変更された項目がない場合は記録しない
+    pub fn updated(old: &TodoEntity, new: &TodoEntity) -> Option<Self> {
+        let before = tracked_fields(old);
+        let after = tracked_fields(new);
+        let (old_value, new_value): (Map<String, Value>, Map<String, Value>) = before
+            .into_iter()
+            .filter(|(key, value)| after.get(key) != Some(value))
+            .map(|(key, value)| {
+                let changed = after[&key].clone();
+                ((key.clone(), value), (key, changed))
+            })
+            .unzip();
+        if new_value.is_empty() {
+            return None;
+        }
+        let action = if !old.completed && new.completed {
+            TodoAction::Completed
+        } else {
+            TodoAction::Updated
+        };
+        Some(Self {
+            todo_id: new.id,
+            action,
+            old_value: Some(Value::Object(old_value)),
+            new_value: Some(Value::Object(new_value)),
+        })
+    }
+
+    pub fn deleted(id: i64) -> Self {
+        Self {
+            todo_id: id,
+            action: TodoAction::Deleted,
+            old_value: None,
+            new_value: None,
+        }
+    }
+}
+
+// 履歴に残す項目。ラベルはidの昇順で比較する
+fn tracked_fields(todo: &TodoEntity) -> Map<String, Value> {
+    let mut labels: Vec<i32> = todo.labels.iter().map(|label| label.id).collect();
+    labels.sort_unstable();
+    match json!({
+        "text": todo.text,
+        "completed": todo.completed,
+        "due_date": todo.due_date,
+        "priority": todo.priority,
+        "labels": labels,
+    }) {
+        Value::Object(fields) => fields,
+        _ => unreachable!(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::repositories::label::Label;
+
+    use super::*;
+
+    #[test]
+    fn todo_change_test() {
+        let old = TodoEntity::new(1, "before".to_string(), vec![]);
+        let created = TodoChange::created(&old);
+        assert_eq!(TodoAction::Created, created.action);
+        assert_eq!(
+            Some(json!({
+                "text": "before", "completed": false, "due_date": null,
+                "priority": "medium", "labels": [],
+            })),
+            created.new_value
+        );
+
+        assert_eq!(None, TodoChange::updated(&old, &old));
+
+        let new = TodoEntity {
+            text: "after".to_string(),
+            labels: vec![
+                Label::new(2, "b".to_string()),
+                Label::new(1, "a".to_string()),
+            ],
+            ..old.clone()
+        };
+        let updated = TodoChange::updated(&old, &new).unwrap();
+        assert_eq!(TodoAction::Updated, updated.action);
+        assert_eq!(
+            Some(json!({ "text": "before", "labels": [] })),
+            updated.old_value
+        );
+        assert_eq!(
+            Some(json!({ "text": "after", "labels": [1, 2] })),
+            updated.new_value
+        );
+
+        let completed = TodoChange::updated(
+            &new,
+            &TodoEntity {
+                completed: true,
+                ..new.clone()
+            },
+        )
+        .unwrap();
+        assert_eq!(TodoAction::Completed, completed.action);
+        assert_eq!(Some(json!({ "completed": true })), completed.new_value);
+    }
+}
@@ -0,0 +1,92 @@
+use async_graphql::SimpleObject;
+use serde::{Deserialize, Serialize};
+use sqlx::FromRow;
+use utoipa::ToSchema;
+use validator::Validate;
+
+use crate::repositories::todo::{validate_not_blank, TodoEntity};
+
+// Todoのチェックリストの項目。positionは0始まりの表示順
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, FromRow, ToSchema, SimpleObject)]
+pub struct TodoItem {
+    pub id: i32,
+    pub todo_id: i64,
+    pub text: String,
+    pub completed: bool,
+    pub position: i32,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate, ToSchema)]
+pub struct CreateTodoItem {
+    #[validate(custom = "validate_not_blank")]
+    #[validate(length(max = 100, message = "Over text length"))]
+    pub text: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq, Validate, ToSchema)]
+pub struct UpdateTodoItem {
+    #[validate(custom = "validate_not_blank")]
+    #[validate(length(max = 100, message = "Over text length"))]
+    pub text: Option<String>,
+    pub completed: Option<bool>,
+    // 指定した位置へ移動し、他の項目を詰め直す。末尾を超える値は末尾として扱う
+    #[validate(range(min = 0, message = "Must be 0 or greater"))]
+    pub position: Option<i32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, ToSchema)]
+pub struct TodoWithItems {
+    #[serde(flatten)]
+    pub todo: TodoEntity,
+    pub items: Vec<TodoItem>,
+}
+
+// position順に並んだitemsのうち、idの項目をpositionへ移動して0から振り直す
+pub fn move_item(items: &mut Vec<TodoItem>, id: i32, position: i32) {
+    if let Some(index) = items.iter().position(|item| item.id == id) {
+        let item = items.remove(index);
+        let position = (position.max(0) as usize).min(items.len());
+        items.insert(position, item);
+    }
+    for (index, item) in items.iter_mut().enumerate() {
+        item.position = index as i32;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn items(ids: &[i32]) -> Vec<TodoItem> {
+        ids.iter()
+            .enumerate()
+            .map(|(index, id)| TodoItem {
+                id: *id,
+                todo_id: 1,
+                text: format!("item {}", id),
+                completed: false,
+                position: index as i32,
+            })
+            .collect()
+    }
+
+    fn order(items: &[TodoItem]) -> Vec<(i32, i32)> {
+        items.iter().map(|item| (item.id, item.position)).collect()
+    }
+
+    #[test]
+    fn move_item_test() {
+        let mut moved = items(&[1, 2, 3]);
+        move_item(&mut moved, 3, 0);
+        assert_eq!(vec![(3, 0), (1, 1), (2, 2)], order(&moved));
+
+        move_item(&mut moved, 3, 99);
+        assert_eq!(vec![(1, 0), (2, 1), (3, 2)], order(&moved));
+
+        // 削除などで空いた番号も詰め直す
+        let mut gapped = items(&[1, 2]);
+        gapped[1].position = 5;
+        move_item(&mut gapped, 1, 1);
+        assert_eq!(vec![(2, 0), (1, 1)], order(&gapped));
+    }
+}
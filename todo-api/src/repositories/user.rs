@@ -0,0 +1,142 @@
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::instrument;
+
+use super::RepositoryError;
+
+mod memory;
+pub use memory::{UserRepositoryForMemory, UserSnapshot};
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "sqlite")]
+pub use sqlite::UserRepositoryForSqlite;
+
+#[async_trait]
+pub trait UserRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
+    async fn create(
+        &self,
+        username: String,
+        password_hash: String,
+    ) -> Result<User, RepositoryError>;
+    async fn find(&self, id: i32) -> Result<User, RepositoryError>;
+    async fn find_by_username(&self, username: &str) -> Result<Option<User>, RepositoryError>;
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct User {
+    pub id: i32,
+    pub username: String,
+    #[serde(skip)]
+    pub password_hash: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct UserRepositoryForDb {
+    pool: PgPool,
+}
+
+impl UserRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl UserRepository for UserRepositoryForDb {
+    #[instrument(name = "user.create", skip_all, fields(db.operation = "INSERT"))]
+    async fn create(
+        &self,
+        username: String,
+        password_hash: String,
+    ) -> Result<User, RepositoryError> {
+        if let Some(user) = self.find_by_username(&username).await? {
+            return Err(RepositoryError::Duplicate(Some(user.id)));
+        }
+
+        let user = sqlx::query_as::<_, User>(
+            "insert into users ( username, password_hash ) values ( $1, $2 ) returning *",
+        )
+        .bind(username)
+        .bind(password_hash)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    #[instrument(name = "user.find", skip_all, fields(db.operation = "SELECT"))]
+    async fn find(&self, id: i32) -> Result<User, RepositoryError> {
+        let user = sqlx::query_as::<_, User>("select * from users where id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or(RepositoryError::NotFound(Some(id.into())))?;
+        Ok(user)
+    }
+
+    #[instrument(name = "user.find_by_username", skip_all, fields(db.operation = "SELECT"))]
+    async fn find_by_username(&self, username: &str) -> Result<Option<User>, RepositoryError> {
+        let user = sqlx::query_as::<_, User>("select * from users where username = $1")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(user)
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use std::env;
+
+    use dotenv::dotenv;
+    use sqlx::PgPool;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn crud_scenario() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+
+        let username = "test_user";
+        sqlx::query("delete from users where username = $1")
+            .bind(username)
+            .execute(&pool)
+            .await
+            .expect("Failed to prepare user data.");
+        let repository = UserRepositoryForDb::new(pool);
+
+        // create
+        let user = repository
+            .create(username.to_string(), "hash".to_string())
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(user.username, username);
+        assert_eq!(user.password_hash, "hash");
+
+        // duplicate
+        let res = repository
+            .create(username.to_string(), "hash".to_string())
+            .await;
+        assert!(res.is_err());
+
+        // find
+        let found = repository.find(user.id).await.expect("[find] returned Err");
+        assert_eq!(user, found);
+
+        // find_by_username
+        let found = repository
+            .find_by_username(username)
+            .await
+            .expect("[find_by_username] returned Err");
+        assert_eq!(Some(user), found);
+    }
+}
@@ -0,0 +1,183 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Arc;
+
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{Notify, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use super::{RepositoryError, User, UserRepository};
+
+type UserData = HashMap<i32, User>;
+
+// 再起動をまたいで復元するための、リポジトリが保持する内容の写し
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserSnapshot {
+    users: Vec<StoredUser>,
+    last_id: i32,
+}
+
+impl UserSnapshot {
+    pub fn ids(&self) -> HashSet<i32> {
+        self.users.iter().map(|user| user.id).collect()
+    }
+}
+
+// Userはpassword_hashを出力しないため、保存用に別に持つ
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct StoredUser {
+    id: i32,
+    username: String,
+    password_hash: String,
+    created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UserRepositoryForMemory {
+    store: Arc<RwLock<UserData>>,
+    last_id: Arc<AtomicI32>,
+    // 書き込みのロックを取るたびに通知する。スナップショットの保存に使う
+    changed: Arc<Notify>,
+}
+
+impl UserRepositoryForMemory {
+    pub fn new() -> Self {
+        UserRepositoryForMemory {
+            store: Arc::default(),
+            last_id: Arc::default(),
+            changed: Arc::default(),
+        }
+    }
+
+    // 前回の通知以降に変更されるまで待つ。待っていない間の変更も取りこぼさない
+    pub async fn changed(&self) {
+        self.changed.notified().await
+    }
+
+    pub async fn snapshot(&self) -> UserSnapshot {
+        let store = self.read_store_ref().await;
+        let mut users: Vec<StoredUser> = store
+            .values()
+            .map(|user| StoredUser {
+                id: user.id,
+                username: user.username.clone(),
+                password_hash: user.password_hash.clone(),
+                created_at: user.created_at,
+            })
+            .collect();
+        users.sort_by_key(|user| user.id);
+        UserSnapshot {
+            users,
+            last_id: self.last_id.load(Ordering::SeqCst),
+        }
+    }
+
+    // 保持している内容をすべて置き換える。復元自体は変更として通知しない
+    pub async fn restore_snapshot(&self, snapshot: UserSnapshot) {
+        let mut store = self.store.write().await;
+        *store = snapshot
+            .users
+            .into_iter()
+            .map(|user| {
+                let user = User {
+                    id: user.id,
+                    username: user.username,
+                    password_hash: user.password_hash,
+                    created_at: user.created_at,
+                };
+                (user.id, user)
+            })
+            .collect();
+        self.last_id.store(snapshot.last_id, Ordering::SeqCst);
+    }
+
+    async fn write_store_ref(&self) -> RwLockWriteGuard<'_, UserData> {
+        self.changed.notify_one();
+        self.store.write().await
+    }
+
+    async fn read_store_ref(&self) -> RwLockReadGuard<'_, UserData> {
+        self.store.read().await
+    }
+
+    fn next_id(&self) -> i32 {
+        self.last_id.fetch_add(1, Ordering::SeqCst) + 1
+    }
+}
+
+#[async_trait]
+impl UserRepository for UserRepositoryForMemory {
+    async fn create(
+        &self,
+        username: String,
+        password_hash: String,
+    ) -> Result<User, RepositoryError> {
+        let mut store = self.write_store_ref().await;
+        if let Some(user) = store.values().find(|user| user.username == username) {
+            return Err(RepositoryError::Duplicate(Some(user.id)));
+        }
+
+        let id = self.next_id();
+        let user = User {
+            id,
+            username,
+            password_hash,
+            created_at: Utc::now(),
+        };
+        store.insert(id, user.clone());
+        Ok(user)
+    }
+
+    async fn find(&self, id: i32) -> Result<User, RepositoryError> {
+        let store = self.read_store_ref().await;
+        let user = store
+            .get(&id)
+            .cloned()
+            .ok_or(RepositoryError::NotFound(Some(id.into())))?;
+        Ok(user)
+    }
+
+    async fn find_by_username(&self, username: &str) -> Result<Option<User>, RepositoryError> {
+        let store = self.read_store_ref().await;
+        let user = store.values().find(|user| user.username == username);
+        Ok(user.cloned())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::repositories::RepositoryError;
+
+    use super::{UserRepository, UserRepositoryForMemory};
+
+    #[tokio::test]
+    async fn user_crud_scenario() {
+        let repository = UserRepositoryForMemory::new();
+
+        // create
+        let user = repository
+            .create("alice".to_string(), "hash".to_string())
+            .await
+            .expect("failed user create");
+        assert_eq!(1, user.id);
+        assert_eq!("alice", user.username);
+
+        // duplicate
+        let err = repository
+            .create("alice".to_string(), "hash".to_string())
+            .await
+            .expect_err("duplicate create returned Ok");
+        assert!(matches!(&err, RepositoryError::Duplicate(Some(1))));
+
+        // find
+        let found = repository.find(user.id).await.unwrap();
+        assert_eq!(user, found);
+        assert!(repository.find(999).await.is_err());
+
+        // find_by_username
+        let found = repository.find_by_username("alice").await.unwrap();
+        assert_eq!(Some(user), found);
+        assert_eq!(None, repository.find_by_username("bob").await.unwrap());
+    }
+}
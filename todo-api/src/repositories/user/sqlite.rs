@@ -0,0 +1,60 @@
+use axum::async_trait;
+use sqlx::SqlitePool;
+use tracing::instrument;
+
+use super::{User, UserRepository};
+use crate::repositories::RepositoryError;
+
+#[derive(Debug, Clone)]
+pub struct UserRepositoryForSqlite {
+    pool: SqlitePool,
+}
+
+impl UserRepositoryForSqlite {
+    pub fn new(pool: SqlitePool) -> Self {
+        Self { pool }
+    }
+}
+
+#[async_trait]
+impl UserRepository for UserRepositoryForSqlite {
+    #[instrument(name = "user.create", skip_all, fields(db.operation = "INSERT"))]
+    async fn create(
+        &self,
+        username: String,
+        password_hash: String,
+    ) -> Result<User, RepositoryError> {
+        if let Some(user) = self.find_by_username(&username).await? {
+            return Err(RepositoryError::Duplicate(Some(user.id)));
+        }
+
+        let user = sqlx::query_as::<_, User>(
+            "insert into users ( username, password_hash ) values ( $1, $2 ) returning *",
+        )
+        .bind(username)
+        .bind(password_hash)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(user)
+    }
+
+    #[instrument(name = "user.find", skip_all, fields(db.operation = "SELECT"))]
+    async fn find(&self, id: i32) -> Result<User, RepositoryError> {
+        let user = sqlx::query_as::<_, User>("select * from users where id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await?
+            .ok_or(RepositoryError::NotFound(Some(id.into())))?;
+        Ok(user)
+    }
+
+    #[instrument(name = "user.find_by_username", skip_all, fields(db.operation = "SELECT"))]
+    async fn find_by_username(&self, username: &str) -> Result<Option<User>, RepositoryError> {
+        let user = sqlx::query_as::<_, User>("select * from users where username = $1")
+            .bind(username)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(user)
+    }
+}
@@ -0,0 +1,439 @@
+use std::str::FromStr;
+
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use hyper::Uri;
+use serde::{Deserialize, Serialize};
+use sqlx::{FromRow, PgPool};
+use tracing::instrument;
+use utoipa::ToSchema;
+use validator::{Validate, ValidationError};
+
+use super::RepositoryError;
+
+mod memory;
+pub use memory::WebhookRepositoryForMemory;
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "sqlite")]
+pub use sqlite::WebhookRepositoryForSqlite;
+
+#[async_trait]
+pub trait WebhookRepository: Clone + std::marker::Send + std::marker::Sync + 'static {
+    async fn create(
+        &self,
+        user_id: i32,
+        payload: CreateWebhook,
+    ) -> Result<Webhook, RepositoryError>;
+    async fn all(&self, user_id: i32) -> Result<Vec<Webhook>, RepositoryError>;
+    async fn find(&self, user_id: i32, id: i32) -> Result<Webhook, RepositoryError>;
+    async fn update(
+        &self,
+        user_id: i32,
+        id: i32,
+        payload: UpdateWebhook,
+    ) -> Result<Webhook, RepositoryError>;
+    async fn delete(&self, user_id: i32, id: i32) -> Result<(), RepositoryError>;
+    // ユーザーの登録先のうち、eventを通知するものを返す
+    async fn subscribed(
+        &self,
+        user_id: i32,
+        event: WebhookEvent,
+    ) -> Result<Vec<Webhook>, RepositoryError>;
+    async fn record_delivery(
+        &self,
+        id: i32,
+        delivery: WebhookDelivery,
+    ) -> Result<(), RepositoryError>;
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    Created,
+    Updated,
+    // 完了に変更した場合はupdatedと合わせて通知する
+    Completed,
+    Deleted,
+    Reminded,
+}
+
+impl WebhookEvent {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            WebhookEvent::Created => "created",
+            WebhookEvent::Updated => "updated",
+            WebhookEvent::Completed => "completed",
+            WebhookEvent::Deleted => "deleted",
+            WebhookEvent::Reminded => "reminded",
+        }
+    }
+}
+
+impl FromStr for WebhookEvent {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "created" => Ok(WebhookEvent::Created),
+            "updated" => Ok(WebhookEvent::Updated),
+            "completed" => Ok(WebhookEvent::Completed),
+            "deleted" => Ok(WebhookEvent::Deleted),
+            "reminded" => Ok(WebhookEvent::Reminded),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq, Eq, ToSchema)]
+pub struct Webhook {
+    pub id: i32,
+    pub url: String,
+    // 署名の鍵は登録時のみ受け取り、応答には含めない
+    #[serde(skip)]
+    pub secret: String,
+    // 空の場合は全てのイベントを通知する
+    pub events: Vec<WebhookEvent>,
+    // 最後の配信結果。応答を受け取れなかった場合、last_statusはNoneになる
+    pub last_status: Option<i32>,
+    pub last_error: Option<String>,
+    pub last_delivered_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+struct WebhookFromRow {
+    id: i32,
+    url: String,
+    secret: String,
+    events: Vec<String>,
+    last_status: Option<i32>,
+    last_error: Option<String>,
+    last_delivered_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+}
+
+impl From<WebhookFromRow> for Webhook {
+    fn from(row: WebhookFromRow) -> Self {
+        Self {
+            id: row.id,
+            url: row.url,
+            secret: row.secret,
+            events: row
+                .events
+                .iter()
+                .filter_map(|event| event.parse().ok())
+                .collect(),
+            last_status: row.last_status,
+            last_error: row.last_error,
+            last_delivered_at: row.last_delivered_at,
+            created_at: row.created_at,
+        }
+    }
+}
+
+// 配信先として扱えるhttp(s)の絶対URLのみ受け付ける
+pub fn validate_webhook_url(url: &str) -> Result<(), ValidationError> {
+    let valid = url
+        .parse::<Uri>()
+        .map(|uri| matches!(uri.scheme_str(), Some("http" | "https")) && uri.host().is_some())
+        .unwrap_or(false);
+    if !valid {
+        let mut error = ValidationError::new("url");
+        error.message = Some("Must be an http(s) URL".into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Validate, ToSchema)]
+pub struct CreateWebhook {
+    #[validate(custom = "validate_webhook_url")]
+    #[schema(example = "https://hooks.slack.com/services/...")]
+    pub url: String,
+    // 本文のHMAC-SHA256署名の鍵
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 200, message = "Over secret length"))]
+    pub secret: String,
+    #[serde(default)]
+    pub events: Vec<WebhookEvent>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq, Validate, ToSchema)]
+pub struct UpdateWebhook {
+    #[validate(custom = "validate_webhook_url")]
+    pub url: Option<String>,
+    #[validate(length(min = 1, message = "Can not be empty"))]
+    #[validate(length(max = 200, message = "Over secret length"))]
+    pub secret: Option<String>,
+    pub events: Option<Vec<WebhookEvent>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebhookDelivery {
+    pub status: Option<u16>,
+    pub error: Option<String>,
+}
+
+fn event_names(events: &[WebhookEvent]) -> Vec<String> {
+    events
+        .iter()
+        .map(|event| event.as_str().to_string())
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+pub struct WebhookRepositoryForDb {
+    pool: PgPool,
+}
+
+impl WebhookRepositoryForDb {
+    pub fn new(pool: PgPool) -> Self {
+        WebhookRepositoryForDb { pool }
+    }
+}
+
+#[async_trait]
+impl WebhookRepository for WebhookRepositoryForDb {
+    #[instrument(name = "webhook.create", skip_all, fields(db.operation = "INSERT"))]
+    async fn create(
+        &self,
+        user_id: i32,
+        payload: CreateWebhook,
+    ) -> Result<Webhook, RepositoryError> {
+        let row = sqlx::query_as::<_, WebhookFromRow>(
+            r#"
+insert into webhooks (user_id, url, secret, events)
+values ($1, $2, $3, $4)
+returning *;
+"#,
+        )
+        .bind(user_id)
+        .bind(payload.url)
+        .bind(payload.secret)
+        .bind(event_names(&payload.events))
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(row.into())
+    }
+
+    #[instrument(name = "webhook.all", skip_all, fields(db.operation = "SELECT"))]
+    async fn all(&self, user_id: i32) -> Result<Vec<Webhook>, RepositoryError> {
+        let rows = sqlx::query_as::<_, WebhookFromRow>(
+            "select * from webhooks where user_id = $1 order by id asc",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(Webhook::from).collect())
+    }
+
+    #[instrument(name = "webhook.find", skip_all, fields(db.operation = "SELECT"))]
+    async fn find(&self, user_id: i32, id: i32) -> Result<Webhook, RepositoryError> {
+        let row = sqlx::query_as::<_, WebhookFromRow>(
+            "select * from webhooks where id = $1 and user_id = $2",
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(RepositoryError::NotFound(Some(id.into())))?;
+        Ok(row.into())
+    }
+
+    #[instrument(name = "webhook.update", skip_all, fields(db.operation = "UPDATE"))]
+    async fn update(
+        &self,
+        user_id: i32,
+        id: i32,
+        payload: UpdateWebhook,
+    ) -> Result<Webhook, RepositoryError> {
+        let row = sqlx::query_as::<_, WebhookFromRow>(
+            r#"
+update webhooks
+set url = coalesce($3, url), secret = coalesce($4, secret), events = coalesce($5, events)
+where id = $1 and user_id = $2
+returning *;
+"#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(payload.url)
+        .bind(payload.secret)
+        .bind(payload.events.as_deref().map(event_names))
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(RepositoryError::NotFound(Some(id.into())))?;
+        Ok(row.into())
+    }
+
+    #[instrument(name = "webhook.delete", skip_all, fields(db.operation = "DELETE"))]
+    async fn delete(&self, user_id: i32, id: i32) -> Result<(), RepositoryError> {
+        let result = sqlx::query("delete from webhooks where id = $1 and user_id = $2")
+            .bind(id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound(Some(id.into())));
+        }
+        Ok(())
+    }
+
+    #[instrument(name = "webhook.subscribed", skip_all, fields(db.operation = "SELECT"))]
+    async fn subscribed(
+        &self,
+        user_id: i32,
+        event: WebhookEvent,
+    ) -> Result<Vec<Webhook>, RepositoryError> {
+        let rows = sqlx::query_as::<_, WebhookFromRow>(
+            r#"
+select * from webhooks
+where user_id = $1 and (cardinality(events) = 0 or $2 = any(events))
+order by id asc;
+"#,
+        )
+        .bind(user_id)
+        .bind(event.as_str())
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(Webhook::from).collect())
+    }
+
+    #[instrument(name = "webhook.record_delivery", skip_all, fields(db.operation = "UPDATE"))]
+    async fn record_delivery(
+        &self,
+        id: i32,
+        delivery: WebhookDelivery,
+    ) -> Result<(), RepositoryError> {
+        // 配信中に削除された登録先は更新対象がないだけなので、エラーにしない
+        sqlx::query(
+            r#"
+update webhooks set last_status = $2, last_error = $3, last_delivered_at = now()
+where id = $1;
+"#,
+        )
+        .bind(id)
+        .bind(delivery.status.map(i32::from))
+        .bind(delivery.error)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+#[cfg(feature = "database-test")]
+mod test {
+    use std::env;
+
+    use dotenv::dotenv;
+    use sqlx::PgPool;
+
+    use crate::repositories::user::User;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn crud_scenario() {
+        dotenv().ok();
+        let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+        let pool = PgPool::connect(database_url)
+            .await
+            .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+
+        // user data prepare
+        let username = "webhook_owner";
+        sqlx::query(
+            "delete from webhooks where user_id in (select id from users where username = $1)",
+        )
+        .bind(username)
+        .execute(&pool)
+        .await
+        .expect("Failed to prepare webhook data.");
+        sqlx::query("delete from users where username = $1")
+            .bind(username)
+            .execute(&pool)
+            .await
+            .expect("Failed to prepare user data.");
+        let user =
+            sqlx::query_as::<_, User>("insert into users ( username ) values ( $1 ) returning *")
+                .bind(username)
+                .fetch_one(&pool)
+                .await
+                .expect("Failed to insert user data.");
+        let repository = WebhookRepositoryForDb::new(pool);
+
+        // create
+        let webhook = repository
+            .create(
+                user.id,
+                CreateWebhook {
+                    url: "https://example.com/hook".to_string(),
+                    secret: "secret".to_string(),
+                    events: vec![WebhookEvent::Completed],
+                },
+            )
+            .await
+            .expect("[create] returned Err");
+        assert_eq!(vec![WebhookEvent::Completed], webhook.events);
+        assert_eq!(None, webhook.last_status);
+
+        // subscribed
+        let found = repository
+            .subscribed(user.id, WebhookEvent::Completed)
+            .await
+            .expect("[subscribed] returned Err");
+        assert_eq!(
+            vec![webhook.id],
+            found.iter().map(|w| w.id).collect::<Vec<_>>()
+        );
+        assert!(repository
+            .subscribed(user.id, WebhookEvent::Created)
+            .await
+            .expect("[subscribed] returned Err")
+            .is_empty());
+
+        // update
+        let updated = repository
+            .update(
+                user.id,
+                webhook.id,
+                UpdateWebhook {
+                    events: Some(vec![]),
+                    ..UpdateWebhook::default()
+                },
+            )
+            .await
+            .expect("[update] returned Err");
+        assert!(updated.events.is_empty());
+        assert_eq!("secret", updated.secret);
+
+        // record_delivery
+        repository
+            .record_delivery(
+                webhook.id,
+                WebhookDelivery {
+                    status: Some(500),
+                    error: Some("server error".to_string()),
+                },
+            )
+            .await
+            .expect("[record_delivery] returned Err");
+        let found = repository
+            .find(user.id, webhook.id)
+            .await
+            .expect("[find] returned Err");
+        assert_eq!(Some(500), found.last_status);
+        assert!(found.last_delivered_at.is_some());
+
+        // delete
+        assert!(repository.find(user.id + 1, webhook.id).await.is_err());
+        repository
+            .delete(user.id, webhook.id)
+            .await
+            .expect("[delete] returned Err");
+        assert!(repository.delete(user.id, webhook.id).await.is_err());
+    }
+}
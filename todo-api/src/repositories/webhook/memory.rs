@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Arc;
+
+use axum::async_trait;
+use chrono::Utc;
+use tokio::sync::RwLock;
+
+use super::{
+    CreateWebhook, RepositoryError, UpdateWebhook, Webhook, WebhookDelivery, WebhookEvent,
+    WebhookRepository,
+};
+
+// 所有者のユーザーidと組で保持する
+type WebhookDatas = HashMap<i32, (i32, Webhook)>;
+
+#[derive(Debug, Clone, Default)]
+pub struct WebhookRepositoryForMemory {
+    store: Arc<RwLock<WebhookDatas>>,
+    last_id: Arc<AtomicI32>,
+}
+
+impl WebhookRepositoryForMemory {
+    pub fn new() -> Self {
+        WebhookRepositoryForMemory {
+            store: Arc::default(),
+            last_id: Arc::default(),
+        }
+    }
+
+    fn owned(store: &WebhookDatas, user_id: i32, id: i32) -> Result<Webhook, RepositoryError> {
+        store
+            .get(&id)
+            .filter(|(owner, _)| *owner == user_id)
+            .map(|(_, webhook)| webhook.clone())
+            .ok_or(RepositoryError::NotFound(Some(id.into())))
+    }
+}
+
+#[async_trait]
+impl WebhookRepository for WebhookRepositoryForMemory {
+    async fn create(
+        &self,
+        user_id: i32,
+        payload: CreateWebhook,
+    ) -> Result<Webhook, RepositoryError> {
+        let id = self.last_id.fetch_add(1, Ordering::SeqCst) + 1;
+        let webhook = Webhook {
+            id,
+            url: payload.url,
+            secret: payload.secret,
+            events: payload.events,
+            last_status: None,
+            last_error: None,
+            last_delivered_at: None,
+            created_at: Utc::now(),
+        };
+        self.store
+            .write()
+            .await
+            .insert(id, (user_id, webhook.clone()));
+        Ok(webhook)
+    }
+
+    async fn all(&self, user_id: i32) -> Result<Vec<Webhook>, RepositoryError> {
+        let store = self.store.read().await;
+        let mut webhooks: Vec<Webhook> = store
+            .values()
+            .filter(|(owner, _)| *owner == user_id)
+            .map(|(_, webhook)| webhook.clone())
+            .collect();
+        webhooks.sort_by_key(|webhook| webhook.id);
+        Ok(webhooks)
+    }
+
+    async fn find(&self, user_id: i32, id: i32) -> Result<Webhook, RepositoryError> {
+        let store = self.store.read().await;
+        Ok(Self::owned(&store, user_id, id)?)
+    }
+
+    async fn update(
+        &self,
+        user_id: i32,
+        id: i32,
+        payload: UpdateWebhook,
+    ) -> Result<Webhook, RepositoryError> {
+        let mut store = self.store.write().await;
+        let mut webhook = Self::owned(&store, user_id, id)?;
+        if let Some(url) = payload.url {
+            webhook.url = url;
+        }
+        if let Some(secret) = payload.secret {
+            webhook.secret = secret;
+        }
+        if let Some(events) = payload.events {
+            webhook.events = events;
+        }
+        store.insert(id, (user_id, webhook.clone()));
+        Ok(webhook)
+    }
+
+    async fn delete(&self, user_id: i32, id: i32) -> Result<(), RepositoryError> {
+        let mut store = self.store.write().await;
+        Self::owned(&store, user_id, id)?;
+        store.remove(&id);
+        Ok(())
+    }
+
+    async fn subscribed(
+        &self,
+        user_id: i32,
+        event: WebhookEvent,
+    ) -> Result<Vec<Webhook>, RepositoryError> {
+        let webhooks = self.all(user_id).await?;
+        Ok(webhooks
+            .into_iter()
+            .filter(|webhook| webhook.events.is_empty() || webhook.events.contains(&event))
+            .collect())
+    }
+
+    async fn record_delivery(
+        &self,
+        id: i32,
+        delivery: WebhookDelivery,
+    ) -> Result<(), RepositoryError> {
+        if let Some((_, webhook)) = self.store.write().await.get_mut(&id) {
+            webhook.last_status = delivery.status.map(i32::from);
+            webhook.last_error = delivery.error;
+            webhook.last_delivered_at = Some(Utc::now());
+        }
+        Ok(())
+    }
+}
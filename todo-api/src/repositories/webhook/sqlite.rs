@@ -0,0 +1,184 @@
+use axum::async_trait;
+use chrono::{DateTime, Utc};
+use sqlx::types::Json;
+use sqlx::{FromRow, SqlitePool};
+use tracing::instrument;
+
+use super::{
+    event_names, CreateWebhook, UpdateWebhook, Webhook, WebhookDelivery, WebhookEvent,
+    WebhookFromRow, WebhookRepository,
+};
+use crate::repositories::RepositoryError;
+
+// SQLiteには配列型がないため、eventsはJSONの配列で持つ
+#[derive(Debug, Clone, PartialEq, Eq, FromRow)]
+struct WebhookFromSqliteRow {
+    id: i32,
+    url: String,
+    secret: String,
+    events: Json<Vec<String>>,
+    last_status: Option<i32>,
+    last_error: Option<String>,
+    last_delivered_at: Option<DateTime<Utc>>,
+    created_at: DateTime<Utc>,
+}
+
+impl From<WebhookFromSqliteRow> for Webhook {
+    fn from(row: WebhookFromSqliteRow) -> Self {
+        WebhookFromRow {
+            id: row.id,
+            url: row.url,
+            secret: row.secret,
+            events: row.events.0,
+            last_status: row.last_status,
+            last_error: row.last_error,
+            last_delivered_at: row.last_delivered_at,
+            created_at: row.created_at,
+        }
+        .into()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WebhookRepositoryForSqlite {
+    pool: SqlitePool,
+}
+
+impl WebhookRepositoryForSqlite {
+    pub fn new(pool: SqlitePool) -> Self {
+        WebhookRepositoryForSqlite { pool }
+    }
+}
+
+#[async_trait]
+impl WebhookRepository for WebhookRepositoryForSqlite {
+    #[instrument(name = "webhook.create", skip_all, fields(db.operation = "INSERT"))]
+    async fn create(
+        &self,
+        user_id: i32,
+        payload: CreateWebhook,
+    ) -> Result<Webhook, RepositoryError> {
+        let row = sqlx::query_as::<_, WebhookFromSqliteRow>(
+            r#"
+insert into webhooks (user_id, url, secret, events)
+values ($1, $2, $3, $4)
+returning *;
+"#,
+        )
+        .bind(user_id)
+        .bind(payload.url)
+        .bind(payload.secret)
+        .bind(Json(event_names(&payload.events)))
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(row.into())
+    }
+
+    #[instrument(name = "webhook.all", skip_all, fields(db.operation = "SELECT"))]
+    async fn all(&self, user_id: i32) -> Result<Vec<Webhook>, RepositoryError> {
+        let rows = sqlx::query_as::<_, WebhookFromSqliteRow>(
+            "select * from webhooks where user_id = $1 order by id asc",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(Webhook::from).collect())
+    }
+
+    #[instrument(name = "webhook.find", skip_all, fields(db.operation = "SELECT"))]
+    async fn find(&self, user_id: i32, id: i32) -> Result<Webhook, RepositoryError> {
+        let row = sqlx::query_as::<_, WebhookFromSqliteRow>(
+            "select * from webhooks where id = $1 and user_id = $2",
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(RepositoryError::NotFound(Some(id.into())))?;
+        Ok(row.into())
+    }
+
+    #[instrument(name = "webhook.update", skip_all, fields(db.operation = "UPDATE"))]
+    async fn update(
+        &self,
+        user_id: i32,
+        id: i32,
+        payload: UpdateWebhook,
+    ) -> Result<Webhook, RepositoryError> {
+        let row = sqlx::query_as::<_, WebhookFromSqliteRow>(
+            r#"
+update webhooks
+set url = coalesce($3, url), secret = coalesce($4, secret), events = coalesce($5, events)
+where id = $1 and user_id = $2
+returning *;
+"#,
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(payload.url)
+        .bind(payload.secret)
+        .bind(payload.events.as_deref().map(event_names).map(Json))
+        .fetch_optional(&self.pool)
+        .await?
+        .ok_or(RepositoryError::NotFound(Some(id.into())))?;
+        Ok(row.into())
+    }
+
+    #[instrument(name = "webhook.delete", skip_all, fields(db.operation = "DELETE"))]
+    async fn delete(&self, user_id: i32, id: i32) -> Result<(), RepositoryError> {
+        let result = sqlx::query("delete from webhooks where id = $1 and user_id = $2")
+            .bind(id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(RepositoryError::NotFound(Some(id.into())));
+        }
+        Ok(())
+    }
+
+    #[instrument(name = "webhook.subscribed", skip_all, fields(db.operation = "SELECT"))]
+    async fn subscribed(
+        &self,
+        user_id: i32,
+        event: WebhookEvent,
+    ) -> Result<Vec<Webhook>, RepositoryError> {
+        let rows = sqlx::query_as::<_, WebhookFromSqliteRow>(
+            r#"
+select * from webhooks
+where user_id = $1
+  and (json_array_length(events) = 0
+       or exists (select 1 from json_each(webhooks.events) where value = $2))
+order by id asc;
+"#,
+        )
+        .bind(user_id)
+        .bind(event.as_str())
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(Webhook::from).collect())
+    }
+
+    #[instrument(name = "webhook.record_delivery", skip_all, fields(db.operation = "UPDATE"))]
+    async fn record_delivery(
+        &self,
+        id: i32,
+        delivery: WebhookDelivery,
+    ) -> Result<(), RepositoryError> {
+        // 配信中に削除された登録先は更新対象がないだけなので、エラーにしない
+        sqlx::query(
+            r#"
+update webhooks
+set last_status = $2, last_error = $3,
+    last_delivered_at = strftime('%Y-%m-%d %H:%M:%f', 'now')
+where id = $1;
+"#,
+        )
+        .bind(id)
+        .bind(delivery.status.map(i32::from))
+        .bind(delivery.error)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+}
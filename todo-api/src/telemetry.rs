@@ -0,0 +1,285 @@
+#[cfg(feature = "otel")]
+use std::env;
+
+#[cfg(feature = "otel")]
+use axum::http::HeaderMap;
+use axum::http::{HeaderValue, Request};
+#[cfg(feature = "otel")]
+use opentelemetry::propagation::{Extractor, TextMapPropagator};
+#[cfg(feature = "otel")]
+use opentelemetry::trace::TraceError;
+#[cfg(feature = "otel")]
+use opentelemetry::KeyValue;
+#[cfg(feature = "otel")]
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+#[cfg(feature = "otel")]
+use opentelemetry_sdk::Resource;
+use tower_http::classify::{ServerErrorsAsFailures, SharedClassifier};
+use tower_http::request_id::{MakeRequestId, RequestId};
+use tower_http::trace::{DefaultOnRequest, DefaultOnResponse, MakeSpan, TraceLayer};
+use tracing::{Level, Span};
+#[cfg(feature = "otel")]
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+use crate::config::LogFormat;
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+const DEFAULT_LOG_FILTER: &str = "info";
+// どちらかを設定した場合のみspanを送る。送り先以外の設定もopentelemetry-otlpが標準の環境変数から読む
+#[cfg(feature = "otel")]
+const OTLP_ENDPOINT_VARS: [&str; 2] = [
+    "OTEL_EXPORTER_OTLP_ENDPOINT",
+    "OTEL_EXPORTER_OTLP_TRACES_ENDPOINT",
+];
+#[cfg(feature = "otel")]
+const SERVICE_NAME: &str = "todo-api";
+
+// テストなどから複数回呼ばれた場合、2回目以降は既存の設定を使い続ける
+pub fn init_tracing(log_format: LogFormat) {
+    let filter =
+        EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_FILTER));
+    // jsonではspanのフィールド(request_idなど)も1行のオブジェクトに含める
+    let fmt = match log_format {
+        LogFormat::Pretty => tracing_subscriber::fmt::layer().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .boxed(),
+    };
+    let registry = tracing_subscriber::registry().with(filter).with(fmt);
+    #[cfg(feature = "otel")]
+    let (registry, otlp_error) = match otlp_tracer() {
+        Ok(tracer) => {
+            let layer = tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer));
+            (registry.with(layer), None)
+        }
+        Err(e) => (registry.with(None), Some(e)),
+    };
+    let _ = registry.try_init();
+    // exporterを作れなくてもログは出力できるため、起動は続ける
+    #[cfg(feature = "otel")]
+    if let Some(e) = otlp_error {
+        tracing::warn!("fail install otlp exporter, spans are not exported: {}", e);
+    }
+}
+
+#[cfg(feature = "otel")]
+fn otlp_tracer() -> Result<Option<opentelemetry_sdk::trace::Tracer>, TraceError> {
+    let configured = OTLP_ENDPOINT_VARS
+        .iter()
+        .any(|key| env::var(key).is_ok_and(|value| !value.trim().is_empty()));
+    if !configured {
+        return Ok(None);
+    }
+    let mut config = opentelemetry_sdk::trace::config();
+    // OTEL_SERVICE_NAMEが未設定の場合、unknown_serviceではなくこのAPIの名前で送る
+    if env::var("OTEL_SERVICE_NAME").is_err() {
+        let service = Resource::new([KeyValue::new("service.name", SERVICE_NAME)]);
+        config = config.with_resource(Resource::default().merge(&service));
+    }
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(opentelemetry_otlp::new_exporter().tonic())
+        .with_trace_config(config)
+        .install_batch(opentelemetry_sdk::runtime::Tokio)
+        .map(Some)
+}
+
+// 送信待ちのspanを書き出してから終了する。書き出しを待つ間はスレッドを止めるため、別スレッドで待つ
+pub async fn shutdown_tracing() {
+    #[cfg(feature = "otel")]
+    let _ = tokio::task::spawn_blocking(opentelemetry::global::shutdown_tracer_provider).await;
+}
+
+#[cfg(feature = "otel")]
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+#[cfg(feature = "otel")]
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|key| key.as_str()).collect()
+    }
+}
+
+// クライアントがx-request-idを指定しなかった場合のみ採番する
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MakeRandomRequestId;
+
+impl MakeRequestId for MakeRandomRequestId {
+    fn make_request_id<B>(&mut self, _request: &Request<B>) -> Option<RequestId> {
+        let id = format!("{:032x}", rand::random::<u128>());
+        HeaderValue::from_str(&id).ok().map(RequestId::new)
+    }
+}
+
+// ハンドラやリポジトリのエラーログもこのspan内で出力され、request_idで追跡できる
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RequestSpan;
+
+impl<B> MakeSpan<B> for RequestSpan {
+    fn make_span(&mut self, request: &Request<B>) -> Span {
+        let request_id = request
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|id| id.to_str().ok())
+            .unwrap_or_default();
+        let span = tracing::info_span!(
+            "request",
+            method = %request.method(),
+            path = %request.uri().path(),
+            request_id = %request_id,
+        );
+        // traceparentを受け取った場合、呼び出し元のtraceの子として送る
+        #[cfg(feature = "otel")]
+        span.set_parent(TraceContextPropagator::new().extract(&HeaderExtractor(request.headers())));
+        span
+    }
+}
+
+// レスポンスのステータスとレイテンシをinfoレベルで出力する
+pub fn trace_layer() -> TraceLayer<
+    SharedClassifier<ServerErrorsAsFailures>,
+    RequestSpan,
+    DefaultOnRequest,
+    DefaultOnResponse,
+> {
+    TraceLayer::new_for_http()
+        .make_span_with(RequestSpan)
+        .on_response(DefaultOnResponse::new().level(Level::INFO))
+}
+
+#[cfg(test)]
+mod test {
+    use axum::body::Body;
+
+    use super::*;
+
+    #[test]
+    fn should_ignore_repeated_init() {
+        init_tracing(LogFormat::Json);
+        init_tracing(LogFormat::Pretty);
+        tracing::info!("still logging after repeated init");
+    }
+
+    #[test]
+    fn should_make_unique_request_ids() {
+        let request = Request::new(Body::empty());
+        let first = MakeRandomRequestId.make_request_id(&request).unwrap();
+        let second = MakeRandomRequestId.make_request_id(&request).unwrap();
+        assert_eq!(32, first.header_value().len());
+        assert_ne!(first.header_value(), second.header_value());
+    }
+
+    // リクエストからリポジトリまでのspanの親子関係を、実際のDBに対して確かめる
+    #[cfg(all(feature = "otel", feature = "database-test"))]
+    mod otel {
+        use std::sync::{Arc, Mutex};
+
+        use dotenv::dotenv;
+        use futures_util::future::BoxFuture;
+        use hyper::header;
+        use opentelemetry::trace::{SpanId, TraceId, TracerProvider as _};
+        use opentelemetry::Value;
+        use opentelemetry_sdk::export::trace::{ExportResult, SpanData, SpanExporter};
+        use opentelemetry_sdk::trace::TracerProvider;
+        use sqlx::PgPool;
+        use tower::ServiceExt;
+
+        use crate::auth::test_utils::{test_keys, test_token};
+        use crate::clock::SystemClock;
+        use crate::config::DEFAULT_API_PREFIX;
+        use crate::create_app;
+        use crate::repositories::comment::CommentRepositoryForDb;
+        use crate::repositories::health::HealthRepositoryForDb;
+        use crate::repositories::label::LabelRepositoryForDb;
+        use crate::repositories::todo::TodoRepositoryForDb;
+        use crate::repositories::user::UserRepositoryForDb;
+        use crate::repositories::webhook::WebhookRepositoryForDb;
+
+        use super::*;
+
+        const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+        const PARENT_SPAN_ID: &str = "00f067aa0ba902b7";
+
+        // 送られたspanをテストから読めるよう、メモリに貯めるだけのexporter
+        #[derive(Debug, Clone, Default)]
+        struct InMemoryExporter(Arc<Mutex<Vec<SpanData>>>);
+
+        impl SpanExporter for InMemoryExporter {
+            fn export(&mut self, batch: Vec<SpanData>) -> BoxFuture<'static, ExportResult> {
+                self.0.lock().unwrap().extend(batch);
+                Box::pin(async { Ok(()) })
+            }
+        }
+
+        #[tokio::test]
+        async fn should_nest_repository_spans_under_request_span() {
+            dotenv().ok();
+            let database_url = &env::var("DATABASE_URL").expect("undefined [DATABASE_URL]");
+            let pool = PgPool::connect(database_url)
+                .await
+                .unwrap_or_else(|_| panic!("fail connect database, url is [{}]", database_url));
+            let exporter = InMemoryExporter::default();
+            let provider = TracerProvider::builder()
+                .with_simple_exporter(exporter.clone())
+                .build();
+            let subscriber = tracing_subscriber::registry()
+                .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+            let _guard = tracing::subscriber::set_default(subscriber);
+
+            let app = create_app(
+                TodoRepositoryForDb::new(pool.clone(), SystemClock),
+                LabelRepositoryForDb::new(pool.clone()),
+                UserRepositoryForDb::new(pool.clone()),
+                CommentRepositoryForDb::new(pool.clone()),
+                WebhookRepositoryForDb::new(pool.clone()),
+                HealthRepositoryForDb::new(pool),
+                test_keys(),
+                DEFAULT_API_PREFIX,
+            );
+            let req = Request::builder()
+                .uri("/todos/0")
+                .header(header::AUTHORIZATION, format!("Bearer {}", test_token(1)))
+                .header(
+                    "traceparent",
+                    format!("00-{}-{}-01", TRACE_ID, PARENT_SPAN_ID),
+                )
+                .body(Body::empty())
+                .unwrap();
+            app.oneshot(req).await.unwrap();
+            provider.force_flush();
+
+            let spans = exporter.0.lock().unwrap().clone();
+            let request = spans
+                .iter()
+                .find(|span| span.name == "request")
+                .expect("request span is not exported");
+            let trace_id = TraceId::from_hex(TRACE_ID).unwrap();
+            assert_eq!(trace_id, request.span_context.trace_id());
+            assert_eq!(
+                SpanId::from_hex(PARENT_SPAN_ID).unwrap(),
+                request.parent_span_id
+            );
+
+            let find = spans
+                .iter()
+                .find(|span| span.name == "todo.find")
+                .expect("repository span is not exported");
+            assert_eq!(trace_id, find.span_context.trace_id());
+            assert_eq!(request.span_context.span_id(), find.parent_span_id);
+            let operation = find
+                .attributes
+                .iter()
+                .find(|attribute| attribute.key.as_str() == "db.operation")
+                .map(|attribute| attribute.value.clone());
+            assert_eq!(Some(Value::from("SELECT")), operation);
+        }
+    }
+}
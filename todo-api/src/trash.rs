@@ -0,0 +1,103 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use tokio::task::JoinHandle;
+
+use crate::clock::Clock;
+use crate::periodic::spawn_periodic;
+use crate::repositories::todo::TodoRepository;
+use crate::repositories::RepositoryError;
+
+// ゴミ箱に保持期間を過ぎたTodoが残らないよう、一定間隔で削除し続ける
+pub fn spawn_purger<T: TodoRepository, C: Clock>(
+    repository: T,
+    clock: C,
+    retention: Duration,
+    interval: Duration,
+) -> JoinHandle<()> {
+    spawn_periodic(
+        "purge trash",
+        interval,
+        (repository, clock),
+        move |state| async move {
+            let (repository, clock) = &*state;
+            let purged = purge_expired(repository, clock.now(), retention).await?;
+            if purged > 0 {
+                tracing::info!("purged {} todos from trash", purged);
+            }
+            Ok(false)
+        },
+    )
+}
+
+async fn purge_expired<T: TodoRepository>(
+    repository: &T,
+    now: DateTime<Utc>,
+    retention: Duration,
+) -> Result<u64, RepositoryError> {
+    repository
+        .purge_deleted_before(cutoff(now, retention))
+        .await
+}
+
+pub(crate) fn cutoff(now: DateTime<Utc>, retention: Duration) -> DateTime<Utc> {
+    chrono::Duration::from_std(retention)
+        .ok()
+        .and_then(|retention| now.checked_sub_signed(retention))
+        .unwrap_or(DateTime::<Utc>::MIN_UTC)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::clock::test_utils::MockClock;
+    use crate::repositories::todo::TodoRepositoryForMemory;
+    use crate::repositories::todo::{CreateTodo, Precondition};
+
+    #[test]
+    fn should_subtract_retention_from_now() {
+        let now = Utc::now();
+        assert_eq!(
+            now - chrono::Duration::days(30),
+            cutoff(now, Duration::from_secs(30 * 24 * 60 * 60))
+        );
+        assert_eq!(
+            DateTime::<Utc>::MIN_UTC,
+            cutoff(now, Duration::from_secs(u64::MAX))
+        );
+    }
+
+    #[tokio::test]
+    async fn should_purge_trash_in_background() {
+        let clock = MockClock::default();
+        let repository = TodoRepositoryForMemory::new(vec![]).with_clock(clock.clone());
+        let todo = repository
+            .create(1, CreateTodo::new("trashed".to_string(), vec![]))
+            .await
+            .expect("failed create todo");
+        repository
+            .delete(1, todo.id, Precondition::default())
+            .await
+            .expect("failed delete todo");
+
+        let purger = spawn_purger(
+            repository.clone(),
+            clock.clone(),
+            Duration::from_secs(60),
+            Duration::from_millis(10),
+        );
+        // 保持期間が経過するまでは削除しない
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(1, repository.trash(1).await.unwrap().len());
+
+        clock.advance(chrono::Duration::minutes(2));
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !repository.trash(1).await.unwrap().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("trash was not purged");
+        purger.abort();
+    }
+}
@@ -0,0 +1,561 @@
+use std::time::Duration;
+
+use axum::async_trait;
+use hmac::{Hmac, Mac};
+use hyper::client::HttpConnector;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Client, Method, Request};
+use hyper_rustls::HttpsConnector;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use tokio::task::JoinHandle;
+
+use crate::events::{TodoEvent, TodoEvents};
+use crate::outbox::EventSink;
+use crate::repositories::outbox::OutboxEvent;
+use crate::repositories::webhook::{Webhook, WebhookDelivery, WebhookEvent, WebhookRepository};
+
+pub const SIGNATURE_HEADER: &str = "x-todo-signature";
+pub const EVENT_HEADER: &str = "x-todo-event";
+// outboxから配信した場合のみ付ける。再送で同じイベントを2回受け取った場合の重複除去に使える
+pub const EVENT_ID_HEADER: &str = "x-todo-event-id";
+// 応答しない配信先で配信タスクが溜まり続けないよう、1回の送信を打ち切る
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+type HttpClient = Client<HttpsConnector<HttpConnector>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+}
+
+impl RetryPolicy {
+    // attempt回目(1始まり)の失敗の後に待つ時間。base_delay, 2倍, 4倍...と伸ばす
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        self.base_delay
+            .checked_mul(1 << attempt.saturating_sub(1).min(16))
+            .unwrap_or(Duration::MAX)
+    }
+}
+
+// Todoの変更を購読し、登録先へ配信し続ける。配信は登録先ごとに別タスクで行うため、
+// 遅い・失敗する登録先があっても他の配信やリクエストへの応答は待たされない
+pub fn spawn_dispatcher<W: WebhookRepository>(
+    repository: W,
+    events: &TodoEvents,
+    policy: RetryPolicy,
+) -> JoinHandle<()> {
+    let mut subscription = events.subscribe_all();
+    let client: HttpClient = Client::builder().build(HttpsConnector::with_webpki_roots());
+    tokio::spawn(async move {
+        while let Some((user_id, event)) = subscription.next().await {
+            let user_id = match user_id {
+                Some(user_id) => user_id,
+                None => continue,
+            };
+            for kind in event_kinds(&event) {
+                let webhooks = match repository.subscribed(user_id, kind).await {
+                    Ok(webhooks) => webhooks,
+                    Err(e) => {
+                        tracing::warn!("fail load webhooks for user {}: {:#}", user_id, e);
+                        continue;
+                    }
+                };
+                let body = payload(kind, &event).to_string();
+                for webhook in webhooks {
+                    tokio::spawn(deliver(
+                        client.clone(),
+                        repository.clone(),
+                        webhook,
+                        kind,
+                        body.clone(),
+                        policy,
+                    ));
+                }
+            }
+        }
+    })
+}
+
+// outboxのイベントを登録先へ1回ずつ送る。失敗した場合の再送はoutbox側で行い、
+// その際は送れた登録先を飛ばし、失敗した登録先へのみ送る
+#[derive(Clone)]
+pub struct WebhookSink<W> {
+    repository: W,
+    client: HttpClient,
+}
+
+impl<W: WebhookRepository> WebhookSink<W> {
+    pub fn new(repository: W) -> Self {
+        WebhookSink {
+            repository,
+            client: Client::builder().build(HttpsConnector::with_webpki_roots()),
+        }
+    }
+}
+
+#[async_trait]
+impl<W: WebhookRepository> EventSink for WebhookSink<W> {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    async fn deliver(&self, event: &OutboxEvent, sent: &mut Vec<String>) -> anyhow::Result<()> {
+        let user_id = match event.user_id {
+            Some(user_id) => user_id,
+            None => return Ok(()),
+        };
+        let mut failures = vec![];
+        for kind in event_kinds(&event.event) {
+            let body = payload(kind, &event.event).to_string();
+            for webhook in self.repository.subscribed(user_id, kind).await? {
+                let target = format!("webhook:{}:{}", webhook.id, kind.as_str());
+                if event.delivered.contains(&target) {
+                    continue;
+                }
+                let signature = sign(&webhook.secret, &body);
+                let delivery = send(
+                    &self.client,
+                    &webhook.url,
+                    kind,
+                    &signature,
+                    &body,
+                    Some(event.id),
+                )
+                .await;
+                match delivery.error.as_deref() {
+                    Some(error) => failures.push(format!("webhook {}: {}", webhook.id, error)),
+                    None => sent.push(target),
+                }
+                if let Err(e) = self.repository.record_delivery(webhook.id, delivery).await {
+                    tracing::warn!("fail record webhook {} delivery: {:#}", webhook.id, e);
+                }
+            }
+        }
+        if !failures.is_empty() {
+            anyhow::bail!(failures.join(", "));
+        }
+        Ok(())
+    }
+}
+
+fn event_kinds(event: &TodoEvent) -> Vec<WebhookEvent> {
+    match event {
+        TodoEvent::Created { .. } => vec![WebhookEvent::Created],
+        TodoEvent::Updated {
+            completed_now: true,
+            ..
+        } => vec![WebhookEvent::Updated, WebhookEvent::Completed],
+        TodoEvent::Updated { .. } => vec![WebhookEvent::Updated],
+        TodoEvent::Deleted { .. } => vec![WebhookEvent::Deleted],
+        TodoEvent::Reminded { .. } => vec![WebhookEvent::Reminded],
+        // Webhookはユーザーごとに登録するため、ユーザーに属さないラベルの変更は送らない
+        TodoEvent::LabelCreated { .. }
+        | TodoEvent::LabelUpdated { .. }
+        | TodoEvent::LabelDeleted { .. }
+        | TodoEvent::LabelsMerged { .. } => vec![],
+    }
+}
+
+fn payload(kind: WebhookEvent, event: &TodoEvent) -> Value {
+    match event {
+        TodoEvent::Created { todo }
+        | TodoEvent::Updated { todo, .. }
+        | TodoEvent::Reminded { todo } => {
+            json!({ "event": kind, "todo": todo })
+        }
+        TodoEvent::Deleted { id } => json!({ "event": kind, "id": id }),
+        // event_kindsが種類を返さないため、ここへは来ない
+        TodoEvent::LabelCreated { .. }
+        | TodoEvent::LabelUpdated { .. }
+        | TodoEvent::LabelDeleted { .. }
+        | TodoEvent::LabelsMerged { .. } => json!({ "event": kind }),
+    }
+}
+
+// 本文のHMAC-SHA256を"sha256=<hex>"の形式で返す。受信側は同じ鍵で計算して比較する
+pub fn sign(secret: &str, body: &str) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(body.as_bytes());
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+// 2xx以外の応答や接続エラーはmax_attemptsまで再送し、最後の結果を記録する
+async fn deliver<W: WebhookRepository>(
+    client: HttpClient,
+    repository: W,
+    webhook: Webhook,
+    kind: WebhookEvent,
+    body: String,
+    policy: RetryPolicy,
+) {
+    let signature = sign(&webhook.secret, &body);
+    let mut attempt = 1;
+    let delivery = loop {
+        let delivery = send(&client, &webhook.url, kind, &signature, &body, None).await;
+        let succeeded = delivery.error.is_none();
+        if succeeded || attempt >= policy.max_attempts {
+            break delivery;
+        }
+        tokio::time::sleep(policy.delay(attempt)).await;
+        attempt += 1;
+    };
+    if let Some(error) = delivery.error.as_deref() {
+        tracing::warn!(
+            "fail deliver webhook {} after {} attempts: {}",
+            webhook.id,
+            attempt,
+            error
+        );
+    }
+    if let Err(e) = repository.record_delivery(webhook.id, delivery).await {
+        tracing::warn!("fail record webhook {} delivery: {:#}", webhook.id, e);
+    }
+}
+
+async fn send(
+    client: &HttpClient,
+    url: &str,
+    kind: WebhookEvent,
+    signature: &str,
+    body: &str,
+    event_id: Option<i64>,
+) -> WebhookDelivery {
+    let mut request = Request::builder()
+        .method(Method::POST)
+        .uri(url)
+        .header(CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+        .header(EVENT_HEADER, kind.as_str())
+        .header(SIGNATURE_HEADER, signature);
+    if let Some(id) = event_id {
+        request = request.header(EVENT_ID_HEADER, id);
+    }
+    let request = request.body(Body::from(body.to_string()));
+    let request = match request {
+        Ok(request) => request,
+        Err(e) => return failed(None, e.to_string()),
+    };
+    match tokio::time::timeout(REQUEST_TIMEOUT, client.request(request)).await {
+        Ok(Ok(response)) if response.status().is_success() => WebhookDelivery {
+            status: Some(response.status().as_u16()),
+            error: None,
+        },
+        Ok(Ok(response)) => failed(
+            Some(response.status().as_u16()),
+            format!("unexpected status {}", response.status()),
+        ),
+        Ok(Err(e)) => failed(None, e.to_string()),
+        Err(_) => failed(None, format!("timed out after {:?}", REQUEST_TIMEOUT)),
+    }
+}
+
+fn failed(status: Option<u16>, error: String) -> WebhookDelivery {
+    WebhookDelivery {
+        status,
+        error: Some(error),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::Infallible;
+    use std::net::{SocketAddr, TcpListener};
+    use std::sync::{Arc, Mutex};
+
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Response, Server, StatusCode};
+    use tokio::sync::mpsc;
+
+    use crate::repositories::todo::TodoEntity;
+    use crate::repositories::webhook::CreateWebhook;
+    use crate::repositories::webhook::WebhookRepositoryForMemory;
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct Received {
+        event: String,
+        event_id: String,
+        signature: String,
+        body: String,
+    }
+
+    // 受け取ったリクエストをチャネルへ流し、statusesの順に応答する(使い切った後は最後の値)
+    fn spawn_receiver(
+        statuses: Vec<StatusCode>,
+    ) -> (SocketAddr, mpsc::UnboundedReceiver<Received>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let statuses = Arc::new(Mutex::new(statuses));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let make_service = make_service_fn(move |_| {
+            let sender = sender.clone();
+            let statuses = statuses.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                    let sender = sender.clone();
+                    let statuses = statuses.clone();
+                    async move {
+                        let header = |name| {
+                            req.headers()
+                                .get(name)
+                                .and_then(|value| value.to_str().ok())
+                                .unwrap_or_default()
+                                .to_string()
+                        };
+                        let event = header(EVENT_HEADER);
+                        let event_id = header(EVENT_ID_HEADER);
+                        let signature = header(SIGNATURE_HEADER);
+                        let body = hyper::body::to_bytes(req.into_body()).await.unwrap();
+                        let _ = sender.send(Received {
+                            event,
+                            event_id,
+                            signature,
+                            body: String::from_utf8(body.to_vec()).unwrap(),
+                        });
+                        let status = {
+                            let mut statuses = statuses.lock().unwrap();
+                            if statuses.len() > 1 {
+                                statuses.remove(0)
+                            } else {
+                                statuses[0]
+                            }
+                        };
+                        let mut response = Response::new(Body::empty());
+                        *response.status_mut() = status;
+                        Ok::<_, Infallible>(response)
+                    }
+                }))
+            }
+        });
+        tokio::spawn(Server::from_tcp(listener).unwrap().serve(make_service));
+        (addr, receiver)
+    }
+
+    async fn register(
+        repository: &WebhookRepositoryForMemory,
+        addr: SocketAddr,
+        events: Vec<WebhookEvent>,
+    ) -> Webhook {
+        repository
+            .create(
+                1,
+                CreateWebhook {
+                    url: format!("http://{}/hook", addr),
+                    secret: "secret".to_string(),
+                    events,
+                },
+            )
+            .await
+            .unwrap()
+    }
+
+    async fn receive(receiver: &mut mpsc::UnboundedReceiver<Received>) -> Received {
+        tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .expect("webhook not delivered")
+            .unwrap()
+    }
+
+    // 配信結果の記録は応答の後に行われるため、記録されるまで待つ
+    async fn wait_recorded(repository: &WebhookRepositoryForMemory, id: i32) -> Webhook {
+        for _ in 0..100 {
+            let webhook = repository.find(1, id).await.unwrap();
+            if webhook.last_delivered_at.is_some() {
+                return webhook;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("webhook delivery not recorded");
+    }
+
+    fn policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            base_delay: Duration::from_millis(10),
+        }
+    }
+
+    #[test]
+    fn retry_delay_test() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(100),
+        };
+        assert_eq!(Duration::from_millis(100), policy.delay(1));
+        assert_eq!(Duration::from_millis(200), policy.delay(2));
+        assert_eq!(Duration::from_millis(800), policy.delay(4));
+    }
+
+    #[test]
+    fn sign_test() {
+        // RFC 4231のテストケース2
+        assert_eq!(
+            "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+            sign("Jefe", "what do ya want for nothing?")
+        );
+    }
+
+    #[tokio::test]
+    async fn should_deliver_signed_payload() {
+        let (addr, mut receiver) = spawn_receiver(vec![StatusCode::OK]);
+        let repository = WebhookRepositoryForMemory::new();
+        let webhook = register(&repository, addr, vec![]).await;
+        let events = TodoEvents::new();
+        spawn_dispatcher(repository.clone(), &events, policy(3));
+
+        let todo = TodoEntity::new(1, "notify".to_string(), vec![]);
+        events.publish(1, TodoEvent::Created { todo: todo.clone() });
+        let received = receive(&mut receiver).await;
+        assert_eq!("created", received.event);
+        assert_eq!("", received.event_id);
+        assert_eq!(sign("secret", &received.body), received.signature);
+        let body: Value = serde_json::from_str(&received.body).unwrap();
+        assert_eq!(json!({ "event": "created", "todo": todo }), body);
+
+        let recorded = wait_recorded(&repository, webhook.id).await;
+        assert_eq!(Some(200), recorded.last_status);
+        assert_eq!(None, recorded.last_error);
+    }
+
+    #[tokio::test]
+    async fn should_deliver_only_subscribed_events() {
+        let (addr, mut receiver) = spawn_receiver(vec![StatusCode::OK]);
+        let repository = WebhookRepositoryForMemory::new();
+        register(&repository, addr, vec![WebhookEvent::Completed]).await;
+        let events = TodoEvents::new();
+        spawn_dispatcher(repository, &events, policy(1));
+
+        let todo = TodoEntity::new(1, "done".to_string(), vec![]);
+        // 他のユーザーの変更と、完了以外の更新は通知されない
+        events.publish(
+            2,
+            TodoEvent::Updated {
+                todo: todo.clone(),
+                completed_now: true,
+            },
+        );
+        events.publish(
+            1,
+            TodoEvent::Updated {
+                todo: todo.clone(),
+                completed_now: false,
+            },
+        );
+        events.publish(
+            1,
+            TodoEvent::Updated {
+                todo,
+                completed_now: true,
+            },
+        );
+        assert_eq!("completed", receive(&mut receiver).await.event);
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn should_retry_and_record_last_failure() {
+        let (addr, mut receiver) = spawn_receiver(vec![StatusCode::INTERNAL_SERVER_ERROR]);
+        let repository = WebhookRepositoryForMemory::new();
+        let webhook = register(&repository, addr, vec![]).await;
+        let events = TodoEvents::new();
+        spawn_dispatcher(repository.clone(), &events, policy(3));
+
+        events.publish(1, TodoEvent::Deleted { id: 1 });
+        for _ in 0..3 {
+            let received = receive(&mut receiver).await;
+            assert_eq!(r#"{"event":"deleted","id":1}"#, received.body);
+        }
+        let recorded = wait_recorded(&repository, webhook.id).await;
+        assert_eq!(Some(500), recorded.last_status);
+        assert!(recorded.last_error.is_some());
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn should_succeed_after_retry() {
+        let (addr, mut receiver) = spawn_receiver(vec![
+            StatusCode::SERVICE_UNAVAILABLE,
+            StatusCode::NO_CONTENT,
+        ]);
+        let repository = WebhookRepositoryForMemory::new();
+        let webhook = register(&repository, addr, vec![]).await;
+        let events = TodoEvents::new();
+        spawn_dispatcher(repository.clone(), &events, policy(5));
+
+        events.publish(1, TodoEvent::Deleted { id: 1 });
+        receive(&mut receiver).await;
+        receive(&mut receiver).await;
+        let recorded = wait_recorded(&repository, webhook.id).await;
+        assert_eq!(Some(204), recorded.last_status);
+        assert_eq!(None, recorded.last_error);
+    }
+
+    #[tokio::test]
+    async fn should_deliver_outbox_event_once() {
+        let (addr, mut receiver) =
+            spawn_receiver(vec![StatusCode::OK, StatusCode::INTERNAL_SERVER_ERROR]);
+        let repository = WebhookRepositoryForMemory::new();
+        let webhook = register(&repository, addr, vec![]).await;
+        let sink = WebhookSink::new(repository.clone());
+        let event = OutboxEvent {
+            id: 7,
+            user_id: Some(1),
+            event: TodoEvent::Deleted { id: 1 },
+            attempts: 0,
+            delivered: vec![],
+        };
+
+        let mut sent = vec![];
+        sink.deliver(&event, &mut sent).await.unwrap();
+        assert_eq!(vec![format!("webhook:{}:deleted", webhook.id)], sent);
+        let received = receive(&mut receiver).await;
+        assert_eq!("7", received.event_id);
+        assert_eq!(r#"{"event":"deleted","id":1}"#, received.body);
+
+        // 失敗しても自身では再送せず、outboxの再送に任せる
+        let mut sent = vec![];
+        let e = sink.deliver(&event, &mut sent).await.unwrap_err();
+        assert!(e.to_string().contains("unexpected status 500"));
+        assert!(sent.is_empty());
+        receive(&mut receiver).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(receiver.try_recv().is_err());
+        let recorded = repository.find(1, webhook.id).await.unwrap();
+        assert_eq!(Some(500), recorded.last_status);
+    }
+
+    #[tokio::test]
+    async fn should_resend_outbox_event_only_to_failed_webhooks() {
+        let (ok_addr, mut ok_receiver) = spawn_receiver(vec![StatusCode::OK]);
+        let (failing_addr, mut failing_receiver) =
+            spawn_receiver(vec![StatusCode::INTERNAL_SERVER_ERROR, StatusCode::OK]);
+        let repository = WebhookRepositoryForMemory::new();
+        let ok = register(&repository, ok_addr, vec![]).await;
+        register(&repository, failing_addr, vec![]).await;
+        let sink = WebhookSink::new(repository.clone());
+        let mut event = OutboxEvent {
+            id: 7,
+            user_id: Some(1),
+            event: TodoEvent::Deleted { id: 1 },
+            attempts: 0,
+            delivered: vec![],
+        };
+
+        let mut sent = vec![];
+        assert!(sink.deliver(&event, &mut sent).await.is_err());
+        assert_eq!(vec![format!("webhook:{}:deleted", ok.id)], sent);
+        receive(&mut ok_receiver).await;
+        receive(&mut failing_receiver).await;
+
+        // 送り直す際は、送れた登録先を飛ばす
+        event.delivered = sent;
+        sink.deliver(&event, &mut vec![]).await.unwrap();
+        receive(&mut failing_receiver).await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(ok_receiver.try_recv().is_err());
+    }
+}
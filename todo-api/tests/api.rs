@@ -0,0 +1,160 @@
+use axum::body::Body;
+use axum::http::{header, Method, Request, StatusCode};
+use axum::Router;
+use serde_json::{json, Value};
+use tower::ServiceExt;
+
+use rust_todo::auth::AuthKeys;
+use rust_todo::config::DEFAULT_API_PREFIX;
+use rust_todo::create_app;
+use rust_todo::repositories::comment::CommentRepositoryForMemory;
+use rust_todo::repositories::health::HealthRepositoryForMemory;
+use rust_todo::repositories::label::LabelRepositoryForMemory;
+use rust_todo::repositories::todo::TodoRepositoryForMemory;
+use rust_todo::repositories::user::UserRepositoryForMemory;
+use rust_todo::repositories::webhook::WebhookRepositoryForMemory;
+
+// クレートの公開APIのみで組み立て、外部のクライアントと同じくHTTPで操作する
+fn app() -> Router {
+    create_app(
+        TodoRepositoryForMemory::new(vec![]),
+        LabelRepositoryForMemory::new(),
+        UserRepositoryForMemory::new(),
+        CommentRepositoryForMemory::new(),
+        WebhookRepositoryForMemory::new(),
+        HealthRepositoryForMemory,
+        AuthKeys::new(b"integration-test-secret"),
+        DEFAULT_API_PREFIX,
+    )
+}
+
+fn request(method: Method, path: &str, token: Option<&str>, body: Option<Value>) -> Request<Body> {
+    let mut builder = Request::builder().uri(path).method(method);
+    if let Some(token) = token {
+        builder = builder.header(header::AUTHORIZATION, format!("Bearer {}", token));
+    }
+    match body {
+        Some(body) => builder
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.as_ref())
+            .body(Body::from(body.to_string()))
+            .unwrap(),
+        None => builder.body(Body::empty()).unwrap(),
+    }
+}
+
+async fn send(app: &Router, req: Request<Body>) -> (StatusCode, Value) {
+    let res = app.clone().oneshot(req).await.unwrap();
+    let status = res.status();
+    let bytes = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    (
+        status,
+        serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+    )
+}
+
+async fn register(app: &Router, username: &str) -> String {
+    let (status, body) = send(
+        app,
+        request(
+            Method::POST,
+            "/api/v1/auth/register",
+            None,
+            Some(json!({ "username": username, "password": "password123" })),
+        ),
+    )
+    .await;
+    assert_eq!(StatusCode::CREATED, status);
+    body["token"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn should_manage_todos_of_registered_user() {
+    let app = app();
+    let token = register(&app, "alice").await;
+
+    let (status, _) = send(&app, request(Method::GET, "/api/v1/todos", None, None)).await;
+    assert_eq!(StatusCode::UNAUTHORIZED, status);
+
+    let (status, created) = send(
+        &app,
+        request(
+            Method::POST,
+            "/api/v1/todos",
+            Some(&token),
+            Some(json!({ "text": "write integration tests", "labels": [] })),
+        ),
+    )
+    .await;
+    assert_eq!(StatusCode::CREATED, status);
+    assert_eq!("write integration tests", created["text"]);
+
+    let path = format!("/api/v1/todos/{}", created["id"]);
+    let (status, found) = send(&app, request(Method::GET, &path, Some(&token), None)).await;
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(created["text"], found["text"]);
+    assert_eq!(json!([]), found["items"]);
+
+    let (status, _) = send(
+        &app,
+        request(
+            Method::PATCH,
+            &path,
+            Some(&token),
+            Some(json!({ "completed": true })),
+        ),
+    )
+    .await;
+    assert_eq!(StatusCode::CREATED, status);
+    let (_, todos) = send(
+        &app,
+        request(Method::GET, "/api/v1/todos", Some(&token), None),
+    )
+    .await;
+    assert_eq!(json!(true), todos[0]["completed"]);
+
+    // 他のユーザーのTodoは見えない
+    let other = register(&app, "bob").await;
+    let (status, _) = send(&app, request(Method::GET, &path, Some(&other), None)).await;
+    assert_eq!(StatusCode::NOT_FOUND, status);
+}
+
+#[tokio::test]
+async fn should_login_and_describe_api() {
+    let app = app();
+    register(&app, "carol").await;
+
+    let (status, body) = send(
+        &app,
+        request(
+            Method::POST,
+            "/api/v1/auth/login",
+            None,
+            Some(json!({ "username": "carol", "password": "wrong-password" })),
+        ),
+    )
+    .await;
+    assert_eq!(StatusCode::UNAUTHORIZED, status);
+    assert_eq!("invalid_credentials", body["error"]["code"]);
+
+    let (status, body) = send(
+        &app,
+        request(
+            Method::POST,
+            "/api/v1/auth/login",
+            None,
+            Some(json!({ "username": "carol", "password": "password123" })),
+        ),
+    )
+    .await;
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!("carol", body["user"]["username"]);
+
+    let (status, spec) = send(
+        &app,
+        request(Method::GET, "/api-docs/openapi.json", None, None),
+    )
+    .await;
+    assert_eq!(StatusCode::OK, status);
+    assert_eq!(DEFAULT_API_PREFIX, spec["servers"][0]["url"]);
+    assert!(spec["paths"]["/todos"]["post"].is_object());
+}
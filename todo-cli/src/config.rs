@@ -0,0 +1,89 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+pub const DEFAULT_BASE_URL: &str = "http://localhost:8000/api/v1";
+
+#[derive(Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+struct FileConfig {
+    base_url: Option<String>,
+    token: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    // API_PREFIXまで含める
+    pub base_url: String,
+    // 未設定の場合、認証が必要なAPIは401になる
+    pub token: Option<String>,
+}
+
+impl Config {
+    // 環境変数のTODO_API_URL・TODO_TOKENを、設定ファイルの値より優先する
+    pub fn load() -> Result<Self, String> {
+        let file = match config_path() {
+            Some(path) if path.exists() => read(&path)?,
+            _ => FileConfig::default(),
+        };
+        Ok(Self::merge(
+            file,
+            env::var("TODO_API_URL").ok(),
+            env::var("TODO_TOKEN").ok(),
+        ))
+    }
+
+    fn merge(file: FileConfig, base_url: Option<String>, token: Option<String>) -> Self {
+        let non_empty = |value: Option<String>| value.filter(|value| !value.trim().is_empty());
+        Self {
+            base_url: non_empty(base_url)
+                .or_else(|| non_empty(file.base_url))
+                .unwrap_or_else(|| DEFAULT_BASE_URL.to_string()),
+            token: non_empty(token).or_else(|| non_empty(file.token)),
+        }
+    }
+}
+
+// XDG_CONFIG_HOMEが未設定の場合は~/.configの下を読む
+fn config_path() -> Option<PathBuf> {
+    let dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(dir.join("todo").join("config.toml"))
+}
+
+fn read(path: &Path) -> Result<FileConfig, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("fail read config [{}]: {}", path.display(), e))?;
+    toml::from_str(&text).map_err(|e| format!("invalid config [{}]: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_prefer_env_over_file() {
+        let file: FileConfig =
+            toml::from_str("base_url = \"https://todo.example.com/api/v1\"\ntoken = \"file\"")
+                .unwrap();
+        assert_eq!(
+            Config {
+                base_url: "https://todo.example.com/api/v1".to_string(),
+                token: Some("env".to_string()),
+            },
+            Config::merge(file, Some(" ".to_string()), Some("env".to_string()))
+        );
+        assert_eq!(
+            Config {
+                base_url: DEFAULT_BASE_URL.to_string(),
+                token: None,
+            },
+            Config::merge(FileConfig::default(), None, None)
+        );
+        assert!(toml::from_str::<FileConfig>("url = \"http://localhost\"").is_err());
+    }
+}
@@ -0,0 +1,257 @@
+use std::process::ExitCode;
+
+use chrono::{DateTime, Days, Local, NaiveDate, TimeZone, Utc};
+use clap::{Parser, Subcommand};
+use serde::Serialize;
+use todo_client::{ApiError, CreateTodo, TodoClient, TodoListQuery, UpdateTodo};
+
+use crate::config::Config;
+
+mod config;
+mod output;
+
+// 終了コード。スクリプトから失敗の種類を見分けられるようにする。2は引数の誤り(clap)
+const EXIT_CONFIG: u8 = 3;
+const EXIT_NETWORK: u8 = 4;
+const EXIT_API: u8 = 5;
+const EXIT_SERVER: u8 = 6;
+
+/// Manage todos on a running rust-todo API.
+///
+/// The base URL and token are read from TODO_API_URL and TODO_TOKEN,
+/// or from base_url and token in ~/.config/todo/config.toml.
+#[derive(Debug, Parser)]
+#[command(name = "todo")]
+struct Cli {
+    /// Print the API response as JSON instead of a table
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// List todos
+    List {
+        /// Only completed todos
+        #[arg(long, conflicts_with = "open")]
+        completed: bool,
+        /// Only todos that are not completed
+        #[arg(long)]
+        open: bool,
+        /// Only todos with this label name
+        #[arg(long)]
+        label: Option<String>,
+        /// Only todos whose text contains this
+        #[arg(long)]
+        search: Option<String>,
+    },
+    /// Add a todo
+    Add {
+        text: String,
+        /// today, tomorrow, YYYY-MM-DD (end of the day) or an RFC 3339 date-time
+        #[arg(long, value_parser = parse_due)]
+        due: Option<DateTime<Utc>>,
+    },
+    /// Mark a todo as completed
+    Done { id: i64 },
+    /// Move a todo to the trash
+    Rm { id: i64 },
+    /// List labels
+    Labels,
+}
+
+enum CliError {
+    Config(String),
+    Api(ApiError),
+}
+
+impl From<ApiError> for CliError {
+    fn from(e: ApiError) -> Self {
+        CliError::Api(e)
+    }
+}
+
+impl CliError {
+    fn exit_code(&self) -> u8 {
+        match self {
+            CliError::Config(_) | CliError::Api(ApiError::InvalidUrl(_)) => EXIT_CONFIG,
+            CliError::Api(ApiError::Http(_)) => EXIT_NETWORK,
+            // 想定外のbodyはサーバー側の問題として扱う
+            CliError::Api(ApiError::Server { .. } | ApiError::Decode(_)) => EXIT_SERVER,
+            CliError::Api(_) => EXIT_API,
+        }
+    }
+
+    fn message(&self) -> String {
+        match self {
+            CliError::Config(message) => message.clone(),
+            CliError::Api(e @ ApiError::Unauthorized { .. }) => {
+                format!("{} (set TODO_TOKEN or token in the config file)", e)
+            }
+            CliError::Api(e) => e.to_string(),
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli).await {
+        Ok(out) => {
+            print!("{}", out);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("todo: {}", e.message());
+            ExitCode::from(e.exit_code())
+        }
+    }
+}
+
+async fn run(cli: Cli) -> Result<String, CliError> {
+    let config = Config::load().map_err(CliError::Config)?;
+    let mut client = TodoClient::new(config.base_url);
+    if let Some(token) = config.token {
+        client = client.with_token(token);
+    }
+
+    match cli.command {
+        Command::List {
+            completed,
+            open,
+            label,
+            search,
+        } => {
+            let filter = TodoListQuery {
+                completed: (completed || open).then_some(completed),
+                q: search,
+                ..Default::default()
+            };
+            let mut todos = client.list_todos(&filter).await?;
+            // APIにラベルでの絞り込みがないため、取得した一覧から名前で絞り込む
+            if let Some(label) = label {
+                todos.retain(|todo| todo.labels.iter().any(|l| l.name == label));
+            }
+            render_list(cli.json, &todos, output::todos)
+        }
+        Command::Add { text, due } => {
+            let mut payload = CreateTodo::new(text, vec![]);
+            if let Some(due) = due {
+                payload = payload.with_due_date(due);
+            }
+            let todo = client.create_todo(&payload).await?;
+            render_one(cli.json, todo, output::todos)
+        }
+        Command::Done { id } => {
+            let todo = client
+                .update_todo(id, &UpdateTodo::default().with_completed(true))
+                .await?;
+            render_one(cli.json, todo, output::todos)
+        }
+        Command::Rm { id } => {
+            client.delete_todo(id).await?;
+            if cli.json {
+                Ok(format!(
+                    "{}\n",
+                    serde_json::json!({ "id": id, "deleted": true })
+                ))
+            } else {
+                Ok(format!("moved todo {} to the trash\n", id))
+            }
+        }
+        Command::Labels => {
+            let labels = client.list_labels().await?;
+            render_list(cli.json, &labels, output::labels)
+        }
+    }
+}
+
+// 一覧は件数に関わらず配列、add・doneは1件のオブジェクトとして出力する
+fn render_list<T: Serialize>(
+    json: bool,
+    values: &[T],
+    table: impl Fn(&[T]) -> String,
+) -> Result<String, CliError> {
+    if !json {
+        return Ok(table(values));
+    }
+    Ok(format!(
+        "{}\n",
+        serde_json::to_string_pretty(values).map_err(ApiError::from)?
+    ))
+}
+
+fn render_one<T: Serialize>(
+    json: bool,
+    value: T,
+    table: impl Fn(&[T]) -> String,
+) -> Result<String, CliError> {
+    if !json {
+        return Ok(table(std::slice::from_ref(&value)));
+    }
+    Ok(format!(
+        "{}\n",
+        serde_json::to_string_pretty(&value).map_err(ApiError::from)?
+    ))
+}
+
+fn parse_due(value: &str) -> Result<DateTime<Utc>, String> {
+    parse_due_from(value, Local::now().date_naive())
+}
+
+// 日付のみの場合はその日の終わり(ローカル時刻)を期限にする
+fn parse_due_from(value: &str, today: NaiveDate) -> Result<DateTime<Utc>, String> {
+    let date = match value {
+        "today" => today,
+        "tomorrow" => today + Days::new(1),
+        value => match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+            Ok(date) => date,
+            Err(_) => {
+                return DateTime::parse_from_rfc3339(value)
+                    .map(|due| due.with_timezone(&Utc))
+                    .map_err(|_| {
+                        format!(
+                        "expected today, tomorrow, YYYY-MM-DD or an RFC 3339 date-time, got [{}]",
+                        value
+                    )
+                    })
+            }
+        },
+    };
+    let end_of_day = date.and_hms_opt(23, 59, 59).expect("valid time");
+    Local
+        .from_local_datetime(&end_of_day)
+        .earliest()
+        .map(|due| due.with_timezone(&Utc))
+        .ok_or_else(|| format!("[{}] does not exist in the local time zone", value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_parse_due_dates() {
+        let today = NaiveDate::from_ymd_opt(2024, 2, 28).unwrap();
+        let end_of = |y, m, d| {
+            let naive = NaiveDate::from_ymd_opt(y, m, d)
+                .unwrap()
+                .and_hms_opt(23, 59, 59)
+                .unwrap();
+            Local
+                .from_local_datetime(&naive)
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+        assert_eq!(Ok(end_of(2024, 2, 28)), parse_due_from("today", today));
+        assert_eq!(Ok(end_of(2024, 2, 29)), parse_due_from("tomorrow", today));
+        assert_eq!(Ok(end_of(2024, 3, 10)), parse_due_from("2024-03-10", today));
+        assert_eq!(
+            Ok(Utc.with_ymd_and_hms(2024, 3, 10, 9, 0, 0).unwrap()),
+            parse_due_from("2024-03-10T18:00:00+09:00", today)
+        );
+        assert!(parse_due_from("next week", today).is_err());
+    }
+}
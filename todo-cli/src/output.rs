@@ -0,0 +1,96 @@
+use chrono::Local;
+use todo_client::{LabelWithUsage, TodoEntity};
+
+// 各列の幅を最も長い値に合わせ、最後の列は揃えない
+pub fn table(header: &[&str], rows: Vec<Vec<String>>) -> String {
+    let mut widths: Vec<usize> = header.iter().map(|title| title.chars().count()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let header = header.iter().map(|title| title.to_string()).collect();
+    let mut out = String::new();
+    for row in std::iter::once(header).chain(rows) {
+        let last = row.len().saturating_sub(1);
+        let line: Vec<String> = row
+            .into_iter()
+            .enumerate()
+            .map(|(i, cell)| {
+                if i == last {
+                    cell
+                } else {
+                    format!("{:width$}", cell, width = widths[i])
+                }
+            })
+            .collect();
+        out.push_str(line.join("  ").trim_end());
+        out.push('\n');
+    }
+    out
+}
+
+pub fn todos(todos: &[TodoEntity]) -> String {
+    let rows = todos
+        .iter()
+        .map(|todo| {
+            vec![
+                todo.id.to_string(),
+                if todo.completed { "x" } else { "" }.to_string(),
+                todo.priority.to_string(),
+                todo.due_date
+                    .map(|due| {
+                        due.with_timezone(&Local)
+                            .format("%Y-%m-%d %H:%M")
+                            .to_string()
+                    })
+                    .unwrap_or_default(),
+                todo.labels
+                    .iter()
+                    .map(|label| label.name.as_str())
+                    .collect::<Vec<_>>()
+                    .join(","),
+                todo.text.clone(),
+            ]
+        })
+        .collect();
+    table(&["ID", "DONE", "PRIORITY", "DUE", "LABELS", "TEXT"], rows)
+}
+
+pub fn labels(labels: &[LabelWithUsage]) -> String {
+    let rows = labels
+        .iter()
+        .map(|label| {
+            vec![
+                label.label.id.to_string(),
+                label.label.color.clone(),
+                label
+                    .todo_count
+                    .map(|count| count.to_string())
+                    .unwrap_or_default(),
+                label.label.name.clone(),
+            ]
+        })
+        .collect();
+    table(&["ID", "COLOR", "TODOS", "NAME"], rows)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_align_columns() {
+        let out = table(
+            &["ID", "DONE", "TEXT"],
+            vec![
+                vec!["1".to_string(), "x".to_string(), "buy milk".to_string()],
+                vec!["12".to_string(), "".to_string(), "write report".to_string()],
+            ],
+        );
+        assert_eq!(
+            "ID  DONE  TEXT\n1   x     buy milk\n12        write report\n",
+            out
+        );
+    }
+}
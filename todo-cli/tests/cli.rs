@@ -0,0 +1,220 @@
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::process::Output;
+
+use serde_json::Value;
+use tokio::process::Command;
+
+use rust_todo::auth::AuthKeys;
+use rust_todo::config::DEFAULT_API_PREFIX;
+use rust_todo::create_app;
+use rust_todo::repositories::comment::CommentRepositoryForMemory;
+use rust_todo::repositories::health::HealthRepositoryForMemory;
+use rust_todo::repositories::label::LabelRepositoryForMemory;
+use rust_todo::repositories::todo::TodoRepositoryForMemory;
+use rust_todo::repositories::user::UserRepositoryForMemory;
+use rust_todo::repositories::webhook::WebhookRepositoryForMemory;
+use todo_client::{CreateLabel, CreateTodo, TodoClient};
+
+struct Server {
+    base_url: String,
+    token: String,
+    // 実行する環境の~/.config/todo/config.tomlを読まないよう、HOMEをテストごとのディレクトリにする
+    home: PathBuf,
+}
+
+// メモリのリポジトリで起動したAPIへユーザーを登録する
+async fn spawn_server(name: &str) -> Server {
+    let label_repository = LabelRepositoryForMemory::new();
+    let app = create_app(
+        TodoRepositoryForMemory::new(vec![]).with_labels(&label_repository),
+        label_repository,
+        UserRepositoryForMemory::new(),
+        CommentRepositoryForMemory::new(),
+        WebhookRepositoryForMemory::new(),
+        HealthRepositoryForMemory,
+        AuthKeys::new(b"cli-test-secret"),
+        DEFAULT_API_PREFIX,
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base_url = format!(
+        "http://{}{}",
+        listener.local_addr().unwrap(),
+        DEFAULT_API_PREFIX
+    );
+    tokio::spawn(
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service()),
+    );
+
+    let token = TodoClient::new(base_url.clone())
+        .register("alice", "password123")
+        .await
+        .unwrap()
+        .token;
+    let home = std::env::temp_dir().join(format!("todo-cli-{}-{}", std::process::id(), name));
+    let _ = std::fs::remove_dir_all(&home);
+    std::fs::create_dir_all(&home).unwrap();
+    Server {
+        base_url,
+        token,
+        home,
+    }
+}
+
+impl Server {
+    fn client(&self) -> TodoClient {
+        TodoClient::new(self.base_url.clone()).with_token(self.token.clone())
+    }
+
+    async fn todo(&self, args: &[&str]) -> Output {
+        self.command(args)
+            .env("TODO_API_URL", &self.base_url)
+            .env("TODO_TOKEN", &self.token)
+            .output()
+            .await
+            .unwrap()
+    }
+
+    fn command(&self, args: &[&str]) -> Command {
+        let mut command = Command::new(env!("CARGO_BIN_EXE_todo"));
+        command
+            .args(args)
+            .env("HOME", &self.home)
+            .env_remove("XDG_CONFIG_HOME")
+            .env_remove("TODO_API_URL")
+            .env_remove("TODO_TOKEN");
+        command
+    }
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn json(output: &Output) -> Value {
+    serde_json::from_str(&stdout(output)).unwrap()
+}
+
+#[tokio::test]
+async fn should_add_complete_and_remove_todos() {
+    let server = spawn_server("crud").await;
+
+    let added = json(
+        &server
+            .todo(&["add", "buy milk", "--due", "tomorrow", "--json"])
+            .await,
+    );
+    assert_eq!("buy milk", added["text"]);
+    assert!(added["due_date"].is_string());
+    let id = added["id"].to_string();
+    server.todo(&["add", "write report"]).await;
+
+    let done = json(&server.todo(&["done", &id, "--json"]).await);
+    assert_eq!(Value::Bool(true), done["completed"]);
+
+    let completed = json(&server.todo(&["list", "--completed", "--json"]).await);
+    assert_eq!(vec!["buy milk"], texts(&completed));
+    let open = json(&server.todo(&["list", "--open", "--json"]).await);
+    assert_eq!(vec!["write report"], texts(&open));
+
+    let removed = json(&server.todo(&["rm", &id, "--json"]).await);
+    assert_eq!(Value::Bool(true), removed["deleted"]);
+    let all = json(&server.todo(&["--json", "list"]).await);
+    assert_eq!(vec!["write report"], texts(&all));
+}
+
+fn texts(todos: &Value) -> Vec<&str> {
+    todos
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|todo| todo["text"].as_str().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn should_print_tables_and_filter_by_label() {
+    let server = spawn_server("table").await;
+    let client = server.client();
+    let work = client
+        .create_label(&CreateLabel::new("work".to_string()))
+        .await
+        .unwrap();
+    client
+        .create_todo(&CreateTodo::new("write report".to_string(), vec![work.id]))
+        .await
+        .unwrap();
+    client
+        .create_todo(&CreateTodo::new("buy milk".to_string(), vec![]))
+        .await
+        .unwrap();
+
+    let out = stdout(&server.todo(&["list", "--label", "work"]).await);
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(2, lines.len(), "{}", out);
+    assert!(lines[0].starts_with("ID"));
+    assert!(lines[1].contains("work") && lines[1].ends_with("write report"));
+
+    let out = stdout(&server.todo(&["labels"]).await);
+    assert!(out.lines().nth(1).unwrap().ends_with("work"), "{}", out);
+}
+
+#[tokio::test]
+async fn should_read_config_file() {
+    let server = spawn_server("config").await;
+    let dir = server.home.join(".config").join("todo");
+    std::fs::create_dir_all(&dir).unwrap();
+    std::fs::write(
+        dir.join("config.toml"),
+        format!(
+            "base_url = \"{}\"\ntoken = \"{}\"\n",
+            server.base_url, server.token
+        ),
+    )
+    .unwrap();
+
+    let output = server.command(&["list", "--json"]).output().await.unwrap();
+    assert_eq!(Value::Array(vec![]), json(&output));
+
+    std::fs::write(dir.join("config.toml"), "base_url = ").unwrap();
+    let output = server.command(&["list"]).output().await.unwrap();
+    assert_eq!(Some(3), output.status.code());
+}
+
+#[tokio::test]
+async fn should_exit_with_distinct_codes() {
+    let server = spawn_server("errors").await;
+
+    // APIのエラー
+    let output = server.todo(&["done", "99"]).await;
+    assert_eq!(Some(5), output.status.code());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("not found"));
+    let output = server
+        .command(&["list"])
+        .env("TODO_API_URL", &server.base_url)
+        .output()
+        .await
+        .unwrap();
+    assert_eq!(Some(5), output.status.code());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("TODO_TOKEN"));
+
+    // 接続できない
+    let output = server
+        .command(&["labels"])
+        .env("TODO_API_URL", "http://127.0.0.1:9/api/v1")
+        .output()
+        .await
+        .unwrap();
+    assert_eq!(Some(4), output.status.code());
+
+    // 引数の誤り
+    let output = server.todo(&["add", "x", "--due", "someday"]).await;
+    assert_eq!(Some(2), output.status.code());
+}
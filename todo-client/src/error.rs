@@ -0,0 +1,126 @@
+use hyper::StatusCode;
+use rust_todo::error::FieldError;
+use serde::Deserialize;
+use serde_json::Value;
+use thiserror::Error;
+
+// APIが返すエラーの種類ごとに分け、呼び出し側でmatchできるようにする
+#[derive(Debug, Error)]
+pub enum ApiError {
+    #[error("unauthorized: {message}")]
+    Unauthorized { message: String },
+    #[error("not found: {message}")]
+    NotFound { message: String },
+    // バージョン競合・名前重複の場合、currentに最新(既存)のリソースが入る
+    #[error("conflict: {message}")]
+    Conflict {
+        message: String,
+        current: Option<Value>,
+    },
+    #[error("validation failed: {message}")]
+    Validation {
+        message: String,
+        fields: Vec<FieldError>,
+    },
+    #[error("server error ({status}): {message}")]
+    Server { status: StatusCode, message: String },
+    // 上記以外の4xx。codeはサーバーが返したエラーコード
+    #[error("request failed ({status}, {code}): {message}")]
+    Other {
+        status: StatusCode,
+        code: String,
+        message: String,
+    },
+    #[error("fail send request: {0}")]
+    Http(#[from] hyper::Error),
+    #[error("fail decode response: {0}")]
+    Decode(#[from] serde_json::Error),
+    #[error("invalid url: {0}")]
+    InvalidUrl(String),
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    error: ErrorDetail,
+    current: Option<Value>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorDetail {
+    code: String,
+    message: String,
+    #[serde(default)]
+    fields: Vec<FieldError>,
+}
+
+impl ApiError {
+    // 共通のエラーのbodyを読めない場合(プロキシが返したHTMLなど)は、bodyをそのままmessageにする
+    pub(crate) fn from_response(status: StatusCode, body: &[u8]) -> Self {
+        let (code, message, fields, current) = match serde_json::from_slice::<ErrorResponse>(body) {
+            Ok(res) => (
+                res.error.code,
+                res.error.message,
+                res.error.fields,
+                res.current,
+            ),
+            Err(_) => (
+                String::new(),
+                String::from_utf8_lossy(body).trim().to_string(),
+                vec![],
+                None,
+            ),
+        };
+        match status {
+            StatusCode::UNAUTHORIZED => Self::Unauthorized { message },
+            StatusCode::NOT_FOUND => Self::NotFound { message },
+            StatusCode::CONFLICT => Self::Conflict { message, current },
+            StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY => {
+                Self::Validation { message, fields }
+            }
+            status if status.is_server_error() => Self::Server { status, message },
+            status => Self::Other {
+                status,
+                code,
+                message,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn should_decode_error_body() {
+        let body = br#"{"error":{"code":"validation_error","message":"Validation failed","fields":[{"field":"text","message":"Can not be empty"}]}}"#;
+        match ApiError::from_response(StatusCode::UNPROCESSABLE_ENTITY, body) {
+            ApiError::Validation { message, fields } => {
+                assert_eq!("Validation failed", message);
+                assert_eq!(1, fields.len());
+                assert_eq!("text", fields[0].field);
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+
+        let body = br#"{"error":{"code":"payload_too_large","message":"too large"}}"#;
+        match ApiError::from_response(StatusCode::PAYLOAD_TOO_LARGE, body) {
+            ApiError::Other { status, code, .. } => {
+                assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, status);
+                assert_eq!("payload_too_large", code);
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn should_keep_body_that_is_not_error_json() {
+        match ApiError::from_response(StatusCode::BAD_GATEWAY, b"<html>Bad Gateway</html>\n") {
+            ApiError::Server { status, message } => {
+                assert_eq!(StatusCode::BAD_GATEWAY, status);
+                assert_eq!("<html>Bad Gateway</html>", message);
+            }
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+}
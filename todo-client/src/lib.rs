@@ -0,0 +1,155 @@
+use hyper::client::HttpConnector;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::{Body, Client, Method, Request, StatusCode, Uri};
+use hyper_rustls::HttpsConnector;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::json;
+
+pub use rust_todo::handlers::auth::TokenResponse;
+pub use rust_todo::repositories::label::{CreateLabel, Label, LabelWithUsage, UpdateLabel};
+pub use rust_todo::repositories::todo::{
+    CreateTodo, Priority, SortField, SortOrder, TodoEntity, TodoListQuery, UpdateTodo,
+};
+pub use rust_todo::repositories::todo_item::TodoWithItems;
+
+pub use crate::error::ApiError;
+
+mod error;
+
+type HttpClient = Client<HttpsConnector<HttpConnector>>;
+
+// サーバーと同じモデルの型でAPIを呼び出す。エラーのレスポンスはApiErrorへ変換する
+#[derive(Clone)]
+pub struct TodoClient {
+    http: HttpClient,
+    base_url: String,
+    token: Option<String>,
+}
+
+impl TodoClient {
+    // base_urlはAPI_PREFIXまで含める。例: http://localhost:8000/api/v1
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self {
+            http: Client::builder().build(HttpsConnector::with_webpki_roots()),
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            token: None,
+        }
+    }
+
+    // register・loginで受け取ったトークンを以降のリクエストに付ける
+    pub fn with_token(self, token: impl Into<String>) -> Self {
+        Self {
+            token: Some(token.into()),
+            ..self
+        }
+    }
+
+    pub async fn register(
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<TokenResponse, ApiError> {
+        let body = json!({ "username": username, "password": password });
+        self.send(Method::POST, "/auth/register", Some(&body)).await
+    }
+
+    pub async fn login(&self, username: &str, password: &str) -> Result<TokenResponse, ApiError> {
+        let body = json!({ "username": username, "password": password });
+        self.send(Method::POST, "/auth/login", Some(&body)).await
+    }
+
+    pub async fn list_todos(&self, filter: &TodoListQuery) -> Result<Vec<TodoEntity>, ApiError> {
+        let query =
+            serde_urlencoded::to_string(filter).map_err(|e| ApiError::InvalidUrl(e.to_string()))?;
+        let path = if query.is_empty() {
+            "/todos".to_string()
+        } else {
+            format!("/todos?{}", query)
+        };
+        self.send(Method::GET, &path, None::<&()>).await
+    }
+
+    pub async fn get_todo(&self, id: i64) -> Result<TodoWithItems, ApiError> {
+        self.send(Method::GET, &format!("/todos/{}", id), None::<&()>)
+            .await
+    }
+
+    pub async fn create_todo(&self, payload: &CreateTodo) -> Result<TodoEntity, ApiError> {
+        self.send(Method::POST, "/todos", Some(payload)).await
+    }
+
+    pub async fn update_todo(&self, id: i64, payload: &UpdateTodo) -> Result<TodoEntity, ApiError> {
+        self.send(Method::PATCH, &format!("/todos/{}", id), Some(payload))
+            .await
+    }
+
+    // 削除したTodoはゴミ箱へ移る
+    pub async fn delete_todo(&self, id: i64) -> Result<(), ApiError> {
+        self.send_empty(Method::DELETE, &format!("/todos/{}", id))
+            .await
+    }
+
+    pub async fn list_labels(&self) -> Result<Vec<LabelWithUsage>, ApiError> {
+        self.send(Method::GET, "/labels", None::<&()>).await
+    }
+
+    pub async fn create_label(&self, payload: &CreateLabel) -> Result<Label, ApiError> {
+        self.send(Method::POST, "/labels", Some(payload)).await
+    }
+
+    pub async fn update_label(&self, id: i32, payload: &UpdateLabel) -> Result<Label, ApiError> {
+        self.send(Method::PATCH, &format!("/labels/{}", id), Some(payload))
+            .await
+    }
+
+    pub async fn delete_label(&self, id: i32) -> Result<(), ApiError> {
+        self.send_empty(Method::DELETE, &format!("/labels/{}", id))
+            .await
+    }
+
+    async fn send<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&impl Serialize>,
+    ) -> Result<T, ApiError> {
+        let bytes = self.request(method, path, body).await?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    async fn send_empty(&self, method: Method, path: &str) -> Result<(), ApiError> {
+        self.request(method, path, None::<&()>).await?;
+        Ok(())
+    }
+
+    // 2xx以外のレスポンスはApiErrorとして返す
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&impl Serialize>,
+    ) -> Result<Vec<u8>, ApiError> {
+        let url = format!("{}{}", self.base_url, path);
+        let uri: Uri = url.parse().map_err(|_| ApiError::InvalidUrl(url))?;
+        let mut req = Request::builder().method(method).uri(uri);
+        if let Some(token) = &self.token {
+            req = req.header(AUTHORIZATION, format!("Bearer {}", token));
+        }
+        let req = match body {
+            Some(body) => req
+                .header(CONTENT_TYPE, "application/json")
+                .body(Body::from(serde_json::to_vec(body)?)),
+            None => req.body(Body::empty()),
+        }
+        .expect("method, uri and headers are valid");
+
+        let res = self.http.request(req).await?;
+        let status: StatusCode = res.status();
+        let bytes = hyper::body::to_bytes(res.into_body()).await?;
+        if !status.is_success() {
+            return Err(ApiError::from_response(status, &bytes));
+        }
+        Ok(bytes.to_vec())
+    }
+}
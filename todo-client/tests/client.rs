@@ -0,0 +1,233 @@
+use std::net::TcpListener;
+
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::{Json, Router};
+use serde_json::json;
+
+use rust_todo::auth::AuthKeys;
+use rust_todo::config::DEFAULT_API_PREFIX;
+use rust_todo::create_app;
+use rust_todo::repositories::comment::CommentRepositoryForMemory;
+use rust_todo::repositories::health::HealthRepositoryForMemory;
+use rust_todo::repositories::label::LabelRepositoryForMemory;
+use rust_todo::repositories::todo::TodoRepositoryForMemory;
+use rust_todo::repositories::user::UserRepositoryForMemory;
+use rust_todo::repositories::webhook::WebhookRepositoryForMemory;
+use todo_client::{
+    ApiError, CreateLabel, CreateTodo, Priority, TodoClient, TodoListQuery, UpdateLabel, UpdateTodo,
+};
+
+// ポート0でbindしたサーバーを起動し、そのURLを返す
+fn spawn(app: Router) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = axum::Server::from_tcp(listener)
+        .unwrap()
+        .serve(app.into_make_service());
+    tokio::spawn(server);
+    format!("http://{}", addr)
+}
+
+fn spawn_api() -> TodoClient {
+    let label_repository = LabelRepositoryForMemory::new();
+    let app = create_app(
+        TodoRepositoryForMemory::new(vec![]).with_labels(&label_repository),
+        label_repository,
+        UserRepositoryForMemory::new(),
+        CommentRepositoryForMemory::new(),
+        WebhookRepositoryForMemory::new(),
+        HealthRepositoryForMemory,
+        AuthKeys::new(b"client-test-secret"),
+        DEFAULT_API_PREFIX,
+    );
+    TodoClient::new(format!("{}{}", spawn(app), DEFAULT_API_PREFIX))
+}
+
+async fn logged_in(client: TodoClient, username: &str) -> TodoClient {
+    let res = client.register(username, "password123").await.unwrap();
+    client.with_token(res.token)
+}
+
+#[tokio::test]
+async fn should_register_and_login() {
+    let client = spawn_api();
+    let registered = client.register("alice", "password123").await.unwrap();
+    assert_eq!("alice", registered.user.username);
+
+    let res = client.login("alice", "password123").await.unwrap();
+    assert_eq!(registered.user.id, res.user.id);
+    let err = client.login("alice", "wrong-password").await.unwrap_err();
+    assert!(matches!(err, ApiError::Unauthorized { .. }), "{:?}", err);
+}
+
+#[tokio::test]
+async fn should_manage_todos() {
+    let client = logged_in(spawn_api(), "alice").await;
+
+    let first = client
+        .create_todo(&CreateTodo::new("buy milk".to_string(), vec![]))
+        .await
+        .unwrap();
+    let second = client
+        .create_todo(
+            &CreateTodo::new("write report".to_string(), vec![]).with_priority(Priority::High),
+        )
+        .await
+        .unwrap();
+    assert_eq!("buy milk", first.text);
+    assert_eq!(Priority::High, second.priority);
+
+    let found = client.get_todo(first.id).await.unwrap();
+    assert_eq!(first, found.todo);
+    assert!(found.items.is_empty());
+
+    let updated = client
+        .update_todo(
+            first.id,
+            &UpdateTodo::default()
+                .with_completed(true)
+                .with_version(first.version),
+        )
+        .await
+        .unwrap();
+    assert!(updated.completed);
+    // 指定しなかった項目は変わらない
+    assert_eq!("buy milk", updated.text);
+
+    let all = client.list_todos(&TodoListQuery::default()).await.unwrap();
+    assert_eq!(2, all.len());
+    let completed = client
+        .list_todos(&TodoListQuery {
+            completed: Some(true),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(vec![updated], completed);
+    let searched = client
+        .list_todos(&TodoListQuery {
+            q: Some("write report".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+    assert_eq!(
+        vec![second.id],
+        searched.iter().map(|todo| todo.id).collect::<Vec<_>>()
+    );
+
+    client.delete_todo(first.id).await.unwrap();
+    let err = client.get_todo(first.id).await.unwrap_err();
+    assert!(matches!(err, ApiError::NotFound { .. }), "{:?}", err);
+}
+
+#[tokio::test]
+async fn should_manage_labels() {
+    let client = logged_in(spawn_api(), "alice").await;
+
+    let label = client
+        .create_label(&CreateLabel::new("work".to_string()).with_color("#ff0000".to_string()))
+        .await
+        .unwrap();
+    assert_eq!("work", label.name);
+    assert_eq!("#ff0000", label.color);
+    client
+        .create_todo(&CreateTodo::new("write report".to_string(), vec![label.id]))
+        .await
+        .unwrap();
+
+    let labels = client.list_labels().await.unwrap();
+    assert_eq!(1, labels.len());
+    assert_eq!(label, labels[0].label);
+
+    let updated = client
+        .update_label(
+            label.id,
+            &UpdateLabel::new("office".to_string()).with_description(Some("9 to 5".to_string())),
+        )
+        .await
+        .unwrap();
+    assert_eq!("office", updated.name);
+    // 指定しなかった色は変わらない
+    assert_eq!("#ff0000", updated.color);
+    assert_eq!(Some("9 to 5".to_string()), updated.description);
+
+    client.delete_label(label.id).await.unwrap();
+    assert!(client.list_labels().await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn should_decode_api_errors() {
+    let anonymous = spawn_api();
+    let err = anonymous
+        .list_todos(&TodoListQuery::default())
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ApiError::Unauthorized { .. }), "{:?}", err);
+
+    let client = logged_in(anonymous, "alice").await;
+    match client
+        .create_todo(&CreateTodo::new(" ".to_string(), vec![]))
+        .await
+    {
+        Err(ApiError::Validation { fields, .. }) => {
+            assert_eq!(
+                vec!["text"],
+                fields.iter().map(|f| &f.field).collect::<Vec<_>>()
+            );
+        }
+        res => panic!("unexpected result: {:?}", res),
+    }
+
+    let todo = client
+        .create_todo(&CreateTodo::new("buy milk".to_string(), vec![]))
+        .await
+        .unwrap();
+    let stale = UpdateTodo::default()
+        .with_text("buy bread".to_string())
+        .with_version(todo.version);
+    client.update_todo(todo.id, &stale).await.unwrap();
+    match client.update_todo(todo.id, &stale).await {
+        Err(ApiError::Conflict { current, .. }) => {
+            assert_eq!(json!("buy bread"), current.unwrap()["text"]);
+        }
+        res => panic!("unexpected result: {:?}", res),
+    }
+
+    let err = client
+        .update_label(99, &UpdateLabel::new("missing".to_string()))
+        .await
+        .unwrap_err();
+    assert!(matches!(err, ApiError::NotFound { .. }), "{:?}", err);
+    let err = client.delete_todo(99).await.unwrap_err();
+    assert!(matches!(err, ApiError::NotFound { .. }), "{:?}", err);
+}
+
+#[tokio::test]
+async fn should_decode_server_errors() {
+    let app = Router::new().route(
+        "/todos",
+        get(|| async {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(json!({
+                    "error": { "code": "internal_error", "message": "Internal server error" }
+                })),
+            )
+        }),
+    );
+    let client = TodoClient::new(spawn(app));
+    match client.list_todos(&TodoListQuery::default()).await {
+        Err(ApiError::Server { status, message }) => {
+            assert_eq!(StatusCode::INTERNAL_SERVER_ERROR, status);
+            assert_eq!("Internal server error", message);
+        }
+        res => panic!("unexpected result: {:?}", res),
+    }
+
+    // 接続できない場合はHTTPのエラー
+    let client = TodoClient::new("http://127.0.0.1:9");
+    let err = client.list_labels().await.unwrap_err();
+    assert!(matches!(err, ApiError::Http(_)), "{:?}", err);
+}
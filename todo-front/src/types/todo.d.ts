@@ -3,6 +3,8 @@ export type Todo = {
   text: string;
   completed: boolean;
   labels: Label[];
+  created_at: string;
+  updated_at: string;
 };
 
 export type NewTodoPayload = {